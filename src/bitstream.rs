@@ -0,0 +1,453 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    bitstream.rs
+
+    Implement a bitstream floppy disk model. Each track is stored as the raw
+    sequence of MFM bitcells recorded on the disk surface, starting at the
+    index hole, so that non-standard gaps, mismatched sector IDs, bad CRCs
+    and missing address marks are all preserved as they were imaged.
+
+    An optional surface mask marks bitcells that don't hold a stable flux
+    transition. A masked cell recorded as 0 is a weak bit that reads back
+    randomly each revolution, and a masked cell recorded as 1 is a hole in
+    the media that never produces a transition.
+
+    Sectors are not stored directly; the FDC decodes them from the bitcells
+    whenever a track is read.
+
+*/
+
+pub const MFM_SYNC_WORD: u16 = 0x4489; // 0xA1 with a missing clock bit
+pub const MFM_SYNC_BYTE: u8 = 0xA1;
+pub const MFM_IDAM: u8 = 0xFE;
+pub const MFM_DAM: u8 = 0xFB;
+pub const MFM_DDAM: u8 = 0xF8;
+pub const MFM_GAP_BYTE: u8 = 0x4E;
+
+pub const CRC_CCITT_INIT: u16 = 0xFFFF;
+
+/// Number of bytes after an ID field to search for a data address mark before the data
+/// field is considered missing.
+const MFM_DAM_SEARCH_BYTES: usize = 64;
+
+const MFM_GAP4A_LEN: usize = 80;
+const MFM_GAP1_LEN: usize = 50;
+const MFM_GAP2_LEN: usize = 22;
+const MFM_GAP3_LEN: usize = 80;
+const MFM_SYNC_LEN: usize = 12;
+
+/// Calculate the CRC-CCITT used by the FDC for ID and data fields. The CRC covers the
+/// address mark sync bytes and the mark itself, and is zero when calculated over a
+/// field including its recorded CRC.
+pub fn crc_ccitt(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// The ID field of a sector: cylinder, head, record (sector number) and size code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SectorId {
+    pub c: u8,
+    pub h: u8,
+    pub r: u8,
+    pub n: u8,
+}
+
+impl SectorId {
+    pub fn size(&self) -> usize {
+        128 << (self.n & 0x07)
+    }
+}
+
+/// A sector as decoded from a track. When encoding a track, the CRC flags select whether
+/// a valid CRC is written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedSector {
+    pub id: SectorId,
+    pub id_crc_ok: bool,
+    /// Sector data, or None if no data address mark followed the ID field.
+    pub data: Option<Vec<u8>>,
+    pub data_crc_ok: bool,
+    /// The data field was recorded with a deleted data address mark.
+    pub deleted: bool,
+}
+
+pub struct BitstreamTrack {
+    /// Bitcells, most significant bit first, starting at the index hole.
+    cells: Vec<u8>,
+    /// Optional surface mask, with the same layout as the bitcells.
+    surface: Option<Vec<u8>>,
+    bit_len: usize,
+    /// State of the random generator used to read weak bits.
+    rng: u32,
+}
+
+impl BitstreamTrack {
+    /// Create a track from bitcells, most significant bit first. The bitcells are rotated
+    /// so that the track starts at the specified index hole position.
+    pub fn new(cells: Vec<u8>, bit_len: usize, index: usize, surface: Option<Vec<u8>>) -> Self {
+        let bit_len = bit_len.min(cells.len() * 8);
+        let rotate = |src: &[u8]| {
+            let mut dst = vec![0; (bit_len + 7) / 8];
+            for i in 0..bit_len {
+                let pos = (index + i) % bit_len;
+                if (src[pos >> 3] >> (7 - (pos & 7))) & 1 != 0 {
+                    dst[i >> 3] |= 0x80 >> (i & 7);
+                }
+            }
+            dst
+        };
+
+        let (cells, surface) = match index % bit_len.max(1) {
+            0 => (cells, surface),
+            _ => (rotate(&cells), surface.as_deref().map(rotate))
+        };
+
+        Self {
+            cells,
+            surface,
+            bit_len,
+            rng: 0x1234_5678,
+        }
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// Return the bitcells of the track, most significant bit first, starting at the index.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    /// Return whether any bitcells on the track are weak or missing.
+    pub fn has_surface_defects(&self) -> bool {
+        self.surface.as_ref().map_or(false, |surface| surface.iter().any(|&b| b != 0))
+    }
+
+    fn next_random(&mut self) -> u8 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng & 1) as u8
+    }
+
+    /// Read the bitcell at the specified position from the index. Positions past the end of
+    /// the track wrap around, so fields that cross the index can be read in full.
+    fn cell(&mut self, pos: usize) -> u8 {
+        if self.bit_len == 0 {
+            return 0;
+        }
+        let pos = pos % self.bit_len;
+        let mask = 0x80 >> (pos & 7);
+        let bit = (self.cells[pos >> 3] & mask != 0) as u8;
+        let defect = self.surface.as_ref().map_or(false, |surface| surface[pos >> 3] & mask != 0);
+
+        match (defect, bit) {
+            (false, _) => bit,
+            (true, 0) => self.next_random(),
+            (true, _) => 0
+        }
+    }
+
+    fn read_word(&mut self, pos: usize) -> u16 {
+        (0..16).fold(0, |word, i| (word << 1) | self.cell(pos + i) as u16)
+    }
+
+    /// Read the MFM encoded byte starting at the specified position. Each data bit follows
+    /// its clock bit.
+    fn read_byte(&mut self, pos: usize) -> u8 {
+        (0..8).fold(0, |byte, i| (byte << 1) | self.cell(pos + i * 2 + 1))
+    }
+
+    fn read_bytes(&mut self, pos: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.read_byte(pos + i * 16)).collect()
+    }
+
+    /// Search for an address mark (three sync bytes followed by a mark byte) starting in the
+    /// range of positions specified. Returns the mark byte and the position following it.
+    fn find_mark(&mut self, start: usize, end: usize) -> Option<(usize, u8)> {
+        let mut shift: u16 = 0;
+        for pos in start..end {
+            shift = (shift << 1) | self.cell(pos) as u16;
+            if shift == MFM_SYNC_WORD {
+                let next = pos + 1;
+                if self.read_word(next) == MFM_SYNC_WORD && self.read_word(next + 16) == MFM_SYNC_WORD {
+                    return Some((next + 48, self.read_byte(next + 32)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Decode all sectors on the track, in the order their ID fields pass under the head
+    /// starting from the index. Weak bits are read again on every call.
+    pub fn decode_sectors(&mut self) -> Vec<DecodedSector> {
+        let mut sectors = Vec::new();
+        let mut pos = 0;
+
+        while let Some((start, mark)) = self.find_mark(pos, self.bit_len) {
+            pos = start;
+            if mark != MFM_IDAM {
+                continue;
+            }
+
+            let id_field = self.read_bytes(start, 6);
+            let id_crc = crc_ccitt(crc_ccitt(CRC_CCITT_INIT, &[MFM_SYNC_BYTE, MFM_SYNC_BYTE, MFM_SYNC_BYTE, mark]), &id_field);
+            let id = SectorId {
+                c: id_field[0],
+                h: id_field[1],
+                r: id_field[2],
+                n: id_field[3],
+            };
+            pos = start + 6 * 16;
+
+            // The data field must follow closely behind the ID field. If the next mark is another
+            // ID field instead, leave it to be found by the next search.
+            let (data, data_crc_ok, deleted) = match self.find_mark(pos, pos + MFM_DAM_SEARCH_BYTES * 16) {
+                Some((data_start, mark)) if mark == MFM_DAM || mark == MFM_DDAM => {
+                    let field = self.read_bytes(data_start, id.size() + 2);
+                    let crc = crc_ccitt(crc_ccitt(CRC_CCITT_INIT, &[MFM_SYNC_BYTE, MFM_SYNC_BYTE, MFM_SYNC_BYTE, mark]), &field);
+                    pos = data_start + field.len() * 16;
+                    (Some(field[..id.size()].to_vec()), crc == 0, mark == MFM_DDAM)
+                }
+                _ => (None, false, false)
+            };
+
+            sectors.push(DecodedSector {
+                id,
+                id_crc_ok: id_crc == 0,
+                data,
+                data_crc_ok,
+                deleted,
+            });
+        }
+
+        sectors
+    }
+
+    /// Encode sectors into a track using the standard IBM MFM track layout. The track is
+    /// padded with gap bytes up to the specified length in bitcells, or extended if the
+    /// sectors don't fit.
+    pub fn encode(sectors: &[DecodedSector], bit_len: usize) -> Self {
+        let mut enc = MfmEncoder::new();
+
+        enc.bytes(MFM_GAP_BYTE, MFM_GAP4A_LEN);
+        enc.bytes(MFM_GAP_BYTE, MFM_GAP1_LEN);
+
+        for sector in sectors {
+            let id = [sector.id.c, sector.id.h, sector.id.r, sector.id.n];
+            enc.field(MFM_IDAM, &id, sector.id_crc_ok);
+            enc.bytes(MFM_GAP_BYTE, MFM_GAP2_LEN);
+
+            if let Some(data) = &sector.data {
+                let mark = if sector.deleted { MFM_DDAM } else { MFM_DAM };
+                enc.field(mark, data, sector.data_crc_ok);
+            }
+            enc.bytes(MFM_GAP_BYTE, MFM_GAP3_LEN);
+        }
+
+        while enc.bit_len < bit_len {
+            enc.byte(MFM_GAP_BYTE);
+        }
+
+        let bit_len = enc.bit_len;
+        BitstreamTrack::new(enc.cells, bit_len, 0, None)
+    }
+}
+
+struct MfmEncoder {
+    cells: Vec<u8>,
+    bit_len: usize,
+    last_bit: u8,
+}
+
+impl MfmEncoder {
+    fn new() -> Self {
+        Self {
+            cells: Vec::new(),
+            bit_len: 0,
+            last_bit: 0,
+        }
+    }
+
+    fn cell(&mut self, bit: u8) {
+        if self.bit_len % 8 == 0 {
+            self.cells.push(0);
+        }
+        if bit != 0 {
+            self.cells[self.bit_len >> 3] |= 0x80 >> (self.bit_len & 7);
+        }
+        self.bit_len += 1;
+    }
+
+    /// Encode a data byte. A clock bit is written only between two zero data bits.
+    fn byte(&mut self, byte: u8) {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) & 1;
+            self.cell((self.last_bit == 0 && bit == 0) as u8);
+            self.cell(bit);
+            self.last_bit = bit;
+        }
+    }
+
+    fn bytes(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.byte(byte);
+        }
+    }
+
+    fn sync(&mut self) {
+        for i in (0..16).rev() {
+            self.cell(((MFM_SYNC_WORD >> i) & 1) as u8);
+        }
+        self.last_bit = MFM_SYNC_BYTE & 1;
+    }
+
+    /// Encode an address mark and its field, followed by the field's CRC. An invalid CRC
+    /// is written if 'crc_ok' is false.
+    fn field(&mut self, mark: u8, data: &[u8], crc_ok: bool) {
+        self.bytes(0x00, MFM_SYNC_LEN);
+        for _ in 0..3 {
+            self.sync();
+        }
+        self.byte(mark);
+        for &byte in data {
+            self.byte(byte);
+        }
+
+        let mut crc = crc_ccitt(crc_ccitt(CRC_CCITT_INIT, &[MFM_SYNC_BYTE, MFM_SYNC_BYTE, MFM_SYNC_BYTE, mark]), data);
+        if !crc_ok {
+            crc ^= 0xFFFF;
+        }
+        self.byte((crc >> 8) as u8);
+        self.byte(crc as u8);
+    }
+}
+
+/// A disk stored as bitstream tracks. Tracks that weren't imaged are left empty and read
+/// as unformatted.
+pub struct BitstreamDisk {
+    pub cylinders: u8,
+    pub heads: u8,
+    pub write_protected: bool,
+    tracks: Vec<Option<BitstreamTrack>>,
+}
+
+impl BitstreamDisk {
+    pub fn new(cylinders: u8, heads: u8) -> Self {
+        Self {
+            cylinders,
+            heads,
+            write_protected: false,
+            tracks: (0..cylinders as usize * heads as usize).map(|_| None).collect(),
+        }
+    }
+
+    fn track_index(&self, cylinder: u8, head: u8) -> Option<usize> {
+        match cylinder < self.cylinders && head < self.heads {
+            true => Some(cylinder as usize * self.heads as usize + head as usize),
+            false => None
+        }
+    }
+
+    pub fn set_track(&mut self, cylinder: u8, head: u8, track: BitstreamTrack) {
+        if let Some(idx) = self.track_index(cylinder, head) {
+            self.tracks[idx] = Some(track);
+        }
+    }
+
+    pub fn track_mut(&mut self, cylinder: u8, head: u8) -> Option<&mut BitstreamTrack> {
+        let idx = self.track_index(cylinder, head)?;
+        self.tracks[idx].as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_sectors() -> Vec<DecodedSector> {
+        (1..=9u8).map(|r| DecodedSector {
+            id: SectorId { c: 0, h: 0, r, n: 2 },
+            id_crc_ok: true,
+            data: Some(vec![r; 512]),
+            data_crc_ok: true,
+            deleted: false,
+        }).collect()
+    }
+
+    #[test]
+    fn test_crc_ccitt() {
+        // The CRC of the ID address mark sync bytes is a commonly quoted check value
+        assert_eq!(crc_ccitt(CRC_CCITT_INIT, &[0xA1, 0xA1, 0xA1]), 0xCDB4);
+        assert_eq!(crc_ccitt(CRC_CCITT_INIT, b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_mfm_round_trip() {
+        let mut sectors = test_sectors();
+        sectors[2].data_crc_ok = false;
+        sectors[4].id_crc_ok = false;
+        sectors[6].deleted = true;
+        sectors[8].data = None;
+        sectors[8].data_crc_ok = false;
+
+        let mut track = BitstreamTrack::encode(&sectors, 100_000);
+        assert_eq!(track.bit_len(), 100_000);
+
+        let decoded = track.decode_sectors();
+        assert_eq!(decoded, sectors);
+
+        // Starting the track part way through a sector still finds every sector once
+        let mut rotated = BitstreamTrack::new(track.cells.clone(), track.bit_len(), 50_000, None);
+        let mut ids: Vec<u8> = rotated.decode_sectors().iter().map(|s| s.id.r).collect();
+        ids.sort();
+        assert_eq!(ids, (1..=9).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_weak_bits() {
+        let track = BitstreamTrack::encode(&test_sectors()[..1], 100_000);
+
+        // Mark the middle of the first sector's data field as weak, clearing the recorded bits
+        let mut cells = track.cells.clone();
+        let mut surface = vec![0; cells.len()];
+        let start = (MFM_GAP4A_LEN + MFM_GAP1_LEN + 300) * 2;
+        for i in start..start + 64 {
+            cells[i] = 0;
+            surface[i] = 0xFF;
+        }
+
+        let mut weak = BitstreamTrack::new(cells, track.bit_len(), 0, Some(surface));
+        assert!(weak.has_surface_defects());
+
+        let first = weak.decode_sectors();
+        let second = weak.decode_sectors();
+        assert!(!first[0].data_crc_ok);
+        assert_ne!(first[0].data, second[0].data);
+    }
+}
@@ -15,8 +15,25 @@
 
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+    breakpoints.rs
+
+    Breakpoint types, and conditions over registers and flags for 
+    conditional breakpoints. A condition can be parsed from text, ie:
+        ax == 4C00h && zf
+        cl >= 0x10 || !cf
+
+    Comparisons are '==', '!=', '<', '<=', '>' and '>=', between a register
+    and a hexadecimal number written as for watch expressions. A flag name 
+    (cf, pf, af, zf, sf, tf, if, df or of) is true when the flag is set, or
+    when clear if prefixed with '!'. '&&' binds tighter than '||'.
 */
 
+use std::fmt::Display;
+
+use crate::cpu_808x::{Flag, Register8, Register16};
+use crate::watch::{self, WatchError, WatchExpr};
+
 #[allow(dead_code)]
 pub enum BreakPointType {
 
@@ -25,6 +42,262 @@ pub enum BreakPointType {
     ExecuteFlat(u32), // Breakpoint on CS<<4+IP
     MemAccess(u16, u16), // Breakpoint on memory access, seg::offset
     MemAccessFlat(u32), // Breakpoint on memory access, seg<<4+offset
+    MemReadFlat(u32), // Breakpoint on memory read, seg<<4+offset
+    MemWriteFlat(u32), // Breakpoint on memory write, seg<<4+offset
     Interrupt(u8), // Breakpoint on interrupt #
 }
 
+
+/// The kind of bus activity that trips a breakpoint set with Cpu::add_breakpoint().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BreakKind {
+    Execute, // Instruction executed at address. Prefetching the byte does not trip the breakpoint.
+    Read, // Memory read cycle at address
+    Write, // Memory write cycle at address
+}
+
+/// A comparison operator for a register condition.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn compare(&self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            CompareOp::Eq => lhs == rhs,
+            CompareOp::Ne => lhs != rhs,
+            CompareOp::Lt => lhs < rhs,
+            CompareOp::Le => lhs <= rhs,
+            CompareOp::Gt => lhs > rhs,
+            CompareOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// A condition over CPU registers and flags for Cpu::add_conditional_breakpoint().
+/// Register comparisons are unsigned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BreakCondition {
+    Reg8(Register8, CompareOp, u8),
+    Reg16(Register16, CompareOp, u16),
+    Flag(Flag, bool), // Flag is set (true) or clear (false)
+    And(Box<BreakCondition>, Box<BreakCondition>),
+    Or(Box<BreakCondition>, Box<BreakCondition>),
+}
+
+impl Display for CompareOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompareOp::Eq => write!(f, "=="),
+            CompareOp::Ne => write!(f, "!="),
+            CompareOp::Lt => write!(f, "<"),
+            CompareOp::Le => write!(f, "<="),
+            CompareOp::Gt => write!(f, ">"),
+            CompareOp::Ge => write!(f, ">="),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Compare(CompareOp),
+    Not,
+    And,
+    Or,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(s) => write!(f, "{}", s),
+            Token::Compare(op) => write!(f, "{}", op),
+            Token::Not => write!(f, "!"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+        }
+    }
+}
+
+fn tokenize(cond_str: &str) -> Result<Vec<Token>, WatchError> {
+    let mut tokens = Vec::new();
+    let mut chars = cond_str.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_alphanumeric() => {
+                let mut word = c.to_ascii_lowercase().to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                    word.push(c.to_ascii_lowercase());
+                }
+                Token::Word(word)
+            }
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Compare(CompareOp::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Compare(CompareOp::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Compare(CompareOp::Le),
+            '<' => Token::Compare(CompareOp::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Compare(CompareOp::Ge),
+            '>' => Token::Compare(CompareOp::Gt),
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            _ => return Err(WatchError::UnexpectedToken(c.to_string()))
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn flag_from_str(name: &str) -> Option<Flag> {
+    let flag = match name {
+        "cf" => Flag::Carry,
+        "pf" => Flag::Parity,
+        "af" => Flag::AuxCarry,
+        "zf" => Flag::Zero,
+        "sf" => Flag::Sign,
+        "tf" => Flag::Trap,
+        "if" => Flag::Interrupt,
+        "df" => Flag::Direction,
+        "of" => Flag::Overflow,
+        _ => return None
+    };
+    Some(flag)
+}
+
+/// A recursive descent parser over the tokens of a breakpoint condition.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, WatchError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(WatchError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn word(&mut self) -> Result<String, WatchError> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => Err(WatchError::UnexpectedToken(token.to_string()))
+        }
+    }
+
+    // cond := conj ('||' conj)*
+    fn cond(&mut self) -> Result<BreakCondition, WatchError> {
+        let mut lhs = self.conj()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            lhs = lhs.or(self.conj()?);
+        }
+        Ok(lhs)
+    }
+
+    // conj := term ('&&' term)*
+    fn conj(&mut self) -> Result<BreakCondition, WatchError> {
+        let mut lhs = self.term()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            lhs = lhs.and(self.term()?);
+        }
+        Ok(lhs)
+    }
+
+    // term := ['!'] flag | register op number
+    fn term(&mut self) -> Result<BreakCondition, WatchError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            let word = self.word()?;
+            return match flag_from_str(&word) {
+                Some(flag) => Ok(BreakCondition::Flag(flag, false)),
+                None => Err(WatchError::UnexpectedToken(word))
+            }
+        }
+
+        let word = self.word()?;
+        if let Some(flag) = flag_from_str(&word) {
+            return Ok(BreakCondition::Flag(flag, true))
+        }
+        let reg = watch::register_from_str(&word).ok_or(WatchError::UnexpectedToken(word))?;
+        let op = match self.next()? {
+            Token::Compare(op) => op,
+            token => return Err(WatchError::UnexpectedToken(token.to_string()))
+        };
+        let number_str = self.word()?;
+        let value = watch::number_from_str(&number_str)?;
+
+        match reg {
+            WatchExpr::Reg8(reg8) => {
+                let value = u8::try_from(value).map_err(|_| WatchError::InvalidNumber(number_str))?;
+                Ok(BreakCondition::Reg8(reg8, op, value))
+            }
+            WatchExpr::Reg16(reg16) => Ok(BreakCondition::Reg16(reg16, op, value)),
+            _ => unreachable!("register_from_str only returns registers")
+        }
+    }
+}
+
+impl BreakCondition {
+    pub fn and(self, other: BreakCondition) -> BreakCondition {
+        BreakCondition::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: BreakCondition) -> BreakCondition {
+        BreakCondition::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Parse a breakpoint condition.
+    pub fn parse(cond_str: &str) -> Result<BreakCondition, WatchError> {
+        let mut parser = Parser {
+            tokens: tokenize(cond_str)?,
+            pos: 0,
+        };
+
+        let cond = parser.cond()?;
+        match parser.peek() {
+            Some(token) => Err(WatchError::UnexpectedToken(token.to_string())),
+            None => Ok(cond)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_condition() {
+        let reg16 = |op| BreakCondition::Reg16(Register16::AX, op, 0x4C00);
+        assert_eq!(BreakCondition::parse("ax == 4C00h"), Ok(reg16(CompareOp::Eq)));
+        assert_eq!(BreakCondition::parse("AX != 0x4C00"), Ok(reg16(CompareOp::Ne)));
+        assert_eq!(BreakCondition::parse("ax < 4c00"), Ok(reg16(CompareOp::Lt)));
+        assert_eq!(BreakCondition::parse("ax<=4c00"), Ok(reg16(CompareOp::Le)));
+        assert_eq!(BreakCondition::parse("ax > 4c00"), Ok(reg16(CompareOp::Gt)));
+        assert_eq!(BreakCondition::parse("ax>=4c00"), Ok(reg16(CompareOp::Ge)));
+
+        // '&&' binds tighter than '||'
+        assert_eq!(
+            BreakCondition::parse("cl >= 10 || !cf && zf"),
+            Ok(BreakCondition::Reg8(Register8::CL, CompareOp::Ge, 0x10)
+                .or(BreakCondition::Flag(Flag::Carry, false).and(BreakCondition::Flag(Flag::Zero, true))))
+        );
+
+        assert_eq!(BreakCondition::parse("ah == 100"), Err(WatchError::InvalidNumber("100".to_string())));
+        assert_eq!(BreakCondition::parse("ax = 1"), Err(WatchError::UnexpectedToken("=".to_string())));
+        assert_eq!(BreakCondition::parse("!ax"), Err(WatchError::UnexpectedToken("ax".to_string())));
+        assert_eq!(BreakCondition::parse("ax >="), Err(WatchError::UnexpectedEnd));
+        assert_eq!(BreakCondition::parse("zf zf"), Err(WatchError::UnexpectedToken("zf".to_string())));
+    }
+}
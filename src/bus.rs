@@ -1,17 +1,18 @@
 #![allow(dead_code)]
 use std::{
-    collections::HashMap,
     io::{BufWriter, Write},
     fmt,
     path::Path
 };
 
 use ringbuf::{Producer};
+use serde_derive::Deserialize;
 
 use crate::cpu_808x::*;
+use crate::cpu_common::CpuType;
 use crate::bytequeue::*;
 
-use crate::syntax_token::SyntaxToken;
+use crate::syntax_token::{SyntaxToken, MemoryDumpFormat};
 use crate::machine_manager::MachineDescriptor;
 use crate::config::VideoType;
 
@@ -23,7 +24,8 @@ use crate::devices::{
     serial::*,
     fdc::FloppyController,
     hdc::*,
-    mouse::*
+    mouse::*,
+    ems::{self, EmsCard}
 };
 
 use crate::tracelogger::TraceLogger;
@@ -35,25 +37,73 @@ use crate::devices::ega::{self, EGACard};
 #[cfg(feature = "vga")]
 use crate::devices::vga::{self, VGACard};
 use crate::memerror::MemError;
+use crate::io_bus::{IoBus, IoBusError};
+use crate::io_trace::{IoDirection, IoTrace};
 
 pub const NO_IO_BYTE: u8 = 0xFF; // This is the byte read from a unconnected IO address.
 pub const FLOATING_BUS_BYTE: u8 = 0x00; // This is the byte read from an unmapped memory address.
 
 const ADDRESS_SPACE: usize = 1_048_576;
 const DEFAULT_WAIT_STATES: u32 = 0;
+pub const DEFAULT_IO_WAIT_STATES: u32 = 1;
 
 const ROM_BIT: u8 = 0b1000_0000;
 pub const MEM_RET_BIT: u8 = 0b0100_0000; // Bit to signify that this address is a return address for a CALL or INT
 pub const MEM_BPE_BIT: u8 = 0b0010_0000; // Bit to signify that this address is associated with a breakpoint on execute
-pub const MEM_BPA_BIT: u8 = 0b0001_0000; // Bit to signify that this address is associated with a breakpoint on access
+pub const MEM_BPA_BIT: u8 = 0b0001_0000; // Bit to signify that this address is associated with a breakpoint on read
 pub const MEM_CP_BIT: u8  = 0b0000_1000; // Bit to signify that this address is a ROM checkpoint
+pub const MEM_WRITTEN_BIT: u8 = 0b0000_0100; // Bit to signify that this address has been written to
+pub const MEM_EXEC_BIT: u8 = 0b0000_0010; // Bit to signify that an instruction has been executed at this address
+pub const MEM_BPW_BIT: u8 = 0b0000_0001; // Bit to signify that this address is associated with a breakpoint on write
+
+const UPPER_MEMORY_START: usize = 0xA0000;
+
+/// The value read from an address where no memory is installed. With nothing driving the
+/// data bus, a read either sees the pull-ups, or the last value left on the bus by capacitance.
+#[derive (Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum OpenBus {
+    Value(u8),
+    LastByte,
+}
 
-#[derive (Copy, Clone, Debug)]
+impl Default for OpenBus {
+    fn default() -> Self { OpenBus::Value(FLOATING_BUS_BYTE) }
+}
+
+/// A clock derived from a base crystal, by either dividing or multiplying its frequency.
+#[derive (Copy, Clone, Debug, PartialEq, Deserialize)]
 pub enum ClockFactor {
     Divisor(u8),
     Multiplier(u8)
 }
 
+impl ClockFactor {
+    /// Return the frequency of the derived clock, given the base crystal frequency in MHz.
+    pub fn mhz(&self, crystal_mhz: f64) -> f64 {
+        match *self {
+            ClockFactor::Divisor(n) => crystal_mhz / (n as f64),
+            ClockFactor::Multiplier(n) => crystal_mhz * (n as f64)
+        }
+    }
+
+    /// Convert a count of derived clock cycles to base crystal ticks.
+    pub fn cycles_to_ticks(&self, cycles: u32) -> u32 {
+        match *self {
+            ClockFactor::Divisor(n) => cycles * (n as u32),
+            ClockFactor::Multiplier(n) => cycles / (n as u32)
+        }
+    }
+
+    /// Convert a count of base crystal ticks to derived clock cycles. If a Divisor is set,
+    /// the result is rounded upwards.
+    pub fn ticks_to_cycles(&self, ticks: u32) -> u32 {
+        match *self {
+            ClockFactor::Divisor(n) => (ticks + (n as u32) - 1) / (n as u32),
+            ClockFactor::Multiplier(n) => ticks * (n as u32)
+        }
+    }
+}
+
 #[derive (Copy, Clone, Debug)]
 pub enum DeviceRunTimeUnit {
     SystemTicks(u32),
@@ -106,6 +156,35 @@ impl MemRangeDescriptor {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegionKind {
+    ConventionalRam,
+    UpperMemoryBlock,
+    Rom,
+    VideoRam,
+    MemoryMappedIo,
+    Unmapped,
+}
+
+/// An entry in the memory map. 'end' is exclusive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryMapEntry {
+    pub start: usize,
+    pub end: usize,
+    pub kind: RegionKind,
+    pub label: String,
+}
+
+/// A range of memory that inserts additional wait states on access, such as slow
+/// expansion RAM.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WaitStateRange {
+    pub address: usize,
+    pub size: usize,
+    pub wait_states: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum IoDeviceType {
     Ppi,
     Pit,
@@ -120,11 +199,8 @@ pub enum IoDeviceType {
     Cga,
     Ega,
     Vga,
-}
-
-pub enum IoDeviceDispatch {
-    Static(IoDeviceType),
-    Dynamic(Box<dyn IoDevice + 'static>)
+    Ems,
+    Dynamic(usize),
 }
 
 pub trait IoDevice {
@@ -164,8 +240,12 @@ pub struct BusInterface {
     mmio_map: Vec<(MemRangeDescriptor, IoDeviceType)>,
     mmio_data: MmioData,
     cursor: usize,
+    installed_ram: usize,
+    open_bus: OpenBus,
+    last_read: u8,
+    log_rom_writes: bool,
 
-    io_map: HashMap<u16, IoDeviceType>,
+    io_bus: IoBus,
     ppi: Option<Ppi>,
     pit: Option<Pit>,
     dma_counter: u16,
@@ -177,7 +257,11 @@ pub struct BusInterface {
     fdc: Option<FloppyController>,
     hdc: Option<HardDiskController>,
     mouse: Option<Mouse>,
+    ems: Option<EmsCard>,
     video: VideoCardDispatch,
+    io_trace: IoTrace,
+    mem_wait_ranges: Vec<WaitStateRange>,
+    io_wait_states: u32,
 
     timer_trigger1_armed: bool,
     timer_trigger2_armed: bool,
@@ -290,9 +374,12 @@ impl Default for BusInterface {
             mmio_map: Vec::new(),
             mmio_data: MmioData::new(),
             cursor: 0,
+            installed_ram: ADDRESS_SPACE,
+            open_bus: Default::default(),
+            last_read: 0,
+            log_rom_writes: false,
 
-
-            io_map: HashMap::new(),
+            io_bus: IoBus::new(),
             ppi: None,
             pit: None,
             dma_counter: 0,
@@ -304,8 +391,13 @@ impl Default for BusInterface {
             fdc: None,
             hdc: None,
             mouse: None,
+            ems: None,
             video: VideoCardDispatch::None,
 
+            io_trace: IoTrace::default(),
+            mem_wait_ranges: Vec::new(),
+            io_wait_states: DEFAULT_IO_WAIT_STATES,
+
             timer_trigger1_armed: false,
             timer_trigger2_armed: false,     
         }        
@@ -325,8 +417,12 @@ impl BusInterface {
             mmio_map: Vec::new(),
             mmio_data: MmioData::new(),            
             cursor: 0,
+            installed_ram: usize::min(machine_desc.conventional_ram as usize, ADDRESS_SPACE),
+            open_bus: Default::default(),
+            last_read: 0,
+            log_rom_writes: false,
 
-            io_map: HashMap::new(),
+            io_bus: IoBus::new(),
             ppi: None,
             pit: None,
             dma_counter: 0,
@@ -338,8 +434,13 @@ impl BusInterface {
             fdc: None,
             hdc: None,
             mouse: None,
+            ems: None,
             video: VideoCardDispatch::None,
 
+            io_trace: IoTrace::default(),
+            mem_wait_ranges: Vec::new(),
+            io_wait_states: DEFAULT_IO_WAIT_STATES,
+
             timer_trigger1_armed: false,
             timer_trigger2_armed: false,          
         }
@@ -349,6 +450,67 @@ impl BusInterface {
         self.memory.len()
     }
 
+    /// Return the amount of RAM installed, starting at address 0.
+    pub fn installed_ram(&self) -> usize {
+        self.installed_ram
+    }
+
+    /// Set the amount of RAM installed, starting at address 0. Reads from addresses above 
+    /// installed RAM that aren't ROM or device memory return the open bus value.
+    pub fn set_installed_ram(&mut self, size: usize) {
+        self.installed_ram = usize::min(size, self.memory.len());
+    }
+
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    /// Log writes to ROM, which are otherwise silently ignored.
+    pub fn set_log_rom_writes(&mut self, state: bool) {
+        self.log_rom_writes = state;
+    }
+
+    /// Return whether memory responds at the specified address, either installed RAM or ROM.
+    #[inline]
+    fn is_populated(&self, address: usize) -> bool {
+        address < self.installed_ram || self.memory_mask[address] & ROM_BIT != 0
+    }
+
+    /// Read a byte from memory that isn't memory-mapped, or the open bus value if no memory
+    /// is populated at the address.
+    #[inline]
+    fn read_memory_u8(&mut self, address: usize) -> u8 {
+        if self.is_populated(address) {
+            self.last_read = self.memory[address];
+            self.last_read
+        }
+        else {
+            match self.open_bus {
+                OpenBus::Value(byte) => byte,
+                OpenBus::LastByte => self.last_read
+            }
+        }
+    }
+
+    /// Write a byte to memory that isn't memory-mapped. Writes to ROM or unpopulated 
+    /// addresses are ignored. Returns whether the byte was stored.
+    #[inline]
+    fn write_memory_u8(&mut self, address: usize, data: u8) -> bool {
+        if self.memory_mask[address] & ROM_BIT != 0 {
+            if self.log_rom_writes {
+                log::debug!("Ignored write to ROM at {:05X}: {:02X}", address, data);
+            }
+            false
+        }
+        else if self.is_populated(address) {
+            self.memory[address] = data;
+            true
+        }
+        else {
+            false
+        }
+    }
+
     /// Register a memory-mapped device.
     /// 
     /// The MemoryMappedDevice trait's read & write methods will be called instead for memory in the range
@@ -420,6 +582,72 @@ impl BusInterface {
         &self.memory[start..start+len]
     }
 
+    /// Replace the contents of memory with 'image', such as one taken with get_slice_at() for
+    /// a save state. Memory-mapped devices are bypassed and the memory flags are unchanged.
+    pub fn restore_memory(&mut self, image: &[u8]) {
+        let len = image.len().min(self.memory.len());
+        self.memory[..len].copy_from_slice(&image[..len]);
+    }
+
+    /// Write bytes from an iterator to memory starting at 'start', for debugging and test setup. 
+    /// Writes go to memory-mapped devices but take no cycles, don't trigger breakpoints and 
+    /// don't set memory access flags. The CPU's prefetch queue is not updated. Nothing is 
+    /// written if the range is out of bounds. Bytes that fall on ROM or unpopulated memory are 
+    /// skipped and reported as an error once the rest of the range has been written.
+    fn debug_write_iter(&mut self, start: usize, len: usize, bytes: impl Iterator<Item = u8>) -> Result<(), MemError> {
+        if start + len > self.memory.len() {
+            return Err(MemError::WriteOutOfBoundsError)
+        }
+        let mut stored = true;
+        for (address, byte) in (start..start + len).zip(bytes) {
+            if self.write_mmio_u8(address, byte, 0).is_none() {
+                stored &= self.write_memory_u8(address, byte);
+            }
+        }
+        match stored {
+            true => Ok(()),
+            false => Err(MemError::WriteIgnoredError)
+        }
+    }
+
+    /// Fill a range of memory with a byte. See debug_write_iter().
+    pub fn debug_fill(&mut self, start: usize, len: usize, byte: u8) -> Result<(), MemError> {
+        self.debug_write_iter(start, len, std::iter::repeat(byte))
+    }
+
+    /// Fill a range of memory with a repeating pattern. See debug_write_iter().
+    pub fn debug_fill_pattern(&mut self, start: usize, len: usize, pattern: &[u8]) -> Result<(), MemError> {
+        if pattern.is_empty() {
+            return Ok(())
+        }
+        self.debug_write_iter(start, len, pattern.iter().copied().cycle())
+    }
+
+    /// Fill a range of memory with values incrementing from 'first', wrapping at 0xFF. 
+    /// See debug_write_iter().
+    pub fn debug_fill_incrementing(&mut self, start: usize, len: usize, first: u8) -> Result<(), MemError> {
+        self.debug_write_iter(start, len, (0..).map(|n: usize| first.wrapping_add(n as u8)))
+    }
+
+    /// Write a slice of bytes to memory. See debug_write_iter().
+    pub fn debug_write(&mut self, start: usize, bytes: &[u8]) -> Result<(), MemError> {
+        self.debug_write_iter(start, bytes.len(), bytes.iter().copied())
+    }
+
+    /// Read a range of memory as the CPU would see it, including memory-mapped devices, without
+    /// taking cycles or disturbing the open bus value.
+    pub fn debug_read(&mut self, start: usize, len: usize) -> Result<Vec<u8>, MemError> {
+        if start + len > self.memory.len() {
+            return Err(MemError::ReadOutOfBoundsError)
+        }
+        let last_read = self.last_read;
+        let bytes = (start..start + len)
+            .map(|address| self.read_u8(address, 0).map(|(byte, _)| byte))
+            .collect();
+        self.last_read = last_read;
+        bytes
+    }
+
     pub fn set_descriptor(&mut self, start: usize, size: usize, cycle_cost: u32, read_only: bool) {
         // TODO: prevent overlapping descriptors
         self.desc_vec.push({
@@ -432,11 +660,45 @@ impl BusInterface {
         });        
     }
 
+    /// Add a range of memory that inserts the specified number of wait states on each bus
+    /// cycle. Where ranges overlap, the most recently added range applies. Memory not covered
+    /// by any range has no wait states. Memory-mapped devices provide their own wait states.
+    pub fn add_mem_wait_states(&mut self, address: usize, size: usize, wait_states: u32) {
+        self.mem_wait_ranges.push(WaitStateRange { address, size, wait_states });
+    }
+
+    pub fn clear_mem_wait_states(&mut self) {
+        self.mem_wait_ranges.clear();
+    }
+
+    pub fn mem_wait_ranges(&self) -> &[WaitStateRange] {
+        &self.mem_wait_ranges
+    }
+
+    /// Return the number of wait states for a memory access at the specified address,
+    /// not including any memory-mapped device.
+    pub fn mem_wait_states(&self, address: usize) -> u32 {
+        self.mem_wait_ranges
+            .iter()
+            .rev()
+            .find(|r| address >= r.address && address < r.address + r.size)
+            .map_or(DEFAULT_WAIT_STATES, |r| r.wait_states)
+    }
+
+    /// Set the number of wait states inserted on each IO bus cycle.
+    pub fn set_io_wait_states(&mut self, wait_states: u32) {
+        self.io_wait_states = wait_states;
+    }
+
+    pub fn io_wait_states(&self) -> u32 {
+        self.io_wait_states
+    }
+
     pub fn clear(&mut self) {
 
-        // Remove return flags
+        // Remove return and access flags
         for byte_ref in &mut self.memory_mask {
-            *byte_ref &= !MEM_RET_BIT;
+            *byte_ref &= !(MEM_RET_BIT | MEM_WRITTEN_BIT | MEM_EXEC_BIT);
         } 
 
         // Set all bytes to 0
@@ -460,27 +722,21 @@ impl BusInterface {
     /// Convert a count of CPU cycles to system clock ticks based on the current CPU
     /// clock divisor.
     fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32)
-        }
+        self.cpu_factor.cycles_to_ticks(cycles)
     }    
 
     #[inline]
     /// Convert a count of system clock ticks to CPU cycles based on the current CPU
     /// clock divisor. If a clock Divisor is set, the dividend will be rounded upwards.
     fn system_ticks_to_cpu_cycles(&self, ticks: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => (ticks + (n as u32) - 1) / (n as u32),
-            ClockFactor::Multiplier(n) => ticks * (n as u32)
-        }
+        self.cpu_factor.ticks_to_cycles(ticks)
     }        
 
     pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
             if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES)
+                return Ok(self.mem_wait_states(address))
             }
             else {
                 // Handle memory-mapped devices
@@ -510,13 +766,18 @@ impl BusInterface {
                                     _ => {}
                                 }
                             }
+                            IoDeviceType::Ems => {
+                                if let Some(ems) = &mut self.ems {
+                                    return Ok(ems.get_read_wait(address, system_ticks));
+                                }
+                            }
                             _=> {}
                         }
                         return Err(MemError::MmioError)
                     }
                 }
                 // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES)
+                return Ok(self.mem_wait_states(address))
             }
         }
         Err(MemError::ReadOutOfBoundsError)        
@@ -526,7 +787,7 @@ impl BusInterface {
         if address < self.memory.len() {
             if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES)
+                return Ok(self.mem_wait_states(address))
             }
             else {
                 // Handle memory-mapped devices
@@ -556,13 +817,18 @@ impl BusInterface {
                                     _ => {}
                                 }
                             }
+                            IoDeviceType::Ems => {
+                                if let Some(ems) = &mut self.ems {
+                                    return Ok(ems.get_write_wait(address, system_ticks));
+                                }
+                            }
                             _=> {}
                         }
                         return Err(MemError::MmioError)
                     }
                 }
                 // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES)
+                return Ok(self.mem_wait_states(address))
             }
         }
         Err(MemError::ReadOutOfBoundsError)        
@@ -572,7 +838,7 @@ impl BusInterface {
         if address < self.memory.len() {
             if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
                 // Address is not mapped.
-                let b: u8 = self.memory[address];
+                let b: u8 = self.read_memory_u8(address);
                 return Ok((b, DEFAULT_WAIT_STATES))
             }
             else {
@@ -603,13 +869,18 @@ impl BusInterface {
                                     _ => {}
                                 }
                             }
+                            IoDeviceType::Ems => {
+                                if let Some(ems) = &mut self.ems {
+                                    return Ok(MemoryMappedDevice::read_u8(ems, address, system_ticks));
+                                }
+                            }
                             _=> {}
                         }
                         return Err(MemError::MmioError)
                     }
                 }
                 // We didn't match any mmio devices, return raw memory
-                let b: u8 = self.memory[address];
+                let b: u8 = self.read_memory_u8(address);
                 return Ok((b, DEFAULT_WAIT_STATES))
             }
         }
@@ -620,7 +891,7 @@ impl BusInterface {
         if address < self.memory.len() - 1 {
             if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
                 // Address is not mapped.
-                let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
+                let w: u16 = self.read_memory_u8(address) as u16 | (self.read_memory_u8(address + 1) as u16) << 8;
                 return Ok((w, DEFAULT_WAIT_STATES))
             }
             else {
@@ -651,13 +922,18 @@ impl BusInterface {
                                     _ => {}
                                 }
                             }
+                            IoDeviceType::Ems => {
+                                if let Some(ems) = &mut self.ems {
+                                    return Ok(MemoryMappedDevice::read_u16(ems, address, system_ticks));
+                                }
+                            }
                             _=> {}
                         }
                         return Err(MemError::MmioError)
                     }
                 }
                 // We didn't match any mmio devices, return raw memory
-                let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
+                let w: u16 = self.read_memory_u8(address) as u16 | (self.read_memory_u8(address + 1) as u16) << 8;
                 return Ok((w, DEFAULT_WAIT_STATES))            
             }
         }
@@ -666,67 +942,71 @@ impl BusInterface {
 
     pub fn write_u8(&mut self, address: usize, data: u8, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-                if self.memory_mask[address] & ROM_BIT == 0 {
-                    self.memory[address] = data;                
-                }
-                return Ok(DEFAULT_WAIT_STATES);
+            self.memory_mask[address] |= MEM_WRITTEN_BIT;
+            if let Some(wait_states) = self.write_mmio_u8(address, data, cycles) {
+                return Ok(wait_states);
             }
-            else {
+            // Address is not mapped.
+            self.write_memory_u8(address, data);
+            return Ok(DEFAULT_WAIT_STATES);
+        }
+        Err(MemError::ReadOutOfBoundsError)
+    }
 
-                let mut handled = false;
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
+    /// Write a byte to the memory-mapped device at 'address', if any. Returns the wait states 
+    /// for the write, or None if no device is mapped there.
+    fn write_mmio_u8(&mut self, address: usize, data: u8, cycles: u32) -> Option<u32> {
+        if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
+            return None;
+        }
+        for map_entry in &self.mmio_map {
+            if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
 
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+                // Convert cpu cycles to system ticks
+                let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
 
-                        match map_entry.1 {
-                            IoDeviceType::Cga | IoDeviceType::Ega | IoDeviceType::Vga => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let syswait = MemoryMappedDevice::write_u8( cga, address, data, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value. 
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        MemoryMappedDevice::write_u8( ega, address, data, system_ticks);
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        MemoryMappedDevice::write_u8(vga, address, data, system_ticks);
-                                    }
-                                    _ => {}
-                                }
+                match map_entry.1 {
+                    IoDeviceType::Cga | IoDeviceType::Ega | IoDeviceType::Vga => {
+                        match &mut self.video {
+                            VideoCardDispatch::Cga(cga) => {
+                                let syswait = MemoryMappedDevice::write_u8( cga, address, data, system_ticks);
+                                return Some(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value. 
                             }
-                            _=> {}
-                        }                        
-                        return Ok(map_entry.0.cycle_cost);
+                            #[cfg(feature = "ega")]
+                            VideoCardDispatch::Ega(ega) => {
+                                MemoryMappedDevice::write_u8( ega, address, data, system_ticks);
+                            }
+                            #[cfg(feature = "vga")]
+                            VideoCardDispatch::Vga(vga) => {
+                                MemoryMappedDevice::write_u8(vga, address, data, system_ticks);
+                            }
+                            _ => {}
+                        }
                     }
-                }
-                
-                // We didn't match any mmio devices, write to memory.
-                if self.memory_mask[address] & ROM_BIT == 0 {
-                    self.memory[address] = data;                
-                }
-                return Ok(DEFAULT_WAIT_STATES);
+                    IoDeviceType::Ems => {
+                        if let Some(ems) = &mut self.ems {
+                            return Some(MemoryMappedDevice::write_u8(ems, address, data, system_ticks));
+                        }
+                    }
+                    _=> {}
+                }                        
+                return Some(map_entry.0.cycle_cost);
             }
         }
-        Err(MemError::ReadOutOfBoundsError)
+        // We didn't match any mmio devices.
+        None
     }
 
     pub fn write_u16(&mut self, address: usize, data: u16, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() - 1 {
+            self.memory_mask[address] |= MEM_WRITTEN_BIT;
+            self.memory_mask[address + 1] |= MEM_WRITTEN_BIT;
             if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
                 // Address is not mapped.
 
                 // Little Endian is LO byte first
-                if self.memory_mask[address] & ROM_BIT == 0 {
-                    self.memory[address] = (data & 0xFF) as u8;
-                    self.memory[address+1] = (data >> 8) as u8;              
-                }
+                self.write_memory_u8(address, (data & 0xFF) as u8);
+                self.write_memory_u8(address + 1, (data >> 8) as u8);
                 return Ok(DEFAULT_WAIT_STATES);
             }
             else {
@@ -760,6 +1040,11 @@ impl BusInterface {
                                     _ => {}
                                 }
                             }
+                            IoDeviceType::Ems => {
+                                if let Some(ems) = &mut self.ems {
+                                    return Ok(MemoryMappedDevice::write_u16(ems, address, data, system_ticks));
+                                }
+                            }
                             _=> {}
                         }                             
                         return Ok(map_entry.0.cycle_cost);
@@ -767,10 +1052,8 @@ impl BusInterface {
                 }
 
                 // We didn't match any mmio devices, write to memory.
-                if self.memory_mask[address] & ROM_BIT == 0 {
-                    self.memory[address] = (data & 0xFF) as u8;
-                    self.memory[address+1] = (data >> 8) as u8;              
-                }
+                self.write_memory_u8(address, (data & 0xFF) as u8);
+                self.write_memory_u8(address + 1, (data >> 8) as u8);
                 return Ok(DEFAULT_WAIT_STATES);
             }
         }
@@ -804,6 +1087,123 @@ impl BusInterface {
         }
     }
 
+    /// Clear the written and executed flags for all of memory.
+    pub fn clear_access_flags(&mut self) {
+        for byte_ref in &mut self.memory_mask {
+            *byte_ref &= !(MEM_WRITTEN_BIT | MEM_EXEC_BIT);
+        }
+    }
+
+    /// Return the contiguous ranges of memory that have any of the specified flags set,
+    /// as (start, end) pairs with an exclusive end. Used with MEM_WRITTEN_BIT and 
+    /// MEM_EXEC_BIT to show which regions have been written to or executed.
+    pub fn flag_ranges(&self, flags: u8) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut start = None;
+
+        for (address, mask) in self.memory_mask.iter().enumerate() {
+            match (mask & flags != 0, start) {
+                (true, None) => start = Some(address),
+                (false, Some(s)) => {
+                    ranges.push((s, address));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            ranges.push((s, self.memory_mask.len()));
+        }
+        ranges
+    }
+
+    /// Classify the region containing 'address'. Memory-mapped devices take priority over 
+    /// ROM, which takes priority over RAM.
+    fn classify_address(&self, address: usize, ram_end: usize) -> (RegionKind, &'static str) {
+        let mmio = self.mmio_map
+            .iter()
+            .find(|(desc, _)| address >= desc.address && address < desc.address + desc.size);
+        let rom = self.desc_vec
+            .iter()
+            .find(|desc| desc.read_only && address >= desc.address && address < desc.address + desc.size);
+
+        match (mmio, rom) {
+            (Some((_, IoDeviceType::Cga)), _) => (RegionKind::VideoRam, "CGA"),
+            (Some((_, IoDeviceType::Ega)), _) => (RegionKind::VideoRam, "EGA"),
+            (Some((_, IoDeviceType::Vga)), _) => (RegionKind::VideoRam, "VGA"),
+            (Some((_, IoDeviceType::Ems)), _) => (RegionKind::MemoryMappedIo, "EMS"),
+            (Some(_), _) => (RegionKind::MemoryMappedIo, "MMIO"),
+            (None, Some(_)) => (RegionKind::Rom, "ROM"),
+            (None, None) if address < usize::min(ram_end, UPPER_MEMORY_START) => (RegionKind::ConventionalRam, "RAM"),
+            (None, None) if address < ram_end => (RegionKind::UpperMemoryBlock, "UMB"),
+            (None, None) => (RegionKind::Unmapped, "Unmapped"),
+        }
+    }
+
+    /// Return the kind of region containing the specified address.
+    pub fn region_kind(&self, address: usize) -> RegionKind {
+        if address >= self.memory.len() {
+            return RegionKind::Unmapped
+        }
+        self.classify_address(address, self.installed_ram).0
+    }
+
+    /// Return whether the specified address could plausibly hold code: ROM, device memory, 
+    /// or RAM that has been written to or had an image loaded into it. Used to catch jumps 
+    /// into unmapped or uninitialized memory.
+    pub fn is_code_address(&self, address: usize) -> bool {
+        match self.region_kind(address) {
+            RegionKind::Unmapped => false,
+            RegionKind::ConventionalRam | RegionKind::UpperMemoryBlock => {
+                self.memory_mask[address] & MEM_WRITTEN_BIT != 0
+                    || self.desc_vec.iter().any(|desc| address >= desc.address && address < desc.address + desc.size)
+            }
+            _ => true
+        }
+    }
+
+    /// Return a map of the address space, as a list of contiguous regions in ascending order.
+    /// 
+    /// Memory-mapped devices take priority over ROM, which takes priority over RAM. RAM
+    /// below 640K is conventional memory; RAM above it is reported as an upper memory block.
+    pub fn memory_map(&self) -> Vec<MemoryMapEntry> {
+
+        let ram_end = self.installed_ram;
+
+        // Collect the boundaries of every region, then classify each span between them.
+        let mut bounds = vec![0, UPPER_MEMORY_START, ram_end, self.memory.len()];
+        for (desc, _) in &self.mmio_map {
+            bounds.push(desc.address);
+            bounds.push(desc.address + desc.size);
+        }
+        for desc in &self.desc_vec {
+            bounds.push(desc.address);
+            bounds.push(desc.address + desc.size);
+        }
+        bounds.retain(|b| *b <= self.memory.len());
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut map: Vec<MemoryMapEntry> = Vec::new();
+
+        for span in bounds.windows(2) {
+            let (start, end) = (span[0], span[1]);
+            let (kind, label) = self.classify_address(start, ram_end);
+
+            // Merge with the previous region if it is of the same kind
+            match map.last_mut() {
+                Some(last) if last.kind == kind && last.label == label && last.end == start => {
+                    last.end = end;
+                }
+                _ => {
+                    map.push(MemoryMapEntry { start, end, kind, label: label.to_string() });
+                }
+            }
+        }
+
+        map
+    }
+
     /// Dump memory to a string representation.
     /// 
     /// Does not honor memory mappings.
@@ -879,10 +1279,18 @@ impl BusInterface {
         vec
     }
 
-    /// Dump memory to a vector of vectors of SyntaxTokens.
+    /// Return whether nothing responds at the specified address: no RAM, ROM, loaded image
+    /// or memory-mapped device.
+    pub fn is_unmapped(&self, address: usize) -> bool {
+        self.region_kind(address) == RegionKind::Unmapped && !self.is_populated(address)
+    }
+
+    /// Dump memory to a vector of vectors of SyntaxTokens. Each row contains an address
+    /// token, value tokens in the specified format, and 16 ASCII tokens. Unmapped bytes
+    /// are sent as MemoryUnmappedValue and MemoryUnmappedAscii placeholder tokens.
     /// 
     /// Does not honor memory mappings.
-    pub fn dump_flat_tokens(&self, address: usize, cursor: usize, mut size: usize) -> Vec<Vec<SyntaxToken>> {
+    pub fn dump_flat_tokens(&self, address: usize, cursor: usize, mut size: usize, format: MemoryDumpFormat) -> Vec<Vec<SyntaxToken>> {
 
         let mut vec: Vec<Vec<SyntaxToken>> = Vec::new();
 
@@ -916,38 +1324,78 @@ impl BusInterface {
                 )
             );
 
-            // Build hex byte value tokens
-            let mut i = 0;
-            for byte in dump_row {
-
-                if (display_address + i) == cursor {
-                    line_vec.push(
-                        SyntaxToken::MemoryByteHexValue(
-                            (display_address + i) as u32, 
-                            *byte,
-                            format!("{:02X}", *byte),
-                            true, // Set cursor on this byte
-                            0
-                        )
-                    );
+            // Build value tokens
+            match format {
+                MemoryDumpFormat::HexByte | MemoryDumpFormat::SignedDecimal => {
+                    for (i, byte) in dump_row.iter().enumerate() {
+                        let byte_address = display_address + i;
+                        // Set cursor on this byte if it matches
+                        let is_cursor = byte_address == cursor;
+
+                        line_vec.push(
+                            match format {
+                                _ if self.is_unmapped(byte_address) => SyntaxToken::MemoryUnmappedValue(
+                                    byte_address as u32,
+                                    1,
+                                    match format {
+                                        MemoryDumpFormat::SignedDecimal => format!("{:>4}", "--"),
+                                        _ => "--".to_string()
+                                    },
+                                    is_cursor
+                                ),
+                                MemoryDumpFormat::SignedDecimal => SyntaxToken::MemoryByteDecimalValue(
+                                    byte_address as u32,
+                                    *byte,
+                                    format!("{:4}", *byte as i8),
+                                    is_cursor,
+                                    0
+                                ),
+                                _ => SyntaxToken::MemoryByteHexValue(
+                                    byte_address as u32, 
+                                    *byte,
+                                    format!("{:02X}", *byte),
+                                    is_cursor,
+                                    0
+                                )
+                            }
+                        );
+                    }
                 }
-                else {
-                    line_vec.push(
-                        SyntaxToken::MemoryByteHexValue(
-                            (display_address + i) as u32, 
-                            *byte,
-                            format!("{:02X}", *byte),
-                            false,
-                            0
-                        )
-                    );
+                MemoryDumpFormat::HexWord => {
+                    for (i, word_bytes) in dump_row.chunks_exact(2).enumerate() {
+                        let word_address = display_address + i * 2;
+                        let word = u16::from_le_bytes([word_bytes[0], word_bytes[1]]);
+                        // Set cursor on this word if it contains the cursor byte
+                        let is_cursor = cursor == word_address || cursor == word_address + 1;
+
+                        if self.is_unmapped(word_address) && self.is_unmapped(word_address + 1) {
+                            line_vec.push(
+                                SyntaxToken::MemoryUnmappedValue(word_address as u32, 2, "----".to_string(), is_cursor)
+                            );
+                            continue;
+                        }
+
+                        line_vec.push(
+                            SyntaxToken::MemoryWordHexValue(
+                                word_address as u32,
+                                word,
+                                format!("{:04X}", word),
+                                is_cursor,
+                                0
+                            )
+                        );
+                    }
                 }
-                i += 1;
             }
 
             // Build ASCII representation tokens
             let mut i = 0;
             for byte in dump_row {
+                if self.is_unmapped(display_address + i) {
+                    line_vec.push(SyntaxToken::MemoryUnmappedAscii((display_address + i) as u32, ".".to_string()));
+                    i += 1;
+                    continue;
+                }
                 let char_str = match byte {
                     00..=31 => ".".to_string(),
                     32..=127 => format!("{}", *byte as char),
@@ -1007,7 +1455,7 @@ impl BusInterface {
         vec
     }
 
-    pub fn get_memory_debug(&mut self, address: usize) -> MemoryDebug {
+    pub fn get_memory_debug(&mut self, address: usize, cpu_type: CpuType) -> MemoryDebug {
         let mut debug = MemoryDebug {
             addr: format!("{:05X}", address),
             byte: String::new(),
@@ -1032,7 +1480,7 @@ impl BusInterface {
 
         self.seek(address);
 
-        debug.instr = match Cpu::decode(self) {
+        debug.instr = match Cpu::decode(self, cpu_type) {
             Ok(instruction) => {
                 format!("{}", instruction)
             },
@@ -1043,6 +1491,21 @@ impl BusInterface {
         debug
     }
     
+    /// Map the ports of a built-in device. A conflict between built-in devices is an error
+    /// in the machine definition, so it is logged and the conflicting device is left unmapped.
+    fn map_io_ports(&mut self, device: IoDeviceType, ports: &[u16]) {
+        if let Err(e) = self.io_bus.map_ports(device, ports) {
+            log::error!("Couldn't map IO ports for {:?}: {}", device, e);
+        }
+    }
+
+    /// Register an additional IO device on the ports given by its port_list(). The bus takes
+    /// ownership of the device. Returns the device id, or an error if any of its ports are
+    /// already mapped.
+    pub fn register_io_device(&mut self, device: Box<dyn IoDevice>) -> Result<usize, IoBusError> {
+        self.io_bus.register(device)
+    }
+
     pub fn install_devices(
         &mut self, 
         video_type: VideoType, 
@@ -1055,9 +1518,9 @@ impl BusInterface {
         // Create PPI if PPI is defined for this machine type
         if machine_desc.have_ppi {
             self.ppi = Some(Ppi::new(machine_desc.machine_type, video_type, machine_desc.num_floppies));
-            // Add PPI ports to io_map
+            // Add PPI ports to the IO bus
             let port_list = self.ppi.as_mut().unwrap().port_list();
-            self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Ppi)));
+            self.map_io_ports(IoDeviceType::Ppi, &port_list);
         }
 
         // Create the PIT. One PIT will always exist, but it may be an 8253 or 8254. 
@@ -1075,9 +1538,9 @@ impl BusInterface {
                 machine_desc.timer_divisor
             );
 
-        // Add PIT ports to io_map
+        // Add PIT ports to the IO bus
         let port_list = pit.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Pit)));
+        self.map_io_ports(IoDeviceType::Pit, &port_list);
         
         // Tie gates for pit channel 0 & 1 high. 
         pit.set_channel_gate(0, true, self);
@@ -1088,38 +1551,38 @@ impl BusInterface {
         // Create DMA. One DMA controller will always exist.
         let dma1 = DMAController::new();
         
-        // Add DMA ports to io_map
+        // Add DMA ports to the IO bus
         let port_list = dma1.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::DmaPrimary)));
+        self.map_io_ports(IoDeviceType::DmaPrimary, &port_list);
         self.dma1 = Some(dma1);
 
         // Create PIC. One PIC will always exist.
         let pic1 = Pic::new();
-        // Add PIC ports to io_map
+        // Add PIC ports to the IO bus
         let port_list = pic1.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::PicPrimary)));
+        self.map_io_ports(IoDeviceType::PicPrimary, &port_list);
         self.pic1 = Some(pic1);
 
         // Create FDC. 
         let fdc = FloppyController::new();
-        // Add FDC ports to io_map
+        // Add FDC ports to the IO bus
         let port_list = fdc.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::FloppyController)));
+        self.map_io_ports(IoDeviceType::FloppyController, &port_list);
         self.fdc = Some(fdc);
 
         // Create HDC. This should probably be specified in the MachineDesc with an option to override it
         // (Such as using a XTIDE instead of Xebec on PC & XT, perhaps)
         let hdc = HardDiskController::new(DRIVE_TYPE2_DIP);
-        // Add HDC ports to io_map
+        // Add HDC ports to the IO bus
         let port_list = hdc.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::HardDiskController)));
+        self.map_io_ports(IoDeviceType::HardDiskController, &port_list);
         self.hdc = Some(hdc);   
 
         // Create serial port.
         let serial = SerialPortController::new();
-        // Add Serial Controller ports to io_map
+        // Add Serial Controller ports to the IO bus
         let port_list = serial.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Serial)));
+        self.map_io_ports(IoDeviceType::Serial, &port_list);
         self.serial = Some(serial);
 
         // Create mouse.
@@ -1131,7 +1594,7 @@ impl BusInterface {
             VideoType::CGA => {
                 let cga = CGACard::new(video_trace, video_frame_debug);
                 let port_list = cga.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Cga)));
+                self.map_io_ports(IoDeviceType::Cga, &port_list);
 
                 let mem_descriptor = MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false );
                 self.register_map(IoDeviceType::Cga, mem_descriptor);
@@ -1142,7 +1605,7 @@ impl BusInterface {
             VideoType::EGA => {
                 let ega = EGACard::new();
                 let port_list = ega.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Ega)));
+                self.map_io_ports(IoDeviceType::Ega, &port_list);
 
                 let mem_descriptor = MemRangeDescriptor::new(ega::EGA_GFX_ADDRESS, ega::EGA_GFX_PLANE_SIZE, false );
                 self.register_map(IoDeviceType::Ega, mem_descriptor);
@@ -1153,7 +1616,7 @@ impl BusInterface {
             VideoType::VGA => {
                 let vga = VGACard::new(video_trace);
                 let port_list = vga.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Vga)));
+                self.map_io_ports(IoDeviceType::Vga, &port_list);
 
                 //let mem_descriptor = MemRangeDescriptor::new(0xB8000, vga::VGA_TEXT_PLANE_SIZE, false );
                 //cpu.bus_mut().register_map(IoDeviceType::Vga, mem_descriptor);
@@ -1169,26 +1632,47 @@ impl BusInterface {
             }
         }
     
+        self.installed_ram = usize::min(machine_desc.conventional_ram as usize, self.memory.len());
         self.machine_desc = Some(machine_desc.clone());
     }
 
+    /// Install an EMS board with its page frame at 'page_frame', page registers at 'io_base',
+    /// and 'size' bytes of expanded memory.
+    pub fn install_ems(&mut self, page_frame: usize, io_base: u16, size: usize) {
+        let ems = EmsCard::new(page_frame, io_base, size);
+        let port_list = ems.port_list();
+        self.map_io_ports(IoDeviceType::Ems, &port_list);
+
+        let mem_descriptor = MemRangeDescriptor::new(ems.page_frame(), ems::EMS_PAGE_FRAME_SIZE, false);
+        self.register_map(IoDeviceType::Ems, mem_descriptor);
+
+        log::debug!(
+            "Installed EMS: page frame {:05X}, port {:03X}, {} pages", 
+            ems.page_frame(), 
+            io_base, 
+            ems.logical_pages()
+        );
+        self.ems = Some(ems);
+    }
+
     /// Return whether NMI is enabled.
     /// On the 5150 & 5160, NMI generation can be disabled via the PPI.
     pub fn nmi_enabled(&self) -> bool {
 
-        if self.machine_desc.unwrap().have_ppi {
-
-            if let Some(ppi) = &self.ppi {
-                ppi.nmi_enabled()
+        match &self.machine_desc {
+            Some(desc) if desc.have_ppi => {
+                if let Some(ppi) = &self.ppi {
+                    ppi.nmi_enabled()
+                }
+                else {
+                    true
+                }
             }
-            else {
+            _ => {
+                // TODO: Determine what controls NMI masking on AT (i8042?)
                 true
             }
         }
-        else {
-            // TODO: Determine what controls NMI masking on AT (i8042?)
-            true
-        }
     }
 
     pub fn run_devices(
@@ -1364,6 +1848,9 @@ impl BusInterface {
     pub fn reset_devices(&mut self) {
         self.pit.as_mut().unwrap().reset();
         self.pic1.as_mut().unwrap().reset();
+        if let Some(ems) = &mut self.ems {
+            ems.reset();
+        }
         //self.video.borrow_mut().reset();
     }
 
@@ -1387,17 +1874,10 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(d) => {
-                d as u32 * cycles
-            }
-            ClockFactor::Multiplier(m) => {
-                cycles / m as u32
-            }
-        };
+        let sys_ticks = self.cpu_factor.cycles_to_ticks(cycles);
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
-        if let Some(device_id) = self.io_map.get(&port) {
+        if let Some(device_id) = self.io_bus.get(port) {
             match device_id {
                 IoDeviceType::Ppi => {
                     if let Some(ppi) = &mut self.ppi {
@@ -1479,6 +1959,20 @@ impl BusInterface {
                         VideoCardDispatch::None => NO_IO_BYTE
                     }
                 }
+                IoDeviceType::Ems => {
+                    if let Some(ems) = &mut self.ems {
+                        IoDevice::read_u8(ems, port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::Dynamic(id) => {
+                    match self.io_bus.device_mut(id) {
+                        Some(device) => device.read_u8(port, DeviceRunTimeUnit::SystemTicks(sys_ticks)),
+                        None => NO_IO_BYTE
+                    }
+                }
                 _ => {
                     NO_IO_BYTE
                 }
@@ -1507,17 +2001,10 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(d) => {
-                d as u32 * cycles
-            }
-            ClockFactor::Multiplier(m) => {
-                cycles / m as u32
-            }
-        };
+        let sys_ticks = self.cpu_factor.cycles_to_ticks(cycles);
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
-        if let Some(device_id) = self.io_map.get(&port) {
+        if let Some(device_id) = self.io_bus.get(port) {
             match device_id {
                 IoDeviceType::Ppi => {
                     if let Some(mut ppi) = self.ppi.take() {
@@ -1589,12 +2076,37 @@ impl BusInterface {
                         VideoCardDispatch::None => {}
                     }
                 }
+                IoDeviceType::Ems => {
+                    if let Some(ems) = &mut self.ems {
+                        // EMS page register writes do not need bus.
+                        IoDevice::write_u8(ems, port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::Dynamic(id) => {
+                    if let Some(mut device) = self.io_bus.take_device(id) {
+                        device.write_u8(port, data, Some(self), DeviceRunTimeUnit::SystemTicks(sys_ticks));
+                        self.io_bus.restore_device(id, device);
+                    }
+                }
                 _ => {}
             }
         }
 
     }
 
+    /// Record an IO bus cycle in the IO trace and port history. See IoTrace::record().
+    pub fn record_io_access(&mut self, port: u16, data: u8, direction: IoDirection, cycle: u64) {
+        self.io_trace.record(port, data, direction, cycle);
+    }
+
+    pub fn io_trace(&self) -> &IoTrace {
+        &self.io_trace
+    }
+
+    pub fn io_trace_mut(&mut self) -> &mut IoTrace {
+        &mut self.io_trace
+    }
+
     // Device accessors
     pub fn pit(&self) -> &Option<Pit> {
         &self.pit
@@ -1616,6 +2128,16 @@ impl BusInterface {
         &mut self.dma1
     }
 
+    /// Return whether DMA channel 0 will take the bus for DRAM refresh. Without a DMA controller 
+    /// nothing can mask the refresh, so it is left to the CPU's refresh simulation option.
+    pub fn dram_refresh_enabled(&self) -> bool {
+        self.dma1.as_ref().map_or(true, |dma| dma.refresh_enabled())
+    }
+
+    pub fn ems(&self) -> &Option<EmsCard> {
+        &self.ems
+    }
+
     pub fn serial_mut(&mut self) -> &mut Option<SerialPortController> {
         &mut self.serial
     }
@@ -1671,4 +2193,137 @@ impl BusInterface {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_map() {
+        let mut bus = BusInterface::default();
+
+        let map = bus.memory_map();
+        assert_eq!(map.first().unwrap().start, 0);
+        assert_eq!(map.last().unwrap().end, 0x100000);
+        assert!(map.windows(2).all(|w| w[0].end == w[1].start));
+        assert!(map.iter().any(|e| e.kind == RegionKind::ConventionalRam && e.start == 0 && e.end == 0xA0000));
+
+        // ROM is reported as its own region
+        bus.copy_from(&[0xCC; 0x2000], 0xFE000, 0, true).unwrap();
+        let map = bus.memory_map();
+        assert!(map.iter().any(|e| e.kind == RegionKind::Rom && e.start == 0xFE000 && e.end == 0x100000));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = BusInterface::default();
+        bus.set_installed_ram(0x20000);
+        bus.write_u8(0x1F000, 0x31, 0).unwrap();
+
+        // Unpopulated memory reads as the open bus value and ignores writes
+        assert_eq!(bus.read_u8(0x30000, 0).unwrap().0, FLOATING_BUS_BYTE);
+        bus.write_u8(0x30000, 0x12, 0).unwrap();
+        assert_eq!(bus.read_u8(0x30000, 0).unwrap().0, FLOATING_BUS_BYTE);
+
+        bus.set_open_bus(OpenBus::Value(0xFF));
+        assert_eq!(bus.read_u8(0x30000, 0).unwrap().0, 0xFF);
+
+        bus.set_open_bus(OpenBus::LastByte);
+        assert_eq!(bus.read_u8(0x1F000, 0).unwrap().0, 0x31);
+        assert_eq!(bus.read_u8(0x30000, 0).unwrap().0, 0x31);
+    }
+
+    #[test]
+    fn test_debug_memory_access() {
+        let mut bus = BusInterface::default();
+
+        bus.debug_fill(0x100, 4, 0xAA).unwrap();
+        bus.debug_fill_pattern(0x104, 5, &[0x12, 0x34]).unwrap();
+        bus.debug_fill_incrementing(0x109, 3, 0xFE).unwrap();
+        assert_eq!(
+            bus.debug_read(0x100, 12).unwrap(),
+            vec![0xAA, 0xAA, 0xAA, 0xAA, 0x12, 0x34, 0x12, 0x34, 0x12, 0xFE, 0xFF, 0x00]
+        );
+
+        // A range that runs off the end of memory is rejected without writing anything
+        assert!(matches!(bus.debug_fill(0xFFFFE, 4, 0x55), Err(MemError::WriteOutOfBoundsError)));
+        assert_eq!(bus.debug_read(0xFFFFE, 2).unwrap(), vec![0x00, 0x00]);
+        assert!(bus.debug_read(0xFFFFE, 4).is_err());
+
+        // Debug writes leave the access flags alone
+        assert!(bus.flag_ranges(MEM_WRITTEN_BIT).is_empty());
+
+        // Bytes that land on ROM or unpopulated memory are skipped and reported
+        bus.set_installed_ram(0x20000);
+        bus.copy_from(&[0xCC; 0x10], 0xFE000, 0, true).unwrap();
+        assert!(matches!(bus.debug_write(0xFDFFF, &[0x11, 0x22]), Err(MemError::WriteIgnoredError)));
+        assert_eq!(bus.debug_read(0xFE000, 1).unwrap(), vec![0xCC]);
+        assert!(matches!(bus.debug_fill(0x1FFFF, 2, 0x33), Err(MemError::WriteIgnoredError)));
+        assert_eq!(bus.debug_read(0x1FFFF, 1).unwrap(), vec![0x33]);
+    }
+
+    #[test]
+    fn test_mem_wait_states() {
+        let mut bus = BusInterface::default();
+        assert_eq!(bus.mem_wait_states(0x0100), 0);
+
+        bus.add_mem_wait_states(0x0000, 0x1000, 3);
+        assert_eq!(bus.mem_wait_states(0x0100), 3);
+        assert_eq!(bus.mem_wait_states(0x10000), 0);
+
+        bus.clear_mem_wait_states();
+        assert_eq!(bus.mem_wait_states(0x0100), 0);
+    }
+
+    #[test]
+    fn test_io_device_dispatch() {
+        use std::{cell::RefCell, rc::Rc};
+
+        struct LatchDevice {
+            writes: Rc<RefCell<Vec<(u16, u8)>>>,
+        }
+
+        impl IoDevice for LatchDevice {
+            fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+                0xA0 | (port & 0x0F) as u8
+            }
+            fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+                self.writes.borrow_mut().push((port, data));
+            }
+            fn port_list(&self) -> Vec<u16> {
+                vec![0x300, 0x301]
+            }
+        }
+
+        let mut bus = BusInterface::default();
+
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        bus.register_io_device(Box::new(LatchDevice { writes: writes.clone() })).unwrap();
+        assert!(bus.register_io_device(Box::new(LatchDevice { writes: writes.clone() })).is_err());
+
+        bus.io_write_u8(0x300, 0x34, 0);
+        bus.io_write_u8(0x301, 0x12, 0);
+        assert_eq!(*writes.borrow(), vec![(0x300, 0x34), (0x301, 0x12)]);
+        assert_eq!(bus.io_read_u8(0x301, 0), 0xA1);
+    }
+
+    #[test]
+    fn test_dump_unmapped_tokens() {
+        let mut bus = BusInterface::default();
+        bus.set_installed_ram(0x10000);
+
+        let rows = bus.dump_flat_tokens(0xFFF0, 0x10000, 32, MemoryDumpFormat::HexByte);
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0][1..17].iter().all(|t| matches!(t, SyntaxToken::MemoryByteHexValue(..))));
+        assert!(rows[0][17..].iter().all(|t| matches!(t, SyntaxToken::MemoryByteAsciiValue(..))));
+
+        // Each placeholder keeps its address so the hex and ascii columns stay linked
+        assert!(matches!(&rows[1][1], SyntaxToken::MemoryUnmappedValue(0x10000, 1, s, true) if s == "--"));
+        assert!(matches!(&rows[1][17], SyntaxToken::MemoryUnmappedAscii(0x10000, s) if s == "."));
+        assert!(matches!(&rows[1][32], SyntaxToken::MemoryUnmappedAscii(0x1000F, _)));
+
+        let rows = bus.dump_flat_tokens(0x10000, 0, 16, MemoryDumpFormat::HexWord);
+        assert!(matches!(&rows[0][1], SyntaxToken::MemoryUnmappedValue(0x10000, 2, s, false) if s == "----"));
+    }
+}
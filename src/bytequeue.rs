@@ -1,4 +1,6 @@
-#[derive (Copy, Clone)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive (Copy, Clone, Serialize, Deserialize)]
 pub enum QueueType {
     First,
     Subsequent
@@ -4,8 +4,17 @@ use std::str::FromStr;
 use bpaf::{Bpaf};
 use serde_derive::{Deserialize};
 
+use crate::cpu_common::CpuType;
+use crate::cpu_808x::RunawayAction;
+use crate::bus::{ClockFactor, OpenBus};
+
 const fn _default_true() -> bool { true }
 const fn _default_false() -> bool { true }
+const fn _default_io_wait_states() -> u32 { 1 }
+const fn _default_off_rails_threshold() -> u32 { crate::cpu_808x::OFF_RAILS_DEFAULT_THRESHOLD }
+const fn _default_ems_page_frame() -> u16 { (crate::devices::ems::EMS_DEFAULT_PAGE_FRAME >> 4) as u16 }
+const fn _default_ems_io_base() -> u16 { crate::devices::ems::EMS_DEFAULT_IO_BASE }
+const fn _default_ems_size() -> u32 { (crate::devices::ems::EMS_DEFAULT_SIZE / 1024) as u32 }
 
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Debug, Bpaf, Deserialize, Hash, Eq, PartialEq)] 
@@ -128,7 +137,38 @@ pub struct RomOverride {
     pub path: PathBuf,
     pub address: u32,
     pub offset: u32,
-    pub org: RomFileOrganization
+    pub org: RomFileOrganization,
+    #[serde(default)]
+    pub checksum: bool,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct MemoryWaitStates {
+    pub address: u32,
+    pub size: u32,
+    pub wait_states: u32,
+}
+
+/// The geometry of a floppy image with a non-standard size. 'image' is the name of the image 
+/// as listed in the floppy menu, relative to the floppy directory.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FloppyGeometry {
+    pub image: String,
+    pub cylinders: u8,
+    pub heads: u8,
+    pub sectors: u8,
+}
+
+/// An EMS board. 'page_frame' is the segment of the page frame and 'size' is the amount of
+/// expanded memory in kilobytes.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Ems {
+    #[serde(default = "_default_ems_page_frame")]
+    pub page_frame: u16,
+    #[serde(default = "_default_ems_io_base")]
+    pub io_base: u16,
+    #[serde(default = "_default_ems_size")]
+    pub size: u32,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)] 
@@ -188,8 +228,18 @@ pub struct Emulator {
     #[serde(default)]
     pub pit_output_file: Option<String>,
     #[serde(default = "_default_false")]
-    pub pit_output_int_trigger: bool
+    pub pit_output_int_trigger: bool,
+
+    #[serde(default)]
+    pub symbol_file: Option<String>,
+    #[serde(default)]
+    pub comment_file: Option<String>,
 
+    #[serde(default)]
+    pub io_history_ports: Vec<u16>,
+
+    #[serde(default)]
+    pub floppy_geometry: Vec<FloppyGeometry>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -212,6 +262,16 @@ pub struct Machine {
     pub model: MachineType,
     pub rom_override: Option<Vec<RomOverride>>,
     pub turbo: bool,
+    #[serde(default)]
+    pub cpu_clock: Option<ClockFactor>,
+    #[serde(default)]
+    pub cpu_turbo_clock: Option<ClockFactor>,
+    #[serde(default)]
+    pub ram_size: Option<u32>,
+    #[serde(default)]
+    pub open_bus: Option<OpenBus>,
+    #[serde(default)]
+    pub ems: Option<Ems>,
     pub video: VideoType,
     pub hdc: HardDiskControllerType,
     pub drive0: Option<String>,
@@ -221,9 +281,32 @@ pub struct Machine {
 
 #[derive(Debug, Deserialize)]
 pub struct Cpu {
+    // Overrides the CPU type specified by the machine model
+    #[serde(default)]
+    pub cpu_type: Option<CpuType>,
     pub wait_states_enabled: bool,
     pub off_rails_detection: bool,
+    #[serde(default = "_default_off_rails_threshold")]
+    pub off_rails_threshold: u32,
+    #[serde(default)]
+    pub off_rails_action: Option<RunawayAction>,
     pub instruction_history: bool,
+    #[serde(default)]
+    pub fpu_present: bool,
+    #[serde(default = "_default_true")]
+    pub rep_prefix_bug: bool,
+    #[serde(default)]
+    pub strict_undefined_flags: bool,
+    #[serde(default)]
+    pub jump_sanity: bool,
+    #[serde(default)]
+    pub jump_sanity_action: Option<RunawayAction>,
+    #[serde(default)]
+    pub instruction_profile: bool,
+    #[serde(default)]
+    pub memory_wait_states: Option<Vec<MemoryWaitStates>>,
+    #[serde(default = "_default_io_wait_states")]
+    pub io_wait_states: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -267,6 +350,9 @@ pub struct CmdLineArgs {
     #[bpaf(long, switch)]
     pub off_rails_detection: bool,
 
+    #[bpaf(long)]
+    pub off_rails_threshold: Option<u32>,
+
     #[bpaf(long, switch)]
     pub correct_aspect: bool,      
 
@@ -337,6 +423,10 @@ impl ConfigFileParams {
 
         self.cpu.off_rails_detection |= shell_args.off_rails_detection;
 
+        if let Some(threshold) = shell_args.off_rails_threshold {
+            self.cpu.off_rails_threshold = threshold;
+        }
+
         self.input.reverse_mouse_buttons |= shell_args.reverse_mouse_buttons;
     }
 }
@@ -1,7 +1,9 @@
 use crate::cpu_808x::*;
 use crate::cpu_808x::biu::*;
 
-#[derive(Copy, Clone, Debug)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum AddressingMode {
     BxSi,
     BxDi,
@@ -46,7 +48,7 @@ impl<'a> Cpu<'a> {
     }
 
     pub fn calc_linear_address(segment: u16, offset: u16) -> u32 {
-        (((segment as u32) << 4) + offset as u32) & 0xFFFFFu32
+        (((segment as u32) << 4) + offset as u32) & ADDRESS_MASK
     }
 
     pub fn relative_offset_u16(base: u16, offset: i16) -> u16 {
@@ -62,7 +64,7 @@ impl<'a> Cpu<'a> {
             Segment::DS => self.ds,
             Segment::SS => self.ss,
         };
-        (((segment_val as u32) << 4) + offset as u32) & 0xFFFFFu32
+        (((segment_val as u32) << 4) + offset as u32) & ADDRESS_MASK
     }
 
     pub fn segment_override(seg_override: SegmentOverride, seg_default: Segment) -> Segment {
@@ -109,12 +111,15 @@ impl<'a> Cpu<'a> {
         // ds:[bx+si+disp]   11 cycles
         // ds:[bx+di+disp]   12 cycles
         // ss:[bp+si+disp]   12 cycles
-        // ss:[bp+si+disp]   8 cycles
-        // ds:[si+disp]      
-        // ds:[di+disp]
-        // ss:[bp+disp]
-        // ds:[bx+disp]
+        // ss:[bp+di+disp]   11 cycles
+        // ds:[si+disp]      9 cycles
+        // ds:[di+disp]      9 cycles
+        // ss:[bp+disp]      9 cycles
+        // ds:[bx+disp]      9 cycles
         // Reference: (210912·001, Table 1-15)
+        //
+        // These cycles are not spent here. The EA microcode is run when the modrm is decoded, 
+        // see the pre- and post-displacement costs in ModRmByte::read().
 
         // Override default segments based on prefix
         let (segment_value_base_ds, segment_base_ds) = match segment_override {
@@ -228,9 +233,34 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    /// Check that an operand's width matches the width of the read or write being performed
+    /// on it. A mismatch indicates that decode and execute disagree about an instruction. Tests 
+    /// panic on it; otherwise it's recorded, and execute_instruction() reports it as an 
+    /// ExecutionError so that arbitrary byte streams can still be executed without aborting.
+    #[cfg(debug_assertions)]
+    fn assert_operand_size(&mut self, operand: OperandType, size: OperandSize) {
+        let bits = |s: OperandSize| if s == OperandSize::Operand8 { 8 } else { 16 };
+        let operand_size = operand.size();
+
+        if operand_size != OperandSize::NoSize && operand_size != size {
+            let msg = format!(
+                "{}-bit access to {}-bit operand",
+                bits(size),
+                bits(operand_size)
+            );
+            #[cfg(test)]
+            panic!("{} in '{}' (opcode {:02X})", msg, self.i, self.i.opcode);
+            #[cfg(not(test))]
+            self.operand_size_error.get_or_insert(msg);
+        }
+    }
+
     /// Return the value of an 8-bit Operand
     pub fn read_operand8(&mut self, operand: OperandType, seg_override: SegmentOverride) -> Option<u8> {
 
+        #[cfg(debug_assertions)]
+        self.assert_operand_size(operand, OperandSize::Operand8);
+
         // The operand enums contain values peeked from instruction fetch. However for accurate cycle
         // timing, we have to fetch them again now.
 
@@ -284,6 +314,9 @@ impl<'a> Cpu<'a> {
     /// Return the value of a 16-bit Operand
     pub fn read_operand16(&mut self, operand: OperandType, seg_override: SegmentOverride) -> Option<u16> {
 
+        #[cfg(debug_assertions)]
+        self.assert_operand_size(operand, OperandSize::Operand16);
+
         // The operand enums contain values peeked from instruction fetch. However for accurate cycle
         // timing, we have to fetch them again now.
 
@@ -413,6 +446,9 @@ impl<'a> Cpu<'a> {
     /// Write an 8-bit value to the specified destination operand
     pub fn write_operand8(&mut self, operand: OperandType, seg_override: SegmentOverride, value: u8, flag: ReadWriteFlag) {
 
+        #[cfg(debug_assertions)]
+        self.assert_operand_size(operand, OperandSize::Operand8);
+
         match operand {
             OperandType::Offset8(_offset8) => {
                 let offset = self.q_read_u16(QueueType::Subsequent, QueueReader::Eu);
@@ -446,6 +482,9 @@ impl<'a> Cpu<'a> {
     // TODO: implement cycle cost
     pub fn write_operand16(&mut self, operand: OperandType, seg_override: SegmentOverride, value: u16, flag: ReadWriteFlag) {
 
+        #[cfg(debug_assertions)]
+        self.assert_operand_size(operand, OperandSize::Operand16);
+
         match operand {
             OperandType::Offset16(_offset16) => {
                 let offset = self.q_read_u16(QueueType::Subsequent, QueueReader::Eu);
@@ -467,13 +506,19 @@ impl<'a> Cpu<'a> {
                     Register16::DI => self.set_register16(Register16::DI, value),
                     Register16::ES => self.set_register16(Register16::ES, value),
                     Register16::CS => {
-                        self.biu_update_cs(value); // Update the PC for the new CS segment.
+                        // MOV CS, r/m16 is valid on the 8088. The PC is rebased into the new CS segment
+                        // but the queue isn't flushed, so any bytes already prefetched from the old 
+                        // segment execute before fetching resumes at the same offset in the new one.
+                        self.biu_update_cs(value);
                     },
                     Register16::SS => {
                         self.set_register16(Register16::SS, value);
                         // Technically only MOV ss, nn instructions will inhibit interrupts for one instruction
-                        // Other writes may not. 
+                        // Other writes may not. A trap is also held off until after the next instruction,
+                        // so that SS:SP can be updated together.
                         self.interrupt_inhibit = true;
+                        self.nmi_inhibit = true;
+                        self.trap_suppressed = true;
                     },
                     Register16::DS => self.set_register16(Register16::DS, value),
                     _=> panic!("read_operand16(): Invalid Register16 operand")
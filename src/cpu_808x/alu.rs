@@ -48,6 +48,8 @@ impl<'a> Cpu<'a> {
         self.set_parity_flag_from_u8(result);
     }
 
+    /// Set the Sign, Zero and Parity flags from a 16-bit result. As on the 8088, parity is
+    /// calculated from the low byte of the result only.
     pub fn set_szp_flags_from_result_u16(&mut self, result: u16) {
         // Set Sign flag to state of Sign (HO) bit
         self.set_flag_state(Flag::Sign, result & 0x8000 != 0);
@@ -406,6 +408,13 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    /// AF is undefined after logical operations. Hardware clears it.
+    fn clear_undefined_aux_carry(&mut self) {
+        if self.strict_undefined_flags {
+            self.clear_flag(Flag::AuxCarry);
+        }
+    }
+
     /// Perform various 8-bit math operations
     pub fn math_op8(&mut self, opcode: Mnemonic, operand1: u8, operand2: u8) -> u8 {
 
@@ -485,6 +494,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u8(result);
                 result
             }
@@ -493,6 +503,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u8(result);
                 result
             }
@@ -501,6 +512,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u8(result);
                 // TEST does not modify operand1
                 operand1
@@ -510,6 +522,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u8(result);
                 result
             }
@@ -606,6 +619,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u16(result);
                 result
             }
@@ -614,6 +628,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u16(result);
                 result
             }        
@@ -622,6 +637,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u16(result);
                 // Do not modify operand
                 operand1  
@@ -631,6 +647,7 @@ impl<'a> Cpu<'a> {
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
                 self.clear_flag(Flag::Overflow);
+                self.clear_undefined_aux_carry();
                 self.set_szp_flags_from_result_u16(result);
                 result
             }
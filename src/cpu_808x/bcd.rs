@@ -97,11 +97,13 @@ impl<'a> Cpu<'a> {
 
     /// Ascii adjust before Divison
     /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
-    pub fn aad(&mut self, imm8: u8) {
+    /// AL is set to AL + (AH * base) and AH is cleared. The base is normally 10, but any immediate
+    /// value is honored.
+    pub fn aad(&mut self, base: u8) {
 
         self.cycles_i(3, &[0x170, 0x171, MC_JUMP]);
-        let product_native = (self.ah as u16).wrapping_mul(imm8 as u16) as u8;
-        let (_, product) = 0u8.corx(self, self.ah as u16, imm8 as u16, false);
+        let product_native = (self.ah as u16).wrapping_mul(base as u16) as u8;
+        let (_, product) = 0u8.corx(self, self.ah as u16, base as u16, false);
         assert!((product as u8) == product_native);
 
         self.set_register8(Register8::AL, self.al.wrapping_add(product as u8));
@@ -165,7 +167,9 @@ impl<'a> Cpu<'a> {
     }
 
     /// DAS — Decimal Adjust AL after Subtraction
-    /// Flags: The SF, ZF, and PF flags are set according to the result.
+    /// Flags: The SF, ZF, and PF flags are set according to the result. AF and CF are set if
+    /// the low or high digit was adjusted. OF is undefined.
+    /// As with DAA, the high digit is adjusted when AL > 0x9F instead of AL > 0x99 if AF is set.
     pub fn das(&mut self) {
 
         let old_al = self.al;
@@ -221,15 +225,16 @@ impl<'a> Cpu<'a> {
 
     /// AAM - Ascii adjust AX After multiply
     /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
-    /// As AAM is implemented via CORD, it can throw an exception. This is indicated by a return value
-    /// of false.
-    pub fn aam(&mut self, imm8: u8) -> bool {
+    /// AH is set to AL / base and AL to AL % base. The base is normally 10, but any immediate value
+    /// is honored. As AAM is implemented via CORD, a base of 0 causes a divide error. This is
+    /// indicated by a return value of false.
+    pub fn aam(&mut self, base: u8) -> bool {
 
         self.cycles_i(3, &[0x175, 0x176, MC_JUMP]);
         // 176: A->tmpc   | UNC CORD
         // Jump delay
 
-        match 0u8.cord(self, 0, imm8 as u16, self.al as u16) {
+        match 0u8.cord(self, 0, base as u16, self.al as u16) {
             Ok((quotient, remainder, _)) => {
 
                 // 177:          | COM1 tmpc
@@ -238,26 +243,12 @@ impl<'a> Cpu<'a> {
                 self.cycle_i(0x177);
                 // Other sources set flags from AX register. Intel's documentation specifies AL
                 self.set_szp_flags_from_result_u8(self.al);
-                return true
+                true
             }
             Err(_) => {
-                return false
+                false
             }
         }
-
-        
-
-
-        /*
-        let temp_al = self.al;
-        self.set_register8(Register8::AH, temp_al / imm8);
-        self.set_register8(Register8::AL, temp_al % imm8);
-        */
-
-
-
-        // Other sources set flags from AX register. Intel's documentation specifies AL
-        // self.set_szp_flags_from_result_u8(self.al);
     }
     
 }
\ No newline at end of file
@@ -200,27 +200,38 @@ impl<'a> Cpu<'a> {
         (word, carry)
     }
 
+    /// OF is undefined after a shift or rotate by more than one bit. The 8088 microcode shifts
+    /// one bit at a time, so OF is left as defined for the final single-bit step. Given the most 
+    /// significant two bits of the result and the carry flag after the operation, return the 
+    /// overflow flag that step leaves, or None if the operation does not modify OF.
+    fn undefined_shift_overflow(opcode: Mnemonic, msb: bool, next_msb: bool, carry: bool) -> Option<bool> {
+        match opcode {
+            // The final step shifted the old MSB into carry.
+            Mnemonic::ROL | Mnemonic::RCL | Mnemonic::SHL => Some(msb ^ carry),
+            // The final step moved the old MSB down one bit. For SAR the two are always equal.
+            Mnemonic::ROR | Mnemonic::RCR | Mnemonic::SHR | Mnemonic::SAR => Some(msb ^ next_msb),
+            _ => None
+        }
+    }
+
     /// Perform various 8-bit binary shift operations
     pub fn bitshift_op8(&mut self, opcode: Mnemonic, operand1: u8, operand2: u8) -> u8 {
 
-        // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
-            // Flags are not changed if shift amount is 0
-            return operand1;
-        }
+        // Operand2 will either be 1, the value of the CL register, or an immediate on 80186+
 
         let result: u8;
         let carry: bool;
 
-        /*
         // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
         let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
             _=> operand2 & 0x1F
         };
-        */
 
-        let rot_count = operand2;
+        if rot_count == 0 {
+            // Flags are not changed if shift amount is 0
+            return operand1;
+        }
 
         match opcode {
             Mnemonic::ROL => {
@@ -293,12 +304,12 @@ impl<'a> Cpu<'a> {
                 }
             }            
             Mnemonic::SHL => {
-                (result, carry) = Cpu::shl_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shl_u8_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Only set overflow on SHL of 1
-                if operand2 == 1 {
+                if rot_count == 1 {
                     // If the two highest order bits were different, then they will change on shift
                     // and overflow should be set
                     self.set_flag_state(Flag::Overflow, (operand1 & 0xC0 == 0x80) || (operand1 & 0xC0 == 0x40));
@@ -307,12 +318,12 @@ impl<'a> Cpu<'a> {
                 self.set_szp_flags_from_result_u8(result);
             }
             Mnemonic::SHR => {
-                (result, carry) = Cpu::shr_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shr_u8_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Only set overflow on SHR of 1
-                if operand2 == 1 {
+                if rot_count == 1 {
                     // Only time SHR sets overflow is if HO was 1 and becomes 0, which it always will,
                     // so set overflow flag if it was set. 
                     self.set_flag_state(Flag::Overflow, operand1 & 0x80 != 0 );
@@ -320,13 +331,13 @@ impl<'a> Cpu<'a> {
                 self.set_szp_flags_from_result_u8(result);
             }
             Mnemonic::SAR => {
-                (result, carry) = Cpu::sar_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::sar_u8_with_carry(operand1, rot_count);
                 // Set Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Clear overflow flag if shift count is 1
                 // AoA 6.6.2.2 SAR
-                if operand2 == 1 {
+                if rot_count == 1 {
                     self.clear_flag(Flag::Overflow);
                 }
                 self.set_szp_flags_from_result_u8(result);
@@ -334,6 +345,13 @@ impl<'a> Cpu<'a> {
             _=> panic!("Invalid opcode provided to bitshift_op8()")
         }
 
+        if self.strict_undefined_flags && rot_count > 1 {
+            let carry = self.get_flag(Flag::Carry);
+            if let Some(overflow) = Cpu::undefined_shift_overflow(opcode, result & 0x80 != 0, result & 0x40 != 0, carry) {
+                self.set_flag_state(Flag::Overflow, overflow);
+            }
+        }
+
         // Return result        
         result
     }
@@ -341,24 +359,21 @@ impl<'a> Cpu<'a> {
     /// Peform various 16-bit binary shift operations
     pub fn bitshift_op16(&mut self, opcode: Mnemonic, operand1: u16, operand2: u8) -> u16 {
 
-        // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
-            // Flags are not changed if shift amount is 0
-            return operand1;
-        }
+        // Operand2 will either be 1, the value of the CL register, or an immediate on 80186+
 
         let result: u16;
         let carry: bool;
 
-        /*
         // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
         let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
             _=> operand2 & 0x1F
         };
-        */
 
-        let rot_count = operand2;
+        if rot_count == 0 {
+            // Flags are not changed if shift amount is 0
+            return operand1;
+        }
 
         match opcode {
             Mnemonic::ROL => {
@@ -447,12 +462,12 @@ impl<'a> Cpu<'a> {
                 }
             }            
             Mnemonic::SHL => {
-                (result, carry) = Cpu::shl_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shl_u16_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Only set overflow on SHL of 1
-                if operand2 == 1 {
+                if rot_count == 1 {
                     // If the two highest order bits were different, then they will change on shift
                     // and overflow should be set
                     self.set_flag_state(Flag::Overflow, (operand1 & 0xC000 == 0x8000) || (operand1 & 0xC000 == 0x4000));
@@ -460,12 +475,12 @@ impl<'a> Cpu<'a> {
                 self.set_szp_flags_from_result_u16(result);
             }
             Mnemonic::SHR => {
-                (result, carry) = Cpu::shr_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shr_u16_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Only set overflow on SHR of 1
-                if operand2 == 1 {
+                if rot_count == 1 {
                     // Only time SHR sets overflow is if HO was 1 and becomes 0, which it always will,
                     // so set overflow flag if it was set. 
                     self.set_flag_state(Flag::Overflow, operand1 & 0x8000 != 0 );
@@ -473,13 +488,13 @@ impl<'a> Cpu<'a> {
                 self.set_szp_flags_from_result_u16(result);
             }
             Mnemonic::SAR => {
-                (result, carry) = Cpu::sar_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::sar_u16_with_carry(operand1, rot_count);
                 // Set Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
                 // Clear overflow flag if shift count is 1
                 // AoA 6.6.2.2 SAR
-                if operand2 == 1 {
+                if rot_count == 1 {
                     self.clear_flag(Flag::Overflow);
                 }
                 self.set_szp_flags_from_result_u16(result);
@@ -487,6 +502,13 @@ impl<'a> Cpu<'a> {
             _=> panic!("Invalid opcode provided to bitshift_op16()")
         }
 
+        if self.strict_undefined_flags && rot_count > 1 {
+            let carry = self.get_flag(Flag::Carry);
+            if let Some(overflow) = Cpu::undefined_shift_overflow(opcode, result & 0x8000 != 0, result & 0x4000 != 0, carry) {
+                self.set_flag_state(Flag::Overflow, overflow);
+            }
+        }
+
         // Return result        
         result
     }        
@@ -2,7 +2,9 @@
 use crate::cpu_808x::*;
 use crate::bytequeue::*;
 
-#[derive (Debug, PartialEq)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BiuState {
     Operating,
     Suspended,
@@ -201,27 +203,33 @@ impl<'a> Cpu<'a> {
         byte
     }
 
+    /// Set the size of the instruction queue and the width of code fetches. 
+    /// The 8088 has a 4 byte queue filled one byte per bus cycle, the 8086 has a 6 byte
+    /// queue filled a word per bus cycle.
+    pub fn biu_set_queue_params(&mut self, queue_size: usize, fetch_size: TransferSize) {
+        self.queue.set_size(queue_size);
+        self.fetch_size = fetch_size;
+    }
+
+    /// Return the number of bytes a code fetch pushes to the queue.
+    #[inline]
+    pub fn biu_fetch_width(&self) -> usize {
+        match self.fetch_size {
+            TransferSize::Byte => 1,
+            TransferSize::Word => 2,
+        }
+    }
+
     pub fn biu_resume_on_queue_read(&mut self) {
 
-        if matches!(self.biu_state, BiuState::Suspended) {
-            match self.cpu_type {
-                // 8088 will have room in queue at 3 bytes,
-                // 8086 will have room in queue at 4 bytes
-                CpuType::Intel8088 => {
-                    if self.queue.len() == 3 {
-                        self.biu_state = BiuState::Resuming(3);
-                        trace_print!(self, "Resuming from suspend due to queue read.");
-                        self.biu_schedule_fetch();
-                    }
-                }
-                CpuType::Intel8086 => {
-                    if self.queue.len() == 4 {
-                        // We can fetch again after this read
-                        self.biu_state = BiuState::Resuming(3);
-                        self.biu_schedule_fetch();
-                    }
-                }
-            }
+        // The queue has room again when a read leaves space for a full fetch: 
+        // at 3 bytes on the 8088 and 4 bytes on the 8086.
+        if matches!(self.biu_state, BiuState::Suspended) 
+            && self.queue.len() + self.biu_fetch_width() == self.queue.size() 
+        {
+            self.biu_state = BiuState::Resuming(3);
+            trace_print!(self, "Resuming from suspend due to queue read.");
+            self.biu_schedule_fetch();
         }
     }
 
@@ -309,14 +317,14 @@ impl<'a> Cpu<'a> {
 
         /*
         match self.cpu_type {
-            CpuType::Intel8088 => {
+            CpuType::Intel8088 | CpuType::Intel80188 | CpuType::NecV20 => {
                 match self.queue.len() {
                     0..=2 => self.fetch_state = FetchState::Scheduled(2),
                     3 => self.fetch_state = FetchState::Scheduled(fetch_delay),
                     _ => {}
                 }
             }
-            CpuType::Intel8086 => {
+            CpuType::Intel8086 | CpuType::Intel80186 => {
                 match self.queue.len() {
                     0..=2 => self.fetch_state = FetchState::Scheduled(2),
                     3..=4 => self.fetch_state = FetchState::Scheduled(fetch_delay),
@@ -397,15 +405,8 @@ impl<'a> Cpu<'a> {
     }    
 
     pub fn biu_queue_has_room(&mut self) -> bool {
-        match self.cpu_type {
-            CpuType::Intel8088 => {
-                self.queue.len() < 4
-            }
-            CpuType::Intel8086 => {
-                // 8086 fetches two bytes at a time, so must be two free bytes in queue
-                self.queue.len() < 5
-            }
-        }
+        // The 8086 fetches two bytes at a time, so there must be two free bytes in the queue
+        self.queue.len() + self.biu_fetch_width() <= self.queue.size()
     }
 
     pub fn biu_make_fetch_decision(&mut self) {
@@ -496,8 +497,10 @@ impl<'a> Cpu<'a> {
         self.cycle();
     }
 
-    /// Issue an interrupt acknowledge, consisting of two consecutive INTA bus cycles.
-    pub fn biu_inta(&mut self, vector: u8) {
+    /// Issue an interrupt acknowledge, consisting of two INTA bus cycles separated by two idle
+    /// cycles. The PIC drives the vector onto the bus during the second INTA cycle. If 'vector'
+    /// is None, the vector is read from the PIC at that point. Returns the vector.
+    pub fn biu_inta(&mut self, vector: Option<u8>) -> u8 {
 
         self.biu_bus_begin(
             BusStatus::InterruptAck,
@@ -510,6 +513,21 @@ impl<'a> Cpu<'a> {
         );
 
         self.biu_bus_wait_finish();
+        if self.t_cycle == TCycle::T4 {
+            self.cycle();
+        }
+
+        // The EU keeps the bus between the INTA cycles, so don't let a prefetch start during
+        // the idle cycles.
+        if self.fetch_state == FetchState::Idle {
+            self.fetch_state = FetchState::BlockedByEU;
+        }
+        self.cycles(2);
+
+        let vector = match vector {
+            Some(vector) => vector,
+            None => self.replay_inta_vector()
+        };
 
         self.biu_bus_begin(
             BusStatus::InterruptAck,
@@ -522,6 +540,20 @@ impl<'a> Cpu<'a> {
         );
 
         self.biu_bus_wait_finish();
+        vector
+    }
+
+    /// Represents the PIC responding to the second INTA cycle. If the request that raised
+    /// INTR has gone away by then, the 8259 responds with the vector for IR7 (a spurious
+    /// interrupt). With no PIC present, the data bus floats high.
+    pub fn pic_inta_vector(&mut self) -> u8 {
+        match self.bus.pic_mut().as_mut() {
+            Some(pic) => match pic.get_interrupt_vector() {
+                Some(vector) => vector,
+                None => pic.vector_base().wrapping_add(7)
+            },
+            None => 0xFF
+        }
     }
 
     pub fn biu_read_u8(&mut self, seg: Segment, addr: u32) -> u8 {
@@ -598,7 +630,8 @@ impl<'a> Cpu<'a> {
         //validate_write_u8!(self, addr, (self.data_bus & 0x00FF) as u8);
     }
 
-    pub fn biu_io_read_u16(&mut self, addr: u16, word: u16, flag: ReadWriteFlag) {
+    /// Read a word from two consecutive IO ports, low byte first.
+    pub fn biu_io_read_u16(&mut self, addr: u16) -> u16 {
         
         self.biu_bus_begin(
             BusStatus::IoRead, 
@@ -610,21 +643,21 @@ impl<'a> Cpu<'a> {
             true
         );
         self.biu_bus_wait_finish();
+        let mut word = self.data_bus & 0x00FF;
 
         self.biu_bus_begin(
             BusStatus::IoRead, 
             Segment::None, 
-            addr as u32, 
+            addr.wrapping_add(1) as u32, 
             0, 
             TransferSize::Byte,
             OperandSize::Operand16,
             false
         );
+        self.biu_bus_wait_finish();
+        word |= (self.data_bus & 0x00FF) << 8;
 
-        match flag {
-            ReadWriteFlag::Normal => self.biu_bus_wait_finish(),
-            ReadWriteFlag::RNI => self.biu_bus_wait_until(TCycle::Tw)
-        };
+        word
     }        
 
     pub fn biu_io_write_u16(&mut self, addr: u16, word: u16, flag: ReadWriteFlag) {
@@ -656,13 +689,24 @@ impl<'a> Cpu<'a> {
         };
     }    
 
+    /// Returns true if a word transfer at the specified address must be performed as two byte
+    /// transfers. The 8088 always transfers words a byte at a time. The 8086 can only transfer 
+    /// a word in a single bus cycle if it is aligned, so words at odd addresses take an extra
+    /// bus cycle.
+    fn biu_word_split(&self, addr: u32) -> bool {
+        match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel80188 | CpuType::NecV20 => true,
+            CpuType::Intel8086 | CpuType::Intel80186 => addr & 1 != 0
+        }
+    }
+
     pub fn biu_read_u16(&mut self, seg: Segment, addr: u32, flag: ReadWriteFlag) -> u16 {
 
         let mut word;
 
-        match self.cpu_type {
-            CpuType::Intel8088 => {
-                // 8088 performs two consecutive byte transfers
+        match self.biu_word_split(addr) {
+            true => {
+                // Perform two consecutive byte transfers
                 self.biu_bus_begin(
                     BusStatus::MemRead, 
                     seg, 
@@ -698,7 +742,7 @@ impl<'a> Cpu<'a> {
                 //validate_read_u8!(self, addr + 1, (self.data_bus & 0x00FF) as u8, ReadType::Data);
                 word
             }
-            CpuType::Intel8086 => {
+            false => {
                 self.biu_bus_begin(
                     BusStatus::MemRead, 
                     seg, 
@@ -720,9 +764,9 @@ impl<'a> Cpu<'a> {
 
     pub fn biu_write_u16(&mut self, seg: Segment, addr: u32, word: u16, flag: ReadWriteFlag) {
 
-        match self.cpu_type {
-            CpuType::Intel8088 => {
-                // 8088 performs two consecutive byte transfers
+        match self.biu_word_split(addr) {
+            true => {
+                // Perform two consecutive byte transfers
                 self.biu_bus_begin(
                     BusStatus::MemWrite, 
                     seg, 
@@ -752,7 +796,7 @@ impl<'a> Cpu<'a> {
                     ReadWriteFlag::RNI => self.biu_bus_wait_until(TCycle::Tw)
                 };
             }
-            CpuType::Intel8086 => {
+            false => {
                 self.biu_bus_begin(
                     BusStatus::MemWrite, 
                     seg, 
@@ -911,11 +955,12 @@ impl<'a> Cpu<'a> {
         */
         self.trace_comment("BUS_BEGIN");
 
-        // Check this address for a memory access breakpoint
-        if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 {
-            // Breakpoint hit
-            self.state = CpuState::BreakpointHit;
-        }
+        // The second byte of a word transfer at FFFFF is at 100000, which wraps to 0.
+        let address = address & ADDRESS_MASK;
+
+        // Check this address for a memory read or write breakpoint. Code fetches are not checked, 
+        // so that prefetching never trips a breakpoint.
+        self.check_access_breakpoint(new_bus_status, address);
 
         // Save current fetch state
         let _old_fetch_state = self.fetch_state;
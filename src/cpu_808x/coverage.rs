@@ -0,0 +1,214 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::coverage.rs
+
+    Records which (opcode, modrm reg, addressing mode) combinations have 
+    been executed, for measuring how much of the decoder a workload exercises.
+
+*/
+
+use std::fmt::Write;
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::addressing::AddressingMode;
+use crate::cpu_common::CpuType;
+use crate::bus::BusInterface;
+use crate::bytequeue::ByteQueue;
+
+// The 24 memory addressing modes, plus register mode and instructions without a modrm.
+const FORM_REGISTER: usize = 24;
+const FORM_NO_MODRM: usize = 25;
+const FORM_COUNT: usize = 26;
+
+const COVERAGE_BITS: usize = 256 * 8 * FORM_COUNT;
+
+const PREFIX_OPCODES: [u8; 7] = [0x26, 0x2E, 0x36, 0x3E, 0xF0, 0xF2, 0xF3];
+
+/// A bitmap of executed (opcode, modrm reg, addressing mode) combinations.
+pub struct CoverageMap {
+    bits: Vec<u64>,
+}
+
+impl Default for CoverageMap {
+    fn default() -> Self {
+        Self {
+            bits: vec![0; (COVERAGE_BITS + 63) / 64]
+        }
+    }
+}
+
+fn addressing_mode_form(mode: &AddressingMode) -> usize {
+    match mode {
+        AddressingMode::BxSi => 0,
+        AddressingMode::BxDi => 1,
+        AddressingMode::BpSi => 2,
+        AddressingMode::BpDi => 3,
+        AddressingMode::Si => 4,
+        AddressingMode::Di => 5,
+        AddressingMode::Disp16(_) => 6,
+        AddressingMode::Bx => 7,
+        AddressingMode::BxSiDisp8(_) => 8,
+        AddressingMode::BxDiDisp8(_) => 9,
+        AddressingMode::BpSiDisp8(_) => 10,
+        AddressingMode::BpDiDisp8(_) => 11,
+        AddressingMode::SiDisp8(_) => 12,
+        AddressingMode::DiDisp8(_) => 13,
+        AddressingMode::BpDisp8(_) => 14,
+        AddressingMode::BxDisp8(_) => 15,
+        AddressingMode::BxSiDisp16(_) => 16,
+        AddressingMode::BxDiDisp16(_) => 17,
+        AddressingMode::BpSiDisp16(_) => 18,
+        AddressingMode::BpDiDisp16(_) => 19,
+        AddressingMode::SiDisp16(_) => 20,
+        AddressingMode::DiDisp16(_) => 21,
+        AddressingMode::BpDisp16(_) => 22,
+        AddressingMode::BxDisp16(_) => 23,
+        AddressingMode::RegisterMode => FORM_REGISTER,
+    }
+}
+
+fn form_name(form: usize) -> &'static str {
+    const NAMES: [&str; FORM_COUNT] = [
+        "[bx+si]", "[bx+di]", "[bp+si]", "[bp+di]", "[si]", "[di]", "[disp16]", "[bx]",
+        "[bx+si+d8]", "[bx+di+d8]", "[bp+si+d8]", "[bp+di+d8]", "[si+d8]", "[di+d8]", "[bp+d8]", "[bx+d8]",
+        "[bx+si+d16]", "[bx+di+d16]", "[bp+si+d16]", "[bp+di+d16]", "[si+d16]", "[di+d16]", "[bp+d16]", "[bx+d16]",
+        "reg", "-"
+    ];
+    NAMES[form]
+}
+
+/// Return the coverage key (opcode, modrm reg, form) for a decoded instruction.
+fn instruction_key(i: &Instruction) -> (u8, u8, usize) {
+    if i.flags & I_HAS_MODRM == 0 {
+        return (i.opcode, 0, FORM_NO_MODRM)
+    }
+
+    let form = match (&i.operand1_type, &i.operand2_type) {
+        (OperandType::AddressingMode(mode), _) | (_, OperandType::AddressingMode(mode)) => addressing_mode_form(mode),
+        _ => FORM_REGISTER
+    };
+    (i.opcode, i.op_ext & 0x07, form)
+}
+
+fn bit_index(opcode: u8, reg: u8, form: usize) -> usize {
+    (opcode as usize * 8 + reg as usize) * FORM_COUNT + form
+}
+
+impl CoverageMap {
+
+    /// Mark the combination used by a decoded instruction as executed.
+    pub fn record(&mut self, i: &Instruction) {
+        let (opcode, reg, form) = instruction_key(i);
+        self.set(bit_index(opcode, reg, form));
+    }
+
+    fn set(&mut self, bit: usize) {
+        self.bits[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn get(&self, bit: usize) -> bool {
+        self.bits[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    pub fn contains(&self, i: &Instruction) -> bool {
+        let (opcode, reg, form) = instruction_key(i);
+        self.get(bit_index(opcode, reg, form))
+    }
+
+    /// Return the number of combinations recorded.
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|w| *w = 0);
+    }
+
+    /// Build a map of every combination the decoder accepts for the given cpu type, by 
+    /// decoding each opcode with each possible modrm byte. Prefixes are not counted, as they 
+    /// are consumed by the decoder rather than executed as instructions.
+    pub fn decodable(cpu_type: CpuType) -> CoverageMap {
+        let mut map = CoverageMap::default();
+        let mut bus = BusInterface::default();
+
+        for opcode in 0..=0xFFu8 {
+            if PREFIX_OPCODES.contains(&opcode) {
+                continue
+            }
+            for modrm in 0..=0xFFu8 {
+                // Displacement and immediate bytes are left zero.
+                let _ = bus.write_u8(0, opcode, 0);
+                let _ = bus.write_u8(1, modrm, 0);
+                bus.seek(0);
+                if let Ok(i) = Cpu::decode(&mut bus, cpu_type) {
+                    map.record(&i);
+                }
+            }
+        }
+        map
+    }
+
+    /// Produce a text report of recorded combinations against those in 'total'. Each opcode 
+    /// is listed with its covered and total count, followed by the missing forms.
+    pub fn report(&self, total: &CoverageMap) -> String {
+        let mut out = String::new();
+
+        let covered = self.count();
+        let possible = total.count();
+        let pct = if possible > 0 { covered as f64 * 100.0 / possible as f64 } else { 0.0 };
+        let _ = writeln!(out, "Instruction coverage: {} of {} combinations ({:.2}%)", covered, possible, pct);
+
+        for opcode in 0..=0xFFu8 {
+            let mut op_covered = 0;
+            let mut op_total = 0;
+            let mut missing = Vec::new();
+
+            for reg in 0..8u8 {
+                for form in 0..FORM_COUNT {
+                    let bit = bit_index(opcode, reg, form);
+                    if !total.get(bit) {
+                        continue
+                    }
+                    op_total += 1;
+                    if self.get(bit) {
+                        op_covered += 1;
+                    }
+                    else {
+                        missing.push((reg, form));
+                    }
+                }
+            }
+
+            if op_total == 0 {
+                continue
+            }
+            let marker = if op_covered == 0 { " (never executed)" } else { "" };
+            let _ = writeln!(out, "{:02X}: {}/{}{}", opcode, op_covered, op_total, marker);
+
+            if op_covered > 0 && !missing.is_empty() {
+                let list: Vec<String> = missing.iter()
+                    .map(|(reg, form)| format!("/{} {}", reg, form_name(*form)))
+                    .collect();
+                let _ = writeln!(out, "    missing: {}", list.join(", "));
+            }
+        }
+        out
+    }
+}
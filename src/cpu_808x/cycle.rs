@@ -28,6 +28,7 @@
 use crate::cpu_808x::*;
 use crate::cpu_808x::biu::*;
 use crate::cpu_808x::addressing::*;
+use crate::io_trace::IoDirection;
 
 #[cfg(feature = "cpu_validator")]
 use crate::cpu_validator::{BusType, ReadType};
@@ -91,6 +92,10 @@ impl<'a> Cpu<'a> {
             self.trace_instr = instr;
         }
 
+        if let Some(record) = &mut self.trace_record {
+            record.microcode.push(self.trace_instr);
+        }
+
         if self.t_cycle == TCycle::TInit {
             self.t_cycle = TCycle::T1;
         }
@@ -147,11 +152,8 @@ impl<'a> Cpu<'a> {
                                 self.bus_wait_states = self.bus.get_write_wait(self.address_bus as usize, self.instr_elapsed).unwrap();
                                 self.instr_elapsed = 0;
                             }
-                            BusStatus::IoRead => {
-                                self.bus_wait_states = 1;
-                            }
-                            BusStatus::IoWrite => {
-                                self.bus_wait_states = 1;
+                            BusStatus::IoRead | BusStatus::IoWrite => {
+                                self.bus_wait_states = self.bus.io_wait_states();
                             }                                                                                                                     
                             _=> {}
                         }
@@ -219,8 +221,14 @@ impl<'a> Cpu<'a> {
                                 }
                                 (BusStatus::IoRead, TransferSize::Byte) => {
                                     self.i8288.iorc = true;
-                                    byte = self.bus.io_read_u8((self.address_bus & 0xFFFF) as u16, self.instr_elapsed);
+                                    byte = self.replay_io_read((self.address_bus & 0xFFFF) as u16);
                                     self.data_bus = byte as u16;
+                                    self.bus.record_io_access(
+                                        (self.address_bus & 0xFFFF) as u16,
+                                        byte,
+                                        IoDirection::Read,
+                                        self.cycle_num
+                                    );
                                     self.instr_elapsed = 0;
                                     self.transfer_n += 1;
 
@@ -233,6 +241,12 @@ impl<'a> Cpu<'a> {
                                         (self.data_bus & 0x00FF) as u8,
                                         self.instr_elapsed
                                     );
+                                    self.bus.record_io_access(
+                                        (self.address_bus & 0xFFFF) as u16,
+                                        (self.data_bus & 0x00FF) as u8,
+                                        IoDirection::Write,
+                                        self.cycle_num
+                                    );
                                     self.instr_elapsed = 0;
                                     self.transfer_n += 1;
 
@@ -255,6 +269,8 @@ impl<'a> Cpu<'a> {
                                 }
                             }
 
+                            self.trace_bus_cycle();
+
                             // If we're not in the middle of a word transfer, schedule a prefetch if appropriate.
                             if self.is_operand_complete() {
                                 self.biu_make_fetch_decision();
@@ -448,8 +464,11 @@ impl<'a> Cpu<'a> {
                 DmaState::Idle => {
                     if self.dram_refresh_cycles == self.dram_refresh_cycle_target + self.dram_refresh_adjust {
                         // DRAM refresh cycle counter has hit target. 
-                        // Begin DMA transfer simulation by issuing a DREQ.
-                        self.dma_state = DmaState::Dreq;
+                        // Begin DMA transfer simulation by issuing a DREQ, unless the DMA 
+                        // controller won't service channel 0.
+                        if self.bus.dram_refresh_enabled() {
+                            self.dma_state = DmaState::Dreq;
+                        }
 
                         // Reset counter.
                         self.dram_refresh_cycles = self.dram_refresh_adjust;
@@ -519,8 +538,13 @@ impl<'a> Cpu<'a> {
                 self.address_bus = self.pc;
                 self.i8288.ale = true;
                 self.data_bus = 0;
-                self.transfer_size = self.fetch_size;
-                self.operand_size = match self.fetch_size {
+                // A word fetch from an odd address only transfers the byte at that address,
+                // after which fetches are word aligned.
+                self.transfer_size = match self.fetch_size {
+                    TransferSize::Word if self.pc & 1 != 0 => TransferSize::Byte,
+                    size => size
+                };
+                self.operand_size = match self.transfer_size {
                     TransferSize::Byte => OperandSize::Operand8,
                     TransferSize::Word => OperandSize::Operand16
                 };
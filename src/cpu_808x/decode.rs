@@ -66,12 +66,17 @@ impl Display for InstructionDecodeError{
 }
 
 impl<'a> Cpu<'a> {
-    pub fn decode(bytes: &mut impl ByteQueue) -> Result<Instruction, Box<dyn std::error::Error>> {
+    pub fn decode(bytes: &mut impl ByteQueue, cpu_type: CpuType) -> Result<Instruction, Box<dyn std::error::Error>> {
 
         let mut operand1_type: OperandType = OperandType::NoOperand;
         let mut operand2_type: OperandType = OperandType::NoOperand;
+        let mut operand3_type: OperandType = OperandType::NoOperand;
         let mut operand1_size: OperandSize = OperandSize::NoOperand;
         let mut operand2_size: OperandSize = OperandSize::NoOperand;
+        let mut operand3_size: OperandSize = OperandSize::NoOperand;
+
+        // 80186 instruction set extensions replace the 8088's aliased opcodes
+        let ext_186 = cpu_type.has_186_instructions();
 
         //let op_address = bytes.tell() as u32;
         bytes.clear_delay();
@@ -123,6 +128,22 @@ impl<'a> Cpu<'a> {
 
         // Match templatizeable instructions
         (mnemonic, operand1_template, operand2_template, op_flags) = match opcode {
+            // 80186 extensions. These must be matched first, as on the 8088 these opcodes are aliases.
+            0x60 if ext_186 => (Mnemonic::PUSHA, OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            0x61 if ext_186 => (Mnemonic::POPA,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            0x62 if ext_186 => (Mnemonic::BOUND, OperandTemplate::Register16, OperandTemplate::ModRM16,    I_LOAD_EA),
+            0x63..=0x67 if ext_186 => (Mnemonic::InvalidOpcode, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+            0x68 if ext_186 => (Mnemonic::PUSH,  OperandTemplate::Immediate16, OperandTemplate::NoOperand, 0),
+            0x69 if ext_186 => (Mnemonic::IMUL,  OperandTemplate::Register16, OperandTemplate::ModRM16,    I_LOAD_EA),
+            0x6A if ext_186 => (Mnemonic::PUSH,  OperandTemplate::Immediate8SignExtended, OperandTemplate::NoOperand, 0),
+            0x6B if ext_186 => (Mnemonic::IMUL,  OperandTemplate::Register16, OperandTemplate::ModRM16,    I_LOAD_EA),
+            0x6C if ext_186 => (Mnemonic::INSB,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            0x6D if ext_186 => (Mnemonic::INSW,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            0x6E if ext_186 => (Mnemonic::OUTSB, OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            0x6F if ext_186 => (Mnemonic::OUTSW, OperandTemplate::NoOperand,  OperandTemplate::NoOperand,  0),
+            // Shift by immediate is a group instruction, handled in the next match statement
+            0xC0 | 0xC1 if ext_186 => (Mnemonic::NoOpcode, OperandTemplate::NoTemplate, OperandTemplate::NoTemplate, 0),
+
             0x00 => (Mnemonic::ADD,  OperandTemplate::ModRM8,   OperandTemplate::Register8,     I_LOAD_EA ),
             0x01 => (Mnemonic::ADD,  OperandTemplate::ModRM16,   OperandTemplate::Register16,   I_LOAD_EA ),
             0x02 => (Mnemonic::ADD,  OperandTemplate::Register8,   OperandTemplate::ModRM8,     I_LOAD_EA ),
@@ -359,6 +380,24 @@ impl<'a> Cpu<'a> {
                 (0x83, 0x06) => (Mnemonic::XOR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8SignExtended,    I_LOAD_EA ),
                 (0x83, 0x07) => (Mnemonic::CMP,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8SignExtended,    I_LOAD_EA ),   
                 
+                (0xC0, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x03) => (Mnemonic::RCR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x04) => (Mnemonic::SHL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x05) => (Mnemonic::SHR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x06) => (Mnemonic::SETMO, OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x07) => (Mnemonic::SAR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+
+                (0xC1, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x03) => (Mnemonic::RCR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x04) => (Mnemonic::SHL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x05) => (Mnemonic::SHR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x06) => (Mnemonic::SETMO, OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x07) => (Mnemonic::SAR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+
                 (0xD0, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
                 (0xD0, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
                 (0xD0, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
@@ -617,6 +656,13 @@ impl<'a> Cpu<'a> {
             _=> (operand2_type, operand2_size) = match_op(operand2_template)?
         }
 
+        // The 80186 IMUL r16, r/m16, imm instructions take a third, immediate operand
+        match opcode {
+            0x69 if ext_186 => (operand3_type, operand3_size) = match_op(OperandTemplate::Immediate16)?,
+            0x6B if ext_186 => (operand3_type, operand3_size) = match_op(OperandTemplate::Immediate8SignExtended)?,
+            _ => {}
+        }
+
         // Set a flag if either of the instruction operands is a memory operand.
         if let OperandType::AddressingMode(_) = operand1_type {
             op_flags |= I_USES_MEM;
@@ -627,8 +673,12 @@ impl<'a> Cpu<'a> {
 
         //size = bytes.tell() as u32 - op_address;
 
+        // The 80186 raises INT 6 for the undefined opcodes 63-67h, so these decode and the 
+        // exception is taken when they are executed.
         if let Mnemonic::InvalidOpcode = mnemonic {
-            return Err(Box::new(InstructionDecodeError::UnsupportedOpcode(opcode)));
+            if !(ext_186 && (0x63..=0x67).contains(&opcode)) {
+                return Err(Box::new(InstructionDecodeError::UnsupportedOpcode(opcode)));
+            }
         }
 
         Ok(Instruction { 
@@ -642,7 +692,10 @@ impl<'a> Cpu<'a> {
             operand1_type,
             operand1_size,
             operand2_type,
-            operand2_size
+            operand2_size,
+            operand3_type,
+            operand3_size,
+            op_ext: modrm.get_op_extension()
         })
     }
 }
@@ -0,0 +1,603 @@
+/*
+    Marty PC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::disassembly.rs
+
+    Disassembly helpers that operate on the bus rather than the instruction
+    queue, for use by the debugger.
+
+*/
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::mnemonic::Mnemonic;
+use crate::cpu_808x::addressing::AddressingMode;
+use crate::symbols::{CommentMap, SymbolMap};
+use crate::syntax_token::SyntaxToken;
+use crate::util;
+
+use serde::Deserialize;
+use serde::de::{IntoDeserializer, value::Error as ValueError};
+
+/// Maximum number of bytes per instruction assumed when choosing a lookback window
+/// for backwards disassembly. Instructions can be longer with prefixes, but this
+/// covers the vast majority of real code.
+pub const DISASSEMBLY_LOOKBACK_PER_INSTR: u16 = 6;
+
+/// The result of disassembling a single instruction from memory.
+#[derive(Clone, Default)]
+pub struct DisassemblyResult {
+    /// The decoded instruction, or None if the bytes could not be decoded.
+    pub instruction: Option<Instruction>,
+    /// Syntax tokens for the instruction, or a single ErrorText token on failure.
+    pub tokens: Vec<SyntaxToken>,
+    /// The raw bytes of the instruction.
+    pub bytes: Vec<u8>,
+    /// Length of the instruction in bytes. This is 1 on failure so that callers
+    /// disassembling a linear range can skip the offending byte.
+    pub size: usize,
+}
+
+impl DisassemblyResult {
+    fn error(msg: &str, bytes: Vec<u8>) -> Self {
+        Self {
+            instruction: None,
+            tokens: vec![SyntaxToken::ErrorText(msg.to_string())],
+            bytes,
+            size: 1,
+        }
+    }
+}
+
+/// Iterator over the addresses at which a byte pattern occurs in memory, returned by
+/// Cpu::find_code. Memory is read directly with no cycle cost.
+pub struct CodeSearch<'b> {
+    bus: &'b BusInterface,
+    pattern: Vec<Option<u8>>,
+    next: CpuAddress,
+    remaining: usize,
+}
+
+impl<'b> CodeSearch<'b> {
+
+    /// Read the byte 'n' bytes past 'addr'. Segmented addresses wrap at the end of the
+    /// segment, flat addresses wrap at the end of the address space.
+    fn byte_at(&self, addr: CpuAddress, n: usize) -> Option<u8> {
+        let flat_addr = u32::from(addr.offset(n as i32)) as usize;
+
+        if flat_addr < self.bus.size() {
+            Some(self.bus.get_slice_at(flat_addr, 1)[0])
+        }
+        else {
+            None
+        }
+    }
+
+    fn matches_at(&self, addr: CpuAddress) -> bool {
+        self.pattern.iter().enumerate().all(|(n, p)| {
+            match (p, self.byte_at(addr, n)) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(b), Some(byte)) => *b == byte
+            }
+        })
+    }
+}
+
+impl<'b> Iterator for CodeSearch<'b> {
+    type Item = CpuAddress;
+
+    fn next(&mut self) -> Option<CpuAddress> {
+        if self.pattern.is_empty() {
+            return None
+        }
+
+        while self.remaining > 0 {
+            let addr = self.next;
+            self.next = addr.offset(1);
+            self.remaining -= 1;
+
+            if self.matches_at(addr) {
+                return Some(addr)
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the instructions in a linear range of memory, returned by
+/// Cpu::disassemble_range. Each item is the flat address of an instruction and its
+/// disassembly; the address of the next instruction is the address plus the result's size.
+pub struct DisassemblyRange<'c, 'a> {
+    cpu: &'c mut Cpu<'a>,
+    next: u32,
+    end: u32,
+}
+
+impl<'c, 'a> Iterator for DisassemblyRange<'c, 'a> {
+    type Item = (u32, DisassemblyResult);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None
+        }
+        let addr = self.next;
+        let result = self.cpu.disassemble_at(CpuAddress::Flat(addr));
+
+        // Undecodable or truncated instructions have a size of 1, so we always make progress.
+        self.next = addr + result.size.max(1) as u32;
+        Some((addr, result))
+    }
+}
+
+/// Parse a byte pattern for Cpu::find_code from a string of hex bytes separated by whitespace,
+/// ie, "CD 21" or "B4 ?? CD 21", where '??' matches any byte.
+pub fn parse_code_pattern(pattern_str: &str) -> Option<Vec<Option<u8>>> {
+    pattern_str
+        .split_whitespace()
+        .map(|token| {
+            match token {
+                "?" | "??" => Some(None),
+                _ => u8::from_str_radix(token, 16).ok().map(Some)
+            }
+        })
+        .collect()
+}
+
+/// Describes the operand characteristics an instruction must have to match a search.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OperandMatch {
+    /// Any immediate operand with the specified value, ie, 'int 21h'
+    Immediate(u16),
+    /// Any memory operand, either a modrm addressing mode or a direct offset
+    Memory,
+    /// A memory operand as the destination of the instruction
+    MemoryWrite,
+    /// A far pointer operand, either immediate or loaded from memory (ie, callf / jmpf)
+    Far,
+}
+
+/// A query for instructions in a disassembled range. Fields left as None match any
+/// instruction, so mnemonics encoded in different ways all match the same query.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InstructionQuery {
+    pub mnemonic: Option<Mnemonic>,
+    pub operand: Option<OperandMatch>,
+    /// Segment used by any memory operand, taking overrides into account.
+    pub segment: Option<Segment>,
+}
+
+impl InstructionQuery {
+
+    /// Parse a query from a mnemonic followed by optional operand terms separated by whitespace,
+    /// ie, "int 21", "mov es: memw" or "* far", where '*' matches any mnemonic. Operand terms are 
+    /// a hex immediate value with an optional 'h' suffix, 'mem' for any memory operand, 'memw' 
+    /// for a memory destination, 'far' for a far pointer, or a segment such as 'cs:' for the 
+    /// segment of a memory operand. Returns None if any term is invalid.
+    pub fn parse(query_str: &str) -> Option<Self> {
+        let mut terms = query_str.split_whitespace();
+        let mut query = InstructionQuery::default();
+
+        query.mnemonic = match terms.next()? {
+            "*" => None,
+            name => {
+                let name = name.to_uppercase();
+                Some(Mnemonic::deserialize(IntoDeserializer::<ValueError>::into_deserializer(name.as_str())).ok()?)
+            }
+        };
+
+        for term in terms {
+            let term = term.to_lowercase();
+            match term.as_str() {
+                "mem" => query.operand = Some(OperandMatch::Memory),
+                "memw" => query.operand = Some(OperandMatch::MemoryWrite),
+                "far" => query.operand = Some(OperandMatch::Far),
+                "es:" => query.segment = Some(Segment::ES),
+                "cs:" => query.segment = Some(Segment::CS),
+                "ss:" => query.segment = Some(Segment::SS),
+                "ds:" => query.segment = Some(Segment::DS),
+                _ => {
+                    let digits = term.strip_suffix('h').unwrap_or(&term);
+                    query.operand = Some(OperandMatch::Immediate(u16::from_str_radix(digits, 16).ok()?));
+                }
+            }
+        }
+
+        Some(query)
+    }
+
+    pub fn matches(&self, i: &Instruction) -> bool {
+
+        if let Some(mnemonic) = self.mnemonic {
+            if i.mnemonic != mnemonic {
+                return false
+            }
+        }
+
+        if let Some(operand) = self.operand {
+            let op_match = match operand {
+                OperandMatch::Immediate(value) => {
+                    operand_immediate(i.operand1_type) == Some(value) || operand_immediate(i.operand2_type) == Some(value)
+                }
+                OperandMatch::Memory => {
+                    operand_is_memory(i.operand1_type) || operand_is_memory(i.operand2_type)
+                }
+                OperandMatch::MemoryWrite => {
+                    operand_is_memory(i.operand1_type) && mnemonic_writes_operand1(i.mnemonic)
+                }
+                OperandMatch::Far => {
+                    matches!(i.operand1_type, OperandType::FarAddress(_,_))
+                        || matches!(i.mnemonic, Mnemonic::CALLF | Mnemonic::JMPF)
+                }
+            };
+            if !op_match {
+                return false
+            }
+        }
+
+        if let Some(segment) = self.segment {
+            let memory_op = if operand_is_memory(i.operand1_type) {
+                i.operand1_type
+            }
+            else if operand_is_memory(i.operand2_type) {
+                i.operand2_type
+            }
+            else {
+                return false
+            };
+
+            let ea_segment = match (i.segment_override, memory_op) {
+                (SegmentOverride::ES, _) => Segment::ES,
+                (SegmentOverride::CS, _) => Segment::CS,
+                (SegmentOverride::SS, _) => Segment::SS,
+                (SegmentOverride::DS, _) => Segment::DS,
+                (SegmentOverride::None, OperandType::AddressingMode(mode)) => {
+                    match mode {
+                        AddressingMode::BpSi | AddressingMode::BpDi 
+                        | AddressingMode::BpSiDisp8(_) | AddressingMode::BpDiDisp8(_) | AddressingMode::BpDisp8(_)
+                        | AddressingMode::BpSiDisp16(_) | AddressingMode::BpDiDisp16(_) | AddressingMode::BpDisp16(_) => Segment::SS,
+                        _ => Segment::DS
+                    }
+                }
+                (SegmentOverride::None, _) => Segment::DS
+            };
+
+            if std::mem::discriminant(&ea_segment) != std::mem::discriminant(&segment) {
+                return false
+            }
+        }
+
+        true
+    }
+}
+
+fn operand_immediate(op: OperandType) -> Option<u16> {
+    match op {
+        OperandType::Immediate8(imm) => Some(imm as u16),
+        OperandType::Immediate16(imm) => Some(imm),
+        OperandType::Immediate8s(imm) => Some(imm as i16 as u16),
+        _ => None
+    }
+}
+
+fn operand_is_memory(op: OperandType) -> bool {
+    match op {
+        OperandType::AddressingMode(AddressingMode::RegisterMode) => false,
+        OperandType::AddressingMode(_) | OperandType::Offset8(_) | OperandType::Offset16(_) => true,
+        _ => false
+    }
+}
+
+/// Return true if the specified mnemonic writes to its first operand.
+fn mnemonic_writes_operand1(mnemonic: Mnemonic) -> bool {
+    !matches!(mnemonic,
+        Mnemonic::CMP | Mnemonic::TEST | Mnemonic::PUSH | Mnemonic::JMP | Mnemonic::JMPF 
+        | Mnemonic::CALL | Mnemonic::CALLF | Mnemonic::MUL | Mnemonic::IMUL | Mnemonic::DIV 
+        | Mnemonic::IDIV | Mnemonic::ESC
+    )
+}
+
+impl<'a> Cpu<'a> {
+
+    /// Disassemble the instruction at the specified address without affecting the state
+    /// of the CPU. Bytes are read directly from memory with no cycle cost.
+    /// 
+    /// Jump and call targets and direct memory operands with an entry in the symbol map are
+    /// shown by name. Memory operands are resolved using the current segment register values.
+    /// A comment defined for the address is appended as a final Comment token.
+    pub fn disassemble_at(&mut self, addr: CpuAddress) -> DisassemblyResult {
+        let cpu_type = self.cpu_type;
+        let mut result = Cpu::disassemble_bus_at(&mut self.bus, cpu_type, addr);
+
+        if !self.symbols.is_empty() {
+            if let Some(i) = &result.instruction {
+                result.tokens = Cpu::tokenize_instruction_with_symbols(i, &|op| self.operand_symbol(i, addr, op));
+            }
+        }
+        if let Some(comment) = self.comments.lookup(addr) {
+            result.tokens.push(SyntaxToken::Comment(comment.to_string()));
+        }
+        result
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolMap) {
+        self.symbols = symbols;
+    }
+
+    pub fn set_comments(&mut self, comments: CommentMap) {
+        self.comments = comments;
+    }
+
+    pub fn comments(&self) -> &CommentMap {
+        &self.comments
+    }
+
+    pub fn comments_mut(&mut self) -> &mut CommentMap {
+        &mut self.comments
+    }
+
+    /// Return the symbol name for the address referenced by an operand of the instruction 
+    /// 'i' decoded at 'addr', if any.
+    fn operand_symbol(&self, i: &Instruction, addr: CpuAddress, op: OperandType) -> Option<String> {
+
+        let rel_target = |rel: i16| {
+            match addr {
+                CpuAddress::Segmented(cs, ip) => {
+                    Some(CpuAddress::Segmented(cs, ip.wrapping_add(i.size as u16).wrapping_add(rel as u16)))
+                }
+                CpuAddress::Flat(a) => {
+                    Some(CpuAddress::Flat(a.wrapping_add(i.size).wrapping_add(rel as i32 as u32) & 0xFFFFF))
+                }
+                CpuAddress::Offset(_) => None
+            }
+        };
+
+        let data_segment = match i.segment_override {
+            SegmentOverride::ES => self.es,
+            SegmentOverride::CS => self.cs,
+            SegmentOverride::SS => self.ss,
+            SegmentOverride::DS | SegmentOverride::None => self.ds,
+        };
+
+        let target = match op {
+            OperandType::Relative8(rel) => rel_target(rel as i16),
+            OperandType::Relative16(rel) => rel_target(rel),
+            OperandType::FarAddress(segment, offset) => Some(CpuAddress::Segmented(segment, offset)),
+            OperandType::Offset8(offset) | OperandType::Offset16(offset) => {
+                Some(CpuAddress::Segmented(data_segment, offset))
+            }
+            OperandType::AddressingMode(AddressingMode::Disp16(disp)) => {
+                Some(CpuAddress::Segmented(data_segment, disp.get_i16() as u16))
+            }
+            _ => None
+        }?;
+
+        self.symbols.lookup(target).map(String::from)
+    }
+
+    /// Disassemble the instruction at the specified address on the bus. An instruction
+    /// that would extend past the end of its segment or past the end of memory is
+    /// reported as an error, as the bytes read linearly would not be the bytes the CPU
+    /// would fetch.
+    pub fn disassemble_bus_at(bus: &mut BusInterface, cpu_type: CpuType, addr: CpuAddress) -> DisassemblyResult {
+
+        let flat_addr = (u32::from(addr) & 0xFFFFF) as usize;
+        if flat_addr >= bus.size() {
+            return DisassemblyResult::error("address out of range", Vec::new());
+        }
+
+        bus.seek(flat_addr);
+        let mut i = match Cpu::decode(bus, cpu_type) {
+            Ok(i) => i,
+            Err(_) => {
+                let byte = bus.get_slice_at(flat_addr, 1).to_vec();
+                return DisassemblyResult::error("invalid instruction", byte)
+            }
+        };
+
+        i.address = flat_addr as u32;
+        let size = i.size as usize;
+        let avail = usize::min(size, bus.size() - flat_addr);
+        let bytes = bus.get_slice_at(flat_addr, avail).to_vec();
+
+        if avail < size {
+            return DisassemblyResult::error("incomplete instruction", bytes);
+        }
+        if let CpuAddress::Segmented(_, offset) = addr {
+            if offset as usize + size > 0x10000 {
+                return DisassemblyResult::error("instruction wraps segment", bytes);
+            }
+        }
+
+        DisassemblyResult {
+            tokens: Cpu::tokenize_instruction(&i),
+            instruction: Some(i),
+            bytes,
+            size,
+        }
+    }
+
+    /// Decode instructions starting at cs:ip until 'count' instructions have been decoded
+    /// or 'end_ip' has been reached or passed. Returns a vector of the offset and decoded
+    /// instruction for each instruction, and a flag indicating whether decoding landed
+    /// exactly on 'end_ip'.
+    fn disassemble_run(
+        bus: &mut BusInterface,
+        cpu_type: CpuType,
+        cs: u16,
+        ip: u16,
+        end_ip: Option<u16>,
+        count: usize
+    ) -> (Vec<(u16, Instruction)>, bool) {
+
+        let mut instructions = Vec::new();
+        let mut offset = ip;
+
+        while instructions.len() < count {
+
+            if let Some(end) = end_ip {
+                if offset == end {
+                    return (instructions, true);
+                }
+                if offset > end || offset < ip {
+                    // Overshot the target or wrapped the segment
+                    return (instructions, false);
+                }
+            }
+
+            bus.seek(Cpu::calc_linear_address(cs, offset) as usize);
+            match Cpu::decode(bus, cpu_type) {
+                Ok(i) => {
+                    let next_offset = offset.wrapping_add(i.size as u16);
+                    instructions.push((offset, i));
+                    offset = next_offset;
+                }
+                Err(_) => break
+            }
+        }
+
+        let landed = matches!(end_ip, Some(end) if offset == end);
+        (instructions, landed)
+    }
+
+    /// Produce a plain text disassembly listing of 'before' instructions preceding and 'after'
+    /// instructions following cs:ip, suitable for pasting into a bug report. The line at cs:ip
+    /// is marked with '>'.
+    ///
+    /// Backwards disassembly on a variable-length instruction set is not exact. We try every
+    /// starting offset within a short lookback window and pick the one that decodes cleanly
+    /// onto cs:ip with the most instructions, preferring the longest run on ties.
+    pub fn disassembly_snippet(
+        bus: &mut BusInterface, 
+        cpu_type: CpuType, 
+        cs: u16, 
+        ip: u16, 
+        before: usize, 
+        after: usize
+    ) -> String {
+
+        let lookback = u16::min(ip, (before as u16).saturating_mul(DISASSEMBLY_LOOKBACK_PER_INSTR));
+
+        let mut best: Vec<(u16, Instruction)> = Vec::new();
+
+        for back in (1..=lookback).rev() {
+            let (run, landed) = Cpu::disassemble_run(bus, cpu_type, cs, ip - back, Some(ip), usize::MAX);
+            if landed && run.len() > best.len() {
+                best = run;
+            }
+        }
+
+        // Only keep the requested number of preceding instructions
+        if best.len() > before {
+            best.drain(0..best.len() - before);
+        }
+
+        let (mut following, _) = Cpu::disassemble_run(bus, cpu_type, cs, ip, None, after + 1);
+        let current_idx = best.len();
+        best.append(&mut following);
+
+        let mut snippet = String::new();
+        for (idx, (offset, i)) in best.iter().enumerate() {
+            let flat_addr = Cpu::calc_linear_address(cs, *offset) as usize;
+            let instr_bytes = util::fmt_byte_array(bus.get_slice_at(flat_addr, i.size as usize));
+
+            snippet.push_str(&format!(
+                "{} {:04X}:{:04X} {:012} {}\n",
+                if idx == current_idx { ">" } else { " " },
+                cs,
+                offset,
+                instr_bytes,
+                i
+            ));
+        }
+
+        snippet
+    }
+
+    /// Scan memory forward from 'start' for a byte pattern, where None matches any byte,
+    /// returning an iterator over the address of each match. At most 'limit' addresses are
+    /// scanned. A segmented start address scans its segment, wrapping at the end of the 
+    /// segment, and is limited to a single pass through the segment. Matches are returned 
+    /// in the same form as the start address.
+    pub fn find_code(&self, pattern: &[Option<u8>], start: CpuAddress, limit: usize) -> CodeSearch<'_> {
+
+        let (next, max_limit) = match start {
+            CpuAddress::Segmented(_, _) => (start, 0x10000),
+            _ => (CpuAddress::Flat(u32::from(start) & 0xFFFFF), 0x100000)
+        };
+
+        CodeSearch {
+            bus: &self.bus,
+            pattern: pattern.to_vec(),
+            next,
+            remaining: usize::min(limit, max_limit),
+        }
+    }
+
+    /// Disassemble the instructions in the flat address range start..end, returning an
+    /// iterator over each instruction's address and disassembly. Decoding proceeds linearly
+    /// from 'start', so it should be a known instruction boundary. An instruction that begins
+    /// before 'end' is returned in full even if it extends past it. Bytes that cannot be
+    /// decoded, including an instruction truncated by the end of memory, are returned as an
+    /// error result of size 1.
+    pub fn disassemble_range(&mut self, start: u32, end: u32) -> DisassemblyRange<'_, 'a> {
+        DisassemblyRange {
+            cpu: self,
+            next: start & 0xFFFFF,
+            end: u32::min(end, 0x100000),
+        }
+    }
+
+    /// Search the range cs:start..cs:end for instructions matching the specified query,
+    /// returning the address of each match. Decoding proceeds linearly from the start
+    /// offset, so the start of the range should be a known instruction boundary.
+    pub fn search_instructions(
+        bus: &mut BusInterface,
+        cpu_type: CpuType,
+        cs: u16,
+        start: u16,
+        end: u16,
+        query: &InstructionQuery
+    ) -> Vec<CpuAddress> {
+
+        let mut results = Vec::new();
+        let mut offset = start;
+
+        while offset < end {
+            bus.seek(Cpu::calc_linear_address(cs, offset) as usize);
+
+            let size = match Cpu::decode(bus, cpu_type) {
+                Ok(i) => {
+                    if query.matches(&i) {
+                        results.push(CpuAddress::Segmented(cs, offset));
+                    }
+                    i.size as u16
+                }
+                // Skip undecodable bytes
+                Err(_) => 1
+            };
+
+            match offset.checked_add(size) {
+                Some(next) => offset = next,
+                None => break
+            }
+        }
+
+        results
+    }
+}
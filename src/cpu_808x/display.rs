@@ -9,7 +9,8 @@ use crate::syntax_token::SyntaxToken;
 #[derive(Copy, Clone)]
 pub enum OperandSelect {
     FirstOperand,
-    SecondOperand
+    SecondOperand,
+    ThirdOperand
 }
 
 fn mnemonic_to_str(op: Mnemonic) -> &'static str {
@@ -22,6 +23,7 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::ADC => "ADC",
         Mnemonic::ADD => "ADD",
         Mnemonic::AND => "AND",
+        Mnemonic::BOUND => "BOUND",
         Mnemonic::CALL => "CALL",
         Mnemonic::CALLF => "CALLF",
         Mnemonic::CBW => "CBW",
@@ -44,6 +46,8 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::IMUL => "IMUL",
         Mnemonic::IN => "IN",
         Mnemonic::INC => "INC",
+        Mnemonic::INSB => "INSB",
+        Mnemonic::INSW => "INSW",
         Mnemonic::INT => "INT",
         Mnemonic::INT3 => "INT3",
         Mnemonic::INTO => "INTO",
@@ -85,9 +89,13 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::NOT => "NOT",
         Mnemonic::OR => "OR",
         Mnemonic::OUT => "OUT",
+        Mnemonic::OUTSB => "OUTSB",
+        Mnemonic::OUTSW => "OUTSW",
         Mnemonic::POP => "POP",
+        Mnemonic::POPA => "POPA",
         Mnemonic::POPF => "POPF",
         Mnemonic::PUSH => "PUSH",
+        Mnemonic::PUSHA => "PUSHA",
         Mnemonic::PUSHF => "PUSHF",
         Mnemonic::RCL => "RCL",
         Mnemonic::RCR => "RCR",
@@ -154,12 +162,104 @@ impl fmt::Display for Instruction {
             instruction_string.push_str(&op2);
         }
 
+        let op3: String = operand_to_string(self, OperandSelect::ThirdOperand);
+        if op3.len() > 0 {
+            instruction_string.push_str(", ");
+            instruction_string.push_str(&op3);
+        }
+
         write!(f, "{}", instruction_string)
      }
 }
 
+/// Names of the bits of the FLAGS register, from bit 15 down to bit 0. Reserved bits are unnamed.
+const FLAG_NAMES: [Option<char>; 16] = [
+    None, None, None, None, Some('O'), Some('D'), Some('I'), Some('T'),
+    Some('S'), Some('Z'), None, Some('A'), None, Some('P'), None, Some('C')
+];
+
+/// Decode a FLAGS value into one StateString token per bit, from bit 15 down to bit 0.
+/// Flags are shown by their letter when set and in lower case when clear. Reserved bits are
+/// shown as '1' or '0' in the fixed state they always have on the 8088, whatever 'flags'
+/// holds. A token is marked dirty if its bit differs from 'previous'.
+pub fn decode_flags_changed(flags: u16, previous: u16) -> Vec<SyntaxToken> {
+    FLAG_NAMES.iter().enumerate().map(|(n, name)| {
+        let mask = 0x8000u16 >> n;
+        let text = match name {
+            Some(c) if flags & mask != 0 => c.to_string(),
+            Some(c) => c.to_ascii_lowercase().to_string(),
+            None => ((CPU_FLAGS_RESERVED_ON & mask != 0) as u8).to_string()
+        };
+        let dirty = name.is_some() && (flags ^ previous) & mask != 0;
+        SyntaxToken::StateString(text, dirty, 0)
+    }).collect()
+}
+
+/// Decode a FLAGS value into StateString tokens as decode_flags_changed() does, without
+/// marking any token dirty.
+pub fn decode_flags(flags: u16) -> Vec<SyntaxToken> {
+    decode_flags_changed(flags, flags)
+}
+
+/// Format a FLAGS value as a string of the tokens produced by decode_flags().
+pub fn flags_string(flags: u16) -> String {
+    decode_flags(flags).iter().filter_map(|token| match token {
+        SyntaxToken::StateString(text, _, _) => Some(text.as_str()),
+        _ => None
+    }).collect()
+}
+
 impl<'a> Cpu<'a> {
+
+    /// Build a tooltip describing a decoded instruction: its starting microcode address, the
+    /// flags it modifies, and how many cycles it took the last time it was executed if it is
+    /// present in the instruction history.
+    pub fn instruction_tooltip(&self, i: &Instruction) -> String {
+
+        let mut tooltip = format!("{}", i);
+
+        if let Some(mc) = self.microcode_address(i) {
+            tooltip.push_str(&format!(" - mc {:03X}", mc));
+        }
+
+        let flags = i.flags_affected();
+        if flags.defined() != 0 {
+            tooltip.push_str(&format!(", flags {}", flags));
+        }
+        if flags.undefined != 0 {
+            tooltip.push_str(&format!(", undefined {}", FlagsAffected::mask_string(flags.undefined)));
+        }
+
+        let last_cycles = self.instruction_history.iter().rev().find_map(|entry| match entry {
+            HistoryEntry::Entry { cycles, i: hi, .. } if hi.address == i.address => Some(*cycles),
+            _ => None
+        });
+        if let Some(cycles) = last_cycles {
+            tooltip.push_str(&format!(", last {} cycles", cycles));
+        }
+
+        tooltip
+    }
+
+    /// Return the starting microcode address of a decoded instruction. Microcode addresses
+    /// are only meaningful for the 8088's own opcodes.
+    pub fn microcode_address(&self, i: &Instruction) -> Option<u16> {
+        match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel8086 => Some(MICROCODE_ADDRESS_8088[i.opcode as usize]),
+            _ => None
+        }
+    }
+
     pub fn tokenize_instruction(i: &Instruction) -> Vec<SyntaxToken> {
+        Cpu::tokenize_instruction_with_symbols(i, &|_| None)
+    }
+
+    /// Tokenize an instruction, emitting a Symbol token in place of the address of any operand
+    /// for which 'symbol' returns a name. 'symbol' is called once for each operand.
+    pub fn tokenize_instruction_with_symbols(
+        i: &Instruction, 
+        symbol: &dyn Fn(OperandType) -> Option<String>
+    ) -> Vec<SyntaxToken> {
 
         let mut i_vec = Vec::new();
 
@@ -172,10 +272,10 @@ impl<'a> Cpu<'a> {
         let mnemonic = mnemonic_to_str(i.mnemonic).to_string().to_lowercase();
         i_vec.push(SyntaxToken::Mnemonic(mnemonic));
 
-        let mut op1_vec = tokenize_operand(i, OperandSelect::FirstOperand);
+        let mut op1_vec = tokenize_operand(i, OperandSelect::FirstOperand, symbol);
         i_vec.append(&mut op1_vec);
 
-        let mut op2_vec = tokenize_operand(i, OperandSelect::SecondOperand);
+        let mut op2_vec = tokenize_operand(i, OperandSelect::SecondOperand, symbol);
 
         if op2_vec.len() > 0 {
             i_vec.push(SyntaxToken::Comma);
@@ -183,6 +283,14 @@ impl<'a> Cpu<'a> {
 
         i_vec.append(&mut op2_vec);
 
+        let mut op3_vec = tokenize_operand(i, OperandSelect::ThirdOperand, symbol);
+
+        if op3_vec.len() > 0 {
+            i_vec.push(SyntaxToken::Comma);
+        }
+
+        i_vec.append(&mut op3_vec);
+
         i_vec
     }
 }
@@ -197,7 +305,8 @@ fn operand_to_string(i: &Instruction, op: OperandSelect) -> String {
 
     let (op_type, op_size) = match op {
         OperandSelect::FirstOperand => (i.operand1_type, i.operand1_size),
-        OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size)
+        OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size),
+        OperandSelect::ThirdOperand => (i.operand3_type, i.operand3_size)
     };
     
     let instruction_string: String = match op_type {
@@ -376,24 +485,42 @@ fn operand_to_string(i: &Instruction, op: OperandSelect) -> String {
 }
 
 
-fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
+fn tokenize_operand(i: &Instruction, op: OperandSelect, symbol: &dyn Fn(OperandType) -> Option<String>) -> Vec<SyntaxToken> {
 
     let (op_type, op_size) = match op {
         OperandSelect::FirstOperand => (i.operand1_type, i.operand1_size),
-        OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size)
+        OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size),
+        OperandSelect::ThirdOperand => (i.operand3_type, i.operand3_size)
     };
     
     let mut op_vec = Vec::new();
 
+    // Use the symbol for the operand's address, if there is one.
+    let symbol_name = symbol(op_type);
+    let address_token = |value: u32, width: u8, hex_str: String| {
+        match &symbol_name {
+            Some(name) => SyntaxToken::Symbol(name.clone()),
+            None => SyntaxToken::HexValue(value, width, hex_str)
+        }
+    };
+    let disp_token = |disp: Displacement| {
+        let (value, width) = match disp {
+            Displacement::Disp8(d) => (d as u8 as u32, 1),
+            Displacement::Disp16(d) => (d as u16 as u32, 2),
+            _ => (0, 2)
+        };
+        SyntaxToken::Displacement(value, width, format!("{}", disp))
+    };
+
     match op_type {
         OperandType::Immediate8(imm8) => {
-            op_vec.push(SyntaxToken::HexValue(format!("{:02X}h", imm8)));
+            op_vec.push(SyntaxToken::HexValue(imm8 as u32, 1, format!("{:02X}h", imm8)));
         }
         OperandType::Immediate8s(imm8s) => {
-            op_vec.push(SyntaxToken::HexValue(format!("{:02X}h", imm8s)));
+            op_vec.push(SyntaxToken::HexValue(imm8s as u8 as u32, 1, format!("{:02X}h", imm8s)));
         }        
         OperandType::Immediate16(imm16) => {
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", imm16)));
+            op_vec.push(SyntaxToken::HexValue(imm16 as u32, 2, format!("{:04X}h", imm16)));
         }
         OperandType::Relative8(rel8) => {
             //if i.flags & INSTRUCTION_REL_JUMP != 0 {
@@ -404,7 +531,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
             //else {
             //    format!("{:#06X}", rel8)
             //}
-            op_vec.push(SyntaxToken::HexValue(format!("{:02X}h", rel8)));
+            op_vec.push(address_token(rel8 as u8 as u32, 1, format!("{:02X}h", rel8)));
         }
         OperandType::Relative16(rel16) => {
             //if i.flags & INSTRUCTION_REL_JUMP != 0 {
@@ -415,7 +542,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
             //else {
             //    format!("{:#06X}", rel16)
             //}            
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", rel16)));
+            op_vec.push(address_token(rel16 as u16 as u32, 2, format!("{:04X}h", rel16)));
         }
         OperandType::Offset8(offset8) => {
             let segment;
@@ -437,7 +564,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
             op_vec.push(SyntaxToken::Segment(segment));
             op_vec.push(SyntaxToken::Colon);
             op_vec.push(SyntaxToken::OpenBracket);
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", offset8)));
+            op_vec.push(address_token(offset8 as u32, 2, format!("{:04X}h", offset8)));
             op_vec.push(SyntaxToken::CloseBracket);
         }
         OperandType::Offset16(offset16) => {
@@ -461,7 +588,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
             op_vec.push(SyntaxToken::Segment(segment));
             op_vec.push(SyntaxToken::Colon);
             op_vec.push(SyntaxToken::OpenBracket);
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", offset16)));
+            op_vec.push(address_token(offset16 as u32, 2, format!("{:04X}h", offset16)));
             op_vec.push(SyntaxToken::CloseBracket);            
         }
         OperandType::Register8(reg8) => {
@@ -585,9 +712,13 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
                     // Have first component of ea
                     op_vec.push(SyntaxToken::Register(ea_vec[0].to_string()));
                 }
+                else if let Some(name) = &symbol_name {
+                    // Direct address with a symbol
+                    op_vec.push(SyntaxToken::Symbol(name.clone()));
+                }
                 else if let Some(disp) = disp_opt {
                     // Displacement by itself
-                    op_vec.push(SyntaxToken::Displacement(format!("{}", disp)));
+                    op_vec.push(disp_token(disp));
                 }
 
                 if ea_vec[1].len() > 0 {
@@ -600,7 +731,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
                     // Have at least one ea component. Add +displacement if present.
                     if let Some(disp) = disp_opt {
                         op_vec.push(SyntaxToken::PlusSign);
-                        op_vec.push(SyntaxToken::Displacement(format!("{}", disp)));
+                        op_vec.push(disp_token(disp));
                     }                    
                 }
 
@@ -610,14 +741,19 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect) -> Vec<SyntaxToken> {
         OperandType::NearAddress(offset) => {
 
             op_vec.push(SyntaxToken::OpenBracket);
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", offset)));
+            op_vec.push(SyntaxToken::HexValue(offset as u32, 2, format!("{:04X}h", offset)));
             op_vec.push(SyntaxToken::CloseBracket);
         }
         OperandType::FarAddress(segment, offset) => {
             op_vec.push(SyntaxToken::Text("far".to_string()));
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", segment)));
-            op_vec.push(SyntaxToken::Colon);
-            op_vec.push(SyntaxToken::HexValue(format!("{:04X}h", offset)));
+            if let Some(name) = &symbol_name {
+                op_vec.push(SyntaxToken::Symbol(name.clone()));
+            }
+            else {
+                op_vec.push(SyntaxToken::HexValue(segment as u32, 2, format!("{:04X}h", segment)));
+                op_vec.push(SyntaxToken::Colon);
+                op_vec.push(SyntaxToken::HexValue(offset as u32, 2, format!("{:04X}h", offset)));
+            }
         }
         _ => {}
     };
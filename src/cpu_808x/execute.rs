@@ -10,17 +10,47 @@ use crate::util;
 
 // This macro can only be used if operand has already been fetched by decode()
 macro_rules! get_operand {
-    ($target: expr, $pat: path) => {
+    ($self: ident, $target: expr, $pat: path) => {
         {
             if let $pat(a) = $target {
                 a
             } else {
-                panic!("Unexpected operand type.");
+                return $self.operand_error("Unexpected operand type")
             }
         }
     };
 }
 
+// The following macros read an operand, returning an ExecutionError from execute_instruction()
+// if the operand type is not valid for the read, so that arbitrary byte streams can be executed
+// without panicking.
+macro_rules! read_operand8 {
+    ($self: ident, $op: expr, $seg: expr) => {
+        match $self.read_operand8($op, $seg) {
+            Some(value) => value,
+            None => return $self.operand_error("Invalid 8-bit operand")
+        }
+    };
+}
+
+macro_rules! read_operand16 {
+    ($self: ident, $op: expr, $seg: expr) => {
+        match $self.read_operand16($op, $seg) {
+            Some(value) => value,
+            None => return $self.operand_error("Invalid 16-bit operand")
+        }
+    };
+}
+
+macro_rules! read_operand_farptr {
+    ($self: ident, $op: expr, $seg: expr, $flag: expr) => {
+        match $self.read_operand_farptr($op, $seg, $flag) {
+            Some(value) => value,
+            None => return $self.operand_error("Invalid far pointer operand")
+        }
+    };
+}
+
 /*
 macro_rules! read_operand {
     ($self:ident, $op: expr) => {
@@ -79,8 +109,10 @@ impl<'a> Cpu<'a> {
         let mut unhandled: bool = false;
         let mut jump: bool = false;
         let mut exception: CpuException = CpuException::NoException;
+        let mut runaway: bool = false;
 
         self.step_over_target = None;
+        self.operand_size_error = None;
 
         let mut handled_override = match self.i.segment_override {
             SegmentOverride::None => true,
@@ -135,6 +167,10 @@ impl<'a> Cpu<'a> {
                 Mnemonic::STOSB | Mnemonic::STOSW | Mnemonic::LODSB | Mnemonic::LODSW | Mnemonic::MOVSB | Mnemonic::MOVSW => {
                     self.rep_type = RepType::Rep;
                 }
+                Mnemonic::INSB | Mnemonic::INSW | Mnemonic::OUTSB | Mnemonic::OUTSW => {
+                    // 80186 string IO instructions
+                    self.rep_type = RepType::Rep;
+                }
                 Mnemonic::SCASB | Mnemonic::SCASW | Mnemonic::CMPSB | Mnemonic::CMPSW => {
                     // Valid string ops with REP prefix
                     if self.i.prefixes & OPCODE_PREFIX_REP1 != 0 {
@@ -163,25 +199,24 @@ impl<'a> Cpu<'a> {
             }
         }
 
-        // Reset the wait cycle after STI
+        // Reset the wait cycle after STI or a load of SS
         self.interrupt_inhibit = false;
+        self.nmi_inhibit = false;
         
         // Most instructions will issue an RNI. We can set RNI to false for those that don't.
         //self.rni = true;
 
         // Keep a tally of how many Opcode 0x00's we've executed in a row. Too many likely means we've run 
-        // off the rails into uninitialized memory, whereupon we halt so we can check things out.
+        // off the rails into uninitialized memory. Once the threshold is exceeded we report it once per run
+        // of 0x00's, and step() decides whether to halt so we can check things out.
 
         // This is now optional in the configuration file, as some test applications like acid88 won't work
         // otherwise.
         if self.i.opcode == 0x00 {
             self.opcode0_counter = self.opcode0_counter.wrapping_add(1);
 
-            if self.off_rails_detection && (self.opcode0_counter > 5) {
-                // Halt permanently by clearing interrupt flag
-
-                self.clear_flag(Flag::Interrupt);
-                self.halted = true;
+            if self.off_rails_detection && (self.opcode0_counter == self.off_rails_threshold.wrapping_add(1)) {
+                runaway = true;
             }
         }
         else {
@@ -198,8 +233,8 @@ impl<'a> Cpu<'a> {
             0x30 | 0x32 | 0x34 => // XOR r/m8, r8 | r8, r/m8 | al, imm8
             { 
                 // 16 bit ADD variants
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 
                 self.cycles_nx_i(2, &[0x008, 0x009]);
 
@@ -220,8 +255,8 @@ impl<'a> Cpu<'a> {
             0x31 | 0x33 | 0x35 => // XOR r/m16, r16 | r16, r/m16 | ax, imm16
             {
                 // 16 bit ADD variants
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 
                 self.cycles_nx_i(2, &[0x008, 0x009]);
 
@@ -254,6 +289,9 @@ impl<'a> Cpu<'a> {
             0x0F => {
                 // POP cs
                 // Flags: None
+                // Unlike a far jump, POP CS doesn't flush the queue. Instructions already in the queue
+                // (and any fetch in progress) come from the old CS, then fetching continues at the
+                // same offset in the new CS. See biu_update_cs().
                 self.pop_register16(Register16::CS, ReadWriteFlag::RNI);
                 //self.cycle();
             }
@@ -302,13 +340,14 @@ impl<'a> Cpu<'a> {
             }
             0x37 => {
                 // AAA
+                // Microcode cycles are run in aaa(), as they depend on whether AL is adjusted
                 self.aaa();
             }
             0x38 | 0x3A | 0x3C => {
                 // CMP r/m8,r8 | r8, r/m8 | al,imm8 
                 // CMP 8-bit variants
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 
                 if self.i.opcode == 0x3C {
                     // 0x018
@@ -326,8 +365,8 @@ impl<'a> Cpu<'a> {
             0x39 | 0x3B | 0x3D => {
                 // CMP r/m16,r16 | r16, r/m16 | ax,imm16 
                 // CMP 16-bit variants
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
 
                 if self.i.opcode == 0x3D {
                     // 0x018
@@ -347,11 +386,12 @@ impl<'a> Cpu<'a> {
             }
             0x3F => {
                 // AAS
+                // Microcode cycles are run in aas(), as for AAA
                 self.aas();
             }
             0x40..=0x47 => {
                 // INC r16 register-encoded operands
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                 // math_op16 handles flags
                 let result = self.math_op16(Mnemonic::INC, op1_value, 0);
                 self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
@@ -361,7 +401,7 @@ impl<'a> Cpu<'a> {
             }
             0x48..=0x4F => {
                 // DEC r16 register-encoded operands
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                 // math_op16 handles flags
                 let result = self.math_op16(Mnemonic::DEC, op1_value, 0);
                 self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
@@ -385,6 +425,113 @@ impl<'a> Cpu<'a> {
                 self.pop_register16(reg, ReadWriteFlag::RNI);
                 self.cycle_nx_i(0x035);
             }
+            0x60 if self.cpu_type.has_186_instructions() => {
+                // PUSHA - Push all general registers (80186)
+                // Flags: None
+                // The value of SP pushed is the value before the instruction began.
+                let sp = self.sp;
+                self.push_register16(Register16::AX, ReadWriteFlag::Normal);
+                self.push_register16(Register16::CX, ReadWriteFlag::Normal);
+                self.push_register16(Register16::DX, ReadWriteFlag::Normal);
+                self.push_register16(Register16::BX, ReadWriteFlag::Normal);
+                self.push_u16(sp, ReadWriteFlag::Normal);
+                self.push_register16(Register16::BP, ReadWriteFlag::Normal);
+                self.push_register16(Register16::SI, ReadWriteFlag::Normal);
+                self.push_register16(Register16::DI, ReadWriteFlag::RNI);
+            }
+            0x61 if self.cpu_type.has_186_instructions() => {
+                // POPA - Pop all general registers (80186)
+                // Flags: None
+                // The stored value of SP is discarded.
+                self.pop_register16(Register16::DI, ReadWriteFlag::Normal);
+                self.pop_register16(Register16::SI, ReadWriteFlag::Normal);
+                self.pop_register16(Register16::BP, ReadWriteFlag::Normal);
+                _ = self.pop_u16();
+                self.pop_register16(Register16::BX, ReadWriteFlag::Normal);
+                self.pop_register16(Register16::DX, ReadWriteFlag::Normal);
+                self.pop_register16(Register16::CX, ReadWriteFlag::Normal);
+                self.pop_register16(Register16::AX, ReadWriteFlag::RNI);
+            }
+            0x62 if self.cpu_type.has_186_instructions() => {
+                // BOUND - Check array index against bounds (80186)
+                // Flags: None
+                // Operand 2 points to a pair of signed words, the lower and upper bound.
+                let index = read_operand16!(self, self.i.operand1_type, self.i.segment_override) as i16;
+                let (upper, lower) = 
+                    read_operand_farptr!(self,
+                        self.i.operand2_type, 
+                        self.i.segment_override,
+                        ReadWriteFlag::Normal
+                    );
+
+                if index < lower as i16 || index > upper as i16 {
+                    // Out of bounds. INT 5 is a fault; the return address is the BOUND instruction itself.
+                    self.sw_interrupt(5);
+                    jump = true;
+                }
+                handled_override = true;
+            }
+            0x63..=0x67 if self.cpu_type.has_186_instructions() => {
+                // Undefined opcode (80186)
+                // INT 6 is a fault; the return address is the undefined opcode itself.
+                self.sw_interrupt(6);
+                jump = true;
+            }
+            0x68 | 0x6A if self.cpu_type.has_186_instructions() => {
+                // PUSH imm16 / PUSH imm8 (sign-extended) (80186)
+                // Flags: None
+                let value = match self.i.operand1_type {
+                    OperandType::Immediate16(_) => read_operand16!(self, self.i.operand1_type, SegmentOverride::None),
+                    _ => read_operand8!(self, self.i.operand1_type, SegmentOverride::None) as i8 as i16 as u16
+                };
+                self.cycles(2);
+                self.push_u16(value, ReadWriteFlag::RNI);
+            }
+            0x69 | 0x6B if self.cpu_type.has_186_instructions() => {
+                // IMUL r16, r/m16, imm16 / imm8 (sign-extended) (80186)
+                // Flags: o..szapc (Only CF and OF are defined)
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
+                let op3_value = match self.i.operand3_type {
+                    OperandType::Immediate16(_) => read_operand16!(self, self.i.operand3_type, SegmentOverride::None),
+                    _ => read_operand8!(self, self.i.operand3_type, SegmentOverride::None) as i8 as i16 as u16
+                };
+
+                let product = (op2_value as i16 as i32) * (op3_value as i16 as i32);
+                let result = product as u16;
+
+                // Carry and overflow are set if the product did not fit in the destination register
+                let overflow = product != (result as i16 as i32);
+                self.set_flag_state(Flag::Carry, overflow);
+                self.set_flag_state(Flag::Overflow, overflow);
+
+                self.cycles(20);
+                self.write_operand16(self.i.operand1_type, SegmentOverride::None, result, ReadWriteFlag::RNI);
+                handled_override = true;
+            }
+            0x6C..=0x6F if self.cpu_type.has_186_instructions() => {
+                // INSB, INSW, OUTSB, OUTSW (80186)
+                // Flags: None
+                if self.rep_start() {
+
+                    self.string_op(self.i.mnemonic, self.i.segment_override);
+                    self.cycles(4);
+
+                    // Check for end condition (CX==0)
+                    if self.in_rep {
+
+                        // Check for interrupt
+                        if self.pending_interrupt {
+                            self.rep_interrupt();
+                        }
+
+                        self.decrement_register16(Register16::CX);
+                        if self.cx == 0 {
+                            self.rep_end();
+                        }
+                    }
+                }
+                handled_override = true;
+            }
             0x60..=0x7F => {
                 // JMP rel8 variants
                 // Note that 0x60-6F maps to 0x70-7F on 8088
@@ -408,7 +555,7 @@ impl<'a> Cpu<'a> {
                     _ => false
                 };
 
-                let rel8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                 self.cycle_i(0x0e9);
 
                 if jump {
@@ -424,8 +571,8 @@ impl<'a> Cpu<'a> {
             }
             0x80 | 0x82 => {
                 // ADD/OR/ADC/SBB/AND/SUB/XOR/CMP r/m8, imm8
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 
                 self.cycle_nx();
                 let result = self.math_op8(self.i.mnemonic, op1_value, op2_value);
@@ -441,8 +588,8 @@ impl<'a> Cpu<'a> {
             }
             0x81 => {
                 // ADD/OR/ADC/SBB/AND/SUB/XOR/CMP r/m16, imm16
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 
                 self.cycle_nx();
                 let result = self.math_op16(self.i.mnemonic, op1_value, op2_value);
@@ -463,8 +610,8 @@ impl<'a> Cpu<'a> {
             }
             0x83 => {
                 // ADD/ADC/SBB/SUB/CMP r/m16, imm8 (sign-extended)
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                 let sign_extended = op2_value as i8 as i16 as u16;
 
@@ -483,8 +630,8 @@ impl<'a> Cpu<'a> {
             0x84 => {
                 // TEST r/m8, r8
                 // Flags: o..sz.pc
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 
                 self.math_op8(Mnemonic::TEST, op1_value, op2_value);
                 self.cycles_nx_i(2, &[0x94]);
@@ -499,8 +646,8 @@ impl<'a> Cpu<'a> {
             0x85 => {
                 // TEST r/m16, r16
                 // Flags: o..sz.pc                
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 // math_op16 handles flags
                 self.math_op16(Mnemonic::TEST, op1_value, op2_value);
                 self.cycles_nx_i(2, &[0x94]);
@@ -513,8 +660,8 @@ impl<'a> Cpu<'a> {
             }
             0x86 => {
                 // XCHG r8, r/m8
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                 self.cycles_nx(3);
                 
@@ -530,8 +677,8 @@ impl<'a> Cpu<'a> {
             }
             0x87 => {
                 // XCHG r16, r/m16
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
 
                 self.cycles_nx(3);
 
@@ -548,7 +695,7 @@ impl<'a> Cpu<'a> {
             0x88 | 0x8A => {
                 // MOV r/m8, r8  |  MOV r8, r/m8
                 self.cycle_nx();
-                let op_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                 if let OperandType::AddressingMode(_) = self.i.operand1_type {
                     self.cycles_i(2, &[0x000, 0x001]);
@@ -559,7 +706,7 @@ impl<'a> Cpu<'a> {
             0x89 | 0x8B => {
                 // MOV r/m16, r16  |  MOV r16, r/m16
                 self.cycle_nx();
-                let op_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
 
                 if let OperandType::AddressingMode(_) = self.i.operand1_type {
                     self.cycles_i(2, &[0x000, 0x001]);
@@ -573,7 +720,7 @@ impl<'a> Cpu<'a> {
                 if let OperandType::AddressingMode(_) = self.i.operand1_type {
                     self.cycle_i(0x0ec);
                 }           
-                let op_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 self.write_operand16(self.i.operand1_type, self.i.segment_override, op_value, ReadWriteFlag::RNI);
                 handled_override = true;
             }
@@ -689,7 +836,7 @@ impl<'a> Cpu<'a> {
             0xA0 => {
                 // MOV al, offset8
                 // These MOV variants are unique in that they take a direct offset with no modr/m byte
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 //self.cycle_i(0x063);
                 self.set_register8(Register8::AL, op2_value);
                 handled_override = true;
@@ -697,7 +844,7 @@ impl<'a> Cpu<'a> {
             0xA1 => {
                 // MOV AX, offset16
                 // These MOV variants are unique in that they take a direct offset with no modr/m byte
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 //self.cycle_i(0x063);
                 self.set_register16(Register16::AX, op2_value);                
                 handled_override = true;
@@ -821,7 +968,7 @@ impl<'a> Cpu<'a> {
                 // TEST al, imm8
                 // Flags: o..sz.pc
                 let op1_value = self.al;
-                let op2_value = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap();
+                let op2_value = read_operand8!(self, self.i.operand2_type, SegmentOverride::None);
                 
                 self.math_op8(Mnemonic::TEST,  op1_value, op2_value);
             }
@@ -829,7 +976,7 @@ impl<'a> Cpu<'a> {
                 // TEST ax, imm16
                 // Flags: o..sz.pc
                 let op1_value = self.ax;
-                let op2_value = self.read_operand16(self.i.operand2_type, SegmentOverride::None).unwrap();
+                let op2_value = read_operand16!(self, self.i.operand2_type, SegmentOverride::None);
                 
                 self.math_op16(Mnemonic::TEST,  op1_value, op2_value);
             }
@@ -907,7 +1054,7 @@ impl<'a> Cpu<'a> {
             }
             0xB0..=0xB7 => {
                 // MOV r8, imm8
-                let op2_value = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap();
+                let op2_value = read_operand8!(self, self.i.operand2_type, SegmentOverride::None);
                 if let OperandType::Register8(reg) = self.i.operand1_type { 
                     self.set_register8(reg, op2_value);
                 }
@@ -916,18 +1063,40 @@ impl<'a> Cpu<'a> {
             }
             0xB8..=0xBF => {
                 // MOV r16, imm16
-                let op2_value = self.read_operand16(self.i.operand2_type, SegmentOverride::None).unwrap();
+                let op2_value = read_operand16!(self, self.i.operand2_type, SegmentOverride::None);
                 if let OperandType::Register16(reg) = self.i.operand1_type { 
                     self.set_register16(reg, op2_value);
                 }
                 //self.cycle_i(0x01e);
             }
+            0xC0 if self.cpu_type.has_186_instructions() => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m8, imm8 (80186)
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, SegmentOverride::None);
+
+                self.cycles(5 + (op2_value & 0x1F) as u32);
+                let result = self.bitshift_op8(self.i.mnemonic, op1_value, op2_value);
+
+                self.write_operand8(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+                handled_override = true;
+            }
+            0xC1 if self.cpu_type.has_186_instructions() => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m16, imm8 (80186)
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, SegmentOverride::None);
+
+                self.cycles(5 + (op2_value & 0x1F) as u32);
+                let result = self.bitshift_op16(self.i.mnemonic, op1_value, op2_value);
+
+                self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+                handled_override = true;
+            }
             0xC0 | 0xC2 => {
                 // RETN imm16 - Return from call w/ release
                 // 0xC0 undocumented alias for 0xC2
                 // Flags: None
 
-                let stack_disp = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let stack_disp = read_operand16!(self, self.i.operand1_type, SegmentOverride::None);
                 self.cycle_i(MC_JUMP); // JMP to FARRET
                 let new_ip = self.pop_u16();
                 self.ip = new_ip;
@@ -965,11 +1134,11 @@ impl<'a> Cpu<'a> {
                 // LES - Load ES from Pointer
                 // Operand 2 is far pointer
                 let (les_segment, les_offset) = 
-                    self.read_operand_farptr(
+                    read_operand_farptr!(self,
                         self.i.operand2_type, 
                         self.i.segment_override,
                         ReadWriteFlag::Normal
-                    ).unwrap();
+                    );
 
                 //log::trace!("LES instruction: Loaded {:04X}:{:04X}", les_segment, les_offset);
                 self.write_operand16(
@@ -984,11 +1153,11 @@ impl<'a> Cpu<'a> {
                 // LDS - Load DS from Pointer
                 // Operand 2 is far pointer
                 let (lds_segment, lds_offset) = 
-                    self.read_operand_farptr(
+                    read_operand_farptr!(self,
                         self.i.operand2_type, 
                         self.i.segment_override,
                         ReadWriteFlag::RNI
-                    ).unwrap();
+                    );
 
                 //log::trace!("LDS instruction: Loaded {:04X}:{:04X}", lds_segment, lds_offset);
                 self.write_operand16(
@@ -1003,7 +1172,7 @@ impl<'a> Cpu<'a> {
             }
             0xC6 => {
                 // MOV r/m8, imm8
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
                 self.cycles(2);
                 self.write_operand8(self.i.operand1_type, self.i.segment_override, op2_value, ReadWriteFlag::RNI);
                 
@@ -1011,7 +1180,7 @@ impl<'a> Cpu<'a> {
             }
             0xC7 => {
                 // MOV r/m16, imm16
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 self.cycle_i(0x01e);
                 self.write_operand16(self.i.operand1_type, self.i.segment_override, op2_value, ReadWriteFlag::RNI);
                 
@@ -1020,7 +1189,7 @@ impl<'a> Cpu<'a> {
             0xC8 | 0xCA => {
                 // RETF imm16 - Far Return w/ release 
                 // 0xC8 undocumented alias for 0xCA
-                let stack_disp = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let stack_disp = read_operand16!(self, self.i.operand1_type, SegmentOverride::None);
                 self.farret(true);
                 self.release(stack_disp);
                 self.cycle_i(0x0ce);
@@ -1055,7 +1224,7 @@ impl<'a> Cpu<'a> {
                 self.step_over_target = Some(CpuAddress::Segmented(self.cs, self.ip));
 
                 // Get interrupt number (immediate operand)
-                let irq = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let irq = read_operand8!(self, self.i.operand1_type, SegmentOverride::None);
                 self.cycle_i(MC_JUMP); // Jump to INTR
                 self.sw_interrupt(irq);
                 jump = true;
@@ -1082,7 +1251,7 @@ impl<'a> Cpu<'a> {
             0xD0 => {
                 // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m8, 0x01
 
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                 let result = self.bitshift_op8(self.i.mnemonic, op1_value, 1);
                 if let OperandType::AddressingMode(_) = self.i.operand1_type {
                     self.cycle_i(0x088);
@@ -1093,7 +1262,7 @@ impl<'a> Cpu<'a> {
             0xD1 => {
                 // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m16, 0x01
 
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                 let result = self.bitshift_op16(self.i.mnemonic, op1_value, 1);
                 if let OperandType::AddressingMode(_) = self.i.operand1_type {
                     self.cycle_i(0x088); 
@@ -1103,8 +1272,8 @@ impl<'a> Cpu<'a> {
             }
             0xD2 => {
                 // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m8, cl
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                 self.cycles_i(6, &[0x08c, 0x08d, 0x08e, MC_JUMP, 0x090, 0x091]);
                 //self.cycles_i(5, &[0x08d, 0x08e, MC_JUMP, 0x090, 0x091]);
@@ -1128,8 +1297,8 @@ impl<'a> Cpu<'a> {
             }
             0xD3 => {
                 // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m16, cl
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                 self.cycles_i(6, &[0x08c, 0x08d, 0x08e, MC_JUMP, 0x090, 0x091]);
                 //self.cycles_i(5, &[0x08d, 0x08e, MC_JUMP, 0x090, 0x091]);
@@ -1153,8 +1322,8 @@ impl<'a> Cpu<'a> {
             }
             0xD4 => {
                 // AAM - Ascii adjust AX after Multiply
-                // Get imm8 value
-                let op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                // Get imm8 base. A base of 0 raises a divide error.
+                let op1_value = read_operand8!(self, self.i.operand1_type, SegmentOverride::None);
                 
                 if !self.aam(op1_value) {
                     exception = CpuException::DivideError;
@@ -1162,19 +1331,22 @@ impl<'a> Cpu<'a> {
             }
             0xD5 => {
                 // AAD - Ascii Adjust before Division
-                let op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let op1_value = read_operand8!(self, self.i.operand1_type, SegmentOverride::None);
                 self.aad(op1_value);
             }
             0xD6 => {
                 // SALC - Undocumented Opcode - Set Carry flag in AL
                 // http://www.rcollins.org/secrets/opcodes/SALC.html
+                // Flags: None
 
-                self.set_register8(Register8::AL,
-                    match self.get_flag(Flag::Carry) {
-                        true => 0xFF,
-                        false => 0
-                    }
-                );
+                if self.cpu_type.has_salc() {
+                    self.set_register8(Register8::AL,
+                        match self.get_flag(Flag::Carry) {
+                            true => 0xFF,
+                            false => 0
+                        }
+                    );
+                }
             }
             0xD7 => {
                 // XLAT
@@ -1194,9 +1366,19 @@ impl<'a> Cpu<'a> {
             }
             0xD8..=0xDF => {
                 // ESC - FPU instructions. 
-                
-                // Perform dummy read if memory operand
-                let _op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override);
+                if self.fpu_present {
+                    // The memory operand, if any, was loaded by EALOAD. The FPU performs any 
+                    // further reads or writes itself.
+                    self.fpu_execute();
+                }
+                else {
+                    // No coprocessor present. Trap to INT 7 with the return address following 
+                    // the ESC instruction, so that a handler can emulate the instruction or 
+                    // simply return.
+                    self.ip = self.ip.wrapping_add(self.i.size as u16);
+                    self.sw_interrupt(7);
+                    jump = true;
+                }
             }
             0xE0 | 0xE1 => {
                 // LOOPNE & LOOPE
@@ -1212,7 +1394,7 @@ impl<'a> Cpu<'a> {
                     zero_condition = !zero_condition;
                 }
 
-                let rel8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
 
                 if self.cx != 0 && zero_condition {
                     let new_ip = util::relative_offset_u16(self.ip, rel8 as i8 as i16 + self.i.size as i16 );
@@ -1230,7 +1412,7 @@ impl<'a> Cpu<'a> {
                 self.decrement_register16(Register16::CX);
                 self.cycles_i(2, &[0x140, 0x141]);
 
-                let rel8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
 
                 if self.cx != 0 {
                     let new_ip = util::relative_offset_u16(self.ip, rel8 as i8 as i16 + self.i.size as i16 );
@@ -1246,7 +1428,7 @@ impl<'a> Cpu<'a> {
                 // Flags: None
             
                 self.cycles_i(2, &[0x138, 0x139]);
-                let rel8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
 
                 self.cycle_i(0x13b);
 
@@ -1261,7 +1443,7 @@ impl<'a> Cpu<'a> {
             }
             0xE4 => {
                 // IN al, imm8
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap(); 
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override); 
                 self.cycles_i(2, &[0x0ad, 0x0ae]);
 
                 let in_byte = self.biu_io_read_u8(op2_value as u16);
@@ -1270,7 +1452,7 @@ impl<'a> Cpu<'a> {
             }
             0xE5 => {
                 // IN ax, imm8
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap(); 
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override); 
                 self.cycles_i(2, &[0x0ad, 0x0ae]);
 
                 let in_byte = self.biu_io_read_u8(op2_value as u16);
@@ -1278,8 +1460,8 @@ impl<'a> Cpu<'a> {
             }
             0xE6 => {
                 // OUT imm8, al
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();                
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);                
                 self.cycles_i(2, &[0x0b1, 0x0b2]);
 
                 // Write to port
@@ -1287,8 +1469,8 @@ impl<'a> Cpu<'a> {
             }
             0xE7 => {
                 // OUT imm8, ax
-                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();                
+                let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);                
                 self.cycles_i(2, &[0x0b1, 0x0b2]);
 
                 // Write to consecutive ports
@@ -1306,7 +1488,7 @@ impl<'a> Cpu<'a> {
                 // Unique microcode routine. Does not call NEARCALL.
 
                 // Fetch rel16 operand
-                let rel16 = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel16 = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
 
                 self.biu_suspend_fetch();
                 self.cycles_i(4, &[0x07e, 0x07f, MC_CORR, 0x080]);
@@ -1344,7 +1526,7 @@ impl<'a> Cpu<'a> {
             }
             0xE9 => {
                 // JMP rel16
-                let rel16 = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel16 = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                 let new_ip = Cpu::relative_offset_u16(self.ip, rel16 as i16 + self.i.size as i16 );
 
                 // We fall through to reljmp, so no jump
@@ -1366,7 +1548,7 @@ impl<'a> Cpu<'a> {
             }
             0xEB => {
                 // JMP rel8
-                let rel8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let rel8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                 let new_ip = Cpu::relative_offset_u16(self.ip, rel8 as i8 as i16 + self.i.size as i16 );
 
                 self.reljmp(new_ip, true); // We jump directly into reljmp
@@ -1374,20 +1556,20 @@ impl<'a> Cpu<'a> {
             }
             0xEC => {
                 // IN al, dx
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap(); 
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override); 
                 let in_byte = self.biu_io_read_u8(op2_value);
                 self.set_register8(Register8::AL, in_byte);
             }
             0xED => {
                 // IN ax, dx
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap(); 
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override); 
                 let in_byte = self.biu_io_read_u8(op2_value);
                 self.set_register16(Register16::AX, in_byte as u16);
             }
             0xEE => {
                 // OUT dx, al
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();                
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);                
                 self.cycle_i(0x0b8);
 
                 self.biu_io_write_u8(op1_value as u16, op2_value, ReadWriteFlag::RNI);  
@@ -1395,8 +1577,8 @@ impl<'a> Cpu<'a> {
             0xEF => {
                 // OUT dx, ax
                 // On the 8088, this does two writes to successive port #'s 
-                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                 self.cycle_i(0x0b8);
 
                 if op1_value == 0x06 {
@@ -1449,8 +1631,8 @@ impl<'a> Cpu<'a> {
                 match self.i.mnemonic {
 
                     Mnemonic::TEST => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
-                        let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
+                        let op2_value = read_operand8!(self, self.i.operand2_type, self.i.segment_override);
 
                         // 8 bit TEST takes a jump
                         self.cycles_i(2, &[MC_JUMP, 0x09a]);
@@ -1459,7 +1641,7 @@ impl<'a> Cpu<'a> {
                         let _result = self.math_op8(self.i.mnemonic, op1_value, op2_value);
                     }
                     Mnemonic::NOT => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op8(self.i.mnemonic, op1_value, 0);
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
@@ -1468,7 +1650,7 @@ impl<'a> Cpu<'a> {
                         self.write_operand8(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
                     }
                     Mnemonic::NEG => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op8(self.i.mnemonic, op1_value, 0);
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
@@ -1477,25 +1659,30 @@ impl<'a> Cpu<'a> {
                         self.write_operand8(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
                     }
                     Mnemonic::MUL => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         
                         //self.multiply_u8(op1_value);
                         let product = self.mul8(self.al, op1_value, false, negate);
                         self.set_register16(Register16::AX, product);
 
-                        self.set_szp_flags_from_result_u8(self.ah);
+                        // With strict undefined flags, mul8/mul16 leave the flags set by the microcode.
+                        if !self.strict_undefined_flags {
+                            self.set_szp_flags_from_result_u8(self.ah);
+                        }
                     }
                     Mnemonic::IMUL => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         
                         //self.multiply_i8(op1_value as i8);
                         let product = self.mul8(self.al, op1_value, true, negate);
                         self.set_register16(Register16::AX, product);
 
-                        self.set_szp_flags_from_result_u8(self.ah);
+                        if !self.strict_undefined_flags {
+                            self.set_szp_flags_from_result_u8(self.ah);
+                        }
                     }                    
                     Mnemonic::DIV => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         
                         /*
                         // Divide handles writing to dx:ax
@@ -1516,7 +1703,7 @@ impl<'a> Cpu<'a> {
                         }
                     }          
                     Mnemonic::IDIV => {
-                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         /*
                         // Divide handles writing to dx:ax
                         let success = self.divide_i8(op1_value);
@@ -1548,15 +1735,15 @@ impl<'a> Cpu<'a> {
                 match self.i.mnemonic {
 
                     Mnemonic::TEST => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
-                        let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
+                        let op2_value = read_operand16!(self, self.i.operand2_type, self.i.segment_override);
                         
                         self.cycle_i(0x09a);
                         // Don't use result, just set flags
                         let _result = self.math_op16(self.i.mnemonic, op1_value, op2_value);
                     }
                     Mnemonic::NOT => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op16(self.i.mnemonic, op1_value, 0);
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
                             self.cycles_i(2,&[0x04c, 0x04d]);
@@ -1564,7 +1751,7 @@ impl<'a> Cpu<'a> {
                         self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
                     }
                     Mnemonic::NEG => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op16(self.i.mnemonic, op1_value, 0);
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
@@ -1573,7 +1760,7 @@ impl<'a> Cpu<'a> {
                         self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
                     }
                     Mnemonic::MUL => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         // Multiply handles writing to ax
                         //self.multiply_u16(op1_value);
 
@@ -1581,10 +1768,12 @@ impl<'a> Cpu<'a> {
                         self.set_register16(Register16::DX, dx);
                         self.set_register16(Register16::AX, ax);
 
-                        self.set_szp_flags_from_result_u16(self.dx);
+                        if !self.strict_undefined_flags {
+                            self.set_szp_flags_from_result_u16(self.dx);
+                        }
                     }
                     Mnemonic::IMUL => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         // Multiply handles writing to dx:ax
                         //self.multiply_i16(op1_value as i16);
 
@@ -1592,10 +1781,12 @@ impl<'a> Cpu<'a> {
                         self.set_register16(Register16::DX, dx);
                         self.set_register16(Register16::AX, ax);    
 
-                        self.set_szp_flags_from_result_u16(self.dx);                    
+                        if !self.strict_undefined_flags {
+                            self.set_szp_flags_from_result_u16(self.dx);
+                        }
                     }
                     Mnemonic::DIV => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         /*
                         // Divide handles writing to dx:ax
                         let success = self.divide_u16(op1_value);
@@ -1615,7 +1806,7 @@ impl<'a> Cpu<'a> {
                         }
                     }
                     Mnemonic::IDIV => {
-                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op1_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         /*
                         // Divide handles writing to dx:ax
                         let success = self.divide_i16(op1_value);
@@ -1675,7 +1866,7 @@ impl<'a> Cpu<'a> {
                 match self.i.mnemonic {
                     // INC/DEC r/m16
                     Mnemonic::INC | Mnemonic::DEC => {
-                        let op_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op8(self.i.mnemonic, op_value, 0);
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
@@ -1688,7 +1879,7 @@ impl<'a> Cpu<'a> {
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
                             // Reads only 8 bit operand from modrm.
-                            let ptr8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                            let ptr8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                             
                             // Push only 8 bits of next IP onto stack
                             let next_i = self.ip + (self.i.size as u16);
@@ -1780,7 +1971,7 @@ impl<'a> Cpu<'a> {
                     // Jump to memory r/m16
                     Mnemonic::JMP => {
                         // Reads only 8 bit operand from modrm.
-                        let ptr8 = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let ptr8 = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
 
                         // Set only lower 8 bits of IP, upper bits FF
                         self.ip = 0xFF00 | ptr8 as u16;
@@ -1826,7 +2017,7 @@ impl<'a> Cpu<'a> {
                     // Push Byte onto stack
                     Mnemonic::PUSH => {
                         // Read one byte from rm
-                        let op_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op_value = read_operand8!(self, self.i.operand1_type, self.i.segment_override);
                         self.cycles_i(3, &[0x024, 0x025, 0x026]);
 
                         // Write one byte to stack
@@ -1845,7 +2036,7 @@ impl<'a> Cpu<'a> {
                 match self.i.mnemonic {
                     Mnemonic::INC | Mnemonic::DEC => {
                         // INC/DEC r/m16
-                        let op_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let op_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         let result = self.math_op16(self.i.mnemonic, op_value, 0);
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
@@ -1857,7 +2048,7 @@ impl<'a> Cpu<'a> {
 
                         if let OperandType::AddressingMode(_) = self.i.operand1_type {
 
-                            let ptr16 = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                            let ptr16 = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
 
                             self.biu_suspend_fetch();
                             self.cycles_i(4, &[0x074, 0x075, MC_CORR, 0x076]);
@@ -1908,7 +2099,7 @@ impl<'a> Cpu<'a> {
                         if let OperandType::AddressingMode(_mode) = self.i.operand1_type {
 
                             self.cycle_i(0x068);
-                            let (segment, offset) = self.read_operand_farptr(self.i.operand1_type, self.i.segment_override, ReadWriteFlag::Normal).unwrap();
+                            let (segment, offset) = read_operand_farptr!(self, self.i.operand1_type, self.i.segment_override, ReadWriteFlag::Normal);
 
                             //log::debug!("CALLF: jump to [{:04X}:{:04X}]", segment, offset);
                             self.cycle_i(0x06a);
@@ -1983,7 +2174,7 @@ impl<'a> Cpu<'a> {
                     }
                     // Jump to memory r/m16
                     Mnemonic::JMP => {
-                        let ptr16 = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let ptr16 = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
 
                         self.biu_suspend_fetch();
                         self.cycle_i(0x0d8);
@@ -2001,7 +2192,7 @@ impl<'a> Cpu<'a> {
                             self.biu_suspend_fetch();
                             self.cycle_i(0x0dd);
 
-                            let (segment, offset) = self.read_operand_farptr(self.i.operand1_type, self.i.segment_override, ReadWriteFlag::Normal).unwrap();
+                            let (segment, offset) = read_operand_farptr!(self, self.i.operand1_type, self.i.segment_override, ReadWriteFlag::Normal);
 
                             self.cs = segment;
                             self.ip = offset;
@@ -2035,7 +2226,7 @@ impl<'a> Cpu<'a> {
                     }                    
                     // Push Word onto stack
                     Mnemonic::PUSH => {
-                        let mut op_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        let mut op_value = read_operand16!(self, self.i.operand1_type, self.i.segment_override);
                         self.cycles_i(3, &[0x024, 0x025, 0x026]);
                         
                         // If SP, push the new value of SP instead of the old value
@@ -2069,6 +2260,12 @@ impl<'a> Cpu<'a> {
         }
         */
 
+        // A divide error terminates the instruction even if it carried a REP prefix (which negates the
+        // quotient), so that IP is advanced to the next instruction before INT 0 is raised.
+        if exception == CpuException::DivideError {
+            self.rep_end();
+        }
+
         // Reset REP init flag. This flag is set after a rep-prefixed instruction is executed for the first time. It
         // should be preserved between executions of a rep-prefixed instruction unless an interrupt occurs, in which
         // case the rep-prefix instruction terminates normally after RPTI. This flag determines whether RPTS is 
@@ -2082,16 +2279,48 @@ impl<'a> Cpu<'a> {
             }            
         }
 
+        // With jump sanity checking enabled, catch control transfers into unmapped or never-written
+        // memory at the jump itself, rather than after executing whatever garbage is found there.
+        let bad_jump = match jump && self.jump_sanity {
+            true => {
+                let target = Cpu::calc_linear_address(self.cs, self.ip);
+                (!self.bus.is_code_address(target as usize)).then_some(target)
+            }
+            false => None
+        };
+
         if unhandled {
-            // This won't happen - the 8088 has no concept of an invalid instruction and we have implemented
-            // all opcodes. 
-            unreachable!("Invalid opcode!");
-            //ExecutionResult::UnsupportedOpcode(self.i.opcode)
+            // This shouldn't happen - the 8088 has no concept of an invalid instruction and we have implemented
+            // all opcodes. Report it rather than panicking so that arbitrary input can be executed safely.
+            ExecutionResult::ExecutionError(
+                format!("Unhandled opcode {:02X} ({})", self.i.opcode, self.i)
+            )
+        }
+        else if let Some(msg) = self.operand_size_error.take() {
+            self.operand_error(&msg)
+        }
+        else if runaway {
+            ExecutionResult::RunawayDetected(self.opcode0_counter)
+        }
+        else if let Some(target) = bad_jump {
+            ExecutionResult::BadJump(target)
         }
         else if self.halted && !self.get_flag(Flag::Interrupt) {
             // CPU was halted with interrupts disabled - will not continue
             ExecutionResult::Halt
         }
+        else if self.halted {
+            // CPU was halted with interrupts enabled - it will idle until an interrupt arrives
+            ExecutionResult::HaltWait
+        }
+        else if exception == CpuException::NoException && self.access_breakpoint.is_some() {
+            // A read or write breakpoint tripped during this instruction.
+            if self.in_rep {
+                self.rep_init = true;
+            }
+            let (address, _) = self.access_breakpoint.take().unwrap();
+            ExecutionResult::Breakpoint(address)
+        }
         else if jump {
             ExecutionResult::OkayJump
         }
@@ -2106,4 +2335,12 @@ impl<'a> Cpu<'a> {
             }                
         }
     }
-}
\ No newline at end of file
+
+    /// Build an ExecutionError for an operand the current instruction's handler could not
+    /// process, with the opcode and decoded operands for context.
+    fn operand_error(&self, msg: &str) -> ExecutionResult {
+        ExecutionResult::ExecutionError(
+            format!("{} for opcode {:02X} ({})", msg, self.i.opcode, self.i)
+        )
+    }
+}
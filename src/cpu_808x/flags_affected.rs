@@ -0,0 +1,135 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::flags_affected.rs
+
+    Describes how each instruction affects the FLAGS register.
+
+*/
+
+use std::fmt;
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::mnemonic::Mnemonic;
+
+const O: u16 = CPU_FLAG_OVERFLOW;
+const D: u16 = CPU_FLAG_DIRECTION;
+const I: u16 = CPU_FLAG_INT_ENABLE;
+const T: u16 = CPU_FLAG_TRAP;
+const S: u16 = CPU_FLAG_SIGN;
+const Z: u16 = CPU_FLAG_ZERO;
+const A: u16 = CPU_FLAG_AUX_CARRY;
+const P: u16 = CPU_FLAG_PARITY;
+const C: u16 = CPU_FLAG_CARRY;
+
+const FLAG_LETTERS: [(u16, char); 9] = [
+    (O, 'O'), (D, 'D'), (I, 'I'), (T, 'T'), (S, 'S'), (Z, 'Z'), (A, 'A'), (P, 'P'), (C, 'C')
+];
+
+/// Bitmasks of the flags an instruction affects, using the CPU_FLAG_* bit positions.
+/// 'modified' flags are set or cleared according to the result, 'cleared' and 'set' flags
+/// are always cleared or set, and 'undefined' flags are left in an undefined state.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FlagsAffected {
+    pub modified: u16,
+    pub cleared: u16,
+    pub set: u16,
+    pub undefined: u16,
+}
+
+impl FlagsAffected {
+    const fn new(modified: u16, cleared: u16, set: u16, undefined: u16) -> Self {
+        Self {
+            modified,
+            cleared,
+            set,
+            undefined
+        }
+    }
+
+    /// Return the mask of all flags given a defined value by the instruction.
+    pub fn defined(&self) -> u16 {
+        self.modified | self.cleared | self.set
+    }
+
+    /// Return the mask of all flags the instruction may change, including undefined flags.
+    pub fn affected(&self) -> u16 {
+        self.defined() | self.undefined
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.affected() == 0
+    }
+
+    /// Format a flag mask as its flag letters, in FLAGS register order.
+    pub fn mask_string(mask: u16) -> String {
+        FLAG_LETTERS.iter().filter(|(bit, _)| mask & bit != 0).map(|(_, c)| c).collect()
+    }
+}
+
+/// Displays the flags given a defined value by the instruction, in FLAGS register order.
+impl fmt::Display for FlagsAffected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", FlagsAffected::mask_string(self.defined()))
+    }
+}
+
+/// Return the flags affected by the specified mnemonic. Shifts and rotates only define the
+/// overflow flag for a count of 1, so 'count_one' selects between the count of 1 forms and
+/// the CL or immediate count forms.
+pub fn mnemonic_flags_affected(op: Mnemonic, count_one: bool) -> FlagsAffected {
+    let shift_o = if count_one { O } else { 0 };
+    let shift_u = if count_one { 0 } else { O };
+
+    match op {
+        Mnemonic::ADD | Mnemonic::ADC | Mnemonic::SUB | Mnemonic::SBB | Mnemonic::CMP
+        | Mnemonic::NEG | Mnemonic::CMPSB | Mnemonic::CMPSW
+        | Mnemonic::SCASB | Mnemonic::SCASW => FlagsAffected::new(O | S | Z | A | P | C, 0, 0, 0),
+        Mnemonic::INC | Mnemonic::DEC => FlagsAffected::new(O | S | Z | A | P, 0, 0, 0),
+        Mnemonic::AND | Mnemonic::OR | Mnemonic::XOR | Mnemonic::TEST => FlagsAffected::new(S | Z | P, O | C, 0, A),
+        Mnemonic::SHL | Mnemonic::SHR | Mnemonic::SAR => FlagsAffected::new(shift_o | S | Z | P | C, 0, 0, shift_u | A),
+        Mnemonic::ROL | Mnemonic::ROR | Mnemonic::RCL | Mnemonic::RCR => FlagsAffected::new(shift_o | C, 0, 0, shift_u),
+        Mnemonic::SETMO | Mnemonic::SETMOC => FlagsAffected::new(0, O | Z | A | C, S | P, 0),
+        Mnemonic::MUL | Mnemonic::IMUL => FlagsAffected::new(O | C, 0, 0, S | Z | A | P),
+        Mnemonic::DIV | Mnemonic::IDIV => FlagsAffected::new(0, 0, 0, O | S | Z | A | P | C),
+        Mnemonic::DAA | Mnemonic::DAS => FlagsAffected::new(S | Z | A | P | C, 0, 0, O),
+        Mnemonic::AAA | Mnemonic::AAS => FlagsAffected::new(A | C, 0, 0, O | S | Z | P),
+        Mnemonic::AAM | Mnemonic::AAD => FlagsAffected::new(S | Z | P, 0, 0, O | A | C),
+        Mnemonic::SAHF => FlagsAffected::new(S | Z | A | P | C, 0, 0, 0),
+        Mnemonic::POPF | Mnemonic::IRET => FlagsAffected::new(O | D | I | T | S | Z | A | P | C, 0, 0, 0),
+        Mnemonic::INT | Mnemonic::INT3 | Mnemonic::INTO => FlagsAffected::new(0, I | T, 0, 0),
+        Mnemonic::CLC => FlagsAffected::new(0, C, 0, 0),
+        Mnemonic::STC => FlagsAffected::new(0, 0, C, 0),
+        Mnemonic::CMC => FlagsAffected::new(C, 0, 0, 0),
+        Mnemonic::CLD => FlagsAffected::new(0, D, 0, 0),
+        Mnemonic::STD => FlagsAffected::new(0, 0, D, 0),
+        Mnemonic::CLI => FlagsAffected::new(0, I, 0, 0),
+        Mnemonic::STI => FlagsAffected::new(0, 0, I, 0),
+        _ => FlagsAffected::default()
+    }
+}
+
+impl Instruction {
+    /// Return the flags affected by this instruction. Group opcodes are resolved by the
+    /// mnemonic selected by their reg field during decode.
+    pub fn flags_affected(&self) -> FlagsAffected {
+        // Only the 0xD0 and 0xD1 shift group forms have a fixed count of 1
+        mnemonic_flags_affected(self.mnemonic, matches!(self.opcode, 0xD0 | 0xD1))
+    }
+}
@@ -0,0 +1,845 @@
+/*
+    Marty PC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::fpu.rs
+
+    Implements the 8087 numeric coprocessor. The CPU performs the bus cycles
+    for ESC memory operands and hands the resulting bytes to the FPU, which
+    maintains the register stack and status, control and tag words.
+
+    The 8087's eight 80-bit registers are held as f64. Values are converted
+    to and from the 80-bit extended format when stored to or loaded from
+    memory, which loses the low 11 bits of the 64-bit mantissa and limits
+    the exponent range to that of a double. Results may differ from real
+    hardware in the lowest bits, and FLD/FSTP of an m80real value that 
+    doesn't fit in a double doesn't round trip exactly.
+
+*/
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::addressing::AddressingMode;
+
+use serde_derive::{Deserialize, Serialize};
+
+pub const FPU_STATUS_IE: u16 = 0b0000_0000_0000_0001;
+pub const FPU_STATUS_ZE: u16 = 0b0000_0000_0000_0100;
+pub const FPU_STATUS_OE: u16 = 0b0000_0000_0000_1000;
+pub const FPU_STATUS_C0: u16 = 0b0000_0001_0000_0000;
+pub const FPU_STATUS_C1: u16 = 0b0000_0010_0000_0000;
+pub const FPU_STATUS_C2: u16 = 0b0000_0100_0000_0000;
+pub const FPU_STATUS_C3: u16 = 0b0100_0000_0000_0000;
+pub const FPU_STATUS_TOP_MASK: u16 = 0b0011_1000_0000_0000;
+
+pub const FPU_CONTROL_DEFAULT: u16 = 0x03FF;
+
+const FPU_CONDITION_MASK: u16 = FPU_STATUS_C0 | FPU_STATUS_C1 | FPU_STATUS_C2 | FPU_STATUS_C3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FpuTag {
+    Valid,
+    Zero,
+    Special,
+    Empty
+}
+
+/// The arithmetic operation selected by the reg field of an ESC modrm.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FpuArith {
+    Add,
+    Mul,
+    Com,
+    Comp,
+    Sub,
+    Subr,
+    Div,
+    Divr
+}
+
+impl FpuArith {
+    pub fn from_ext(ext: u8) -> Self {
+        match ext & 0x07 {
+            0 => FpuArith::Add,
+            1 => FpuArith::Mul,
+            2 => FpuArith::Com,
+            3 => FpuArith::Comp,
+            4 => FpuArith::Sub,
+            5 => FpuArith::Subr,
+            6 => FpuArith::Div,
+            _ => FpuArith::Divr,
+        }
+    }
+
+    /// The reversed form of the operation, used when the destination is st(i).
+    pub fn reversed(&self) -> Self {
+        match self {
+            FpuArith::Sub => FpuArith::Subr,
+            FpuArith::Subr => FpuArith::Sub,
+            FpuArith::Div => FpuArith::Divr,
+            FpuArith::Divr => FpuArith::Div,
+            _ => *self
+        }
+    }
+}
+
+/// The 8087 register stack, status, control and tag words. See the notes above on the 
+/// precision of the registers.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fpu {
+    regs: [f64; 8],
+    empty: [bool; 8],
+    top: u8,
+    status: u16,
+    control: u16,
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        Fpu::new()
+    }
+}
+
+impl Fpu {
+
+    pub fn new() -> Self {
+        Self {
+            regs: [0.0; 8],
+            empty: [true; 8],
+            top: 0,
+            status: 0,
+            control: FPU_CONTROL_DEFAULT,
+        }
+    }
+
+    /// Equivalent to FNINIT.
+    pub fn reset(&mut self) {
+        *self = Fpu::new();
+    }
+
+    /// Return the status word, including the current stack top.
+    pub fn status_word(&self) -> u16 {
+        (self.status & !FPU_STATUS_TOP_MASK) | ((self.top as u16) << 11)
+    }
+
+    pub fn control_word(&self) -> u16 {
+        self.control
+    }
+
+    pub fn set_control_word(&mut self, word: u16) {
+        self.control = word;
+    }
+
+    /// Flag an invalid operation, such as an encoding the FPU doesn't implement.
+    pub fn invalid_operation(&mut self) {
+        self.status |= FPU_STATUS_IE;
+    }
+
+    pub fn clear_exceptions(&mut self) {
+        self.status &= FPU_CONDITION_MASK;
+    }
+
+    pub fn tag(&self, physical: usize) -> FpuTag {
+        let value = self.regs[physical & 0x07];
+        if self.empty[physical & 0x07] {
+            FpuTag::Empty
+        }
+        else if value == 0.0 {
+            FpuTag::Zero
+        }
+        else if !value.is_normal() {
+            FpuTag::Special
+        }
+        else {
+            FpuTag::Valid
+        }
+    }
+
+    /// Return the tag word. Each physical register is described by two bits.
+    pub fn tag_word(&self) -> u16 {
+        let mut word = 0;
+        for i in 0..8 {
+            let tag = match self.tag(i) {
+                FpuTag::Valid => 0b00,
+                FpuTag::Zero => 0b01,
+                FpuTag::Special => 0b10,
+                FpuTag::Empty => 0b11,
+            };
+            word |= tag << (i * 2);
+        }
+        word
+    }
+
+    fn physical(&self, i: usize) -> usize {
+        (self.top as usize + i) & 0x07
+    }
+
+    /// Return the value of st(i). Reading an empty register is a stack underflow and
+    /// produces the indefinite NaN.
+    pub fn st(&mut self, i: usize) -> f64 {
+        let p = self.physical(i);
+        if self.empty[p] {
+            self.status |= FPU_STATUS_IE;
+            self.status &= !FPU_STATUS_C1;
+            return f64::NAN
+        }
+        self.regs[p]
+    }
+
+    /// Return the value of st(i) without side effects, for display.
+    pub fn peek_st(&self, i: usize) -> Option<f64> {
+        let p = self.physical(i);
+        if self.empty[p] {
+            None
+        }
+        else {
+            Some(self.regs[p])
+        }
+    }
+
+    pub fn set_st(&mut self, i: usize, value: f64) {
+        let p = self.physical(i);
+        self.regs[p] = value;
+        self.empty[p] = false;
+    }
+
+    /// Push a value onto the register stack. Pushing onto a full stack is a stack
+    /// overflow and loads the indefinite NaN.
+    pub fn push(&mut self, value: f64) {
+        self.top = self.top.wrapping_sub(1) & 0x07;
+        let p = self.top as usize;
+        if !self.empty[p] {
+            self.status |= FPU_STATUS_IE | FPU_STATUS_C1;
+            self.regs[p] = f64::NAN;
+        }
+        else {
+            self.regs[p] = value;
+        }
+        self.empty[p] = false;
+    }
+
+    pub fn pop(&mut self) {
+        let p = self.top as usize;
+        self.empty[p] = true;
+        self.top = (self.top + 1) & 0x07;
+    }
+
+    pub fn free(&mut self, i: usize) {
+        let p = self.physical(i);
+        self.empty[p] = true;
+    }
+
+    pub fn exchange(&mut self, i: usize) {
+        let a = self.st(0);
+        let b = self.st(i);
+        self.set_st(0, b);
+        self.set_st(i, a);
+    }
+
+    /// Set the condition codes from comparing a with b.
+    pub fn compare(&mut self, a: f64, b: f64) {
+        self.status &= !(FPU_STATUS_C0 | FPU_STATUS_C2 | FPU_STATUS_C3);
+        if a.is_nan() || b.is_nan() {
+            self.status |= FPU_STATUS_IE | FPU_STATUS_C0 | FPU_STATUS_C2 | FPU_STATUS_C3;
+        }
+        else if a < b {
+            self.status |= FPU_STATUS_C0;
+        }
+        else if a == b {
+            self.status |= FPU_STATUS_C3;
+        }
+    }
+
+    /// Perform 'dst op src' and return the result, setting exception flags as needed.
+    /// Comparisons update the condition codes and return dst unchanged.
+    pub fn arith(&mut self, op: FpuArith, dst: f64, src: f64) -> f64 {
+
+        let divide_by_zero = (op == FpuArith::Div && src == 0.0) || (op == FpuArith::Divr && dst == 0.0);
+
+        let result = match op {
+            FpuArith::Add => dst + src,
+            FpuArith::Mul => dst * src,
+            FpuArith::Com | FpuArith::Comp => {
+                self.compare(dst, src);
+                return dst
+            }
+            FpuArith::Sub => dst - src,
+            FpuArith::Subr => src - dst,
+            FpuArith::Div => self.divide(dst, src),
+            FpuArith::Divr => self.divide(src, dst),
+        };
+
+        if result.is_nan() && !dst.is_nan() && !src.is_nan() {
+            // Invalid operation, ie, inf - inf
+            self.status |= FPU_STATUS_IE;
+        }
+        else if result.is_infinite() && dst.is_finite() && src.is_finite() && !divide_by_zero {
+            self.status |= FPU_STATUS_OE;
+        }
+        result
+    }
+
+    fn divide(&mut self, dividend: f64, divisor: f64) -> f64 {
+        if divisor == 0.0 && dividend.is_finite() && dividend != 0.0 {
+            self.status |= FPU_STATUS_ZE;
+        }
+        dividend / divisor
+    }
+
+    /// Round a value to an integer using the rounding control field of the control word.
+    pub fn round(&self, value: f64) -> f64 {
+        match (self.control >> 10) & 0x03 {
+            0b00 => {
+                // Round to nearest, ties to even
+                let floor = value.floor();
+                let diff = value - floor;
+                if diff > 0.5 || (diff == 0.5 && floor % 2.0 != 0.0) {
+                    floor + 1.0
+                }
+                else {
+                    floor
+                }
+            }
+            0b01 => value.floor(),
+            0b10 => value.ceil(),
+            _ => value.trunc(),
+        }
+    }
+
+    /// Convert a value to an integer of the specified width in bits for FIST/FISTP. Values
+    /// that are out of range produce the integer indefinite.
+    pub fn to_integer(&mut self, value: f64, bits: u32) -> i64 {
+        let rounded = self.round(value);
+        let max = (1u64 << (bits - 1)) as f64;
+        let indefinite = i64::MIN >> (64 - bits);
+
+        if rounded.is_nan() || rounded >= max || rounded < -max {
+            self.status |= FPU_STATUS_IE;
+            indefinite
+        }
+        else {
+            rounded as i64
+        }
+    }
+}
+
+/// Scale a value by a power of two, in steps small enough not to overflow the exponent.
+fn ldexp(mut value: f64, mut exp: i32) -> f64 {
+    while exp > 1000 {
+        value *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        value *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    value * 2f64.powi(exp)
+}
+
+/// Convert a double to the 80-bit extended precision format, as stored in memory.
+pub fn f64_to_f80(value: f64) -> [u8; 10] {
+    let bits = value.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exp = ((bits >> 52) & 0x7FF) as i32;
+    let frac = bits & 0x000F_FFFF_FFFF_FFFF;
+
+    let (exp80, mantissa) = if exp == 0 {
+        if frac == 0 {
+            (0u16, 0u64)
+        }
+        else {
+            // Denormal doubles can be represented as normals in extended precision
+            let lz = frac.leading_zeros() as i32;
+            ((63 - lz - 1074 + 16383) as u16, frac << lz)
+        }
+    }
+    else if exp == 0x7FF {
+        (0x7FFF, 0x8000_0000_0000_0000 | (frac << 11))
+    }
+    else {
+        ((exp - 1023 + 16383) as u16, 0x8000_0000_0000_0000 | (frac << 11))
+    };
+
+    let mut bytes = [0; 10];
+    bytes[0..8].copy_from_slice(&mantissa.to_le_bytes());
+    bytes[8..10].copy_from_slice(&(sign | exp80).to_le_bytes());
+    bytes
+}
+
+/// Convert an 80-bit extended precision value to a double, rounding the mantissa.
+pub fn f80_to_f64(bytes: &[u8; 10]) -> f64 {
+    let mut mantissa_bytes = [0; 8];
+    mantissa_bytes.copy_from_slice(&bytes[0..8]);
+    let mantissa = u64::from_le_bytes(mantissa_bytes);
+    let sign_exp = u16::from_le_bytes([bytes[8], bytes[9]]);
+
+    let sign = if sign_exp & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = (sign_exp & 0x7FFF) as i32;
+
+    if exp == 0x7FFF {
+        if mantissa << 1 == 0 {
+            return sign * f64::INFINITY
+        }
+        return f64::NAN
+    }
+    if mantissa == 0 {
+        return sign * 0.0
+    }
+
+    let unbiased = if exp == 0 { -16382 } else { exp - 16383 };
+    sign * ldexp(mantissa as f64, unbiased - 63)
+}
+
+/// Map the register operand of a register-form ESC instruction to a stack index.
+fn fpu_stack_index(operand: OperandType) -> usize {
+    match operand {
+        OperandType::Register16(Register16::AX) => 0,
+        OperandType::Register16(Register16::CX) => 1,
+        OperandType::Register16(Register16::DX) => 2,
+        OperandType::Register16(Register16::BX) => 3,
+        OperandType::Register16(Register16::SP) => 4,
+        OperandType::Register16(Register16::BP) => 5,
+        OperandType::Register16(Register16::SI) => 6,
+        OperandType::Register16(Register16::DI) => 7,
+        _ => 0
+    }
+}
+
+impl<'a> Cpu<'a> {
+
+    /// Read a memory operand of 'len' bytes for the FPU. The first word was already read
+    /// by EALOAD, as on real hardware the 8087 captures it from the bus; the remaining
+    /// bytes are read here.
+    fn fpu_read_mem(&mut self, mode: AddressingMode, len: usize) -> [u8; 10] {
+        let mut bytes = [0; 10];
+        let (segment_val, segment, offset) = self.calc_effective_address(mode, self.i.segment_override);
+
+        bytes[0..2].copy_from_slice(&self.ea_opr.to_le_bytes());
+        for (n, byte) in bytes.iter_mut().enumerate().take(len).skip(2) {
+            let flat_addr = Cpu::calc_linear_address(segment_val, offset.wrapping_add(n as u16));
+            *byte = self.biu_read_u8(segment, flat_addr);
+        }
+        bytes
+    }
+
+    fn fpu_write_mem(&mut self, mode: AddressingMode, bytes: &[u8]) {
+        let (segment_val, segment, offset) = self.calc_effective_address(mode, self.i.segment_override);
+
+        for (n, byte) in bytes.iter().enumerate() {
+            let flat_addr = Cpu::calc_linear_address(segment_val, offset.wrapping_add(n as u16));
+            self.biu_write_u8(segment, flat_addr, *byte, ReadWriteFlag::Normal);
+        }
+    }
+
+    fn fpu_read_f32(&mut self, mode: AddressingMode) -> f64 {
+        let b = self.fpu_read_mem(mode, 4);
+        f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+    }
+
+    fn fpu_read_f64(&mut self, mode: AddressingMode) -> f64 {
+        let b = self.fpu_read_mem(mode, 8);
+        f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+    }
+
+    fn fpu_read_i16(&mut self) -> f64 {
+        // A word operand was read completely by EALOAD
+        self.ea_opr as i16 as f64
+    }
+
+    fn fpu_read_i32(&mut self, mode: AddressingMode) -> f64 {
+        let b = self.fpu_read_mem(mode, 4);
+        i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+    }
+
+    fn fpu_read_i64(&mut self, mode: AddressingMode) -> f64 {
+        let b = self.fpu_read_mem(mode, 8);
+        i64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f64
+    }
+
+    /// Execute an ESC instruction on the FPU.
+    pub fn fpu_execute(&mut self) {
+
+        let esc = self.i.opcode & 0x07;
+        let ext = self.i.op_ext;
+
+        match self.i.operand1_type {
+            OperandType::AddressingMode(mode) => self.fpu_execute_mem(esc, ext, mode),
+            operand => self.fpu_execute_reg(esc, ext, fpu_stack_index(operand)),
+        }
+    }
+
+    fn fpu_execute_mem(&mut self, esc: u8, ext: u8, mode: AddressingMode) {
+
+        match (esc, ext) {
+            (0 | 2 | 4 | 6, _) => {
+                // Arithmetic with a memory operand: m32real, m32int, m64real, m16int
+                let src = match esc {
+                    0 => self.fpu_read_f32(mode),
+                    2 => self.fpu_read_i32(mode),
+                    4 => self.fpu_read_f64(mode),
+                    _ => self.fpu_read_i16(),
+                };
+                let op = FpuArith::from_ext(ext);
+                let dst = self.fpu.st(0);
+                let result = self.fpu.arith(op, dst, src);
+                match op {
+                    FpuArith::Com => {}
+                    FpuArith::Comp => self.fpu.pop(),
+                    _ => self.fpu.set_st(0, result),
+                }
+            }
+            (1, 0) => {
+                // FLD m32real
+                let value = self.fpu_read_f32(mode);
+                self.fpu.push(value);
+            }
+            (1, 2) | (1, 3) => {
+                // FST / FSTP m32real
+                let value = self.fpu.st(0) as f32;
+                self.fpu_write_mem(mode, &value.to_le_bytes());
+                if ext == 3 {
+                    self.fpu.pop();
+                }
+            }
+            (1, 5) => {
+                // FLDCW m16
+                self.fpu.set_control_word(self.ea_opr);
+            }
+            (1, 7) => {
+                // FSTCW m16
+                let word = self.fpu.control_word();
+                self.fpu_write_mem(mode, &word.to_le_bytes());
+            }
+            (3, 0) => {
+                // FILD m32int
+                let value = self.fpu_read_i32(mode);
+                self.fpu.push(value);
+            }
+            (3, 2) | (3, 3) => {
+                // FIST / FISTP m32int
+                let st0 = self.fpu.st(0);
+                let value = self.fpu.to_integer(st0, 32) as i32;
+                self.fpu_write_mem(mode, &value.to_le_bytes());
+                if ext == 3 {
+                    self.fpu.pop();
+                }
+            }
+            (3, 5) => {
+                // FLD m80real
+                let bytes = self.fpu_read_mem(mode, 10);
+                self.fpu.push(f80_to_f64(&bytes));
+            }
+            (3, 7) => {
+                // FSTP m80real
+                let bytes = f64_to_f80(self.fpu.st(0));
+                self.fpu_write_mem(mode, &bytes);
+                self.fpu.pop();
+            }
+            (5, 0) => {
+                // FLD m64real
+                let value = self.fpu_read_f64(mode);
+                self.fpu.push(value);
+            }
+            (5, 2) | (5, 3) => {
+                // FST / FSTP m64real
+                let value = self.fpu.st(0);
+                self.fpu_write_mem(mode, &value.to_le_bytes());
+                if ext == 3 {
+                    self.fpu.pop();
+                }
+            }
+            (5, 7) => {
+                // FSTSW m16
+                let word = self.fpu.status_word();
+                self.fpu_write_mem(mode, &word.to_le_bytes());
+            }
+            (7, 0) => {
+                // FILD m16int
+                let value = self.fpu_read_i16();
+                self.fpu.push(value);
+            }
+            (7, 2) | (7, 3) => {
+                // FIST / FISTP m16int
+                let st0 = self.fpu.st(0);
+                let value = self.fpu.to_integer(st0, 16) as i16;
+                self.fpu_write_mem(mode, &value.to_le_bytes());
+                if ext == 3 {
+                    self.fpu.pop();
+                }
+            }
+            (7, 5) => {
+                // FILD m64int
+                let value = self.fpu_read_i64(mode);
+                self.fpu.push(value);
+            }
+            (7, 7) => {
+                // FISTP m64int
+                let st0 = self.fpu.st(0);
+                let value = self.fpu.to_integer(st0, 64);
+                self.fpu_write_mem(mode, &value.to_le_bytes());
+                self.fpu.pop();
+            }
+            _ => {
+                log::warn!("Unimplemented FPU instruction: {:02X} /{}", self.i.opcode, ext);
+                self.fpu.invalid_operation();
+            }
+        }
+    }
+
+    fn fpu_execute_reg(&mut self, esc: u8, ext: u8, i: usize) {
+
+        match (esc, ext) {
+            (0, _) => {
+                // Arithmetic, st(0) = st(0) op st(i)
+                let op = FpuArith::from_ext(ext);
+                let dst = self.fpu.st(0);
+                let src = self.fpu.st(i);
+                let result = self.fpu.arith(op, dst, src);
+                match op {
+                    FpuArith::Com => {}
+                    FpuArith::Comp => self.fpu.pop(),
+                    _ => self.fpu.set_st(0, result),
+                }
+            }
+            (4, _) | (6, _) => {
+                // Arithmetic, st(i) = st(i) op st(0), popping if DE.
+                // The sub and div encodings are reversed relative to D8.
+                let op = FpuArith::from_ext(ext).reversed();
+                let dst = self.fpu.st(i);
+                let src = self.fpu.st(0);
+                let result = self.fpu.arith(op, dst, src);
+                match op {
+                    FpuArith::Com => {}
+                    FpuArith::Comp => {
+                        self.fpu.pop();
+                        if esc == 6 && i == 1 {
+                            // FCOMPP
+                            self.fpu.pop();
+                        }
+                    }
+                    _ => {
+                        self.fpu.set_st(i, result);
+                        if esc == 6 {
+                            self.fpu.pop();
+                        }
+                    }
+                }
+            }
+            (1, 0) => {
+                // FLD st(i)
+                let value = self.fpu.st(i);
+                self.fpu.push(value);
+            }
+            (1, 1) => {
+                // FXCH st(i)
+                self.fpu.exchange(i);
+            }
+            (1, 2) => {
+                // FNOP
+            }
+            (1, 4) => {
+                match i {
+                    0 => {
+                        // FCHS
+                        let value = self.fpu.st(0);
+                        self.fpu.set_st(0, -value);
+                    }
+                    1 => {
+                        // FABS
+                        let value = self.fpu.st(0);
+                        self.fpu.set_st(0, value.abs());
+                    }
+                    4 => {
+                        // FTST
+                        let value = self.fpu.st(0);
+                        self.fpu.compare(value, 0.0);
+                    }
+                    _ => {
+                        log::warn!("Unimplemented FPU instruction: D9 {:02X}", 0xE0 + i);
+                        self.fpu.invalid_operation();
+                    }
+                }
+            }
+            (1, 5) => {
+                // Load constant
+                let value = match i {
+                    0 => 1.0,                           // FLD1
+                    1 => std::f64::consts::LOG2_10,     // FLDL2T
+                    2 => std::f64::consts::LOG2_E,      // FLDL2E
+                    3 => std::f64::consts::PI,          // FLDPI
+                    4 => std::f64::consts::LOG10_2,     // FLDLG2
+                    5 => std::f64::consts::LN_2,        // FLDLN2
+                    6 => 0.0,                           // FLDZ
+                    _ => {
+                        log::warn!("Invalid FPU instruction: D9 EF");
+                        self.fpu.invalid_operation();
+                        return
+                    }
+                };
+                self.fpu.push(value);
+            }
+            (1, 7) => {
+                match i {
+                    2 => {
+                        // FSQRT
+                        let value = self.fpu.st(0);
+                        if value < 0.0 {
+                            self.fpu.status |= FPU_STATUS_IE;
+                        }
+                        self.fpu.set_st(0, value.sqrt());
+                    }
+                    4 => {
+                        // FRNDINT
+                        let value = self.fpu.st(0);
+                        let rounded = self.fpu.round(value);
+                        self.fpu.set_st(0, rounded);
+                    }
+                    _ => {
+                        log::warn!("Unimplemented FPU instruction: D9 {:02X}", 0xF8 + i);
+                        self.fpu.invalid_operation();
+                    }
+                }
+            }
+            (3, 4) => {
+                match i {
+                    0 | 1 => {
+                        // FENI / FDISI. Interrupt masking is not emulated.
+                    }
+                    2 => {
+                        // FNCLEX
+                        self.fpu.clear_exceptions();
+                    }
+                    3 => {
+                        // FNINIT
+                        self.fpu.reset();
+                    }
+                    _ => {
+                        log::warn!("Invalid FPU instruction: DB {:02X}", 0xE0 + i);
+                        self.fpu.invalid_operation();
+                    }
+                }
+            }
+            (5, 0) => {
+                // FFREE st(i)
+                self.fpu.free(i);
+            }
+            (5, 2) | (5, 3) => {
+                // FST / FSTP st(i)
+                let value = self.fpu.st(0);
+                self.fpu.set_st(i, value);
+                if ext == 3 {
+                    self.fpu.pop();
+                }
+            }
+            _ => {
+                log::warn!("Unimplemented FPU instruction: {:02X} {:02X}", self.i.opcode, 0xC0 | (ext << 3) | i as u8);
+                self.fpu.invalid_operation();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f80_conversion() {
+        for value in [0.0, -0.0, 1.0, -2.5, std::f64::consts::PI, 1.0e300, 5.0e-324, f64::INFINITY] {
+            let bytes = f64_to_f80(value);
+            assert_eq!(f80_to_f64(&bytes).to_bits(), value.to_bits());
+        }
+
+        // 1.0 in extended precision has an explicit integer bit
+        assert_eq!(f64_to_f80(1.0), [0, 0, 0, 0, 0, 0, 0, 0x80, 0xFF, 0x3F]);
+        assert!(f80_to_f64(&f64_to_f80(f64::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_fpu_stack() {
+        let mut fpu = Fpu::new();
+
+        fpu.push(2.0);
+        fpu.push(3.0);
+        assert_eq!(fpu.status_word() & FPU_STATUS_TOP_MASK, 6 << 11);
+        assert_eq!(fpu.tag_word(), 0x0FFF);
+
+        // fsubr st(1), st  ->  st(1) = st(0) - st(1)
+        let dst = fpu.st(1);
+        let src = fpu.st(0);
+        let result = fpu.arith(FpuArith::Sub.reversed(), dst, src);
+        fpu.set_st(1, result);
+        fpu.pop();
+        assert_eq!(fpu.st(0), 1.0);
+
+        let st0 = fpu.peek_st(0).unwrap();
+        fpu.compare(st0, 1.0);
+        assert_eq!(fpu.status_word() & (FPU_STATUS_C0 | FPU_STATUS_C3), FPU_STATUS_C3);
+
+        let quotient = fpu.arith(FpuArith::Div, 1.0, 0.0);
+        assert!(quotient.is_infinite());
+        assert!(fpu.status_word() & FPU_STATUS_ZE != 0);
+
+        // Reading an empty register is a stack underflow
+        fpu.pop();
+        assert!(fpu.st(0).is_nan());
+        assert!(fpu.status_word() & FPU_STATUS_IE != 0);
+
+        assert_eq!(fpu.to_integer(2.5, 16), 2);
+        assert_eq!(fpu.to_integer(3.5, 16), 4);
+        assert_eq!(fpu.to_integer(40000.0, 16), -32768);
+    }
+
+    #[test]
+    fn test_esc_options() {
+        use crate::cpu_common::CpuOption;
+        use crate::cpu_808x::tests::{set_ivt_entry, setup_cpu};
+
+        // Set up a CPU with an INT 7 handler at 2000:0000 and step the first instruction of 'code'
+        let run = |code: &[u8], fpu_present: bool| {
+            let mut cpu = setup_cpu(
+                code,
+                [CpuOption::FpuPresent(fpu_present)],
+                &[(Register16::DS, 0x0000), (Register16::SS, 0x0000), (Register16::SP, 0x0100)]
+            );
+            set_ivt_entry(&mut cpu, 7, 0x2000, 0x0000);
+            cpu.bus_mut().debug_write(0x0200, &1234u16.to_le_bytes()).unwrap();
+            cpu.step(false).unwrap();
+            cpu
+        };
+
+        // fild word [0200h] ; fistp word [0202h] ; nop
+        let code = [0xDF, 0x06, 0x00, 0x02, 0xDF, 0x1E, 0x02, 0x02, 0x90];
+
+        // With no coprocessor, ESC traps to INT 7 with the address of the next instruction on the stack
+        let mut cpu = run(&code, false);
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x2000, 0x0000));
+        assert_eq!(cpu.bus_mut().read_u16(0x00FA, 0).unwrap().0, 0x0004);
+
+        // With the FPU present, the value is loaded and stored
+        let mut cpu = run(&code, true);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0008));
+        assert_eq!(cpu.bus_mut().read_u16(0x0202, 0).unwrap().0, 1234);
+        assert_eq!(cpu.fpu.status_word() & FPU_STATUS_IE, 0);
+
+        // An unimplemented encoding flags an invalid operation. D9 D8 (fstp1)
+        let cpu = run(&[0xD9, 0xD8, 0x90], true);
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0002));
+        assert_ne!(cpu.fpu.status_word() & FPU_STATUS_IE, 0);
+    }
+}
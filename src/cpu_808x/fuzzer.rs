@@ -188,6 +188,24 @@ impl<'a> Cpu<'a> {
         self.bus.copy_from(instr.make_contiguous(), addr as usize, 0, false).unwrap();
 
     }
-    
 
-}
\ No newline at end of file
+    /// Copy an arbitrary byte sequence to memory at CS:IP and execute a single instruction
+    /// from it. The prefetch queue is flushed first so the bytes are always fetched from
+    /// memory; this should be called after reset() or randomize_regs(). Sequences the CPU
+    /// cannot execute return a CpuError rather than panicking, so a fuzzer can log the input
+    /// and continue.
+    pub fn execute_bytes(&mut self, bytes: &[u8]) -> Result<(StepResult, u32), CpuError> {
+
+        let addr = Cpu::calc_linear_address(self.cs, self.ip);
+        if self.bus.copy_from(bytes, addr as usize, 0, false).is_err() {
+            return Err(CpuError::ExecutionError(addr, "Instruction bytes exceed memory size".to_string()))
+        }
+
+        // Adjust pc
+        self.pc = addr;
+        // Flush queue
+        self.queue.flush();
+
+        self.step(false)
+    }
+}
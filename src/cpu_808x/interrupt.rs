@@ -48,6 +48,8 @@ impl<'a> Cpu<'a> {
             return
         }
 
+        self.check_interrupt_breakpoint(interrupt);
+
         self.cycles_i(3, &[0x19d, 0x19e, 0x19f]);
         // Read the IVT
         let ivt_addr = Cpu::calc_linear_address(0x0000, (interrupt as usize * INTERRUPT_VEC_LEN) as u16);
@@ -74,7 +76,7 @@ impl<'a> Cpu<'a> {
         self.cycles_i(2, &[0x1a3, 0x1a4]);
         self.push_flags(ReadWriteFlag::Normal);
         self.clear_flag(Flag::Interrupt);
-        self.clear_flag(Flag::Trap);
+        self.clear_trap_on_entry();
 
         // FARCALL2
         self.cycles_i(4, &[0x1a6, MC_JUMP, 0x06c, MC_CORR]);
@@ -137,12 +139,17 @@ impl<'a> Cpu<'a> {
     /// Handle a CPU exception
     pub fn handle_exception(&mut self, exception: u8) {
 
+        self.check_interrupt_breakpoint(exception);
+
         self.push_flags(ReadWriteFlag::Normal);
+        self.clear_flag(Flag::Interrupt);
+        self.clear_trap_on_entry();
 
-        // Push return address of next instruction onto stack
         self.push_register16(Register16::CS, ReadWriteFlag::Normal);
 
-        // Don't push address of next instruction
+        // Push the return address. IP has already been advanced past the faulting instruction, 
+        // so on the 8088 a divide error returns to the instruction following the divide. (Later
+        // CPUs push the address of the divide instruction itself.)
         self.push_u16(self.ip, ReadWriteFlag::Normal);
         
         if exception == 0x0 {
@@ -215,9 +222,7 @@ impl<'a> Cpu<'a> {
     pub fn intr_routine(&mut self, vector: u8, itype: InterruptType, skip_first: bool) {
 
         // Check for interrupt breakpoint.
-        if self.int_flags[vector as usize] & INTERRUPT_BREAKPOINT != 0 {
-            self.set_breakpoint_flag();
-        }
+        self.check_interrupt_breakpoint(vector);
 
         //log::debug!("in INTR routine!");
         if !skip_first {
@@ -249,30 +254,55 @@ impl<'a> Cpu<'a> {
         self.cycles_i(2, &[0x1a3, 0x1a4]);
         self.push_flags(ReadWriteFlag::Normal);
         self.clear_flag(Flag::Interrupt);
-        self.clear_flag(Flag::Trap);        
+        self.clear_trap_on_entry();
         self.cycle_i(0x1a6);
 
         self.farcall2(new_cs, new_ip);
     }
 
-    /// Perform a hardware interrupt
+    /// Perform a hardware interrupt with the specified vector.
     pub fn hw_interrupt(&mut self, vector: u8) {
+        self.hw_interrupt_routine(Some(vector));
+    }
+
+    /// Acknowledge a maskable interrupt request and perform the interrupt. The vector is read
+    /// from the PIC during the second INTA cycle. Returns the vector.
+    pub fn intr_acknowledge(&mut self) -> u8 {
+        self.hw_interrupt_routine(None)
+    }
+
+    fn hw_interrupt_routine(&mut self, vector: Option<u8>) -> u8 {
 
         // Begin IRQ routine
         self.set_mc_pc(0x19a);
-        self.biu_inta(vector);
+        let vector = self.biu_inta(vector);
         self.biu_suspend_fetch();
         self.cycles_i(2, &[0x19b, 0x19c]);
 
         // Begin INTR routine
         self.intr_routine(vector, InterruptType::Hardware, false);
         self.int_count += 1;
+        vector
+    }
+
+    /// Clear the trap flag on entry to an interrupt. If a trap was due after the instruction
+    /// that entered the interrupt, it is still taken, before the first instruction of the handler.
+    /// This is why single-stepping an INT instruction steps into the interrupt handler. A trap
+    /// is never due after INTR, as a trap takes priority over it.
+    fn clear_trap_on_entry(&mut self) {
+        let trap_due = (self.get_flag(Flag::Trap) || self.trap_disable_delay != 0) && self.trap_enable_delay == 0;
+
+        self.clear_flag(Flag::Trap);
+        self.trap_enable_delay = 0;
+        self.trap_disable_delay = if trap_due { 1 } else { 0 };
     }
 
     /// Perform INT1 (Trap)
     pub fn int1(&mut self) {
         self.cycles_i(2, &[0x198, MC_JUMP]);
         self.intr_routine(1, InterruptType::Hardware, true);
+        // The trap that was due has now been taken
+        self.trap_disable_delay = 0;
         self.int_count += 1;        
     }
 
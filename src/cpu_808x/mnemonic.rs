@@ -1,4 +1,6 @@
-#[derive(PartialEq, Copy, Clone, Debug)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Mnemonic {
     InvalidOpcode,
     NoOpcode,
@@ -10,6 +12,7 @@ pub enum Mnemonic {
     ADC,
     ADD,
     AND,
+    BOUND,
     CALL,
     CALLF,
     CBW,
@@ -32,6 +35,8 @@ pub enum Mnemonic {
     IMUL,
     IN,
     INC,
+    INSB,
+    INSW,
     INT,
     INT3,
     INTO,
@@ -73,9 +78,13 @@ pub enum Mnemonic {
     NOT,
     OR,
     OUT,
+    OUTSB,
+    OUTSW,
     POP,
+    POPA,
     POPF,
     PUSH,
+    PUSHA,
     PUSHF,
     RCL,
     RCR,
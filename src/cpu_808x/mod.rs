@@ -13,6 +13,7 @@ use core::fmt::Display;
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 
 // Pull in all CPU module components
 mod addressing;
@@ -22,8 +23,11 @@ mod bitwise;
 mod biu;
 mod cycle;
 mod decode;
+mod disassembly;
 mod display;
 mod execute;
+mod flags_affected;
+mod fpu;
 mod interrupt;
 mod jump;
 mod microcode;
@@ -33,13 +37,27 @@ mod muldiv;
 mod stack;
 mod string;
 mod queue;
+mod trace;
+mod snapshot;
+mod replay;
 mod fuzzer;
+#[cfg(feature = "cpu_coverage")]
+mod coverage;
 
 use crate::cpu_808x::mnemonic::Mnemonic;
 use crate::cpu_808x::microcode::*;
 use crate::cpu_808x::addressing::AddressingMode;
 use crate::cpu_808x::queue::{InstructionQueue, QueueDelay};
 use crate::cpu_808x::biu::*;
+use crate::cpu_808x::fpu::Fpu;
+use crate::cpu_808x::replay::ReplayMode;
+pub use crate::cpu_808x::snapshot::CpuSnapshot;
+pub use crate::cpu_808x::disassembly::{DisassemblyResult, InstructionQuery, parse_code_pattern};
+pub use crate::cpu_808x::display::{decode_flags, decode_flags_changed, flags_string};
+pub use crate::cpu_808x::flags_affected::FlagsAffected;
+pub use crate::cpu_808x::trace::{TraceBusCycle, TraceRecord, TraceRecordWriter};
+#[cfg(feature = "cpu_coverage")]
+pub use crate::cpu_808x::coverage::CoverageMap;
 
 use crate::cpu_common::{CpuType, CpuOption};
 
@@ -47,14 +65,16 @@ use crate::config::TraceMode;
 #[cfg(feature = "cpu_validator")]
 use crate::config::ValidatorType;
 
-use crate::breakpoints::BreakPointType;
-use crate::bus::{BusInterface, MEM_RET_BIT, MEM_BPA_BIT, MEM_BPE_BIT};
+use crate::breakpoints::{BreakPointType, BreakKind, BreakCondition};
+use crate::symbols::{CommentMap, SymbolMap};
+use crate::bus::{BusInterface, MEM_RET_BIT, MEM_BPA_BIT, MEM_BPE_BIT, MEM_BPW_BIT, MEM_EXEC_BIT};
 use crate::devices::pic::Pic;
 use crate::bytequeue::*;
 //use crate::interrupt::log_post_interrupt;
 
 use crate::syntax_token::*;
 use crate::tracelogger::TraceLogger;
+use crate::util;
 
 #[cfg(feature = "cpu_validator")]
 use crate::cpu_validator::{
@@ -80,6 +100,7 @@ const FETCH_DELAY: u8 = 2;
 
 const CPU_HISTORY_LEN: usize = 32;
 const CPU_CALL_STACK_LEN: usize = 16;
+const STEP_OVER_CYCLE_LIMIT: u64 = 320000;
 
 const INTERRUPT_VEC_LEN: usize = 4;
 const INTERRUPT_BREAKPOINT: u8 = 1;
@@ -112,6 +133,13 @@ const REGISTER_LO_MASK: u16    = 0b1111_1111_0000_0000;
 
 pub const MAX_INSTRUCTION_SIZE: usize = 15;
 
+/// The 808x has 20 address lines, so linear addresses wrap around at 1MB. Software such as 
+/// the BIOS relies on FFFF:0010 aliasing address 0. An A20 gate for later CPUs would vary this mask.
+pub const ADDRESS_MASK: u32 = 0xFFFFF;
+
+/// Number of consecutive 0x00 opcodes allowed before off rails detection triggers.
+pub const OFF_RAILS_DEFAULT_THRESHOLD: u32 = 5;
+
 const OPCODE_REGISTER_SELECT_MASK: u8 = 0b0000_0111;
 
 const MODRM_REG_MASK:          u8 = 0b00_111_000;
@@ -215,7 +243,7 @@ pub enum CpuException {
     DivideError
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CpuState {
     Normal,
     BreakpointHit
@@ -255,7 +283,7 @@ pub enum ServiceEvent {
     TriggerPITLogging
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CallStackEntry {
     Call { 
         ret_cs: u16, 
@@ -279,7 +307,19 @@ pub enum CallStackEntry {
     }
 }
 
+impl CallStackEntry {
+    /// Return the flat address this entry returns to.
+    pub fn return_address(&self) -> u32 {
+        match *self {
+            CallStackEntry::Call { ret_cs, ret_ip, .. }
+            | CallStackEntry::CallF { ret_cs, ret_ip, .. }
+            | CallStackEntry::Interrupt { ret_cs, ret_ip, .. } => Cpu::calc_linear_address(ret_cs, ret_ip)
+        }
+    }
+}
+
 /// Representation of a flag in the eFlags CPU register
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Flag {
     Carry,
     Parity,
@@ -315,7 +355,7 @@ pub enum Register {
     IP,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[derive(PartialEq)]
 pub enum Register8 {
     AL,
@@ -328,7 +368,7 @@ pub enum Register8 {
     BH
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[derive(PartialEq)]
 pub enum Register16 {
     AX, 
@@ -347,7 +387,7 @@ pub enum Register16 {
     InvalidRegister
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub enum OperandType {
     Immediate8(u8),
     Immediate16(u16),
@@ -365,6 +405,25 @@ pub enum OperandType {
     InvalidOperand
 }
 
+impl OperandType {
+    /// Return the width of the value an operand refers to. Operands whose width depends on
+    /// the instruction, such as modrm addressing modes, have no size.
+    pub fn size(&self) -> OperandSize {
+        match self {
+            OperandType::Immediate8(_)
+            | OperandType::Immediate8s(_)
+            | OperandType::Relative8(_)
+            | OperandType::Offset8(_)
+            | OperandType::Register8(_) => OperandSize::Operand8,
+            OperandType::Immediate16(_)
+            | OperandType::Relative16(_)
+            | OperandType::Offset16(_)
+            | OperandType::Register16(_) => OperandSize::Operand16,
+            _ => OperandSize::NoSize
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum DispType {
     NoDisp,
@@ -372,7 +431,7 @@ pub enum DispType {
     Disp16,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Displacement {
     NoDisp,
     Pending8,
@@ -381,7 +440,7 @@ pub enum Displacement {
     Disp16(i16),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum DmaState {
     Idle,
     Dreq,
@@ -424,7 +483,7 @@ impl fmt::Display for Displacement {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum RepType {
     NoRep,
     Rep,
@@ -435,7 +494,7 @@ impl Default for RepType {
     fn default() -> Self { RepType::NoRep }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Segment {
     None,
     ES,
@@ -451,7 +510,7 @@ impl Default for Segment {
 }
 
 // TODO: This enum duplicates Segment. Why not just store a Segment in an override field?
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SegmentOverride {
     None,
     ES,
@@ -460,7 +519,7 @@ pub enum SegmentOverride {
     DS
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OperandSize {
     NoOperand,
     NoSize,
@@ -474,7 +533,7 @@ impl Default for OperandSize {
     }
 }
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum InterruptType {
     NMI,
     Exception,
@@ -503,7 +562,7 @@ impl Default for InterruptDescriptor {
     }
 }
 
-#[derive (Copy, Clone)]
+#[derive (Copy, Clone, Serialize, Deserialize)]
 pub struct Instruction {
     pub(crate) opcode: u8,
     pub(crate) flags: u32,
@@ -516,6 +575,9 @@ pub struct Instruction {
     pub(crate) operand1_size: OperandSize,
     pub(crate) operand2_type: OperandType,
     pub(crate) operand2_size: OperandSize,
+    pub(crate) operand3_type: OperandType,
+    pub(crate) operand3_size: OperandSize,
+    pub(crate) op_ext: u8,          // The reg field of the modrm, if the instruction has one
 }
 
 impl Default for Instruction {
@@ -532,11 +594,14 @@ impl Default for Instruction {
             operand1_size: OperandSize::NoOperand,
             operand2_type: OperandType::NoOperand,
             operand2_size: OperandSize::NoOperand,
+            operand3_type: OperandType::NoOperand,
+            operand3_size: OperandSize::NoOperand,
+            op_ext:   0,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum TransferSize {
     Byte,
     Word
@@ -548,7 +613,7 @@ impl Default for TransferSize {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CpuAddress {
     Flat(u32),
     Segmented(u16, u16),
@@ -571,11 +636,50 @@ impl From<CpuAddress> for u32 {
     }
 }
 
+impl CpuAddress {
+    /// Return the linear address as a CpuAddress::Flat. Offset addresses have no segment and
+    /// are taken as linear.
+    pub fn to_flat(&self) -> CpuAddress {
+        CpuAddress::Flat(u32::from(*self))
+    }
+
+    /// Return the address advanced by 'delta' bytes. Segmented and offset addresses wrap within
+    /// the 64K segment; flat addresses wrap at the end of the 20-bit address space.
+    pub fn offset(&self, delta: i32) -> CpuAddress {
+        match *self {
+            CpuAddress::Flat(a) => CpuAddress::Flat((a as i32).wrapping_add(delta) as u32 & 0xFFFFF),
+            CpuAddress::Segmented(s, o) => CpuAddress::Segmented(s, o.wrapping_add(delta as u16)),
+            CpuAddress::Offset(o) => CpuAddress::Offset(o.wrapping_add(delta as u16)),
+        }
+    }
+
+    /// Return the canonical segment:offset form of the address, with an offset from 0 to F.
+    /// Any two segmented addresses referring to the same linear address normalize to the same
+    /// value. Offset addresses are returned unchanged.
+    pub fn normalize(&self) -> CpuAddress {
+        match *self {
+            CpuAddress::Offset(_) => *self,
+            _ => {
+                let linear = u32::from(*self) & 0xFFFFF;
+                CpuAddress::Segmented((linear >> 4) as u16, (linear & 0x0F) as u16)
+            }
+        }
+    }
+}
+
+/// Segmented addresses display as SSSS:OOOO and flat addresses as XXXXX. The alternate form
+/// ({:#}) of a segmented address appends its linear address, ie: 1000:0010 [01010]
 impl fmt::Display for CpuAddress {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CpuAddress::Flat(a) => write!(f, "{:05X}", a),
-            CpuAddress::Segmented(s, o) => write!(f, "{:04X}:{:04X}", s, o),
+            CpuAddress::Segmented(s, o) => {
+                write!(f, "{:04X}:{:04X}", s, o)?;
+                if f.alternate() {
+                    write!(f, " [{:05X}]", Cpu::calc_linear_address(*s, *o))?;
+                }
+                Ok(())
+            }
             CpuAddress::Offset(a) => write!(f, "{:04X}", a),
         }
     }
@@ -597,12 +701,13 @@ impl PartialEq for CpuAddress {
             (CpuAddress::Segmented(s1,o1), CpuAddress::Segmented(s2,o2)) => {
                 *s1 == *s2 && *o1 == *o2
             }
+            (CpuAddress::Offset(a), CpuAddress::Offset(b)) => a == b,
             _ => false
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
 pub struct I8288 {
     // Command bus
     mrdc: bool,
@@ -713,16 +818,26 @@ pub struct Cpu<'a>
     cycle_num: u64,
     instr_cycle: u32,
     instr_elapsed: u32,
+    instr_retired: bool,
     instruction_count: u64,
     i: Instruction,                 // Currently executing instruction 
     instruction_history_on: bool,
     instruction_history: VecDeque<HistoryEntry>,
     call_stack: VecDeque<CallStackEntry>,
+    call_stack_overflow: bool,
 
     // Breakpoints
     breakpoints: Vec<BreakPointType>,
+    // Conditional breakpoints, with the result of their last evaluation
+    conditional_breakpoints: Vec<(BreakCondition, bool)>,
+    // Interrupt breakpoints added with break_on_interrupt(), with an optional register filter
+    interrupt_breakpoints: Vec<(u8, Option<BreakCondition>)>,
+    access_breakpoint: Option<(u32, BreakKind)>,
+    last_breakpoint: Option<(u32, BreakKind)>,
 
     step_over_target: Option<CpuAddress>,
+    symbols: SymbolMap,
+    comments: CommentMap,
 
     // Interrupts
     int_stack: Vec<InterruptDescriptor>,
@@ -739,10 +854,19 @@ pub struct Cpu<'a>
     trace_comment: Vec<&'static str>,
     trace_instr: u16,
     trace_str_vec: Vec<String>,
+    trace_callback: Option<Box<dyn FnMut(&TraceRecord) + 'a>>,
+    trace_record_writer: Option<TraceRecordWriter<Box<dyn Write + 'a>>>,
+    trace_record: Option<TraceRecord>,
+    retire_callback: Option<Box<dyn FnMut(u32, u8, u32) + 'a>>,
+    retire_cycles: u32,
 
     enable_wait_states: bool,
     off_rails_detection: bool,
+    off_rails_threshold: u32,
     opcode0_counter: u32,
+    runaway_callback: Option<Box<dyn FnMut(u32, u32) -> RunawayAction + 'a>>,
+    jump_sanity: bool,
+    bad_jump_callback: Option<Box<dyn FnMut(u32, u32) -> RunawayAction + 'a>>,
 
     rng: Option<rand::rngs::StdRng>,
 
@@ -769,15 +893,28 @@ pub struct Cpu<'a>
     dma_aen: bool,
 
     // Trap stuff
-    trap_enable_delay: u32,             // Number of cycles to delay trap flag enablement. 
-    trap_disable_delay: u32,            // Number of cycles to delay trap flag disablement.
+    trap_enable_delay: u32,             // Number of instructions to delay trap flag enablement. 
+    trap_disable_delay: u32,            // Number of instructions to delay trap flag disablement.
     trap_suppressed: bool,              // Suppress trap handling for the last executed instruction.
 
     nmi: bool,                          // Status of NMI line.
-    nmi_triggered: bool,                // Has NMI been edge-triggered?
+    nmi_pending: bool,                  // NMI edge latched, waiting to be serviced
+    nmi_inhibit: bool,                  // NMI inhibited for one instruction after a load of SS
 
     halt_resume_delay: u32,
     int_flags: Vec<u8>,
+
+    fpu: Fpu,
+    fpu_present: bool,                  // Execute ESC instructions on the emulated 8087, or trap to INT 7.
+
+    rep_prefix_bug: bool,               // Emulate loss of prefixes when an interrupted string instruction resumes.
+    strict_undefined_flags: bool,       // Reproduce the flag values hardware leaves in 'undefined' flags.
+    operand_size_error: Option<String>, // Operand width mismatch found during the current instruction.
+
+    replay: ReplayMode,                 // Recording or replaying of IO reads and interrupts.
+
+    #[cfg(feature = "cpu_coverage")]
+    coverage: CoverageMap,
 }
 
 #[cfg(feature = "cpu_validator")]
@@ -796,6 +933,7 @@ impl Default for CpuValidatorState {
     }
 }
 
+#[derive(Clone)]
 pub struct CpuRegisterState {
     pub ah: u8,
     pub al: u8,
@@ -855,6 +993,8 @@ pub struct CpuStringState {
     pub i_fl: String,
     pub d_fl: String,
     pub o_fl: String,
+    // Each bit of FLAGS, from decode_flags()
+    pub flag_tokens: Vec<SyntaxToken>,
     pub piq: String,
     pub instruction_count: String,
     pub cycle_count: String
@@ -871,10 +1011,67 @@ pub enum StepResult {
     // If a call occurred, we return the address of the next instruction after the call
     // so that we can step over the call in the debugger.
     Call(CpuAddress),
+    // If a hardware interrupt was dispatched, we return the address of the interrupted
+    // instruction so that the debugger can run the ISR to completion.
+    Interrupt(CpuAddress),
+    // The CPU is halted with interrupts enabled and is waiting for an interrupt. The caller 
+    // should keep running devices so that one can arrive.
+    HaltWait,
     BreakpointHit,
     ProgramEnd
 }
 
+/// The outcome of a step over operation.
+#[derive (Debug, PartialEq)]
+pub enum StepOverResult {
+    // Execution reached the instruction following the stepped instruction.
+    Completed,
+    // A breakpoint was hit before execution returned.
+    HitBreakpoint,
+    // The CPU halted, or reached the program end address, before execution returned.
+    Halted,
+    // Execution did not return within the cycle or instruction limit.
+    TimedOut,
+}
+
+/// The routine being stepped out of, captured by Cpu::return_frame() so that 
+/// Cpu::has_returned() can tell when it has returned to its caller.
+#[derive (Copy, Clone, Debug)]
+pub struct ReturnFrame {
+    depth: usize,
+    return_addr: Option<u32>,
+}
+
+impl ReturnFrame {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// The reason a budgeted run stopped.
+#[derive (Debug)]
+pub enum StopReason {
+    // The instruction or cycle budget was used up.
+    BudgetExhausted,
+    // A breakpoint was hit.
+    BreakpointHit,
+    // The CPU halted. Devices are not run, so nothing can resume it.
+    Halted,
+    // The program end address was reached.
+    ProgramEnd,
+    // Execution failed with the error returned by step(), which gives the address of the
+    // failing instruction.
+    Error(CpuError),
+}
+
+/// The outcome of run_for_instructions() or run_for_cycles().
+#[derive (Debug)]
+pub struct ExecutionSummary {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub stop_reason: StopReason,
+}
+
 #[derive (Debug, PartialEq)]
 pub enum ExecutionResult {
     Okay,
@@ -883,10 +1080,32 @@ pub enum ExecutionResult {
     UnsupportedOpcode(u8),
     ExecutionError(String),
     ExceptionError(CpuException),
-    Halt
+    Halt,
+    HaltWait,
+    // A read or write breakpoint was tripped at the specified address during execution.
+    Breakpoint(u32),
+    // Off rails detection tripped after the specified number of consecutive 0x00 opcodes.
+    RunawayDetected(u32),
+    // Jump sanity checking found a control transfer to the specified address, which is 
+    // unmapped or has never been written.
+    BadJump(u32),
 }
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+/// The action to take when off rails detection or jump sanity checking trips, as chosen by
+/// the runaway or bad jump callback.
+#[derive (Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum RunawayAction {
+    // Halt permanently with interrupts disabled. This is the default for off rails detection
+    // with no callback installed.
+    Halt,
+    // Stop as if a breakpoint was hit, so the debugger can inspect the CPU. This is the default
+    // for jump sanity checking with no callback installed.
+    Break,
+    // Keep running. Off rails detection won't trip again until a run of 0x00 opcodes is broken.
+    Ignore,
+}
+
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TCycle {
     TInit,
     T1,
@@ -902,7 +1121,7 @@ impl Default for TCycle {
     }
 }
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BusStatus {
     InterruptAck = 0,   // IRQ Acknowledge
     IoRead = 1,         // IO Read
@@ -920,7 +1139,7 @@ impl Default for BusStatus {
     }
 }
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum QueueDirection {
     None,
     Read,
@@ -934,7 +1153,7 @@ impl Default for QueueDirection {
 }
 
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum QueueOp {
     Idle,
     First,
@@ -948,7 +1167,7 @@ impl Default for QueueOp {
     }
 }
 
-#[derive (Copy, Clone, Debug, PartialEq)]
+#[derive (Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum FetchState {
     Idle,
     InProgress,
@@ -980,13 +1199,11 @@ impl<'a> Cpu<'a> {
         let mut cpu: Cpu = Default::default();
         
         match cpu_type {
-            CpuType::Intel8088 => {
-                cpu.queue.set_size(4);
-                cpu.fetch_size = TransferSize::Byte;
+            CpuType::Intel8088 | CpuType::Intel80188 | CpuType::NecV20 => {
+                cpu.biu_set_queue_params(4, TransferSize::Byte);
             }
-            CpuType::Intel8086 => {
-                cpu.queue.set_size(6);
-                cpu.fetch_size = TransferSize::Word;
+            CpuType::Intel8086 | CpuType::Intel80186 => {
+                cpu.biu_set_queue_params(6, TransferSize::Word);
             }
         }
 
@@ -1022,6 +1239,8 @@ impl<'a> Cpu<'a> {
         cpu.instruction_history = VecDeque::with_capacity(16);
 
         cpu.reset_vector = CpuAddress::Segmented(0xFFFF, 0x0000);
+        cpu.rep_prefix_bug = true;
+        cpu.off_rails_threshold = OFF_RAILS_DEFAULT_THRESHOLD;
         cpu.reset();
         cpu
     }
@@ -1057,29 +1276,65 @@ impl<'a> Cpu<'a> {
         }
 
         self.address_bus = 0;
+        self.data_bus = 0;
+        self.last_ea = 0;
+        self.ea_opr = 0;
         self.bus_status = BusStatus::Passive;
+        self.bus_segment = Segment::None;
+        self.transfer_size = TransferSize::Byte;
+        self.operand_size = OperandSize::NoOperand;
+        self.lock = false;
         self.t_cycle = TCycle::T1;
         
         self.instruction_count = 0; 
         self.int_count = 0;
         self.iret_count = 0;
         self.instr_cycle = 0;
+        self.instr_elapsed = 0;
+        self.instr_retired = false;
         self.cycle_num = 1;
         
         self.in_rep = false;
+        self.rep_init = false;
+        self.rep_saved = false;
         self.halted = false;
         self.opcode0_counter = 0;
         self.interrupt_inhibit = false;
+        self.nmi_pending = false;
+        self.nmi_inhibit = false;
         self.pending_interrupt = false;
         self.is_error = false;
         self.instruction_history.clear();
         self.call_stack.clear();
-        self.int_flags = vec![0; 256];
+        self.call_stack_overflow = false;
+        self.access_breakpoint = None;
+        self.last_breakpoint = None;
+        self.int_stack.clear();
+        self.update_interrupt_flags();
+        self.fpu.reset();
 
         self.queue_op = QueueOp::Idle;
         self.last_queue_op = QueueOp::Idle;
         self.last_queue_delay = QueueDelay::None;
+        self.last_queue_len = 0;
+        self.last_queue_direction = QueueDirection::None;
+        self.queue_byte = 0;
+        self.last_queue_byte = 0;
         self.fetch_state = FetchState::Idle;
+        self.next_fetch_state = FetchState::Idle;
+        self.fetch_suspended = false;
+        self.fetch_delay = 0;
+        self.bus_pending_eu = false;
+        self.biu_state = BiuState::Operating;
+        self.transfer_n = 0;
+        self.wait_states = 0;
+        self.bus_wait_states = 0;
+
+        // Clear any pending microcode state from the previous instruction so that the
+        // first instruction after reset does not begin with an RNI cycle.
+        self.mc_pc = MC_NONE;
+        self.nx = false;
+        self.rni = false;
 
         self.i8288.ale = false;
         self.i8288.mrdc = false;
@@ -1088,16 +1343,30 @@ impl<'a> Cpu<'a> {
         self.i8288.iorc = false;
         self.i8288.aiowc = false;
         self.i8288.iowc = false;
+        self.i8288.inta = false;
 
         self.step_over_target = None;
         self.end_addr = 0xFFFFF;
 
-        // Reset takes 6 cycles before first fetch
+        // Only compare undefined flags against the hardware CPU when we claim to model them.
+        // This is applied on reset rather than in set_option() so an active validator isn't
+        // reinitialized mid-run.
+        #[cfg(feature = "cpu_validator")]
+        if let Some(ref mut validator) = self.validator {
+            if !validator.init(ValidatorMode::Cycle, !self.strict_undefined_flags, true, true) {
+                log::error!("Failed to reinitialize cpu validator on reset.");
+            }
+        }
+
+        // Reset takes 6 cycles before first fetch. At the end of this sequence the queue is
+        // empty and a code fetch from the reset vector is in T1, so the first instruction
+        // pays the full fetch penalty.
         self.cycle();
         self.biu_suspend_fetch();
         self.cycles_i(2, &[0x1e4, 0x1e5]);
         self.biu_queue_flush();
         self.cycles_i(3, &[0x1e6, 0x1e7, 0x1e8]);
+        self.instr_cycle = 0;
 
         #[cfg(feature = "cpu_validator")]
         {
@@ -1113,6 +1382,13 @@ impl<'a> Cpu<'a> {
         self.in_rep
     }
 
+    /// Returns true if the last call to step() completed an instruction. This is false for a step
+    /// that only dispatched an interrupt, ran a cycle of halt state, or ran a REP iteration that did
+    /// not end the string instruction.
+    pub fn instruction_retired(&self) -> bool {
+        self.instr_retired
+    }
+
     pub fn bus(&self) -> &BusInterface {
         &self.bus
     }   
@@ -1121,6 +1397,14 @@ impl<'a> Cpu<'a> {
         &mut self.bus
     }
 
+    pub fn get_cpu_type(&self) -> CpuType {
+        self.cpu_type
+    }
+
+    pub fn get_instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
     pub fn get_csip(&self) -> CpuAddress {
         CpuAddress::Segmented(self.cs, self.ip)
     }
@@ -1161,7 +1445,11 @@ impl<'a> Cpu<'a> {
                 self.transfer_n == 1
             }
             OperandSize::Operand16 => {
-                self.transfer_n == 2
+                // A word transfer on the 8086 moves both bytes in one bus cycle
+                match self.transfer_size {
+                    TransferSize::Byte => self.transfer_n == 2,
+                    TransferSize::Word => self.transfer_n == 1,
+                }
             }
             _ => true
         }
@@ -1288,14 +1576,30 @@ impl<'a> Cpu<'a> {
         self.is_error
     }
 
+    /// Set the state of the NMI line. NMI is edge-triggered: a low to high transition latches
+    /// a pending NMI, which is serviced at the next instruction boundary even if the line has
+    /// been lowered again by then. Holding the line high does not trigger further NMIs.
     pub fn set_nmi(&mut self, nmi_state: bool) {
 
-        if nmi_state == false {
-            self.nmi_triggered = false;
+        if nmi_state && !self.nmi {
+            self.nmi_pending = true;
         }
         self.nmi = nmi_state;
     }
 
+    /// Raise the NMI line. The line must be lowered before another NMI can be raised.
+    pub fn raise_nmi(&mut self) {
+        self.set_nmi(true);
+    }
+
+    pub fn lower_nmi(&mut self) {
+        self.set_nmi(false);
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
     #[inline(always)]
     pub fn set_flag(&mut self, flag: Flag ) {
 
@@ -1693,6 +1997,7 @@ impl<'a> Cpu<'a> {
                 format!("{:1}", fl as u8)
             },
             
+            flag_tokens: decode_flags(self.flags),
             piq: self.queue.to_string(),
             flags: format!("{:04}", self.flags),
             instruction_count: format!("{}", self.instruction_count),
@@ -1795,8 +2100,24 @@ impl<'a> Cpu<'a> {
     }
 
     /// Push an entry on to the call stack. This can either be a CALL or an INT.
+    /// The call stack holds at most CPU_CALL_STACK_LEN entries. If it is full, the oldest entry
+    /// is dropped and the overflow flag is set.
     pub fn push_call_stack(&mut self, entry: CallStackEntry, cs: u16, ip: u16) {
 
+        if self.call_stack.len() == CPU_CALL_STACK_LEN {
+            if let Some(dropped) = self.call_stack.pop_front() {
+                // Clear the return flag for the dropped entry unless another entry shares it
+                let dropped_addr = dropped.return_address();
+                if !self.call_stack.iter().any(|call| call.return_address() == dropped_addr) {
+                    self.bus.clear_flags(dropped_addr as usize, MEM_RET_BIT);
+                }
+            }
+            if !self.call_stack_overflow {
+                log::warn!("push_call_stack(): call stack exceeded {} entries", CPU_CALL_STACK_LEN);
+            }
+            self.call_stack_overflow = true;
+        }
+
         self.call_stack.push_back(entry);
 
         // Flag the specified CS:IP as a return address
@@ -1805,6 +2126,25 @@ impl<'a> Cpu<'a> {
         self.bus.set_flags(return_addr as usize, MEM_RET_BIT);
     }
 
+    pub fn call_stack_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// Iterate over the call stack, from the oldest entry to the most recent.
+    pub fn call_stack_iter(&self) -> impl Iterator<Item = &CallStackEntry> {
+        self.call_stack.iter()
+    }
+
+    /// Returns true if entries have been dropped from the call stack since the last reset
+    /// or call to clear_call_stack_overflow().
+    pub fn call_stack_overflowed(&self) -> bool {
+        self.call_stack_overflow
+    }
+
+    pub fn clear_call_stack_overflow(&mut self) {
+        self.call_stack_overflow = false;
+    }
+
     /// Rewind the call stack to the specified address.
     /// We have to rewind the call stack to the earliest appearance of this address we returned to, 
     /// because popping the call stack clears the return flag from the memory location, so we don't 
@@ -1861,7 +2201,8 @@ impl<'a> Cpu<'a> {
         }
     }    
     
-    /// Resume from halted state
+    /// Resume from halted state. Fetching stays suspended from HLT until the interrupt 
+    /// routine that woke us flushes the queue and jumps to the ISR, which restarts it.
     pub fn resume(&mut self) {
         if self.halted {
             //log::debug!("Resuming from halt");
@@ -1876,6 +2217,61 @@ impl<'a> Cpu<'a> {
         self.halted = false;
     }
 
+    /// Add the instruction that just executed at 'cs':'ip' to the instruction history.
+    fn push_history(&mut self, cs: u16, ip: u16) {
+        if self.instruction_history.len() == CPU_HISTORY_LEN {
+            self.instruction_history.pop_front();
+        }
+        self.instruction_history.push_back(
+            HistoryEntry::Entry {
+                cs, 
+                ip, 
+                cycles: self.instr_cycle as u16, 
+                i: self.i
+            }
+        );
+    }
+
+    /// Record an instruction that completed at 'cs':'ip' in the instruction history and 
+    /// trace, and return its step result: Call if the instruction set a step over target, 
+    /// otherwise Normal.
+    fn complete_instruction(&mut self, cs: u16, ip: u16) -> StepResult {
+        if self.instruction_history_on {
+            self.push_history(cs, ip);
+            self.instruction_count += 1;
+        }
+
+        // Perform instruction tracing, if enabled
+        if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
+            self.trace_print(&self.instruction_state_string());   
+        }
+
+        match self.step_over_target {
+            Some(step_over_target) => StepResult::Call(step_over_target),
+            None => StepResult::Normal
+        }
+    }
+
+    /// Carry out a RunawayAction for the instruction at 'address', which completed normally 
+    /// with the step result 'result'. Ignoring the condition keeps that result.
+    fn runaway_action_result(&mut self, action: RunawayAction, address: u32, result: StepResult) -> Result<(StepResult, u32), CpuError> {
+        match action {
+            RunawayAction::Halt => {
+                // Halt permanently by clearing interrupt flag
+                self.clear_flag(Flag::Interrupt);
+                self.halted = true;
+                self.is_running = false;
+                self.is_error = true;
+                Err(CpuError::CpuHaltedError(address))
+            }
+            RunawayAction::Break => {
+                self.set_breakpoint_flag();
+                Ok((StepResult::BreakpointHit, self.instr_cycle))
+            }
+            RunawayAction::Ignore => Ok((result, self.instr_cycle))
+        }
+    }
+
     /// Execute a single instruction.
     /// 
     /// We divide instruction execution into separate fetch/decode and execute phases.
@@ -1891,6 +2287,7 @@ impl<'a> Cpu<'a> {
 
         self.instr_cycle = 0;
         self.instr_elapsed = 0;
+        self.instr_retired = false;
 
         // If tracing is enabled, clear the trace string vector that holds the trace from the last instruction.
         if self.trace_enabled {
@@ -1907,57 +2304,73 @@ impl<'a> Cpu<'a> {
         // interrupts themselves in microcode. Therefore we want to model that behavior. This allows the 
         // microcode routine for RPTI to execute within the REP-prefixed instruction. The interrupt then
         // fires after.
+        //
+        // Prefixes are decoded as part of the instruction they modify and IP always points at the first 
+        // prefix byte, so an interrupt taken here returns to the start of the prefixed instruction and 
+        // the prefixes are re-fetched on IRET, as on a real 8088.
         self.pending_interrupt = false;
-        let mut irq = 7;
-
-        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
-            // NMI takes priority over trap and INTR.
-            if self.halted {
-                // Resume from halt on interrupt
-                self.resume();
-            }            
-            log::debug!("Triggered NMI!");
-            self.nmi_triggered = true;
-            self.int2();
-            let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
-            return step_result              
+        let mut pending_nmi = false;
+
+        let nmi = self.nmi_pending && self.bus.nmi_enabled() && !self.nmi_inhibit;
+        if self.replay_nmi(nmi) {
+            // NMI takes priority over trap and INTR. NMI ignores the interrupt flag, but is inhibited
+            // for one instruction after a load of SS, so that SS:SP can be updated together.
+            if self.in_rep {
+                // Like INTR, NMI is taken by the RPTI routine after the current iteration.
+                self.pending_interrupt = true;
+                pending_nmi = true;
+            }
+            else {
+                if self.halted {
+                    // Resume from halt on interrupt
+                    self.resume();
+                }            
+                log::debug!("Triggered NMI!");
+                self.nmi_pending = false;
+                self.int2();
+                let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
+                return step_result              
+            }
         }
         else if self.trap_enabled() {
             // Trap takes priority over INTR.
+            if self.halted {
+                // A trap following HLT wakes the CPU, as any other interrupt would
+                self.resume();
+            }
             self.int1();
             let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
             return step_result              
         }
         else if self.interrupts_enabled() {
-            if let Some(pic) = self.bus.pic_mut().as_mut() {
-                // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
-                if pic.query_interrupt_line() {
-                    if let Some(iv) = pic.get_interrupt_vector() {
-                        irq = iv;
-                        if self.in_rep {
-                            // Set pending interrupt to execute after RPTI
-                            self.pending_interrupt = true;
-                        }
-                        else {
-                            if self.halted {
-                                // Resume from halt on interrupt
-                                self.resume();
-                            }
-                            // We will be jumping into an ISR now. Set the step result to Call and return
-                            // the address of the next instruction. (Step Over skips ISRs)
-
-                            // Set breakpoint flag if we have a breakpoint for this interrupt.
-                            if self.int_flags[irq as usize] != 0 {
-                                self.set_breakpoint_flag();
-                            }
+            // Is INTR active? The PIC raises INTR when its priority resolver has an unmasked
+            // request of higher priority than any interrupt in service. The vector isn't known
+            // until the PIC puts it on the bus during the INTA cycles.
+            let intr = self.bus.pic_mut().as_ref().map_or(false, |pic| pic.query_interrupt_line());
+            if self.replay_intr(intr) {
+                if self.in_rep {
+                    // Set pending interrupt to execute after RPTI
+                    self.pending_interrupt = true;
+                }
+                else {
+                    if self.halted {
+                        // Resume from halt on interrupt
+                        self.resume();
+                    }
+                    // We will be jumping into an ISR now. Set the step result to Interrupt and return
+                    // the address of the interrupted instruction. (Step Over skips ISRs)
+                    let return_addr = CpuAddress::Segmented(self.cs, self.ip);
 
-                            // Do interrupt
-                            self.hw_interrupt(irq);
-                            //log::debug!("hardware interrupt took {} cycles", self.instr_cycle);
-                            let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
-                            return step_result                                                 
-                        }
+                    // Do interrupt
+                    if self.trace_records_wanted() {
+                        self.trace_record = Some(TraceRecord::new(self.get_state(), Cpu::calc_linear_address(self.cs, self.ip)));
                     }
+                    // A breakpoint on this interrupt is checked as it is delivered.
+                    let irq = self.intr_acknowledge();
+                    self.emit_interrupt_trace_record(irq);
+                    //log::debug!("hardware interrupt took {} cycles", self.instr_cycle);
+                    let step_result = Ok((StepResult::Interrupt(return_addr), self.instr_cycle));
+                    return step_result
                 }
             }
         }
@@ -1965,7 +2378,7 @@ impl<'a> Cpu<'a> {
         if self.halted {
             // Run one cycle of halt state
             self.cycle_i(self.mc_pc);
-            return Ok((StepResult::Normal, 1))
+            return Ok((StepResult::HaltWait, 1))
         }
 
         // A real 808X CPU maintains a single Program Counter or PC register that points to the next instruction
@@ -1990,13 +2403,29 @@ impl<'a> Cpu<'a> {
         if !skip_breakpoint && self.bus.get_flags(instruction_address as usize) & MEM_BPE_BIT != 0 {
             // Breakpoint hit.
             log::debug!("Breakpoint hit at {:05X}", instruction_address);
+            self.last_breakpoint = Some((instruction_address, BreakKind::Execute));
+            self.set_breakpoint_flag();
+            return Ok((StepResult::BreakpointHit, 0))
+        }
+
+        // Check conditional breakpoints at the instruction boundary
+        if !self.in_rep && self.check_conditional_breakpoints() && !skip_breakpoint {
+            log::debug!("Conditional breakpoint hit at {:05X}", instruction_address);
             self.set_breakpoint_flag();
             return Ok((StepResult::BreakpointHit, 0))
         }
 
+        // Mark the instruction address as executed for the memory map.
+        self.bus.set_flags(instruction_address as usize, MEM_EXEC_BIT);
+
         // Fetch the next instruction unless we are executing a REP
         if !self.in_rep {
 
+            // Begin a new trace record with the state before execution.
+            if self.trace_records_wanted() {
+                self.trace_record = Some(TraceRecord::new(self.get_state(), instruction_address));
+            }
+
             // Clear the validator cycle states from the last instruction.
             #[cfg(feature = "cpu_validator")]
             {
@@ -2019,7 +2448,7 @@ impl<'a> Cpu<'a> {
             // anyway.
             if self.trace_mode == TraceMode::Cycle {
                 self.bus.seek(instruction_address as usize);
-                self.i = match Cpu::decode(&mut self.bus) {
+                self.i = match Cpu::decode(&mut self.bus, self.cpu_type) {
                     Ok(i) => i,
                     Err(_) => {
                         self.is_running = false;
@@ -2033,7 +2462,8 @@ impl<'a> Cpu<'a> {
             
             // Fetch and decode the current instruction. This uses the CPU's own ByteQueue trait 
             // implementation, which fetches instruction bytes through the processor instruction queue.
-            self.i = match Cpu::decode(self) {
+            let cpu_type = self.cpu_type;
+            self.i = match Cpu::decode(self, cpu_type) {
                 Ok(i) => i,
                 Err(_) => {
                     self.is_running = false;
@@ -2042,6 +2472,9 @@ impl<'a> Cpu<'a> {
                 }                
             };
 
+            #[cfg(feature = "cpu_coverage")]
+            self.coverage.record(&self.i);
+
             // Begin the current instruction validation context.
             #[cfg(feature = "cpu_validator")]
             {
@@ -2084,7 +2517,9 @@ impl<'a> Cpu<'a> {
         // Load the mod/rm operand for the instruction, if applicable.
         self.load_operand();
 
-        // Execute the current decoded instruction.
+        // Execute the current decoded instruction. An instruction is retired unless it is suspended
+        // by an interrupt (see rep_interrupt()) or has further REP iterations to run.
+        self.instr_retired = true;
         let exec_result = self.execute_instruction();
 
         // Finalize execution. This runs cycles until the next instruction byte has been fetched. This fetch period is technically
@@ -2187,63 +2622,25 @@ impl<'a> Cpu<'a> {
 
        let mut step_result = match exec_result {
 
-            ExecutionResult::Okay => {
+            ExecutionResult::Okay | ExecutionResult::HaltWait => {
                 // Normal non-jump instruction updates CS:IP to next instruction during execute()
-                if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
-                    self.instruction_count += 1;
-                }
-
-                check_interrupts = true;
-
-                // Perform instruction tracing, if enabled
-                if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
-                    self.trace_print(&self.instruction_state_string());   
-                }                
-
-                Ok((StepResult::Normal, self.instr_cycle))
-            }
-            ExecutionResult::OkayJump => {
-                // A control flow instruction updated CS:IP.
-                if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
-                    self.instruction_count += 1;
-                }
-
+                self.complete_instruction(last_cs, last_ip);
                 check_interrupts = true;
 
-                // Perform instruction tracing, if enabled
-                if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
-                    self.trace_print(&self.instruction_state_string());   
-                }
-   
-                // Only CALLS will set a step over target. 
-                if let Some(step_over_target) = self.step_over_target {
-                    Ok((StepResult::Call(step_over_target), self.instr_cycle))
+                if exec_result == ExecutionResult::HaltWait {
+                    // HLT with interrupts enabled. We will idle in halt state until an interrupt
+                    // resumes execution at the next instruction.
+                    Ok((StepResult::HaltWait, self.instr_cycle))
                 }
                 else {
                     Ok((StepResult::Normal, self.instr_cycle))
-                }                
+                }
+            }
+            ExecutionResult::OkayJump => {
+                // A control flow instruction updated CS:IP. Only CALLS will set a step over target. 
+                let result = self.complete_instruction(last_cs, last_ip);
+                check_interrupts = true;
+                Ok((result, self.instr_cycle))
             }
             ExecutionResult::OkayRep => {
                 // We are in a REPx-prefixed instruction.
@@ -2253,18 +2650,7 @@ impl<'a> Cpu<'a> {
                 // earlier so that a REP string operation can call RPTI to be ready for
                 // an interrupt to occur.
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
+                    self.push_history(last_cs, last_ip);
                 }
                 self.instruction_count += 1;
                 check_interrupts = true;
@@ -2292,6 +2678,41 @@ impl<'a> Cpu<'a> {
                 self.is_error = true;
                 Err(CpuError::CpuHaltedError(instruction_address))
             }
+            ExecutionResult::Breakpoint(address) => {
+                // A read or write breakpoint tripped. The instruction (or the current REP iteration) 
+                // has completed, so record it as normal, but stop here.
+                let result = self.complete_instruction(last_cs, last_ip);
+                log::debug!("Memory breakpoint hit at {:05X} by instruction at {:05X}", address, instruction_address);
+                let step_result = self.runaway_action_result(RunawayAction::Break, instruction_address, result);
+                check_interrupts = step_result.is_ok();
+                step_result
+            }
+            ExecutionResult::RunawayDetected(count) => {
+                // The instruction executed normally, so record it before deciding what to do.
+                let result = self.complete_instruction(last_cs, last_ip);
+                let action = match &mut self.runaway_callback {
+                    Some(callback) => callback(instruction_address, count),
+                    None => RunawayAction::Halt
+                };
+
+                log::warn!("Off rails detection: {} consecutive 0x00 opcodes at {:05X}, action: {:?}", count, instruction_address, action);
+                let step_result = self.runaway_action_result(action, instruction_address, result);
+                check_interrupts = step_result.is_ok();
+                step_result
+            }
+            ExecutionResult::BadJump(target) => {
+                // The jump itself completed normally, so record it before deciding what to do.
+                let result = self.complete_instruction(last_cs, last_ip);
+                let action = match &mut self.bad_jump_callback {
+                    Some(callback) => callback(instruction_address, target),
+                    None => RunawayAction::Break
+                };
+
+                log::warn!("Jump sanity: instruction at {:05X} jumped to unmapped or unwritten address {:05X}, action: {:?}", instruction_address, target, action);
+                let step_result = self.runaway_action_result(action, instruction_address, result);
+                check_interrupts = step_result.is_ok();
+                step_result
+            }
             ExecutionResult::ExceptionError(exception) => {
                 // A CPU exception occurred. On the 8088, these are limited in scope to 
                 // division errors, and overflow after INTO.
@@ -2308,19 +2729,46 @@ impl<'a> Cpu<'a> {
             }
         };
 
+        // Cycles spent by the instruction itself, not including any interrupt taken after it
+        let exec_cycles = self.instr_cycle;
+
         // Handle pending interrupts now that execution has completed. This is to to allow execution of
         // RPTI microcode routine.
         if check_interrupts && self.pending_interrupt {
 
-            // We will be jumping into an ISR now. Set the step result to Call and return
-            // the address of the next instruction. (Step Over skips ISRs)
-            step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
+            // We will be jumping into an ISR now. Set the step result to Interrupt and return
+            // the address of the interrupted instruction. (Step Over skips ISRs)
+            step_result = Ok((StepResult::Interrupt(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
             
-            if self.int_flags[irq as usize] != 0 {
-                // This interrupt has a breakpoint
-                self.set_breakpoint_flag();
-            }            
-            self.hw_interrupt(irq);
+            if pending_nmi {
+                log::debug!("Triggered NMI!");
+                self.nmi_pending = false;
+                self.int2();
+            }
+            else {
+                self.intr_acknowledge();
+            }
+        }
+
+        self.instr_retired &= step_result.is_ok() && !self.in_rep;
+
+        if let Some(record) = &mut self.trace_record {
+            record.cycles += self.instr_cycle;
+        }
+        if self.instr_retired {
+            self.emit_trace_record();
+        }
+
+        // A REP instruction runs over several steps. Its cycles are reported when it ends, either
+        // by retiring or by being suspended for an interrupt.
+        if let Some(callback) = &mut self.retire_callback {
+            self.retire_cycles += exec_cycles;
+            if !self.in_rep || step_result.is_err() {
+                if step_result.is_ok() {
+                    callback(instruction_address, self.i.opcode, self.retire_cycles);
+                }
+                self.retire_cycles = 0;
+            }
         }
 
         // Check registers and flags for internal consistency.
@@ -2330,6 +2778,179 @@ impl<'a> Cpu<'a> {
         step_result
     }
 
+    /// Execute a single instruction, stepping over it if it is a CALL or INT, or if it
+    /// dispatched an interrupt.
+    /// 
+    /// If the step produces a step over target, execution continues until CS:IP returns to the
+    /// target address with the stack pointer back at or above its value before the call. This
+    /// acts as a one-shot breakpoint that a recursive call returning to the same address will
+    /// not trip early, as its frame is lower on the stack. If there is no step over target, 
+    /// this is equivalent to a single step.
+    /// 
+    /// Devices are not run between instructions, so this is intended for use when the CPU is
+    /// driven without the rest of the machine. The Machine performs its own step over.
+    pub fn step_over(&mut self, skip_breakpoint: bool) -> Result<StepOverResult, CpuError> {
+
+        // The stack frame we return to is the one in place before the call pushed its return address
+        let (frame_ss, frame_sp) = (self.ss, self.sp);
+
+        let target = match self.step(skip_breakpoint)? {
+            (StepResult::Call(target), _) | (StepResult::Interrupt(target), _) => target,
+            (StepResult::BreakpointHit, _) => return Ok(StepOverResult::HitBreakpoint),
+            (StepResult::ProgramEnd, _) | (StepResult::HaltWait, _) => return Ok(StepOverResult::Halted),
+            (StepResult::Normal, _) => {
+                if self.halted {
+                    return Ok(StepOverResult::Halted)
+                }
+                return Ok(StepOverResult::Completed)
+            }
+        };
+
+        let mut cycles: u64 = 0;
+
+        while self.get_csip() != target || (self.ss == frame_ss && self.sp < frame_sp) {
+
+            match self.step(false)? {
+                (StepResult::BreakpointHit, _) => return Ok(StepOverResult::HitBreakpoint),
+                (StepResult::ProgramEnd, _) => return Ok(StepOverResult::Halted),
+                (_, step_cycles) => cycles += step_cycles as u64,
+            }
+
+            if self.halted {
+                return Ok(StepOverResult::Halted)
+            }
+
+            if cycles > STEP_OVER_CYCLE_LIMIT {
+                log::warn!("step_over(): No return to {} after {} cycles.", target, cycles);
+                return Ok(StepOverResult::TimedOut)
+            }
+        }
+
+        Ok(StepOverResult::Completed)
+    }
+
+    /// Capture the call stack depth and return address of the routine currently executing.
+    pub fn return_frame(&self) -> ReturnFrame {
+        ReturnFrame {
+            depth: self.call_stack.len(),
+            return_addr: self.call_stack.back().map(|call| call.return_address()),
+        }
+    }
+
+    /// Returns true if the routine captured in 'frame' has returned to its caller. 
+    /// 
+    /// CS:IP reaching the frame's return address only counts at the frame's own depth, so a 
+    /// nested call returning into the routine does not trip it. If the call stack was empty when 
+    /// the frame was captured there is no return address to watch for, so the first RET, RETF or
+    /// IRET executed with an empty call stack is taken as the return.
+    pub fn has_returned(&self, frame: &ReturnFrame) -> bool {
+        match frame.return_addr {
+            Some(addr) => {
+                self.call_stack.len() < frame.depth 
+                    || (self.call_stack.len() == frame.depth && self.get_linear_ip() == addr)
+            }
+            None => {
+                self.call_stack.is_empty() 
+                    && matches!(self.i.mnemonic, Mnemonic::RETN | Mnemonic::RETF | Mnemonic::IRET)
+            }
+        }
+    }
+
+    /// Run until the current routine returns to its caller ("step out").
+    /// 
+    /// Nested calls made by the routine are run to completion. If the routine has not returned
+    /// after 'max_instructions' instructions, TimedOut is returned. Like step_over(), devices
+    /// are not run between instructions.
+    pub fn run_until_return(&mut self, skip_breakpoint: bool, max_instructions: u64) -> Result<StepOverResult, CpuError> {
+
+        let frame = self.return_frame();
+        let mut skip_breakpoint = skip_breakpoint;
+
+        for _ in 0..max_instructions {
+
+            match self.step(skip_breakpoint)? {
+                (StepResult::BreakpointHit, _) => return Ok(StepOverResult::HitBreakpoint),
+                (StepResult::ProgramEnd, _) => return Ok(StepOverResult::Halted),
+                _ => {}
+            }
+            skip_breakpoint = false;
+
+            if self.halted {
+                return Ok(StepOverResult::Halted)
+            }
+
+            if self.has_returned(&frame) {
+                return Ok(StepOverResult::Completed)
+            }
+        }
+
+        log::warn!("run_until_return(): No return from depth {} after {} instructions.", frame.depth, max_instructions);
+        Ok(StepOverResult::TimedOut)
+    }
+
+    /// Run until 'n' instructions have been retired, or execution stops for another reason.
+    /// 
+    /// REP iterations and interrupt dispatches consume cycles but do not count as instructions;
+    /// a REP-prefixed string instruction counts once, when it completes. Like step_over(), 
+    /// devices are not run between instructions.
+    pub fn run_for_instructions(&mut self, n: u64) -> ExecutionSummary {
+        self.run_budgeted(|summary| summary.instructions >= n)
+    }
+
+    /// Run until at least 'n' cycles have elapsed, or execution stops for another reason.
+    /// 
+    /// The budget is checked between instructions, so the instruction in progress when the 
+    /// budget runs out is always completed and the cycle count may exceed 'n'. A REP-prefixed
+    /// instruction may be stopped between iterations.
+    pub fn run_for_cycles(&mut self, n: u64) -> ExecutionSummary {
+        self.run_budgeted(|summary| summary.cycles >= n)
+    }
+
+    fn run_budgeted<F>(&mut self, budget_exhausted: F) -> ExecutionSummary 
+    where 
+        F: Fn(&ExecutionSummary) -> bool
+    {
+        let mut summary = ExecutionSummary {
+            instructions: 0,
+            cycles: 0,
+            stop_reason: StopReason::BudgetExhausted,
+        };
+
+        while !budget_exhausted(&summary) {
+            match self.step(false) {
+                Ok((step_result, step_cycles)) => {
+                    summary.cycles += step_cycles as u64;
+                    if self.instr_retired {
+                        summary.instructions += 1;
+                    }
+
+                    match step_result {
+                        StepResult::BreakpointHit => {
+                            summary.stop_reason = StopReason::BreakpointHit;
+                            break
+                        }
+                        StepResult::ProgramEnd => {
+                            summary.stop_reason = StopReason::ProgramEnd;
+                            break
+                        }
+                        _ => {}
+                    }
+
+                    if self.halted {
+                        summary.stop_reason = StopReason::Halted;
+                        break
+                    }
+                }
+                Err(e) => {
+                    summary.stop_reason = StopReason::Error(e);
+                    break
+                }
+            }
+        }
+
+        summary
+    }
+
     /// Set a terminating code address for the CPU. This is mostly used in conjunction with the 
     /// CPU validator or running standalone binaries.
     pub fn set_end_address(&mut self, end: usize) {
@@ -2354,43 +2975,205 @@ impl<'a> Cpu<'a> {
     pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
 
         // Clear bus flags for current breakpoints
-        self.breakpoints.iter().for_each(|bp| {
-            match bp {
-                BreakPointType::ExecuteFlat(addr) => {
-                    log::debug!("Clearing breakpoint on execute at address: {:05X}", *addr);
-                    self.bus.clear_flags(*addr as usize, MEM_BPE_BIT );
-                },
-                BreakPointType::MemAccessFlat(addr) => {
-                    self.bus.clear_flags(*addr as usize, MEM_BPA_BIT );
-                }
-                BreakPointType::Interrupt(vector) => {
-                    self.int_flags[*vector as usize] = 0;
-                }
-                _ => {}
+        for bp in &self.breakpoints {
+            if let Some(addr) = Cpu::breakpoint_address(bp) {
+                log::debug!("Clearing breakpoint at address: {:05X}", addr);
+                self.bus.clear_flags(addr as usize, Cpu::breakpoint_flags(bp));
             }
-        });
+        }
 
         // Replace current breakpoint list
         self.breakpoints = bp_list;
 
         // Set bus flags for new breakpoints
-        self.breakpoints.iter().for_each(|bp| {
-            match bp {
-                BreakPointType::ExecuteFlat(addr) => {
-                    log::debug!("Setting breakpoint on execute at address: {:05X}", *addr);
-                    self.bus.set_flags(*addr as usize, MEM_BPE_BIT );
-                },
-                BreakPointType::MemAccessFlat(addr) => {
-                    log::debug!("Setting breakpoint on memory access at address: {:05X}", *addr);
-                    self.bus.set_flags(*addr as usize, MEM_BPA_BIT );
-                }
-                BreakPointType::Interrupt(vector) => {
-                    self.int_flags[*vector as usize] = INTERRUPT_BREAKPOINT;
-                }                
-                _ => {}
+        for bp in &self.breakpoints {
+            if let Some(addr) = Cpu::breakpoint_address(bp) {
+                log::debug!("Setting breakpoint at address: {:05X}", addr);
+                self.bus.set_flags(addr as usize, Cpu::breakpoint_flags(bp));
             }
+        }
+        self.update_interrupt_flags();
+    }
+
+    /// Break when an interrupt with the specified vector is taken, whether by an INT instruction,
+    /// an exception or a hardware interrupt. The CPU stops after the interrupt has been delivered,
+    /// before the first instruction of the handler executes.
+    /// 
+    /// If 'cond' is provided, it is evaluated against the register state as the interrupt is 
+    /// taken, and the breakpoint only trips if it is true, ie, only INT 21h with AH=3Dh.
+    pub fn break_on_interrupt(&mut self, vector: u8, cond: Option<BreakCondition>) {
+        self.interrupt_breakpoints.push((vector, cond));
+        self.update_interrupt_flags();
+    }
+
+    /// Remove all breakpoints added with break_on_interrupt().
+    pub fn clear_interrupt_breakpoints(&mut self) {
+        self.interrupt_breakpoints.clear();
+        self.update_interrupt_flags();
+    }
+
+    /// Rebuild the interrupt breakpoint flags from the breakpoint list and interrupt breakpoints.
+    fn update_interrupt_flags(&mut self) {
+        self.int_flags = vec![0; 256];
+
+        let list_vectors = self.breakpoints.iter().filter_map(|bp| match bp {
+            BreakPointType::Interrupt(vector) => Some(*vector),
+            _ => None
+        });
+        let vectors = list_vectors.chain(self.interrupt_breakpoints.iter().map(|(vector, _)| *vector));
+
+        for vector in vectors {
+            self.int_flags[vector as usize] = INTERRUPT_BREAKPOINT;
+        }
+    }
+
+    /// Set the breakpoint flag if a breakpoint is set on the interrupt being taken. Called as 
+    /// each interrupt is delivered.
+    fn check_interrupt_breakpoint(&mut self, vector: u8) {
+        if self.int_flags[vector as usize] & INTERRUPT_BREAKPOINT == 0 {
+            return
+        }
+
+        let unconditional = self.breakpoints.iter().any(|bp| matches!(bp, BreakPointType::Interrupt(v) if *v == vector));
+        let tripped = unconditional || self.interrupt_breakpoints.iter().any(|(v, cond)| {
+            *v == vector && cond.as_ref().map_or(true, |c| self.eval_condition(c))
+        });
+
+        if tripped {
+            log::debug!("Interrupt breakpoint hit: INT {:02X}", vector);
+            self.set_breakpoint_flag();
+        }
+    }
+
+    /// Add a breakpoint of the specified kind on the linear address 'addr'.
+    /// 
+    /// Execute breakpoints trip before the instruction at 'addr' is executed. Read and write 
+    /// breakpoints trip on a matching memory bus cycle, and are reported once the instruction 
+    /// that performed the access has completed.
+    pub fn add_breakpoint(&mut self, addr: u32, kind: BreakKind) {
+        let addr = addr & 0xFFFFF;
+        let bp = match kind {
+            BreakKind::Execute => BreakPointType::ExecuteFlat(addr),
+            BreakKind::Read => BreakPointType::MemReadFlat(addr),
+            BreakKind::Write => BreakPointType::MemWriteFlat(addr),
+        };
+        self.bus.set_flags(addr as usize, Cpu::breakpoint_flags(&bp));
+        self.breakpoints.push(bp);
+    }
+
+    /// Remove a breakpoint added with add_breakpoint(). Flags for any other breakpoints 
+    /// on the same address are preserved. Returns false if no such breakpoint was set.
+    pub fn remove_breakpoint(&mut self, addr: u32, kind: BreakKind) -> bool {
+        let addr = addr & 0xFFFFF;
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| {
+            !matches!(
+                (bp, kind), 
+                (BreakPointType::ExecuteFlat(a), BreakKind::Execute) 
+                | (BreakPointType::MemReadFlat(a), BreakKind::Read) 
+                | (BreakPointType::MemWriteFlat(a), BreakKind::Write) if *a == addr
+            )
         });
 
+        if self.breakpoints.len() == len {
+            return false
+        }
+
+        self.bus.clear_flags(addr as usize, MEM_BPE_BIT | MEM_BPA_BIT | MEM_BPW_BIT);
+        let flags = self.breakpoints.iter()
+            .filter(|bp| Cpu::breakpoint_address(bp) == Some(addr))
+            .fold(0, |acc, bp| acc | Cpu::breakpoint_flags(bp));
+        self.bus.set_flags(addr as usize, flags);
+        true
+    }
+
+    /// Add a breakpoint that trips at an instruction boundary when 'cond' becomes true.
+    /// 
+    /// Conditions are edge-triggered: a condition that is already true when added, or that 
+    /// remains true after it trips, does not trip again until it has become false.
+    pub fn add_conditional_breakpoint(&mut self, cond: BreakCondition) {
+        let state = self.eval_condition(&cond);
+        self.conditional_breakpoints.push((cond, state));
+    }
+
+    /// Remove all conditional breakpoints.
+    pub fn clear_conditional_breakpoints(&mut self) {
+        self.conditional_breakpoints.clear();
+    }
+
+    /// Evaluate a breakpoint condition against the current CPU state.
+    pub fn eval_condition(&self, cond: &BreakCondition) -> bool {
+        match cond {
+            BreakCondition::Reg8(reg, op, value) => op.compare(self.get_register8(*reg) as u16, *value as u16),
+            BreakCondition::Reg16(reg, op, value) => op.compare(self.get_register16(*reg), *value),
+            BreakCondition::Flag(flag, state) => self.get_flag(*flag) == *state,
+            BreakCondition::And(a, b) => self.eval_condition(a) && self.eval_condition(b),
+            BreakCondition::Or(a, b) => self.eval_condition(a) || self.eval_condition(b),
+        }
+    }
+
+    /// Update the state of each conditional breakpoint. Returns true if any condition 
+    /// has become true since it was last evaluated.
+    fn check_conditional_breakpoints(&mut self) -> bool {
+        if self.conditional_breakpoints.is_empty() {
+            return false
+        }
+
+        let mut conditions = std::mem::take(&mut self.conditional_breakpoints);
+        let mut tripped = false;
+        for (cond, last_state) in conditions.iter_mut() {
+            let state = self.eval_condition(cond);
+            if state && !*last_state {
+                log::debug!("Condition tripped: {:?}", cond);
+                tripped = true;
+            }
+            *last_state = state;
+        }
+        self.conditional_breakpoints = conditions;
+        tripped
+    }
+
+    /// Return the address and kind of the last breakpoint hit, if the CPU is in the 
+    /// BreakpointHit state due to an execute, read or write breakpoint.
+    pub fn last_breakpoint(&self) -> Option<(u32, BreakKind)> {
+        self.last_breakpoint
+    }
+
+    /// Record a tripped read or write breakpoint. Called by the BIU at the start of a 
+    /// memory bus cycle.
+    fn check_access_breakpoint(&mut self, bus_status: BusStatus, address: u32) {
+        let kind = match bus_status {
+            BusStatus::MemRead if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 => BreakKind::Read,
+            BusStatus::MemWrite if self.bus.get_flags(address as usize) & MEM_BPW_BIT != 0 => BreakKind::Write,
+            _ => return
+        };
+
+        // Only the first access in an instruction is reported.
+        if self.access_breakpoint.is_none() {
+            self.access_breakpoint = Some((address, kind));
+            self.last_breakpoint = Some((address, kind));
+        }
+        self.state = CpuState::BreakpointHit;
+    }
+
+    fn breakpoint_flags(bp: &BreakPointType) -> u8 {
+        match bp {
+            BreakPointType::ExecuteFlat(_) => MEM_BPE_BIT,
+            BreakPointType::MemAccessFlat(_) => MEM_BPA_BIT | MEM_BPW_BIT,
+            BreakPointType::MemReadFlat(_) => MEM_BPA_BIT,
+            BreakPointType::MemWriteFlat(_) => MEM_BPW_BIT,
+            _ => 0
+        }
+    }
+
+    fn breakpoint_address(bp: &BreakPointType) -> Option<u32> {
+        match bp {
+            BreakPointType::ExecuteFlat(a) 
+            | BreakPointType::MemAccessFlat(a) 
+            | BreakPointType::MemReadFlat(a) 
+            | BreakPointType::MemWriteFlat(a) => Some(*a),
+            _ => None
+        }
     }
 
     pub fn get_breakpoint_flag(&self) -> bool {
@@ -2408,6 +3191,8 @@ impl<'a> Cpu<'a> {
 
     pub fn clear_breakpoint_flag(&mut self) {
         self.state = CpuState::Normal;
+        self.access_breakpoint = None;
+        self.last_breakpoint = None;
     }
 
     pub fn dump_instruction_history_string(&self) -> String {
@@ -2440,9 +3225,39 @@ impl<'a> Cpu<'a> {
         history_vec
     }    
 
+    /// Return the contents of the prefetch queue as a vector of SyntaxTokens. Each queued byte
+    /// is returned as a MemoryByteHexValue token addressed by the linear address it was fetched
+    /// from, followed by StateString tokens for the queue length and the next fetch address.
+    /// Flushing the queue clears the byte tokens.
+    pub fn queue_tokens(&self) -> Vec<SyntaxToken> {
+
+        let mut token_vec = Vec::new();
+        let base = self.pc.wrapping_sub(self.queue.len() as u32);
+
+        for (i, byte) in self.queue.iter().enumerate() {
+            token_vec.push(
+                SyntaxToken::MemoryByteHexValue(
+                    base.wrapping_add(i as u32) & 0xFFFFF,
+                    byte,
+                    format!("{:02X}", byte),
+                    false,
+                    0
+                )
+            );
+        }
+
+        token_vec.push(SyntaxToken::StateString(format!("{}", self.queue.len()), false, 0));
+        token_vec.push(SyntaxToken::StateString(format!("{:05X}", self.pc), false, 0));
+        token_vec
+    }
+
     pub fn dump_call_stack(&self) -> String {
         let mut call_stack_string = String::new();
 
+        if self.call_stack_overflow {
+            call_stack_string.push_str(&format!("Call stack exceeded {} entries, oldest entries dropped.\n", CPU_CALL_STACK_LEN));
+        }
+
         for call in &self.call_stack {
             match call {
                 CallStackEntry::Call{ ret_cs, ret_ip, call_ip } => {
@@ -2710,7 +3525,7 @@ impl<'a> Cpu<'a> {
         instr_str.push_str(&format!("AX: {:04x} BX: {:04x} CX: {:04x} DX: {:04x}\n", self.ax, self.bx, self.cx, self.dx));
         instr_str.push_str(&format!("SP: {:04x} BP: {:04x} SI: {:04x} DI: {:04x}\n", self.sp, self.bp, self.si, self.di));
         instr_str.push_str(&format!("CS: {:04x} DS: {:04x} ES: {:04x} SS: {:04x}\n", self.cs, self.ds, self.es, self.ss));
-        instr_str.push_str(&format!("IP: {:04x} FLAGS: {:04x}", self.ip, self.flags));
+        instr_str.push_str(&format!("IP: {:04x} FLAGS: {:04x} {}", self.ip, self.flags, flags_string(self.flags)));
 
         instr_str
     }
@@ -2727,6 +3542,11 @@ impl<'a> Cpu<'a> {
         if let Some(w) = self.trace_writer.as_mut() {
             w.flush().unwrap();
         }
+        if let Some(w) = self.trace_record_writer.as_mut() {
+            if let Err(e) = w.flush() {
+                log::error!("Failed to flush trace records: {}", e);
+            }
+        }
 
         #[cfg(feature = "cpu_validator")]
         {
@@ -2736,6 +3556,122 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    /// Install a callback to choose the action taken when off rails detection trips. The 
+    /// callback receives the address of the instruction and the number of consecutive 0x00
+    /// opcodes executed. Without a callback, the CPU halts.
+    pub fn set_runaway_callback(&mut self, callback: Box<dyn FnMut(u32, u32) -> RunawayAction + 'a>) {
+        self.runaway_callback = Some(callback);
+    }
+
+    pub fn clear_runaway_callback(&mut self) {
+        self.runaway_callback = None;
+    }
+
+    /// Install a callback to choose the action taken when jump sanity checking finds a jump 
+    /// into unmapped or unwritten memory. The callback receives the address of the jump 
+    /// instruction and the destination address. Without a callback, the CPU breaks.
+    pub fn set_bad_jump_callback(&mut self, callback: Box<dyn FnMut(u32, u32) -> RunawayAction + 'a>) {
+        self.bad_jump_callback = Some(callback);
+    }
+
+    pub fn clear_bad_jump_callback(&mut self) {
+        self.bad_jump_callback = None;
+    }
+
+    /// Install a callback to receive a TraceRecord for each instruction as it retires.
+    pub fn set_trace_callback(&mut self, callback: Box<dyn FnMut(&TraceRecord) + 'a>) {
+        self.trace_callback = Some(callback);
+    }
+
+    /// Install a TraceRecordWriter to write a line for each instruction as it retires. The 
+    /// writer is flushed by trace_flush(), and flushed and removed by clear_trace_callback().
+    pub fn set_trace_record_writer(&mut self, writer: TraceRecordWriter<Box<dyn Write + 'a>>) {
+        self.trace_record_writer = Some(writer);
+    }
+
+    /// Remove the trace callback and any TraceRecordWriter, flushing the writer first.
+    pub fn clear_trace_callback(&mut self) {
+        self.trace_callback = None;
+        self.trace_record = None;
+        if let Some(mut w) = self.trace_record_writer.take() {
+            if let Err(e) = w.flush() {
+                log::error!("Failed to flush trace records: {}", e);
+            }
+        }
+    }
+
+    #[inline]
+    fn trace_records_wanted(&self) -> bool {
+        self.trace_callback.is_some() || self.trace_record_writer.is_some()
+    }
+
+    /// Install a callback that is called as each instruction retires, with the address of the
+    /// instruction, its opcode and the number of cycles it took. This is intended for building
+    /// profilers. A REP instruction that is suspended by an interrupt is reported with the 
+    /// cycles spent until it was suspended, and again when it resumes.
+    pub fn set_retire_callback(&mut self, callback: Box<dyn FnMut(u32, u8, u32) + 'a>) {
+        self.retire_callback = Some(callback);
+        self.retire_cycles = 0;
+    }
+
+    pub fn clear_retire_callback(&mut self) {
+        self.retire_callback = None;
+    }
+
+    /// Record a completed bus transfer in the trace record for the current instruction.
+    #[inline]
+    pub fn trace_bus_cycle(&mut self) {
+        if let Some(record) = &mut self.trace_record {
+            record.bus_cycles.push(TraceBusCycle {
+                cycle: record.cycles + self.instr_cycle,
+                status: self.bus_status,
+                address: self.address_bus,
+                data: self.data_bus,
+                size: self.transfer_size,
+                wait_states: self.bus_wait_states,
+            });
+        }
+    }
+
+    /// Complete the trace record for the instruction that just retired and pass it to the
+    /// trace callback.
+    fn emit_trace_record(&mut self) {
+        if let Some(mut record) = self.trace_record.take() {
+            record.bytes = (0..self.i.size)
+                .map(|n| self.bus.get_slice_at((record.address + n) as usize & 0xFFFFF, 1)[0])
+                .collect();
+            record.disassembly = format!("{}", self.i);
+            record.tokens = self.i.tokenize();
+            record.microcode_start = self.microcode_address(&self.i);
+
+            self.dispatch_trace_record(&record);
+        }
+    }
+
+    /// Complete the trace record for a hardware interrupt taken between instructions and pass
+    /// it to the trace callback. The record has no instruction bytes, and its disassembly
+    /// names the vector taken.
+    fn emit_interrupt_trace_record(&mut self, vector: u8) {
+        if let Some(mut record) = self.trace_record.take() {
+            record.cycles = self.instr_cycle;
+            record.disassembly = format!("INTR {:02X}", vector);
+
+            self.dispatch_trace_record(&record);
+        }
+    }
+
+    /// Pass a completed trace record to the trace callback and TraceRecordWriter.
+    fn dispatch_trace_record(&mut self, record: &TraceRecord) {
+        if let Some(callback) = &mut self.trace_callback {
+            callback(record);
+        }
+        if let Some(w) = &mut self.trace_record_writer {
+            if let Err(e) = w.write(record) {
+                log::error!("Failed to write trace record: {}", e);
+            }
+        }
+    }
+
     #[inline]
     pub fn trace_comment(&mut self, comment: &'static str) {
         if self.trace_enabled {
@@ -2788,6 +3724,70 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    /// Write a disassembly listing of the current code segment to 'cs.asm' in the specified
+    /// directory. The listing is decoded linearly from cs:0000, so data within the segment is 
+    /// disassembled as well.
+    pub fn dump_cs_listing(&mut self, path: &Path) {
+
+        let mut filename = path.to_path_buf();
+        filename.push("cs.asm");
+
+        let cs = self.cs;
+        let start = Cpu::calc_linear_address(cs, 0);
+        log::debug!("Writing listing of segment {:04X} at address {:05X}", cs, start);
+
+        let mut listing = String::new();
+        for (addr, result) in self.disassemble_range(start, start + 0x10000) {
+            let text = match &result.instruction {
+                Some(i) => i.to_string(),
+                None => "???".to_string()
+            };
+            listing.push_str(&format!(
+                "{:04X}:{:04X} {:012} {}\n", 
+                cs, 
+                addr - start, 
+                util::fmt_byte_array(&result.bytes), 
+                text
+            ));
+        }
+
+        match std::fs::write(filename.clone(), listing) {
+            Ok(_) => {
+                log::debug!("Wrote listing: {}", filename.display())
+            }
+            Err(e) => {
+                log::error!("Failed to write listing '{}': {}", filename.display(), e)
+            }
+        }
+    }
+
+    /// Return the instruction coverage recorded so far.
+    #[cfg(feature = "cpu_coverage")]
+    pub fn coverage(&self) -> &CoverageMap {
+        &self.coverage
+    }
+
+    #[cfg(feature = "cpu_coverage")]
+    pub fn coverage_report(&self) -> String {
+        self.coverage.report(&CoverageMap::decodable(self.cpu_type))
+    }
+
+    #[cfg(feature = "cpu_coverage")]
+    pub fn dump_coverage(&self, path: &Path) {
+
+        let mut filename = path.to_path_buf();
+        filename.push("coverage.txt");
+
+        match std::fs::write(filename.clone(), self.coverage_report()) {
+            Ok(_) => {
+                log::debug!("Wrote coverage report: {}", filename.display())
+            }
+            Err(e) => {
+                log::error!("Failed to write coverage report '{}': {}", filename.display(), e)
+            }
+        }
+    }
+
     pub fn get_service_event(&mut self) -> Option<ServiceEvent> {
         self.service_events.pop_front()
     }
@@ -2822,10 +3822,31 @@ impl<'a> Cpu<'a> {
                 log::debug!("Setting OffRailsDetection to: {:?}", state);
                 self.off_rails_detection = state;
             }
+            CpuOption::OffRailsThreshold(threshold) => {
+                log::debug!("Setting OffRailsThreshold to: {}", threshold);
+                self.off_rails_threshold = threshold;
+            }
             CpuOption::EnableWaitStates(state) => {
                 log::debug!("Setting EnableWaitStates to: {:?}", state);
                 self.enable_wait_states = state;
             }   
+            CpuOption::FpuPresent(state) => {
+                log::debug!("Setting FpuPresent to: {:?}", state);
+                self.fpu_present = state;
+            }
+            CpuOption::RepPrefixBug(state) => {
+                log::debug!("Setting RepPrefixBug to: {:?}", state);
+                self.rep_prefix_bug = state;
+            }
+            CpuOption::StrictUndefinedFlags(state) => {
+                log::debug!("Setting StrictUndefinedFlags to: {:?}", state);
+                // Takes effect in the validator on the next reset
+                self.strict_undefined_flags = state;
+            }
+            CpuOption::JumpSanity(state) => {
+                log::debug!("Setting JumpSanity to: {:?}", state);
+                self.jump_sanity = state;
+            }
             CpuOption::TraceLoggingEnabled(state) => {
                 log::debug!("Setting {:?} to: {:?}", opt, state);
                 self.trace_enabled = state;
@@ -2856,9 +3877,24 @@ impl<'a> Cpu<'a> {
             CpuOption::OffRailsDetection(_) => {
                 self.off_rails_detection
             }
+            CpuOption::OffRailsThreshold(..) => {
+                true
+            }
             CpuOption::EnableWaitStates(_) => {
                 self.enable_wait_states
             }   
+            CpuOption::FpuPresent(_) => {
+                self.fpu_present
+            }
+            CpuOption::RepPrefixBug(_) => {
+                self.rep_prefix_bug
+            }
+            CpuOption::StrictUndefinedFlags(_) => {
+                self.strict_undefined_flags
+            }
+            CpuOption::JumpSanity(_) => {
+                self.jump_sanity
+            }
             CpuOption::TraceLoggingEnabled(_) => {
                 self.trace_enabled
             }                       
@@ -2876,4 +3912,2683 @@ impl<'a> Cpu<'a> {
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn test_cpu<'a>() -> Cpu<'a> {
+        test_cpu_type(CpuType::Intel8088)
+    }
+
+    fn test_cpu_type<'a>(cpu_type: CpuType) -> Cpu<'a> {
+        Cpu::new(
+            cpu_type,
+            TraceMode::None,
+            None::<std::io::Sink>,
+            #[cfg(feature = "cpu_validator")]
+            ValidatorType::None,
+            #[cfg(feature = "cpu_validator")]
+            TraceLogger::None,
+        )
+    }
+
+    /// Reset the CPU to 1000:0000 and load 'code' there.
+    pub(crate) fn load_code(cpu: &mut Cpu, code: &[u8]) {
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+        cpu.bus_mut().debug_write(0x10000, code).unwrap();
+    }
+
+    /// Create a CPU with 'options' set and 'code' loaded at 1000:0000, then set the registers
+    /// in 'regs'.
+    pub(crate) fn setup_cpu<'a>(
+        code: &[u8],
+        options: impl IntoIterator<Item = CpuOption>,
+        regs: &[(Register16, u16)]
+    ) -> Cpu<'a> {
+        setup_cpu_type(CpuType::Intel8088, code, options, regs)
+    }
+
+    fn setup_cpu_type<'a>(
+        cpu_type: CpuType,
+        code: &[u8],
+        options: impl IntoIterator<Item = CpuOption>,
+        regs: &[(Register16, u16)]
+    ) -> Cpu<'a> {
+        let mut cpu = test_cpu_type(cpu_type);
+        for option in options {
+            cpu.set_option(option);
+        }
+        load_code(&mut cpu, code);
+        for (reg, value) in regs {
+            cpu.set_register16(*reg, *value);
+        }
+        cpu
+    }
+
+    /// Point interrupt 'vector' at segment:offset.
+    pub(crate) fn set_ivt_entry(cpu: &mut Cpu, vector: u8, segment: u16, offset: u16) {
+        let [off_lo, off_hi] = offset.to_le_bytes();
+        let [seg_lo, seg_hi] = segment.to_le_bytes();
+        cpu.bus_mut().debug_write(vector as usize * 4, &[off_lo, off_hi, seg_lo, seg_hi]).unwrap();
+    }
+
+    /// Step 'n' instructions, returning the cycles taken by each.
+    fn step_cycles(cpu: &mut Cpu, n: usize) -> Vec<u32> {
+        (0..n).map(|_| cpu.step(false).unwrap().1).collect()
+    }
+
+    #[test]
+    fn test_reset_queue_state() {
+
+        let cpu = test_cpu();
+
+        // Queue should be empty with the first fetch from the reset vector about to begin.
+        assert_eq!(cpu.queue.len(), 0);
+        assert!(!cpu.queue.has_preload());
+        assert_eq!(cpu.last_queue_op, QueueOp::Idle);
+        assert!(!cpu.nx);
+        assert!(!cpu.rni);
+        assert_eq!(cpu.biu_state, BiuState::Operating);
+        assert_eq!(cpu.fetch_state, FetchState::InProgress);
+        assert_eq!(cpu.bus_status, BusStatus::CodeFetch);
+        assert_eq!(cpu.t_cycle, TCycle::T1);
+        assert_eq!(cpu.address_bus, 0xFFFF0);
+    }
+
+    #[test]
+    fn test_reset_first_instruction_cycles() {
+
+        let mut cpu = test_cpu();
+
+        for addr in 0xFFFF0..0xFFFF8 {
+            cpu.bus_mut().write_u8(addr, 0x90, 0).unwrap();
+        }
+
+        // The first NOP waits for its opcode byte to be fetched (4 cycles), takes its first
+        // cycle and two EU cycles, and then waits for the next opcode to arrive in the queue.
+        let (_, first_cycles) = cpu.step(false).unwrap();
+        assert_eq!(first_cycles, 9);
+
+        // Subsequent NOPs are limited by the 4 cycle byte fetch rate of the 8088.
+        let (_, second_cycles) = cpu.step(false).unwrap();
+        assert_eq!(second_cycles, 4);
+
+        // Resetting again must return to the same initial state.
+        cpu.reset();
+        let (_, reset_cycles) = cpu.step(false).unwrap();
+        assert_eq!(reset_cycles, first_cycles);
+    }
+
+    #[test]
+    fn test_irq_before_prefixed_instruction() {
+
+        let mut cpu = test_cpu();
+
+        // nop ; mov al, cs:[bx]
+        let code = [0x90, 0x2E, 0x8A, 0x07];
+        load_code(&mut cpu, &code);
+        cpu.bus_mut().write_u8(0x10100, 0x5A, 0).unwrap();
+
+        // IVT entry for vector 8 points to an iret at 0000:0500
+        cpu.bus_mut().write_u16(8 * 4, 0x0500, 0).unwrap();
+        cpu.bus_mut().write_u16(8 * 4 + 2, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x00500, 0xCF, 0).unwrap();
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+        cpu.set_register16(Register16::BX, 0x0100);
+
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0001);
+
+        // An IRQ recognized after the nop, before the prefix of the next instruction has 
+        // been executed. The return address must point at the prefix byte, not the opcode.
+        cpu.hw_interrupt(8);
+        assert_eq!(cpu.get_register16(Register16::CS), 0x0000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0500);
+
+        let sp = cpu.get_register16(Register16::SP) as usize;
+        let (ret_ip, _) = cpu.bus_mut().read_u16(sp, 0).unwrap();
+        let (ret_cs, _) = cpu.bus_mut().read_u16(sp + 2, 0).unwrap();
+        assert_eq!((ret_cs, ret_ip), (0x1000, 0x0001));
 
+        // Return from the handler and re-execute the entire prefixed instruction, including
+        // its segment override.
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::CS), 0x1000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0001);
+
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register8(Register8::AL), 0x5A);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0004);
+    }
+
+    #[test]
+    fn test_salc() {
+
+        let mut cpu = test_cpu();
+
+        // salc ; salc
+        cpu.bus_mut().write_u8(0xFFFF0, 0xD6, 0).unwrap();
+        cpu.bus_mut().write_u8(0xFFFF1, 0xD6, 0).unwrap();
+
+        cpu.set_flag(Flag::Carry);
+        let flags = cpu.flags;
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register8(Register8::AL), 0xFF);
+        assert_eq!(cpu.flags, flags);
+
+        cpu.clear_flag(Flag::Carry);
+        let flags = cpu.flags;
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register8(Register8::AL), 0x00);
+        assert_eq!(cpu.flags, flags);
+    }
+
+    #[test]
+    fn test_186_decode_selects_model() {
+
+        let mut cpu = test_cpu();
+
+        // 0x60 is an alias for JO on the 8088, and PUSHA on the 80186
+        cpu.bus_mut().write_u8(0x1000, 0x60, 0).unwrap();
+        cpu.bus_mut().write_u8(0x1001, 0x05, 0).unwrap();
+
+        cpu.bus_mut().seek(0x1000);
+        let i = Cpu::decode(cpu.bus_mut(), CpuType::Intel8088).unwrap();
+        assert_eq!(i.mnemonic, Mnemonic::JO);
+        assert_eq!(i.size, 2);
+
+        cpu.bus_mut().seek(0x1000);
+        let i = Cpu::decode(cpu.bus_mut(), CpuType::Intel80188).unwrap();
+        assert_eq!(i.mnemonic, Mnemonic::PUSHA);
+        assert_eq!(i.size, 1);
+    }
+
+    #[test]
+    fn test_186_instructions() {
+
+        let mut cpu = test_cpu_type(CpuType::Intel80188);
+
+        let code = [
+            0xBB, 0x07, 0x00,   // mov bx, 7
+            0x6B, 0xC3, 0xFD,   // imul ax, bx, -3
+            0xC1, 0xE3, 0x04,   // shl bx, 4
+            0x6A, 0xFE,         // push -2
+            0x60,               // pusha
+            0x61,               // popa
+        ];
+        for (i, byte) in code.iter().enumerate() {
+            cpu.bus_mut().write_u8(0xFFFF0 + i, *byte, 0).unwrap();
+        }
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+        cpu.set_register16(Register16::DX, 0x1234);
+
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::AX), 0xFFEB);
+        assert!(!cpu.get_flag(Flag::Carry));
+        assert!(!cpu.get_flag(Flag::Overflow));
+
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::BX), 0x0070);
+
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::SP), 0x03FE);
+        assert_eq!(cpu.bus_mut().read_u16(0x3FE, 0).unwrap().0, 0xFFFE);
+
+        // PUSHA pushes the original value of SP
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::SP), 0x03EE);
+        assert_eq!(cpu.bus_mut().read_u16(0x3FC, 0).unwrap().0, 0xFFEB);
+        assert_eq!(cpu.bus_mut().read_u16(0x3F8, 0).unwrap().0, 0x1234);
+        assert_eq!(cpu.bus_mut().read_u16(0x3F4, 0).unwrap().0, 0x03FE);
+
+        cpu.set_register16(Register16::DX, 0);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::SP), 0x03FE);
+        assert_eq!(cpu.get_register16(Register16::DX), 0x1234);
+        assert_eq!(cpu.get_register16(Register16::AX), 0xFFEB);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x000D);
+    }
+
+    #[test]
+    fn test_186_invalid_opcode_and_insw() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // 63h is JNB on the 8088, and an undefined opcode on the 80186
+        let mut cpu = test_cpu_type(CpuType::Intel80188);
+        cpu.bus_mut().write_u8(0x1000, 0x63, 0).unwrap();
+        cpu.bus_mut().seek(0x1000);
+        assert_eq!(Cpu::decode(cpu.bus_mut(), CpuType::Intel8088).unwrap().mnemonic, Mnemonic::JNB);
+        cpu.bus_mut().seek(0x1000);
+        assert_eq!(Cpu::decode(cpu.bus_mut(), CpuType::Intel80188).unwrap().mnemonic, Mnemonic::InvalidOpcode);
+
+        let records: Rc<RefCell<Vec<TraceRecord>>> = Rc::new(RefCell::new(Vec::new()));
+
+        // insw ; 63h
+        load_code(&mut cpu, &[0x6D, 0x63]);
+        cpu.bus_mut().write_u16(6 * 4, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u16(6 * 4 + 2, 0x2000, 0).unwrap();
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+        cpu.set_register16(Register16::ES, 0x3000);
+        cpu.set_register16(Register16::DI, 0x0000);
+        cpu.set_register16(Register16::DX, 0x0300);
+
+        let records_cb = records.clone();
+        cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+            records_cb.borrow_mut().push(record.clone());
+        }));
+        cpu.step(false).unwrap();
+        cpu.clear_trace_callback();
+
+        // INSW reads a word from two consecutive ports. Unmapped ports read as FFh.
+        let io_reads: Vec<u32> = records.borrow()[0].bus_cycles.iter()
+            .filter(|c| matches!(c.status, BusStatus::IoRead))
+            .map(|c| c.address)
+            .collect();
+        assert_eq!(io_reads, vec![0x0300, 0x0301]);
+        assert_eq!(cpu.bus_mut().read_u16(0x30000, 0).unwrap().0, 0xFFFF);
+        assert_eq!(cpu.get_register16(Register16::DI), 2);
+
+        // INT 6 is a fault, returning to the undefined opcode
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::CS), 0x2000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0000);
+        assert_eq!(cpu.bus_mut().read_u16(0x3FA, 0).unwrap().0, 0x0001);
+    }
+
+    /// Run 'prefix rep movsb' copying four bytes, taking an interrupt during the second iteration.
+    /// Returns the IP pushed by the interrupt and the bytes copied to es:0100.
+    fn run_interrupted_rep_movsb(prefix: u8, rep_prefix_bug: bool) -> (u16, Vec<u8>) {
+
+        let mut cpu = test_cpu();
+        cpu.set_option(CpuOption::RepPrefixBug(rep_prefix_bug));
+
+        // prefix ; rep movsb
+        let code = [prefix, 0xF3, 0xA4];
+        load_code(&mut cpu, &code);
+        for i in 0..4 {
+            cpu.bus_mut().write_u8(0x20000 + i, 0xD1 + i as u8, 0).unwrap();
+            cpu.bus_mut().write_u8(0x30000 + i, 0xE1 + i as u8, 0).unwrap();
+        }
+
+        // IVT entry for vector 8 points to an iret at 0000:0500
+        cpu.bus_mut().write_u16(8 * 4, 0x0500, 0).unwrap();
+        cpu.bus_mut().write_u16(8 * 4 + 2, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x00500, 0xCF, 0).unwrap();
+
+        let mut pic = Pic::new();
+        pic.handle_data_register_write(0x00);
+        *cpu.bus_mut().pic_mut() = Some(pic);
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+        cpu.set_register16(Register16::DS, 0x2000);
+        cpu.set_register16(Register16::ES, 0x3000);
+        cpu.set_register16(Register16::SI, 0x0000);
+        cpu.set_register16(Register16::DI, 0x0100);
+        cpu.set_register16(Register16::CX, 4);
+        cpu.set_flag(Flag::Interrupt);
+
+        cpu.step(false).unwrap();
+        assert!(cpu.in_rep());
+
+        // The interrupt is taken after the current iteration completes
+        cpu.bus_mut().pic_mut().as_mut().unwrap().request_interrupt(0);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::CS), 0x0000);
+        assert_eq!(cpu.get_register16(Register16::CX), 2);
+
+        let sp = cpu.get_register16(Register16::SP) as usize;
+        let (ret_ip, _) = cpu.bus_mut().read_u16(sp, 0).unwrap();
+
+        // Return from the handler and finish the string operation
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::IP), ret_ip);
+        for _ in 0..2 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.get_register16(Register16::CX), 0);
+
+        let copied = (0..4).map(|i| cpu.bus_mut().read_u8(0x30100 + i, 0).unwrap().0).collect();
+        (ret_ip, copied)
+    }
+
+    #[test]
+    fn test_rep_override_interrupt_restart() {
+
+        // The 8088 resumes from the last prefix, so a ds override preceding rep is dropped. 
+        // The source segment is ds regardless.
+        let (ret_ip, copied) = run_interrupted_rep_movsb(0x3E, true);
+        assert_eq!(ret_ip, 0x0001);
+        assert_eq!(copied, vec![0xD1, 0xD2, 0xD3, 0xD4]);
+
+        // An es override is lost on resume, and the remaining bytes are copied from ds.
+        let (ret_ip, copied) = run_interrupted_rep_movsb(0x26, true);
+        assert_eq!(ret_ip, 0x0001);
+        assert_eq!(copied, vec![0xE1, 0xE2, 0xD3, 0xD4]);
+
+        // With the bug disabled the whole instruction restarts, keeping the override.
+        let (ret_ip, copied) = run_interrupted_rep_movsb(0x26, false);
+        assert_eq!(ret_ip, 0x0000);
+        assert_eq!(copied, vec![0xE1, 0xE2, 0xE3, 0xE4]);
+    }
+
+    #[test]
+    fn test_divide_error_return_address() {
+
+        // div bl ; rep div bl
+        for (code, next_ip) in [(vec![0xF6, 0xF3], 0x0002), (vec![0xF3, 0xF6, 0xF3], 0x0003)] {
+
+            let mut cpu = test_cpu();
+
+            load_code(&mut cpu, &code);
+
+            // IVT entry for vector 0 points to 0000:0500
+            cpu.bus_mut().write_u16(0, 0x0500, 0).unwrap();
+            cpu.bus_mut().write_u16(2, 0x0000, 0).unwrap();
+
+            cpu.set_register16(Register16::SS, 0x0000);
+            cpu.set_register16(Register16::SP, 0x0400);
+            cpu.set_register16(Register16::AX, 0x1234);
+            cpu.set_register8(Register8::BL, 0);
+            cpu.set_flag(Flag::Interrupt);
+
+            cpu.step(false).unwrap();
+            assert_eq!(cpu.get_register16(Register16::CS), 0x0000);
+            assert_eq!(cpu.get_register16(Register16::IP), 0x0500);
+            assert!(!cpu.in_rep());
+            assert!(!cpu.get_flag(Flag::Interrupt));
+
+            // The 8088 pushes the address of the instruction following the divide
+            let sp = cpu.get_register16(Register16::SP) as usize;
+            assert_eq!(sp, 0x03FA);
+            let (ret_ip, _) = cpu.bus_mut().read_u16(sp, 0).unwrap();
+            let (ret_cs, _) = cpu.bus_mut().read_u16(sp + 2, 0).unwrap();
+            let (ret_flags, _) = cpu.bus_mut().read_u16(sp + 4, 0).unwrap();
+            assert_eq!((ret_cs, ret_ip), (0x1000, next_ip));
+            assert_eq!(ret_flags, cpu.flags | CPU_FLAG_INT_ENABLE);
+        }
+    }
+
+    #[test]
+    fn test_save_restore_state() {
+
+        let mut cpu = test_cpu();
+
+        let code = [
+            0xB9, 0x05, 0x00,   // mov cx, 5
+            0x01, 0xC8,         // add ax, cx
+            0xE2, 0xFC,         // loop -4
+            0xB9, 0x04, 0x00,   // mov cx, 4
+            0xF3, 0xAC,         // rep lodsb
+            0x90, 0x90, 0x90,   // nop
+        ];
+        load_code(&mut cpu, &code);
+
+        // Stop in the middle of the rep lodsb
+        for _ in 0..14 {
+            cpu.step(false).unwrap();
+        }
+        assert!(cpu.in_rep());
+
+        fn run_trace(cpu: &mut Cpu) -> Vec<(u64, u16, u16, u16, u16, usize, u32)> {
+            (0..6).map(|_| {
+                cpu.step(false).unwrap();
+                (cpu.cycle_num, cpu.ip, cpu.ax, cpu.cx, cpu.si, cpu.queue.len(), cpu.pc)
+            }).collect()
+        }
+
+        let snapshot = cpu.save_state();
+        let trace = run_trace(&mut cpu);
+
+        cpu.restore_state(&snapshot);
+        assert!(cpu.in_rep());
+        assert_eq!(run_trace(&mut cpu), trace);
+
+        // Memory is saved and restored separately from the CPU
+        let image = cpu.bus().get_slice_at(0, cpu.bus().size()).to_vec();
+        cpu.bus_mut().write_u8(0x10000, 0xF4, 0).unwrap();
+        cpu.bus_mut().restore_memory(&image);
+        assert_eq!(cpu.bus().get_slice_at(0x10000, 1), &code[..1]);
+    }
+
+    #[test]
+    fn test_disassemble_at() {
+        let mut cpu = test_cpu();
+
+        // mov ax, 1234h
+        load_code(&mut cpu, &[0xB8, 0x34, 0x12]);
+        cpu.bus_mut().debug_write(0x2FFFF, &[0xB8, 0x34, 0x12]).unwrap();
+        cpu.bus_mut().write_u8(0xFFFFF, 0xB8, 0).unwrap();
+
+        let cycle_num = cpu.cycle_num;
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 0));
+        assert!(result.instruction.is_some());
+        assert_eq!(result.size, 3);
+        assert_eq!(result.bytes, vec![0xB8, 0x34, 0x12]);
+        assert!(matches!(result.tokens[0], SyntaxToken::Mnemonic(ref m) if m == "mov"));
+        assert_eq!(cpu.cycle_num, cycle_num);
+        assert_eq!(cpu.ip, 0);
+
+        // Instruction crossing the end of its segment
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x2000, 0xFFFF));
+        assert!(result.instruction.is_none());
+        assert_eq!(result.size, 1);
+        assert!(matches!(result.tokens[0], SyntaxToken::ErrorText(_)));
+
+        // Instruction crossing the end of memory
+        let result = cpu.disassemble_at(CpuAddress::Flat(0xFFFFF));
+        assert!(result.instruction.is_none());
+        assert_eq!(result.bytes, vec![0xB8]);
+        assert!(matches!(result.tokens[0], SyntaxToken::ErrorText(_)));
+    }
+
+    #[test]
+    fn test_io_trace_word_out() {
+        use crate::io_trace::IoDirection;
+
+        let mut cpu = test_cpu();
+
+        // mov dx, 3D4h ; mov ax, 0E0Fh ; out dx, ax ; in al, dx
+        let code = [0xBA, 0xD4, 0x03, 0xB8, 0x0F, 0x0E, 0xEF, 0xEC];
+        load_code(&mut cpu, &code);
+
+        cpu.bus_mut().io_trace_mut().set_enabled(true);
+        cpu.bus_mut().io_trace_mut().set_filter(vec![0x3D0..=0x3DF]);
+
+        for _ in 0..4 {
+            cpu.step(false).unwrap();
+        }
+
+        let entries: Vec<_> = cpu.bus().io_trace().entries().iter().copied().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].port, entries[0].value, entries[0].direction), (0x3D4, 0x0F, IoDirection::Write));
+        assert_eq!((entries[1].port, entries[1].value, entries[1].direction), (0x3D5, 0x0E, IoDirection::Write));
+        assert_eq!((entries[2].port, entries[2].value, entries[2].direction), (0x3D4, 0xFF, IoDirection::Read));
+        assert!(entries[0].cycle < entries[1].cycle && entries[1].cycle < entries[2].cycle);
+    }
+
+    #[test]
+    fn test_step_over_recursive_call() {
+        let mut cpu = test_cpu();
+
+        // 0000: mov cx, 3 ; call 0010 ; nop
+        // 0010: dec cx ; jz 0016 ; call 0010 ; ret
+        let main = [0xB9, 0x03, 0x00, 0xE8, 0x0A, 0x00, 0x90];
+        let func = [0x49, 0x74, 0x03, 0xE8, 0xFA, 0xFF, 0xC3];
+        load_code(&mut cpu, &main);
+        cpu.bus_mut().debug_write(0x10010, &func).unwrap();
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        // A non-call instruction is a single step
+        assert_eq!(cpu.step_over(false).unwrap(), StepOverResult::Completed);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+
+        // Enter the function and run to the recursive call
+        for _ in 0..3 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0013);
+        let sp = cpu.get_register16(Register16::SP);
+
+        // The innermost recursive call returns to 0016 first; step over must not stop there.
+        assert_eq!(cpu.step_over(false).unwrap(), StepOverResult::Completed);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0016);
+        assert_eq!(cpu.get_register16(Register16::SP), sp);
+        assert_eq!(cpu.get_register16(Register16::CX), 0);
+    }
+
+    #[test]
+    fn test_queue_params_8088_8086() {
+
+        // 0000: mov cx, 8 ; nop
+        // 0004: mul bx ; nop ; nop ; loop 0004 ; hlt
+        let code = [
+            0xB9, 0x08, 0x00, 0x90,
+            0xF7, 0xE3, 0x90, 0x90, 0xE2, 0xFA, 0xF4,
+        ];
+
+        // Run the loop with cycle tracing, returning the total cycles, the number of code fetch 
+        // bus cycles and the maximum observed queue length.
+        fn run_loop(cpu_type: CpuType, code: &[u8]) -> (Cpu, u64, usize, usize) {
+            let mut cpu = Cpu::new(
+                cpu_type,
+                TraceMode::Cycle,
+                None::<std::io::Sink>,
+                #[cfg(feature = "cpu_validator")]
+                ValidatorType::None,
+                #[cfg(feature = "cpu_validator")]
+                TraceLogger::None,
+            );
+            cpu.set_option(CpuOption::TraceLoggingEnabled(true));
+
+            load_code(&mut cpu, code);
+
+            let start = cpu.cycle_num;
+            let mut fetches = 0;
+            let mut max_queue = 0;
+            while cpu.get_register16(Register16::IP) != 0x000A {
+                cpu.step(false).unwrap();
+                fetches += cpu.trace_str_vec.iter().filter(|s| s.contains("CODE T1")).count();
+                max_queue = usize::max(max_queue, cpu.queue.len() + cpu.queue.has_preload() as usize);
+                assert!(cpu.queue.len() <= cpu.queue.size());
+            }
+            let cycles = cpu.cycle_num - start;
+            (cpu, cycles, fetches, max_queue)
+        }
+
+        let (cpu88, cycles88, fetches88, max_queue88) = run_loop(CpuType::Intel8088, &code);
+        let (cpu86, cycles86, fetches86, max_queue86) = run_loop(CpuType::Intel8086, &code);
+
+        assert_eq!(cpu88.queue.size(), 4);
+        assert_eq!(cpu86.queue.size(), 6);
+
+        // Both models must execute the loop to the same result.
+        assert_eq!(cpu88.get_register16(Register16::CX), 0);
+        assert_eq!(cpu86.get_register16(Register16::CX), 0);
+
+        // The 8086 fills its queue a word at a time, so it needs fewer fetches to run the same
+        // code, fills a deeper queue during the multiply, and the loop completes sooner.
+        assert!(fetches86 < fetches88, "8086 fetches: {} 8088 fetches: {}", fetches86, fetches88);
+        assert!(max_queue88 <= 4);
+        assert!(max_queue86 > 4);
+        assert!(cycles86 < cycles88, "8086 cycles: {} 8088 cycles: {}", cycles86, cycles88);
+    }
+
+    #[test]
+    fn test_call_stack_overflow() {
+        let mut cpu = test_cpu();
+
+        // 0000: call 0000
+        load_code(&mut cpu, &[0xE8, 0xFD, 0xFF]);
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        for n in 0..CPU_CALL_STACK_LEN {
+            cpu.step(false).unwrap();
+            assert_eq!(cpu.call_stack_depth(), n + 1);
+        }
+        assert!(!cpu.call_stack_overflowed());
+
+        for _ in 0..4 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.call_stack_depth(), CPU_CALL_STACK_LEN);
+        assert!(cpu.call_stack_overflowed());
+        assert!(cpu.call_stack_iter().all(|call| {
+            matches!(call, CallStackEntry::Call { ret_cs: 0x1000, ret_ip: 0x0003, call_ip: 0x0000 })
+        }));
+
+        // Remaining entries still share the return address, so it stays flagged.
+        assert_ne!(cpu.bus().get_flags(0x10003) & MEM_RET_BIT, 0);
+
+        cpu.reset();
+        assert_eq!(cpu.call_stack_depth(), 0);
+        assert!(!cpu.call_stack_overflowed());
+    }
+
+    #[test]
+    fn test_aam_aad_base() {
+
+        // Execute 'code' with the divide error handler at 0000:0500
+        let run = |code: &[u8], ax: u16| {
+            let mut cpu = setup_cpu(code, [], &[(Register16::SS, 0x0000), (Register16::SP, 0x0400), (Register16::AX, ax)]);
+            set_ivt_entry(&mut cpu, 0, 0x0000, 0x0500);
+            cpu.step(false).unwrap();
+            cpu
+        };
+
+        fn check_szp(cpu: &Cpu, value: u8) {
+            assert_eq!(cpu.get_flag(Flag::Zero), value == 0);
+            assert_eq!(cpu.get_flag(Flag::Sign), value & 0x80 != 0);
+            assert_eq!(cpu.get_flag(Flag::Parity), value.count_ones() % 2 == 0);
+        }
+
+        let bases = [0x0A, 0x01, 0x02, 0x07, 0x10, 0x3C, 0x80, 0xFF];
+        let values = [0x00, 0x09, 0x0A, 0x4F, 0x63, 0x80, 0xFE, 0xFF];
+
+        for base in bases {
+            for al in values {
+                // aam base
+                let cpu = run(&[0xD4, base], 0x5A00 | al as u16);
+                assert_eq!(
+                    (cpu.get_register8(Register8::AH), cpu.get_register8(Register8::AL)),
+                    (al / base, al % base),
+                    "aam {:02X} with al={:02X}", base, al
+                );
+                assert_eq!(cpu.get_register16(Register16::IP), 0x0002);
+                check_szp(&cpu, al % base);
+
+                // aad base
+                for ah in [0x00u8, 0x01, 0x09, 0x7F, 0xFF] {
+                    let cpu = run(&[0xD5, base], (ah as u16) << 8 | al as u16);
+                    let expected = al.wrapping_add(ah.wrapping_mul(base));
+                    assert_eq!(
+                        (cpu.get_register8(Register8::AH), cpu.get_register8(Register8::AL)),
+                        (0, expected),
+                        "aad {:02X} with ax={:02X}{:02X}", base, ah, al
+                    );
+                    check_szp(&cpu, expected);
+                }
+            }
+        }
+
+        // aam 0 raises a divide error, leaving AX unchanged
+        let cpu = run(&[0xD4, 0x00], 0x1234);
+        assert_eq!(cpu.get_register16(Register16::CS), 0x0000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0500);
+        assert_eq!(cpu.get_register16(Register16::AX), 0x1234);
+    }
+
+    #[test]
+    fn test_wait_state_ranges() {
+
+        // mov al, [0100h] ; in al, dx
+        let code = [0xA0, 0x00, 0x01, 0xEC];
+
+        let run = |mem_wait: Option<u32>, io_wait: u32| {
+            let mut cpu = setup_cpu(
+                &code,
+                [CpuOption::EnableWaitStates(true)],
+                &[(Register16::DS, 0x0000), (Register16::DX, 0x0300)]
+            );
+            if let Some(wait) = mem_wait {
+                cpu.bus_mut().add_mem_wait_states(0x0000, 0x1000, wait);
+            }
+            cpu.bus_mut().set_io_wait_states(io_wait);
+            step_cycles(&mut cpu, 2)
+        };
+
+        let base = run(None, 1);
+
+        // Wait states on the data range slow the memory read. Code outside the range is unaffected.
+        let slow_mem = run(Some(3), 1);
+        assert!(slow_mem[0] > base[0]);
+
+        // IO wait states only slow the IO read
+        let slow_io = run(None, 4);
+        assert_eq!(slow_io[0], base[0]);
+        assert!(slow_io[1] > base[1]);
+    }
+
+    #[test]
+    fn test_dram_refresh_contention() {
+        use crate::devices::dma::{DMAController, DMA_COMMAND_DISABLE};
+
+        // Run a string of nops with a refresh every 72 cycles, as programmed by the BIOS, 
+        // returning the total cycles taken.
+        let run = |refresh: bool, dma: Option<DMAController>| -> u32 {
+            let mut cpu = setup_cpu(
+                &[0x90; 64],
+                [CpuOption::EnableWaitStates(true), CpuOption::SimulateDramRefresh(refresh, 72, 0)],
+                &[]
+            );
+            *cpu.bus_mut().dma_mut() = dma;
+            step_cycles(&mut cpu, 48).iter().sum()
+        };
+
+        let base = run(false, None);
+        let refresh = run(true, None);
+        assert!(refresh > base);
+        assert_eq!(run(true, Some(DMAController::new())), refresh);
+
+        // Masking channel 0 or disabling the controller stops refresh taking the bus
+        let mut dma = DMAController::new();
+        dma.handle_channel_mask_register_write(0x04);
+        assert_eq!(run(true, Some(dma)), base);
+
+        let mut dma = DMAController::new();
+        dma.handle_command_register_write(DMA_COMMAND_DISABLE);
+        assert_eq!(run(true, Some(dma)), base);
+    }
+
+    #[test]
+    fn test_memory_access_flags() {
+        use crate::bus::MEM_WRITTEN_BIT;
+
+        let mut cpu = test_cpu();
+
+        // mov [0200h], ax ; nop
+        load_code(&mut cpu, &[0xA3, 0x00, 0x02, 0x90]);
+        cpu.bus_mut().clear_access_flags();
+        cpu.set_register16(Register16::DS, 0x0000);
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+
+        assert_eq!(cpu.bus().flag_ranges(MEM_WRITTEN_BIT), vec![(0x200, 0x202)]);
+        assert_eq!(cpu.bus().flag_ranges(MEM_EXEC_BIT), vec![(0x10000, 0x10001), (0x10003, 0x10004)]);
+    }
+
+    #[test]
+    fn test_memory_breakpoints() {
+        let mut cpu = test_cpu();
+
+        // mov ax, [0200h] ; mov [0300h], ax ; nop ; nop
+        load_code(&mut cpu, &[0xA1, 0x00, 0x02, 0xA3, 0x00, 0x03, 0x90, 0x90]);
+        cpu.bus_mut().write_u8(0x200, 0x34, 0).unwrap();
+        cpu.bus_mut().write_u8(0x201, 0x12, 0).unwrap();
+        cpu.set_register16(Register16::DS, 0x0000);
+
+        cpu.add_breakpoint(0x201, BreakKind::Read);
+        cpu.add_breakpoint(0x300, BreakKind::Write);
+        cpu.add_breakpoint(0x10007, BreakKind::Execute);
+        // Fetching code from a read breakpoint address must not trip it
+        cpu.add_breakpoint(0x10006, BreakKind::Read);
+
+        // Read breakpoints are reported after the accessing instruction completes
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, _))));
+        assert_eq!(cpu.last_breakpoint(), Some((0x201, BreakKind::Read)));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x1234);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+
+        // The CPU stays stopped until the breakpoint is cleared
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+        cpu.clear_breakpoint_flag();
+
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, _))));
+        assert_eq!(cpu.last_breakpoint(), Some((0x300, BreakKind::Write)));
+        cpu.clear_breakpoint_flag();
+
+        // 0x10007 has been prefetched by now, but the execute breakpoint only trips when
+        // the instruction there is about to be executed.
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+        assert_eq!(cpu.last_breakpoint(), Some((0x10007, BreakKind::Execute)));
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0007);
+        cpu.clear_breakpoint_flag();
+        assert!(matches!(cpu.step(true), Ok((StepResult::Normal, _))));
+
+        assert!(cpu.remove_breakpoint(0x201, BreakKind::Read));
+        assert!(!cpu.remove_breakpoint(0x201, BreakKind::Read));
+        assert!(!cpu.remove_breakpoint(0x300, BreakKind::Read));
+        assert_eq!(cpu.bus().get_flags(0x201) & MEM_BPA_BIT, 0);
+        assert_ne!(cpu.bus().get_flags(0x300) & MEM_BPW_BIT, 0);
+    }
+
+    #[test]
+    fn test_disassemble_symbols() {
+        let mut cpu = test_cpu();
+
+        // call 0010h ; mov ax, [0200h] ; jmp far F000:E05B
+        let code = [0xE8u8, 0x0D, 0x00, 0xA1, 0x00, 0x02, 0xEA, 0x5B, 0xE0, 0x00, 0xF0];
+        load_code(&mut cpu, &code);
+        cpu.set_register16(Register16::DS, 0x0040);
+
+        let symbols = SymbolMap::parse("1000:0010=_dos_print\n00600=bda_var\nF000:E05B=bios_reset").unwrap();
+        cpu.set_symbols(symbols);
+
+        let has_symbol = |tokens: &Vec<SyntaxToken>, name: &str| {
+            tokens.iter().any(|t| matches!(t, SyntaxToken::Symbol(s) if s == name))
+        };
+
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 0));
+        assert!(has_symbol(&result.tokens, "_dos_print"));
+        // A segmented symbol does not match a linear address
+        let result = cpu.disassemble_at(CpuAddress::Flat(0x10000));
+        assert!(!has_symbol(&result.tokens, "_dos_print"));
+
+        // Data symbols are resolved through the current value of ds
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 3));
+        assert!(has_symbol(&result.tokens, "bda_var"));
+        assert!(!result.tokens.iter().any(|t| matches!(t, SyntaxToken::HexValue(..))));
+
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 6));
+        assert!(has_symbol(&result.tokens, "bios_reset"));
+
+        // Without symbols, addresses are shown as hex
+        cpu.set_symbols(SymbolMap::new());
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 0));
+        assert!(!result.tokens.iter().any(|t| matches!(t, SyntaxToken::Symbol(_))));
+    }
+
+    #[test]
+    fn test_run_for_budget() {
+        // mov cx, 4 ; mov di, 0 ; cld ; rep stosb ; nop ; nop ; sti ; hlt
+        let code = [0xB9u8, 0x04, 0x00, 0xBF, 0x00, 0x00, 0xFC, 0xF3, 0xAA, 0x90, 0x90, 0xFB, 0xF4];
+        let new_cpu = || {
+            let mut cpu = test_cpu();
+            load_code(&mut cpu, &code);
+            cpu.set_register16(Register16::ES, 0x2000);
+            cpu
+        };
+
+        // The REP-prefixed instruction counts once, however many iterations it runs
+        let mut cpu = new_cpu();
+        let summary = cpu.run_for_instructions(4);
+        assert!(matches!(summary.stop_reason, StopReason::BudgetExhausted));
+        assert_eq!(summary.instructions, 4);
+        assert_eq!(cpu.get_register16(Register16::CX), 0);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0009);
+
+        let summary2 = new_cpu().run_for_instructions(4);
+        assert_eq!(summary2.cycles, summary.cycles);
+
+        let summary = cpu.run_for_instructions(100);
+        assert!(matches!(summary.stop_reason, StopReason::Halted));
+        assert_eq!(summary.instructions, 4);
+
+        // A cycle budget always completes the instruction in progress
+        let mut cpu = new_cpu();
+        let summary = cpu.run_for_cycles(1);
+        assert!(matches!(summary.stop_reason, StopReason::BudgetExhausted));
+        assert_eq!(summary.instructions, 1);
+        assert!(summary.cycles > 1);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+
+        let mut cpu = new_cpu();
+        let summary = cpu.run_for_cycles(summary2.cycles);
+        assert_eq!(summary.instructions, 4);
+        assert_eq!(summary.cycles, summary2.cycles);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0009);
+
+        // An error stops the run and is passed back to the caller
+        let mut cpu = test_cpu();
+        load_code(&mut cpu, &[0x90, 0xFA, 0xF4]);
+        let summary = cpu.run_for_instructions(100);
+        assert_eq!(summary.instructions, 2);
+        match summary.stop_reason {
+            StopReason::Error(CpuError::CpuHaltedError(address)) => assert_eq!(address, 0x10002),
+            reason => panic!("unexpected stop reason: {:?}", reason)
+        }
+    }
+
+    #[test]
+    fn test_decode_flags() {
+        let text = |tokens: &Vec<SyntaxToken>| -> String {
+            tokens.iter().map(|t| match t {
+                SyntaxToken::StateString(s, _, _) => s.clone(),
+                _ => String::new()
+            }).collect()
+        };
+
+        // Reserved bits are shown in their fixed state, whatever the value holds
+        assert_eq!(flags_string(0x0000), "1111oditsz0a0p1c");
+        assert_eq!(flags_string(0xFFFF), "1111ODITSZ0A0P1C");
+
+        let mut cpu = test_cpu();
+        cpu.reset();
+        cpu.set_flag(Flag::Zero);
+        cpu.set_flag(Flag::Carry);
+        assert_eq!(text(&decode_flags(cpu.flags)), "1111oditsZ0a0p1C");
+
+        let tokens = decode_flags_changed(cpu.flags, CPU_FLAGS_RESERVED_ON | CPU_FLAG_CARRY);
+        let dirty: Vec<bool> = tokens.iter().map(|t| matches!(t, SyntaxToken::StateString(_, true, _))).collect();
+        assert_eq!(dirty.iter().filter(|d| **d).count(), 1);
+        assert!(dirty[9]);
+    }
+
+    #[test]
+    fn test_halt_wait_wakeup() {
+        let mut cpu = test_cpu();
+
+        // sti ; hlt ; nop
+        load_code(&mut cpu, &[0xFB, 0xF4, 0x90]);
+
+        // IVT entry for vector 8 points to an iret at 0000:0500
+        cpu.bus_mut().write_u16(8 * 4, 0x0500, 0).unwrap();
+        cpu.bus_mut().write_u16(8 * 4 + 2, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x00500, 0xCF, 0).unwrap();
+
+        let mut pic = Pic::new();
+        pic.handle_data_register_write(0x00);
+        *cpu.bus_mut().pic_mut() = Some(pic);
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::HaltWait, _))));
+
+        // Idle in halt state until an interrupt arrives
+        for _ in 0..10 {
+            assert!(matches!(cpu.step(false), Ok((StepResult::HaltWait, 1))));
+        }
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0002);
+
+        cpu.bus_mut().pic_mut().as_mut().unwrap().request_interrupt(0);
+        match cpu.step(false) {
+            Ok((StepResult::Interrupt(addr), _)) => assert_eq!(addr, CpuAddress::Segmented(0x1000, 0x0002)),
+            _ => panic!("Expected an interrupt to wake the CPU")
+        }
+        assert!(!cpu.halted);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0500);
+
+        // The ISR returns to the instruction following HLT
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0002));
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+
+        // HLT with interrupts disabled cannot be resumed
+        let mut cpu = test_cpu();
+        load_code(&mut cpu, &[0xFA, 0xF4]);
+        cpu.step(false).unwrap();
+        assert!(matches!(cpu.step(false), Err(CpuError::CpuHaltedError(_))));
+    }
+
+    #[test]
+    fn test_nmi_edge_triggered() {
+        let mut cpu = test_cpu();
+
+        // cli ; mov ss, ax ; jmp $
+        load_code(&mut cpu, &[0xFA, 0x8E, 0xD0, 0xEB, 0xFE]);
+
+        // IVT entry for vector 2 points to an iret at 0000:0600
+        cpu.bus_mut().write_u16(2 * 4, 0x0600, 0).unwrap();
+        cpu.bus_mut().write_u16(2 * 4 + 2, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x00600, 0xCF, 0).unwrap();
+
+        cpu.set_register16(Register16::AX, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert!(!cpu.get_flag(Flag::Interrupt));
+
+        // NMI is held off for the instruction following the load of SS
+        cpu.raise_nmi();
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0003));
+        assert!(cpu.nmi_pending());
+
+        // ...then fires with interrupts disabled
+        assert!(matches!(cpu.step(false), Ok((StepResult::Call(_), _))));
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x0000, 0x0600));
+        assert!(!cpu.nmi_pending());
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0003));
+
+        // Holding the line high does not trigger another NMI
+        for _ in 0..4 {
+            cpu.step(false).unwrap();
+            assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0003));
+        }
+
+        // A new edge is latched even if the line is lowered before the next instruction boundary
+        cpu.lower_nmi();
+        cpu.raise_nmi();
+        cpu.lower_nmi();
+        assert!(matches!(cpu.step(false), Ok((StepResult::Call(_), _))));
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x0000, 0x0600));
+    }
+
+    #[test]
+    fn test_queue_tokens() {
+        let mut cpu = test_cpu();
+
+        // aam ; nop ; jmp 0040 ; ... ; nop ; nop ; nop ; nop
+        load_code(&mut cpu, &[0xD4, 0x0A, 0x90, 0xEB, 0x3B]);
+        for n in 0..8 {
+            cpu.bus_mut().write_u8(0x10005 + n, 0xCC, 0).unwrap();
+            cpu.bus_mut().write_u8(0x10040 + n, 0x90, 0).unwrap();
+        }
+
+        let check_tokens = |cpu: &Cpu| -> Vec<u32> {
+            let tokens = cpu.queue_tokens();
+            let addrs: Vec<u32> = tokens.iter().filter_map(|t| match t {
+                SyntaxToken::MemoryByteHexValue(addr, val, _, _, _) => {
+                    assert_eq!(cpu.bus().get_slice_at(*addr as usize, 1)[0], *val);
+                    Some(*addr)
+                }
+                _ => None
+            }).collect();
+            assert_eq!(tokens.len(), addrs.len() + 2);
+            assert!(addrs.windows(2).all(|w| w[1] == w[0] + 1));
+            assert!(matches!(&tokens[addrs.len()], SyntaxToken::StateString(s, _, _) if *s == addrs.len().to_string()));
+            assert!(matches!(&tokens[addrs.len() + 1], SyntaxToken::StateString(s, _, _) if *s == format!("{:05X}", cpu.pc)));
+            addrs
+        };
+
+        // aam runs long enough to fill the queue
+        cpu.step(false).unwrap();
+        let addrs = check_tokens(&cpu);
+        assert!(!addrs.is_empty());
+        assert!(addrs[0] < 0x10005);
+
+        // The jump flushes the queue, so no bytes from before the jump target remain
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0040));
+        let addrs = check_tokens(&cpu);
+        assert!(addrs.iter().all(|a| *a >= 0x10040));
+    }
+
+    #[test]
+    fn test_reset_deterministic() {
+
+        // mov ax, 1234h ; mov ds, ax ; stc ; std ; nop ; nop
+        let code = [0xB8u8, 0x34, 0x12, 0x8E, 0xD8, 0xF9, 0xFD, 0x90, 0x90];
+
+        // Dump the post-reset register and BIU state, followed by the state of the first
+        // several bus cycles, for comparison against hardware reset traces.
+        fn reset_dump(cpu: &mut Cpu) -> Vec<String> {
+            let mut dump = Vec::new();
+            for reg in [
+                Register16::AX, Register16::BX, Register16::CX, Register16::DX,
+                Register16::SP, Register16::BP, Register16::SI, Register16::DI,
+                Register16::CS, Register16::DS, Register16::SS, Register16::ES,
+                Register16::IP
+            ] {
+                dump.push(format!("{:?}: {:04X}", reg, cpu.get_register16(reg)));
+            }
+            dump.push(format!("FLAGS: {:04X}", cpu.flags));
+            dump.push(format!(
+                "PC: {:05X} MC: {:03X} Q: {} BIU: {:?} FETCH: {:?}",
+                cpu.pc, cpu.mc_pc, cpu.queue.len(), cpu.biu_state, cpu.fetch_state
+            ));
+            for _ in 0..20 {
+                dump.push(format!(
+                    "{:02} {:?} {:?} {:05X} Q: {}",
+                    cpu.cycle_num, cpu.t_cycle, cpu.bus_status, cpu.address_bus, cpu.queue.len()
+                ));
+                cpu.cycle();
+            }
+            dump
+        }
+
+        let mut fresh = test_cpu();
+        fresh.bus_mut().debug_write(0xFFFF0, &code).unwrap();
+        let fresh_dump = reset_dump(&mut fresh);
+
+        assert_eq!(fresh_dump[8], "CS: FFFF");
+        assert_eq!(fresh_dump[12], "IP: 0000");
+        assert_eq!(fresh_dump[13], format!("FLAGS: {:04X}", CPU_FLAGS_RESERVED_ON));
+        assert!(fresh_dump[..8].iter().chain(fresh_dump[9..12].iter()).all(|s| s.ends_with("0000")));
+        assert!(fresh_dump[14].starts_with("PC: FFFF0"));
+        assert!(fresh_dump[15].contains("CodeFetch FFFF0 Q: 0"));
+
+        // The BIU fills the queue from the reset vector with nothing executing.
+        assert_eq!(fresh.queue.len(), 4);
+        assert_eq!(fresh.pc, 0xFFFF4);
+
+        // Dirty the CPU state by executing some code, then stop mid-prefetch and reset.
+        let mut cpu = test_cpu();
+        cpu.bus_mut().debug_write(0xFFFF0, &code).unwrap();
+        for _ in 0..5 {
+            cpu.step(false).unwrap();
+        }
+        cpu.cycle();
+        assert_eq!(cpu.get_register16(Register16::DS), 0x1234);
+        assert!(cpu.get_flag(Flag::Direction));
+
+        cpu.reset();
+        assert_eq!(reset_dump(&mut cpu), fresh_dump);
+    }
+
+    #[test]
+    fn test_parity_low_byte() {
+        let mut cpu = test_cpu();
+
+        // Results such as 0x0101 and 0x0103 have a different parity over 16 bits than over 
+        // their low byte. Only the low byte should be considered.
+        let cases: [(Mnemonic, u16, u16, u16, bool); 8] = [
+            (Mnemonic::ADD, 0x0100, 0x0001, 0x0101, false),
+            (Mnemonic::ADD, 0x0080, 0x0081, 0x0101, false),
+            (Mnemonic::ADD, 0x00FF, 0x0004, 0x0103, true),
+            (Mnemonic::SUB, 0x0102, 0x0001, 0x0101, false),
+            (Mnemonic::SUB, 0x0301, 0x0001, 0x0300, true),
+            (Mnemonic::SUB, 0x0002, 0x0001, 0x0001, false),
+            (Mnemonic::AND, 0x0F03, 0x0101, 0x0101, false),
+            (Mnemonic::AND, 0xFF03, 0x0103, 0x0103, true),
+        ];
+
+        for (op, op1, op2, expected, parity) in cases {
+            let result = cpu.math_op16(op, op1, op2);
+            assert_eq!(result, expected);
+            assert_eq!(cpu.get_flag(Flag::Parity), parity, "{:?} {:04X}, {:04X}", op, op1, op2);
+            assert_eq!(parity, (result & 0xFF).count_ones() % 2 == 0);
+        }
+
+        // AF reflects a carry or borrow out of the low nibble for arithmetic ops only.
+        cpu.math_op16(Mnemonic::ADD, 0x000F, 0x0001);
+        assert!(cpu.get_flag(Flag::AuxCarry));
+        cpu.math_op16(Mnemonic::ADD, 0x0F00, 0x0100);
+        assert!(!cpu.get_flag(Flag::AuxCarry));
+        cpu.math_op16(Mnemonic::SUB, 0x0010, 0x0001);
+        assert!(cpu.get_flag(Flag::AuxCarry));
+        cpu.math_op16(Mnemonic::INC, 0x00FF, 0);
+        assert!(cpu.get_flag(Flag::AuxCarry));
+
+        // Logical ops leave AF untouched.
+        for op in [Mnemonic::AND, Mnemonic::OR, Mnemonic::XOR, Mnemonic::TEST] {
+            cpu.set_flag(Flag::AuxCarry);
+            cpu.math_op16(op, 0x000F, 0x0001);
+            assert!(cpu.get_flag(Flag::AuxCarry));
+            cpu.clear_flag(Flag::AuxCarry);
+            cpu.math_op16(op, 0x000F, 0x0001);
+            assert!(!cpu.get_flag(Flag::AuxCarry));
+        }
+    }
+
+    #[test]
+    fn test_trace_records() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let records: Rc<RefCell<Vec<TraceRecord>>> = Rc::new(RefCell::new(Vec::new()));
+        let lines = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = test_cpu();
+
+        // mov ax, 1234h ; mov [0100h], al ; mov cx, 2 ; rep stosb ; nop
+        let code = [0xB8u8, 0x34, 0x12, 0xA2, 0x00, 0x01, 0xB9, 0x02, 0x00, 0xF3, 0xAA, 0x90];
+        load_code(&mut cpu, &code);
+        cpu.set_register16(Register16::DI, 0x0200);
+
+        let records_cb = records.clone();
+        let lines_cb = lines.clone();
+        let mut writer = TraceRecordWriter::new(Vec::new());
+        cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+            writer.write(record).unwrap();
+            lines_cb.borrow_mut().push(String::from_utf8(std::mem::take(writer.get_mut())).unwrap());
+            records_cb.borrow_mut().push(record.clone());
+        }));
+
+        // The REP instruction takes several steps but produces one record.
+        while cpu.get_register16(Register16::IP) < code.len() as u16 {
+            cpu.step(false).unwrap();
+        }
+        cpu.clear_trace_callback();
+        cpu.step(false).unwrap();
+
+        let records = records.borrow();
+        let lines = lines.borrow();
+        assert_eq!(records.len(), 5);
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|l| l.ends_with('\n') && l.matches('\n').count() == 1));
+
+        // Registers are captured before execution
+        assert_eq!(records[0].regs.ax, 0x0000);
+        assert_eq!(records[1].regs.ax, 0x1234);
+        assert_eq!((records[0].regs.cs, records[0].regs.ip), (0x1000, 0x0000));
+        assert_eq!(records[0].address, 0x10000);
+        assert_eq!(records[0].bytes, vec![0xB8, 0x34, 0x12]);
+        assert_eq!(records[3].bytes, vec![0xF3, 0xAA]);
+        assert!(matches!(records[0].tokens[0], SyntaxToken::Mnemonic(_)));
+        assert!(lines[0].starts_with("1000:0000 B83412"));
+
+        // Bus transfers are recorded, with the EU write following the instruction's own fetches
+        let write = records[1].bus_cycles.iter().find(|b| b.status == BusStatus::MemWrite).unwrap();
+        assert_eq!((write.address, write.data & 0xFF), (0x00100, 0x34));
+        assert!(lines[1].contains(":MEMW:00100:34"));
+        assert!(records.iter().all(|r| r.bus_cycles.iter().all(|b| b.cycle < r.cycles)));
+        assert!(records.iter().all(|r| r.cycles > 0));
+
+        let writes: Vec<u32> = records[3].bus_cycles.iter()
+            .filter(|b| b.status == BusStatus::MemWrite)
+            .map(|b| b.address)
+            .collect();
+        assert_eq!(writes, vec![0x00200, 0x00201]);
+    }
+
+    #[test]
+    fn test_trace_record_writer_flush() {
+        use std::cell::RefCell;
+        use std::io::BufWriter;
+        use std::rc::Rc;
+
+        struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = test_cpu();
+        load_code(&mut cpu, &[0x90, 0x90, 0x90]);
+        let writer: Box<dyn Write> = Box::new(BufWriter::with_capacity(4096, SharedBuf(out.clone())));
+        cpu.set_trace_record_writer(TraceRecordWriter::new(writer));
+
+        // Records stay buffered until the writer is flushed
+        cpu.step(false).unwrap();
+        assert!(out.borrow().is_empty());
+        cpu.trace_flush();
+        assert_eq!(String::from_utf8(out.borrow().clone()).unwrap().lines().count(), 1);
+
+        // Clearing the callback flushes what's left
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        cpu.clear_trace_callback();
+        assert_eq!(String::from_utf8(out.borrow().clone()).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_setmo() {
+
+        let run = |code: &[u8], cl: u8| {
+            let mut cpu = setup_cpu(code, [], &[(Register16::DX, 0x1200), (Register16::CX, cl as u16)]);
+            // All flags that SETMO clears are set, and the ones it sets are clear.
+            cpu.set_flag(Flag::Carry);
+            cpu.set_flag(Flag::AuxCarry);
+            cpu.set_flag(Flag::Zero);
+            cpu.set_flag(Flag::Overflow);
+            cpu.clear_flag(Flag::Parity);
+            cpu.clear_flag(Flag::Sign);
+            let (_, cycles) = cpu.step(false).unwrap();
+            (cpu, cycles)
+        };
+
+        fn check_setmo_flags(cpu: &Cpu) {
+            assert!(!cpu.get_flag(Flag::Carry));
+            assert!(!cpu.get_flag(Flag::AuxCarry));
+            assert!(!cpu.get_flag(Flag::Zero));
+            assert!(!cpu.get_flag(Flag::Overflow));
+            assert!(cpu.get_flag(Flag::Parity));
+            assert!(cpu.get_flag(Flag::Sign));
+        }
+
+        // setmo al
+        let (cpu, _) = run(&[0xD0, 0xF0], 0);
+        assert!(matches!(cpu.i.mnemonic, Mnemonic::SETMO));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x00FF);
+        check_setmo_flags(&cpu);
+
+        // setmo bx
+        let (cpu, _) = run(&[0xD1, 0xF3], 0);
+        assert!(matches!(cpu.i.mnemonic, Mnemonic::SETMO));
+        assert_eq!(cpu.get_register16(Register16::BX), 0xFFFF);
+        check_setmo_flags(&cpu);
+
+        // setmoc al, cl and setmoc dx, cl
+        for (code, reg, ones) in [([0xD2u8, 0xF0], Register16::AX, 0x00FF), ([0xD3, 0xF2], Register16::DX, 0xFFFF)] {
+            let (cpu, base_cycles) = run(&code, 0);
+            assert!(matches!(cpu.i.mnemonic, Mnemonic::SETMOC));
+
+            // A count of 0 leaves the operand and flags unmodified
+            assert_eq!(cpu.get_register16(reg), if reg == Register16::AX { 0x0000 } else { 0x1200 });
+            assert!(cpu.get_flag(Flag::Carry));
+            assert!(cpu.get_flag(Flag::Zero));
+            assert!(!cpu.get_flag(Flag::Sign));
+
+            for count in [1u8, 2, 7] {
+                let (cpu, cycles) = run(&code, count);
+                assert_eq!(cpu.get_register16(reg), ones);
+                check_setmo_flags(&cpu);
+                // Like a shift by CL, each count takes 4 cycles
+                assert_eq!(cycles, base_cycles + 4 * count as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn test_flags_affected() {
+
+        let decode = |bytes: &[u8]| {
+            let mut cpu = setup_cpu(bytes, [], &[]);
+            cpu.bus_mut().seek(0x10000);
+            Cpu::decode(cpu.bus_mut(), CpuType::Intel8088).unwrap()
+        };
+
+        // and ax, ax: OF and CF are cleared, AF is undefined
+        let f = decode(&[0x21, 0xC0]).flags_affected();
+        assert_eq!(f.modified, CPU_FLAG_SIGN | CPU_FLAG_ZERO | CPU_FLAG_PARITY);
+        assert_eq!(f.cleared, CPU_FLAG_OVERFLOW | CPU_FLAG_CARRY);
+        assert_eq!(f.undefined, CPU_FLAG_AUX_CARRY);
+        assert_eq!(format!("{}", f), "OSZPC");
+
+        // shl ax, 1 defines OF, shl ax, cl leaves it undefined
+        let f = decode(&[0xD1, 0xE0]).flags_affected();
+        assert_ne!(f.modified & CPU_FLAG_OVERFLOW, 0);
+        let f = decode(&[0xD3, 0xE0]).flags_affected();
+        assert_eq!(f.modified & CPU_FLAG_OVERFLOW, 0);
+        assert_ne!(f.undefined & CPU_FLAG_OVERFLOW, 0);
+
+        // Group 3 selects DIV by the reg field, which leaves all arithmetic flags undefined
+        let f = decode(&[0xF7, 0xF3]).flags_affected();
+        assert_eq!(f.defined(), 0);
+        assert_eq!(FlagsAffected::mask_string(f.undefined), "OSZAPC");
+
+        // Group 3 TEST shares the opcode
+        let f = decode(&[0xF7, 0xC0, 0x00, 0x00]).flags_affected();
+        assert_eq!(f.cleared, CPU_FLAG_OVERFLOW | CPU_FLAG_CARRY);
+
+        assert_eq!(decode(&[0xF8]).flags_affected().cleared, CPU_FLAG_CARRY);
+        assert_eq!(decode(&[0xF9]).flags_affected().set, CPU_FLAG_CARRY);
+        assert!(decode(&[0x90]).flags_affected().is_empty());
+    }
+
+    #[test]
+    fn test_off_rails_threshold() {
+
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // Memory is zeroed, so the CPU executes a run of ADD [BX+SI], AL
+        let mut cpu = test_cpu();
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+        cpu.set_option(CpuOption::OffRailsDetection(true));
+        cpu.set_option(CpuOption::OffRailsThreshold(3));
+
+        // With no callback, the CPU halts once the threshold is exceeded
+        for _ in 0..3 {
+            assert!(cpu.step(false).is_ok());
+        }
+        assert!(matches!(cpu.step(false), Err(CpuError::CpuHaltedError(_))));
+        assert!(cpu.halted);
+        assert!(!cpu.get_flag(Flag::Interrupt));
+
+        // The callback can ignore the detection, and is only called once per run of 0x00 opcodes
+        let mut cpu = test_cpu();
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+        cpu.set_option(CpuOption::OffRailsDetection(true));
+        cpu.set_option(CpuOption::OffRailsThreshold(3));
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let calls_cb = calls.clone();
+        cpu.set_runaway_callback(Box::new(move |address, count| {
+            calls_cb.borrow_mut().push((address, count));
+            RunawayAction::Ignore
+        }));
+
+        for _ in 0..10 {
+            assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        }
+        assert!(!cpu.halted);
+        assert_eq!(*calls.borrow(), vec![(0x10006, 4)]);
+
+        // Or break into the debugger
+        let mut cpu = test_cpu();
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+        cpu.set_option(CpuOption::OffRailsDetection(true));
+        cpu.set_runaway_callback(Box::new(|_, _| RunawayAction::Break));
+
+        for _ in 0..OFF_RAILS_DEFAULT_THRESHOLD {
+            assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        }
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, _))));
+        assert!(!cpu.halted);
+    }
+
+    #[test]
+    fn test_daa_das_exhaustive() {
+
+        // Reference model of the 8088's decimal adjust. Unlike Intel's pseudocode, the high digit
+        // is adjusted when AL > 0x9F if AF is set. Returns (AL, AF, CF).
+        fn reference(al: u8, af: bool, cf: bool, sub: bool) -> (u8, bool, bool) {
+            let adjust = |a: u8, n: u8| if sub { a.wrapping_sub(n) } else { a.wrapping_add(n) };
+            let mut new_al = al;
+            let new_af = (al & 0x0F) > 9 || af;
+            if new_af {
+                new_al = adjust(new_al, 0x06);
+            }
+            let new_cf = al > (if af { 0x9F } else { 0x99 }) || cf;
+            if new_cf {
+                new_al = adjust(new_al, 0x60);
+            }
+            (new_al, new_af, new_cf)
+        }
+
+        // Spot check the reference model, including the AF threshold difference
+        let known = [
+            // sub, al, af, cf, result al, af, cf
+            (false, 0x9A, false, false, 0x00, true, true),
+            (false, 0x9F, true, false, 0xA5, true, false),
+            (false, 0xA0, true, false, 0x06, true, true),
+            (false, 0x00, true, true, 0x66, true, true),
+            (false, 0x45, false, false, 0x45, false, false),
+            (true, 0x9A, false, false, 0x34, true, true),
+            (true, 0x00, true, false, 0xFA, true, false),
+            (true, 0x00, false, true, 0xA0, false, true),
+        ];
+        for (sub, al, af, cf, r_al, r_af, r_cf) in known {
+            assert_eq!(reference(al, af, cf, sub), (r_al, r_af, r_cf));
+        }
+
+        let mut cpu = test_cpu();
+
+        for sub in [false, true] {
+            for al in 0..=0xFFu8 {
+                for (af, cf) in [(false, false), (true, false), (false, true), (true, true)] {
+                    cpu.set_register8(Register8::AL, al);
+                    cpu.set_flag_state(Flag::AuxCarry, af);
+                    cpu.set_flag_state(Flag::Carry, cf);
+                    if sub { cpu.das() } else { cpu.daa() }
+
+                    let (r_al, r_af, r_cf) = reference(al, af, cf, sub);
+                    let result = cpu.get_register8(Register8::AL);
+                    let name = if sub { "das" } else { "daa" };
+                    assert_eq!(
+                        (result, cpu.get_flag(Flag::AuxCarry), cpu.get_flag(Flag::Carry)),
+                        (r_al, r_af, r_cf),
+                        "{} with al={:02X} af={} cf={}", name, al, af, cf
+                    );
+                    assert_eq!(cpu.get_flag(Flag::Zero), r_al == 0);
+                    assert_eq!(cpu.get_flag(Flag::Sign), r_al & 0x80 != 0);
+                    assert_eq!(cpu.get_flag(Flag::Parity), r_al.count_ones() % 2 == 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_code() {
+        let mut cpu = test_cpu();
+
+        // mov ah, 09h; int 21h at 1000:0010, and int 21h at 1000:FFFF, wrapping the segment
+        let mut code = [0; 0x14];
+        code[0x00] = 0x21;
+        code[0x10..].copy_from_slice(&[0xB4, 0x09, 0xCD, 0x21]);
+        load_code(&mut cpu, &code);
+        cpu.bus_mut().debug_write(0x1FFFF, &[0xCD]).unwrap();
+
+        let int21 = parse_code_pattern("CD 21").unwrap();
+        let matches: Vec<_> = cpu.find_code(&int21, CpuAddress::Segmented(0x1000, 0x0001), usize::MAX).collect();
+        assert_eq!(matches, vec![CpuAddress::Segmented(0x1000, 0x0012), CpuAddress::Segmented(0x1000, 0xFFFF)]);
+
+        // Flat searches don't wrap the segment
+        let matches: Vec<_> = cpu.find_code(&int21, CpuAddress::Flat(0x10000), 0x10000).collect();
+        assert_eq!(matches, vec![CpuAddress::Flat(0x10012)]);
+
+        // Wildcards
+        let pattern = parse_code_pattern("B4 ?? CD").unwrap();
+        assert_eq!(pattern, vec![Some(0xB4), None, Some(0xCD)]);
+        assert_eq!(cpu.find_code(&pattern, CpuAddress::Flat(0x10000), 0x100).next(), Some(CpuAddress::Flat(0x10010)));
+
+        // Limit
+        assert_eq!(cpu.find_code(&int21, CpuAddress::Flat(0x10000), 0x12).next(), None);
+        assert_eq!(cpu.find_code(&int21, CpuAddress::Flat(0x10000), 0x13).count(), 1);
+
+        assert!(parse_code_pattern("CD 2G").is_none());
+        assert_eq!(cpu.find_code(&[], CpuAddress::Flat(0), 0x100).next(), None);
+    }
+
+    #[test]
+    fn test_search_instructions() {
+        let mut cpu = test_cpu();
+
+        let code = [
+            0xA1, 0x10, 0x00,               // 1000:0000 mov ax, [0010h]
+            0xCD, 0x21,                     // 1000:0003 int 21h
+            0x26, 0x88, 0x07,               // 1000:0005 mov es:[bx], al
+            0x9A, 0x78, 0x56, 0x34, 0x12,   // 1000:0008 callf 1234:5678
+            0xB8, 0x21, 0x00,               // 1000:000D mov ax, 21h
+            0xCD, 0x21,                     // 1000:0010 int 21h
+        ];
+        load_code(&mut cpu, &code);
+
+        let mut search = |query_str: &str| -> Vec<u16> {
+            let query = InstructionQuery::parse(query_str).unwrap();
+            Cpu::search_instructions(cpu.bus_mut(), CpuType::Intel8088, 0x1000, 0, code.len() as u16, &query)
+                .iter()
+                .map(|addr| match addr {
+                    CpuAddress::Segmented(_, offset) => *offset,
+                    _ => panic!("expected a segmented address")
+                })
+                .collect()
+        };
+
+        assert_eq!(search("int 21h"), vec![0x0003, 0x0010]);
+        assert_eq!(search("* 21"), vec![0x0003, 0x000D, 0x0010]);
+        assert_eq!(search("* far"), vec![0x0008]);
+        assert_eq!(search("MOV mem"), vec![0x0000, 0x0005]);
+        assert_eq!(search("mov memw"), vec![0x0005]);
+        assert_eq!(search("mov ds:"), vec![0x0000]);
+        assert_eq!(search("mov es: mem"), vec![0x0005]);
+        assert_eq!(search("int 20"), vec![]);
+
+        // A range starting mid-instruction decodes from that byte
+        let query = InstructionQuery::parse("int 21").unwrap();
+        let results = Cpu::search_instructions(cpu.bus_mut(), CpuType::Intel8088, 0x1000, 4, code.len() as u16, &query);
+        assert_eq!(results, vec![CpuAddress::Segmented(0x1000, 0x0010)]);
+
+        assert!(InstructionQuery::parse("").is_none());
+        assert!(InstructionQuery::parse("bogus").is_none());
+        assert!(InstructionQuery::parse("mov xyz").is_none());
+    }
+
+    #[test]
+    fn test_unaligned_word_string_timing() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // Run rep stosw for 3 words at the specified DI, returning the cycles taken and the
+        // address and size of each write.
+        let run = |cpu_type: CpuType, di: u16| -> (u32, Vec<(u32, bool)>) {
+            let mut cpu = setup_cpu_type(
+                cpu_type,
+                &[0xF3, 0xAB, 0x90],
+                [],
+                &[(Register16::ES, 0x0000), (Register16::DI, di), (Register16::CX, 3), (Register16::AX, 0xBEEF)]
+            );
+
+            let records = Rc::new(RefCell::new(Vec::new()));
+            let records_cb = records.clone();
+            cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+                records_cb.borrow_mut().push(record.clone());
+            }));
+
+            while cpu.get_register16(Register16::IP) < 2 {
+                cpu.step(false).unwrap();
+            }
+            cpu.clear_trace_callback();
+
+            for n in 0..6 {
+                let (byte, _) = cpu.bus_mut().read_u8(di as usize + n, 0).unwrap();
+                assert_eq!(byte, if n & 1 == 0 { 0xEF } else { 0xBE });
+            }
+
+            let records = records.borrow();
+            assert_eq!(records.len(), 1);
+            let writes = records[0].bus_cycles.iter()
+                .filter(|b| b.status == BusStatus::MemWrite)
+                .map(|b| (b.address, matches!(b.size, TransferSize::Word)))
+                .collect();
+            (records[0].cycles, writes)
+        };
+
+        // The 8086 writes aligned words in a single bus cycle, and unaligned words a byte at a time
+        let (even_cycles, writes) = run(CpuType::Intel8086, 0x0200);
+        assert_eq!(writes, vec![(0x200, true), (0x202, true), (0x204, true)]);
+        let (odd_cycles, writes) = run(CpuType::Intel8086, 0x0201);
+        assert_eq!(writes, vec![(0x201, false), (0x202, false), (0x203, false), (0x204, false), (0x205, false), (0x206, false)]);
+        assert!(odd_cycles > even_cycles, "odd: {} even: {}", odd_cycles, even_cycles);
+
+        // The 8088 always writes words a byte at a time, so alignment makes no difference
+        let (even_cycles, writes) = run(CpuType::Intel8088, 0x0200);
+        assert_eq!(writes.len(), 6);
+        assert!(writes.iter().all(|(_, word)| !word));
+        let (odd_cycles, _) = run(CpuType::Intel8088, 0x0201);
+        assert_eq!(odd_cycles, even_cycles);
+    }
+
+    #[test]
+    fn test_cpu_address() {
+        let addr = CpuAddress::Segmented(0x1000, 0xFFFF);
+
+        // Offsets wrap within the segment
+        assert_eq!(addr.offset(1), CpuAddress::Segmented(0x1000, 0x0000));
+        assert_eq!(u32::from(addr.offset(1)), 0x10000);
+        assert_eq!(CpuAddress::Segmented(0x1000, 0).offset(-1), CpuAddress::Segmented(0x1000, 0xFFFF));
+        assert_eq!(CpuAddress::Offset(0xFFFF).offset(1), CpuAddress::Offset(0));
+
+        // Flat addresses wrap at 1MB
+        assert_eq!(CpuAddress::Flat(0xFFFFF).offset(1), CpuAddress::Flat(0));
+        assert_eq!(CpuAddress::Flat(0).offset(-1), CpuAddress::Flat(0xFFFFF));
+        assert_eq!(CpuAddress::Segmented(0xFFFF, 0x0010).to_flat(), CpuAddress::Flat(0));
+
+        assert_eq!(addr.to_flat(), CpuAddress::Flat(0x1FFFF));
+        assert_eq!(addr.normalize(), CpuAddress::Segmented(0x1FFF, 0x000F));
+        assert!(matches!(
+            CpuAddress::Segmented(0x1234, 0x0010).normalize(),
+            CpuAddress::Segmented(0x1235, 0x0000)
+        ));
+        assert!(matches!(CpuAddress::Flat(0x12345).normalize(), CpuAddress::Segmented(0x1234, 0x0005)));
+
+        assert_eq!(format!("{}", addr), "1000:FFFF");
+        assert_eq!(format!("{:#}", addr), "1000:FFFF [1FFFF]");
+        assert_eq!(format!("{}", addr.to_flat()), "1FFFF");
+        assert_eq!(format!("{}", addr.offset(1).to_flat()), "10000");
+    }
+
+    #[test]
+    fn test_execute_bytes() {
+        let mut cpu = test_cpu();
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+
+        cpu.set_register16(Register16::AX, 0x1234);
+        let (result, _) = cpu.execute_bytes(&[0x40]).unwrap(); // INC AX
+        assert!(matches!(result, StepResult::Normal));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x1235);
+        assert_eq!(cpu.get_register16(Register16::IP), 1);
+
+        // The illegal register form of LES uses the last calculated EA rather than failing
+        cpu.reset();
+        assert!(cpu.execute_bytes(&[0xC4, 0xC0]).is_ok()); // LES AX, AX
+        assert_eq!(cpu.get_register16(Register16::IP), 2);
+
+        // Bytes that would run past the end of the address space are rejected without executing
+        cpu.reset();
+        cpu.set_register16(Register16::CS, 0xF000);
+        cpu.set_register16(Register16::IP, 0xFFFF);
+        let err = cpu.execute_bytes(&[0x40, 0x40]).unwrap_err(); // INC AX ; INC AX
+        assert!(matches!(err, CpuError::ExecutionError(0xFFFFF, _)));
+        assert_eq!(cpu.get_register16(Register16::AX), 0);
+        assert_eq!(cpu.get_register16(Register16::IP), 0xFFFF);
+    }
+
+    #[test]
+    fn test_intr_acknowledge() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use crate::devices::pic::Pic;
+
+        let records: Rc<RefCell<Vec<TraceRecord>>> = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = test_cpu();
+
+        // Timer interrupt at vector 8, with IR0 unmasked
+        let mut pic = Pic::new();
+        pic.handle_command_register_write(0x13);
+        pic.handle_data_register_write(0x08);
+        pic.handle_data_register_write(0x01);
+        pic.handle_data_register_write(0xFE);
+        *cpu.bus_mut().pic_mut() = Some(pic);
+
+        // sti ; nop ; nop
+        load_code(&mut cpu, &[0xFB, 0x90, 0x90]);
+        cpu.bus_mut().write_u16(8 * 4, 0x0500, 0).unwrap();
+        cpu.bus_mut().write_u16(8 * 4 + 2, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x00500, 0xCF, 0).unwrap();
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        let records_cb = records.clone();
+        cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+            records_cb.borrow_mut().push(record.clone());
+        }));
+
+        // The interrupt is recognized after the instruction following STI
+        cpu.step(false).unwrap();
+        cpu.bus_mut().pic_mut().as_mut().unwrap().request_interrupt(0);
+        cpu.step(false).unwrap();
+        let (result, _) = cpu.step(false).unwrap();
+        assert!(matches!(result, StepResult::Interrupt(CpuAddress::Segmented(0x1000, 0x0002))));
+        assert_eq!(cpu.get_register16(Register16::CS), 0x0000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0500);
+        assert_eq!(cpu.bus_mut().pic_mut().as_ref().unwrap().isr(), 0x01);
+        cpu.clear_trace_callback();
+
+        let records = records.borrow();
+        let entry = records.last().unwrap();
+        assert_eq!(entry.disassembly, "INTR 08");
+
+        // Two INTA cycles separated by two idle cycles, the PIC driving the vector on the second,
+        // followed by the IVT read.
+        let inta: Vec<&TraceBusCycle> = entry.bus_cycles.iter().filter(|b| b.status == BusStatus::InterruptAck).collect();
+        assert_eq!(inta.len(), 2);
+        assert_eq!(inta[1].data & 0xFF, 0x08);
+        assert_eq!(inta[1].cycle - inta[0].cycle, 6);
+        assert!(entry.bus_cycles.iter().any(|b| b.status == BusStatus::MemRead && b.address == 0x00020));
+        assert!(entry.bus_cycles.iter().all(|b| b.status != BusStatus::MemRead || b.cycle > inta[1].cycle));
+    }
+
+    #[test]
+    fn test_disassemble_value_radix() {
+        let mut cpu = test_cpu();
+
+        // mov al, 0A5h ; mov ax, [bx-2]
+        load_code(&mut cpu, &[0xB0, 0xA5, 0x8B, 0x47, 0xFE]);
+
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 0));
+        let (value, width) = result.tokens.iter().find_map(|t| match t {
+            SyntaxToken::HexValue(v, w, s) if s == "A5h" => Some((*v, *w)),
+            _ => None
+        }).unwrap();
+        assert_eq!((value, width), (0xA5, 1));
+        assert_eq!(ValueRadix::Hex.format(value, width), None);
+        assert_eq!(ValueRadix::Binary.format(value, width).unwrap(), "0b10100101");
+        assert_eq!(ValueRadix::Octal.format(value, width).unwrap(), "0o245");
+
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 2));
+        let (value, width) = result.tokens.iter().find_map(|t| match t {
+            SyntaxToken::Displacement(v, w, _) => Some((*v, *w)),
+            _ => None
+        }).unwrap();
+        assert_eq!((value, width), (0xFE, 1));
+        assert_eq!(ValueRadix::Binary.format(value, width).unwrap(), "0b11111110");
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        use crate::breakpoints::CompareOp;
+
+        let mut cpu = test_cpu();
+
+        // mov ax, 4C00h ; xor bx, bx ; nop ; nop
+        load_code(&mut cpu, &[0xB8, 0x00, 0x4C, 0x31, 0xDB, 0x90, 0x90]);
+
+        cpu.add_conditional_breakpoint(
+            BreakCondition::Reg16(Register16::AX, CompareOp::Eq, 0x4C00)
+                .and(BreakCondition::Reg8(Register8::AH, CompareOp::Eq, 0x4C))
+        );
+        cpu.add_conditional_breakpoint(BreakCondition::Flag(Flag::Zero, true));
+
+        // The register condition trips before the instruction following the mov
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+        assert_eq!(cpu.ip, 3);
+        cpu.clear_breakpoint_flag();
+
+        // The flag condition trips after xor sets ZF. The register condition, still true, 
+        // doesn't trip again.
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+        assert_eq!(cpu.ip, 5);
+        cpu.clear_breakpoint_flag();
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+
+        assert!(cpu.eval_condition(&BreakCondition::Reg16(Register16::BX, CompareOp::Lt, 1)
+            .or(BreakCondition::Flag(Flag::Carry, true))));
+        assert!(!cpu.eval_condition(&BreakCondition::Reg8(Register8::AL, CompareOp::Gt, 0)));
+
+        cpu.clear_conditional_breakpoints();
+        cpu.set_register16(Register16::AX, 0);
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+    }
+
+    #[test]
+    fn test_trace_microcode() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let records: Rc<RefCell<Vec<TraceRecord>>> = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = test_cpu();
+
+        // mov ax, 1234h ; nop
+        load_code(&mut cpu, &[0xB8, 0x34, 0x12, 0x90]);
+
+        let records_cb = records.clone();
+        cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+            records_cb.borrow_mut().push(record.clone());
+        }));
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        cpu.clear_trace_callback();
+
+        let records = records.borrow();
+        let mc = MICROCODE_ADDRESS_8088[0xB8];
+        assert_eq!(records[0].microcode_start, Some(mc));
+        assert_eq!(records[1].microcode_start, Some(MICROCODE_ADDRESS_8088[0x90]));
+
+        // One microcode line per cycle, starting from the opcode's entry point
+        assert_eq!(records[0].microcode.len(), records[0].cycles as usize);
+        assert!(records[0].microcode.contains(&mc));
+
+        let line = format!("{:#}", records[0]);
+        assert!(line.contains(&format!("| MC {:03X} |", mc)));
+        assert!(!format!("{}", records[0]).contains("| MC "));
+
+        let mut writer = TraceRecordWriter::new(Vec::new());
+        writer.set_microcode(true);
+        writer.write(&records[0]).unwrap();
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), format!("{}\n", line));
+
+        // Other CPU types have no microcode addresses
+        let mut cpu = test_cpu_type(CpuType::NecV20);
+        let i = cpu.disassemble_at(CpuAddress::Flat(0)).instruction.unwrap();
+        assert_eq!(cpu.microcode_address(&i), None);
+    }
+
+    #[test]
+    fn test_run_until_return() {
+        let mut cpu = test_cpu();
+
+        // 0000: call 0010 ; hlt
+        // 0010: call 0020 ; nop ; ret
+        // 0020: nop ; ret
+        let main = [0xE8, 0x0D, 0x00, 0xF4];
+        let func = [0xE8, 0x0D, 0x00, 0x90, 0xC3];
+        let inner = [0x90, 0xC3];
+        load_code(&mut cpu, &main);
+        cpu.bus_mut().debug_write(0x10010, &func).unwrap();
+        cpu.bus_mut().debug_write(0x10020, &inner).unwrap();
+
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        // Enter the function. Returning from the nested call to 0013 must not stop the step out.
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0010);
+        assert_eq!(cpu.run_until_return(false, 100).unwrap(), StepOverResult::Completed);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+        assert_eq!(cpu.get_register16(Register16::SP), 0x0400);
+
+        // A routine that never returns exhausts the instruction budget.
+        let mut cpu = test_cpu();
+
+        // 0000: call 0030
+        // 0030: jmp 0030
+        load_code(&mut cpu, &[0xE8, 0x2D, 0x00]);
+        cpu.bus_mut().debug_write(0x10030, &[0xEB, 0xFE]).unwrap();
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0400);
+
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0030);
+        assert_eq!(cpu.run_until_return(false, 50).unwrap(), StepOverResult::TimedOut);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0030);
+    }
+
+    #[test]
+    fn test_strict_undefined_flags() {
+
+        const OSZAPC: u16 = CPU_FLAG_OVERFLOW | CPU_FLAG_SIGN | CPU_FLAG_ZERO | CPU_FLAG_AUX_CARRY | CPU_FLAG_PARITY | CPU_FLAG_CARRY;
+
+        // Execute one instruction with AL, BL and CL loaded and all flags in 'mask' preset,
+        // returning the flags afterwards.
+        let run = |code: &[u8], al: u8, bl: u8, cl: u8, mask: u16, strict: bool| {
+            let mut cpu = setup_cpu(
+                code,
+                [CpuOption::StrictUndefinedFlags(strict)],
+                &[(Register16::AX, al as u16), (Register16::BX, bl as u16), (Register16::CX, cl as u16)]
+            );
+            cpu.set_flags(mask);
+            cpu.step(false).unwrap();
+            cpu.flags & OSZAPC
+        };
+
+        // and al, 0F: AF is cleared in strict mode, and left alone otherwise.
+        let and = [0x24, 0x0F];
+        assert_eq!(run(&and, 0x3C, 0, 0, CPU_FLAG_AUX_CARRY, true) & CPU_FLAG_AUX_CARRY, 0);
+        assert_ne!(run(&and, 0x3C, 0, 0, CPU_FLAG_AUX_CARRY, false) & CPU_FLAG_AUX_CARRY, 0);
+
+        // Expected values follow the hardware behavior, worked step by step below. In strict mode
+        // the cpu_validator compares these flags against a real 8088 instead of masking them.
+        // AF after a shift is not modelled, so the shift cases leave it out of the comparison.
+        const OSZPC: u16 = OSZAPC & !CPU_FLAG_AUX_CARRY;
+
+        // (code, al, bl, cl, preset flags, compared flags, expected flags)
+        let cases: [(&[u8], u8, u8, u8, u16, u16, u16); 9] = [
+            // mul bl: 10h * 10h = 0100h. AH is nonzero, so OF=CF=1. The microcode sets the
+            // remaining flags on a PASS of AH=01h: not zero, positive, odd parity, no AF.
+            (&[0xF6, 0xE3], 0x10, 0x10, 0, OSZAPC, OSZAPC, CPU_FLAG_OVERFLOW | CPU_FLAG_CARRY),
+            // mul bl: 02h * 03h = 0006h. PASS of AH=00h: ZF=1, PF=1. ZF is preset clear so a 
+            // leftover value can't satisfy the test.
+            (&[0xF6, 0xE3], 0x02, 0x03, 0, OSZAPC & !CPU_FLAG_ZERO, OSZAPC, CPU_FLAG_ZERO | CPU_FLAG_PARITY),
+            // imul bl: -1 * 1 = FFFFh. The product fits in AL, so OF=CF=0. The microcode sets 
+            // flags on AH + 0 + (sign of AL): FFh + 0 + 1 = 00h with a carry out of bit 3,
+            // giving ZF=1, PF=1 and AF=1.
+            (&[0xF6, 0xEB], 0xFF, 0x01, 0, 0, OSZAPC, CPU_FLAG_ZERO | CPU_FLAG_AUX_CARRY | CPU_FLAG_PARITY),
+            // imul bl: 40h * 04h = 0100h. OF=CF=1. 01h + 0 + 0 = 01h: no other flags.
+            (&[0xF6, 0xEB], 0x40, 0x04, 0, OSZAPC, OSZAPC, CPU_FLAG_OVERFLOW | CPU_FLAG_CARRY),
+            // shl al, cl: the 8088 shifts one bit per microcode loop, so OF is that of the last 
+            // single-bit shift. 60h -> C0h -> 80h, CF=1. OF = MSB ^ CF = 0.
+            (&[0xD2, 0xE0], 0x60, 0, 2, CPU_FLAG_OVERFLOW, OSZPC, CPU_FLAG_SIGN | CPU_FLAG_CARRY),
+            // shl al, cl: 40h -> 80h -> 00h, CF=1. OF = 0 ^ 1 = 1.
+            (&[0xD2, 0xE0], 0x40, 0, 2, 0, OSZPC, CPU_FLAG_OVERFLOW | CPU_FLAG_ZERO | CPU_FLAG_PARITY | CPU_FLAG_CARRY),
+            // shr al, cl: 80h -> 40h -> 20h -> 10h, CF=0. The last step shifted in a zero
+            // below a zero MSB, so OF=0 even though it was preset.
+            (&[0xD2, 0xE8], 0x80, 0, 3, CPU_FLAG_OVERFLOW, OSZPC, 0),
+            // ror al, cl: 01h -> 80h -> 40h, CF=0. OF = MSB ^ MSB-1 = 1.
+            (&[0xD2, 0xC8], 0x01, 0, 2, 0, OSZPC, CPU_FLAG_OVERFLOW),
+            // rcr al, cl: 01h, CF=0 -> 00h, CF=1 -> 80h, CF=0. OF = 1 ^ 0 = 1.
+            (&[0xD2, 0xD8], 0x01, 0, 2, 0, OSZPC, CPU_FLAG_OVERFLOW),
+        ];
+
+        for (n, (code, al, bl, cl, preset, compared, expected)) in cases.iter().enumerate() {
+            let flags = run(code, *al, *bl, *cl, *preset, true) & compared;
+            assert_eq!(
+                flags, *expected, 
+                "case {}: flags {} expected {}", n, FlagsAffected::mask_string(flags), FlagsAffected::mask_string(*expected)
+            );
+        }
+
+        // Without strict mode, OF is left unchanged by a multi-bit shift.
+        assert_eq!(run(&[0xD2, 0xE8], 0x80, 0, 3, CPU_FLAG_OVERFLOW, false) & CPU_FLAG_OVERFLOW, CPU_FLAG_OVERFLOW);
+    }
+
+    #[cfg(feature = "cpu_coverage")]
+    #[test]
+    fn test_coverage_map() {
+        let mut cpu = test_cpu();
+
+        // mov [bx+si], ax ; add ax, bx ; nop ; nop
+        let code = [0x89, 0x00, 0x01, 0xD8, 0x90, 0x90];
+        load_code(&mut cpu, &code);
+        cpu.set_register16(Register16::DS, 0x2000);
+
+        for _ in 0..4 {
+            cpu.step(false).unwrap();
+        }
+        // The second nop is the same combination as the first.
+        assert_eq!(cpu.coverage().count(), 3);
+
+        let total = CoverageMap::decodable(CpuType::Intel8088);
+        // mov rm16, r16: 8 reg values by 24 memory modes plus register mode.
+        let report = cpu.coverage().report(&total);
+        assert!(report.contains("89: 1/200\n"));
+        assert!(report.contains("01: 1/200\n"));
+        assert!(report.contains("90: 1/1\n"));
+        assert!(report.contains("F4: 0/1 (never executed)"));
+        assert!(!report.contains("\n26:"));
+
+        cpu.coverage.clear();
+        assert_eq!(cpu.coverage().count(), 0);
+    }
+
+    #[test]
+    fn test_jump_sanity() {
+
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // 1000:0000 jmp short 0010 ; 1000:0010 jmp far 2000:0000
+        let setup = |cpu: &mut Cpu| {
+            load_code(cpu, &[0xEB, 0x0E]);
+            // Written through the bus, so the jump target is marked as written memory
+            for (n, byte) in [0xEA, 0x00, 0x00, 0x00, 0x20].iter().enumerate() {
+                cpu.bus_mut().write_u8(0x10010 + n, *byte, 0).unwrap();
+            }
+        };
+
+        // Disabled by default
+        let mut cpu = test_cpu();
+        setup(&mut cpu);
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+
+        // Jumping into written memory is fine, but 20000 has never been written so the far 
+        // jump breaks, with CS:IP at the destination.
+        let mut cpu = test_cpu();
+        setup(&mut cpu);
+        cpu.set_option(CpuOption::JumpSanity(true));
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, _))));
+        assert_eq!(cpu.get_register16(Register16::CS), 0x2000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0000);
+
+        // The callback receives the jump and destination addresses
+        let mut cpu = test_cpu();
+        setup(&mut cpu);
+        cpu.set_option(CpuOption::JumpSanity(true));
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let calls_cb = calls.clone();
+        cpu.set_bad_jump_callback(Box::new(move |address, target| {
+            calls_cb.borrow_mut().push((address, target));
+            RunawayAction::Ignore
+        }));
+        cpu.step(false).unwrap();
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert_eq!(*calls.borrow(), vec![(0x10010, 0x20000)]);
+
+        // An ignored bad call can still be stepped over. call far 3000:0000
+        let mut cpu = test_cpu();
+        load_code(&mut cpu, &[0x9A, 0x00, 0x00, 0x00, 0x30]);
+        cpu.set_option(CpuOption::JumpSanity(true));
+        cpu.set_bad_jump_callback(Box::new(|_, _| RunawayAction::Ignore));
+        assert!(matches!(
+            cpu.step(false), 
+            Ok((StepResult::Call(CpuAddress::Segmented(0x1000, 0x0005)), _))
+        ));
+    }
+
+    #[test]
+    fn test_disassemble_comments() {
+        let mut cpu = test_cpu();
+
+        // nop ; nop
+        load_code(&mut cpu, &[0x90, 0x90]);
+
+        cpu.set_comments(CommentMap::parse("1000:0001=second nop").unwrap());
+
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 0));
+        assert!(!result.tokens.iter().any(|t| matches!(t, SyntaxToken::Comment(_))));
+
+        // The comment is the last token on the line
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 1));
+        assert!(matches!(result.tokens.last(), Some(SyntaxToken::Comment(s)) if s == "second nop"));
+
+        cpu.comments_mut().remove(CpuAddress::Segmented(0x1000, 1));
+        let result = cpu.disassemble_at(CpuAddress::Segmented(0x1000, 1));
+        assert!(!result.tokens.iter().any(|t| matches!(t, SyntaxToken::Comment(_))));
+    }
+
+    #[test]
+    fn test_address_wraparound() {
+        assert_eq!(Cpu::calc_linear_address(0xFFFF, 0x0010), 0x00000);
+        assert_eq!(Cpu::calc_linear_address(0xFFFF, 0xFFFF), 0x0FFEF);
+
+        let mut cpu = test_cpu();
+
+        // mov al, [0010h] ; mov bx, [000Fh]
+        let code = [0xA0u8, 0x10, 0x00, 0x8B, 0x1E, 0x0F, 0x00];
+        load_code(&mut cpu, &code);
+        cpu.bus_mut().write_u8(0x00000, 0xAB, 0).unwrap();
+        cpu.bus_mut().write_u8(0xFFFFF, 0xCD, 0).unwrap();
+        cpu.set_register16(Register16::DS, 0xFFFF);
+
+        // FFFF:0010 aliases physical address 0
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register8(Register8::AL), 0xAB);
+
+        // A word at FFFF:000F takes its high byte from physical address 0
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::BX), 0xABCD);
+    }
+
+    /// Set up a CPU for trap tests, with the code at 1000:0000, a stack at 3000:0100 and the 
+    /// trap handler at 2000:0000. The handler is a single IRET.
+    fn trap_test_cpu<'a>(code: &[u8]) -> Cpu<'a> {
+        let mut cpu = test_cpu();
+
+        load_code(&mut cpu, code);
+        cpu.bus_mut().write_u16(0x0004, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u16(0x0006, 0x2000, 0).unwrap();
+        cpu.bus_mut().write_u8(0x20000, 0xCF, 0).unwrap();
+
+        cpu.set_register16(Register16::SS, 0x3000);
+        cpu.set_register16(Register16::SP, 0x0100);
+        cpu
+    }
+
+    /// Step the CPU until it reaches 1000:'end_ip', returning the address each IRET from the 
+    /// trap handler returned to. The return address is taken from CS:IP after the IRET rather 
+    /// than read from the stack, as the last write of the trap's stack frame may still be in 
+    /// progress on the bus when the step that took the trap returns. If TF is still set on
+    /// reaching 'end_ip', the trap that follows the last instruction is taken first.
+    fn trap_returns(cpu: &mut Cpu, end_ip: u16) -> Vec<(u16, u16)> {
+        let mut returns = Vec::new();
+        let mut returned = false;
+        for _ in 0..100 {
+            if cpu.get_register16(Register16::CS) == 0x1000 && cpu.get_register16(Register16::IP) == end_ip
+                && (returned || !cpu.get_flag(Flag::Trap))
+            {
+                return returns
+            }
+            let in_handler = cpu.get_register16(Register16::CS) == 0x2000;
+            cpu.step(false).unwrap();
+            returned = in_handler;
+            if in_handler {
+                returns.push((cpu.get_register16(Register16::CS), cpu.get_register16(Register16::IP)));
+            }
+        }
+        panic!("Didn't reach 1000:{:04X}", end_ip);
+    }
+
+    #[test]
+    fn test_trap_popf() {
+        let code = [
+            0x9C, 0x58, 0x0D, 0x00, 0x01, 0x50, // pushf ; pop ax ; or ax, 0100h ; push ax
+            0x9D,                               // 0006: popf (sets TF)
+            0x90,                               // 0007: nop
+            0x90,                               // 0008: nop
+            0x9C, 0x58, 0x25, 0xFF, 0xFE, 0x50, // 0009: pushf ; pop ax ; and ax, FEFFh ; push ax
+            0x9D,                               // 000F: popf (clears TF)
+            0x90, 0x90,                         // 0010: nop ; nop
+        ];
+        let mut cpu = trap_test_cpu(&code);
+
+        // The instruction following the POPF that set TF is executed before the first trap.
+        // Each IRET from the trap handler then executes one instruction, and the POPF that
+        // clears TF is itself trapped.
+        let returns = trap_returns(&mut cpu, 0x0012);
+        let ips: Vec<u16> = returns.iter().map(|(_, ip)| *ip).collect();
+        assert_eq!(ips, vec![0x0008, 0x0009, 0x000A, 0x000B, 0x000E, 0x000F, 0x0010]);
+        assert!(!cpu.get_flag(Flag::Trap));
+    }
+
+    #[test]
+    fn test_trap_int() {
+        let code = [
+            0x9C, 0x58, 0x0D, 0x00, 0x01, 0x50, // pushf ; pop ax ; or ax, 0100h ; push ax
+            0x9D,                               // 0006: popf (sets TF)
+            0xCD, 0x80,                         // 0007: int 80h
+            0x90,                               // 0009: nop
+            0x90,                               // 000A: nop
+        ];
+        let mut cpu = trap_test_cpu(&code);
+
+        // int 80h handler at 2100:0000 is nop ; iret
+        cpu.bus_mut().write_u16(0x80 * 4, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u16(0x80 * 4 + 2, 0x2100, 0).unwrap();
+        cpu.bus_mut().write_u8(0x21000, 0x90, 0).unwrap();
+        cpu.bus_mut().write_u8(0x21001, 0xCF, 0).unwrap();
+
+        // Single-stepping the INT stops at the first instruction of its handler, which then
+        // runs with TF clear. The IRET restores TF, so the instruction after the INT executes 
+        // before the next trap.
+        let returns = trap_returns(&mut cpu, 0x000B);
+        assert_eq!(returns, vec![(0x2100, 0x0000), (0x1000, 0x000A), (0x1000, 0x000B)]);
+    }
+
+    #[test]
+    fn test_trap_hw_interrupt() {
+        let code = [
+            0x9C, 0x58, 0x0D, 0x00, 0x01, 0x50, // pushf ; pop ax ; or ax, 0100h ; push ax
+            0x9D,                               // 0006: popf (sets TF)
+            0x90,                               // 0007: nop
+            0x90,                               // 0008: nop
+        ];
+        let mut cpu = trap_test_cpu(&code);
+
+        // Vector 8 handler at 2200:0000 is nop ; iret
+        cpu.bus_mut().write_u16(8 * 4, 0x0000, 0).unwrap();
+        cpu.bus_mut().write_u16(8 * 4 + 2, 0x2200, 0).unwrap();
+        cpu.bus_mut().write_u8(0x22000, 0x90, 0).unwrap();
+        cpu.bus_mut().write_u8(0x22001, 0xCF, 0).unwrap();
+
+        for _ in 0..5 {
+            cpu.step(false).unwrap();
+        }
+        assert!(cpu.get_flag(Flag::Trap));
+
+        // A hardware interrupt taken before the next instruction clears TF on entry...
+        cpu.hw_interrupt(8);
+        assert!(!cpu.get_flag(Flag::Trap));
+        cpu.step(false).unwrap();
+        assert_eq!((cpu.get_register16(Register16::CS), cpu.get_register16(Register16::IP)), (0x2200, 0x0001));
+
+        // ...and IRET restores it, so tracing resumes after the interrupted instruction.
+        let returns = trap_returns(&mut cpu, 0x0009);
+        assert!(cpu.get_flag(Flag::Trap));
+        assert_eq!(returns, vec![(0x1000, 0x0008), (0x1000, 0x0009)]);
+    }
+
+    #[test]
+    fn test_memory_sizing() {
+        let mut cpu = test_cpu();
+        cpu.bus_mut().set_installed_ram(0x20000);
+        cpu.reset_vector = CpuAddress::Segmented(0x1F00, 0);
+        cpu.reset();
+
+        // Probe each 16K block with a test pattern, as the BIOS POST does, until one doesn't
+        // read back.
+        let code = [
+            0x31, 0xC0,                                     // 0000: xor ax, ax
+            0x8E, 0xC0,                                     // 0002: mov es, ax
+            0x26, 0xC7, 0x06, 0x00, 0x00, 0x55, 0xAA,       // 0004: mov word es:[0], AA55h
+            0x26, 0x81, 0x3E, 0x00, 0x00, 0x55, 0xAA,       // 000B: cmp word es:[0], AA55h
+            0x75, 0x09,                                     // 0012: jnz 001D
+            0x8C, 0xC0,                                     // 0014: mov ax, es
+            0x05, 0x00, 0x04,                               // 0016: add ax, 0400h
+            0x8E, 0xC0,                                     // 0019: mov es, ax
+            0xEB, 0xE7,                                     // 001B: jmp 0004
+            0xF4,                                           // 001D: hlt
+        ];
+        cpu.bus_mut().debug_write(0x1F000, &code).unwrap();
+
+        let mut steps = 0;
+        while cpu.get_register16(Register16::IP) != 0x001D {
+            cpu.step(false).unwrap();
+            steps += 1;
+            assert!(steps < 200, "memory sizing loop didn't terminate");
+        }
+        let detected = (cpu.get_register16(Register16::ES) as usize) << 4;
+        assert_eq!(detected, cpu.bus().installed_ram());
+    }
+
+    #[test]
+    fn test_mov_cs() {
+        let mut cpu = test_cpu();
+
+        let code = [
+            0xBE, 0x00, 0x20,   // 0000: mov si, 2000h
+            0xF7, 0xE1,         // 0003: mul cx (long enough to fill the queue)
+            0x8E, 0xCE,         // 0005: mov cs, si
+        ];
+        load_code(&mut cpu, &code);
+        // inc bx in the old segment, inc dx at the same offsets in the new one
+        for n in 0..0x20 {
+            cpu.bus_mut().write_u8(0x10007 + n, 0x43, 0).unwrap();
+            cpu.bus_mut().write_u8(0x20007 + n, 0x42, 0).unwrap();
+        }
+        cpu.set_register16(Register16::BX, 0);
+        cpu.set_register16(Register16::DX, 0);
+
+        for _ in 0..3 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x2000, 0x0007));
+
+        for _ in 0..8 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x2000, 0x000F));
+
+        // The queue isn't flushed. The MUL filled it, so the two bytes after MOV CS were 
+        // prefetched from the old segment and run first.
+        assert_eq!(cpu.get_register16(Register16::BX), 2);
+        assert_eq!(cpu.get_register16(Register16::DX), 6);
+    }
+
+    #[test]
+    fn test_trap_mov_ss() {
+        let code = [
+            0x9C, 0x58, 0x0D, 0x00, 0x01, 0x50, // pushf ; pop ax ; or ax, 0100h ; push ax
+            0x9D,                               // 0006: popf (sets TF)
+            0x8C, 0xD0,                         // 0007: mov ax, ss
+            0x8E, 0xD0,                         // 0009: mov ss, ax
+            0x90,                               // 000B: nop
+            0x90,                               // 000C: nop
+        ];
+        let mut cpu = trap_test_cpu(&code);
+
+        // No trap is taken between the load of SS and the following instruction
+        let returns = trap_returns(&mut cpu, 0x000D);
+        assert_eq!(returns, vec![(0x1000, 0x0009), (0x1000, 0x000C), (0x1000, 0x000D)]);
+    }
+
+    #[test]
+    fn test_replay() {
+        use std::{cell::Cell, rc::Rc};
+        use crate::bus::{DeviceRunTimeUnit, IoDevice};
+        use crate::cpu_808x::replay::ReplayEvent;
+
+        // A device whose reads change each time, standing in for a timer or keyboard
+        struct CounterDevice {
+            count: Rc<Cell<u8>>,
+        }
+
+        impl IoDevice for CounterDevice {
+            fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+                self.count.set(self.count.get().wrapping_add(7));
+                self.count.get()
+            }
+            fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {}
+            fn port_list(&self) -> Vec<u16> {
+                vec![0x300]
+            }
+        }
+
+        fn replay_cpu<'a>() -> Cpu<'a> {
+            let mut cpu = Cpu::new(
+                CpuType::Intel8088,
+                TraceMode::Cycle,
+                None::<std::io::Sink>,
+                #[cfg(feature = "cpu_validator")]
+                ValidatorType::None,
+                #[cfg(feature = "cpu_validator")]
+                TraceLogger::None,
+            );
+            cpu.set_option(CpuOption::TraceLoggingEnabled(true));
+
+            // mov dx, 0300h ; in al, dx ; add bl, al ; inc si ; inc si ; inc si ; inc si ; jmp 0003
+            let code = [0xBAu8, 0x00, 0x03, 0xEC, 0x00, 0xC3, 0x46, 0x46, 0x46, 0x46, 0xEB, 0xF7];
+            load_code(&mut cpu, &code);
+            // NMI handler at 0000:0600 is inc cx ; iret
+            cpu.bus_mut().write_u16(2 * 4, 0x0600, 0).unwrap();
+            cpu.bus_mut().write_u16(2 * 4 + 2, 0x0000, 0).unwrap();
+            cpu.bus_mut().write_u8(0x00600, 0x41, 0).unwrap();
+            cpu.bus_mut().write_u8(0x00601, 0xCF, 0).unwrap();
+            cpu.set_register16(Register16::SP, 0x0400);
+            cpu
+        }
+
+        fn run(cpu: &mut Cpu, steps: usize, nmi_at: &[usize]) -> Vec<String> {
+            let mut trace = Vec::new();
+            for n in 0..steps {
+                if nmi_at.contains(&n) {
+                    cpu.raise_nmi();
+                    cpu.lower_nmi();
+                }
+                cpu.step(false).unwrap();
+                trace.extend(cpu.get_cycle_trace().iter().cloned());
+            }
+            trace
+        }
+
+        let mut cpu = replay_cpu();
+        cpu.bus_mut().register_io_device(Box::new(CounterDevice { count: Rc::new(Cell::new(0)) })).unwrap();
+        cpu.start_recording();
+        // The second NMI is raised in the run of INC instructions, away from any IO read
+        let recorded_trace = run(&mut cpu, 60, &[17, 40]);
+        let log = cpu.stop_recording().unwrap();
+        assert_eq!(cpu.get_register16(Register16::CX), 2);
+        assert_eq!(log.events.iter().filter(|e| matches!(e, ReplayEvent::Nmi { .. })).count(), 2);
+        assert!(log.events.iter().filter(|e| matches!(e, ReplayEvent::IoRead { .. })).count() > 5);
+
+        // The replaying CPU has no device on the port and never sees the NMI line, so it can
+        // only reproduce the run from the log.
+        let mut replay = replay_cpu();
+        replay.start_replay(log);
+        let replayed_trace = run(&mut replay, 60, &[]);
+        assert!(!replay.is_replaying());
+
+        assert!(!recorded_trace.is_empty());
+        assert_eq!(replayed_trace, recorded_trace);
+        assert_eq!(replay.get_register16(Register16::BX), cpu.get_register16(Register16::BX));
+        assert_eq!(replay.get_register16(Register16::CX), 2);
+        assert_eq!(replay.get_register16(Register16::SI), cpu.get_register16(Register16::SI));
+        assert_eq!(replay.get_csip(), cpu.get_csip());
+    }
+
+    #[test]
+    fn test_inc_dec_flags() {
+        let mut cpu = test_cpu();
+
+        let flags = |cpu: &Cpu| [
+            cpu.get_flag(Flag::Overflow),
+            cpu.get_flag(Flag::Sign),
+            cpu.get_flag(Flag::Zero),
+            cpu.get_flag(Flag::AuxCarry),
+            cpu.get_flag(Flag::Parity),
+        ];
+
+        // Operation, operand, result, [OF, SF, ZF, AF, PF]
+        let cases16: [(Mnemonic, u16, u16, [bool; 5]); 6] = [
+            (Mnemonic::INC, 0x7FFF, 0x8000, [true, true, false, true, true]),
+            (Mnemonic::DEC, 0x8000, 0x7FFF, [true, false, false, true, true]),
+            (Mnemonic::DEC, 0x0000, 0xFFFF, [false, true, false, true, true]),
+            (Mnemonic::INC, 0xFFFF, 0x0000, [false, false, true, true, true]),
+            (Mnemonic::DEC, 0x0001, 0x0000, [false, false, true, false, true]),
+            (Mnemonic::INC, 0x0003, 0x0004, [false, false, false, false, false]),
+        ];
+        let cases8: [(Mnemonic, u8, u8, [bool; 5]); 4] = [
+            (Mnemonic::INC, 0x7F, 0x80, [true, true, false, true, false]),
+            (Mnemonic::DEC, 0x80, 0x7F, [true, false, false, true, false]),
+            (Mnemonic::DEC, 0x00, 0xFF, [false, true, false, true, true]),
+            (Mnemonic::INC, 0xFF, 0x00, [false, false, true, true, true]),
+        ];
+
+        // CF is left as it was, whether set or clear
+        for carry in [false, true] {
+            for (op, operand, expected, expected_flags) in cases16 {
+                cpu.set_flag_state(Flag::Carry, carry);
+                assert_eq!(cpu.math_op16(op, operand, 0), expected);
+                assert_eq!(flags(&cpu), expected_flags, "{:?} {:04X}", op, operand);
+                assert_eq!(cpu.get_flag(Flag::Carry), carry, "{:?} {:04X}", op, operand);
+            }
+            for (op, operand, expected, expected_flags) in cases8 {
+                cpu.set_flag_state(Flag::Carry, carry);
+                assert_eq!(cpu.math_op8(op, operand, 0), expected);
+                assert_eq!(flags(&cpu), expected_flags, "{:?} {:02X}", op, operand);
+                assert_eq!(cpu.get_flag(Flag::Carry), carry, "{:?} {:02X}", op, operand);
+            }
+        }
+
+        // The same through each encoding: 40-4F, FE /0 and FF /1
+        let code = [
+            0xF9,           // stc
+            0x40,           // inc ax
+            0xFE, 0xC3,     // inc bl
+            0xFF, 0xC9,     // dec cx
+            0x4A,           // dec dx
+        ];
+        load_code(&mut cpu, &code);
+        cpu.set_register16(Register16::AX, 0x7FFF);
+        cpu.set_register16(Register16::BX, 0x00FF);
+        cpu.set_register16(Register16::CX, 0x8000);
+        cpu.set_register16(Register16::DX, 0x0000);
+
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::AX), 0x8000);
+        assert_eq!(flags(&cpu), [true, true, false, true, true]);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::BX), 0x0000);
+        assert_eq!(flags(&cpu), [false, false, true, true, true]);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::CX), 0x7FFF);
+        assert_eq!(flags(&cpu), [true, false, false, true, true]);
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::DX), 0xFFFF);
+        assert_eq!(flags(&cpu), [false, true, false, true, true]);
+        assert!(cpu.get_flag(Flag::Carry));
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let mut cpu = test_cpu();
+
+        // mov ax, 1234h; nop; jmp $
+        load_code(&mut cpu, &[0xB8, 0x34, 0x12, 0x90, 0xEB, 0xFE]);
+        // mov ax, imm16 truncated by the end of memory
+        cpu.bus_mut().write_u8(0xFFFFE, 0xB8, 0).unwrap();
+        cpu.bus_mut().write_u8(0xFFFFF, 0x34, 0).unwrap();
+
+        let listing: Vec<(u32, usize)> = cpu.disassemble_range(0x10000, 0x10006).map(|(a, r)| (a, r.size)).collect();
+        assert_eq!(listing, vec![(0x10000, 3), (0x10003, 1), (0x10004, 2)]);
+
+        // An instruction starting before the end is returned in full
+        let listing: Vec<(u32, usize)> = cpu.disassemble_range(0x10000, 0x10005).map(|(a, r)| (a, r.size)).collect();
+        assert_eq!(listing, vec![(0x10000, 3), (0x10003, 1), (0x10004, 2)]);
+
+        // Starting mid-instruction decodes 'xor al, 12h'
+        let listing: Vec<(u32, usize)> = cpu.disassemble_range(0x10001, 0x10004).map(|(a, r)| (a, r.size)).collect();
+        assert_eq!(listing, vec![(0x10001, 2), (0x10003, 1)]);
+
+        assert_eq!(cpu.disassemble_range(0x10004, 0x10004).count(), 0);
+
+        // Truncated instructions at the end of memory are skipped a byte at a time
+        let listing: Vec<(u32, DisassemblyResult)> = cpu.disassemble_range(0xFFFFE, 0x200000).collect();
+        assert_eq!(listing.len(), 2);
+        assert!(listing.iter().all(|(_, r)| r.instruction.is_none() && r.size == 1));
+        assert_eq!(cpu.ip, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "16-bit access to 8-bit operand")]
+    fn test_operand_size_assert() {
+        let mut cpu = test_cpu();
+        cpu.reset_vector = CpuAddress::Segmented(0x1000, 0);
+        cpu.reset();
+
+        assert!(OperandType::Register8(Register8::AL).size() == OperandSize::Operand8);
+        assert!(OperandType::Immediate16(0).size() == OperandSize::Operand16);
+        assert!(OperandType::NoOperand.size() == OperandSize::NoSize);
+
+        cpu.al = 0x12;
+        assert_eq!(cpu.read_operand8(OperandType::Register8(Register8::AL), SegmentOverride::None), Some(0x12));
+        cpu.read_operand16(OperandType::Register8(Register8::AL), SegmentOverride::None);
+    }
+
+    #[test]
+    fn test_break_on_interrupt() {
+        use crate::breakpoints::CompareOp;
+
+        let mut cpu = test_cpu();
+
+        // mov ah, 3Eh ; int 21h ; mov ah, 3Dh ; int 21h ; int 21h
+        load_code(&mut cpu, &[0xB4, 0x3E, 0xCD, 0x21, 0xB4, 0x3D, 0xCD, 0x21, 0xCD, 0x21]);
+        // INT 21h handler at 2000:0000 is a single IRET
+        set_ivt_entry(&mut cpu, 0x21, 0x2000, 0x0000);
+        cpu.bus_mut().debug_write(0x20000, &[0xCF]).unwrap();
+        cpu.set_register16(Register16::SS, 0x3000);
+        cpu.set_register16(Register16::SP, 0x0100);
+
+        cpu.break_on_interrupt(0x21, Some(BreakCondition::Reg8(Register8::AH, CompareOp::Eq, 0x3D)));
+
+        // The first call doesn't match the filter
+        for _ in 0..3 {
+            assert!(!matches!(cpu.step(false), Ok((StepResult::BreakpointHit, _))));
+        }
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0004));
+
+        // The second call stops at the first instruction of the handler
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x2000, 0x0000));
+
+        // Stepping resumes normally once the breakpoint is cleared
+        cpu.clear_breakpoint_flag();
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x1000, 0x0008));
+
+        // An unconditional interrupt breakpoint survives replacing the breakpoint list
+        cpu.clear_interrupt_breakpoints();
+        cpu.break_on_interrupt(0x21, None);
+        cpu.set_breakpoints(Vec::new());
+        cpu.step(false).unwrap();
+        assert!(matches!(cpu.step(false), Ok((StepResult::BreakpointHit, 0))));
+    }
+
+    #[test]
+    fn test_ea_cycle_penalty() {
+
+        // Run aam to let the queue fill, then return the cycles taken by the instruction with the
+        // specified opcode and modrm.
+        fn ea_cycles(opcode: u8, modrm: &[u8]) -> u32 {
+            let mut cpu = test_cpu();
+            let code: Vec<u8> = [0xD4, 0x0A, opcode].iter().chain(modrm.iter()).copied().collect();
+            load_code(&mut cpu, &code);
+            cpu.set_register16(Register16::DS, 0x0000);
+            cpu.set_register16(Register16::BX, 0x0100);
+            cpu.set_register16(Register16::SI, 0x0010);
+
+            cpu.step(false).unwrap();
+            cpu.step(false).unwrap().1
+        }
+
+        // Documented EA calculation times are 5 for [bx], 7 for [bx+si] and [bp+di], 8 for 
+        // [bp+si] and [bx+di], 6 for a direct offset, and 4 more for a displacement. Each is
+        // checked as an offset from [bx], so the instruction's other cycles cancel out.
+        // lea performs no memory access, so the EA calculation can't overlap a prefetch.
+        let bx = ea_cycles(0x8D, &[0x07]);              // lea ax, [bx]
+        let cases: [(&[u8], u32, &str); 9] = [
+            (&[0x04], 5, "[si]"),
+            (&[0x00], 7, "[bx+si]"),
+            (&[0x03], 7, "[bp+di]"),
+            (&[0x02], 8, "[bp+si]"),
+            (&[0x01], 8, "[bx+di]"),
+            (&[0x06, 0x34, 0x12], 6, "[1234h]"),
+            (&[0x47, 0x12], 9, "[bx+12h]"),
+            (&[0x40, 0x12], 11, "[bx+si+12h]"),
+            (&[0x81, 0x34, 0x12], 12, "[bx+di+1234h]"),
+        ];
+        for (modrm, ea, mode) in cases {
+            assert_eq!(ea_cycles(0x8D, modrm) - bx, ea - 5, "EA cycles for {}", mode);
+        }
+
+        // The same penalties apply on a real memory read path.
+        let bx = ea_cycles(0x8B, &[0x07]);                      // mov ax, [bx]
+        let bx_si = ea_cycles(0x8B, &[0x00]);                   // mov ax, [bx+si]
+        let bx_si_disp8 = ea_cycles(0x8B, &[0x40, 0x12]);       // mov ax, [bx+si+12h]
+        let bx_si_disp16 = ea_cycles(0x8B, &[0x80, 0x34, 0x12]); // mov ax, [bx+si+1234h]
+        assert_eq!(bx_si - bx, 2);
+        assert_eq!(bx_si_disp8 - bx, 6);
+        assert_eq!(bx_si_disp16 - bx, 6);
+    }
+
+    #[test]
+    fn test_retire_callback() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let retired = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = test_cpu();
+
+        // mov cx, 3 ; rep stosb ; nop
+        let code = [0xB9u8, 0x03, 0x00, 0xF3, 0xAA, 0x90];
+        load_code(&mut cpu, &code);
+
+        let retired_cb = retired.clone();
+        cpu.set_retire_callback(Box::new(move |address: u32, opcode: u8, cycles: u32| {
+            retired_cb.borrow_mut().push((address, opcode, cycles));
+        }));
+
+        let mut steps = 0;
+        let mut total_cycles = 0;
+        while cpu.get_register16(Register16::IP) < code.len() as u16 {
+            total_cycles += cpu.step(false).unwrap().1;
+            steps += 1;
+        }
+        cpu.clear_retire_callback();
+
+        // The REP instruction takes several steps but retires once, with the cycles of every iteration
+        let retired = retired.borrow();
+        assert!(steps > retired.len());
+        assert_eq!(retired.iter().map(|r| (r.0, r.1)).collect::<Vec<_>>(), vec![(0x10000, 0xB9), (0x10003, 0xAA), (0x10005, 0x90)]);
+        assert_eq!(retired.iter().map(|r| r.2).sum::<u32>(), total_cycles);
+    }
+
+    #[test]
+    fn test_pop_cs_queue() {
+        let mut cpu = test_cpu();
+
+        // aam ; pop cs ; followed by inc ax in the old code segment and inc bx in the new one
+        load_code(&mut cpu, &[0xD4, 0x0A, 0x0F]);
+        for n in 3..16 {
+            cpu.bus_mut().write_u8(0x10000 + n, 0x40, 0).unwrap();
+            cpu.bus_mut().write_u8(0x20000 + n, 0x43, 0).unwrap();
+        }
+        cpu.set_register16(Register16::SS, 0x0000);
+        cpu.set_register16(Register16::SP, 0x0100);
+        cpu.bus_mut().write_u8(0x0100, 0x00, 0).unwrap();
+        cpu.bus_mut().write_u8(0x0101, 0x20, 0).unwrap();
+
+        // aam runs long enough to fill the queue
+        cpu.step(false).unwrap();
+        cpu.step(false).unwrap();
+        assert_eq!(cpu.get_register16(Register16::CS), 0x2000);
+        assert_eq!(cpu.get_register16(Register16::IP), 0x0003);
+        assert_eq!(cpu.get_register16(Register16::SP), 0x0102);
+
+        // The queue keeps the preloaded byte at offset 3 and the bytes at 4 and 5 from the old
+        // segment, and the next fetch continues at offset 6 in the new one.
+        assert!(cpu.queue.has_preload());
+        assert_eq!(cpu.queue.len(), 2);
+        assert_eq!(cpu.pc, 0x20006);
+
+        for _ in 0..10 {
+            cpu.step(false).unwrap();
+        }
+        assert_eq!(cpu.get_register16(Register16::AX), 3);
+        assert_eq!(cpu.get_register16(Register16::BX), 7);
+        assert_eq!(cpu.get_csip(), CpuAddress::Segmented(0x2000, 0x000D));
+    }
+
+    #[test]
+    fn test_debug_write_breakpoint() {
+        let mut cpu = test_cpu();
+
+        // Debug writes don't trip write breakpoints, while a CPU write to the same address does.
+        // mov ax, 1234h ; mov [0200h], al
+        load_code(&mut cpu, &[0xB8, 0x34, 0x12, 0xA2, 0x00, 0x02]);
+        cpu.add_breakpoint(0x200, BreakKind::Write);
+        cpu.set_register16(Register16::DS, 0x0000);
+        cpu.bus_mut().debug_write(0x200, &[0x01, 0x02]).unwrap();
+        assert!(matches!(cpu.step(false), Ok((StepResult::Normal, _))));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x1234);
+        assert_eq!(cpu.bus_mut().debug_read(0x200, 2).unwrap(), vec![0x01, 0x02]);
+        assert_eq!(cpu.last_breakpoint(), None);
+
+        _ = cpu.step(false);
+        assert_eq!(cpu.last_breakpoint(), Some((0x200, BreakKind::Write)));
+        assert_eq!(cpu.bus_mut().debug_read(0x200, 2).unwrap(), vec![0x34, 0x02]);
+    }
+
+    #[test]
+    fn test_bcd_adjust_microcode() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let records: Rc<RefCell<Vec<TraceRecord>>> = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cpu = test_cpu();
+
+        // nop ; daa ; das ; aaa ; aas ; mov al, 0Ah ; aaa ; aas
+        // The leading nop keeps the reset prefetch out of the daa trace.
+        let program = [0x90, 0x27, 0x2F, 0x37, 0x3F, 0xB0, 0x0A, 0x37, 0x3F];
+        load_code(&mut cpu, &program);
+        cpu.set_register16(Register16::AX, 0);
+
+        let records_cb = records.clone();
+        cpu.set_trace_callback(Box::new(move |record: &TraceRecord| {
+            records_cb.borrow_mut().push(record.clone());
+        }));
+        for _ in 0..8 {
+            cpu.step(false).unwrap();
+        }
+        cpu.clear_trace_callback();
+
+        let records = records.borrow();
+        let daa_das = vec![MC_NONE, 0x144, 0x145, 0x146];
+        assert_eq!(records[1].microcode, daa_das);
+        assert_eq!(records[2].microcode, daa_das);
+
+        // AAS always jumps at 14b rather than running 14c. No adjustment: the jump after 14d
+        // is taken.
+        assert_eq!(records[3].microcode, vec![MC_NONE, 0x148, 0x149, 0x14a, 0x14b, 0x14c, 0x14d, MC_JUMP, MC_JUMP]);
+        assert_eq!(records[4].microcode, vec![MC_NONE, 0x148, 0x149, 0x14a, 0x14b, MC_JUMP, 0x14d, MC_JUMP, MC_JUMP]);
+
+        // AL adjusted: AAA carries into AH and leaves AF set, so AAS adjusts back
+        // to 000A. Both fall through 14d without the jump
+        assert_eq!(cpu.get_register16(Register16::AX), 0x000A);
+        assert_eq!(records[6].microcode, vec![MC_NONE, 0x148, 0x149, 0x14a, 0x14b, 0x14c, 0x14d, 0x14d]);
+        assert_eq!(records[7].microcode, vec![MC_NONE, 0x148, 0x149, 0x14a, 0x14b, MC_JUMP, 0x14d, 0x14d]);
+        for record in &records[1..] {
+            assert_eq!(record.microcode.len(), record.cycles as usize);
+        }
+    }
+}
@@ -320,6 +320,23 @@ impl_cor_negate!(u16);
 
 impl<'a> Cpu<'a> {
 
+    /// SF, ZF, AF and PF are undefined after MUL and IMUL. In hardware they are left by the
+    /// ALU operation on the 'F' line of the MULCOF/IMULCOF routine: PASS of the high half of 
+    /// the product for MUL, and ADC of the high half with the sign of the low half for IMUL.
+    fn set_undefined_mul_flags8(&mut self, sigma: u8, aux_carry: bool) {
+        if self.strict_undefined_flags {
+            self.set_szp_flags_from_result_u8(sigma);
+            self.set_flag_state(Flag::AuxCarry, aux_carry);
+        }
+    }
+
+    fn set_undefined_mul_flags16(&mut self, sigma: u16, aux_carry: bool) {
+        if self.strict_undefined_flags {
+            self.set_szp_flags_from_result_u16(sigma);
+            self.set_flag_state(Flag::AuxCarry, aux_carry);
+        }
+    }
+
     #[allow (dead_code)]
     #[allow(unused_assignments)] // This isn't pretty but we are trying to mirror the microcode
     /// Microcode routine for multiplication, 8 bit
@@ -394,9 +411,11 @@ impl<'a> Cpu<'a> {
             tmpb = 0; 
             //(_, carry) = rcl_u8_with_carry(tmpc as u8, 1, carry);  // Test if tmpc is negative
             carry = tmpc & 0x80 != 0; // LRCY is just checking msb of tmpc
-            (sigma8, _, _, _) = (tmpa as u8).alu_adc(tmpb as u8, carry);
+            let aux_carry;
+            (sigma8, _, _, aux_carry) = (tmpa as u8).alu_adc(tmpb as u8, carry);
             self.cycles_i(3, &[0x1cd, 0x1ce, 0x1cf]);
             // SET FLAGS HERE
+            self.set_undefined_mul_flags8(sigma8, aux_carry);
 
             // 1d0:             | Z 8
             if sigma8 == 0 {
@@ -435,6 +454,7 @@ impl<'a> Cpu<'a> {
         // JMP
 
         self.cycles_i(6, &[0x155, 0x156, MC_JUMP, 0x1d2, 0x1d3, MC_JUMP]);
+        self.set_undefined_mul_flags8(sigma as u8, false);
         zf = sigma == 0;
 
         // 1d0:                | Z 8  (jump if zero)
@@ -524,9 +544,11 @@ impl<'a> Cpu<'a> {
             tmpb = 0; // 1cd
             //(_, carry) = rcl_u16_with_carry(tmpc, 1, carry);  // Test if tmpc is negative
             carry = tmpc & 0x8000 != 0; // 1cd: LRCY is just checking msb of tmpc
-            (sigma, _, _, _) = tmpa.alu_adc(tmpb, carry);
+            let aux_carry;
+            (sigma, _, _, aux_carry) = tmpa.alu_adc(tmpb, carry);
             self.cycles_i(3, &[0x1cd, 0x1ce, 0x1cf]);
             // Set flags here
+            self.set_undefined_mul_flags16(sigma, aux_carry);
 
             // 1d0:             | Z 8
             if sigma == 0 {
@@ -559,6 +581,7 @@ impl<'a> Cpu<'a> {
         // 1d3: SIGMA->.       | UNC 12  | F  (Set flags)
         // JMP
         self.cycles_i(6, &[0x15d, 0x15e, MC_JUMP, 0x1d2, 0x1d3, MC_JUMP]);
+        self.set_undefined_mul_flags16(sigma, false);
         zf = sigma == 0;
 
         // 1d0:                | Z 8  (jump if zero)
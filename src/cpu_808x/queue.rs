@@ -1,7 +1,9 @@
 use crate::cpu_808x::*;
 use crate::bytequeue::*;
 
-#[derive (Copy, Clone, PartialEq)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive (Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum QueueDelay {
     Read,
     Write,
@@ -14,6 +16,7 @@ impl Default for QueueDelay {
     }
 }
 
+#[derive (Clone, Serialize, Deserialize)]
 pub struct InstructionQueue {
     size: usize,
     len: usize,
@@ -45,9 +48,16 @@ impl InstructionQueue {
         }
     }
 
+    /// Set the size of the queue. This flushes the queue.
     pub fn set_size(&mut self, size: usize) {
-        assert!(size <= QUEUE_MAX);
+        assert!(size > 0 && size <= QUEUE_MAX);
         self.size = size;
+        self.flush();
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
     }
 
     #[inline]
@@ -170,6 +180,12 @@ impl InstructionQueue {
         base_str
     }
 
+    /// Return an iterator over the bytes currently in the processor instruction queue, 
+    /// from the next byte to be read to the most recently fetched.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len).map(move |i| self.q[(self.back + i) % self.size])
+    }
+
     /// Write the contents of the processor instruction queue in order to the
     /// provided slice of u8. The slice must be the same size as the current piq 
     /// length for the given cpu type.
@@ -0,0 +1,174 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::replay.rs
+
+    Implements recording and replaying the inputs to the CPU that come from
+    outside of it: the results of IO port reads, and the cycle at which each
+    NMI and INTR is taken, along with the vector read during INTA.
+
+    Given the same starting state and the same inputs, the CPU executes on
+    exactly the same cycles, so a replay reproduces a recorded run without
+    the devices that originally produced the inputs. The log starts with a
+    snapshot of the CPU; memory is not included and must be restored by the
+    caller before starting a replay.
+
+*/
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cpu_808x::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    IoRead { port: u16, byte: u8 },
+    Nmi { cycle: u64 },
+    Intr { cycle: u64 },
+    InterruptAck { vector: u8 },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayLog {
+    pub initial_state: CpuSnapshot,
+    pub events: Vec<ReplayEvent>,
+}
+
+pub enum ReplayMode {
+    Off,
+    Recording(ReplayLog),
+    Replaying(ReplayLog, usize),
+}
+
+impl Default for ReplayMode {
+    fn default() -> Self {
+        ReplayMode::Off
+    }
+}
+
+impl<'a> Cpu<'a> {
+
+    /// Start recording a replay log from the current CPU state.
+    pub fn start_recording(&mut self) {
+        self.replay = ReplayMode::Recording(ReplayLog {
+            initial_state: self.save_state(),
+            events: Vec::new(),
+        });
+    }
+
+    /// Stop recording and return the replay log, if one was being recorded.
+    pub fn stop_recording(&mut self) -> Option<ReplayLog> {
+        match std::mem::take(&mut self.replay) {
+            ReplayMode::Recording(log) => Some(log),
+            other => {
+                self.replay = other;
+                None
+            }
+        }
+    }
+
+    /// Restore the CPU to the initial state of a replay log and begin replaying its inputs.
+    /// Memory must already hold the contents it had when recording started.
+    pub fn start_replay(&mut self, log: ReplayLog) {
+        self.restore_state(&log.initial_state);
+        self.replay = ReplayMode::Replaying(log, 0);
+    }
+
+    pub fn stop_replay(&mut self) {
+        self.replay = ReplayMode::Off;
+    }
+
+    /// Returns true if a replay is in progress and has inputs left to replay.
+    pub fn is_replaying(&self) -> bool {
+        matches!(&self.replay, ReplayMode::Replaying(log, pos) if *pos < log.events.len())
+    }
+
+    /// Consume the next replay event if 'accept' returns true for it.
+    fn replay_next(&mut self, accept: impl Fn(&ReplayEvent) -> bool) -> Option<ReplayEvent> {
+        if let ReplayMode::Replaying(log, pos) = &mut self.replay {
+            if let Some(event) = log.events.get(*pos).filter(|e| accept(e)) {
+                *pos += 1;
+                return Some(*event)
+            }
+        }
+        None
+    }
+
+    fn replay_record(&mut self, event: ReplayEvent) {
+        if let ReplayMode::Recording(log) = &mut self.replay {
+            log.events.push(event);
+        }
+    }
+
+    /// Read a byte from an IO port, or replay the byte that was read when recording.
+    pub fn replay_io_read(&mut self, port: u16) -> u8 {
+        if let ReplayMode::Replaying(..) = self.replay {
+            match self.replay_next(|e| matches!(e, ReplayEvent::IoRead { port: p, .. } if *p == port)) {
+                Some(ReplayEvent::IoRead { byte, .. }) => return byte,
+                _ => {
+                    log::warn!("Replay diverged at IO read of port {:04X}, cycle {}", port, self.cycle_num);
+                    self.stop_replay();
+                }
+            }
+        }
+        let byte = self.bus.io_read_u8(port, self.instr_elapsed);
+        self.replay_record(ReplayEvent::IoRead { port, byte });
+        byte
+    }
+
+    /// Decide whether an NMI is taken at the start of the current instruction. 'nmi' is the
+    /// state of the CPU's own NMI logic, which is replaced by the log when replaying.
+    pub fn replay_nmi(&mut self, nmi: bool) -> bool {
+        let cycle = self.cycle_num;
+        if let ReplayMode::Replaying(..) = self.replay {
+            return self.replay_next(|e| *e == ReplayEvent::Nmi { cycle }).is_some()
+        }
+        if nmi {
+            self.replay_record(ReplayEvent::Nmi { cycle });
+        }
+        nmi
+    }
+
+    /// Decide whether INTR is active at the start of the current instruction. 'intr' is the
+    /// state of the INTR line, which is replaced by the log when replaying.
+    pub fn replay_intr(&mut self, intr: bool) -> bool {
+        let cycle = self.cycle_num;
+        if let ReplayMode::Replaying(..) = self.replay {
+            return self.replay_next(|e| *e == ReplayEvent::Intr { cycle }).is_some()
+        }
+        if intr {
+            self.replay_record(ReplayEvent::Intr { cycle });
+        }
+        intr
+    }
+
+    /// Return the vector read from the PIC during the second INTA cycle, or the recorded
+    /// vector when replaying.
+    pub fn replay_inta_vector(&mut self) -> u8 {
+        if let ReplayMode::Replaying(..) = self.replay {
+            if let Some(ReplayEvent::InterruptAck { vector }) = self.replay_next(|e| matches!(e, ReplayEvent::InterruptAck { .. })) {
+                return vector
+            }
+            log::warn!("Replay diverged at INTA, cycle {}", self.cycle_num);
+            self.stop_replay();
+        }
+        let vector = self.pic_inta_vector();
+        self.replay_record(ReplayEvent::InterruptAck { vector });
+        vector
+    }
+}
@@ -0,0 +1,336 @@
+/*
+    Marty PC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::snapshot.rs
+
+    Implements saving and restoring the complete execution state of the CPU,
+    for use by save states.
+
+    Since the CPU is cycle-accurate, the snapshot includes the prefetch queue,
+    BIU and microcode state along with the registers, so that a restored CPU
+    resumes fetching and executing on exactly the same cycle. The bus and
+    attached devices are not included and must be saved separately.
+
+*/
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::fpu::Fpu;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CpuSnapshot {
+    cpu_type: CpuType,
+    state: CpuState,
+
+    // Registers
+    ax: u16,
+    bx: u16,
+    cx: u16,
+    dx: u16,
+    sp: u16,
+    bp: u16,
+    si: u16,
+    di: u16,
+    cs: u16,
+    ds: u16,
+    ss: u16,
+    es: u16,
+    ip: u16,
+    flags: u16,
+
+    // EU and microcode state
+    i: Instruction,
+    mc_pc: u16,
+    nx: bool,
+    rni: bool,
+    ea_opr: u16,
+    last_ea: u16,
+
+    // BIU state
+    address_bus: u32,
+    data_bus: u16,
+    i8288: I8288,
+    pc: u32,
+    biu_state: BiuState,
+    ready: bool,
+    queue: InstructionQueue,
+    fetch_size: TransferSize,
+    fetch_state: FetchState,
+    next_fetch_state: FetchState,
+    fetch_suspended: bool,
+    fetch_delay: u32,
+    bus_pending_eu: bool,
+    queue_op: QueueOp,
+    last_queue_op: QueueOp,
+    last_queue_direction: QueueDirection,
+    queue_byte: u8,
+    last_queue_byte: u8,
+    last_queue_len: usize,
+    last_queue_delay: QueueDelay,
+    t_cycle: TCycle,
+    bus_status: BusStatus,
+    bus_segment: Segment,
+    transfer_size: TransferSize,
+    operand_size: OperandSize,
+    transfer_n: u32,
+    bus_wait_states: u32,
+    wait_states: u32,
+    lock: bool,
+
+    // Execution state
+    halted: bool,
+    in_rep: bool,
+    rep_init: bool,
+    rep_saved: bool,
+    rep_mnemonic: Mnemonic,
+    rep_type: RepType,
+    cycle_num: u64,
+    instr_cycle: u32,
+    instr_elapsed: u32,
+    instruction_count: u64,
+    call_stack: VecDeque<CallStackEntry>,
+    call_stack_overflow: bool,
+    step_over_target: Option<CpuAddress>,
+    opcode0_counter: u32,
+
+    // Interrupts
+    int_count: u64,
+    iret_count: u64,
+    interrupt_inhibit: bool,
+    pending_interrupt: bool,
+    trap_enable_delay: u32,
+    trap_disable_delay: u32,
+    trap_suppressed: bool,
+    nmi: bool,
+    nmi_pending: bool,
+    nmi_inhibit: bool,
+    halt_resume_delay: u32,
+
+    // DMA
+    dma_state: DmaState,
+    dram_refresh_simulation: bool,
+    dram_refresh_cycle_target: u32,
+    dram_refresh_cycles: u32,
+    dram_refresh_adjust: u32,
+    dram_bus_owner_cycles: u32,
+    dma_aen: bool,
+
+    fpu: Fpu,
+}
+
+impl<'a> Cpu<'a> {
+
+    /// Capture the execution state of the CPU.
+    pub fn save_state(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            cpu_type: self.cpu_type,
+            state: self.state,
+
+            ax: self.ax,
+            bx: self.bx,
+            cx: self.cx,
+            dx: self.dx,
+            sp: self.sp,
+            bp: self.bp,
+            si: self.si,
+            di: self.di,
+            cs: self.cs,
+            ds: self.ds,
+            ss: self.ss,
+            es: self.es,
+            ip: self.ip,
+            flags: self.flags,
+
+            i: self.i,
+            mc_pc: self.mc_pc,
+            nx: self.nx,
+            rni: self.rni,
+            ea_opr: self.ea_opr,
+            last_ea: self.last_ea,
+
+            address_bus: self.address_bus,
+            data_bus: self.data_bus,
+            i8288: self.i8288,
+            pc: self.pc,
+            biu_state: self.biu_state,
+            ready: self.ready,
+            queue: self.queue.clone(),
+            fetch_size: self.fetch_size,
+            fetch_state: self.fetch_state,
+            next_fetch_state: self.next_fetch_state,
+            fetch_suspended: self.fetch_suspended,
+            fetch_delay: self.fetch_delay,
+            bus_pending_eu: self.bus_pending_eu,
+            queue_op: self.queue_op,
+            last_queue_op: self.last_queue_op,
+            last_queue_direction: self.last_queue_direction,
+            queue_byte: self.queue_byte,
+            last_queue_byte: self.last_queue_byte,
+            last_queue_len: self.last_queue_len,
+            last_queue_delay: self.last_queue_delay,
+            t_cycle: self.t_cycle,
+            bus_status: self.bus_status,
+            bus_segment: self.bus_segment,
+            transfer_size: self.transfer_size,
+            operand_size: self.operand_size,
+            transfer_n: self.transfer_n,
+            bus_wait_states: self.bus_wait_states,
+            wait_states: self.wait_states,
+            lock: self.lock,
+
+            halted: self.halted,
+            in_rep: self.in_rep,
+            rep_init: self.rep_init,
+            rep_saved: self.rep_saved,
+            rep_mnemonic: self.rep_mnemonic,
+            rep_type: self.rep_type,
+            cycle_num: self.cycle_num,
+            instr_cycle: self.instr_cycle,
+            instr_elapsed: self.instr_elapsed,
+            instruction_count: self.instruction_count,
+            call_stack: self.call_stack.clone(),
+            call_stack_overflow: self.call_stack_overflow,
+            step_over_target: self.step_over_target,
+            opcode0_counter: self.opcode0_counter,
+
+            int_count: self.int_count,
+            iret_count: self.iret_count,
+            interrupt_inhibit: self.interrupt_inhibit,
+            pending_interrupt: self.pending_interrupt,
+            trap_enable_delay: self.trap_enable_delay,
+            trap_disable_delay: self.trap_disable_delay,
+            trap_suppressed: self.trap_suppressed,
+            nmi: self.nmi,
+            nmi_pending: self.nmi_pending,
+            nmi_inhibit: self.nmi_inhibit,
+            halt_resume_delay: self.halt_resume_delay,
+
+            dma_state: self.dma_state,
+            dram_refresh_simulation: self.dram_refresh_simulation,
+            dram_refresh_cycle_target: self.dram_refresh_cycle_target,
+            dram_refresh_cycles: self.dram_refresh_cycles,
+            dram_refresh_adjust: self.dram_refresh_adjust,
+            dram_bus_owner_cycles: self.dram_bus_owner_cycles,
+            dma_aen: self.dma_aen,
+
+            fpu: self.fpu.clone(),
+        }
+    }
+
+    /// Restore the execution state of the CPU from a snapshot. Configuration such as trace
+    /// and debugging options is left unchanged.
+    pub fn restore_state(&mut self, snapshot: &CpuSnapshot) {
+        self.cpu_type = snapshot.cpu_type;
+        self.state = snapshot.state;
+
+        // Use set_register16 so that the 8-bit register halves are updated.
+        self.set_register16(Register16::AX, snapshot.ax);
+        self.set_register16(Register16::BX, snapshot.bx);
+        self.set_register16(Register16::CX, snapshot.cx);
+        self.set_register16(Register16::DX, snapshot.dx);
+        self.sp = snapshot.sp;
+        self.bp = snapshot.bp;
+        self.si = snapshot.si;
+        self.di = snapshot.di;
+        self.cs = snapshot.cs;
+        self.ds = snapshot.ds;
+        self.ss = snapshot.ss;
+        self.es = snapshot.es;
+        self.ip = snapshot.ip;
+        self.flags = snapshot.flags;
+
+        self.i = snapshot.i;
+        self.mc_pc = snapshot.mc_pc;
+        self.nx = snapshot.nx;
+        self.rni = snapshot.rni;
+        self.ea_opr = snapshot.ea_opr;
+        self.last_ea = snapshot.last_ea;
+
+        self.address_bus = snapshot.address_bus;
+        self.data_bus = snapshot.data_bus;
+        self.i8288 = snapshot.i8288;
+        self.pc = snapshot.pc;
+        self.biu_state = snapshot.biu_state;
+        self.ready = snapshot.ready;
+        self.queue = snapshot.queue.clone();
+        self.fetch_size = snapshot.fetch_size;
+        self.fetch_state = snapshot.fetch_state;
+        self.next_fetch_state = snapshot.next_fetch_state;
+        self.fetch_suspended = snapshot.fetch_suspended;
+        self.fetch_delay = snapshot.fetch_delay;
+        self.bus_pending_eu = snapshot.bus_pending_eu;
+        self.queue_op = snapshot.queue_op;
+        self.last_queue_op = snapshot.last_queue_op;
+        self.last_queue_direction = snapshot.last_queue_direction;
+        self.queue_byte = snapshot.queue_byte;
+        self.last_queue_byte = snapshot.last_queue_byte;
+        self.last_queue_len = snapshot.last_queue_len;
+        self.last_queue_delay = snapshot.last_queue_delay;
+        self.t_cycle = snapshot.t_cycle;
+        self.bus_status = snapshot.bus_status;
+        self.bus_segment = snapshot.bus_segment;
+        self.transfer_size = snapshot.transfer_size;
+        self.operand_size = snapshot.operand_size;
+        self.transfer_n = snapshot.transfer_n;
+        self.bus_wait_states = snapshot.bus_wait_states;
+        self.wait_states = snapshot.wait_states;
+        self.lock = snapshot.lock;
+
+        self.halted = snapshot.halted;
+        self.in_rep = snapshot.in_rep;
+        self.rep_init = snapshot.rep_init;
+        self.rep_saved = snapshot.rep_saved;
+        self.rep_mnemonic = snapshot.rep_mnemonic;
+        self.rep_type = snapshot.rep_type;
+        self.cycle_num = snapshot.cycle_num;
+        self.instr_cycle = snapshot.instr_cycle;
+        self.instr_elapsed = snapshot.instr_elapsed;
+        self.instruction_count = snapshot.instruction_count;
+        self.call_stack = snapshot.call_stack.clone();
+        self.call_stack_overflow = snapshot.call_stack_overflow;
+        self.step_over_target = snapshot.step_over_target;
+        self.opcode0_counter = snapshot.opcode0_counter;
+
+        self.int_count = snapshot.int_count;
+        self.iret_count = snapshot.iret_count;
+        self.interrupt_inhibit = snapshot.interrupt_inhibit;
+        self.pending_interrupt = snapshot.pending_interrupt;
+        self.trap_enable_delay = snapshot.trap_enable_delay;
+        self.trap_disable_delay = snapshot.trap_disable_delay;
+        self.trap_suppressed = snapshot.trap_suppressed;
+        self.nmi = snapshot.nmi;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.nmi_inhibit = snapshot.nmi_inhibit;
+        self.halt_resume_delay = snapshot.halt_resume_delay;
+
+        self.dma_state = snapshot.dma_state;
+        self.dram_refresh_simulation = snapshot.dram_refresh_simulation;
+        self.dram_refresh_cycle_target = snapshot.dram_refresh_cycle_target;
+        self.dram_refresh_cycles = snapshot.dram_refresh_cycles;
+        self.dram_refresh_adjust = snapshot.dram_refresh_adjust;
+        self.dram_bus_owner_cycles = snapshot.dram_bus_owner_cycles;
+        self.dma_aen = snapshot.dma_aen;
+
+        self.fpu = snapshot.fpu.clone();
+
+        // Instruction history is not part of the snapshot and would no longer be accurate.
+        self.instruction_history.clear();
+    }
+}
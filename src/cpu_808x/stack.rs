@@ -87,8 +87,10 @@ impl<'a> Cpu<'a> {
             Register16::DS => self.ds = data,
             Register16::SS => {
                 self.ss = data;
-                // Inhibit interrupts for one instruction after issuing POP SS
-                self.interrupt_inhibit = true
+                // Inhibit interrupts and traps for one instruction after issuing POP SS
+                self.interrupt_inhibit = true;
+                self.nmi_inhibit = true;
+                self.trap_suppressed = true;
             },
             Register16::ES => self.es = data,     
             Register16::IP => self.ip = data,      
@@ -125,13 +127,14 @@ impl<'a> Cpu<'a> {
         self.flags = result & FLAGS_POP_MASK;
         self.flags |= CPU_FLAGS_RESERVED_ON;
 
-        // Was trap flag just set? Set trap enable delay.
+        // Was trap flag just set? The trap is taken after the instruction following this one, 
+        // so that single-stepping with IRET from a trap handler executes one instruction per trap.
         let trap_is_set = self.get_flag(Flag::Trap);
         if !trap_was_set && trap_is_set {
-            self.trap_enable_delay = 2;
+            self.trap_enable_delay = 1;
         }
 
-        // Was trap flag just disabled? Set trap disable delay.
+        // Was trap flag just disabled? The trap is still taken after this instruction.
         if trap_was_set && !trap_is_set {
             self.trap_disable_delay = 1;
         }
@@ -263,6 +263,86 @@ impl<'a> Cpu<'a> {
                     }
                 }
             }            
+            Mnemonic::INSB => {
+                // INSB: Read byte from port [dx] into [es:di] (80186)
+                // No flags affected
+                // Override: ES cannot be overridden
+                let dest_addr = Cpu::calc_linear_address(self.es, self.di);
+
+                let data = self.biu_io_read_u8(self.dx);
+                self.biu_write_u8(Segment::ES, dest_addr, data, ReadWriteFlag::Normal);
+
+                match self.get_flag(Flag::Direction) {
+                    false => {
+                        // Direction flag clear, process forwards
+                        self.di = self.di.wrapping_add(1);
+                    }
+                    true => {
+                        // Direction flag set, process backwards
+                        self.di = self.di.wrapping_sub(1);
+                    }
+                }
+            }
+            Mnemonic::INSW => {
+                // INSW: Read word from port [dx] into [es:di] (80186)
+                // No flags affected
+                // Override: ES cannot be overridden
+                let dest_addr = Cpu::calc_linear_address(self.es, self.di);
+
+                let data = self.biu_io_read_u16(self.dx);
+                self.biu_write_u16(Segment::ES, dest_addr, data, ReadWriteFlag::Normal);
+
+                match self.get_flag(Flag::Direction) {
+                    false => {
+                        // Direction flag clear, process forwards
+                        self.di = self.di.wrapping_add(2);
+                    }
+                    true => {
+                        // Direction flag set, process backwards
+                        self.di = self.di.wrapping_sub(2);
+                    }
+                }
+            }
+            Mnemonic::OUTSB => {
+                // OUTSB: Write byte from [ds:si] to port [dx] (80186)
+                // No flags affected
+                // Override: DS can be overridden
+                let src_addr = Cpu::calc_linear_address(segment_value_base_ds, self.si);
+
+                let data = self.biu_read_u8(segment_base_ds, src_addr);
+                self.biu_io_write_u8(self.dx, data, ReadWriteFlag::Normal);
+
+                match self.get_flag(Flag::Direction) {
+                    false => {
+                        // Direction flag clear, process forwards
+                        self.si = self.si.wrapping_add(1);
+                    }
+                    true => {
+                        // Direction flag set, process backwards
+                        self.si = self.si.wrapping_sub(1);
+                    }
+                }
+            }
+            Mnemonic::OUTSW => {
+                // OUTSW: Write word from [ds:si] to port [dx] (80186)
+                // No flags affected
+                // Override: DS can be overridden
+                let src_addr = Cpu::calc_linear_address(segment_value_base_ds, self.si);
+
+                let data = self.biu_read_u16(segment_base_ds, src_addr, ReadWriteFlag::Normal);
+                self.biu_io_write_u16(self.dx, data, ReadWriteFlag::Normal);
+
+                match self.get_flag(Flag::Direction) {
+                    false => {
+                        // Direction flag clear, process forwards
+                        self.si = self.si.wrapping_add(2);
+                    }
+                    true => {
+                        // Direction flag set, process backwards
+                        self.si = self.si.wrapping_sub(2);
+                    }
+                }
+            }
             _ => {
                 panic!("CPU: Unhandled opcode to string_op(): {:?}", opcode);
             }            
@@ -315,10 +395,19 @@ impl<'a> Cpu<'a> {
         self.cycles_i(4, &[0x118, 0x119, MC_CORR, 0x11a]);
         self.biu_queue_flush();
 
-        // Rewind IP so that it points to REP instruction again afterwards.
-        // This behavior will emulate the 8088's bug with string operations and segment overrides,
-        // as the next time the instruction is fetched it will be with only a single prefix.
-        self.ip = self.ip.wrapping_sub(2); 
+        // Rewind IP so that the string instruction is executed again after the interrupt.
+        // The 8088 only resumes from the last prefix byte, so any preceding prefixes such as a 
+        // segment override are lost. When the bug is disabled we resume from the first prefix.
+        let resume_ip = if self.rep_prefix_bug {
+            self.ip.wrapping_add(self.i.size as u16).wrapping_sub(2)
+        }
+        else {
+            self.ip
+        };
+
+        // IP is advanced by the instruction size when the instruction completes.
+        self.ip = resume_ip.wrapping_sub(self.i.size as u16);
+        self.instr_retired = false;
             
         self.rep_end();
         // Flush was on RNI so no extra cycle here
@@ -0,0 +1,194 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    cpu_808x::trace.rs
+
+    Implements structured per-instruction trace records. When a trace
+    callback is installed, the CPU collects the register state before
+    each instruction and every bus transfer performed until the
+    instruction retires, and passes the completed TraceRecord to the
+    callback.
+
+    TraceRecord implements Display, producing one line per instruction
+    in the following format:
+
+    CS:IP BYTES DISASSEMBLY | AX BX CX DX SP BP SI DI ES CS SS DS IP FLAGS | CYCLES | BUS...
+
+    Each bus transfer is printed as CYCLE:TYPE:ADDRESS:DATA, followed by
+    +N if N wait states were inserted. CYCLE is the instruction cycle
+    on which data was transferred (T3 or the last Tw), counting from 0.
+
+    The alternate form ({:#}) appends the starting microcode address of
+    the instruction and the microcode line executed on each cycle, with
+    '---' for cycles not executing microcode:
+
+    ... | MC 0A0 | --- 0A0 0A1 ...
+
+*/
+
+use std::fmt;
+use std::io::Write;
+
+use crate::cpu_808x::*;
+use crate::syntax_token::SyntaxToken;
+
+/// A single bus transfer performed while an instruction was executing.
+#[derive(Copy, Clone, Debug)]
+pub struct TraceBusCycle {
+    pub cycle: u32,
+    pub status: BusStatus,
+    pub address: u32,
+    pub data: u16,
+    pub size: TransferSize,
+    pub wait_states: u32,
+}
+
+/// A trace of a single executed instruction.
+#[derive(Clone)]
+pub struct TraceRecord {
+    /// Register state before the instruction was executed
+    pub regs: CpuRegisterState,
+    /// Linear address of the instruction
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    pub tokens: Vec<SyntaxToken>,
+    /// Total cycles taken by the instruction, including all iterations of a REP prefixed instruction
+    pub cycles: u32,
+    pub bus_cycles: Vec<TraceBusCycle>,
+    /// Starting microcode address of the instruction, if it has one
+    pub microcode_start: Option<u16>,
+    /// Microcode line executed on each cycle, or MC_NONE
+    pub microcode: Vec<u16>,
+}
+
+impl TraceRecord {
+    pub fn new(regs: CpuRegisterState, address: u32) -> Self {
+        Self {
+            regs,
+            address,
+            bytes: Vec::new(),
+            disassembly: String::new(),
+            tokens: Vec::new(),
+            cycles: 0,
+            bus_cycles: Vec::new(),
+            microcode_start: None,
+            microcode: Vec::new(),
+        }
+    }
+}
+
+fn bus_status_str(status: BusStatus) -> &'static str {
+    match status {
+        BusStatus::InterruptAck => "INTA",
+        BusStatus::IoRead => "IOR",
+        BusStatus::IoWrite => "IOW",
+        BusStatus::Halt => "HALT",
+        BusStatus::CodeFetch => "CODE",
+        BusStatus::MemRead => "MEMR",
+        BusStatus::MemWrite => "MEMW",
+        BusStatus::Passive => "PASV",
+    }
+}
+
+impl fmt::Display for TraceBusCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{:05X}:", self.cycle, bus_status_str(self.status), self.address)?;
+        match self.size {
+            TransferSize::Byte => write!(f, "{:02X}", self.data & 0xFF)?,
+            TransferSize::Word => write!(f, "{:04X}", self.data)?
+        }
+        if self.wait_states > 0 {
+            write!(f, "+{}", self.wait_states)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.regs;
+        let bytes: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+        write!(
+            f,
+            "{:04X}:{:04X} {:<12} {:<32} | {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} | {} |",
+            r.cs, r.ip, bytes, self.disassembly,
+            r.ax, r.bx, r.cx, r.dx, r.sp, r.bp, r.si, r.di,
+            r.es, r.cs, r.ss, r.ds, r.ip, r.flags,
+            self.cycles
+        )?;
+        for bus_cycle in &self.bus_cycles {
+            write!(f, " {}", bus_cycle)?;
+        }
+
+        if f.alternate() {
+            match self.microcode_start {
+                Some(mc) => write!(f, " | MC {:03X} |", mc)?,
+                None => write!(f, " | MC --- |")?
+            }
+            for line in &self.microcode {
+                match *line {
+                    MC_NONE => write!(f, " ---")?,
+                    mc => write!(f, " {:03X}", mc)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes TraceRecords to the provided writer, one line per instruction.
+pub struct TraceRecordWriter<W: Write> {
+    writer: W,
+    microcode: bool,
+}
+
+impl<W: Write> TraceRecordWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            microcode: false,
+        }
+    }
+
+    /// Include microcode columns in each line written.
+    pub fn set_microcode(&mut self, state: bool) {
+        self.microcode = state;
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        match self.microcode {
+            true => writeln!(self.writer, "{:#}", record),
+            false => writeln!(self.writer, "{}", record)
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
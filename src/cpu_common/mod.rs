@@ -1,16 +1,42 @@
 #![allow(dead_code)]
 
+use serde_derive::{Deserialize, Serialize};
 
-#[derive (Copy, Clone, Debug)]
+#[derive (Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum CpuType {
     Intel8088,
     Intel8086,
+    Intel80188,
+    Intel80186,
+    NecV20,
 }
 
 impl Default for CpuType {
     fn default() -> Self { CpuType::Intel8088 }
 }
 
+impl CpuType {
+    /// Returns true if this CPU implements the undocumented SALC instruction (0xD6).
+    /// CPU types that do not implement SALC execute 0xD6 as a no-op.
+    pub fn has_salc(&self) -> bool {
+        match self {
+            CpuType::Intel8088 | CpuType::Intel8086 => true,
+            CpuType::Intel80188 | CpuType::Intel80186 => true,
+            CpuType::NecV20 => false,
+        }
+    }
+
+    /// Returns true if this CPU implements the 80186 instruction set extensions, 
+    /// ie, PUSHA/POPA, BOUND, PUSH imm, IMUL imm, INS/OUTS and shifts by immediate.
+    /// On the 8088 and 8086, opcodes 0x60-0x6F alias the conditional jumps 0x70-0x7F.
+    pub fn has_186_instructions(&self) -> bool {
+        match self {
+            CpuType::Intel8088 | CpuType::Intel8086 => false,
+            CpuType::Intel80188 | CpuType::Intel80186 | CpuType::NecV20 => true,
+        }
+    }
+}
+
 #[derive (Debug)]
 pub enum CpuOption {
     InstructionHistory(bool),
@@ -18,7 +44,12 @@ pub enum CpuOption {
     DramRefreshAdjust(u32),
     HaltResumeDelay(u32),
     OffRailsDetection(bool),
+    OffRailsThreshold(u32),
     EnableWaitStates(bool),
+    FpuPresent(bool),
+    RepPrefixBug(bool),
+    StrictUndefinedFlags(bool),
+    JumpSanity(bool),
     TraceLoggingEnabled(bool)
 }
 
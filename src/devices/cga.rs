@@ -128,10 +128,13 @@ const SCANLINE_HBLANK_US: f64 = 52.38095911;
 const CGA_HBLANK: f64 = 0.1785714;
 
 const CGA_DEFAULT_CURSOR_BLINK_RATE: f64 = 0.0625;
-const CGA_CURSOR_BLINK_RATE_CLOCKS: u32 = FRAME_TIME_CLOCKS * 8;
 const CGA_CURSOR_BLINK_RATE_US: f64 = FRAME_TIME_US * 8.0;
 
 const CGA_DEFAULT_CURSOR_FRAME_CYCLE: u32 = 8;
+// The 6845 blinks the cursor at 1/16 or 1/32 of the field rate depending on the cursor
+// start register. The CGA blinks text with the blink attribute at 1/32 of the field rate.
+const CGA_SLOW_CURSOR_FRAME_CYCLE: u32 = 16;
+const CGA_TEXT_BLINK_FRAME_CYCLE: u32 = 16;
 
 pub const CRTC_REGISTER_SELECT: u16         = 0x3D4;
 pub const CRTC_REGISTER: u16                = 0x3D5;
@@ -140,6 +143,7 @@ pub const CGA_MODE_CONTROL_REGISTER: u16    = 0x3D8;
 pub const CGA_COLOR_CONTROL_REGISTER: u16   = 0x3D9;
 pub const CGA_STATUS_REGISTER: u16          = 0x3DA;
 pub const CGA_LIGHTPEN_REGISTER: u16        = 0x3DB;
+pub const CGA_LIGHTPEN_PRESET: u16          = 0x3DC;
 
 const MODE_MATCH_MASK: u8       = 0b0001_1111;
 const MODE_HIRES_TEXT: u8       = 0b0000_0001;
@@ -275,6 +279,7 @@ pub struct CGACard {
     frame_us: f64,
 
     cursor_frames: u32,
+    cursor_blink_state: bool,
 
     frame_count: u64,
     status_reads: u64,
@@ -304,6 +309,7 @@ pub struct CGACard {
     crtc_start_address_ho: u8,
     crtc_start_address_lo: u8,
     crtc_cursor_address_lo: u8,
+    crtc_light_pen_address: usize,
     crtc_cursor_address_ho: u8,
     crtc_cursor_address: usize,
     crtc_frame_address: usize,
@@ -352,9 +358,8 @@ pub struct CGACard {
     vma_t: usize,                   // VMA' register - Video memory address temporary
     vmws: usize,                    // Video memory word size
     rba: usize,                     // Render buffer address
-    blink_state: bool,              // Used to control blinking of cursor and text with blink attribute
+    blink_state: bool,              // Used to control blinking of text with blink attribute
     blink_accum_us: f64,            // Microsecond accumulator for blink state flipflop
-    blink_accum_clocks: u32,        // CGA Clock accumulator for blink state flipflop
     accumulated_us: f64,
     ticks_advanced: u32,            // Number of ticks we have advanced mid-instruction via port or mmio access.
 
@@ -445,6 +450,14 @@ impl IoDevice for CGACard {
             CGA_COLOR_CONTROL_REGISTER => {
                 self.handle_cc_register_write(data);
             }
+            CGA_LIGHTPEN_REGISTER => {
+                // Clear light pen latch
+                self.crtc_light_pen_address = 0;
+            }
+            CGA_LIGHTPEN_PRESET => {
+                // Set light pen latch. The CRTC captures the current memory address.
+                self.crtc_light_pen_address = self.vma & 0x3FFF;
+            }
             _ => {}
         }
     }
@@ -456,6 +469,7 @@ impl IoDevice for CGACard {
             CGA_MODE_CONTROL_REGISTER,
             CGA_COLOR_CONTROL_REGISTER,
             CGA_LIGHTPEN_REGISTER,
+            CGA_LIGHTPEN_PRESET,
             CGA_STATUS_REGISTER,
         ]
     }
@@ -505,7 +519,7 @@ impl CGACard {
             mode_graphics: false,
             mode_bw: false,
             mode_hires_gfx: false,
-            mode_hires_txt: true,
+            mode_hires_txt: false,
             mode_blinking: true,
             cc_palette: 0,
             cc_altcolor: 0,
@@ -513,6 +527,7 @@ impl CGACard {
             frame_us: 0.0,
 
             cursor_frames: 0,
+            cursor_blink_state: false,
             scanline_us: 0.0,
 
             frame_count: 0,
@@ -543,6 +558,7 @@ impl CGACard {
             crtc_start_address_ho: 0,
             crtc_start_address_lo: 0,
             crtc_cursor_address_lo: 0,
+            crtc_light_pen_address: 0,
             crtc_cursor_address_ho: 0,
             crtc_cursor_address: 0,
             crtc_frame_address: 0,
@@ -555,7 +571,8 @@ impl CGACard {
 
             cc_register: CC_PALETTE_BIT | CC_BRIGHT_BIT,
 
-            clock_divisor: 1,
+            // The default CRTC registers are for 40 column text, which is scanned at half clock.
+            clock_divisor: 2,
 
             beam_x: 0,
             beam_y: 0,
@@ -593,7 +610,6 @@ impl CGACard {
             rba: 0,
             blink_state: false,
             blink_accum_us: 0.0,
-            blink_accum_clocks: 0,
 
             accumulated_us: 0.0,
             ticks_advanced: 0,
@@ -665,8 +681,26 @@ impl CGACard {
     }
 
     /// Update the CRTC cursor address. Usually called after a CRTC register write updates the HO or LO byte.
+    /// The cursor address register is 14 bits, like the start address.
     fn update_cursor_address(&mut self) {
-        self.crtc_cursor_address = (self.crtc_cursor_address_ho as usize) << 8 | self.crtc_cursor_address_lo as usize
+        self.crtc_cursor_address = ((self.crtc_cursor_address_ho as usize) << 8 | self.crtc_cursor_address_lo as usize) & 0x3FFF
+    }
+
+    /// Update the cursor and text blink flipflops. Called once per frame, as the 6845 derives
+    /// its blink rates from the field rate.
+    fn update_blink_state(&mut self) {
+        self.cursor_frames = self.cursor_frames.wrapping_add(1);
+
+        let cursor_cycle = match self.cursor_slowblink {
+            true => CGA_SLOW_CURSOR_FRAME_CYCLE,
+            false => CGA_DEFAULT_CURSOR_FRAME_CYCLE
+        };
+        if self.cursor_frames % cursor_cycle == 0 {
+            self.cursor_blink_state = !self.cursor_blink_state;
+        }
+        if self.cursor_frames % CGA_TEXT_BLINK_FRAME_CYCLE == 0 {
+            self.blink_state = !self.blink_state;
+        }
     }
 
     /// Update the CRTC start address. Usually called after a CRTC register write updates the HO or LO byte.
@@ -807,7 +841,8 @@ impl CGACard {
                 self.update_cursor_data();
             }
             CRTCRegister::CursorAddressH => {
-                self.crtc_cursor_address_ho = byte;
+                // Cursor Address HO register is only 6 bits wide.
+                self.crtc_cursor_address_ho = byte & 0x3F;
                 self.update_cursor_address();
             }
             CRTCRegister::CursorAddressL => {
@@ -855,8 +890,11 @@ impl CGACard {
                 //log::debug!("CGA: Read from CRTC register: {:?}: {:02}", self.crtc_register_selected, self.crtc_cursor_address_lo );
                 self.crtc_cursor_address_lo
             }
+            CRTCRegister::LightPenPositionH => (self.crtc_light_pen_address >> 8) as u8 & 0x3F,
+            CRTCRegister::LightPenPositionL => (self.crtc_light_pen_address & 0xFF) as u8,
             _ => {
-                log::debug!("CGA: Read from unsupported CRTC register: {:?}", self.crtc_register_selected);
+                // Remaining registers, including the start address pair, are write only.
+                log::debug!("CGA: Read from write-only CRTC register: {:?}", self.crtc_register_selected);
                 0
             }
         }
@@ -1096,8 +1134,8 @@ impl CGACard {
         };
 
         // Do cursor
-        if self.cursor_status && self.blink_state && (self.vma == self.crtc_cursor_address) {
-            // This cell has the cursor address, cursor is enabled and not blinking
+        if self.cursor_status && self.cursor_blink_state && ((self.vma & 0x3FFF) == self.crtc_cursor_address) {
+            // This cell has the cursor address, cursor is enabled and in the visible blink phase
             if self.cursor_data[(self.vlc_c9 & 0x1F) as usize] {
                 new_pixel = self.cur_fg;
             }
@@ -1603,6 +1641,7 @@ impl CGACard {
 
             self.scanline = 0;
             self.frame_count += 1;
+            self.update_blink_state();
 
             // Swap the display buffers
             self.swap();   
@@ -1844,6 +1883,11 @@ impl VideoCard for CGACard {
         (palette, intensity)
     }    
 
+    /// The color burst is disabled by the black & white bit of the mode register.
+    fn is_color_burst_enabled(&self) -> bool {
+        !self.mode_bw
+    }
+
     fn get_videocard_string_state(&self) -> HashMap<String, Vec<(String, VideoCardStateEntry)>> {
 
         let mut map = HashMap::new();
@@ -1947,13 +1991,6 @@ impl VideoCard for CGACard {
         }        
         */
 
-        // Handle blinking. TODO: Move blink handling into tick().
-        self.blink_accum_clocks += clocks;
-        if self.blink_accum_clocks > CGA_CURSOR_BLINK_RATE_CLOCKS {
-            self.blink_state = !self.blink_state;
-            self.blink_accum_clocks -= CGA_CURSOR_BLINK_RATE_CLOCKS;
-        }
-
         // Tick the card.
         for _ in 0..clocks {
             self.tick();
@@ -2106,4 +2143,131 @@ impl MemoryMappedDevice for CGACard {
         0
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::VideoRenderer;
+
+    fn crtc_write(cga: &mut CGACard, reg: u8, byte: u8) {
+        IoDevice::write_u8(cga, CRTC_REGISTER_SELECT, reg, None, DeviceRunTimeUnit::Microseconds(0.0));
+        IoDevice::write_u8(cga, CRTC_REGISTER, byte, None, DeviceRunTimeUnit::Microseconds(0.0));
+    }
+
+    fn crtc_read(cga: &mut CGACard, reg: u8) -> u8 {
+        IoDevice::write_u8(cga, CRTC_REGISTER_SELECT, reg, None, DeviceRunTimeUnit::Microseconds(0.0));
+        IoDevice::read_u8(cga, CRTC_REGISTER, DeviceRunTimeUnit::Microseconds(0.0))
+    }
+
+    #[test]
+    fn test_crtc_address_registers() {
+        let mut cga = CGACard::new(TraceLogger::None, false);
+
+        // Start address is 14 bits and write only.
+        crtc_write(&mut cga, 0x0C, 0xFF);
+        crtc_write(&mut cga, 0x0D, 0x50);
+        assert_eq!(cga.crtc_start_address, 0x3F50);
+        assert_eq!(cga.get_start_address(), 0x3F50);
+        assert_eq!(crtc_read(&mut cga, 0x0C), 0);
+        assert_eq!(crtc_read(&mut cga, 0x0D), 0);
+
+        // Cursor address is 14 bits and readable.
+        crtc_write(&mut cga, 0x0E, 0xC7);
+        crtc_write(&mut cga, 0x0F, 0xD0);
+        assert_eq!(cga.get_cursor_address(), 0x07D0);
+        assert_eq!(crtc_read(&mut cga, 0x0E), 0x07);
+        assert_eq!(crtc_read(&mut cga, 0x0F), 0xD0);
+
+        // Setting the light pen latch captures the current memory address.
+        cga.vma = 0x1234;
+        IoDevice::write_u8(&mut cga, CGA_LIGHTPEN_PRESET, 0, None, DeviceRunTimeUnit::Microseconds(0.0));
+        assert_eq!(crtc_read(&mut cga, 0x10), 0x12);
+        assert_eq!(crtc_read(&mut cga, 0x11), 0x34);
+    }
+
+    #[test]
+    fn test_crtc_cursor_blink() {
+        let mut cga = CGACard::new(TraceLogger::None, false);
+
+        // Blink at 1/16 field rate: toggles every 8 frames.
+        crtc_write(&mut cga, 0x0A, 0x46);
+        let start = cga.cursor_blink_state;
+        for _ in 0..CGA_DEFAULT_CURSOR_FRAME_CYCLE {
+            cga.update_blink_state();
+        }
+        assert_ne!(cga.cursor_blink_state, start);
+
+        // Blink at 1/32 field rate: toggles every 16 frames.
+        crtc_write(&mut cga, 0x0A, 0x66);
+        cga.cursor_frames = 0;
+        let start = cga.cursor_blink_state;
+        for _ in 0..CGA_DEFAULT_CURSOR_FRAME_CYCLE {
+            cga.update_blink_state();
+        }
+        assert_eq!(cga.cursor_blink_state, start);
+        for _ in 0..CGA_DEFAULT_CURSOR_FRAME_CYCLE {
+            cga.update_blink_state();
+        }
+        assert_ne!(cga.cursor_blink_state, start);
+
+        // Cursor display disabled.
+        crtc_write(&mut cga, 0x0A, 0x26);
+        assert!(!cga.get_cursor_status());
+    }
+
+    #[test]
+    fn test_capture_frame() {
+        let mut cga = CGACard::new(TraceLogger::None, true);
+        let bus = BusInterface::default();
+
+        // Paint the first pixel of the front buffer bright white.
+        cga.buf[cga.front_buf][0] = 0x0F;
+
+        let mut renderer = VideoRenderer::new(VideoType::CGA);
+        let capture = renderer.capture(Box::new(&cga as &dyn VideoCard), &bus).unwrap();
+
+        assert_eq!(capture.width, CGA_XRES_MAX);
+        assert_eq!(capture.height, 240 * 2);
+        assert_eq!(capture.stride, CGA_XRES_MAX as usize * 4);
+        assert_eq!(capture.data.len(), capture.stride * capture.height as usize);
+
+        // Scanlines are doubled.
+        assert_eq!(&capture.data[0..4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&capture.data[capture.stride..capture.stride + 4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&capture.data[4..8], &[0x10, 0x10, 0x10, 0xFF]);
+    }
+
+    #[test]
+    fn test_status_retrace_timing() {
+        let mut cga = CGACard::new(TraceLogger::None, false);
+
+        // Poll the status register for one second of CGA clocks, roughly as often as
+        // an 'in al, dx; test al, 8; jz' loop would on a 4.77Mhz 8088.
+        const POLL_TICKS: u32 = 57;
+        let polls = (CGA_CLOCK * 1_000_000.0) as u32 / POLL_TICKS;
+
+        let mut retraces = 0;
+        let mut in_retrace = false;
+        let mut display_enable_polls = 0;
+        for _ in 0..polls {
+            let status = IoDevice::read_u8(&mut cga, CGA_STATUS_REGISTER, DeviceRunTimeUnit::SystemTicks(POLL_TICKS));
+            let retrace = status & STATUS_VERTICAL_RETRACE != 0;
+            if retrace {
+                // It is always safe to write VRAM during vertical retrace.
+                assert!(status & STATUS_DISPLAY_ENABLE != 0);
+                if !in_retrace {
+                    retraces += 1;
+                }
+            }
+            if status & STATUS_DISPLAY_ENABLE == 0 {
+                display_enable_polls += 1;
+            }
+            in_retrace = retrace;
+        }
+
+        // 262 scanlines of 912 clocks is a 59.92Hz field rate.
+        assert!((59..=61).contains(&retraces), "{} retraces per second", retraces);
+        assert!(display_enable_polls > 0 && display_enable_polls < polls);
+    }
+}
@@ -103,7 +103,6 @@ pub struct DMAChannel {
     transfer_type: TransferType,
     terminal_count: bool,
     terminal_count_reached: bool,
-    request: bool,
     masked: bool,
     page: u8
 }
@@ -147,8 +146,6 @@ pub struct DMAController {
     request_reg: u8,
     status_reg: u8,
     temp_reg: u8,
-
-    dreq: bool
 }
 
 impl IoDevice for DMAController {
@@ -326,8 +323,6 @@ impl DMAController {
             request_reg: 0,
             status_reg: 0,
             temp_reg: 0,
-
-            dreq: false
         }
     }
 
@@ -442,7 +437,7 @@ impl DMAController {
             }
             
             // Intel: Bits 4-7 are set whenever their corresponding channel is requesting service.
-            if chan.request {
+            if self.request_reg & (0x01 << i) != 0 {
                 status_byte |= 0x01 << (i + 4);
             }
         }
@@ -482,7 +477,15 @@ impl DMAController {
     }
 
     pub fn handle_write_req_register(&mut self, data: u8 ) {
-        log::debug!("DMA: Unimplemented write to Write Request Register: {:02X}", data);
+        // Bits 0-1: Channel Number
+        // Bit 2: Set or reset request. A software request is serviced as if DREQ were asserted.
+        let chan = (data & 0x03) as usize;
+        if data & 0x04 != 0 {
+            self.request_service(chan);
+        }
+        else {
+            self.clear_service(chan);
+        }
     }
 
     pub fn handle_channel_mask_register_write(&mut self, data: u8) {
@@ -595,7 +598,7 @@ impl DMAController {
         DMAControllerStringState { 
             enabled: format!("{:?}", self.enabled),
             flipflop: format!("{:?}", self.flipflop),
-            dreq: format!("{:?}", self.dreq),
+            dreq: format!("{:04b}", self.request_reg & 0x0F),
             dma_channel_state: chan_vec 
         }
     }
@@ -649,6 +652,13 @@ impl DMAController {
         true
     }
 
+    /// Return whether the controller will perform DRAM refresh cycles when channel 0 is requested.
+    /// Refresh cycles take the bus from the CPU, so masking channel 0 or disabling the controller
+    /// removes the contention (and stops the refresh).
+    pub fn refresh_enabled(&self) -> bool {
+        self.enabled && !self.channels[0].masked
+    }
+
     pub fn check_terminal_count(&self, channel: usize) -> bool {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
@@ -657,116 +667,110 @@ impl DMAController {
         self.channels[channel].terminal_count
     }
 
-    pub fn do_dma_read_u8(&mut self, bus: &mut BusInterface, channel: usize ) -> u8 {
+    /// Return the address for the next transfer on the specified channel and advance the channel's
+    /// current address and word count registers, or None if the channel has reached terminal count.
+    /// 
+    /// A channel transfers 'word count + 1' bytes. On the last transfer, terminal count is set, or 
+    /// the channel is reloaded from its base registers if auto-initialize is enabled.
+    fn next_transfer_address(&mut self, channel: usize) -> Option<usize> {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
         }  
 
-        if !self.enabled {
-            return 0;
+        let bus_address = self.get_dma_transfer_address(channel);
+        let chan = &mut self.channels[channel];
+
+        if chan.terminal_count {
+            // Trying to transfer on a terminal count
+            return None
         }
 
-        let mut data: u8 = 0;
-        let mut _cost = 0;
-        let bus_address = self.get_dma_transfer_address(channel);
+        if chan.current_word_count_reg > 0 {
+            // Internal address register wraps around
+            chan.current_address_reg = match chan.address_mode {
+                AddressMode::Increment => chan.current_address_reg.wrapping_add(1),
+                AddressMode::Decrement => chan.current_address_reg.wrapping_sub(1),
+            };
+            chan.current_word_count_reg -= 1;
+        }
+        else {
+            // Transfer one more on a 0 count, then set TC
+            if chan.auto_init {
+                // Reload channel if auto-init on
+                chan.current_address_reg = chan.base_address_reg;
+                chan.current_word_count_reg = chan.base_word_count_reg;
+            }
+            else {
+                chan.terminal_count = true;
+                log::trace!("Terminal count reached on DMA channel {:01X}", channel);
+                log::trace!(
+                    "Completed DMA of {} bytes at address {:05X}", 
+                    chan.base_word_count_reg as u32 + 1, 
+                    ((chan.page as u32) << 16) + (chan.base_address_reg as u32)
+                );
+            }
+            // Set the tc status bit regardless of auto-init
+            chan.terminal_count_reached = true;
+        }
 
-        
-        match self.channels[channel].address_mode {
-            AddressMode::Increment => {
-                if self.channels[channel].current_word_count_reg > 0 {
-
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
-                    
-                    if self.channels[channel].current_word_count_reg == 1 {
-                        //log::trace!("car: {} cwc: {} ", self.channels[channel].current_address_reg, self.channels[channel].current_word_count_reg);
-                    }
+        Some(bus_address)
+    }
 
-                    // Internal address register wraps around
-                    self.channels[channel].current_address_reg = self.channels[channel].current_address_reg.wrapping_add(1);
-                    self.channels[channel].current_word_count_reg -= 1;
-                    
-                    //log::trace!("DMA read {:02X} from address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                }
-                else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
-                    
-                    // Transfer one more on a 0 count, then set TC
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
-
-                    //self.channels[channel].current_address_reg += 1;
-
-                    //log::trace!("DMA read {:02X} from address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                    if self.channels[channel].auto_init {
-                        // Reload channel if auto-init on
-                        self.channels[channel].current_address_reg = self.channels[channel].base_address_reg;
-                        self.channels[channel].current_word_count_reg  = self.channels[channel].base_word_count_reg;
-                    }
-                    else {
-                        self.channels[channel].terminal_count = true;
-                        log::trace!("Terminal count reached on DMA channel {:01X}", channel);
-                    }
-                    // Set the tc status bit regardless of auto-init
-                    self.channels[channel].terminal_count_reached = true;
-                }
-                else {
-                    // Trying to transfer on a terminal count
-                }                
+    /// Perform a single DMA transfer from memory on the specified channel, returning the byte read.
+    pub fn do_dma_read_u8(&mut self, bus: &mut BusInterface, channel: usize ) -> u8 {
+
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.next_transfer_address(channel) {
+            Some(bus_address) => {
+                let (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+                //log::trace!("DMA read {:02X} from address: {:06X}", data, bus_address);
+                data
             }
-            _=> panic!("DMA Decrement address mode unimplemented")
-        }        
-        
-        data
+            None => 0
+        }
     }
 
+    /// Perform a single DMA transfer to memory on the specified channel. Nothing is written if the 
+    /// channel is in Verify mode, but the channel registers advance as normal.
     pub fn do_dma_write_u8(&mut self, bus: &mut BusInterface, channel: usize, data: u8) {
-        if channel >= DMA_CHANNEL_COUNT {
-            panic!("Invalid DMA Channel");
-        }  
 
-        let bus_address = self.get_dma_transfer_address(channel);
-
-        match self.channels[channel].address_mode {
-            AddressMode::Increment => {
+        if let Some(bus_address) = self.next_transfer_address(channel) {
+            // Don't transfer anything if in Verify mode
+            if let TransferType::Write = self.channels[channel].transfer_type {
+                bus.write_u8(bus_address, data, 0).unwrap();
+            }
+            //log::trace!("DMA write {:02X} to address: {:06X}", data, bus_address);
+        }
+    }
 
-                if self.channels[channel].current_word_count_reg > 0 {
-                    
-                    // Don't transfer anything if in Verify mode
-                    if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
-                    }
-                    
-                    self.channels[channel].current_address_reg = self.channels[channel].current_address_reg.wrapping_add(1);
-                    self.channels[channel].current_word_count_reg -= 1;
+    /// Perform a block transfer of 'data' to memory on the specified channel. The transfer ends 
+    /// early if the channel reaches terminal count. Returns the number of bytes transferred.
+    pub fn do_dma_write_block(&mut self, bus: &mut BusInterface, channel: usize, data: &[u8]) -> usize {
+        let mut count = 0;
+        for byte in data {
+            if self.check_terminal_count(channel) {
+                break;
+            }
+            self.do_dma_write_u8(bus, channel, *byte);
+            count += 1;
+        }
+        count
+    }
 
-                    //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                }
-                else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
-                    
-                    // Transfer one more on a 0 count, then set TC
-                    if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
-                    }
-                    //self.channels[channel].current_address_reg += 1;
-
-                    //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
-                    self.channels[channel].terminal_count = true;
-                    log::trace!("Terminal count reached on DMA channel {:01X}", channel);
-                    log::trace!(
-                        "Completed DMA of {} bytes to address {:05X}", 
-                        self.channels[channel].base_word_count_reg + 1, 
-                        ((self.channels[channel].page as u32) << 16) + (self.channels[channel].base_address_reg as u32)
-                    );
-                        
-                    // TODO: Support auto-init here
-
-                    // Set the tc status bit regardless of auto-init
-                    self.channels[channel].terminal_count_reached = true;
-                }
-                else {
-                    // Trying to transfer on a terminal count
-                }
+    /// Perform a block transfer of up to 'len' bytes from memory on the specified channel. The 
+    /// transfer ends early if the channel reaches terminal count.
+    pub fn do_dma_read_block(&mut self, bus: &mut BusInterface, channel: usize, len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len);
+        for _ in 0..len {
+            if !self.enabled || self.check_terminal_count(channel) {
+                break;
             }
-            _=> panic!("DMA Decrement address mode unimplemented")
-        }        
+            data.push(self.do_dma_read_u8(bus, channel));
+        }
+        data
     }
 
     /// Fake the DMA controller. This should eventually be replaced by a tick procedure that 
@@ -775,15 +779,23 @@ impl DMAController {
 
         for i in 0..DMA_CHANNEL_COUNT {
 
-            if self.request_reg & (0x01 << i) != 0 {
+            // A masked channel's request stays pending until the channel is unmasked
+            if self.request_reg & (0x01 << i) != 0 && self.check_dma_ready(i) {
                 // We have an active DREQ on this channel, service it
+                // Single mode performs one transfer per request. Block mode performs transfers 
+                // until terminal count.
+                let transfers = match self.channels[i].service_mode {
+                    ServiceMode::Single => 1,
+                    ServiceMode::Block => self.channels[i].current_word_count_reg as usize + 1,
+                    _ => 0
+                };
+
                 match self.channels[i].service_mode {
-                    ServiceMode::Single => {
-                        // We can handle single byte mode
+                    ServiceMode::Single | ServiceMode::Block => {
                         match self.channels[i].transfer_type {
                             TransferType::Read | TransferType::Verify => {
                                 if i == 0 {
-                                    self.do_dma_read_u8(bus, i);
+                                    self.do_dma_read_block(bus, i, transfers);
                                 }
                             }
                             TransferType::Write => {
@@ -794,7 +806,7 @@ impl DMAController {
                             }
                         }
 
-                        // Since this is single byte service, we can now reset the request register bit.
+                        // The request has been serviced, so we can now reset the request register bit.
                         self.request_reg &= !(0x01 << i);
                     }
                     _=> {
@@ -804,4 +816,64 @@ impl DMAController {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program_channel(dma: &mut DMAController, channel: usize, mode: u8, page: u8, address: u16, count: u16) {
+        dma.handle_clear_flopflop();
+        dma.handle_addr_port_write(channel, (address & 0xFF) as u8);
+        dma.handle_addr_port_write(channel, (address >> 8) as u8);
+        dma.handle_wc_port_write(channel, (count & 0xFF) as u8);
+        dma.handle_wc_port_write(channel, (count >> 8) as u8);
+        dma.handle_page_register_write(channel, page);
+        dma.handle_channel_mode_register_write(mode | channel as u8);
+        dma.handle_channel_mask_register_write(channel as u8);
+    }
+
+    #[test]
+    fn test_dma_block_and_auto_init() {
+        let mut bus = BusInterface::default();
+        let mut dma = DMAController::new();
+
+        // Channel 2: single mode, write to memory, 4 bytes at 2000:1000
+        program_channel(&mut dma, 2, 0x44, 0x02, 0x1000, 3);
+        assert_eq!(dma.do_dma_write_block(&mut bus, 2, &[1, 2, 3, 4, 5, 6]), 4);
+        assert!(dma.check_terminal_count(2));
+        for i in 0..4 {
+            assert_eq!(bus.read_u8(0x21000 + i, 0).unwrap().0, i as u8 + 1);
+        }
+        assert_eq!(bus.read_u8(0x21004, 0).unwrap().0, 0);
+        assert_eq!(dma.handle_status_register_read() & 0x0F, 0x04);
+        assert_eq!(dma.handle_status_register_read() & 0x0F, 0x00);
+
+        // Channel 1: single mode, decrement, auto-init, read from memory, 2 bytes ending at 0103
+        bus.write_u8(0x102, 0xAA, 0).unwrap();
+        bus.write_u8(0x103, 0xBB, 0).unwrap();
+        program_channel(&mut dma, 1, 0x78, 0x00, 0x0103, 1);
+        assert_eq!(dma.do_dma_read_block(&mut bus, 1, 3), vec![0xBB, 0xAA, 0xBB]);
+        assert!(!dma.check_terminal_count(1));
+        assert_eq!(dma.handle_status_register_read() & 0x0F, 0x02);
+
+        // Software requests are reflected in the status register
+        dma.handle_write_req_register(0x04 | 0x03);
+        assert_eq!(dma.handle_status_register_read() & 0xF0, 0x80);
+        dma.handle_write_req_register(0x03);
+        assert_eq!(dma.handle_status_register_read() & 0xF0, 0x00);
+
+        // Channel 0 refresh: single mode, read, auto-init. A masked channel's refresh request 
+        // is held until the channel is unmasked.
+        program_channel(&mut dma, 0, 0x58, 0x00, 0x0000, 0xFFFF);
+        assert!(dma.refresh_enabled());
+        dma.handle_channel_mask_register_write(0x04);
+        assert!(!dma.refresh_enabled());
+        dma.request_service(0);
+        dma.run(&mut bus);
+        assert_eq!(dma.get_dma_transfer_address(0), 0x0000);
+        dma.handle_channel_mask_register_write(0x00);
+        dma.run(&mut bus);
+        assert_eq!(dma.get_dma_transfer_address(0), 0x0001);
+    }
+}
@@ -1167,6 +1167,11 @@ impl VideoCard for EGACard {
         (palette, intensity)
     }    
 
+    /// The color burst is always present in CGA compatible modes.
+    fn is_color_burst_enabled(&self) -> bool {
+        true
+    }
+
     #[allow (dead_code)]
     /// Returns a string representation of all the CRTC Registers.
     fn get_videocard_string_state(&self) -> HashMap<String, Vec<(String, VideoCardStateEntry)>> {
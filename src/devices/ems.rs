@@ -0,0 +1,220 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+    --------------------------------------------------------------------------
+
+    devices::ems.rs
+
+    Implements a LIM EMS expanded memory board with a simple register
+    interface, in the style of the Lo-tech 2MB EMS board.
+
+    The board provides a 64K page frame in upper memory, divided into four
+    16K physical pages. Each physical page has a write-only page register at
+    consecutive IO ports from the board's base port. Writing a logical page
+    number to a register maps that 16K page of the board's memory pool into
+    the corresponding physical page. Page numbers beyond the size of the pool
+    wrap, as unused high address lines are not decoded.
+
+    The mapping is kept as an offset into the pool for each physical page, so
+    an access is a single table lookup. The page registers reset to page 0.
+    An EMM driver for the board (such as LTEMM.EXE) provides the LIM API.
+
+*/
+
+use crate::bus::{BusInterface, IoDevice, MemoryMappedDevice, DeviceRunTimeUnit, NO_IO_BYTE};
+
+pub const EMS_DEFAULT_PAGE_FRAME: usize = 0xE0000;
+pub const EMS_DEFAULT_IO_BASE: u16 = 0x260;
+pub const EMS_DEFAULT_SIZE: usize = 0x200000;
+
+pub const EMS_PAGE_SIZE: usize = 0x4000;
+pub const EMS_PAGE_FRAME_SIZE: usize = 0x10000;
+pub const EMS_PHYSICAL_PAGES: usize = 4;
+
+const EMS_PAGE_SHIFT: usize = 14;
+const EMS_PAGE_MASK: usize = EMS_PAGE_SIZE - 1;
+
+pub struct EmsCard {
+    page_frame: usize,
+    io_base: u16,
+    pool: Vec<u8>,
+    logical_pages: usize,
+    page_regs: [u8; EMS_PHYSICAL_PAGES],
+    page_offsets: [usize; EMS_PHYSICAL_PAGES],
+}
+
+#[derive(Clone, Default)]
+pub struct EmsStringState {
+    pub page_frame: String,
+    pub io_base: String,
+    pub logical_pages: String,
+    /// The address of each physical page and the logical page mapped into it
+    pub mapping: Vec<(String, String)>,
+}
+
+impl IoDevice for EmsCard {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // Page registers are write-only
+        NO_IO_BYTE
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        let physical_page = port.wrapping_sub(self.io_base) as usize;
+        if physical_page < EMS_PHYSICAL_PAGES {
+            self.map_page(physical_page, data);
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (0..EMS_PHYSICAL_PAGES as u16).map(|p| self.io_base + p).collect()
+    }
+}
+
+impl EmsCard {
+    /// Create an EMS board with a pool of 'size' bytes, rounded down to a whole number of pages.
+    /// 'page_frame' is the linear address of the page frame and must be 16K aligned.
+    pub fn new(page_frame: usize, io_base: u16, size: usize) -> Self {
+        // Page registers are 8 bits.
+        let logical_pages = usize::clamp(size / EMS_PAGE_SIZE, 1, 256);
+
+        Self {
+            page_frame: page_frame & !EMS_PAGE_MASK,
+            io_base,
+            pool: vec![0; logical_pages * EMS_PAGE_SIZE],
+            logical_pages,
+            page_regs: [0; EMS_PHYSICAL_PAGES],
+            page_offsets: [0; EMS_PHYSICAL_PAGES],
+        }
+    }
+
+    pub fn page_frame(&self) -> usize {
+        self.page_frame
+    }
+
+    pub fn logical_pages(&self) -> usize {
+        self.logical_pages
+    }
+
+    /// Map a logical page into one of the four physical pages of the page frame.
+    pub fn map_page(&mut self, physical_page: usize, logical_page: u8) {
+        self.page_regs[physical_page] = logical_page;
+        self.page_offsets[physical_page] = (logical_page as usize % self.logical_pages) << EMS_PAGE_SHIFT;
+    }
+
+    pub fn reset(&mut self) {
+        for physical_page in 0..EMS_PHYSICAL_PAGES {
+            self.map_page(physical_page, 0);
+        }
+    }
+
+    /// Translate an address within the page frame to an index into the memory pool.
+    #[inline]
+    fn pool_index(&self, address: usize) -> usize {
+        let frame_offset = address.wrapping_sub(self.page_frame) & (EMS_PAGE_FRAME_SIZE - 1);
+        self.page_offsets[frame_offset >> EMS_PAGE_SHIFT] + (frame_offset & EMS_PAGE_MASK)
+    }
+
+    pub fn get_string_state(&self) -> EmsStringState {
+        EmsStringState {
+            page_frame: format!("{:05X}", self.page_frame),
+            io_base: format!("{:03X}", self.io_base),
+            logical_pages: format!("{}", self.logical_pages),
+            mapping: self.page_regs
+                .iter()
+                .enumerate()
+                .map(|(i, page)| {
+                    (
+                        format!("{:05X}", self.page_frame + i * EMS_PAGE_SIZE),
+                        format!("{}", *page as usize % self.logical_pages)
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+impl MemoryMappedDevice for EmsCard {
+    fn get_read_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn read_u8(&mut self, address: usize, _cycles: u32) -> (u8, u32) {
+        (self.pool[self.pool_index(address)], 0)
+    }
+
+    fn read_u16(&mut self, address: usize, cycles: u32) -> (u16, u32) {
+        // The two bytes of a word may fall in different physical pages.
+        let (lo, _) = MemoryMappedDevice::read_u8(self, address, cycles);
+        let (hi, _) = MemoryMappedDevice::read_u8(self, address + 1, cycles);
+        ((hi as u16) << 8 | lo as u16, 0)
+    }
+
+    fn get_write_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn write_u8(&mut self, address: usize, data: u8, _cycles: u32) -> u32 {
+        let index = self.pool_index(address);
+        self.pool[index] = data;
+        0
+    }
+
+    fn write_u16(&mut self, address: usize, data: u16, cycles: u32) -> u32 {
+        MemoryMappedDevice::write_u8(self, address, (data & 0xFF) as u8, cycles);
+        MemoryMappedDevice::write_u8(self, address + 1, (data >> 8) as u8, cycles);
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ems_mapping() {
+        let mut ems = EmsCard::new(EMS_DEFAULT_PAGE_FRAME, EMS_DEFAULT_IO_BASE, 0x10000);
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+        assert_eq!(ems.logical_pages(), 4);
+        assert_eq!(ems.port_list(), vec![0x260, 0x261, 0x262, 0x263]);
+
+        // Map logical pages 2 and 3 into physical pages 0 and 1
+        IoDevice::write_u8(&mut ems, 0x260, 2, None, delta);
+        IoDevice::write_u8(&mut ems, 0x261, 3, None, delta);
+        MemoryMappedDevice::write_u8(&mut ems, 0xE0000, 0x12, 0);
+        MemoryMappedDevice::write_u8(&mut ems, 0xE4000, 0x34, 0);
+
+        // Physical page 1 now sees what was written through physical page 0
+        IoDevice::write_u8(&mut ems, 0x261, 2, None, delta);
+        assert_eq!(MemoryMappedDevice::read_u8(&mut ems, 0xE4000, 0).0, 0x12);
+        assert_eq!(ems.page_regs, [2, 2, 0, 0]);
+
+        // A word crossing physical pages is split between their logical pages
+        IoDevice::write_u8(&mut ems, 0x260, 3, None, delta);
+        MemoryMappedDevice::write_u16(&mut ems, 0xE3FFF, 0xBEEF, 0);
+        assert_eq!(MemoryMappedDevice::read_u16(&mut ems, 0xE3FFF, 0).0, 0xBEEF);
+        assert_eq!(ems.pool[3 * EMS_PAGE_SIZE + EMS_PAGE_MASK], 0xEF);
+        assert_eq!(ems.pool[2 * EMS_PAGE_SIZE], 0xBE);
+
+        // Page numbers beyond the pool wrap
+        IoDevice::write_u8(&mut ems, 0x263, 7, None, delta);
+        assert_eq!(MemoryMappedDevice::read_u8(&mut ems, 0xEFFFF, 0).0, 0xEF);
+        assert_eq!(ems.get_string_state().mapping[3], ("EC000".to_string(), "3".to_string()));
+
+        assert_eq!(IoDevice::read_u8(&mut ems, 0x260, delta), NO_IO_BYTE);
+    }
+}
@@ -13,6 +13,7 @@ use crate::devices::{
     pic,
 };
 use crate::bus::BusInterface;
+use crate::bitstream::BitstreamDisk;
 
 pub const FDC_IRQ: u8 = 0x06;
 pub const FDC_DMA: usize = 2;
@@ -79,7 +80,7 @@ pub const ST0_HEAD_ACTIVE: u8   = 0b0000_0100;
 pub const ST0_NOT_READY: u8     = 0b0000_1000;
 pub const ST0_UNIT_CHECK: u8    = 0b0001_0000;
 pub const ST0_SEEK_END: u8      = 0b0010_0000;
-pub const ST0_ABNORMAL_TERMINATION: u8 = 0b01000_0000;
+pub const ST0_ABNORMAL_TERMINATION: u8 = 0b0100_0000;
 pub const ST0_INVALID_OPCODE: u8    = 0b1000_0000;
 pub const ST0_ABNORMAL_POLLING: u8  = 0b1100_0000;
 pub const ST0_RESET: u8             = 0b1100_0000;
@@ -87,6 +88,11 @@ pub const ST0_RESET: u8             = 0b1100_0000;
 pub const ST1_NO_ID: u8         = 0b0000_0001;
 pub const ST1_WRITE_PROTECT: u8 = 0b0000_0010;
 pub const ST1_NODATA: u8        = 0b0000_0100;
+pub const ST1_CRC_ERROR: u8     = 0b0010_0000;
+
+pub const ST2_NO_DAM: u8         = 0b0000_0001;
+pub const ST2_DATA_CRC_ERROR: u8 = 0b0010_0000;
+pub const ST2_CONTROL_MARK: u8   = 0b0100_0000;
 
 
 pub const ST3_ESIG: u8          = 0b1000_0000;
@@ -96,64 +102,159 @@ pub const ST3_TRACK0: u8        = 0b0001_0000;
 pub const ST3_DOUBLESIDED: u8   = 0b0000_1000;
 pub const ST3_HEAD: u8          = 0b0000_0100;
 
+/// Physical size of the diskette a format is written on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiskFormFactor {
+    FiveQuarter,
+    ThreeHalf,
+}
+
+/// Recording density of a disk format, which determines the data rate the FDC must use.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiskDensity {
+    /// 250Kbps MFM (300Kbps in a high density 5.25" drive)
+    Double,
+    /// 500Kbps MFM
+    High,
+    /// 1Mbps MFM
+    Extended,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct DiskFormat {
-    cylinders: u8,
-    heads: u8,
-    sectors: u8
+    pub cylinders: u8,
+    pub heads: u8,
+    pub sectors: u8,
+    pub sector_size: usize,
+    pub form_factor: DiskFormFactor,
+    pub density: DiskDensity,
+}
+
+impl DiskFormat {
+    /// Look up the standard disk format for a raw sector image of the specified size.
+    pub fn from_image_size(image_len: usize) -> Option<&'static DiskFormat> {
+        DISK_FORMATS.get(&image_len)
+    }
+
+    /// Return the size in bytes of the smallest standard disk format.
+    pub fn min_image_size() -> usize {
+        DISK_FORMATS.keys().copied().min().unwrap_or_default()
+    }
+
+    /// Build a format for a non-standard geometry of 512 byte sectors. The density and form
+    /// factor are inferred from the number of cylinders and sectors per track.
+    pub fn from_geometry(cylinders: u8, heads: u8, sectors: u8) -> DiskFormat {
+        let density = match sectors {
+            0..=10 => DiskDensity::Double,
+            11..=21 => DiskDensity::High,
+            _ => DiskDensity::Extended,
+        };
+        // 40 track and 15 sector high density formats are only written on 5.25" disks
+        let form_factor = if cylinders <= 42 || (density == DiskDensity::High && sectors <= 15) {
+            DiskFormFactor::FiveQuarter
+        }
+        else {
+            DiskFormFactor::ThreeHalf
+        };
+        DiskFormat {
+            cylinders,
+            heads,
+            sectors,
+            sector_size: SECTOR_SIZE,
+            form_factor,
+            density,
+        }
+    }
+
+    /// Return the size in bytes of a raw sector image with this format.
+    pub fn image_size(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors as usize * self.sector_size
+    }
 }
 
 lazy_static! {
     static ref DISK_FORMATS: HashMap<usize, DiskFormat> = {
         let map = HashMap::from([
             (
-                163_840, 
+                163_840,
                 DiskFormat{
                     cylinders: 40,
                     heads: 1,
-                    sectors: 8
+                    sectors: 8,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::FiveQuarter,
+                    density: DiskDensity::Double
                 }
             ),(
                 184_320,
                 DiskFormat{
                     cylinders: 40,
                     heads: 1,
-                    sectors: 9
+                    sectors: 9,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::FiveQuarter,
+                    density: DiskDensity::Double
                 }
             ),(
                 327_680,
                 DiskFormat{
                     cylinders: 40,
                     heads: 2,
-                    sectors: 8
+                    sectors: 8,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::FiveQuarter,
+                    density: DiskDensity::Double
                 }
-            ),( 
+            ),(
                 368_640,
                 DiskFormat{
                     cylinders: 40,
                     heads: 2,
-                    sectors: 9
+                    sectors: 9,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::FiveQuarter,
+                    density: DiskDensity::Double
                 }
             ),(
                 737_280,
                 DiskFormat{
                     cylinders: 80,
                     heads: 2,
-                    sectors: 9
-                }                
+                    sectors: 9,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::ThreeHalf,
+                    density: DiskDensity::Double
+                }
             ),(
                 1_228_800,
                 DiskFormat{
                     cylinders: 80,
                     heads: 2,
-                    sectors: 15
-                }                                
+                    sectors: 15,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::FiveQuarter,
+                    density: DiskDensity::High
+                }
+            ),(
+                1_474_560,
+                DiskFormat{
+                    cylinders: 80,
+                    heads: 2,
+                    sectors: 18,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::ThreeHalf,
+                    density: DiskDensity::High
+                }
             ),(
-                1474560,
-                DiskFormat{ 
+                2_949_120,
+                DiskFormat{
                     cylinders: 80,
                     heads: 2,
-                    sectors: 18
-                }                                
+                    sectors: 36,
+                    sector_size: SECTOR_SIZE,
+                    form_factor: DiskFormFactor::ThreeHalf,
+                    density: DiskDensity::Extended
+                }
             )
         ]);
         map
@@ -209,6 +310,12 @@ pub enum DriveError {
     BadWrite,
     WriteProtect,
     DMAError,
+    NoAddressMark,
+    SectorNotFound,
+    IdCrcError,
+    NoDataAddressMark,
+    DataCrcError,
+    DeletedData,
 }
 
 /// Classify operations - an Operation is intiated by any Command that does not immediately
@@ -236,7 +343,11 @@ pub struct DiskDrive {
     positioning: bool,
     have_disk: bool,
     write_protected: bool,
-    disk_image: Vec<u8>
+    dirty: bool,
+    disk_image: Vec<u8>,
+    /// Bitstream tracks for disks loaded from surface images. When present, sectors are
+    /// decoded from the track under the head instead of read from disk_image.
+    bitstream: Option<BitstreamDisk>,
 }
 
 impl DiskDrive {
@@ -254,7 +365,52 @@ impl DiskDrive {
             positioning: false,
             have_disk: false,
             write_protected: false,
+            dirty: false,
             disk_image: Vec::new(),
+            bitstream: None,
+        }
+    }
+}
+
+/// Describes the most recent command processed by the controller, and running totals of 
+/// commands since reset, for display in the debugger. Sense Interrupt Status commands are 
+/// not recorded, as they are issued after every seek and would hide the command of interest.
+#[derive (Clone, Debug)]
+pub struct FloppyDriveStatus {
+    pub last_command: Command,
+    pub drive_select: usize,
+    pub cylinder: u8,
+    pub head: u8,
+    pub sector: u8,
+    /// Number of sectors transferred by the last read or write command
+    pub sector_count: u32,
+    pub error: DriveError,
+    pub msr: u8,
+    pub st0: u8,
+    pub st1: u8,
+    pub st2: u8,
+    pub reads: u64,
+    pub writes: u64,
+    pub seeks: u64,
+}
+
+impl Default for FloppyDriveStatus {
+    fn default() -> Self {
+        Self {
+            last_command: Command::NoCommand,
+            drive_select: 0,
+            cylinder: 0,
+            head: 0,
+            sector: 0,
+            sector_count: 0,
+            error: DriveError::NoError,
+            msr: 0,
+            st0: 0,
+            st1: 0,
+            st2: 0,
+            reads: 0,
+            writes: 0,
+            seeks: 0,
         }
     }
 }
@@ -300,7 +456,14 @@ pub struct FloppyController {
 
     in_dma: bool,
     dma_byte_count: usize,
-    dma_bytes_left: usize
+    dma_bytes_left: usize,
+
+    /// Sector data decoded from a bitstream track for the current read operation.
+    read_buffer: Vec<u8>,
+    /// The last sector read from a bitstream track, or the sector the read stopped at.
+    read_last_sector: u8,
+
+    status: FloppyDriveStatus,
 }
 
 /// IO Port handlers for the FDC
@@ -388,6 +551,11 @@ impl FloppyController {
             in_dma: false,
             dma_byte_count: 0,
             dma_bytes_left: 0,
+
+            read_buffer: Vec::new(),
+            read_last_sector: 0,
+
+            status: Default::default(),
         }
     }
 
@@ -427,15 +595,14 @@ impl FloppyController {
         self.dma_byte_count = 0;
         self.dma_bytes_left = 0;
 
+        self.read_buffer.clear();
+        self.read_last_sector = 0;
 
+        self.status = Default::default();
     }
 
-    /// Load a disk into the specified drive
+    /// Load a disk into the specified drive, looking up its geometry from the image size.
     pub fn load_image_from(&mut self, drive_select: usize, src_vec: Vec<u8>) -> Result<(), &'static str>  {
-        
-        if drive_select >= FDC_MAX_DRIVES {
-            return Err("Invalid drive selection");
-        }
 
         let image_len: usize = src_vec.len();
 
@@ -445,28 +612,44 @@ impl FloppyController {
         }
 
         // Look up disk parameters based on image size
-        if let Some(fmt) = DISK_FORMATS.get(&image_len) {
-            self.drives[drive_select].max_cylinders = fmt.cylinders;
-            self.drives[drive_select].max_heads = fmt.heads;
-            self.drives[drive_select].max_sectors = fmt.sectors;
+        let fmt = match DISK_FORMATS.get(&image_len) {
+            Some(fmt) => *fmt,
+            // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
+            // This is useful for loading things like boot sector images without having to copy them to
+            // a full disk image
+            None if image_len < DiskFormat::min_image_size() => DISK_FORMATS[&DiskFormat::min_image_size()],
+            None => return Err("Invalid image length")
+        };
+
+        self.load_image_with_format(drive_select, src_vec, fmt)
+    }
+
+    /// Load a disk into the specified drive with the given geometry. This allows images with
+    /// a non-standard size to be loaded once their geometry has been specified. The image may
+    /// be shorter than the geometry describes.
+    pub fn load_image_with_format(&mut self, drive_select: usize, src_vec: Vec<u8>, fmt: DiskFormat) -> Result<(), &'static str> {
+        
+        if drive_select >= FDC_MAX_DRIVES {
+            return Err("Invalid drive selection");
         }
-        else {
-            // No image format found. 
-            if image_len < 163_840 {
-                // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
-                // This is useful for loading things like boot sector images without having to copy them to
-                // a full disk image
-                self.drives[drive_select].max_cylinders = 40;
-                self.drives[drive_select].max_heads = 1;
-                self.drives[drive_select].max_sectors = 8;
-            }
-            else {
-                return Err("Invalid image length")
-            }
+
+        if fmt.sector_size != SECTOR_SIZE {
+            return Err("Unsupported sector size")
         }
 
+        if src_vec.len() % SECTOR_SIZE > 0 || src_vec.len() > fmt.image_size() {
+            return Err("Invalid image length")
+        }
+
+        self.drives[drive_select].max_cylinders = fmt.cylinders;
+        self.drives[drive_select].max_heads = fmt.heads;
+        self.drives[drive_select].max_sectors = fmt.sectors;
+
         self.drives[drive_select].have_disk = true;
+        self.drives[drive_select].dirty = false;
+        self.drives[drive_select].write_protected = false;
         self.drives[drive_select].disk_image = src_vec;
+        self.drives[drive_select].bitstream = None;
         log::debug!("Loaded floppy image, size: {} c: {} h: {} s: {}", 
             self.drives[drive_select].disk_image.len(),
             self.drives[drive_select].max_cylinders,
@@ -477,7 +660,45 @@ impl FloppyController {
         Ok(())
     }
 
-    /// Unload (eject) the disk in the specified drive
+    /// Load a bitstream disk into the specified drive. Writing to bitstream disks is not yet
+    /// supported, so the disk is always write protected.
+    pub fn load_bitstream_from(&mut self, drive_select: usize, mut disk: BitstreamDisk) -> Result<(), &'static str> {
+
+        if drive_select >= FDC_MAX_DRIVES {
+            return Err("Invalid drive selection");
+        }
+
+        if disk.cylinders == 0 || disk.heads == 0 {
+            return Err("Disk has no tracks");
+        }
+
+        // The highest sector number on the first track is used as the track length when 
+        // advancing to the next sector after a transfer.
+        let max_sectors = disk.track_mut(0, 0)
+            .and_then(|track| track.decode_sectors().iter().map(|s| s.id.r).max())
+            .unwrap_or(9);
+
+        let drive = &mut self.drives[drive_select];
+        drive.max_cylinders = disk.cylinders;
+        drive.max_heads = disk.heads;
+        drive.max_sectors = max_sectors;
+        drive.have_disk = true;
+        drive.dirty = false;
+        drive.write_protected = true;
+        drive.disk_image.clear();
+        drive.bitstream = Some(disk);
+
+        log::debug!("Loaded bitstream floppy image, c: {} h: {} s: {}", 
+            drive.max_cylinders,
+            drive.max_heads,
+            drive.max_sectors
+        );
+
+        Ok(())
+    }
+
+    /// Unload (eject) the disk in the specified drive. The drive is left empty and reports
+    /// not ready until another disk is loaded.
     pub fn unload_image(&mut self, drive_select: usize) {
         let drive = &mut self.drives[drive_select];
 
@@ -488,7 +709,47 @@ impl FloppyController {
         drive.max_heads = 1;
         drive.max_sectors = 8;
         drive.have_disk = false;
+        drive.ready = false;
+        drive.dirty = false;
+        drive.write_protected = false;
         drive.disk_image.clear();
+        drive.bitstream = None;
+    }
+
+    /// Return the image data for the disk in the specified drive, if any. Bitstream disks
+    /// have no flat image data.
+    pub fn get_image_data(&self, drive_select: usize) -> Option<&[u8]> {
+        match self.drives.get(drive_select) {
+            Some(drive) if drive.have_disk && drive.bitstream.is_none() => Some(&drive.disk_image),
+            _ => None
+        }
+    }
+
+    /// Return whether the disk in the specified drive has been written to since it was
+    /// loaded or last saved.
+    pub fn is_dirty(&self, drive_select: usize) -> bool {
+        self.drives.get(drive_select).map_or(false, |drive| drive.dirty)
+    }
+
+    /// Mark the disk in the specified drive as saved.
+    pub fn clear_dirty(&mut self, drive_select: usize) {
+        if let Some(drive) = self.drives.get_mut(drive_select) {
+            drive.dirty = false;
+        }
+    }
+
+    /// Return the status of the most recent command for display in the debugger.
+    pub fn get_status(&mut self) -> FloppyDriveStatus {
+        let mut status = self.status.clone();
+        status.msr = self.handle_status_register_read();
+        status
+    }
+
+    fn set_status_chs(&mut self, drive_select: usize, cylinder: u8, head: u8, sector: u8) {
+        self.status.drive_select = drive_select;
+        self.status.cylinder = cylinder;
+        self.status.head = head;
+        self.status.sector = sector;
     }
 
     pub fn handle_status_register_read(&mut self) -> u8 {
@@ -635,9 +896,18 @@ impl FloppyController {
 
         // Set the "No Data" bit if we received an invalid request
         match self.last_error {
-            DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => {
+            DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek | DriveError::SectorNotFound => {
                 st1_byte |= ST1_NODATA
             }
+            DriveError::NoAddressMark | DriveError::NoDataAddressMark => {
+                st1_byte |= ST1_NO_ID
+            }
+            DriveError::IdCrcError | DriveError::DataCrcError => {
+                st1_byte |= ST1_CRC_ERROR
+            }
+            DriveError::WriteProtect => {
+                st1_byte |= ST1_WRITE_PROTECT
+            }
             _=> {}
         }
 
@@ -652,9 +922,14 @@ impl FloppyController {
 
     /// Generate the value of the ST2 Status Register in response to a command
     pub fn make_st2_byte(&self, _drive_select: usize) -> u8 {
-        // The ST2 status register contains mostly error codes, so for now we can just always return success
-        // by returning 0 until we handle possible errors.
-        0
+        // The ST2 status register contains mostly error codes. These can only occur when decoding
+        // sectors from a bitstream disk.
+        match self.last_error {
+            DriveError::NoDataAddressMark => ST2_NO_DAM,
+            DriveError::DataCrcError => ST2_DATA_CRC_ERROR,
+            DriveError::DeletedData => ST2_CONTROL_MARK,
+            _ => 0
+        }
     }
 
     /// Generate the value of the ST3 Status Register in response to a command
@@ -675,8 +950,8 @@ impl FloppyController {
             st3_byte |= ST3_TRACK0;
         }
 
-        // Drive ready - Should drive be ready when no disk is present?
-        if self.drives[drive_select].ready {
+        // Drive ready. An empty drive is never ready, even with its motor on.
+        if self.drives[drive_select].ready && self.drives[drive_select].have_disk {
             st3_byte |= ST3_READY;
         }
 
@@ -819,6 +1094,10 @@ impl FloppyController {
                     // We read last byte expected for this command, so dispatch to the appropriate command handler
                     // We read last byte expected for this command, so dispatch to the appropriate command handler
                     let mut result = Continuation::CommandComplete;
+
+                    self.status.last_command = self.command;
+                    self.status.sector_count = 0;
+                    self.status.error = DriveError::NoError;
                     
                     match self.command_fn {
                         None => log::error!("No associated method for command: {:?}!", self.command),
@@ -827,6 +1106,11 @@ impl FloppyController {
                         }
                     }
 
+                    // Commands without a result phase report errors via Sense Interrupt Status
+                    if !matches!(self.last_error, DriveError::NoError) {
+                        self.status.error = self.last_error;
+                    }
+
                     // Clear command if complete
                     if let Continuation::CommandComplete = result {
 
@@ -977,6 +1261,9 @@ impl FloppyController {
         self.drives[drive_select].cylinder = 0;
         self.drives[drive_select].head = head_select;
         self.drives[drive_select].sector = 1;
+
+        self.set_status_chs(drive_select, 0, head_select, 1);
+        self.status.seeks += 1;
         
         log::trace!("command_calibrate_drive completed: {}", drive_select);
 
@@ -997,6 +1284,9 @@ impl FloppyController {
         let drive_select = (drive_head_select & 0x03) as usize;
         let head_select = (drive_head_select >> 2) & 0x01;
 
+        self.set_status_chs(drive_select, cylinder, head_select, 1);
+        self.status.seeks += 1;
+
         // Is this seek out of bounds?
         if !self.is_id_valid(drive_select, cylinder, head_select, 1) {
             self.last_error = DriveError::BadSeek;
@@ -1040,6 +1330,9 @@ impl FloppyController {
         // Set drive_select for status register reads
         self.drive_select = drive_select;
 
+        self.set_status_chs(drive_select, cylinder, head, sector);
+        self.status.reads += 1;
+
         // Is there no disk in the drive?
         // 
         // Initially I had this command send an interrupt and try to return some error code in the 
@@ -1051,8 +1344,10 @@ impl FloppyController {
             return Continuation::CommandComplete
         }
 
-        // Is this read out of bounds?
-        if !self.is_id_valid(drive_select, cylinder, head, sector) {
+        // Is this read out of bounds? Sector IDs on bitstream disks can have any value, so they
+        // are checked when the track is decoded instead.
+        let bitstream = self.drives[drive_select].bitstream.is_some();
+        if !bitstream && !self.is_id_valid(drive_select, cylinder, head, sector) {
             self.last_error = DriveError::BadRead;
             self.send_interrupt = true;
             log::warn!("command_read_sector: invalid chs: drive:{}, c:{} h:{} s:{}", 
@@ -1060,8 +1355,11 @@ impl FloppyController {
             return Continuation::CommandComplete;
         }
 
-        // "Seek" to values given in command
-        self.drives[drive_select].cylinder = cylinder;
+        // "Seek" to values given in command. Bitstream disks are read from the track under the 
+        // head, whose sector IDs may not match the physical cylinder.
+        if !bitstream {
+            self.drives[drive_select].cylinder = cylinder;
+        }
         self.drives[drive_select].head = head;
         self.drives[drive_select].sector = sector;
         
@@ -1121,6 +1419,18 @@ impl FloppyController {
             log::warn!("command_write_sector: non-matching head specifiers");
         }
 
+        self.set_status_chs(drive_select, cylinder, head, sector);
+        self.status.writes += 1;
+
+        // Bitstream disks can't be written yet, so fail as if the disk was write protected
+        if self.drives[drive_select].bitstream.is_some() {
+            self.drive_select = drive_select;
+            self.last_error = DriveError::WriteProtect;
+            self.send_results_phase(InterruptCode::AbnormalTermination, drive_select, cylinder, head, sector, sector_size);
+            self.send_interrupt = true;
+            return Continuation::CommandComplete;
+        }
+
         // Set CHS
         self.drives[drive_select].cylinder = cylinder;
         self.drives[drive_select].head = head;
@@ -1225,6 +1535,11 @@ impl FloppyController {
         let st1_byte = self.make_st1_byte(drive_select);
         let st2_byte = self.make_st2_byte(drive_select);
 
+        self.status.st0 = st0_byte;
+        self.status.st1 = st1_byte;
+        self.status.st2 = st2_byte;
+        self.status.error = self.last_error;
+
         // Push result codes into FIFO
         self.data_register_out.clear();
         self.data_register_out.push_back(st0_byte);
@@ -1249,7 +1564,7 @@ impl FloppyController {
         head: u8,
         sector: u8, 
         sector_size: u8, 
-        _track_len: u8 ) {
+        track_len: u8 ) {
 
         if !self.in_dma {
             log::error!("FDC in invalid state: ReadSector operation without DMA! Aborting.");
@@ -1275,8 +1590,14 @@ impl FloppyController {
 
             self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
             self.operation_init = true;
+
+            if self.drives[self.drive_select].bitstream.is_some() {
+                self.read_bitstream_sectors(cylinder, head, sector, track_len);
+            }
         }
 
+        let bitstream = self.drives[self.drive_select].bitstream.is_some();
+
         if self.dma_bytes_left > 0 {
             // Bytes left to transfer
 
@@ -1286,12 +1607,19 @@ impl FloppyController {
                 let byte_address = base_address + self.dma_byte_count;
 
                 //log::trace!("Byte address for FDC read: {:04X}", byte_address);
-                if byte_address >= self.drives[self.drive_select].disk_image.len() {
+                if bitstream && self.dma_byte_count >= self.read_buffer.len() {
+                    // All sectors decoded from the track have been transferred
+                    self.dma_bytes_left = 0;
+                }
+                else if !bitstream && byte_address >= self.drives[self.drive_select].disk_image.len() {
                     log::error!("Read past end of disk image: {}/{}!", byte_address, self.drives[self.drive_select].disk_image.len() );
                     self.dma_bytes_left = 0;
                 }
                 else {
-                    let byte = self.drives[self.drive_select].disk_image[byte_address];
+                    let byte = match bitstream {
+                        true => self.read_buffer[self.dma_byte_count],
+                        false => self.drives[self.drive_select].disk_image[byte_address]
+                    };
 
                     dma.do_dma_write_u8(bus, FDC_DMA, byte);
                     self.dma_byte_count += 1;
@@ -1311,21 +1639,35 @@ impl FloppyController {
             // No more bytes left to transfer. Finalize operation
 
             let tc = dma.check_terminal_count(FDC_DMA);
-            if !tc {
+            if !tc && matches!(self.last_error, DriveError::NoError) {
                 log::warn!("FDC sector read complete without DMA terminal count.");
             }
 
+            self.status.sector_count = (self.dma_byte_count / SECTOR_SIZE) as u32;
             self.dma_byte_count = 0;
             self.dma_bytes_left = 0;
+            self.read_buffer.clear();
+
+            // A read from a bitstream disk stops at the first sector with an error, and reports
+            // the ID of that sector.
+            let (result, new_c, new_h, new_s) = match self.last_error {
+                DriveError::NoError => {
+                    let last_sector = if bitstream { self.read_last_sector } else { sector };
+                    let (c, h, s) = self.get_next_sector(self.drive_select, cylinder, head, last_sector);
+                    (InterruptCode::NormalTermination, c, h, s)
+                }
+                DriveError::DeletedData => (InterruptCode::NormalTermination, cylinder, head, self.read_last_sector),
+                _ => (InterruptCode::AbnormalTermination, cylinder, head, self.read_last_sector)
+            };
 
-            let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, cylinder, head, sector);
-
-            // Terminate normally by sending results registers
-            self.send_results_phase(InterruptCode::NormalTermination, self.drive_select, new_c, new_h, new_s, sector_size);
+            // Send results registers
+            self.send_results_phase(result, self.drive_select, new_c, new_h, new_s, sector_size);
 
-            // Set new CHS
-            self.drives[self.drive_select].cylinder = new_c;
-            self.drives[self.drive_select].head = new_h;
+            // Set new CHS. The physical cylinder of a bitstream disk only changes by seeking.
+            if !bitstream {
+                self.drives[self.drive_select].cylinder = new_c;
+                self.drives[self.drive_select].head = new_h;
+            }
             self.drives[self.drive_select].sector = new_s;
         
             // Finalize operation
@@ -1334,6 +1676,62 @@ impl FloppyController {
         }
     }
 
+    /// Decode the sectors for a read operation from the track under the head of a bitstream
+    /// disk into the read buffer, starting at the specified sector and continuing up to the
+    /// end of track sector. Decoding stops at the first sector with an error, which is 
+    /// reported in the result phase once the data read so far has been transferred.
+    fn read_bitstream_sectors(&mut self, cylinder: u8, head: u8, sector: u8, track_len: u8) {
+
+        self.read_buffer.clear();
+        self.read_last_sector = sector;
+
+        let drive = &mut self.drives[self.drive_select];
+        let (track_c, track_h) = (drive.cylinder, drive.head);
+        let sectors = match drive.bitstream.as_mut().and_then(|disk| disk.track_mut(track_c, track_h)) {
+            Some(track) => track.decode_sectors(),
+            None => Vec::new()
+        };
+
+        if sectors.is_empty() {
+            log::warn!("read_bitstream_sectors: no address marks on track c:{} h:{}", track_c, track_h);
+            self.last_error = DriveError::NoAddressMark;
+            return;
+        }
+
+        let mut r = sector;
+        loop {
+            self.read_last_sector = r;
+            let found = sectors.iter().find(|s| s.id.c == cylinder && s.id.h == head && s.id.r == r);
+
+            let error = match found {
+                None => DriveError::SectorNotFound,
+                Some(s) if !s.id_crc_ok => DriveError::IdCrcError,
+                Some(s) => match &s.data {
+                    None => DriveError::NoDataAddressMark,
+                    Some(data) => {
+                        // Data with a bad CRC or deleted data mark is still transferred
+                        self.read_buffer.extend_from_slice(data);
+                        match (s.data_crc_ok, s.deleted) {
+                            (false, _) => DriveError::DataCrcError,
+                            (true, true) => DriveError::DeletedData,
+                            (true, false) => DriveError::NoError
+                        }
+                    }
+                }
+            };
+
+            if !matches!(error, DriveError::NoError) {
+                log::trace!("read_bitstream_sectors: c:{} h:{} s:{} error: {:?}", cylinder, head, r, error);
+                self.last_error = error;
+                return;
+            }
+            if r >= track_len {
+                return;
+            }
+            r += 1;
+        }
+    }
+
     fn operation_write_sector(
         &mut self, 
         dma: &mut dma::DMAController, 
@@ -1387,6 +1785,7 @@ impl FloppyController {
 
                     let byte = dma.do_dma_read_u8(bus, FDC_DMA);
                     self.drives[self.drive_select].disk_image[byte_address] = byte;
+                    self.drives[self.drive_select].dirty = true;
                     self.dma_byte_count += 1;
                     self.dma_bytes_left -= 1;
 
@@ -1408,6 +1807,7 @@ impl FloppyController {
                 log::warn!("FDC sector write complete without DMA terminal count.");
             }
 
+            self.status.sector_count = (self.dma_byte_count / SECTOR_SIZE) as u32;
             self.dma_byte_count = 0;
             self.dma_bytes_left = 0;
 
@@ -1567,4 +1967,143 @@ impl FloppyController {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_command(fdc: &mut FloppyController, bytes: &[u8]) {
+        for byte in bytes {
+            fdc.handle_data_register_write(*byte);
+        }
+    }
+
+    #[test]
+    fn test_fdc_status() {
+        let mut fdc = FloppyController::new();
+        fdc.load_image_from(0, vec![0; 368_640]).unwrap();
+
+        send_command(&mut fdc, &[COMMAND_SEEK_HEAD, 0x04, 12]);
+        let status = fdc.get_status();
+        assert!(matches!(status.last_command, Command::SeekParkHead));
+        assert!(matches!(status.error, DriveError::NoError));
+        assert_eq!((status.drive_select, status.cylinder, status.head, status.sector), (0, 12, 1, 1));
+
+        // Seek past the last cylinder
+        send_command(&mut fdc, &[COMMAND_SEEK_HEAD, 0x00, 45]);
+        let status = fdc.get_status();
+        assert!(matches!(status.error, DriveError::BadSeek));
+        assert_eq!(status.cylinder, 45);
+
+        // Sense Interrupt Status does not replace the last command
+        send_command(&mut fdc, &[COMMAND_SENSE_INT_STATUS]);
+        assert!(matches!(fdc.get_status().last_command, Command::SeekParkHead));
+
+        send_command(&mut fdc, &[COMMAND_CALIBRATE_DRIVE, 0x00]);
+        let status = fdc.get_status();
+        assert!(matches!(status.last_command, Command::CalibrateDrive));
+        assert!(matches!(status.error, DriveError::NoError));
+        assert_eq!((status.seeks, status.reads, status.writes), (3, 0, 0));
+
+        fdc.reset();
+        assert_eq!(fdc.get_status().seeks, 0);
+    }
+
+    #[test]
+    fn test_fdc_read_crc_error() {
+        use crate::bitstream::{BitstreamTrack, DecodedSector, SectorId};
+        use crate::devices::pic::Pic;
+
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        let mut dma = dma::DMAController::new();
+        let mut fdc = FloppyController::new();
+
+        // A single track of nine sectors, where sector 3 has a bad data CRC
+        let sectors: Vec<DecodedSector> = (1..=9u8).map(|r| DecodedSector {
+            id: SectorId { c: 0, h: 0, r, n: 2 },
+            id_crc_ok: true,
+            data: Some(vec![r; SECTOR_SIZE]),
+            data_crc_ok: r != 3,
+            deleted: false,
+        }).collect();
+        let mut disk = BitstreamDisk::new(1, 1);
+        disk.set_track(0, 0, BitstreamTrack::encode(&sectors, 100_000));
+        fdc.load_bitstream_from(0, disk).unwrap();
+
+        // Program DMA channel 2 to write 8 sectors to memory at 01000
+        let count = 8 * SECTOR_SIZE - 1;
+        dma.handle_clear_flopflop();
+        dma.handle_addr_port_write(FDC_DMA, 0x00);
+        dma.handle_addr_port_write(FDC_DMA, 0x10);
+        dma.handle_wc_port_write(FDC_DMA, (count & 0xFF) as u8);
+        dma.handle_wc_port_write(FDC_DMA, (count >> 8) as u8);
+        dma.handle_page_register_write(FDC_DMA, 0);
+        dma.handle_channel_mode_register_write(0x44 | FDC_DMA as u8);
+        dma.handle_channel_mask_register_write(FDC_DMA as u8);
+
+        // Read sectors 2 through 9
+        send_command(&mut fdc, &[COMMAND_READ_SECTOR | 0x40, 0x00, 0, 0, 2, 2, 9, 0x2A, 0xFF]);
+        for _ in 0..(8 * SECTOR_SIZE + 10) {
+            fdc.run(&mut dma, &mut bus, 0.0);
+            if matches!(fdc.operation, Operation::NoOperation) {
+                break;
+            }
+        }
+        assert!(matches!(fdc.operation, Operation::NoOperation));
+
+        // The transfer stops after the bad sector, whose data is still transferred
+        assert_eq!(bus.read_u8(0x1000, 0).unwrap().0, 2);
+        assert_eq!(bus.read_u8(0x13FF, 0).unwrap().0, 3);
+        assert_eq!(bus.read_u8(0x1400, 0).unwrap().0, 0);
+
+        // The result phase reports the data CRC error at sector 3. The BIOS returns
+        // CRC error status (10h) for ST1 bit 5.
+        let result: Vec<u8> = (0..7).map(|_| fdc.handle_data_register_read()).collect();
+        assert_eq!(result[0] & 0xC0, ST0_ABNORMAL_TERMINATION);
+        assert_eq!(result[1], ST1_CRC_ERROR);
+        assert_eq!(result[2], ST2_DATA_CRC_ERROR);
+        assert_eq!(&result[3..], &[0, 0, 3, 2]);
+        assert!(matches!(fdc.get_status().error, DriveError::DataCrcError));
+    }
+
+    #[test]
+    fn test_fdc_empty_drive() {
+        use crate::devices::pic::Pic;
+
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        let mut dma = dma::DMAController::new();
+        let mut fdc = FloppyController::new();
+
+        // Attempt to boot from drive A as the BIOS does: turn on the motor and read the boot sector.
+        // With no disk the read never completes, so the BIOS times out and moves on.
+        let try_boot = |fdc: &mut FloppyController, dma: &mut dma::DMAController, bus: &mut BusInterface| {
+            fdc.handle_dor_write(DOR_FDC_RESET | DOR_MOTOR_FDD_A);
+            send_command(fdc, &[COMMAND_READ_SECTOR, 0x00, 0, 0, 1, 2, 9, 0x2A, 0xFF]);
+            for _ in 0..100 {
+                fdc.run(dma, bus, 0.0);
+            }
+            assert!(!fdc.pending_interrupt);
+
+            // Sense Drive Status reports the drive not ready
+            send_command(fdc, &[COMMAND_CHECK_DRIVE_STATUS, 0x00]);
+            let st3 = fdc.handle_data_register_read();
+            assert_eq!(st3 & ST3_READY, 0);
+        };
+
+        try_boot(&mut fdc, &mut dma, &mut bus);
+
+        // A blank disk is present and ready
+        fdc.load_image_from(0, vec![0; 368_640]).unwrap();
+        fdc.handle_dor_write(DOR_FDC_RESET | DOR_MOTOR_FDD_A);
+        send_command(&mut fdc, &[COMMAND_CHECK_DRIVE_STATUS, 0x00]);
+        assert_ne!(fdc.handle_data_register_read() & ST3_READY, 0);
+
+        // Ejecting the disk returns the drive to the empty state
+        fdc.unload_image(0);
+        assert!(fdc.get_image_data(0).is_none());
+        try_boot(&mut fdc, &mut dma, &mut bus);
+    }
+}
@@ -33,9 +33,11 @@ pub mod vga;
 pub mod pit;
 pub mod pic;
 pub mod ppi;
+pub mod speaker;
 pub mod serial;
 pub mod hdc;
 pub mod fdc;
 pub mod dma;
 pub mod mouse;
+pub mod ems;
 
@@ -2,6 +2,15 @@
     pic.rc
     Implement the 8259 PIC (Programmable Interrupt Controller)
 
+    The PIC latches interrupt requests in the IRR. The priority resolver selects 
+    the highest priority request not masked by the IMR and not blocked by an 
+    interrupt of equal or higher priority already in service (ISR), and raises 
+    INTR. On interrupt acknowledge the request is moved from the IRR to the ISR 
+    and the vector (ICW2 base + IR number) is supplied to the CPU. 
+
+    Priority is fixed (IR0 highest) unless rotated by an OCW2 rotate or set 
+    priority command.
+
 */
 
 #![allow(dead_code)]
@@ -29,11 +38,25 @@ const ICW4_NESTED: u8           = 0b0001_0000; // Bit on if Fully Nested mode
 
 const OCW_IS_OCW3: u8           = 0b0000_1000; // Bit on if OCW is OCW3
 
+const OCW2_COMMAND_MASK: u8     = 0b1110_0000; // R, SL and EOI bits of OCW2
+const OCW2_LEVEL_MASK: u8       = 0b0000_0111; // IR level for specific commands
+const OCW2_ROTATE_AEOI_CLEAR: u8 = 0b0000_0000;
 const OCW2_NONSPECIFIC_EOI: u8  = 0b0010_0000;
+const OCW2_NOP: u8              = 0b0100_0000;
 const OCW2_SPECIFIC_EOI: u8     = 0b0110_0000;
+const OCW2_ROTATE_AEOI_SET: u8  = 0b1000_0000;
+const OCW2_ROTATE_NONSPECIFIC_EOI: u8 = 0b1010_0000;
+const OCW2_SET_PRIORITY: u8     = 0b1100_0000;
+const OCW2_ROTATE_SPECIFIC_EOI: u8 = 0b1110_0000;
+
+const OCW3_ESMM: u8             = 0b0100_0000; // Bit on to enable setting of special mask mode
+const OCW3_SMM: u8              = 0b0010_0000; // Special mask mode (if ESMM set)
 const OCW3_POLL_COMMAND: u8     = 0b0000_0100;
 const OCW3_RR_COMMAND: u8       = 0b0000_0011;
 
+const ICW2_VECTOR_MASK: u8      = 0b1111_1000; // Bits of ICW2 that form the vector base in 8086 mode
+const POLL_INTERRUPT: u8        = 0b1000_0000; // Bit set in poll word if an interrupt was pending
+
 pub enum InitializationState {
     Normal,             // Normal operation, can receive an ICW1 at any point
     ExpectingICW2,      // In initialization sequence, expecting ICW2
@@ -86,7 +109,9 @@ pub struct Pic {
     buffered: bool,          // Buffered mode
     nested: bool,            // Nested mode
     special_nested: bool,    // Special fully nested mode
-    polled: bool,            // Polled mode
+    special_mask: bool,      // Special mask mode
+    lowest_priority: u8,     // IR level with the lowest priority. IR7 unless rotated
+    polled: bool,            // Polled mode. Next read of the command port returns the poll word
     auto_eoi: bool,          // Auto-EOI mode
     rotate_on_aeoi: bool,    // Should rotate in Auto-EOI mode
     trigger_mode: TriggerMode,
@@ -154,6 +179,8 @@ impl Pic {
             buffered: false,
             nested: true,
             special_nested: false,
+            special_mask: false,
+            lowest_priority: 7,
             polled: false,
             auto_eoi: false,
             trigger_mode: TriggerMode::Edge,
@@ -167,6 +194,7 @@ impl Pic {
 
     pub fn reset(&mut self) {
         self.init_state = InitializationState::Normal;
+        self.int_offset = PIC_INTERRUPT_OFFSET;
         self.imr = 0xFF;
         self.isr = 0x00;
         self.irr = 0x00;
@@ -177,6 +205,8 @@ impl Pic {
         self.buffered = false;
         self.nested = true;
         self.special_nested = false;
+        self.special_mask = false;
+        self.lowest_priority = 7;
         self.polled = false;
         self.auto_eoi = false;
         self.rotate_on_aeoi = false;
@@ -219,16 +249,21 @@ impl Pic {
                 self.trigger_mode = TriggerMode::Edge;
             }
 
-            self.init_state = InitializationState::ExpectingICW2;
-            if byte & ICW1_ICW4_NEEDED != 0 {
-                self.expecting_icw4 = true;
+            // ICW1 clears the IMR and the special mask mode, restores fixed priority and selects IRR 
+            // for reading.
+            self.imr = 0;
+            self.special_mask = false;
+            self.lowest_priority = 7;
+            self.read_select = ReadSelect::IRR;
+            self.polled = false;
+            self.expecting_icw4 = byte & ICW1_ICW4_NEEDED != 0;
+            // The edge sense circuit is reset, so latched requests are discarded.
+            if self.trigger_mode == TriggerMode::Edge {
+                self.irr = 0;
             }
-        }
-        else if byte & OCW2_NONSPECIFIC_EOI != 0 {
-            self.eoi(None);
-        }
-        else if byte & OCW2_SPECIFIC_EOI != 0 {
-            self.eoi(Some(byte & 0x07));
+
+            self.init_state = InitializationState::ExpectingICW2;
+            self.update_intr();
         }
         else if byte & OCW_IS_OCW3 != 0  { 
             
@@ -245,9 +280,41 @@ impl Pic {
             };
             self.read_select = rr;
 
+            if byte & OCW3_ESMM != 0 {
+                self.special_mask = byte & OCW3_SMM != 0;
+                self.update_intr();
+            }
+
+            self.polled = byte & OCW3_POLL_COMMAND != 0;
         }
         else {
-            log::trace!("PIC: Unhandled command: {:02X}", byte)
+            // OCW2
+            let level = byte & OCW2_LEVEL_MASK;
+            match byte & OCW2_COMMAND_MASK {
+                OCW2_NONSPECIFIC_EOI => {
+                    self.eoi(None);
+                }
+                OCW2_SPECIFIC_EOI => {
+                    self.eoi(Some(level));
+                }
+                OCW2_ROTATE_NONSPECIFIC_EOI => {
+                    if let Some(ir) = self.eoi(None) {
+                        self.lowest_priority = ir;
+                    }
+                }
+                OCW2_ROTATE_SPECIFIC_EOI => {
+                    self.eoi(Some(level));
+                    self.lowest_priority = level;
+                }
+                OCW2_SET_PRIORITY => {
+                    self.lowest_priority = level;
+                }
+                OCW2_ROTATE_AEOI_SET => self.rotate_on_aeoi = true,
+                OCW2_ROTATE_AEOI_CLEAR => self.rotate_on_aeoi = false,
+                OCW2_NOP => {}
+                _ => unreachable!()
+            };
+            self.update_intr();
         }
     }
 
@@ -255,58 +322,93 @@ impl Pic {
     /// An EOI resets a bit in the ISR.
     /// If an IR number is provided, it will perform a specific EOI and reset a specific bit.
     /// If None is provided, it will perform a non-specific EOI and reset the highest priority bit.
-    pub fn eoi(&mut self, line: Option<u8>)  {
+    /// Returns the IR level that was reset, if any.
+    pub fn eoi(&mut self, line: Option<u8>) -> Option<u8> {
 
-        if let Some(ir) = line {
+        let ir = match line {
             // Specific EOI
+            Some(ir) => Some(ir & 0x07),
+            // Non-specific EOI
+            None => self.get_highest_priority_is()
+        };
 
+        if let Some(ir) = ir {
             self.isr = Pic::clear_bit(self.isr, ir);
-            // Is there a corresponding bit set in the IRR?
-            if Pic::check_bit(self.irr, ir) {
-                // Raise INTR for new interrupt.
-                self.intr = true;
-            }
         }
-        else {
+        // Raise INTR if another interrupt can now be serviced.
+        self.update_intr();
+        ir
+    }
 
-            let ir = self.get_highest_priority_is();
+    /// Return the current priority of the specified IR level, where 0 is the highest priority.
+    pub fn priority(&self, ir: u8) -> u8 {
+        (ir + 7 - self.lowest_priority) % 8
+    }
 
-            self.isr = Pic::clear_bit(self.isr, ir);
-            // Is there a corresponding bit set in the IRR?
-            if Pic::check_bit(self.irr, ir) {
-                // Raise INTR for new interrupt.
-                self.intr = true;
-            }            
-        }
+    /// Return the highest priority IR level set in the specified bitfield.
+    fn highest_priority_bit(&self, bits: u8) -> Option<u8> {
+        (0..8)
+            .map(|p| (self.lowest_priority + 1 + p) % 8)
+            .find(|ir| Pic::check_bit(bits, *ir))
     }
 
-    pub fn get_highest_priority_ir(&self) -> u8 {
+    /// Return the highest priority IR level with a request in the IRR, regardless of masking.
+    pub fn get_highest_priority_ir(&self) -> Option<u8> {
+        self.highest_priority_bit(self.irr)
+    }
 
-        let mask: u8 = 0x01;
-        let mut ir = 0;
-        
-        for i in 0..8 {
-            ir = i;
-            if self.irr & (mask << ir) != 0 {
-                break;
-            }
-        }
-        ir
+    /// Return the highest priority IR level currently in service.
+    pub fn get_highest_priority_is(&self) -> Option<u8> {
+        self.highest_priority_bit(self.isr)
     }
 
-    pub fn get_highest_priority_is(&self) -> u8 {
+    /// The priority resolver. Returns the IR level that will be serviced on the next interrupt
+    /// acknowledge: the highest priority request not masked by the IMR and not blocked by an 
+    /// interrupt of equal or higher priority in service. 
+    /// 
+    /// In special fully nested mode, a request of the same level as the one in service is not 
+    /// blocked. In special mask mode, an in-service level only blocks itself.
+    pub fn get_pending_interrupt(&self) -> Option<u8> {
 
-        let mask: u8 = 0x01;
-        let mut ir = 0;
+        let requests = self.irr & !self.imr;
 
-        for i in 0..8 {
-            ir = i;
-            if self.isr & (mask << ir) != 0 {
-                break;
+        if self.special_mask {
+            return self.highest_priority_bit(requests & !self.isr)
+        }
+
+        let ir = self.highest_priority_bit(requests)?;
+
+        match self.get_highest_priority_is() {
+            Some(is) if self.priority(is) < self.priority(ir) => None,
+            Some(is) if is == ir && !self.special_nested => None,
+            _ => Some(ir)
+        }
+    }
+
+    /// Update the INT request line from the state of the priority resolver.
+    fn update_intr(&mut self) {
+        self.intr = self.get_pending_interrupt().is_some();
+    }
+
+    /// Move the specified request into service, as during interrupt acknowledge or a poll.
+    fn acknowledge(&mut self, ir: u8) {
+        let ir_bit = 0x01 << ir;
+
+        // Clear its bit in the IRR...
+        self.irr &= !ir_bit;
+        // ...and set it in ISR being serviced
+        self.isr |= ir_bit;
+        // ...unless Auto-EOI is on
+        if self.auto_eoi {
+            //log::trace!("Executing Auto-EOI");
+            self.isr &= !ir_bit;
+            if self.rotate_on_aeoi {
+                self.lowest_priority = ir;
             }
         }
-        ir
-    }    
+        self.irq = ir;
+        self.update_intr();
+    }
 
     pub fn clear_lsb(byte: u8) -> u8 {
 
@@ -348,10 +450,16 @@ impl Pic {
                 return;
             }
             InitializationState::ExpectingICW2 => {
-                // This value should be an ICW2 based on just receiving an ICW1 on control port
-
+                // This value should be an ICW2 based on just receiving an ICW1 on control port.
+                // In 8086 mode, the upper five bits of ICW2 provide the base interrupt vector.
                 log::debug!("PIC: Read ICW2: {:02X}", byte);
-                self.init_state = InitializationState::ExpectingICW4;
+                self.int_offset = byte & ICW2_VECTOR_MASK;
+                if self.expecting_icw4 {
+                    self.init_state = InitializationState::ExpectingICW4;
+                }
+                else {
+                    self.init_state = InitializationState::Normal;
+                }
                 return;
             }
             InitializationState::ExpectingICW4 => {
@@ -365,10 +473,8 @@ impl Pic {
                 }
                 self.auto_eoi = byte & ICW4_AEOI_MODE != 0;
                 self.buffered = byte & ICW4_BUFFERED != 0;
-                if byte & ICW4_NESTED != 0 {
-                    log::error!("PIC: Error: MCS-80/85 mode unsupported");
-                    self.error = true;
-                }
+                self.special_nested = byte & ICW4_NESTED != 0;
+                self.expecting_icw4 = false;
                 return;
             }
         }
@@ -376,6 +482,19 @@ impl Pic {
     }
 
     pub fn handle_command_register_read(&mut self) -> u8 {
+        if self.polled {
+            // A read following a poll command acknowledges the highest priority request as an
+            // INTA would, and returns its level.
+            self.polled = false;
+            return match self.get_pending_interrupt() {
+                Some(ir) => {
+                    self.acknowledge(ir);
+                    POLL_INTERRUPT | ir
+                }
+                None => 0
+            }
+        }
+
         match self.read_select {
             ReadSelect::ISR => {
                 self.isr
@@ -392,25 +511,10 @@ impl Pic {
 
     fn set_imr(&mut self, byte: u8) {
 
-        // Changing the IMR will allow devices with current high IR lines to generate interrupts
-        // in Level triggered mode.  In Edge triggered mode they will not.
+        // Unmasking an IR with a latched request will raise INTR if the request is of 
+        // sufficient priority.
         self.imr = byte;
-
-        let mut ir_bit = 0x01;
-        for interrupt in 0..8 {
-
-            let have_request = ir_bit & self.irr != 0;
-            let is_masked = ir_bit & self.imr != 0;
-            let is_in_service = ir_bit & self.isr != 0;
-
-            if self.trigger_mode == TriggerMode::Level && have_request && !is_masked && !is_in_service {
-                // (Set INT request line high)
-                self.intr = true;
-                self.interrupt_stats[interrupt as usize].serviced_count += 1;
-            }
-
-            ir_bit <<= 1;
-        }
+        self.update_intr();
     }
 
     pub fn request_interrupt(&mut self, interrupt: u8) {
@@ -439,10 +543,9 @@ impl Pic {
         }
         else {
             // Interrupt is not masked or already in service, process it...
-            // (Set INT request line high)
-            self.intr = true;
             self.interrupt_stats[interrupt as usize].serviced_count += 1;
         }
+        self.update_intr();
     }
 
     pub fn clear_interrupt(&mut self, interrupt: u8) {
@@ -455,6 +558,12 @@ impl Pic {
         // Clear the corresponding bit in the IR lines
         let intr_bit: u8 = 0x01 << interrupt;
         self.ir &= !intr_bit;
+
+        // In level triggered mode, the request is withdrawn along with the IR line.
+        if self.trigger_mode == TriggerMode::Level {
+            self.irr &= !intr_bit;
+            self.update_intr();
+        }
     }
 
     pub fn query_interrupt_line(&self) -> bool {
@@ -462,41 +571,36 @@ impl Pic {
     }
 
     /// Represents the PIC's response to the 2nd INTA 'pulse'. The PIC will put the 
-    /// highest-priority interrupt vector onto the bus.
+    /// vector of the highest-priority pending interrupt onto the bus, and move that 
+    /// interrupt into service. Returns None if no interrupt can be serviced.
     pub fn get_interrupt_vector(&mut self) -> Option<u8> {
 
         //log::trace!("Getting interrupt vector, auto-eoi: {:?}.", self.auto_eoi);
 
-        // Return the highest priority vector not currently masked from the IRR
-        let mut ir_bit: u8 = 0x01;
-        for irq in 0..8 {
-
-            let have_request = ir_bit & self.irr != 0;
-            let is_masked = ir_bit & self.imr != 0;
-            let _is_in_service = ir_bit & self.isr != 0;
-
-            if have_request && !is_masked {
-                // found highest priority IRR not masked
-
-                // Clear its bit in the IR...
-                self.irr &= !ir_bit;
-                // ...and set it in ISR being serviced
-                self.isr |= ir_bit;
-                // ...unless Auto-EOI is on
-                if self.auto_eoi {
-                    //log::trace!("Executing Auto-EOI");
-                    self.isr &= !ir_bit;
-                }
-                self.irq = irq;
-                // INT line low
-                self.intr = false;
+        let irq = self.get_pending_interrupt()?;
+        self.acknowledge(irq);
+        Some(self.int_offset + irq)
+    }
 
-                return Some(irq + PIC_INTERRUPT_OFFSET)
-            }
-            ir_bit <<= 1;
-        }
+    pub fn irr(&self) -> u8 {
+        self.irr
+    }
+
+    pub fn isr(&self) -> u8 {
+        self.isr
+    }
+
+    pub fn imr(&self) -> u8 {
+        self.imr
+    }
 
-        None
+    /// Return the base interrupt vector programmed by ICW2.
+    pub fn vector_base(&self) -> u8 {
+        self.int_offset
+    }
+
+    pub fn lowest_priority(&self) -> u8 {
+        self.lowest_priority
     }
 
     pub fn get_string_state(&self) -> PicStringState {
@@ -522,4 +626,98 @@ impl Pic {
         }
         state
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_pic(base: u8, icw4: u8) -> Pic {
+        let mut pic = Pic::new();
+        pic.handle_command_register_write(0x13); // ICW1: edge triggered, single, ICW4 needed
+        pic.handle_data_register_write(base);    // ICW2
+        pic.handle_data_register_write(icw4);    // ICW4
+        pic.handle_data_register_write(0x00);    // IMR
+        pic
+    }
+
+    #[test]
+    fn test_pic_priority_and_eoi() {
+        let mut pic = init_pic(0x70, 0x01);
+        assert_eq!(pic.vector_base(), 0x70);
+        assert!(!pic.query_interrupt_line());
+
+        // Masked requests are latched but do not raise INTR until unmasked
+        pic.handle_data_register_write(0x02);
+        pic.request_interrupt(1);
+        assert!(!pic.query_interrupt_line());
+        assert_eq!(pic.irr(), 0x02);
+
+        pic.request_interrupt(3);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x73));
+        assert_eq!(pic.isr(), 0x08);
+
+        // IR1 is higher priority than the in-service IR3, so it nests once unmasked
+        pic.handle_data_register_write(0x00);
+        assert!(pic.query_interrupt_line());
+        assert_eq!(pic.get_interrupt_vector(), Some(0x71));
+        assert_eq!(pic.isr(), 0x0A);
+
+        // IR5 is blocked by the higher priority interrupts in service
+        pic.request_interrupt(5);
+        assert!(!pic.query_interrupt_line());
+
+        // Non-specific EOI clears the highest priority in-service level
+        pic.handle_command_register_write(0x20);
+        assert_eq!(pic.isr(), 0x08);
+        assert!(!pic.query_interrupt_line());
+
+        // Specific EOI for IR3 unblocks IR5
+        pic.handle_command_register_write(0x63);
+        assert_eq!(pic.isr(), 0x00);
+        assert!(pic.query_interrupt_line());
+        assert_eq!(pic.get_interrupt_vector(), Some(0x75));
+        assert_eq!(pic.get_interrupt_vector(), None);
+
+        // ISR can be read back via OCW3
+        pic.handle_command_register_write(0x0B);
+        assert_eq!(pic.handle_command_register_read(), 0x20);
+    }
+
+    #[test]
+    fn test_pic_rotation_and_poll() {
+        let mut pic = init_pic(0x08, 0x01);
+
+        // Set priority: IR2 lowest, making IR3 highest
+        pic.handle_command_register_write(0xC2);
+        assert_eq!(pic.lowest_priority(), 2);
+        assert_eq!(pic.priority(3), 0);
+        assert_eq!(pic.priority(2), 7);
+
+        pic.request_interrupt(0);
+        pic.request_interrupt(4);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0C));
+
+        // Rotate on non-specific EOI makes IR4 the lowest priority, so IR6 now outranks IR0
+        pic.handle_command_register_write(0xA0);
+        assert_eq!(pic.lowest_priority(), 4);
+        pic.request_interrupt(6);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0E));
+        pic.handle_command_register_write(0x20);
+
+        // Poll command acknowledges the pending request and returns its level
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x80);
+        assert_eq!(pic.isr(), 0x01);
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x00);
+
+        // Auto-EOI with rotation
+        let mut pic = init_pic(0x08, 0x03);
+        pic.handle_command_register_write(0x80);
+        pic.request_interrupt(1);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x09));
+        assert_eq!(pic.isr(), 0x00);
+        assert_eq!(pic.lowest_priority(), 1);
+    }
+}
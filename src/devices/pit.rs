@@ -32,6 +32,8 @@ use std::collections::{VecDeque, BTreeMap};
 use modular_bitfield::prelude::*;
 
 use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::devices::ppi::PORTB_SPEAKER_DATA;
+use crate::devices::speaker::Speaker;
 
 use crate::syntax_token::*;
 use crate::updatable::*;
@@ -313,8 +315,8 @@ impl Channel {
         self.armed = false;
         //self.ce_undefined = false;
 
-        // Default load mask
-        //self.load_mask = 0xFFFF;
+        // Default load mask. Square wave mode restricts this on the 8254.
+        self.load_mask = 0xFFFF;
 
         log::debug!(
             "PIT: Channel {} selected, channel_mode {:?}, rw mode {:?}, bcd: {:?}", 
@@ -395,8 +397,11 @@ impl Channel {
     }
 
     pub fn latch_count(&mut self) {
-        self.latch_register = *self.counting_element;
-        self.count_is_latched = true;
+        // Latch commands are ignored until a previously latched count has been read.
+        if !self.count_is_latched {
+            self.latch_register = *self.counting_element;
+            self.change_read_state(ReadState::Latched);
+        }
     }
 
     pub fn set_gate(
@@ -491,12 +496,15 @@ impl Channel {
                 ((*self.counting_element >> 8) & 0xFF) as u8
             }
             ReadState::Latched => {
-                // Latched and no read in progress
+                // Latched and no read in progress. The latch is released once the latched count
+                // has been read in full.
                 match *self.rw_mode {
                     RwMode::Lsb => {
+                        self.change_read_state(ReadState::Unlatched);
                         (self.latch_register & 0xFF) as u8
                     },
                     RwMode::Msb => {
+                        self.change_read_state(ReadState::Unlatched);
                         ((self.latch_register >> 8) & 0xFF) as u8
                     },
                     RwMode::LsbMsb => {
@@ -764,9 +772,10 @@ impl Channel {
                                         self.change_channel_state(ChannelState::WaitingForLoadCycle); // Reload next cycle                      
                                     }
                                     else {
-                                        // Output is low. Reload and update output immediately.
+                                        // Output is low. Reload and update output immediately. The load mask
+                                        // must be applied here as well, or an odd count would never reach 0.
                                         self.change_output_state(!*self.output, bus); // Toggle output state
-                                        self.counting_element.update(*self.count_register); // Reload counting element
+                                        self.counting_element.update(*self.count_register & self.load_mask); // Reload counting element
                                     }
                                 }
                             }
@@ -1062,10 +1071,10 @@ impl ProgrammableIntervalTimer {
 
         // Get timer channel 2 state from ppi.
         // TODO: it would be better to push this state from PPI when changed then to poll it on tick here.
-        let mut speaker_data = true;
+        let mut portb = PORTB_SPEAKER_DATA;
 
         if let Some(ppi) = bus.ppi_mut() {
-            speaker_data = ppi.get_pb1_state();
+            portb = ppi.handle_portb_read();
             self.channels[2].set_gate(ppi.get_pit_channel2_gate(), bus);
 
         }
@@ -1074,7 +1083,14 @@ impl ProgrammableIntervalTimer {
         self.channels[1].tick(bus, None);
         self.channels[2].tick(bus, None);
 
-        let mut speaker_sample = *self.channels[2].output && speaker_data;
+        let speaker_level = Speaker::output_level(*self.channels[2].output, portb);
+        let mut speaker_sample = speaker_level;
+
+        // Feed channel 2's output and the speaker level back to port C of the PPI.
+        if let Some(ppi) = bus.ppi_mut() {
+            ppi.set_pit_output_bit(*self.channels[2].output);
+            ppi.set_speaker_bit(speaker_level);
+        }
 
         if let ChannelMode::SquareWaveGenerator = *self.channels[2].mode {
             // Silence speaker if frequency is > 14Khz (approx)
@@ -1209,3 +1225,189 @@ impl ProgrammableIntervalTimer {
         state_vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::pic::Pic;
+
+    fn test_bus() -> BusInterface {
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        bus
+    }
+
+    /// Tick the PIT for the specified number of cycles, returning the channel 0 output state
+    /// after each tick.
+    fn run_outputs(pit: &mut Pit, bus: &mut BusInterface, ticks: usize) -> Vec<bool> {
+        (0..ticks).map(|_| {
+            pit.tick(bus, None);
+            pit.get_output_state(0)
+        }).collect()
+    }
+
+    /// Return the tick indices of each rising edge in a list of output states.
+    fn rising_edges(outputs: &[bool]) -> Vec<usize> {
+        outputs.windows(2).enumerate().filter(|(_, w)| !w[0] && w[1]).map(|(i, _)| i + 1).collect()
+    }
+
+    fn irq0_requests(bus: &mut BusInterface) -> u64 {
+        // Each request is counted as either masked or serviced
+        let state = bus.pic_mut().as_ref().unwrap().get_string_state();
+        let (imr_masked, isr_masked, serviced) = &state.interrupt_stats[0];
+        [imr_masked, isr_masked, serviced].iter().map(|s| s.parse::<u64>().unwrap()).sum()
+    }
+
+    fn program(pit: &mut Pit, bus: &mut BusInterface, control: u8, bytes: &[u8]) {
+        pit.control_register_write(control, bus);
+        for byte in bytes {
+            pit.data_write(0, *byte, bus);
+        }
+    }
+
+    #[test]
+    fn test_pit_rate_generator_irq0() {
+        let mut bus = test_bus();
+        let mut pit = Pit::new(PitType::Model8253, PIT_FREQ, 4);
+        pit.set_channel_gate(0, true, &mut bus);
+
+        // Channel 0, lsb/msb, mode 2, divisor 1000. Writing the control word drives the output 
+        // high from its initial low state, which is itself a rising edge seen by the PIC.
+        program(&mut pit, &mut bus, 0x34, &[0xE8, 0x03]);
+        assert_eq!(irq0_requests(&mut bus), 1);
+        let outputs = run_outputs(&mut pit, &mut bus, 5500);
+
+        // Output is low for one cycle of every 1000, and each reload raises IRQ0
+        let edges = rising_edges(&outputs);
+        assert_eq!(edges, vec![1000, 2000, 3000, 4000, 5000]);
+        assert_eq!(outputs.iter().filter(|o| !**o).count(), 5);
+        assert_eq!(irq0_requests(&mut bus), 6);
+
+        // A falling gate stops the count and holds output high
+        pit.set_channel_gate(0, false, &mut bus);
+        assert!(run_outputs(&mut pit, &mut bus, 2000).iter().all(|o| *o));
+        assert_eq!(irq0_requests(&mut bus), 6);
+    }
+
+    #[test]
+    fn test_pit_square_wave_odd_count() {
+        for ptype in [PitType::Model8253, PitType::Model8254] {
+            let mut bus = test_bus();
+            let mut pit = Pit::new(ptype, PIT_FREQ, 4);
+            pit.set_channel_gate(0, true, &mut bus);
+
+            // Channel 0, lsb only, mode 3, count 5: high for 3 cycles and low for 2
+            program(&mut pit, &mut bus, 0x16, &[5]);
+            let outputs = run_outputs(&mut pit, &mut bus, 100);
+
+            let edges = rising_edges(&outputs);
+            assert!(edges.len() >= 18, "{:?}: {:?}", ptype, edges);
+            for w in edges.windows(2) {
+                assert_eq!(w[1] - w[0], 5, "{:?}", ptype);
+                assert_eq!(outputs[w[0]..w[1]].iter().filter(|o| **o).count(), 3, "{:?}", ptype);
+            }
+        }
+
+        // Square wave mode's load mask does not apply to other modes
+        let mut bus = test_bus();
+        let mut pit = Pit::new(PitType::Model8254, PIT_FREQ, 4);
+        pit.set_channel_gate(0, true, &mut bus);
+        program(&mut pit, &mut bus, 0x16, &[5]);
+        run_outputs(&mut pit, &mut bus, 10);
+        program(&mut pit, &mut bus, 0x14, &[5]);
+        let edges = rising_edges(&run_outputs(&mut pit, &mut bus, 40));
+        assert!(edges.windows(2).all(|w| w[1] - w[0] == 5));
+    }
+
+    #[test]
+    fn test_pit_interrupt_on_terminal_count() {
+        let mut bus = test_bus();
+        let mut pit = Pit::new(PitType::Model8253, PIT_FREQ, 4);
+        pit.set_channel_gate(0, true, &mut bus);
+
+        // Channel 0, lsb/msb, mode 0, count 10. Output goes high N+1 cycles after the count is written.
+        program(&mut pit, &mut bus, 0x30, &[10, 0]);
+        assert!(!pit.get_output_state(0));
+        let outputs = run_outputs(&mut pit, &mut bus, 100);
+        assert_eq!(outputs.iter().position(|o| *o), Some(10));
+        assert!(outputs[10..].iter().all(|o| *o));
+        assert_eq!(irq0_requests(&mut bus), 1);
+
+        // Writing the first byte of a new count sets output low and stops counting
+        pit.data_write(0, 20, &mut bus);
+        assert!(!pit.get_output_state(0));
+        assert!(run_outputs(&mut pit, &mut bus, 50).iter().all(|o| !*o));
+        pit.data_write(0, 0, &mut bus);
+        assert_eq!(run_outputs(&mut pit, &mut bus, 50).iter().position(|o| *o), Some(20));
+    }
+
+    #[test]
+    fn test_pit_latch() {
+        let mut bus = test_bus();
+        let mut pit = Pit::new(PitType::Model8253, PIT_FREQ, 4);
+        pit.set_channel_gate(0, true, &mut bus);
+
+        // Channel 0, lsb/msb, mode 2, divisor 1000
+        program(&mut pit, &mut bus, 0x34, &[0xE8, 0x03]);
+        run_outputs(&mut pit, &mut bus, 11);
+
+        pit.control_register_write(0x00, &mut bus);
+        run_outputs(&mut pit, &mut bus, 5);
+        // A second latch command before the count is read is ignored
+        pit.control_register_write(0x00, &mut bus);
+        let latched = pit.data_read(0) as u16 | (pit.data_read(0) as u16) << 8;
+        assert_eq!(latched, 990);
+        let live = pit.data_read(0) as u16 | (pit.data_read(0) as u16) << 8;
+        assert_eq!(live, 985);
+
+        // In lsb only mode the latch is released after one read
+        program(&mut pit, &mut bus, 0x14, &[200]);
+        run_outputs(&mut pit, &mut bus, 11);
+        pit.control_register_write(0x00, &mut bus);
+        run_outputs(&mut pit, &mut bus, 5);
+        assert_eq!(pit.data_read(0), 190);
+        assert_eq!(pit.data_read(0), 185);
+    }
+
+    #[test]
+    fn test_pit_channel2_ppi_wiring() {
+        use crate::config::{MachineType, VideoType};
+        use crate::devices::ppi::Ppi;
+
+        let mut bus = test_bus();
+        *bus.ppi_mut() = Some(Ppi::new(MachineType::IBM_XT_5160, VideoType::CGA, 2));
+        let mut pit = Pit::new(PitType::Model8253, PIT_FREQ, 4);
+
+        // Channel 2, lsb/msb, mode 3, count 4
+        pit.control_register_write(0xB6, &mut bus);
+        pit.data_write(2, 4, &mut bus);
+        pit.data_write(2, 0, &mut bus);
+
+        // Run for 'ticks', returning the states of PC5 (timer 2 out) and PC4 (speaker monitor) seen.
+        let sample = |pit: &mut Pit, bus: &mut BusInterface, ticks: usize| {
+            let mut seen = Vec::new();
+            for _ in 0..ticks {
+                pit.tick(bus, None);
+                let pc = bus.ppi_mut().as_mut().unwrap().calc_port_c_value();
+                seen.push((pc & 0x20 != 0, pc & 0x10 != 0));
+            }
+            seen
+        };
+
+        // With the gate low, channel 2 doesn't count and its output stays high.
+        let seen = sample(&mut pit, &mut bus, 20);
+        assert!(seen.iter().all(|(out, spk)| *out && !*spk));
+
+        // Raising the gate alone toggles OUT2, but the speaker stays off without PB1.
+        bus.ppi_mut().as_mut().unwrap().handle_portb_write(0x01 | 0x40);
+        let seen = sample(&mut pit, &mut bus, 20);
+        assert!(seen.iter().any(|(out, _)| *out) && seen.iter().any(|(out, _)| !*out));
+        assert!(seen.iter().all(|(_, spk)| !*spk));
+
+        // With PB1 set, the speaker follows OUT2.
+        bus.ppi_mut().as_mut().unwrap().handle_portb_write(0x03 | 0x40);
+        let seen = sample(&mut pit, &mut bus, 20);
+        assert!(seen.iter().any(|(out, _)| !*out));
+        assert!(seen.iter().all(|(out, spk)| out == spk));
+    }
+}
@@ -5,6 +5,17 @@
     Other than reporting DIP switch status and other system information the PPI
     acts as the interface for the PC/XT keyboard. We emulate the keyboard through 
     the PPI.
+
+    The PC and XT have no keyboard controller, so there is no command port at 64h
+    and reads from it return NO_IO_BYTE. The only keyboard 'command' is a reset,
+    made by holding the keyboard clock low through PB6. On release the keyboard
+    responds with AAh on port A and raises IRQ1, which POST checks for. Setting 
+    PB7 clears the keyboard byte and withdraws IRQ1.
+
+    Of the 8255 control port commands, the mode word 99h written by POST (mode 0,
+    ports A and C input, port B output) is expected. The port directions are 
+    fixed by the motherboard, so other mode words are logged and ignored, as are
+    port C bit set/reset commands. Reads of the control port return NO_IO_BYTE.
 */
 #![allow(dead_code)]
 
@@ -19,6 +30,9 @@ pub const PPI_PORT_B: u16 = 0x61;
 pub const PPI_PORT_C: u16 = 0x62;
 pub const PPI_COMMAND_PORT: u16 = 0x63;
 
+pub const PPI_MODE_SET: u8 = 0b1000_0000;
+pub const PPI_MODE_PC: u8 = 0x99; // Mode 0, port A input, port B output, port C input
+
 pub const KB_RESET_US: f64 = 10_000.0; // Time with clock line pulled low before kb is reset - 10ms
 pub const KB_RESET_DELAY_US: f64 = 1_000.0; // Delay period between detecting reset and sending reset byte - 1ms
 
@@ -151,6 +165,13 @@ pub struct PpiStringState {
     pub kb_resets_counter: String,
     pub port_c_mode: String,
     pub port_c_value: String,
+    pub timer2_gate: String,
+    pub speaker_data: String,
+    pub timer2_out: String,
+    pub speaker_out: String,
+    pub kb_clock: String,
+    pub dip_sw1: String,
+    pub dip_sw2: String,
 }
 
 impl Ppi {
@@ -273,6 +294,15 @@ impl Ppi {
 
     pub fn handle_command_port_write(&mut self, byte: u8) {
         log::trace!("PPI: Write to command port: {:02X}", byte);
+        if byte & PPI_MODE_SET != 0 {
+            if byte != PPI_MODE_PC {
+                log::warn!("PPI: Ignoring unsupported mode word: {:02X}", byte);
+            }
+        }
+        else {
+            // Port C is wired as an input, so setting or resetting its bits has no effect.
+            log::trace!("PPI: Ignoring port C bit set/reset: {:02X}", byte);
+        }
     }
     
     pub fn handle_portb_read(&self) -> u8 {
@@ -337,14 +367,17 @@ impl Ppi {
         else if self.kb_clock_low {
             //log::trace!("PPI: Keyboard clock resume HIGH");
             self.kb_clock_low = false;
+            self.kb_counting_low = false;
 
             if self.kb_low_count > KB_RESET_DELAY_US {
                 // Clock line was low long enough to trigger reset
                 // Start timer until reset byte is sent
-                self.kb_low_count = 0.0;
                 self.kb_do_reset = true;
                 self.kb_count_until_reset_byte = 0.0;
             }
+            // Start timing the next low period from zero, so that short pulses of the 
+            // clock line don't add up to a reset.
+            self.kb_low_count = 0.0;
         }
 
     }
@@ -407,7 +440,14 @@ impl Ppi {
             kb_byte_value_hex: format!("{:02X}", self.kb_byte),
             kb_resets_counter: format!("{}", self.kb_resets_counter),
             port_c_mode: format!("{:?}", self.port_c_mode),
-            port_c_value: format!("{:08b}", port_c_value )
+            port_c_value: format!("{:08b}", port_c_value ),
+            timer2_gate: format!("{}", self.get_pb0_state()),
+            speaker_data: format!("{}", self.get_pb1_state()),
+            timer2_out: format!("{}", self.timer_in),
+            speaker_out: format!("{}", self.speaker_in),
+            kb_clock: if self.kb_clock_low { "Low".to_string() } else { "High".to_string() },
+            dip_sw1: format!("{:08b}", self.dip_sw1),
+            dip_sw2: format!("{:08b}", self.dip_sw2),
         }
     }
 
@@ -423,10 +463,12 @@ impl Ppi {
         self.pb_byte & PORTB_TIMER2_GATE != 0
     }
 
+    /// Set the state of PIT channel 2's output, read back on PC5.
     pub fn set_pit_output_bit(&mut self, state: bool) {
         self.timer_in = state;
     }
 
+    /// Set the speaker level, channel 2's output ANDed with PB1. The 5160 reads this back on PC4.
     pub fn set_speaker_bit(&mut self, state: bool) {
         self.speaker_in = state;
     }
@@ -470,4 +512,107 @@ impl Ppi {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_portb(ppi: &mut Ppi, byte: u8) {
+        ppi.write_u8(PPI_PORT_B, byte, None, DeviceRunTimeUnit::Microseconds(0.0));
+    }
+
+    fn read(ppi: &mut Ppi, port: u16) -> u8 {
+        ppi.read_u8(port, DeviceRunTimeUnit::Microseconds(0.0))
+    }
+
+    #[test]
+    fn test_ppi_5150_switches() {
+        let mut ppi = Ppi::new(MachineType::IBM_PC_5150, VideoType::CGA, 2);
+        ppi.send_keyboard(0x1C);
+
+        // PB7 presents SW1 on port A, otherwise the keyboard byte
+        write_portb(&mut ppi, PORTB_PRESENT_SW1_PORTA | PORTB_SW2_SELECT | PORTB_PULL_KB_LOW);
+        assert_eq!(read(&mut ppi, PPI_PORT_A), SW1_HAS_FLOPPIES | SW1_RAM_BANKS | SW1_TWO_FLOPPIES | SW1_HAVE_CGA_HIRES);
+        assert_eq!(read(&mut ppi, PPI_PORT_B), PORTB_PRESENT_SW1_PORTA | PORTB_SW2_SELECT | PORTB_PULL_KB_LOW);
+
+        // PB2 selects SW2 switches 1-4, otherwise switch 5 on PC0
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x0F, SW2_RAM_TEST & 0x0F);
+        write_portb(&mut ppi, PORTB_PULL_KB_LOW);
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x0F, (SW2_RAM_TEST >> 4) & 0x01);
+        assert_eq!(read(&mut ppi, PPI_PORT_A), 0x1C);
+    }
+
+    #[test]
+    fn test_ppi_5160_portb() {
+        let mut ppi = Ppi::new(MachineType::IBM_XT_5160, VideoType::CGA, 2);
+        let sw1 = SW1_HAS_FLOPPIES | SW1_RAM_BANKS | SW1_TWO_FLOPPIES | SW1_HAVE_CGA_HIRES;
+
+        // PB3 selects the high or low nibble of SW1 on PC0-PC3
+        write_portb(&mut ppi, PORTB_PULL_KB_LOW);
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x0F, sw1 & 0x0F);
+        write_portb(&mut ppi, PORTB_SW1_SELECT | PORTB_PULL_KB_LOW);
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x0F, sw1 >> 4);
+
+        // PB0 is the channel 2 gate and PB1 the speaker data bit
+        assert!(!ppi.get_pit_channel2_gate());
+        write_portb(&mut ppi, PORTB_TIMER2_GATE | PORTB_SPEAKER_DATA | PORTB_PULL_KB_LOW);
+        assert!(ppi.get_pit_channel2_gate());
+        assert!(ppi.get_pb1_state());
+
+        // Timer 2 output is read back on PC5, the speaker level on PC4
+        ppi.set_pit_output_bit(true);
+        ppi.set_speaker_bit(true);
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x30, 0x30);
+        ppi.set_pit_output_bit(false);
+        ppi.set_speaker_bit(false);
+        assert_eq!(read(&mut ppi, PPI_PORT_C) & 0x30, 0x00);
+
+        // Pulling PB6 low holds the keyboard clock low
+        write_portb(&mut ppi, 0);
+        assert_eq!(ppi.get_string_state().kb_clock, "Low");
+    }
+
+    #[test]
+    fn test_ppi_keyboard_reset() {
+        let mut ppi = Ppi::new(MachineType::IBM_PC_5150, VideoType::CGA, 2);
+        let mut pic = pic::Pic::new();
+
+        // POST sets the PPI mode. The control port isn't readable.
+        ppi.write_u8(PPI_COMMAND_PORT, PPI_MODE_PC, None, DeviceRunTimeUnit::Microseconds(0.0));
+        ppi.write_u8(PPI_COMMAND_PORT, 0x05, None, DeviceRunTimeUnit::Microseconds(0.0));
+        assert_eq!(read(&mut ppi, PPI_COMMAND_PORT), NO_IO_BYTE);
+
+        // Short pulses of the clock line don't reset the keyboard, even if they add up
+        for _ in 0..4 {
+            write_portb(&mut ppi, PORTB_PRESENT_SW1_PORTA);
+            ppi.run(&mut pic, 500.0);
+            write_portb(&mut ppi, PORTB_PRESENT_SW1_PORTA | PORTB_PULL_KB_LOW);
+            ppi.run(&mut pic, 500.0);
+        }
+        ppi.run(&mut pic, 2_000.0);
+        assert_eq!(pic.irr() & 0x02, 0);
+
+        // The BIOS holds the clock low for 20ms, then releases it and enables the keyboard
+        write_portb(&mut ppi, 0x08);
+        for _ in 0..20 {
+            ppi.run(&mut pic, 1_000.0);
+        }
+        write_portb(&mut ppi, 0xC8);
+        write_portb(&mut ppi, 0x48);
+        ppi.run(&mut pic, 100.0);
+        assert_eq!(pic.irr() & 0x02, 0);
+        ppi.run(&mut pic, 1_000.0);
+
+        // The keyboard answers with AAh and IRQ1
+        assert_eq!(pic.irr() & 0x02, 0x02);
+        assert_eq!(read(&mut ppi, PPI_PORT_A), 0xAA);
+        assert_eq!(ppi.get_string_state().kb_resets_counter, "1");
+
+        // Setting PB7 clears the keyboard byte
+        write_portb(&mut ppi, 0xC8);
+        ppi.run(&mut pic, 100.0);
+        write_portb(&mut ppi, 0x48);
+        assert_eq!(read(&mut ppi, PPI_PORT_A), 0x00);
+    }
+}
@@ -9,8 +9,11 @@
 */
 
 use std::{
-    io::Read, 
-    collections::VecDeque
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread
 };
 
 use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
@@ -109,8 +112,8 @@ impl IoDevice for SerialPortController {
             SERIAL2_INTERRUPT_ID => self.port[1].interrupt_id_read(),
             SERIAL1_LINE_CONTROL => self.port[0].line_control_read(),
             SERIAL2_LINE_CONTROL => self.port[1].line_control_read(),
-            SERIAL1_MODEM_CONTROL => 0,
-            SERIAL2_MODEM_CONTROL => 0,
+            SERIAL1_MODEM_CONTROL => self.port[0].modem_control_read(),
+            SERIAL2_MODEM_CONTROL => self.port[1].modem_control_read(),
             SERIAL1_LINE_STATUS => self.port[0].line_status_read(),
             SERIAL2_LINE_STATUS => self.port[1].line_status_read(),
             SERIAL1_MODEM_STATUS => self.port[0].modem_status_read(),         
@@ -168,6 +171,124 @@ pub enum StopBits {
     Two
 }
 
+/// A host-side connection for a serial port. Bytes transmitted by the emulated UART are
+/// written to the backend, and bytes read from the backend are queued for reception.
+/// Both methods should not block; a read with no data available should return Ok(0) 
+/// or an error of kind WouldBlock or TimedOut. Any other read or write error disconnects
+/// the backend from the port.
+pub trait SerialBackend {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()>;
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
+}
+
+/// A host serial port or pseudo-terminal opened through the serialport crate.
+impl SerialBackend for Box<dyn serialport::SerialPort> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.write_all(data)
+    }
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Read::read(self, buf)
+    }
+}
+
+/// A TCP connection to a host socket, such as a telnet BBS or another emulator's null modem.
+/// The connection is established on a background thread so that connecting never stalls the
+/// emulator; bytes written before the connection completes, or while the socket is full, are
+/// buffered and sent on later calls.
+pub struct TcpBackend {
+    state: TcpState,
+    out_buf: VecDeque<u8>
+}
+
+enum TcpState {
+    Connecting(Receiver<std::io::Result<TcpStream>>),
+    Connected(TcpStream),
+    Closed
+}
+
+impl TcpBackend {
+    pub fn connect(addr: &str) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let addr = addr.to_string();
+        thread::spawn(move || {
+            let result = TcpStream::connect(addr).and_then(|stream| {
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true)?;
+                Ok(stream)
+            });
+            _ = tx.send(result);
+        });
+        Self {
+            state: TcpState::Connecting(rx),
+            out_buf: VecDeque::new()
+        }
+    }
+
+    /// Advance a pending connection. Returns an error if the connection failed or was closed.
+    fn poll_connect(&mut self) -> std::io::Result<()> {
+        if let TcpState::Connecting(rx) = &self.state {
+            match rx.try_recv() {
+                Ok(Ok(stream)) => self.state = TcpState::Connected(stream),
+                Ok(Err(e)) => {
+                    self.state = TcpState::Closed;
+                    return Err(e)
+                }
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => self.state = TcpState::Closed
+            }
+        }
+        match self.state {
+            TcpState::Closed => Err(ErrorKind::NotConnected.into()),
+            _ => Ok(())
+        }
+    }
+
+    /// Send as much of the output buffer as the socket will accept without blocking.
+    fn flush_out(&mut self) -> std::io::Result<()> {
+        if let TcpState::Connected(stream) = &mut self.state {
+            while !self.out_buf.is_empty() {
+                let (front, _) = self.out_buf.as_slices();
+                match stream.write(front) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(ct) => { self.out_buf.drain(..ct); },
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, e: std::io::Error) -> std::io::Error {
+        self.state = TcpState::Closed;
+        e
+    }
+}
+
+impl SerialBackend for TcpBackend {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.poll_connect()?;
+        self.out_buf.extend(data);
+        self.flush_out().map_err(|e| self.close(e))
+    }
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.poll_connect()?;
+        self.flush_out().map_err(|e| self.close(e))?;
+        let result = match &mut self.state {
+            TcpState::Connected(stream) => stream.read(buf),
+            _ => return Ok(0)
+        };
+        match result {
+            // A zero length read from a socket means the peer closed the connection
+            Ok(0) if !buf.is_empty() => Err(self.close(ErrorKind::ConnectionAborted.into())),
+            Ok(ct) => Ok(ct),
+            Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => Ok(0),
+            Err(e) => Err(self.close(e))
+        }
+    }
+}
+
 pub struct SerialPort {
     name: String,
     irq: u8,
@@ -195,8 +316,8 @@ pub struct SerialPort {
     tx_timer: f64,
     us_per_byte: f64,
 
-    // Serial port bridge
-    bridge_port: Option<Box<dyn serialport::SerialPort>>,
+    // Host backend
+    backend: Option<Box<dyn SerialBackend>>,
     bridge_buf: Vec<u8>
 }
 
@@ -229,7 +350,7 @@ impl SerialPort {
             tx_timer: 0.0,
             us_per_byte: 833.333, // 9600 baud
 
-            bridge_port: None,
+            backend: None,
             bridge_buf: vec![0; 1000]
         }
     }
@@ -240,15 +361,23 @@ impl SerialPort {
 
     /// Sets the value of us_per_byte, the microsecond delay between sending a byte out of the 
     /// Send or receive queue based on the current baud rate.
-    /// This function should be called whenever the divisor has changed.
+    /// This function should be called whenever the divisor or line control has changed.
     fn set_timing(&mut self) {
 
         if self.divisor < 12 {
             // Minimum divisor of 12 (9600 baud)
             self.divisor = 12;
         }
-        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u16;
-        self.us_per_byte = 1.0 / bytes_per_second as f64 * 1_000_000.0;
+
+        // A character frame is a start bit, the data bits, an optional parity bit and the stop bits.
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1.0,
+            StopBits::OneAndAHalf => 1.5,
+            StopBits::Two => 2.0
+        };
+        let frame_bits = 1.0 + self.word_length as f64 + if self.parity_enable { 1.0 } else { 0.0 } + stop_bits;
+        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) as f64 / frame_bits;
+        self.us_per_byte = 1_000_000.0 / bytes_per_second;
     }
 
     fn line_control_read(&self) -> u8 {
@@ -275,6 +404,7 @@ impl SerialPort {
 
         self.parity_enable = byte & PARITY_ENABLE_BIT != 0;
         self.divisor_latch_access = byte & DIVISOR_LATCH_ACCESS_BIT != 0;
+        self.set_timing();
 
         log::trace!("{}: Write to Line Control Register: {:02X} Word Length: {} Parity: {} Stop Bits: {:?}", 
             self.name, 
@@ -311,7 +441,8 @@ impl SerialPort {
 
     /// Send a byte to the serial port tx buffer register.
     /// For COM1, COM1 is always attached to Mouse which ignores input.
-    /// COM2 may be bridged to a host serial port.
+    /// COM2 may be bridged to a host serial port or socket.
+    /// In loopback mode the byte is returned to the receiver once transmitted.
     fn tx_buffer_write(&mut self, byte: u8) {
         // If DSLAB, set Divisor Latch LSB
         if self.divisor_latch_access { 
@@ -431,6 +562,10 @@ impl SerialPort {
         byte
     }    
 
+    fn modem_control_read(&self) -> u8 {
+        self.modem_control_reg
+    }
+
     /// Handle writing to the Modem Control Register
     fn modem_control_write(&mut self, byte: u8) {
 
@@ -489,6 +624,19 @@ impl SerialPort {
         }
     }
 
+    fn set_modem_status_disconnected(&mut self) {
+
+        if self.modem_status_reg & MODEM_STATUS_CTS != 0 {
+            self.modem_status_reg &= !MODEM_STATUS_CTS;
+            self.modem_status_reg |= MODEM_STATUS_DCTS;
+        }
+
+        if self.modem_status_reg & MODEM_STATUS_DSR != 0 {
+            self.modem_status_reg &= !MODEM_STATUS_DSR;
+            self.modem_status_reg |= MODEM_STATUS_DDSR;
+        }
+    }
+
     fn raise_interrupt_type(&mut self, interrupt_flag: u8) {
 
         // Interrupt enable register completely disables interrupts
@@ -528,8 +676,7 @@ impl SerialPort {
         match port_result {
             Ok(bridge_port) => {
                 log::trace!("Successfully opened host port {}", port_name);
-                self.bridge_port = Some(bridge_port);
-                self.set_modem_status_connected();
+                self.attach_backend(Box::new(bridge_port));
                 Ok(true)
             }
             Err(e) => {
@@ -538,6 +685,17 @@ impl SerialPort {
             }
         }
     }
+
+    fn attach_backend(&mut self, backend: Box<dyn SerialBackend>) {
+        self.backend = Some(backend);
+        self.set_modem_status_connected();
+    }
+
+    fn detach_backend(&mut self) {
+        self.backend = None;
+        self.tx_queue.clear();
+        self.set_modem_status_disconnected();
+    }
 }
 
 
@@ -577,6 +735,19 @@ impl SerialPortController {
         self.port[port].bridge_port(port_name)
     }
 
+    /// Connect the specified serial port to a host TCP socket. The connection completes in
+    /// the background; if it fails, the port is disconnected on the next update.
+    pub fn connect_tcp(&mut self, port: usize, address: &str) -> anyhow::Result<bool> {
+        log::trace!("Connecting {} to {}", self.port[port].name, address);
+        self.port[port].attach_backend(Box::new(TcpBackend::connect(address)));
+        Ok(true)
+    }
+
+    /// Connect the specified serial port to an arbitrary backend
+    pub fn attach_backend(&mut self, port: usize, backend: Box<dyn SerialBackend>) {
+        self.port[port].attach_backend(backend);
+    }
+
     /// Run the serial ports for the specified number of microseconds
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
 
@@ -632,8 +803,13 @@ impl SerialPortController {
                 // Is there a byte waiting to be sent in the tx holding register?
                 if !port.tx_holding_empty {
                     
+                    if port.loopback {
+                        // In loopback mode the transmitter output is connected to the receiver
+                        // input, and nothing is sent to the backend.
+                        port.rx_queue.push_back(port.tx_holding_reg);
+                    }
                     // If we have bridged this serial port, send the byte to the tx queue
-                    if let Some(_) = &port.bridge_port {
+                    else if let Some(_) = &port.backend {
                         //log::trace!("{}: Sending byte: {:02X}", port.name, port.tx_holding_reg);
                         port.tx_queue.push_back(port.tx_holding_reg);
                     }
@@ -657,9 +833,11 @@ impl SerialPortController {
     pub fn update(&mut self) {
 
         for port in &mut self.port {
+
+            let mut disconnected = false;
             
-            match &mut port.bridge_port {
-                Some(bridge_port) => {
+            match &mut port.backend {
+                Some(backend) => {
                     
                     // Write any pending bytes
                     if port.tx_queue.len() > 0 {
@@ -667,40 +845,201 @@ impl SerialPortController {
                         port.tx_queue.make_contiguous();
                         let (tx1, _) = port.tx_queue.as_slices();
                         
-                        match bridge_port.write(tx1) {
+                        match backend.write(tx1) {
                             Ok(_) => {
                                 //log::trace!("Wrote bytes: {:?}", tx1);
                             }
-                            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-                            Err(e) => log::error!("Error writing byte: {:?}", e),                            
+                            Err(ref e) if e.kind() == ErrorKind::TimedOut => (),
+                            Err(e) => {
+                                log::error!("{}: Error writing to backend: {:?}", port.name, e);
+                                disconnected = true;
+                            }
                         }
 
                         port.tx_queue.clear();
                     }
 
 
-                    // Read any pending bytes
-                    match bridge_port.read(port.bridge_buf.as_mut_slice()) {
+                    // Read any pending bytes. Received data is discarded in loopback mode.
+                    match backend.read(port.bridge_buf.as_mut_slice()) {
+                        _ if disconnected => {},
+                        Ok(_) if port.loopback => {},
                         Ok(ct) => {
 
                             if ct > 0 {
                                 log::trace!("Read {} bytes from serial port", ct);
                             }
-                            for i in 0..ct {
-                                // TODO: Must be a more efficient way to copy the vec to vecdeque?
-                                let byte = port.bridge_buf[i];
-                                port.rx_queue.push_back(byte);
-                                //log::trace!("Wrote byte : {:02X} to buf", byte);
-                            }
+                            port.rx_queue.extend(&port.bridge_buf[..ct]);
                         },
-                        Err(_) => {
-                            //log::error!("Error reading serial device: {}", e);
+                        Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+                        Err(e) => {
+                            log::warn!("{}: Backend disconnected: {}", port.name, e);
+                            disconnected = true;
                         }
                     }
                 },
                 None => {}
             }
+
+            if disconnected {
+                port.detach_backend();
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    const DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::Microseconds(0.0);
+
+    /// A backend that records transmitted bytes and supplies queued bytes on read.
+    struct TestBackend {
+        sent: Rc<RefCell<Vec<u8>>>,
+        pending: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SerialBackend for TestBackend {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+            self.sent.borrow_mut().extend_from_slice(data);
+            Ok(())
+        }
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut pending = self.pending.borrow_mut();
+            let ct = pending.len().min(buf.len());
+            buf[..ct].copy_from_slice(&pending[..ct]);
+            pending.drain(..ct);
+            Ok(ct)
         }
     }
 
-}
\ No newline at end of file
+    fn run_for(spc: &mut SerialPortController, pic: &mut pic::Pic, us: u32) {
+        for _ in 0..(us / 100) {
+            spc.run(pic, 100.0);
+        }
+    }
+
+    #[test]
+    fn test_serial_loopback() {
+        let mut spc = SerialPortController::new();
+        let mut pic = pic::Pic::new();
+
+        // 9600 baud, 8N1, loopback with interrupts gated on, data available interrupt enabled
+        spc.write_u8(SERIAL1_LINE_CONTROL, 0x83, None, DELTA);
+        spc.write_u8(SERIAL1_RX_TX_BUFFER, 12, None, DELTA);
+        spc.write_u8(SERIAL1_INTERRUPT_ENABLE, 0, None, DELTA);
+        spc.write_u8(SERIAL1_LINE_CONTROL, 0x03, None, DELTA);
+        spc.write_u8(SERIAL1_MODEM_CONTROL, MODEM_CONTROL_LOOP | MODEM_CONTROL_OUT2, None, DELTA);
+        spc.write_u8(SERIAL1_INTERRUPT_ENABLE, INTERRUPT_DATA_AVAIL, None, DELTA);
+        assert_eq!(spc.read_u8(SERIAL1_MODEM_CONTROL, DELTA), MODEM_CONTROL_LOOP | MODEM_CONTROL_OUT2);
+        assert_eq!(spc.read_u8(SERIAL1_INTERRUPT_ID, DELTA), 0x01);
+
+        spc.write_u8(SERIAL1_RX_TX_BUFFER, 0x55, None, DELTA);
+        assert_eq!(spc.read_u8(SERIAL1_LINE_STATUS, DELTA) & STATUS_TRANSMIT_EMPTY, 0);
+
+        // A 10 bit frame at 9600 baud takes ~1042us to transmit and again to receive.
+        run_for(&mut spc, &mut pic, 1000);
+        assert_eq!(spc.read_u8(SERIAL1_LINE_STATUS, DELTA) & STATUS_DATA_READY, 0);
+        run_for(&mut spc, &mut pic, 1200);
+        assert_ne!(spc.read_u8(SERIAL1_LINE_STATUS, DELTA) & STATUS_DATA_READY, 0);
+        assert_eq!(spc.read_u8(SERIAL1_INTERRUPT_ID, DELTA), 0x04);
+
+        assert_eq!(spc.read_u8(SERIAL1_RX_TX_BUFFER, DELTA), 0x55);
+        assert_eq!(spc.read_u8(SERIAL1_LINE_STATUS, DELTA) & STATUS_DATA_READY, 0);
+        assert_eq!(spc.read_u8(SERIAL1_INTERRUPT_ID, DELTA), 0x01);
+
+        // Modem status inputs reflect the modem control outputs
+        spc.write_u8(SERIAL1_MODEM_CONTROL, MODEM_CONTROL_LOOP | MODEM_CONTROL_RTS | MODEM_CONTROL_DTR, None, DELTA);
+        assert_eq!(spc.read_u8(SERIAL1_MODEM_STATUS, DELTA) & 0xF0, MODEM_STATUS_CTS | MODEM_STATUS_DSR);
+    }
+
+    #[test]
+    fn test_serial_backend() {
+        let mut spc = SerialPortController::new();
+        let mut pic = pic::Pic::new();
+
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let pending = Rc::new(RefCell::new(vec![0x41, 0x42]));
+        spc.attach_backend(1, Box::new(TestBackend { sent: sent.clone(), pending: pending.clone() }));
+
+        // Connecting a backend raises CTS and DSR
+        let msr = spc.read_u8(SERIAL2_MODEM_STATUS, DELTA);
+        assert_eq!(msr & (MODEM_STATUS_CTS | MODEM_STATUS_DSR), MODEM_STATUS_CTS | MODEM_STATUS_DSR);
+
+        spc.write_u8(SERIAL2_LINE_CONTROL, 0x03, None, DELTA);
+        spc.write_u8(SERIAL2_RX_TX_BUFFER, 0x5A, None, DELTA);
+        run_for(&mut spc, &mut pic, 1100);
+        spc.update();
+        assert_eq!(*sent.borrow(), vec![0x5A]);
+        assert!(pending.borrow().is_empty());
+
+        run_for(&mut spc, &mut pic, 1100);
+        assert_eq!(spc.read_u8(SERIAL2_RX_TX_BUFFER, DELTA), 0x41);
+        run_for(&mut spc, &mut pic, 1100);
+        assert_eq!(spc.read_u8(SERIAL2_RX_TX_BUFFER, DELTA), 0x42);
+
+        // Nothing reaches the backend in loopback mode
+        spc.write_u8(SERIAL2_MODEM_CONTROL, MODEM_CONTROL_LOOP, None, DELTA);
+        spc.write_u8(SERIAL2_RX_TX_BUFFER, 0x33, None, DELTA);
+        run_for(&mut spc, &mut pic, 2200);
+        spc.update();
+        assert_eq!(*sent.borrow(), vec![0x5A]);
+        assert_eq!(spc.read_u8(SERIAL2_RX_TX_BUFFER, DELTA), 0x33);
+    }
+
+    #[test]
+    fn test_serial_tcp() {
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut spc = SerialPortController::new();
+        let mut pic = pic::Pic::new();
+        spc.connect_tcp(0, &address).unwrap();
+        spc.write_u8(SERIAL1_LINE_CONTROL, 0x03, None, DELTA);
+
+        // A byte transmitted before the connection completes is buffered, not lost
+        spc.write_u8(SERIAL1_RX_TX_BUFFER, 0x5A, None, DELTA);
+        run_for(&mut spc, &mut pic, 1100);
+        spc.update();
+
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        peer.write_all(&[0x41]).unwrap();
+
+        let mut received = [0u8; 1];
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            spc.update();
+            if let Ok(1) = peer.read(&mut received) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for buffered byte");
+        }
+        assert_eq!(received[0], 0x5A);
+
+        while spc.port[0].rx_queue.is_empty() {
+            spc.update();
+            assert!(Instant::now() < deadline, "timed out waiting for peer byte");
+            thread::sleep(Duration::from_millis(1));
+        }
+        run_for(&mut spc, &mut pic, 1100);
+        assert_eq!(spc.read_u8(SERIAL1_RX_TX_BUFFER, DELTA), 0x41);
+
+        // The peer closing the socket disconnects the port and drops CTS and DSR
+        drop(peer);
+        while spc.port[0].backend.is_some() {
+            spc.update();
+            assert!(Instant::now() < deadline, "timed out waiting for disconnect");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let msr = spc.read_u8(SERIAL1_MODEM_STATUS, DELTA);
+        assert_eq!(msr & (MODEM_STATUS_CTS | MODEM_STATUS_DSR), 0);
+        assert_eq!(msr & (MODEM_STATUS_DCTS | MODEM_STATUS_DDSR), MODEM_STATUS_DCTS | MODEM_STATUS_DDSR);
+    }
+}
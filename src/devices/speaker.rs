@@ -0,0 +1,147 @@
+/*
+    Marty PC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    speaker.rs
+
+    Emulates the PC speaker. The speaker is driven by the output of PIT
+    channel 2 ANDed with the speaker data bit of PPI port B (61h). The gate
+    bit of port B controls whether channel 2 counts, and so is reflected in
+    the channel output by the PIT itself.
+
+    The speaker level is sampled once per PIT tick and resampled to the host
+    audio sample rate by averaging the levels that fall within each output
+    sample. Averaging also reproduces the intermediate levels produced when
+    software toggles the speaker data bit rapidly to play PWM sound.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::devices::ppi::PORTB_SPEAKER_DATA;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+pub struct Speaker {
+    ticks_per_sample: f64,
+    fractional_part: f64,
+    sample_ticks: usize,
+    tick_count: usize,
+    level_sum: u32,
+    samples: VecDeque<f32>,
+}
+
+impl Speaker {
+
+    /// Create a speaker sampled at 'input_hz' (the PIT clock) that produces samples at the
+    /// host 'sample_rate'.
+    pub fn new(input_hz: f64, sample_rate: u32) -> Self {
+        let ticks_per_sample = input_hz / u32::max(sample_rate, 1) as f64;
+        Self {
+            ticks_per_sample,
+            fractional_part: ticks_per_sample.fract(),
+            sample_ticks: usize::max(ticks_per_sample.trunc() as usize, 1),
+            tick_count: 0,
+            level_sum: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Return the speaker level for the given PIT channel 2 output and value of PPI port B.
+    #[inline]
+    pub fn output_level(timer2_out: bool, portb: u8) -> bool {
+        timer2_out && (portb & PORTB_SPEAKER_DATA != 0)
+    }
+
+    /// Return the number of input ticks required to complete the next output sample.
+    pub fn ticks_remaining(&self) -> usize {
+        self.sample_ticks - self.tick_count
+    }
+
+    /// Sample the speaker level for one PIT tick. An output sample is produced once enough
+    /// ticks have been accumulated.
+    pub fn tick(&mut self, level: bool) {
+        self.level_sum += level as u32;
+        self.tick_count += 1;
+
+        if self.tick_count >= self.sample_ticks {
+            // Averaging samples is effectively a poor lowpass filter.
+            // TODO: replace with actual lowpass filter from biquad?
+            let sample = self.level_sum as f32 / self.tick_count as f32;
+            self.samples.push_back(sample);
+            self.level_sum = 0;
+            self.tick_count = 0;
+
+            // Calculate size of next audio sample in ticks by carrying over fractional part
+            let next_sample_f = self.ticks_per_sample + self.fractional_part;
+            self.sample_ticks = usize::max(next_sample_f as usize, 1);
+            self.fractional_part = next_sample_f.fract();
+        }
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Remove and return the oldest output sample, in the range 0.0 to 1.0.
+    pub fn pop_sample(&mut self) -> Option<f32> {
+        self.samples.pop_front()
+    }
+
+    /// Remove and return all available output samples.
+    pub fn drain_samples(&mut self) -> std::collections::vec_deque::Drain<'_, f32> {
+        self.samples.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_resample() {
+        let pit_hz = 1_193_182.0;
+        let mut speaker = Speaker::new(pit_hz, DEFAULT_SAMPLE_RATE);
+
+        assert!(!Speaker::output_level(true, 0x01));
+        assert!(!Speaker::output_level(false, 0x03));
+        assert!(Speaker::output_level(true, 0x03));
+
+        // One second of a 1kHz square wave
+        let half_period = (pit_hz / 2000.0) as u32;
+        for t in 0..pit_hz as u32 {
+            speaker.tick((t / half_period) % 2 == 0);
+        }
+
+        let samples: Vec<f32> = speaker.drain_samples().collect();
+        assert!((samples.len() as i64 - DEFAULT_SAMPLE_RATE as i64).abs() <= 1);
+        assert!(samples.iter().all(|s| (0.0..=1.0).contains(s)));
+        assert!(samples.iter().any(|s| *s == 1.0) && samples.iter().any(|s| *s == 0.0));
+
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01);
+
+        // Toggling the level every tick produces an intermediate level
+        for t in 0..speaker.ticks_remaining() {
+            speaker.tick(t % 2 == 0);
+        }
+        let sample = speaker.pop_sample().unwrap();
+        assert!(sample > 0.4 && sample < 0.6);
+        assert_eq!(speaker.samples_available(), 0);
+    }
+}
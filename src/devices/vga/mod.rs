@@ -1017,7 +1017,7 @@ impl VGACard {
     /// address.
     fn get_pixels(&mut self, byte: usize) {
         for p in 0..8 {
-            self.pixel_buf[p] |= self.planes[0].buf[byte] >> (7 - p) & 0x01;
+            self.pixel_buf[p] = self.planes[0].buf[byte] >> (7 - p) & 0x01;
             self.pixel_buf[p] |= (self.planes[1].buf[byte] >> (7 - p) & 0x01) << 1;
             self.pixel_buf[p] |= (self.planes[2].buf[byte] >> (7 - p) & 0x01) << 2;
             self.pixel_buf[p] |= (self.planes[3].buf[byte] >> (7 - p) & 0x01) << 3;
@@ -1472,6 +1472,11 @@ impl VideoCard for VGACard {
         (palette, intensity)
     }        
 
+    /// The color burst is always present in CGA compatible modes.
+    fn is_color_burst_enabled(&self) -> bool {
+        true
+    }
+
     /// Returns a string representation of all the CRTC Registers.
     fn get_videocard_string_state(&self) -> HashMap<String, Vec<(String, VideoCardStateEntry)>> {
 
@@ -1882,7 +1887,7 @@ impl MemoryMappedDevice for VGACard {
         }
 
         // Validate address is within current memory map and get the offset into VRAM
-        let offset = match self.plane_bounds_check(address) {
+        let mut offset = match self.plane_bounds_check(address) {
             Some(offset) => offset,
             None => {
                 trace!(self, "Read out of range: Failed to set latches for address: {:05X}", address);
@@ -1895,6 +1900,13 @@ impl MemoryMappedDevice for VGACard {
             }
         };
 
+        // In chain4 mode, the first two bits of the memory address select the plane to read
+        let mut c4_plane_select = None;
+        if self.sequencer_memory_mode.chain4_enable() {
+            c4_plane_select = Some(offset & 0x03);
+            offset >>= 2;
+        }
+
         // Load all the latches regardless of selected plane or read mode
         self.latch_addr = address as u32;
         for i in 0..4 {
@@ -1906,7 +1918,7 @@ impl MemoryMappedDevice for VGACard {
             ReadMode::ReadSelectedPlane => {
                 // In Read Mode 0, the processor reads data from the memory plane selected 
                 // by the read map select register.
-                let plane = (self.graphics_read_map_select & 0x03) as usize;
+                let plane = c4_plane_select.unwrap_or((self.graphics_read_map_select & 0x03) as usize);
                 let byte = self.planes[plane].buf[offset];
 
                 trace!(self, "READ ({:?}) [{:05X}]: BYTE:{:02X} L:[{:02X},{:02X},{:02X},{:02X}]",
@@ -1947,7 +1959,6 @@ impl MemoryMappedDevice for VGACard {
         let (lo_byte, wait1) = MemoryMappedDevice::read_u8(self, address, 0);
         let (ho_byte, wait2) = MemoryMappedDevice::read_u8(self, address + 1, 0);
 
-        log::warn!("Unsupported 16 bit read from VRAM");
         ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2)
     }
 
@@ -2157,14 +2168,20 @@ impl MemoryMappedDevice for VGACard {
                 let mask = data_rot & self.graphics_bitmask;
 
                 for i in 0..4 {
+                    // Only write to planes enabled in the Sequencer Map Mask.
+                    if self.sequencer_map_mask & (0x01 << i) == 0 {
+                        continue;
+                    }
+
                     // Select bits all ON or OFF depending on the corresponding value of the set/reset register
                     let all_bits = match self.graphics_set_reset & (0x01 << i) != 0 {
                         true => 0xFF,
                         false => 0x00
                     };
 
-                    // Use the mask calculated earlier to mask the identical bits from the set/reset register
-                    self.planes[i].buf[offset] = (self.planes[i].buf[offset] & !mask) | (all_bits & mask);
+                    // Use the mask calculated earlier to select between the set/reset bits and the latches
+                    self.pipeline_buf[i] = (all_bits & mask) | (self.planes[i].latch & !mask);
+                    self.planes[i].buf[offset] = self.pipeline_buf[i];
                 }
 
             }
@@ -2174,9 +2191,13 @@ impl MemoryMappedDevice for VGACard {
     }
 
     fn write_u16(&mut self, address: usize, data: u16, _cycles: u32) -> u32 {
-        trace!(self, "16 byte write to VRAM, {:04X} -> {:05X} ", data, address);
-        log::warn!("Unsupported 16 bit write to VRAM");
-        0
+        trace!(self, "16 bit write to VRAM, {:04X} -> {:05X} ", data, address);
+
+        // A word write is performed as two byte writes, low byte first, each passing through
+        // the full write pipeline.
+        let wait1 = MemoryMappedDevice::write_u8(self, address, (data & 0xFF) as u8, 0);
+        let wait2 = MemoryMappedDevice::write_u8(self, address + 1, (data >> 8) as u8, 0);
+        wait1 + wait2
     }
 }
 
@@ -2239,4 +2260,180 @@ mod tests {
         */
 
     }
-}
\ No newline at end of file
+
+    fn io_write(vga: &mut VGACard, port: u16, byte: u8) {
+        IoDevice::write_u8(vga, port, byte, None, DeviceRunTimeUnit::Microseconds(0.0));
+    }
+
+    fn gc_write(vga: &mut VGACard, reg: u8, byte: u8) {
+        io_write(vga, GRAPHICS_ADDRESS, reg);
+        io_write(vga, GRAPHICS_DATA, byte);
+    }
+
+    fn seq_write(vga: &mut VGACard, reg: u8, byte: u8) {
+        io_write(vga, SEQUENCER_ADDRESS_REGISTER, reg);
+        io_write(vga, SEQUENCER_DATA_REGISTER, byte);
+    }
+
+    fn mem_read(vga: &mut VGACard, address: usize) -> u8 {
+        MemoryMappedDevice::read_u8(vga, address, 0).0
+    }
+
+    fn mem_write(vga: &mut VGACard, address: usize, byte: u8) {
+        MemoryMappedDevice::write_u8(vga, address, byte, 0);
+    }
+
+    /// Read back a byte from a single plane using Read Mode 0.
+    fn plane_read(vga: &mut VGACard, plane: u8, address: usize) -> u8 {
+        gc_write(vga, 0x05, 0x00);
+        gc_write(vga, 0x04, plane);
+        mem_read(vga, address)
+    }
+
+    /// Create a card with RAM enabled and a 64K graphics aperture at A0000.
+    fn planar_card() -> VGACard {
+        let mut vga = VGACard::new(TraceLogger::None);
+
+        io_write(&mut vga, MISC_OUTPUT_REGISTER_WRITE, 0x03);
+        gc_write(&mut vga, 0x06, 0x05);
+        gc_write(&mut vga, 0x00, 0x00);
+        gc_write(&mut vga, 0x01, 0x00);
+        gc_write(&mut vga, 0x03, 0x00);
+        gc_write(&mut vga, 0x05, 0x00);
+        gc_write(&mut vga, 0x08, 0xFF);
+        seq_write(&mut vga, 0x02, 0x0F);
+        seq_write(&mut vga, 0x04, 0x06);
+        vga
+    }
+
+    #[test]
+    fn test_planar_write_mode0() {
+        let mut vga = planar_card();
+
+        // Set/Reset replaces the data for planes 0 and 2
+        gc_write(&mut vga, 0x00, 0x01);
+        gc_write(&mut vga, 0x01, 0x05);
+        mem_write(&mut vga, 0xA0000, 0xA5);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0000), 0xFF);
+        assert_eq!(plane_read(&mut vga, 1, 0xA0000), 0xA5);
+        assert_eq!(plane_read(&mut vga, 2, 0xA0000), 0x00);
+        assert_eq!(plane_read(&mut vga, 3, 0xA0000), 0xA5);
+
+        // Bits outside the Bit Mask come from the latches loaded by the last read
+        gc_write(&mut vga, 0x01, 0x00);
+        gc_write(&mut vga, 0x08, 0x0F);
+        mem_read(&mut vga, 0xA0000);
+        mem_write(&mut vga, 0xA0000, 0x00);
+        gc_write(&mut vga, 0x08, 0xFF);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0000), 0xF0);
+        assert_eq!(plane_read(&mut vga, 1, 0xA0000), 0xA0);
+
+        // Only planes enabled in the Map Mask are written
+        seq_write(&mut vga, 0x02, 0x02);
+        mem_write(&mut vga, 0xA0001, 0x3C);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0001), 0x00);
+        assert_eq!(plane_read(&mut vga, 1, 0xA0001), 0x3C);
+        assert_eq!(plane_read(&mut vga, 2, 0xA0001), 0x00);
+    }
+
+    #[test]
+    fn test_planar_write_mode1() {
+        let mut vga = planar_card();
+
+        for plane in 0..4 {
+            seq_write(&mut vga, 0x02, 0x01 << plane);
+            mem_write(&mut vga, 0xA0000, 0x11 * (plane + 1));
+        }
+
+        // Copy the latches to a new address
+        seq_write(&mut vga, 0x02, 0x0F);
+        mem_read(&mut vga, 0xA0000);
+        gc_write(&mut vga, 0x05, 0x01);
+        mem_write(&mut vga, 0xA0010, 0x00);
+
+        for plane in 0..4 {
+            assert_eq!(plane_read(&mut vga, plane, 0xA0010), 0x11 * (plane + 1));
+        }
+    }
+
+    #[test]
+    fn test_planar_write_mode2() {
+        let mut vga = planar_card();
+
+        mem_read(&mut vga, 0xA0020);
+        gc_write(&mut vga, 0x05, 0x02);
+        gc_write(&mut vga, 0x08, 0xF0);
+        mem_write(&mut vga, 0xA0020, 0x09);
+        gc_write(&mut vga, 0x08, 0xFF);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0020), 0xF0);
+        assert_eq!(plane_read(&mut vga, 1, 0xA0020), 0x00);
+        assert_eq!(plane_read(&mut vga, 2, 0xA0020), 0x00);
+        assert_eq!(plane_read(&mut vga, 3, 0xA0020), 0xF0);
+    }
+
+    #[test]
+    fn test_planar_write_mode3() {
+        let mut vga = planar_card();
+
+        mem_write(&mut vga, 0xA0030, 0xFF);
+        mem_read(&mut vga, 0xA0030);
+
+        // The rotated data ANDed with the Bit Mask selects Set/Reset bits over the latches
+        gc_write(&mut vga, 0x05, 0x03);
+        gc_write(&mut vga, 0x00, 0x01);
+        mem_write(&mut vga, 0xA0030, 0x0F);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0030), 0xFF);
+        assert_eq!(plane_read(&mut vga, 1, 0xA0030), 0xF0);
+        assert_eq!(plane_read(&mut vga, 2, 0xA0030), 0xF0);
+        assert_eq!(plane_read(&mut vga, 3, 0xA0030), 0xF0);
+    }
+
+    #[test]
+    fn test_planar_read_mode1() {
+        let mut vga = planar_card();
+
+        seq_write(&mut vga, 0x02, 0x01);
+        mem_write(&mut vga, 0xA0040, 0xF0);
+        seq_write(&mut vga, 0x02, 0x02);
+        mem_write(&mut vga, 0xA0040, 0xCC);
+
+        gc_write(&mut vga, 0x05, 0x08);
+        gc_write(&mut vga, 0x02, 0x01);
+        gc_write(&mut vga, 0x07, 0x0F);
+        assert_eq!(mem_read(&mut vga, 0xA0040), 0x30);
+
+        // Planes cleared in Color Don't Care are ignored in the comparison
+        gc_write(&mut vga, 0x07, 0x01);
+        assert_eq!(mem_read(&mut vga, 0xA0040), 0xF0);
+    }
+
+    #[test]
+    fn test_planar_word_write() {
+        let mut vga = planar_card();
+
+        MemoryMappedDevice::write_u16(&mut vga, 0xA0050, 0xBEEF, 0);
+
+        assert_eq!(plane_read(&mut vga, 0, 0xA0050), 0xEF);
+        assert_eq!(plane_read(&mut vga, 0, 0xA0051), 0xBE);
+        assert_eq!(MemoryMappedDevice::read_u16(&mut vga, 0xA0050, 0).0, 0xBEEF);
+    }
+
+    #[test]
+    fn test_chain4_read_write() {
+        let mut vga = planar_card();
+
+        seq_write(&mut vga, 0x04, 0x0E);
+        mem_write(&mut vga, 0xA0006, 0x77);
+
+        assert_eq!(mem_read(&mut vga, 0xA0006), 0x77);
+        assert_eq!(mem_read(&mut vga, 0xA0005), 0x00);
+
+        seq_write(&mut vga, 0x04, 0x06);
+        assert_eq!(plane_read(&mut vga, 2, 0xA0001), 0x77);
+    }
+}
@@ -5,5 +5,14 @@ use egui::Color32;
 pub const PIT_VIEWER_WIDTH: f32 = 350.0;
 pub const DMA_VIEWER_WIDTH: f32 = 350.0;
 
+// Number of samples of timer output shown in the PIT viewer plot
+pub const PIT_VIEWER_PLOT_SAMPLES: usize = 200;
+
+// Number of instruction bytes the disassembly view's bytes column is sized to fit
+pub const DISASSEMBLY_BYTES_COLUMN_WIDTH: usize = 6;
+
 // Color definitions
 pub const COLOR32_CYAN: Color32 = Color32::from_rgb(0, 255, 255);
+pub const COLOR32_DIFF_CHANGED: Color32 = Color32::from_rgb(255, 80, 80);
+pub const COLOR32_DIFF_UNCHANGED: Color32 = Color32::from_rgb(80, 220, 100);
+pub const COLOR32_UNMAPPED: Color32 = Color32::from_rgb(80, 80, 80);
@@ -39,6 +39,7 @@ pub struct CpuControl {
     breakpoint: String,
     mem_breakpoint: String,
     int_breakpoint: String,
+    cond_breakpoint: String,
 }
 
 impl CpuControl {
@@ -49,6 +50,7 @@ impl CpuControl {
             breakpoint: String::new(),
             mem_breakpoint: String::new(),
             int_breakpoint: String::new(),
+            cond_breakpoint: String::new(),
         }
     }
 
@@ -75,7 +77,7 @@ impl CpuControl {
                    exec_control.set_op(ExecutionOperation::StepOver);
                 };
 
-                if ui.input().key_pressed(egui::Key::F10) {
+                if ui.input().key_pressed(egui::Key::F10) && !ui.input().modifiers.shift {
                     exec_control.set_op(ExecutionOperation::StepOver);
                 }                             
             });   
@@ -86,10 +88,35 @@ impl CpuControl {
                 };
 
                 if ui.input().key_pressed(egui::Key::F11) {
-                    exec_control.set_op(ExecutionOperation::Step);
+                    if ui.input().modifiers.shift {
+                        exec_control.set_op(ExecutionOperation::StepSkipIrq);
+                    }
+                    else {
+                        exec_control.set_op(ExecutionOperation::Step);
+                    }
                 }                             
             });                 
 
+            ui.add_enabled_ui(step_enabled, |ui| {
+                if ui.button(egui::RichText::new("⇥").font(egui::FontId::proportional(20.0)))
+                    .on_hover_text("Step, running interrupt handlers in the background")
+                    .clicked() {
+                   exec_control.set_op(ExecutionOperation::StepSkipIrq);
+                };
+            });
+
+            ui.add_enabled_ui(step_enabled, |ui| {
+                if ui.button(egui::RichText::new("⤴").font(egui::FontId::proportional(20.0)))
+                    .on_hover_text("Step out of the current routine")
+                    .clicked() {
+                   exec_control.set_op(ExecutionOperation::StepOut);
+                };
+
+                if ui.input().key_pressed(egui::Key::F10) && ui.input().modifiers.shift {
+                    exec_control.set_op(ExecutionOperation::StepOut);
+                }
+            });
+
             ui.add_enabled_ui(run_enabled, |ui| {
                 if ui.button(egui::RichText::new("▶").font(egui::FontId::proportional(20.0))).clicked() {
                     exec_control.set_op(ExecutionOperation::Run);
@@ -170,11 +197,18 @@ impl CpuControl {
             if ui.text_edit_singleline(&mut self.int_breakpoint).changed() {
                 events.push_back(GuiEvent::EditBreakpoint);
             }
+        });
+        ui.separator();
+        ui.horizontal(|ui|{
+            ui.label("Cond Breakpoint: ");
+            if ui.text_edit_singleline(&mut self.cond_breakpoint).changed() {
+                events.push_back(GuiEvent::EditBreakpoint);
+            }
         });                
     }
 
-    pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
-        (&self.breakpoint, &self.mem_breakpoint, &self.int_breakpoint)
+    pub fn get_breakpoints(&mut self) -> (&str, &str, &str, &str) {
+        (&self.breakpoint, &self.mem_breakpoint, &self.int_breakpoint, &self.cond_breakpoint)
     }
 
 
@@ -184,26 +184,17 @@ impl CpuViewerControl {
             //const CPU_FLAG_DIRECTION: u16  = 0b0100_0000_0000;
             //const CPU_FLAG_OVERFLOW: u16   = 0b1000_0000_0000;
         
+            // Each flag is drawn highlighted when it changes, fading out as it ages.
             ui.horizontal( |ui| {
-                //ui.add(egui::Label::new("SP:"));
-                ui.label(egui::RichText::new("O:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.o_fl).font(egui::TextStyle::Monospace));
-                ui.label(egui::RichText::new("D:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.d_fl).font(egui::TextStyle::Monospace)); 
-                ui.label(egui::RichText::new("I:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.i_fl).font(egui::TextStyle::Monospace));  
-                ui.label(egui::RichText::new("T:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.t_fl).font(egui::TextStyle::Monospace));
-                ui.label(egui::RichText::new("S:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.s_fl).font(egui::TextStyle::Monospace));
-                ui.label(egui::RichText::new("Z:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.z_fl).font(egui::TextStyle::Monospace));      
-                ui.label(egui::RichText::new("A:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.a_fl).font(egui::TextStyle::Monospace));  
-                ui.label(egui::RichText::new("P:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.p_fl).font(egui::TextStyle::Monospace));             
-                ui.label(egui::RichText::new("C:").text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.c_fl).font(egui::TextStyle::Monospace));                                        
+                for token in &self.cpu_state.flag_tokens {
+                    if let SyntaxToken::StateString(text, _, age) = token {
+                        ui.label(
+                            egui::RichText::new(text)
+                                .text_style(egui::TextStyle::Monospace)
+                                .color(fade_c32(Color32::GRAY, STATUS_UPDATE_COLOR, 255 - *age))
+                        );
+                    }
+                }
             });
           
             ui.end_row();  
@@ -225,7 +216,25 @@ impl CpuViewerControl {
   });     
   }
     
-  pub fn update_state(&mut self, state: CpuStringState) {
+  pub fn update_state(&mut self, mut state: CpuStringState) {
+
+    // Age the flag tokens. A flag that changed since the last update starts at age 0.
+    for (n, token) in state.flag_tokens.iter_mut().enumerate() {
+      if let SyntaxToken::StateString(text, dirty, age) = token {
+        match self.cpu_state.flag_tokens.get(n) {
+          Some(SyntaxToken::StateString(old_text, _, old_age)) if old_text == text => {
+            *dirty = false;
+            *age = old_age.saturating_add(2);
+          }
+          Some(_) => {
+            *dirty = true;
+            *age = 0;
+          }
+          None => *age = TOKEN_MAX_AGE
+        }
+      }
+    }
+
     self.cpu_state = state;
   }
     
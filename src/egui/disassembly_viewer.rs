@@ -24,6 +24,14 @@
     the next X instructions from the specified address. This address can
     be an expression, such as 'cs:ip'
 
+    Clicking the address of an instruction selects it, so that a comment
+    can be entered for that address.
+
+    The Find field searches forward from the top of the view for a pattern
+    of hex bytes, such as 'CD 21' or 'B4 ?? CD 21', or for an instruction 
+    query such as 'int 21' or 'mov es: memw', and moves the view to the 
+    next match.
+
 */
 use std::collections::VecDeque;
 
@@ -37,6 +45,14 @@ pub struct DisassemblyControl {
     pub row: usize,
     pub lastrow: usize,
     tlv: TokenListView,
+    snippet: Option<String>,
+    radix: ValueRadix,
+    microcode: bool,
+    /// Segment and offset of the instruction selected for commenting
+    selected: Option<(u16, u16)>,
+    comment: String,
+    find: String,
+    find_status: Option<String>,
 }
 
 impl DisassemblyControl {
@@ -46,7 +62,14 @@ impl DisassemblyControl {
             address: "cs:ip".to_string(),
             row: 0,
             lastrow: 0,
-            tlv: TokenListView::new()
+            tlv: TokenListView::new(),
+            snippet: None,
+            radix: ValueRadix::Hex,
+            microcode: false,
+            selected: None,
+            comment: String::new(),
+            find: String::new(),
+            find_status: None,
         }
     }
 
@@ -57,22 +80,116 @@ impl DisassemblyControl {
             if ui.text_edit_singleline(&mut self.address).changed() {
                 events.push_back(GuiEvent::MemoryUpdate);
             }
+            if ui.button("Copy context").clicked() {
+                events.push_back(GuiEvent::CopyDisassemblySnippet);
+            }
+            if ui.button("Copy view").clicked() {
+                self.snippet = Some(self.tlv.to_plaintext());
+            }
+            ui.separator();
+            ui.label("Values: ");
+            ui.radio_value(&mut self.radix, ValueRadix::Hex, "Hex");
+            ui.radio_value(&mut self.radix, ValueRadix::Octal, "Oct");
+            ui.radio_value(&mut self.radix, ValueRadix::Binary, "Bin");
+            ui.separator();
+            if ui.checkbox(&mut self.microcode, "Microcode").changed() {
+                events.push_back(GuiEvent::MemoryUpdate);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Find: ");
+            let response = ui.text_edit_singleline(&mut self.find);
+            let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+            if ui.button("Next").clicked() || entered {
+                events.push_back(GuiEvent::FindCode(self.find.clone()));
+            }
+            if let Some(status) = &self.find_status {
+                ui.label(status);
+            }
         });
         ui.separator();
 
+        // Place a pending disassembly snippet on the clipboard
+        if let Some(snippet) = self.snippet.take() {
+            ui.output().copied_text = snippet;
+        }
+
         self.tlv.set_capacity(24);
         self.tlv.set_visible(24);
+        self.tlv.set_radix(self.radix);
 
         let mut new_row = self.row;
-        ui.horizontal(|ui| {
+        let list_response = ui.horizontal(|ui| {
             self.tlv.draw(ui, events, &mut new_row);
+        }).response;
+
+        // Ctrl+Shift+C over the disassembly copies it as text
+        let copy_pressed = {
+            let input = ui.input();
+            input.modifiers.ctrl && input.modifiers.shift && input.key_pressed(egui::Key::C)
+        };
+        if copy_pressed && list_response.hovered() {
+            self.snippet = Some(self.tlv.to_plaintext());
+        }
+
+        if let Some(row) = self.tlv.take_clicked_row() {
+            self.select_row(row);
+        }
+
+        if let Some((segment, offset)) = self.selected {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(format!("Comment at {:04X}:{:04X}: ", segment, offset));
+                let response = ui.text_edit_singleline(&mut self.comment);
+                let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                if ui.button("Set").clicked() || entered {
+                    events.push_back(GuiEvent::SetComment(segment, offset, self.comment.clone()));
+                }
+            });
+        }
+    }
+
+    /// Select the instruction on the specified row, and start editing its comment.
+    fn select_row(&mut self, row: usize) {
+        let tokens = match self.tlv.contents.get(row) {
+            Some(tokens) => tokens,
+            None => return
+        };
+
+        self.selected = tokens.iter().find_map(|token| match token {
+            SyntaxToken::MemoryAddressSeg16(segment, offset, _) => Some((*segment, *offset)),
+            _ => None
         });
+        self.comment = tokens.iter().find_map(|token| match token {
+            SyntaxToken::Comment(s) => Some(s.clone()),
+            _ => None
+        }).unwrap_or_default();
+        self.tlv.set_selected_row(self.selected.map(|_| row));
     }
 
     pub fn set_content(&mut self, mem: Vec<Vec<SyntaxToken>>) {
+        // Keep the selected instruction highlighted as the disassembly moves
+        let selected_row = self.selected.and_then(|(segment, offset)| {
+            mem.iter().position(|tokens| {
+                tokens.iter().any(|token| {
+                    matches!(token, SyntaxToken::MemoryAddressSeg16(s, o, _) if *s == segment && *o == offset)
+                })
+            })
+        });
+        self.tlv.set_selected_row(selected_row);
         self.tlv.set_contents(mem);
     }
 
+    /// Set a tooltip for each instruction row, shown when hovering over its mnemonic.
+    pub fn set_tooltips(&mut self, tooltips: Vec<Option<String>>) {
+        self.tlv.set_row_tooltips(tooltips);
+    }
+
+    /// Whether to show the starting microcode address of each instruction.
+    pub fn show_microcode(&self) -> bool {
+        self.microcode
+    }
+
     pub fn set_address(&mut self, address: String) {
         self.address = address;
     }
@@ -80,4 +197,14 @@ impl DisassemblyControl {
     pub fn get_address(&mut self) -> String {
         self.address.clone()
     }
+
+    /// Set the message shown next to the Find field, such as when no match was found.
+    pub fn set_find_status(&mut self, status: Option<String>) {
+        self.find_status = status;
+    }
+
+    /// Set a disassembly snippet to be copied to the clipboard on the next draw.
+    pub fn set_snippet(&mut self, snippet: String) {
+        self.snippet = Some(snippet);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    egui::ems_viewer.rs
+
+    Implements a viewer control for the EMS board, showing the logical
+    page mapped into each physical page of the page frame.
+
+*/
+
+use crate::egui::*;
+use crate::devices::ems::EmsStringState;
+
+pub struct EmsViewerControl {
+    state: Option<EmsStringState>,
+}
+
+impl EmsViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            state: None,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent> ) {
+
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => {
+                ui.label("No EMS board installed.");
+                return
+            }
+        };
+
+        egui::Grid::new("ems_view")
+            .striped(true)
+            .min_col_width(100.0)
+            .show(ui, |ui| {
+
+                ui.label(egui::RichText::new("Page frame: ").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut state.page_frame).font(egui::TextStyle::Monospace));
+                ui.end_row();
+                ui.label(egui::RichText::new("IO base: ").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut state.io_base).font(egui::TextStyle::Monospace));
+                ui.end_row();
+                ui.label(egui::RichText::new("Logical pages: ").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut state.logical_pages).font(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                ui.label(egui::RichText::new("Address").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Logical Page").text_style(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                for (address, page) in state.mapping.iter_mut() {
+                    ui.add(egui::TextEdit::singleline(address).font(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(page).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+                }
+            });
+    }
+
+    pub fn update_state(&mut self, state: Option<EmsStringState>) {
+        self.state = state;
+    }
+}
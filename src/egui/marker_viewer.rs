@@ -0,0 +1,83 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    egui::marker_viewer.rs
+
+    Implements a list of named debugging markers. Markers can be added at
+    the current point of execution and the machine restored to them later.
+
+*/
+
+use crate::egui::*;
+use crate::markers::Marker;
+
+pub struct MarkerViewerControl {
+    label: String,
+    markers: Vec<Marker>,
+}
+
+impl MarkerViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            label: String::new(),
+            markers: Vec::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            ui.label("Label: ");
+            ui.text_edit_singleline(&mut self.label);
+            if ui.add_enabled(!self.label.is_empty(), egui::Button::new("Add marker")).clicked() {
+                events.push_back(GuiEvent::AddMarker(self.label.clone()));
+                self.label.clear();
+            }
+        });
+        ui.separator();
+
+        egui::Grid::new("marker_view")
+            .num_columns(4)
+            .striped(true)
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+
+                for (i, marker) in self.markers.iter().enumerate() {
+                    ui.label(egui::RichText::new(&marker.label).text_style(egui::TextStyle::Monospace));
+                    ui.label(
+                        egui::RichText::new(format!("[{:04X}:{:04X}] #{}", marker.cs, marker.ip, marker.instruction_count))
+                            .text_style(egui::TextStyle::Monospace)
+                    );
+                    // Markers from a previous session have no snapshot and can only be shown
+                    if ui.button(if marker.snapshot.is_some() { "Go" } else { "Show" }).clicked() {
+                        events.push_back(GuiEvent::GotoMarker(i));
+                    }
+                    if ui.button("Delete").clicked() {
+                        events.push_back(GuiEvent::DeleteMarker(i));
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    pub fn set_markers(&mut self, markers: &[Marker]) {
+        self.markers = markers.to_vec();
+    }
+}
@@ -25,6 +25,13 @@
     active display as it is scrolled by sending GuiEvent::MemoryUpdate
     events.
 
+    The viewer can optionally follow the current execution address. Scrolling
+    or editing the address while following suspends it until resumed.
+
+    The visible memory can be captured as a baseline. In diff mode, values
+    are highlighted by whether they differ from the baseline, to show what
+    code modified between two breakpoints.
+
 */
 
 use std::collections::VecDeque;
@@ -33,6 +40,9 @@ use crate::egui::*;
 use crate::egui::token_listview::*;
 use crate::syntax_token::*;
 
+// Number of bytes above the followed address to show, to keep it centered in the view.
+const FOLLOW_CENTER_OFFSET: usize = 0x80;
+
 pub struct MemoryViewerControl {
 
     pub address: String,
@@ -40,6 +50,10 @@ pub struct MemoryViewerControl {
     pub lastrow: usize,
     pub mem: Vec<String>,
     //update_scroll_pos: bool,
+    format: MemoryDumpFormat,
+    follow: bool,
+    follow_suspended: bool,
+    diff: bool,
 
     tlv: TokenListView,
 }
@@ -53,6 +67,10 @@ impl MemoryViewerControl {
             lastrow: 0,
             mem: Vec::new(),
             //update_scroll_pos: false,
+            format: Default::default(),
+            follow: false,
+            follow_suspended: false,
+            diff: false,
             tlv: TokenListView::new()
         }
     }
@@ -62,6 +80,41 @@ impl MemoryViewerControl {
         ui.horizontal(|ui| {
             ui.label("Address: ");
             if ui.text_edit_singleline(&mut self.address).changed() {
+                self.suspend_follow();
+                events.push_back(GuiEvent::MemoryUpdate);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.follow, "Follow CS:IP").changed() {
+                self.follow_suspended = false;
+            }
+            if self.follow_suspended && ui.button("Resume follow").clicked() {
+                self.follow_suspended = false;
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Set baseline").clicked() {
+                let contents = self.tlv.contents.clone();
+                self.tlv.set_baseline(&contents);
+            }
+            ui.add_enabled_ui(self.tlv.has_baseline(), |ui| {
+                if ui.checkbox(&mut self.diff, "Diff from baseline").changed() {
+                    self.tlv.set_diff_mode(self.diff);
+                }
+                if ui.button("Clear baseline").clicked() {
+                    self.tlv.clear_baseline();
+                    self.diff = false;
+                    self.tlv.set_diff_mode(false);
+                }
+            });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Format: ");
+            let mut changed = false;
+            changed |= ui.radio_value(&mut self.format, MemoryDumpFormat::HexByte, "Byte").changed();
+            changed |= ui.radio_value(&mut self.format, MemoryDumpFormat::HexWord, "Word").changed();
+            changed |= ui.radio_value(&mut self.format, MemoryDumpFormat::SignedDecimal, "Signed").changed();
+            if changed {
                 events.push_back(GuiEvent::MemoryUpdate);
             }
         });
@@ -80,6 +133,7 @@ impl MemoryViewerControl {
             log::debug!("update address to: {:05X}", new_row);
             self.address = format!("{:05X}", new_row);
             self.row = new_row;
+            self.suspend_follow();
         }
 
     }
@@ -92,6 +146,36 @@ impl MemoryViewerControl {
     pub fn set_row(&mut self, row: usize) {
         //log::warn!("Set row to {}", row & !0x0F);
         self.row = row & !0x0F;
+        if self.row != self.tlv.row & !0x0F {
+            // Row was set from the address bar, so move the view to match.
+            self.tlv.scroll_to(self.row);
+        }
+    }
+
+    pub fn get_row(&self) -> usize {
+        self.row
+    }
+
+    /// Returns true if the view should follow the current execution address.
+    pub fn is_following(&self) -> bool {
+        self.follow && !self.follow_suspended
+    }
+
+    /// Center the view on the current execution address. Should be called each frame
+    /// while is_following() is true.
+    pub fn follow_address(&mut self, address: usize) {
+        self.address = format!("{:05X}", address);
+        let row = address.saturating_sub(FOLLOW_CENTER_OFFSET) & !0x0F;
+        if row != self.row {
+            self.row = row;
+            self.tlv.scroll_to(row);
+        }
+    }
+
+    fn suspend_follow(&mut self) {
+        if self.follow {
+            self.follow_suspended = true;
+        }
     }
 
     #[allow (dead_code)]
@@ -103,6 +187,10 @@ impl MemoryViewerControl {
         self.address.clone()
     }
 
+    pub fn get_format(&self) -> MemoryDumpFormat {
+        self.format
+    }
+
     pub fn set_memory(&mut self, mem: Vec<Vec<SyntaxToken>>) {
         self.tlv.set_contents(mem);
     }
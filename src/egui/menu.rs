@@ -125,6 +125,16 @@ impl GuiState {
                     }
                 });      
                 
+                if ui.button("💾 Save Floppy in Drive A:").clicked() {
+                    self.event_queue.push_back(GuiEvent::SaveFloppy(0));
+                    ui.close_menu();
+                };
+
+                if ui.button("💾 Save Floppy in Drive B:").clicked() {
+                    self.event_queue.push_back(GuiEvent::SaveFloppy(1));
+                    ui.close_menu();
+                };
+
                 if ui.button("⏏ Eject Floppy in Drive A:").clicked() {
                     self.event_queue.push_back(GuiEvent::EjectFloppy(0));
                     ui.close_menu();
@@ -194,11 +204,23 @@ impl GuiState {
                         self.event_queue.push_back(GuiEvent::DumpCS);
                         ui.close_menu();
                     }
+                    if ui.button("Code Segment Listing").clicked() {
+                        self.event_queue.push_back(GuiEvent::DumpCSListing);
+                        ui.close_menu();
+                    }
                     if ui.button("All Memory").clicked() {
                         self.event_queue.push_back(GuiEvent::DumpAllMem);
                         ui.close_menu();
                     }                    
                 });
+                if ui.button("Dump Instruction Profile").clicked() {
+                    self.event_queue.push_back(GuiEvent::DumpInstructionProfile);
+                    ui.close_menu();
+                }
+                if ui.button("Dump IO Port History").clicked() {
+                    self.event_queue.push_back(GuiEvent::DumpIoHistory);
+                    ui.close_menu();
+                }
                 if ui.button("CPU Control...").clicked() {
                     *self.window_flag(GuiWindow::CpuControl) = true;
                     ui.close_menu();
@@ -273,6 +295,10 @@ impl GuiState {
                     *self.window_flag(GuiWindow::CycleTraceViewer) = true;
                     ui.close_menu();
                 }                
+                if ui.button("Prefetch Queue...").clicked() {
+                    *self.window_flag(GuiWindow::QueueViewer) = true;
+                    ui.close_menu();
+                }
                 if ui.button("Call Stack...").clicked() {
                     *self.window_flag(GuiWindow::CallStack) = true;
                     ui.close_menu();
@@ -281,6 +307,14 @@ impl GuiState {
                     *self.window_flag(GuiWindow::DisassemblyViewer) = true;
                     ui.close_menu();
                 }
+                if ui.button("Markers...").clicked() {
+                    *self.window_flag(GuiWindow::MarkerViewer) = true;
+                    ui.close_menu();
+                }
+                if ui.button("Watch...").clicked() {
+                    *self.window_flag(GuiWindow::WatchViewer) = true;
+                    ui.close_menu();
+                }
                 if ui.button("IVR...").clicked() {
                     *self.window_flag(GuiWindow::IvrViewer) = true;
                     ui.close_menu();
@@ -301,10 +335,18 @@ impl GuiState {
                     *self.window_flag(GuiWindow::PpiViewer) = true;
                     ui.close_menu();
                 }
+                if ui.button("FDC...").clicked() {
+                    *self.window_flag(GuiWindow::FdcViewer) = true;
+                    ui.close_menu();
+                }
                 if ui.button("DMA...").clicked() {
                     *self.window_flag(GuiWindow::DmaViewer) = true;
                     ui.close_menu();
                 }
+                if ui.button("EMS...").clicked() {
+                    *self.window_flag(GuiWindow::EmsViewer) = true;
+                    ui.close_menu();
+                }
                 if ui.button("Video Card...").clicked() {
                     *self.window_flag(GuiWindow::VideoCardViewer) = true;
                     ui.close_menu();
@@ -362,6 +404,15 @@ impl GuiState {
                             ui.close_menu();
                         }
                     }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("TCP: ");
+                        ui.text_edit_singleline(&mut self.serial_tcp_address);
+                        if ui.button("Connect").clicked() {
+                            self.event_queue.push_back(GuiEvent::ConnectSerialTcp(self.serial_tcp_address.clone()));
+                            ui.close_menu();
+                        }
+                    });
                 });                                
             });
         });
@@ -44,17 +44,21 @@ mod delay_adjust;
 mod device_control;
 mod disassembly_viewer;
 mod dma_viewer;
+mod ems_viewer;
 mod image;
 mod instruction_history_viewer;
 mod ivr_viewer;
+mod marker_viewer;
 mod memory_viewer;
 mod menu;
 mod performance_viewer;
 mod pic_viewer;
 mod pit_viewer;
+mod queue_viewer;
 mod theme;
 mod token_listview;
 mod videocard_viewer;
+mod watch_viewer;
 
 use crate::{
 
@@ -72,11 +76,15 @@ use crate::{
     egui::device_control::DeviceControl,
     egui::disassembly_viewer::DisassemblyControl,
     egui::dma_viewer::DmaViewerControl,
+    egui::ems_viewer::EmsViewerControl,
     egui::performance_viewer::PerformanceViewerControl,
     egui::pic_viewer::PicViewerControl,
     egui::pit_viewer::PitViewerControl,
+    egui::queue_viewer::QueueViewerControl,
     egui::instruction_history_viewer::InstructionHistoryControl,
     egui::ivr_viewer::IvrViewerControl,
+    egui::marker_viewer::MarkerViewerControl,
+    egui::watch_viewer::WatchViewerControl,
     egui::theme::GuiTheme,
 
     machine::{MachineState, ExecutionControl},
@@ -88,6 +96,7 @@ use crate::{
         pit::PitDisplayState, 
         pic::PicStringState,
         ppi::PpiStringState, 
+        fdc::FloppyDriveStatus,
     },
 
     render::CompositeParams,
@@ -113,12 +122,17 @@ pub(crate) enum GuiWindow {
     PitViewer,
     PicViewer,
     PpiViewer,
+    FdcViewer,
     DmaViewer,
+    EmsViewer,
     VideoCardViewer,
     VideoMemViewer,
     CallStack,
     VHDCreator,
     CycleTraceViewer,
+    QueueViewer,
+    MarkerViewer,
+    WatchViewer,
 }
 
 #[derive(PartialEq, Eq, Hash)]
@@ -137,12 +151,18 @@ pub enum GuiEvent {
     CreateVHD(OsString, HardDiskFormat),
     LoadFloppy(usize, OsString),
     EjectFloppy(usize),
+    SaveFloppy(usize),
     BridgeSerialPort(String),
+    ConnectSerialTcp(String),
     DumpVRAM,
     DumpCS,
+    DumpCSListing,
+    DumpInstructionProfile,
+    DumpIoHistory,
     DumpAllMem,
     EditBreakpoint,
     MemoryUpdate,
+    MemoryWrite(usize, u8),
     TokenHover(usize),
     OptionChanged(GuiOption, bool),
     CompositeAdjust(CompositeParams),
@@ -151,6 +171,14 @@ pub enum GuiEvent {
     TickDevice(DeviceSelection, u32),
     MachineStateChange(MachineState),
     TakeScreenshot,
+    CopyDisassemblySnippet,
+    AddMarker(String),
+    GotoMarker(usize),
+    DeleteMarker(usize),
+    AddWatch(String),
+    DeleteWatch(usize),
+    SetComment(u16, u16, String),
+    FindCode(String),
     Exit,
     SetNMI(bool),
     TriggerParity,
@@ -228,6 +256,7 @@ pub(crate) struct GuiState {
     // Serial ports
     serial_ports: Vec<SerialPortInfo>,
     serial_port_name: String,
+    serial_tcp_address: String,
 
     exec_control: Rc<RefCell<ExecutionControl>>,
 
@@ -237,6 +266,7 @@ pub(crate) struct GuiState {
     pub cpu_control: CpuControl,
     pub cpu_viewer: CpuViewerControl,
     pub cycle_trace_viewer: CycleTraceViewerControl,
+    pub queue_viewer: QueueViewerControl,
     pub memory_viewer: MemoryViewerControl,
     pub cpu_state: CpuStringState,
 
@@ -246,6 +276,7 @@ pub(crate) struct GuiState {
     pub pit_viewer: PitViewerControl,
     pub pic_viewer: PicViewerControl,
     pub ppi_state: PpiStringState,
+    pub fdc_state: FloppyDriveStatus,
     
     pub videocard_state: VideoCardState,
     videocard_set_select: String,
@@ -257,10 +288,13 @@ pub(crate) struct GuiState {
     disassembly_viewer_address: String,
     pub disassembly_viewer: DisassemblyControl,
     pub dma_viewer: DmaViewerControl,
+    pub ems_viewer: EmsViewerControl,
     pub trace_viewer: InstructionHistoryControl,
     pub composite_adjust: CompositeAdjustControl,
     pub ivr_viewer: IvrViewerControl,
     pub device_control: DeviceControl,
+    pub marker_viewer: MarkerViewerControl,
+    pub watch_viewer: WatchViewerControl,
 
     trace_string: String,
     call_stack_string: String,
@@ -434,12 +468,17 @@ impl GuiState {
             (GuiWindow::PitViewer, false),
             (GuiWindow::PicViewer, false),
             (GuiWindow::PpiViewer, false),
+            (GuiWindow::FdcViewer, false),
             (GuiWindow::DmaViewer, false),
+            (GuiWindow::EmsViewer, false),
             (GuiWindow::VideoCardViewer, false),
             (GuiWindow::VideoMemViewer, false),
             (GuiWindow::CallStack, false),
             (GuiWindow::VHDCreator, false),
             (GuiWindow::CycleTraceViewer, false),
+            (GuiWindow::QueueViewer, false),
+            (GuiWindow::MarkerViewer, false),
+            (GuiWindow::WatchViewer, false),
         ].into();
 
         let option_flags: HashMap<GuiOption, bool> = [
@@ -484,6 +523,7 @@ impl GuiState {
 
             serial_ports: Vec::new(),
             serial_port_name: String::new(),
+            serial_tcp_address: "localhost:2323".to_string(),
 
             exec_control: exec_control.clone(),
 
@@ -493,6 +533,7 @@ impl GuiState {
             cpu_control: CpuControl::new(exec_control.clone()),
             cpu_viewer: CpuViewerControl::new(),
             cycle_trace_viewer: CycleTraceViewerControl::new(),
+            queue_viewer: QueueViewerControl::new(),
             memory_viewer_dump: String::new(),
             memory_viewer: MemoryViewerControl::new(),
             cpu_state: Default::default(),
@@ -502,6 +543,7 @@ impl GuiState {
             pit_viewer: PitViewerControl::new(),
             pic_viewer: PicViewerControl::new(),
             ppi_state: Default::default(),
+            fdc_state: Default::default(),
             dma_channel_select: 0,
             dma_channel_select_str: String::new(),
 
@@ -511,11 +553,14 @@ impl GuiState {
             disassembly_viewer_address: "cs:ip".to_string(),
             disassembly_viewer: DisassemblyControl::new(),
             dma_viewer: DmaViewerControl::new(),
+            ems_viewer: EmsViewerControl::new(),
             trace_viewer: InstructionHistoryControl::new(),
             trace_string: String::new(),
             composite_adjust: CompositeAdjustControl::new(),
             ivr_viewer: IvrViewerControl::new(),
             device_control: DeviceControl::new(),
+            marker_viewer: MarkerViewerControl::new(),
+            watch_viewer: WatchViewerControl::new(),
             call_stack_string: String::new(),
 
             // Options menu items
@@ -626,7 +671,7 @@ impl GuiState {
         self.composite
     }
 
-    pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
+    pub fn get_breakpoints(&mut self) -> (&str, &str, &str, &str) {
         self.cpu_control.get_breakpoints()
     }
 
@@ -642,6 +687,10 @@ impl GuiState {
         self.ppi_state = state;
     }
 
+    pub fn update_fdc_state(&mut self, state: FloppyDriveStatus) {
+        self.fdc_state = state;
+    }
+
     pub fn update_vhd_formats(&mut self, formats: Vec<HardDiskFormat>) {
         self.vhd_formats = formats
     }
@@ -735,6 +784,14 @@ impl GuiState {
                 self.cycle_trace_viewer.draw(ui, &mut self.event_queue);
             });               
 
+        egui::Window::new("Prefetch Queue")
+            .open(self.window_open_flags.get_mut(&GuiWindow::QueueViewer).unwrap())
+            .resizable(true)
+            .default_width(300.0)
+            .show(ctx, |ui| {
+                self.queue_viewer.draw(ui, &mut self.event_queue);
+            });
+
         egui::Window::new("Call Stack")
             .open(self.window_open_flags.get_mut(&GuiWindow::CallStack).unwrap())
             .resizable(true)
@@ -757,6 +814,22 @@ impl GuiState {
                 self.disassembly_viewer.draw(ui, &mut self.event_queue);
             });             
 
+        egui::Window::new("Markers")
+            .open(self.window_open_flags.get_mut(&GuiWindow::MarkerViewer).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                self.marker_viewer.draw(ui, &mut self.event_queue);
+            });
+
+        egui::Window::new("Watch")
+            .open(self.window_open_flags.get_mut(&GuiWindow::WatchViewer).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                self.watch_viewer.draw(ui, &mut self.event_queue);
+            });
+
         egui::Window::new("IVR Viewer")
             .open(self.window_open_flags.get_mut(&GuiWindow::IvrViewer).unwrap())
             .resizable(true)
@@ -810,6 +883,15 @@ impl GuiState {
                 self.pic_viewer.draw(ui, &mut self.event_queue);
             });           
             
+        egui::Window::new("EMS View")
+            .open(self.window_open_flags.get_mut(&GuiWindow::EmsViewer).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+
+                self.ems_viewer.draw(ui, &mut self.event_queue);
+            });
+
         egui::Window::new("PPI View")
             .open(self.window_open_flags.get_mut(&GuiWindow::PpiViewer).unwrap())
             .resizable(true)
@@ -852,9 +934,71 @@ impl GuiState {
                     ui.label(egui::RichText::new("Port C Value: ").text_style(egui::TextStyle::Monospace));
                     ui.add(egui::TextEdit::singleline(&mut self.ppi_state.port_c_value).font(egui::TextStyle::Monospace));
                     ui.end_row();
+
+                    ui.label(egui::RichText::new("Timer 2 Gate: ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.timer2_gate).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("Speaker Data: ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.speaker_data).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("Timer 2 Out:  ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.timer2_out).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("Speaker Out:  ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.speaker_out).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("Keyboard Clock:").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.kb_clock).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("DIP SW1:      ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.dip_sw1).font(egui::TextStyle::Monospace));
+                    ui.end_row();
+
+                    ui.label(egui::RichText::new("DIP SW2:      ").text_style(egui::TextStyle::Monospace));
+                    ui.add(egui::TextEdit::singleline(&mut self.ppi_state.dip_sw2).font(egui::TextStyle::Monospace));
+                    ui.end_row();
                 });
             });
 
+        egui::Window::new("FDC View")
+            .open(self.window_open_flags.get_mut(&GuiWindow::FdcViewer).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                let fdc = &self.fdc_state;
+                let rows = [
+                    ("Last command:", format!("{:?}", fdc.last_command)),
+                    ("Drive:", format!("{}", fdc.drive_select)),
+                    ("CHS requested:", format!("{}/{}/{}", fdc.cylinder, fdc.head, fdc.sector)),
+                    ("Sector count:", format!("{}", fdc.sector_count)),
+                    ("Result:", format!("{:?}", fdc.error)),
+                    ("MSR:", format!("{:08b}", fdc.msr)),
+                    ("ST0:", format!("{:08b}", fdc.st0)),
+                    ("ST1:", format!("{:08b}", fdc.st1)),
+                    ("ST2:", format!("{:08b}", fdc.st2)),
+                    ("Reads:", format!("{}", fdc.reads)),
+                    ("Writes:", format!("{}", fdc.writes)),
+                    ("Seeks:", format!("{}", fdc.seeks)),
+                ];
+
+                egui::Grid::new("fdc_view")
+                    .num_columns(2)
+                    .striped(true)
+                    .spacing([40.0, 4.0])
+                    .show(ui, |ui| {
+                        for (label, value) in rows {
+                            ui.label(egui::RichText::new(label).text_style(egui::TextStyle::Monospace));
+                            ui.label(egui::RichText::new(value).text_style(egui::TextStyle::Monospace));
+                            ui.end_row();
+                        }
+                    });
+            });
+
         egui::Window::new("DMA View")
             .open(self.window_open_flags.get_mut(&GuiWindow::DmaViewer).unwrap())
             .resizable(false)
@@ -29,9 +29,9 @@
 use egui::*;
 use egui::plot::{
     Line, 
-    //Plot, 
+    Plot, 
     PlotPoints, 
-    //PlotBounds
+    PlotBounds
 };
 
 use crate::egui::*;
@@ -41,13 +41,12 @@ use crate::egui::constants::*;
 use crate::devices::pit::PitDisplayState;
 use crate::syntax_token::*;
 
-#[allow (dead_code)]
 pub struct PitViewerControl {
 
     pit_state: PitDisplayState,
-    channel_vecs: [Vec<u8>; 3],
-    channel_data: [PlotPoints; 3],
-    channel_lines: [Line; 3]
+    /// Plot lines for each channel's output, built when channel data is updated. 
+    /// Line isn't Clone, so the line is taken when drawn.
+    channel_lines: [Option<Line>; 3]
 }
 
 impl PitViewerControl {
@@ -55,19 +54,7 @@ impl PitViewerControl {
     pub fn new() -> Self {
         Self {
             pit_state: Default::default(),
-            channel_vecs: [
-                Vec::new(), Vec::new(), Vec::new()
-            ],
-            channel_data: [
-                PlotPoints::new(Vec::new()),
-                PlotPoints::new(Vec::new()),
-                PlotPoints::new(Vec::new())
-            ],
-            channel_lines: [
-                Line::new(PlotPoints::new(Vec::new())),
-                Line::new(PlotPoints::new(Vec::new())),
-                Line::new(PlotPoints::new(Vec::new()))
-            ]
+            channel_lines: [None, None, None]
         }
     }
 
@@ -111,32 +98,23 @@ impl PitViewerControl {
                     });
                 });
 
-                /*
-                Plot::new(format!("pit_plot{}", i))
-                .view_aspect(2.0)
-                .width(PIT_VIEWER_WIDTH - 10.0)
-                .height(75.0)
-                .allow_scroll(false)
-                .allow_zoom(false)
-                .show_x(true)
-                .show_y(true)
-                .show(ui, |ui| {
-
-                    ui.set_plot_bounds(PlotBounds::from_min_max([0.0,0.0], [100.0,1.0]));
-
-                    let points: PlotPoints = self.channel_vecs[i].iter().enumerate().map(|i| {
-                        
-                        let x = i.0 as f64;
-            
-                        // Convert u8 to f32
-                        let y = if *i.1 == 1u8 { 1.0 } else { 0.0 };
-            
-                        [x, y]
-                    }).collect();
-
-                    ui.line(Line::new(points));
-                });
-                */
+                // Only channels with output data have a plot
+                if let Some(line) = self.channel_lines[i].take() {
+                    Plot::new(format!("pit_plot{}", i))
+                    .view_aspect(2.0)
+                    .width(PIT_VIEWER_WIDTH - 10.0)
+                    .height(75.0)
+                    .allow_scroll(false)
+                    .allow_zoom(false)
+                    .allow_drag(false)
+                    .allow_boxed_zoom(false)
+                    .show_x(true)
+                    .show_y(true)
+                    .show(ui, |ui| {
+                        ui.set_plot_bounds(PlotBounds::from_min_max([0.0, -0.1], [PIT_VIEWER_PLOT_SAMPLES as f64, 1.1]));
+                        ui.line(line);
+                    });
+                }
             });
 
         }  
@@ -169,16 +147,29 @@ impl PitViewerControl {
         self.pit_state = new_pit_state;
     }
 
+    /// Update the output plot for a channel from a buffer of output samples, where each 
+    /// sample is 0 or 1. Only the most recent PIT_VIEWER_PLOT_SAMPLES samples are shown.
     pub fn update_channel_data(&mut self, channel: usize, data: &[u8]) {
 
-        self.channel_vecs[channel] = data.to_vec();
+        if channel >= self.channel_lines.len() {
+            return;
+        }
 
+        let start = data.len().saturating_sub(PIT_VIEWER_PLOT_SAMPLES);
 
+        // Emit two points per sample so that transitions are drawn as vertical edges,
+        // giving a square wave rather than a sawtooth.
+        let mut points = Vec::with_capacity((data.len() - start) * 2);
+        let mut last_y = None;
+        for (x, sample) in data[start..].iter().enumerate() {
+            let y = if *sample != 0 { 1.0 } else { 0.0 };
+            if let Some(last_y) = last_y {
+                points.push([x as f64, last_y]);
+            }
+            points.push([x as f64, y]);
+            last_y = Some(y);
+        }
 
-        //self.channel_data[channel] = points;
-        //self.channel_lines[channel] = Line::new(points);
+        self.channel_lines[channel] = Some(Line::new(PlotPoints::new(points)));
     }
-    
-
 }
-
@@ -0,0 +1,116 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    egui::queue_viewer.rs
+
+    Implements a viewer control for the CPU's instruction prefetch queue,
+    showing the queued bytes, the queue length and the next fetch address.
+    Bytes flash when they enter the queue, so a flush is visible as the
+    whole queue refilling.
+
+*/
+
+use egui::*;
+
+use crate::egui::*;
+use crate::egui::color::*;
+use crate::syntax_token::*;
+
+pub struct QueueViewerControl {
+    bytes: Vec<SyntaxToken>,
+    length: SyntaxToken,
+    next_fetch: SyntaxToken,
+}
+
+impl QueueViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            length: SyntaxToken::StateString(String::new(), false, TOKEN_MAX_AGE),
+            next_fetch: SyntaxToken::StateString(String::new(), false, TOKEN_MAX_AGE),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            if self.bytes.is_empty() {
+                ui.label(egui::RichText::new("Empty").text_style(egui::TextStyle::Monospace));
+            }
+            for token in &self.bytes {
+                if let SyntaxToken::MemoryByteHexValue(_, _, text, _, age) = token {
+                    ui.label(
+                        egui::RichText::new(text)
+                            .text_style(egui::TextStyle::Monospace)
+                            .color(fade_c32(Color32::GRAY, STATUS_UPDATE_COLOR, 255-*age))
+                    );
+                }
+            }
+        });
+        ui.separator();
+
+        egui::Grid::new("queue_view")
+            .num_columns(2)
+            .striped(true)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                for (label, token) in [("Length:", &self.length), ("Next fetch:", &self.next_fetch)] {
+                    if let SyntaxToken::StateString(text, _, age) = token {
+                        ui.label(egui::RichText::new(label).text_style(egui::TextStyle::Monospace));
+                        ui.label(
+                            egui::RichText::new(text)
+                                .text_style(egui::TextStyle::Monospace)
+                                .color(fade_c32(Color32::GRAY, STATUS_UPDATE_COLOR, 255-*age))
+                        );
+                        ui.end_row();
+                    }
+                }
+            });
+    }
+
+    /// Update the queue contents from the tokens returned by Cpu::queue_tokens(). A byte is new 
+    /// if its address wasn't in the queue before, so every byte is new after a flush.
+    pub fn update_state(&mut self, mut tokens: Vec<SyntaxToken>) {
+
+        let next_fetch = tokens.pop();
+        let length = tokens.pop();
+
+        for token in tokens.iter_mut() {
+            if let SyntaxToken::MemoryByteHexValue(addr, _, _, _, age) = token {
+                let old_age = self.bytes.iter().find_map(|old| match old {
+                    SyntaxToken::MemoryByteHexValue(old_addr, _, _, _, old_age) if old_addr == addr => Some(*old_age),
+                    _ => None
+                });
+                *age = match old_age {
+                    Some(old_age) => old_age.saturating_add(2),
+                    None => 0
+                };
+            }
+        }
+        self.bytes = tokens;
+
+        for (new, old) in [(length, &mut self.length), (next_fetch, &mut self.next_fetch)] {
+            if let (Some(SyntaxToken::StateString(text, _, _)), SyntaxToken::StateString(old_text, _, old_age)) = (new, old) {
+                *old_age = if text == *old_text { old_age.saturating_add(2) } else { 0 };
+                *old_text = text;
+            }
+        }
+    }
+}
@@ -27,7 +27,12 @@
     visible window. Contents are constructed from vectors of syntax tokens
     to enable color syntax highlighting, hover tooltips and other features.
 
+    Memory value tokens are normally colored by age, so that recently 
+    changed values flash. A baseline can be supplied to instead color 
+    values by whether they differ from the baseline.
+
 */
+use std::collections::HashMap;
 use std::mem::discriminant;
 
 use egui::*;
@@ -50,6 +55,22 @@ pub struct TokenListView {
     pub t_margin: f32,
 
     hover_text: String,
+    /// Optional per-row tooltips, shown when hovering over a row's Mnemonic token
+    row_tooltips: Vec<Option<String>>,
+    /// Address and text of the byte currently being edited, if any
+    edit: Option<(u32, String)>,
+    /// Row to scroll the view to on the next draw, if any
+    scroll_target: Option<usize>,
+    /// Byte values by address from the baseline contents
+    baseline: HashMap<u32, u8>,
+    /// Color memory values by difference from the baseline instead of by age
+    diff_mode: bool,
+    /// Radix used to draw numeric value tokens
+    radix: ValueRadix,
+    /// Row highlighted as selected, if any
+    selected_row: Option<usize>,
+    /// Row whose address token was clicked during the last draw, if any
+    clicked_row: Option<usize>,
 }
 
 impl TokenListView {
@@ -66,7 +87,15 @@ impl TokenListView {
             l_margin: 5.0,
             t_margin: 3.0,
 
-            hover_text: String::new()
+            hover_text: String::new(),
+            row_tooltips: Vec::new(),
+            edit: None,
+            scroll_target: None,
+            baseline: HashMap::new(),
+            diff_mode: false,
+            radix: ValueRadix::Hex,
+            selected_row: None,
+            clicked_row: None,
         }
     }
 
@@ -78,6 +107,12 @@ impl TokenListView {
         self.max_rows = size;
     }
 
+    /// Scroll the view so that the row containing the specified address is at the top.
+    /// The address is rounded down to a 16 byte row boundary.
+    pub fn scroll_to(&mut self, address: usize) {
+        self.scroll_target = Some(address & !0x0F);
+    }
+
     pub fn set_contents(&mut self, mut contents: Vec<Vec<SyntaxToken>>) {
 
         if self.contents.len() != contents.len() {
@@ -87,7 +122,9 @@ impl TokenListView {
                 for mut token in row {
 
                     match &mut token {
-                        SyntaxToken::MemoryByteHexValue(_,_,_,_,new_age) => {
+                        SyntaxToken::MemoryByteHexValue(_,_,_,_,new_age) 
+                        | SyntaxToken::MemoryWordHexValue(_,_,_,_,new_age)
+                        | SyntaxToken::MemoryByteDecimalValue(_,_,_,_,new_age) => {
                             *new_age = TOKEN_MAX_AGE;
                         }
                         SyntaxToken::MemoryByteAsciiValue(_,_,_,new_age) => {
@@ -126,6 +163,29 @@ impl TokenListView {
                                 *new_age = 255;
                             }
                         }
+                        (SyntaxToken::MemoryWordHexValue(new_addr,new_val,_,_,new_age), SyntaxToken::MemoryWordHexValue(old_addr,old_val,_,_,old_age)) => {
+                            if old_addr == new_addr {
+                                // This is the same word as before. Compare values.
+                                if old_val == new_val {
+                                    // Word hasn't changed, so increment age.
+                                    *new_age = old_age.saturating_add(2);    
+                                }
+                            }
+                            else {
+                                // Different word address in this position. Set age to maximum so it doesn't flash.
+                                *new_age = 255;
+                            }
+                        }
+                        (SyntaxToken::MemoryByteDecimalValue(new_addr,new_val,_,_,new_age), SyntaxToken::MemoryByteDecimalValue(old_addr,old_val,_,_,old_age)) => {
+                            if old_addr == new_addr {
+                                if old_val == new_val {
+                                    *new_age = old_age.saturating_add(2);    
+                                }
+                            }
+                            else {
+                                *new_age = 255;
+                            }
+                        }
                         (SyntaxToken::MemoryByteAsciiValue(new_addr,new_val,_,new_age), SyntaxToken::MemoryByteAsciiValue(old_addr,old_val,_,old_age)) => {
                             if old_addr == new_addr {
                                 // This is the same byte as before. Compare values.
@@ -148,10 +208,145 @@ impl TokenListView {
         self.contents = contents;
     }
 
+    /// Set the baseline that memory values are compared against in diff mode. Only the 
+    /// addresses of memory value tokens in the baseline are compared; values at other 
+    /// addresses are not highlighted.
+    pub fn set_baseline(&mut self, baseline: &[Vec<SyntaxToken>]) {
+        self.baseline.clear();
+        for token in baseline.iter().flatten() {
+            match token {
+                SyntaxToken::MemoryByteHexValue(addr, value, ..)
+                | SyntaxToken::MemoryByteDecimalValue(addr, value, ..)
+                | SyntaxToken::MemoryByteAsciiValue(addr, value, ..) => {
+                    self.baseline.insert(*addr, *value);
+                }
+                SyntaxToken::MemoryWordHexValue(addr, value, ..) => {
+                    self.baseline.insert(*addr, *value as u8);
+                    self.baseline.insert(addr.wrapping_add(1), (*value >> 8) as u8);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn clear_baseline(&mut self) {
+        self.baseline.clear();
+    }
+
+    pub fn has_baseline(&self) -> bool {
+        !self.baseline.is_empty()
+    }
+
+    pub fn set_diff_mode(&mut self, state: bool) {
+        self.diff_mode = state;
+    }
+
+    /// Set the radix used to draw HexValue and Displacement tokens.
+    pub fn set_radix(&mut self, radix: ValueRadix) {
+        self.radix = radix;
+    }
+
+    /// Return the color for 'len' bytes of 'value' at 'addr'. In diff mode this is based on
+    /// whether the value differs from the baseline, otherwise the provided age color is used.
+    fn value_color(&self, addr: u32, value: u16, len: u32, age_color: Color32) -> Color32 {
+        if !self.diff_mode {
+            return age_color
+        }
+
+        let mut in_baseline = false;
+        let mut changed = false;
+        for n in 0..len {
+            if let Some(base_byte) = self.baseline.get(&addr.wrapping_add(n)) {
+                in_baseline = true;
+                changed |= *base_byte != (value >> (n * 8)) as u8;
+            }
+        }
+
+        match (in_baseline, changed) {
+            (false, _) => Color32::GRAY,
+            (true, true) => COLOR32_DIFF_CHANGED,
+            (true, false) => COLOR32_DIFF_UNCHANGED
+        }
+    }
+
+    pub fn set_selected_row(&mut self, row: Option<usize>) {
+        self.selected_row = row;
+    }
+
+    /// Return the row whose address was clicked since the last call, if any.
+    pub fn take_clicked_row(&mut self) -> Option<usize> {
+        self.clicked_row.take()
+    }
+
     pub fn set_hover_text(&mut self, text: String) {
         self.hover_text = text;
     }
 
+    /// Set tooltips for each row of the current contents. Rows without a tooltip, or beyond
+    /// the end of the provided vector, show no tooltip.
+    pub fn set_row_tooltips(&mut self, tooltips: Vec<Option<String>>) {
+        self.row_tooltips = tooltips;
+    }
+
+    /// Return the visible rows as plain text, one line per row. Columns are aligned with spaces
+    /// the way draw() aligns them, and values are formatted in the current radix.
+    pub fn to_plaintext(&self) -> String {
+        let show_rows = usize::min(self.visible_rows, self.contents.len());
+        let mut text = String::new();
+
+        for row in &self.contents[0..show_rows] {
+            let mut line = String::new();
+            for token in row {
+                match token {
+                    SyntaxToken::MemoryAddressFlat(_, s)
+                    | SyntaxToken::MemoryAddressSeg16(_, _, s)
+                    | SyntaxToken::MicrocodeAddress(_, s)
+                    | SyntaxToken::Prefix(s) => {
+                        line.push_str(s);
+                        line.push(' ');
+                    }
+                    SyntaxToken::MemoryByteHexValue(_, _, s, _, _)
+                    | SyntaxToken::MemoryWordHexValue(_, _, s, _, _)
+                    | SyntaxToken::MemoryByteDecimalValue(_, _, s, _, _)
+                    | SyntaxToken::MemoryUnmappedValue(_, _, s, _) => {
+                        line.push_str(s);
+                        line.push(' ');
+                    }
+                    SyntaxToken::InstructionBytes(s) => {
+                        line.push_str(&format!("{:width$} ", s, width = DISASSEMBLY_BYTES_COLUMN_WIDTH * 2));
+                    }
+                    SyntaxToken::Mnemonic(s) => {
+                        line.push_str(&format!("{:6} ", s));
+                    }
+                    SyntaxToken::Displacement(value, width, s) | SyntaxToken::HexValue(value, width, s) => {
+                        line.push_str(&self.radix.format(*value, *width).unwrap_or_else(|| s.clone()));
+                    }
+                    SyntaxToken::MemoryByteAsciiValue(_, _, s, _)
+                    | SyntaxToken::MemoryUnmappedAscii(_, s)
+                    | SyntaxToken::Register(s)
+                    | SyntaxToken::Segment(s)
+                    | SyntaxToken::Symbol(s)
+                    | SyntaxToken::Text(s)
+                    | SyntaxToken::ErrorString(s)
+                    | SyntaxToken::ErrorText(s) => line.push_str(s),
+                    SyntaxToken::OpenBracket => line.push('['),
+                    SyntaxToken::CloseBracket => line.push(']'),
+                    SyntaxToken::Colon => line.push(':'),
+                    SyntaxToken::Comma => line.push_str(", "),
+                    SyntaxToken::PlusSign => line.push('+'),
+                    SyntaxToken::Comment(s) => {
+                        line.push_str(" ; ");
+                        line.push_str(s);
+                    }
+                    _ => {}
+                }
+            }
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
     pub fn measure_token(&self, ui: &mut Ui, token: &SyntaxToken, fontid: FontId ) -> Rect {
 
         let old_clip_rect = ui.clip_rect();
@@ -162,6 +357,7 @@ impl TokenListView {
             egui::Align2::LEFT_TOP,
             match token {
                 SyntaxToken::MemoryByteHexValue(_, _, s, _, _) => s.clone(),
+                SyntaxToken::InstructionBytes(s) => s.clone(),
                 _ => "0".to_string()
             },
             fontid,
@@ -172,6 +368,42 @@ impl TokenListView {
         r
     }
 
+    /// Draw an inline editor for the byte being edited. Enter commits a valid hex value by
+    /// sending GuiEvent::MemoryWrite; Escape or clicking away reverts it.
+    fn draw_byte_edit(edit: &mut Option<(u32, String)>, ui: &mut egui::Ui, rect: Rect, events: &mut VecDeque<GuiEvent>) {
+
+        let (addr, text) = match edit {
+            Some((addr, text)) => (*addr, text),
+            None => return
+        };
+
+        let response = ui.put(
+            rect,
+            egui::TextEdit::singleline(text)
+                .font(egui::TextStyle::Monospace)
+                .frame(false)
+        );
+        response.request_focus();
+
+        // Only hex digits are accepted, up to one byte
+        text.retain(|c| c.is_ascii_hexdigit());
+        text.truncate(2);
+
+        if ui.input().key_pressed(egui::Key::Escape) {
+            *edit = None;
+        }
+        else if ui.input().key_pressed(egui::Key::Enter) {
+            // Invalid or empty input leaves the editor open
+            if let Ok(value) = u8::from_str_radix(text, 16) {
+                events.push_back(GuiEvent::MemoryWrite(addr as usize, value));
+                *edit = None;
+            }
+        }
+        else if response.clicked_elsewhere() {
+            *edit = None;
+        }
+    }
+
     pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent>, new_row: &mut usize) {
 
         let font_id = egui::TextStyle::Monospace.resolve(ui.style());
@@ -191,8 +423,14 @@ impl TokenListView {
             egui::Color32::BLACK
         );
 
-        egui::ScrollArea::vertical()
-            .auto_shrink([false; 2])
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false; 2]);
+        let scroll_target = self.scroll_target.take();
+        if let Some(target) = scroll_target {
+            // Offset into the middle of the row so rounding never lands on the previous row.
+            scroll_area = scroll_area.vertical_scroll_offset((target as f32 + 0.5) * row_height);
+        }
+
+        scroll_area
             .show_viewport(ui, |ui, viewport| {
 
                 ui.set_height(row_height * num_rows as f32);
@@ -208,7 +446,12 @@ impl TokenListView {
 
                 self.row = first_item;
 
-                if self.row != self.previous_row {
+                if let Some(target) = scroll_target {
+                    // View was scrolled by scroll_to(), not by the user.
+                    self.row = target;
+                    self.previous_row = target;
+                }
+                else if self.row != self.previous_row {
                     // View was scrolled, update address
                     
                     *new_row = self.row & !0x0F;
@@ -231,6 +474,14 @@ impl TokenListView {
                         font_id.clone()
                     );
 
+                // Measure the size of the instruction bytes column.
+                let bytes_rect =
+                    self.measure_token(
+                        ui,
+                        &SyntaxToken::InstructionBytes("00".repeat(DISASSEMBLY_BYTES_COLUMN_WIDTH)),
+                        font_id.clone()
+                    );
+
                 let l_bracket = "[".to_string();
                 let r_bracket = "]".to_string();
                 let colon = ":".to_string();
@@ -244,8 +495,9 @@ impl TokenListView {
 
                     let mut token_x = x;
 
-                    let mut column_select = 32; // Initial value out of range to not highlight anything
-                    for (j, token) in row.iter().enumerate() {
+                    // Address range of the hovered value token, used to highlight the corresponding ascii bytes
+                    let mut hover_range: Option<std::ops::Range<u32>> = None;
+                    for token in row.iter() {
 
                         let mut text_rect;
 
@@ -259,34 +511,81 @@ impl TokenListView {
                                     font_id.clone(),
                                     Color32::LIGHT_GRAY,
                                 );
+
+                                if ui.interact(text_rect, ui.id().with(("row_select", i)), Sense::click()).clicked() {
+                                    self.clicked_row = Some(i);
+                                }
+                                if self.selected_row == Some(i) {
+                                    ui.painter().rect(
+                                        text_rect.expand(2.0),
+                                        egui::Rounding::none(),
+                                        Color32::TRANSPARENT,
+                                        egui::Stroke::new(1.0, COLOR32_CYAN)
+                                    );
+                                }
                                 token_x = text_rect.max.x + 10.0;
                                 used_rect = used_rect.union(text_rect);
                                 drawn = true;
                             }
-                            SyntaxToken::MemoryByteHexValue(addr, _, s, cursor, age) => {
+                            SyntaxToken::MemoryByteHexValue(addr, _, s, cursor, age)
+                            | SyntaxToken::MemoryWordHexValue(addr, _, s, cursor, age)
+                            | SyntaxToken::MemoryByteDecimalValue(addr, _, s, cursor, age) => {
+
+                                // Size the label by the width of the formatted value, based on the measured
+                                // width of a two-digit hex byte.
+                                let label_width = label_rect.max.x * (s.len() as f32 / 2.0);
+                                let (value, value_bytes) = match token {
+                                    SyntaxToken::MemoryWordHexValue(_, value, ..) => (*value, 2),
+                                    SyntaxToken::MemoryByteHexValue(_, value, ..)
+                                    | SyntaxToken::MemoryByteDecimalValue(_, value, ..) => (*value as u16, 1),
+                                    _ => (0, 1)
+                                };
+                                let value_color = self.value_color(
+                                    *addr,
+                                    value,
+                                    value_bytes,
+                                    fade_c32(Color32::GRAY, Color32::from_rgb(0, 255, 255), 255-*age)
+                                );
 
-                                if ui.put(
-                                    Rect {
-                                        min: egui::pos2(token_x, y), 
-                                        max: egui::pos2(token_x + label_rect.max.x + 1.0, y + label_rect.max.y)
-                                    },
-                                    egui::Label::new(
-                                        egui::RichText::new(s)
-                                            .text_style(egui::TextStyle::Monospace)
-                                            .color(fade_c32(Color32::GRAY, Color32::from_rgb(0, 255, 255), 255-*age))
-                                        )
-                                )
-                                .on_hover_text(format!("{}", self.hover_text))
-                                .hovered() {
-                                    column_select = j;
-                                    events.push_back(GuiEvent::TokenHover(*addr as usize));
+                                let value_rect = Rect {
+                                    min: egui::pos2(token_x, y), 
+                                    max: egui::pos2(token_x + label_width + 1.0, y + label_rect.max.y)
+                                };
+
+                                let editing = matches!(self.edit, Some((edit_addr, _)) if edit_addr == *addr)
+                                    && matches!(token, SyntaxToken::MemoryByteHexValue(..));
+
+                                if editing {
+                                    TokenListView::draw_byte_edit(&mut self.edit, ui, value_rect, events);
+                                }
+                                else {
+                                    let response = ui.put(
+                                        value_rect,
+                                        egui::Label::new(
+                                            egui::RichText::new(s)
+                                                .text_style(egui::TextStyle::Monospace)
+                                                .color(value_color)
+                                            )
+                                            .sense(Sense::click())
+                                    )
+                                    .on_hover_text(format!("{}", self.hover_text));
+
+                                    if response.hovered() {
+                                        hover_range = Some(*addr..*addr + value_bytes);
+                                        events.push_back(GuiEvent::TokenHover(*addr as usize));
+                                    }
+
+                                    // Clicking a hex byte starts editing it
+                                    if response.clicked() && matches!(token, SyntaxToken::MemoryByteHexValue(..)) {
+                                        self.edit = Some((*addr, s.clone()));
+                                    }
                                 }
 
                                 if *cursor {
                                     ui.painter().rect(
                                         Rect {
                                             min: egui::pos2(token_x, y), 
-                                            max: egui::pos2(token_x + label_rect.max.x + 1.0, y + label_rect.max.y)
+                                            max: egui::pos2(token_x + label_width + 1.0, y + label_rect.max.y)
                                         },
                                         egui::Rounding::none(),
                                         Color32::TRANSPARENT,
@@ -294,7 +593,7 @@ impl TokenListView {
                                     );                                    
                                 }
 
-                                token_x += label_rect.max.x + 7.0;
+                                token_x += label_width + 7.0;
                                 drawn = true;
                                 /*
                                 text_rect = ui.painter().text(
@@ -308,19 +607,51 @@ impl TokenListView {
                                 used_rect = used_rect.union(text_rect);
                                 */
                             }
-                            SyntaxToken::MemoryByteAsciiValue(_addr, _, s, age) => {
+                            SyntaxToken::MemoryUnmappedValue(addr, len, s, cursor) => {
+                                let label_width = label_rect.max.x * (s.len() as f32 / 2.0);
+                                let value_rect = Rect {
+                                    min: egui::pos2(token_x, y), 
+                                    max: egui::pos2(token_x + label_width + 1.0, y + label_rect.max.y)
+                                };
+
+                                // Unmapped bytes can't be edited, but are still linked to the ascii column
+                                let response = ui.put(
+                                    value_rect,
+                                    egui::Label::new(
+                                        egui::RichText::new(s)
+                                            .text_style(egui::TextStyle::Monospace)
+                                            .color(COLOR32_UNMAPPED)
+                                        )
+                                        .sense(Sense::hover())
+                                );
+
+                                if response.hovered() {
+                                    hover_range = Some(*addr..*addr + *len as u32);
+                                    events.push_back(GuiEvent::TokenHover(*addr as usize));
+                                }
+
+                                if *cursor {
+                                    ui.painter().rect(
+                                        value_rect,
+                                        egui::Rounding::none(),
+                                        Color32::TRANSPARENT,
+                                        egui::Stroke::new(1.0, Color32::WHITE)
+                                    );
+                                }
+
+                                token_x += label_width + 7.0;
+                                drawn = true;
+                            }
+                            SyntaxToken::MemoryUnmappedAscii(addr, s) => {
                                 text_rect = ui.painter().text(
                                     egui::pos2(token_x, y),
                                     egui::Align2::LEFT_TOP,
                                     s,
                                     font_id.clone(),
-                                    fade_c32(Color32::LIGHT_GRAY, Color32::from_rgb(0, 255, 255), 255-*age),
+                                    COLOR32_UNMAPPED,
                                 );
 
-                                // If previous hex byte was hovered, show a rectangle around this ascii byte
-                                // TODO: Rather than rely on hex bytes directly preceding the ascii bytes, 
-                                // use an 'index' field in the enum?
-                                if (j - 16) == column_select {
+                                if hover_range.as_ref().map_or(false, |r| r.contains(addr)) {
                                     ui.painter().rect(
                                         text_rect.expand(2.0),
                                         egui::Rounding::none(),
@@ -333,6 +664,52 @@ impl TokenListView {
                                 used_rect = used_rect.union(text_rect);
                                 drawn = true;
                             }
+                            SyntaxToken::MemoryByteAsciiValue(addr, value, s, age) => {
+                                // The ascii column mirrors the highlighting of the value columns
+                                let ascii_color = self.value_color(
+                                    *addr,
+                                    *value as u16,
+                                    1,
+                                    fade_c32(Color32::LIGHT_GRAY, Color32::from_rgb(0, 255, 255), 255-*age)
+                                );
+                                text_rect = ui.painter().text(
+                                    egui::pos2(token_x, y),
+                                    egui::Align2::LEFT_TOP,
+                                    s,
+                                    font_id.clone(),
+                                    ascii_color,
+                                );
+
+                                // If the value token for this byte was hovered, show a rectangle around this ascii byte
+                                if hover_range.as_ref().map_or(false, |r| r.contains(addr)) {
+                                    ui.painter().rect(
+                                        text_rect.expand(2.0),
+                                        egui::Rounding::none(),
+                                        Color32::TRANSPARENT,
+                                        egui::Stroke::new(1.0, COLOR32_CYAN)
+                                    );
+                                }
+
+                                token_x = text_rect.max.x + 2.0;
+                                used_rect = used_rect.union(text_rect);
+                                drawn = true;
+                            }
+                            SyntaxToken::InstructionBytes(s) => {
+                                text_rect = ui.painter().text(
+                                    egui::pos2(token_x, y),
+                                    egui::Align2::LEFT_TOP,
+                                    s,
+                                    font_id.clone(),
+                                    Color32::from_rgb(6, 152, 255),
+                                );
+
+                                // Pad the bytes to a fixed width column so that mnemonics line up. Longer
+                                // instructions push the mnemonic to the right.
+                                let column_end = token_x + bytes_rect.width();
+                                token_x = f32::max(text_rect.max.x, column_end) + 6.0;
+                                used_rect = used_rect.union(text_rect);
+                                drawn = true;
+                            }
                             SyntaxToken::Mnemonic(s) => {
                                 text_rect = ui.painter().text(
                                     egui::pos2(token_x, y),
@@ -341,6 +718,12 @@ impl TokenListView {
                                     font_id.clone(),
                                     Color32::from_rgb(128, 255, 158),
                                 );
+
+                                if let Some(Some(tooltip)) = self.row_tooltips.get(i) {
+                                    ui.interact(text_rect, ui.id().with(("row_tooltip", i)), Sense::hover())
+                                        .on_hover_text(tooltip);
+                                }
+
                                 token_x = text_rect.min.x + 45.0;
                                 used_rect = used_rect.union(text_rect);
                                 drawn = true;
@@ -352,13 +735,12 @@ impl TokenListView {
 
                         if !drawn { 
                             
+                            let radix_text: String;
+                            let comment_text: String;
                             let (token_color, token_text, token_padding) = match token {
                                 SyntaxToken::MemoryAddressSeg16(_,_,s) => {
                                     (Color32::LIGHT_GRAY, s, 10.0) 
                                 }
-                                SyntaxToken::InstructionBytes(s) => {
-                                    (Color32::from_rgb(6, 152, 255), s, 1.0)
-                                }
                                 SyntaxToken::Prefix(s) => {
                                     (Color32::from_rgb(116, 228, 227), s, 2.0)
                                 }
@@ -380,8 +762,19 @@ impl TokenListView {
                                 SyntaxToken::PlusSign => {
                                     (Color32::LIGHT_GRAY, &plus, 1.0) 
                                 }                                                              
-                                SyntaxToken::Displacement(s) | SyntaxToken::HexValue(s) => {
-                                    (Color32::from_rgb(96, 200, 210), s, 2.0)
+                                SyntaxToken::Displacement(value, width, s) | SyntaxToken::HexValue(value, width, s) => {
+                                    radix_text = self.radix.format(*value, *width).unwrap_or_else(|| s.clone());
+                                    (Color32::from_rgb(96, 200, 210), &radix_text, 2.0)
+                                }
+                                SyntaxToken::Symbol(s) => {
+                                    (Color32::from_rgb(240, 200, 80), s, 2.0)
+                                }
+                                SyntaxToken::MicrocodeAddress(_, s) => {
+                                    (Color32::from_rgb(180, 140, 230), s, 10.0)
+                                }
+                                SyntaxToken::Comment(s) => {
+                                    comment_text = format!("; {}", s);
+                                    (Color32::GRAY, &comment_text, 2.0)
                                 }
                                 SyntaxToken::Segment(s) => {
                                     (Color32::from_rgb(245, 138, 52), s, 1.0)
@@ -389,7 +782,7 @@ impl TokenListView {
                                 SyntaxToken::Text(s) => {
                                     (Color32::LIGHT_GRAY, s, 2.0) 
                                 }
-                                SyntaxToken::ErrorString(s) => {
+                                SyntaxToken::ErrorString(s) | SyntaxToken::ErrorText(s) => {
                                     (Color32::RED, s, 2.0) 
                                 }                                                                                                                                 
                                 _ => (Color32::WHITE, &null, 2.0)
@@ -415,4 +808,64 @@ impl TokenListView {
                 ui.allocate_rect(used_rect, egui::Sense::hover());
             });
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address_tokens(offset: u16) -> Vec<SyntaxToken> {
+        vec![
+            SyntaxToken::MemoryAddressFlat(0x10000 + offset as u32, format!("{:05X}", 0x10000 + offset as u32)),
+            SyntaxToken::MemoryAddressSeg16(0x1000, offset, format!("1000:{:04X}", offset)),
+        ]
+    }
+
+    #[test]
+    fn test_to_plaintext() {
+        let mut row1 = address_tokens(0);
+        row1.extend([
+            SyntaxToken::InstructionBytes("B82100".to_string()),
+            SyntaxToken::Mnemonic("mov".to_string()),
+            SyntaxToken::Register("ax".to_string()),
+            SyntaxToken::Comma,
+            SyntaxToken::HexValue(0x21, 2, "21h".to_string()),
+            SyntaxToken::Comment("function 21h".to_string()),
+        ]);
+        let mut row2 = address_tokens(3);
+        row2.extend([
+            SyntaxToken::InstructionBytes("8B4702".to_string()),
+            SyntaxToken::Mnemonic("mov".to_string()),
+            SyntaxToken::Register("ax".to_string()),
+            SyntaxToken::Comma,
+            SyntaxToken::OpenBracket,
+            SyntaxToken::Register("bx".to_string()),
+            SyntaxToken::PlusSign,
+            SyntaxToken::Displacement(2, 1, "02h".to_string()),
+            SyntaxToken::CloseBracket,
+        ]);
+        let row3 = vec![SyntaxToken::ErrorText("hidden".to_string())];
+
+        let mut tlv = TokenListView::new();
+        tlv.set_capacity(24);
+        tlv.set_visible(2);
+        tlv.set_contents(vec![row1, row2, row3]);
+
+        // Bytes and mnemonics are padded to fixed columns, and only visible rows are included
+        assert_eq!(
+            tlv.to_plaintext(),
+            "10000 1000:0000 B82100       mov    ax, 21h ; function 21h\n\
+             10003 1000:0003 8B4702       mov    ax, [bx+02h]\n"
+        );
+
+        tlv.set_radix(ValueRadix::Octal);
+        assert_eq!(
+            tlv.to_plaintext(),
+            "10000 1000:0000 B82100       mov    ax, 0o41 ; function 21h\n\
+             10003 1000:0003 8B4702       mov    ax, [bx+0o2]\n"
+        );
+
+        // Trailing padding is trimmed
+        tlv.set_contents(vec![address_tokens(5)]);
+        assert_eq!(tlv.to_plaintext(), "10005 1000:0005\n");
+    }
+}
@@ -0,0 +1,114 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    egui::watch_viewer.rs
+
+    Implements a list of watch expressions, such as 'word [ds:1234]', that
+    are evaluated every frame. Values flash when they change.
+
+*/
+
+use egui::*;
+
+use crate::egui::*;
+use crate::egui::color::*;
+use crate::syntax_token::*;
+
+pub struct WatchViewerControl {
+    expression: String,
+    watch_state: Vec<(String, SyntaxToken)>,
+}
+
+impl WatchViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            expression: String::new(),
+            watch_state: Vec::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            ui.label("Expression: ");
+            let response = ui.text_edit_singleline(&mut self.expression);
+            let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+            if ui.add_enabled(!self.expression.is_empty(), egui::Button::new("Add watch")).clicked()
+                || (entered && !self.expression.is_empty())
+            {
+                events.push_back(GuiEvent::AddWatch(self.expression.clone()));
+                self.expression.clear();
+            }
+        });
+        ui.separator();
+
+        egui::Grid::new("watch_view")
+            .num_columns(3)
+            .striped(true)
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+
+                for (i, (text, value)) in self.watch_state.iter().enumerate() {
+                    ui.label(egui::RichText::new(text).text_style(egui::TextStyle::Monospace));
+                    match value {
+                        SyntaxToken::StateString(value_text, _, age) => {
+                            ui.label(
+                                egui::RichText::new(value_text)
+                                    .text_style(egui::TextStyle::Monospace)
+                                    .color(fade_c32(Color32::GRAY, STATUS_UPDATE_COLOR, 255-*age))
+                            );
+                        }
+                        SyntaxToken::ErrorString(e) => {
+                            ui.label(egui::RichText::new(e).color(Color32::RED));
+                        }
+                        _ => {
+                            ui.label("");
+                        }
+                    }
+                    if ui.button("Delete").clicked() {
+                        events.push_back(GuiEvent::DeleteWatch(i));
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Update the watch values. A value that differs from the previous value of the same
+    /// watch is marked as new, otherwise its age is advanced.
+    pub fn update_state(&mut self, mut state: Vec<(String, SyntaxToken)>) {
+
+        for (i, (text, value)) in state.iter_mut().enumerate() {
+            if let SyntaxToken::StateString(value_text, dirty, age) = value {
+                match self.watch_state.get(i) {
+                    Some((old_text, SyntaxToken::StateString(old_value_text, _, old_age))) if old_text == text => {
+                        *dirty = value_text != old_value_text;
+                        *age = if *dirty { 0 } else { old_age.saturating_add(2) };
+                    }
+                    _ => {
+                        // Newly added watches start faded
+                        *age = TOKEN_MAX_AGE;
+                    }
+                }
+            }
+        }
+
+        self.watch_state = state;
+    }
+}
@@ -0,0 +1,359 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    f86.rs
+
+    Parse an 86Box surface (86F) formatted floppy image.
+
+    86F images record each track as the raw bitcells on the disk surface
+    rather than as sector data, preserving the weak bits, non-standard gaps
+    and bad CRCs that copy protection schemes rely on. The file consists of
+    an 8 byte header, a table of track offsets indexed by cylinder and side,
+    and the track records. Each track record holds its flags, an optional
+    extra bitcell count, the bitcell position of the index hole, the
+    bitcells themselves and an optional surface description of the same
+    length.
+
+    Bitcells are stored in 16 bit big-endian words, most significant bit
+    first, unless the disk flags specify reversed byte order.
+
+    Only MFM encoded tracks at fixed RPM are supported. Images using other
+    features are rejected with a list of the features found.
+
+*/
+
+use std::error::Error;
+use core::fmt::Display;
+
+use crate::bitstream::{BitstreamDisk, BitstreamTrack};
+
+pub const F86_SIGNATURE: &[u8] = b"86BF";
+pub const F86_SUPPORTED_MAJOR_VERSION: u8 = 2;
+pub const F86_HEADER_SIZE: usize = 8;
+
+const F86_DISK_SURFACE: u16         = 0b0000_0000_0000_0001;
+const F86_DISK_SIDES: u16           = 0b0000_0000_0000_1000;
+const F86_DISK_WRITE_PROTECT: u16   = 0b0000_0000_0001_0000;
+const F86_DISK_EXTRA_BITCELLS: u16  = 0b0000_0000_1000_0000;
+const F86_DISK_ZONED_RPM_MASK: u16  = 0b0000_0110_0000_0000;
+const F86_DISK_REVERSE_BYTES: u16   = 0b0000_1000_0000_0000;
+
+const F86_TRACK_RATE_MASK: u16      = 0b0000_0000_0000_0111;
+const F86_TRACK_ENCODING_MASK: u16  = 0b0000_0000_0001_1000;
+const F86_TRACK_RPM_MASK: u16       = 0b0000_0000_1110_0000;
+
+const F86_ENCODING_FM: u16 = 0;
+const F86_ENCODING_MFM: u16 = 1;
+const F86_ENCODING_M2FM: u16 = 2;
+
+/// Number of track table entries per side.
+const F86_TRACKS_PER_SIDE: usize = 256;
+
+#[derive (Debug, PartialEq)]
+pub enum F86Error {
+    InvalidHeader,
+    UnsupportedVersion(u8, u8),
+    UnsupportedFeatures(Vec<String>),
+    InvalidTrack,
+    Truncated,
+}
+impl Error for F86Error {}
+impl Display for F86Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            F86Error::InvalidHeader => write!(f, "The 86F header was missing or invalid."),
+            F86Error::UnsupportedVersion(major, minor) => write!(f, "The 86F file is an unsupported version: {}.{:02}", major, minor),
+            F86Error::UnsupportedFeatures(features) => write!(f, "The 86F file uses unsupported features: {}", features.join(", ")),
+            F86Error::InvalidTrack => write!(f, "An 86F track record contained an invalid value."),
+            F86Error::Truncated => write!(f, "The 86F file ended unexpectedly."),
+        }
+    }
+}
+
+/// A single 86F track record.
+pub struct F86Track {
+    pub cylinder: u8,
+    pub head: u8,
+    pub flags: u16,
+    /// Bitcell position of the index hole.
+    pub index: u32,
+    pub bit_len: usize,
+    /// Bitcells, most significant bit first.
+    pub cells: Vec<u8>,
+    pub surface: Option<Vec<u8>>,
+}
+
+fn track_data_rate(flags: u16) -> Option<u32> {
+    match flags & F86_TRACK_RATE_MASK {
+        0 => Some(500),
+        1 => Some(300),
+        2 => Some(250),
+        3 => Some(1000),
+        _ => None
+    }
+}
+
+fn track_rpm(flags: u16) -> Option<u32> {
+    match (flags & F86_TRACK_RPM_MASK) >> 5 {
+        0 => Some(300),
+        1 => Some(360),
+        _ => None
+    }
+}
+
+impl F86Track {
+    /// Return the data rate of the track in kilobits per second.
+    pub fn data_rate(&self) -> Option<u32> {
+        track_data_rate(self.flags)
+    }
+
+    /// Return the nominal number of bitcells in one revolution, for MFM encoding.
+    fn nominal_bit_len(flags: u16) -> Option<usize> {
+        let rate = track_data_rate(flags)? as usize;
+        let rpm = track_rpm(flags)? as usize;
+        // Two bitcells per data bit
+        Some(rate * 1000 * 2 * 60 / rpm)
+    }
+}
+
+pub struct F86Image {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub flags: u16,
+    pub heads: u8,
+    pub tracks: Vec<F86Track>,
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, F86Error> {
+    match data.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(F86Error::Truncated)
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, F86Error> {
+    match data.get(pos..pos + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(F86Error::Truncated)
+    }
+}
+
+impl F86Image {
+
+    pub fn parse(data: &[u8]) -> Result<F86Image, F86Error> {
+
+        if !data.starts_with(F86_SIGNATURE) || data.len() < F86_HEADER_SIZE {
+            return Err(F86Error::InvalidHeader);
+        }
+
+        let minor_version = data[4];
+        let major_version = data[5];
+        if major_version != F86_SUPPORTED_MAJOR_VERSION {
+            return Err(F86Error::UnsupportedVersion(major_version, minor_version));
+        }
+
+        let flags = read_u16(data, 6)?;
+        let heads = if flags & F86_DISK_SIDES != 0 { 2 } else { 1 };
+
+        // Collect every unsupported feature before failing, so the user can see all of them
+        let mut unsupported = Vec::new();
+        if flags & F86_DISK_ZONED_RPM_MASK != 0 {
+            unsupported.push(format!("zoned RPM (type {})", (flags & F86_DISK_ZONED_RPM_MASK) >> 9));
+        }
+
+        let mut tracks = Vec::new();
+        for entry in 0..F86_TRACKS_PER_SIDE * heads as usize {
+            let offset = read_u32(data, F86_HEADER_SIZE + entry * 4)? as usize;
+            if offset == 0 {
+                continue;
+            }
+
+            let cylinder = (entry / heads as usize) as u8;
+            let head = (entry % heads as usize) as u8;
+            let mut pos = offset;
+
+            let track_flags = read_u16(data, pos)?;
+            pos += 2;
+
+            match (track_flags & F86_TRACK_ENCODING_MASK) >> 3 {
+                F86_ENCODING_MFM => {}
+                F86_ENCODING_FM => unsupported.push("FM encoding".to_string()),
+                F86_ENCODING_M2FM => unsupported.push("M2FM encoding".to_string()),
+                _ => unsupported.push("GCR encoding".to_string()),
+            }
+
+            let nominal = match F86Track::nominal_bit_len(track_flags) {
+                Some(nominal) => nominal as i64,
+                None => {
+                    unsupported.push(format!("track flags {:04X}", track_flags));
+                    continue;
+                }
+            };
+
+            let extra = if flags & F86_DISK_EXTRA_BITCELLS != 0 {
+                let extra = read_u32(data, pos)? as i32;
+                pos += 4;
+                extra as i64
+            }
+            else {
+                0
+            };
+
+            let index = read_u32(data, pos)?;
+            pos += 4;
+
+            let bit_len = nominal + extra;
+            if bit_len <= 0 {
+                return Err(F86Error::InvalidTrack);
+            }
+            let bit_len = bit_len as usize;
+
+            // Bitcells are stored in whole words
+            let len = (bit_len + 15) / 16 * 2;
+            let read_cells = |pos: usize| -> Result<Vec<u8>, F86Error> {
+                let mut cells = data.get(pos..pos + len).ok_or(F86Error::Truncated)?.to_vec();
+                if flags & F86_DISK_REVERSE_BYTES != 0 {
+                    cells.chunks_exact_mut(2).for_each(|word| word.swap(0, 1));
+                }
+                Ok(cells)
+            };
+
+            let cells = read_cells(pos)?;
+            let surface = match flags & F86_DISK_SURFACE != 0 {
+                true => Some(read_cells(pos + len)?),
+                false => None
+            };
+
+            tracks.push(F86Track {
+                cylinder,
+                head,
+                flags: track_flags,
+                index,
+                bit_len,
+                cells,
+                surface
+            });
+        }
+
+        if !unsupported.is_empty() {
+            unsupported.sort();
+            unsupported.dedup();
+            return Err(F86Error::UnsupportedFeatures(unsupported));
+        }
+
+        Ok(F86Image {
+            major_version,
+            minor_version,
+            flags,
+            heads,
+            tracks
+        })
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        self.flags & F86_DISK_WRITE_PROTECT != 0
+    }
+
+    /// Convert the image into a bitstream disk for the FDC.
+    pub fn into_bitstream(self) -> BitstreamDisk {
+        let cylinders = self.tracks.iter().map(|t| t.cylinder as usize + 1).max().unwrap_or(0).min(u8::MAX as usize);
+
+        let mut disk = BitstreamDisk::new(cylinders as u8, self.heads);
+        disk.write_protected = self.is_write_protected();
+
+        for track in self.tracks {
+            disk.set_track(
+                track.cylinder,
+                track.head,
+                BitstreamTrack::new(track.cells, track.bit_len, track.index as usize, track.surface)
+            );
+        }
+        disk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitstream::{DecodedSector, SectorId};
+
+    const TEST_TRACK_FLAGS: u16 = 0x000A; // 250Kbps, MFM, 300RPM
+
+    /// Build a single sided 86F image with one track at 250Kbps, 300RPM. The fifth sector
+    /// has a bad data CRC.
+    fn test_image(disk_flags: u16, track_flags: u16) -> Vec<u8> {
+        let sectors: Vec<DecodedSector> = (1..=9u8).map(|r| DecodedSector {
+            id: SectorId { c: 0, h: 0, r, n: 2 },
+            id_crc_ok: true,
+            data: Some(vec![r; 512]),
+            data_crc_ok: r != 5,
+            deleted: false,
+        }).collect();
+        let track = BitstreamTrack::encode(&sectors, 100_000);
+
+        let mut img = F86_SIGNATURE.to_vec();
+        img.extend_from_slice(&[0x0C, F86_SUPPORTED_MAJOR_VERSION]);
+        img.extend_from_slice(&disk_flags.to_le_bytes());
+
+        let track_offset = F86_HEADER_SIZE + F86_TRACKS_PER_SIDE * 4;
+        img.extend_from_slice(&(track_offset as u32).to_le_bytes());
+        img.resize(track_offset, 0);
+
+        img.extend_from_slice(&track_flags.to_le_bytes());
+        img.extend_from_slice(&0u32.to_le_bytes());
+        img.extend_from_slice(track.cells());
+        img
+    }
+
+    #[test]
+    fn test_f86_parse() {
+        let image = F86Image::parse(&test_image(F86_DISK_WRITE_PROTECT, TEST_TRACK_FLAGS)).unwrap();
+        assert_eq!(image.heads, 1);
+        assert_eq!(image.tracks.len(), 1);
+        assert_eq!(image.tracks[0].bit_len, 100_000);
+        assert_eq!(image.tracks[0].data_rate(), Some(250));
+        assert!(image.is_write_protected());
+
+        let mut disk = image.into_bitstream();
+        assert_eq!(disk.cylinders, 1);
+        let sectors = disk.track_mut(0, 0).unwrap().decode_sectors();
+        assert_eq!(sectors.len(), 9);
+        assert_eq!(sectors[0].data, Some(vec![1; 512]));
+        assert!(sectors[3].data_crc_ok);
+        assert!(!sectors[4].data_crc_ok);
+    }
+
+    #[test]
+    fn test_f86_errors() {
+        let data = test_image(0, TEST_TRACK_FLAGS);
+        assert_eq!(F86Image::parse(&data[..data.len() - 1]).err(), Some(F86Error::Truncated));
+        assert_eq!(F86Image::parse(b"86XF").err(), Some(F86Error::InvalidHeader));
+
+        let mut bad_version = data.clone();
+        bad_version[5] = 1;
+        assert_eq!(F86Image::parse(&bad_version).err(), Some(F86Error::UnsupportedVersion(1, 0x0C)));
+
+        // FM encoding and zoned RPM are reported together
+        let data = test_image(0x0200, TEST_TRACK_FLAGS & !F86_TRACK_ENCODING_MASK);
+        assert_eq!(
+            F86Image::parse(&data).err(),
+            Some(F86Error::UnsupportedFeatures(vec!["FM encoding".to_string(), "zoned RPM (type 1)".to_string()]))
+        );
+    }
+}
@@ -10,13 +10,55 @@ use std::{
     ffi::OsString,
     fs,
     error::Error,
-    fmt::Display
+    fmt::Display,
+    io::{Cursor, Read}
 };
 
+use flate2::read::GzDecoder;
+
+use crate::bitstream::{BitstreamDisk, BitstreamTrack, DecodedSector, SectorId};
+use crate::devices::fdc::{DiskDensity, DiskFormat, FloppyController, FDC_MAX_DRIVES, SECTOR_SIZE};
+use crate::f86::{F86Image, F86Error};
+use crate::imd::{ImdImage, ImdError, ImdSector, ImdTrack};
+use crate::td0::{Td0Image, Td0Error};
+
+/// Maximum depth of subdirectories to scan for images. This also guards against 
+/// symlink loops.
+pub const FLOPPY_SCAN_MAX_DEPTH: usize = 8;
+
+pub const FLOPPY_EXTENSIONS: [&str; 5] = ["img", "ima", "imd", "td0", "86f"];
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+// IMD track modes used when converting images that don't record a data rate
+const IMD_MODE_MFM_500K: u8 = 3;
+const IMD_MODE_MFM_300K: u8 = 4;
+const IMD_MODE_MFM_250K: u8 = 5;
+
+// Track lengths in MFM bitcells for one revolution at each IMD MFM data rate
+const MFM_TRACK_BITS_500K: usize = 200_000;
+const MFM_TRACK_BITS_300K: usize = 166_666;
+const MFM_TRACK_BITS_250K: usize = 100_000;
+
 #[derive(Debug)]
 pub enum FloppyError {
     DirNotFound,
     FileReadError,
+    FileWriteError,
+    ImageNotFound,
+    WriteProtected,
+    ImageSizeMismatch,
+    UnsupportedImdVersion(String),
+    UnsupportedTd0Compression(u8),
+    Unsupported86fFeatures(Vec<String>),
+    ImageParseError(String),
+    DecompressionError(String),
+    ArchiveNoImage,
+    ArchiveMultipleImages(Vec<String>),
+    UnsupportedGeometry,
+    InvalidImageSize(usize),
+    InvalidDrive(usize),
+    DriveError(String),
 }
 impl Error for FloppyError {}
 impl Display for FloppyError {
@@ -24,26 +66,172 @@ impl Display for FloppyError {
         match &*self {
             FloppyError::DirNotFound => write!(f, "Couldn't find the requested directory."),
             FloppyError::FileReadError => write!(f, "A file read error occurred."),
+            FloppyError::FileWriteError => write!(f, "A file write error occurred."),
+            FloppyError::ImageNotFound => write!(f, "The requested floppy image was not found."),
+            FloppyError::WriteProtected => write!(f, "The floppy image is write protected."),
+            FloppyError::ImageSizeMismatch => write!(f, "The floppy image data does not match the size of the original image."),
+            FloppyError::UnsupportedImdVersion(v) => write!(f, "Unsupported IMD image version: {}", v),
+            FloppyError::UnsupportedTd0Compression(v) => {
+                write!(f, "Unsupported Teledisk advanced compression (Teledisk version {}.{})", v / 10, v % 10)
+            }
+            FloppyError::Unsupported86fFeatures(features) => {
+                write!(f, "Unsupported 86F image features: {}", features.join(", "))
+            }
+            FloppyError::ImageParseError(e) => write!(f, "Couldn't parse floppy image: {}", e),
+            FloppyError::DecompressionError(e) => write!(f, "Couldn't decompress floppy image: {}", e),
+            FloppyError::ArchiveNoImage => write!(f, "The archive does not contain a floppy image."),
+            FloppyError::ArchiveMultipleImages(names) => {
+                write!(f, "The archive contains more than one floppy image: {}", names.join(", "))
+            }
+            FloppyError::UnsupportedGeometry => {
+                write!(f, "The floppy image doesn't have a standard geometry that can be converted.")
+            }
+            FloppyError::InvalidImageSize(size) => write!(f, "A floppy image of {} bytes doesn't match any disk format.", size),
+            FloppyError::InvalidDrive(drive) => write!(f, "Invalid floppy drive: {}", drive),
+            FloppyError::DriveError(e) => write!(f, "The floppy drive couldn't load the image: {}", e),
+        }
+    }
+}
+
+/// Compression applied to an image file, detected from the file name when scanning 
+/// and from the file's magic bytes when loading.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FloppyCompression {
+    None,
+    Gzip,
+    Zip,
+}
+
+/// Floppy image data as loaded. Sector images are expanded into a flat sector buffer, 
+/// while surface images such as 86F are kept as bitstream tracks for the FDC to decode.
+pub enum FloppyData {
+    Sectors(Vec<u8>),
+    Bitstream(BitstreamDisk),
+}
+
+/// Image formats a floppy image can be converted to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FloppyImageFormat {
+    /// A flat image of 512 byte sectors in CHS order (.img, .ima)
+    Raw,
+    Imd,
+}
+
+/// The result of a successful conversion. A conversion to a format that can't represent
+/// everything in the source image still succeeds, with a warning for each kind of
+/// information that was dropped.
+#[derive(Debug, Default)]
+pub struct FloppyConversion {
+    pub warnings: Vec<String>,
+}
+
+impl FloppyConversion {
+    pub fn is_lossless(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    fn warn_count(&mut self, count: usize, what: &str) {
+        if count > 0 {
+            self.warnings.push(format!("{} {}", count, what));
+        }
+    }
+}
+
+/// The geometry of a sector image, inferred from the image size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FloppyGeometry {
+    /// The image size isn't known until the image is loaded.
+    Unknown,
+    Known(DiskFormat),
+    /// The image size doesn't match a standard capacity. The geometry must be specified
+    /// with FloppyManager::set_geometry(), or the floppy_geometry config option, for the FDC to
+    /// report the correct disk parameters.
+    NonStandard,
+}
+
+impl FloppyGeometry {
+    pub fn from_size(size: Option<u64>) -> FloppyGeometry {
+        match size {
+            Some(size) => match DiskFormat::from_image_size(size as usize) {
+                Some(fmt) => FloppyGeometry::Known(*fmt),
+                None => FloppyGeometry::NonStandard
+            },
+            None => FloppyGeometry::Unknown
         }
     }
 }
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct FloppyImage {
     path: PathBuf,
-    size: u64
+    rel_path: PathBuf,
+    /// Size of the image once decompressed and expanded. This isn't known for compressed
+    /// and IMD or TD0 images until they have been loaded, and doesn't apply to 86F images.
+    size: Option<u64>,
+    compression: FloppyCompression,
+    write_protected: bool,
+    geometry: FloppyGeometry,
+}
+
+impl FloppyImage {
+    /// Describe the image file at 'path', or return None if the file isn't named as a 
+    /// floppy image. 'rel_path' is the name the image is known by.
+    fn from_path(path: &Path, rel_path: PathBuf) -> Option<FloppyImage> {
+
+        let compression = FloppyManager::match_image_name(path)?;
+        let metadata = fs::metadata(path).ok()?;
+
+        // Images that are read-only on the host start out write protected. Compressed,
+        // TD0 and 86F images can't be written back, so they are always write protected.
+        let is_td0 = FloppyManager::path_has_extension(path, "td0");
+        let is_86f = FloppyManager::path_has_extension(path, "86f");
+        let read_only = metadata.permissions().readonly()
+            || compression != FloppyCompression::None
+            || is_td0
+            || is_86f;
+
+        // The size of raw images is known without loading them
+        let size = match compression {
+            FloppyCompression::None if !FloppyManager::path_has_extension(path, "imd") && !is_td0 && !is_86f => {
+                Some(metadata.len())
+            }
+            _ => None
+        };
+
+        let geometry = FloppyGeometry::from_size(size);
+        if geometry == FloppyGeometry::NonStandard {
+            log::warn!("Floppy image {:?} has a non-standard size. Its geometry must be specified manually.", path);
+        }
+
+        Some(FloppyImage {
+            path: path.to_path_buf(),
+            rel_path,
+            size,
+            compression,
+            write_protected: read_only,
+            geometry
+        })
+    }
+
+    pub fn geometry(&self) -> FloppyGeometry {
+        self.geometry
+    }
 }
 
 pub struct FloppyManager {
     image_vec: Vec<FloppyImage>,
-    image_map: HashMap<OsString, FloppyImage>
+    image_map: HashMap<OsString, FloppyImage>,
+    /// The image mounted in each drive, used to write changes back on eject
+    mounted: HashMap<usize, FloppyImage>,
 }
 
 impl FloppyManager {
     pub fn new() -> Self {
         Self {
             image_vec: Vec::new(),
-            image_map: HashMap::new()
+            image_map: HashMap::new(),
+            mounted: HashMap::new(),
         }
     }
 
@@ -55,43 +243,50 @@ impl FloppyManager {
             Err(_) => return Err(FloppyError::DirNotFound)
         };
 
-        let extensions = ["img", "ima"];
-
         // Clear and rebuild image lists.
         self.image_vec.clear();
         self.image_map.clear();
 
+        self.scan_subdir(path, dir, 0);
+        Ok(true)
+    }
+
+    /// Scan a directory for floppy images, recursing into subdirectories up to 
+    /// FLOPPY_SCAN_MAX_DEPTH levels deep. Images are keyed by their path relative to 
+    /// the base directory, so images with the same name in different directories
+    /// don't collide.
+    fn scan_subdir(&mut self, base: &Path, dir: fs::ReadDir, depth: usize) {
+
         // Scan through all entries in the directory and find all files with matching extension
         for entry in dir {
             if let Ok(entry) = entry {
-                if entry.path().is_file() {
-                    if let Some(extension) = entry.path().extension() {
-                        if extensions.contains(&extension.to_string_lossy().to_lowercase().as_ref()) {
-
-                            println!("Found floppy image: {:?} size: {}", entry.path(), entry.metadata().unwrap().len());
-                            
-                            self.image_vec.push( 
-                                FloppyImage {
-                                    path: entry.path(),
-                                    size: entry.metadata().unwrap().len()
-                                }
-                            );
-                        
-                            self.image_map.insert(entry.file_name(), 
-                                FloppyImage { 
-                                    path: entry.path(),
-                                    size: entry.metadata().unwrap().len()
-                                 }
-                            );
+                if entry.path().is_dir() {
+                    if depth < FLOPPY_SCAN_MAX_DEPTH {
+                        if let Ok(subdir) = fs::read_dir(entry.path()) {
+                            self.scan_subdir(base, subdir, depth + 1);
                         }
                     }
                 }
+                else if entry.path().is_file() {
+                    let rel_path = match entry.path().strip_prefix(base) {
+                        Ok(rel_path) => rel_path.to_path_buf(),
+                        Err(_) => PathBuf::from(entry.file_name())
+                    };
+
+                    if let Some(floppy) = FloppyImage::from_path(&entry.path(), rel_path.clone()) {
+
+                        println!("Found floppy image: {:?} size: {}", entry.path(), entry.metadata().unwrap().len());
+
+                        self.image_vec.push(floppy.clone());
+                        self.image_map.insert(rel_path.into_os_string(), floppy);
+                    }
+                }
             }
         }
-        Ok(true)
     }
 
-
+    /// Return the names of all images found, as paths relative to the floppy directory.
+    /// Sorting by path groups images in the same subdirectory together.
     pub fn get_floppy_names(&self) -> Vec<OsString> {
         let mut vec: Vec<OsString> = Vec::new();
         for (key, _val) in &self.image_map {
@@ -101,20 +296,698 @@ impl FloppyManager {
         vec
     }
 
-    pub fn load_floppy_data(&self, name: &OsString ) -> Result<Vec<u8>, FloppyError> {
+    /// Return the geometry of the named image.
+    pub fn get_geometry(&self, name: &OsString) -> Option<FloppyGeometry> {
+        self.image_map.get(name).map(|floppy| floppy.geometry)
+    }
+
+    /// Specify the geometry of the named image, for images with a non-standard size. If the
+    /// size of the image is known, it must not be larger than the geometry describes.
+    pub fn set_geometry(&mut self, name: &OsString, fmt: DiskFormat) -> Result<(), FloppyError> {
+        let floppy = self.image_map.get_mut(name).ok_or(FloppyError::ImageNotFound)?;
+        if let Some(size) = floppy.size {
+            if size as usize > fmt.image_size() {
+                return Err(FloppyError::ImageSizeMismatch);
+            }
+        }
+        floppy.geometry = FloppyGeometry::Known(fmt);
+        Ok(())
+    }
+
+    /// Mount the named image from the floppy directory in the specified drive. See mount_image().
+    pub fn mount_floppy(&mut self, fdc: &mut FloppyController, drive: usize, name: &OsString) -> Result<(), FloppyError> {
+        let floppy = self.image_map.get(name).ok_or(FloppyError::ImageNotFound)?.clone();
+        self.mount(fdc, drive, floppy)?;
+
+        // Keep the size found when loading, to check geometry specified for the image later
+        if let (Some(mounted), Some(floppy)) = (self.mounted.get(&drive), self.image_map.get_mut(name)) {
+            floppy.size = mounted.size;
+            floppy.geometry = mounted.geometry;
+        }
+        Ok(())
+    }
+
+    /// Mount the image at 'path' in the specified drive. The image doesn't need to be in the 
+    /// floppy directory. Any disk already in the drive is ejected first. Sector images must
+    /// match the size of a standard disk format, or be smaller than a single sided 160K disk.
+    pub fn mount_image(&mut self, fdc: &mut FloppyController, drive: usize, path: &Path) -> Result<(), FloppyError> {
+        let name = path.file_name().map(PathBuf::from).unwrap_or_default();
+        let floppy = FloppyImage::from_path(path, name).ok_or(FloppyError::ImageNotFound)?;
+        self.mount(fdc, drive, floppy)
+    }
+
+    /// If the new image can't be loaded after the previous disk was ejected, the previous disk
+    /// is reloaded from its image so that a failed mount leaves the drive as it was.
+    fn mount(&mut self, fdc: &mut FloppyController, drive: usize, floppy: FloppyImage) -> Result<(), FloppyError> {
+
+        if drive >= FDC_MAX_DRIVES {
+            return Err(FloppyError::InvalidDrive(drive));
+        }
+        let previous = self.mounted.get(&drive).cloned();
+        self.eject(fdc, drive)?;
+
+        let result = self.load_into_drive(fdc, drive, floppy);
+        if result.is_err() {
+            if let Some(previous) = previous {
+                if let Err(e) = self.load_into_drive(fdc, drive, previous) {
+                    log::warn!("Couldn't reload the previous floppy in drive {}: {}", drive, e);
+                }
+            }
+        }
+        result
+    }
+
+    fn load_into_drive(&mut self, fdc: &mut FloppyController, drive: usize, mut floppy: FloppyImage) -> Result<(), FloppyError> {
 
-        let mut floppy_vec = Vec::new();
-        if let Some(floppy) = self.image_map.get(name) {
-            floppy_vec = match std::fs::read(&floppy.path) {
-                Ok(vec) => vec,
-                Err(e) => {
-                    eprintln!("Couldn't open floppy image: {}", e);
-                    return Err(FloppyError::FileReadError);
+        let result = match FloppyManager::load_image(&mut floppy)? {
+            FloppyData::Sectors(vec) => match floppy.geometry {
+                FloppyGeometry::Known(fmt) => fdc.load_image_with_format(drive, vec, fmt),
+                // The FDC accepts images smaller than the smallest disk format, such as boot 
+                // sector images, but anything else must match a disk format.
+                _ if vec.is_empty() || vec.len() % SECTOR_SIZE != 0 || vec.len() >= DiskFormat::min_image_size() => {
+                    return Err(FloppyError::InvalidImageSize(vec.len()));
                 }
+                _ => fdc.load_image_from(drive, vec)
+            },
+            FloppyData::Bitstream(disk) => fdc.load_bitstream_from(drive, disk),
+        };
+        result.map_err(|e| FloppyError::DriveError(e.to_string()))?;
+
+        log::info!("Mounted floppy image {:?} in drive {}", floppy.path, drive);
+        self.mounted.insert(drive, floppy);
+        Ok(())
+    }
+
+    /// Write changes to the disk in the specified drive back to the image it was mounted from.
+    pub fn save_mounted(&mut self, fdc: &mut FloppyController, drive: usize) -> Result<(), FloppyError> {
+        let floppy = self.mounted.get(&drive).ok_or(FloppyError::ImageNotFound)?;
+        if let Some(data) = fdc.get_image_data(drive) {
+            FloppyManager::write_image(floppy, data)?;
+            fdc.clear_dirty(drive);
+        }
+        Ok(())
+    }
+
+    /// Eject the disk in the specified drive, first writing any changes back to its image. If 
+    /// the changes can't be written, the disk is left in the drive and the error is returned.
+    /// Changes to write protected images are discarded.
+    pub fn eject(&mut self, fdc: &mut FloppyController, drive: usize) -> Result<(), FloppyError> {
+
+        if drive >= FDC_MAX_DRIVES {
+            return Err(FloppyError::InvalidDrive(drive));
+        }
+
+        if fdc.is_dirty(drive) && self.mounted.contains_key(&drive) {
+            match self.save_mounted(fdc, drive) {
+                Ok(()) => log::info!("Saved changes to floppy in drive {}", drive),
+                Err(FloppyError::WriteProtected) => {
+                    log::warn!("Floppy in drive {} is write protected. Unsaved changes were discarded.", drive);
+                }
+                Err(e) => return Err(e)
+            }
+        }
+
+        fdc.unload_image(drive);
+        self.mounted.remove(&drive);
+        Ok(())
+    }
+
+    /// Load an image, expanding sector images into a flat sector buffer. The image's size 
+    /// and geometry are updated once the expanded size is known.
+    fn load_image(floppy: &mut FloppyImage) -> Result<FloppyData, FloppyError> {
+
+        let (image_name, mut floppy_vec) = FloppyManager::read_image(floppy)?;
+
+        let is_imd = FloppyManager::path_has_extension(&image_name, "imd");
+        if is_imd || FloppyManager::path_has_extension(&image_name, "td0") {
+            let image = match is_imd {
+                true => FloppyManager::parse_imd(&floppy_vec)?,
+                false => FloppyManager::parse_td0(&floppy_vec)?
             };
+
+            // Sector images can't carry CRC errors, so images with bad sectors are loaded
+            // as bitstream disks for the FDC to report them.
+            if image.tracks.iter().flat_map(|t| t.sectors.iter()).any(|s| s.has_error()) {
+                if let Some(disk) = FloppyManager::imd_to_bitstream(&image) {
+                    log::debug!("Image has sectors with CRC errors, loading as bitstream");
+                    return Ok(FloppyData::Bitstream(disk));
+                }
+                log::warn!("Image has sectors with CRC errors, but uses FM encoding. Errors will be lost.");
+            }
+            floppy_vec = image.to_raw(SECTOR_SIZE).map_err(|e| FloppyError::ImageParseError(e.to_string()))?;
+        }
+        else if FloppyManager::path_has_extension(&image_name, "86f") {
+            return Ok(FloppyData::Bitstream(FloppyManager::load_86f(&floppy_vec)?));
+        }
+
+        floppy.size = Some(floppy_vec.len() as u64);
+        if floppy.geometry == FloppyGeometry::Unknown {
+            floppy.geometry = FloppyGeometry::from_size(floppy.size);
+        }
+
+        Ok(FloppyData::Sectors(floppy_vec))
+    }
+
+    /// Read an image file, decompressing it if required. Returns the name of the image once
+    /// decompressed, which identifies its format, and the image data.
+    fn read_image(floppy: &FloppyImage) -> Result<(PathBuf, Vec<u8>), FloppyError> {
+
+        let mut floppy_vec = match std::fs::read(&floppy.path) {
+            Ok(vec) => vec,
+            Err(e) => {
+                eprintln!("Couldn't open floppy image: {}", e);
+                return Err(FloppyError::FileReadError);
+            }
+        };
+
+        // The compression method is detected by magic bytes. The image name is then used to 
+        // identify the format of the image once decompressed.
+        let mut image_name = floppy.path.clone();
+        if floppy.compression != FloppyCompression::None {
+            if floppy_vec.starts_with(GZIP_MAGIC) {
+                floppy_vec = FloppyManager::gunzip(&floppy_vec)?;
+                image_name.set_extension("");
+            }
+            else if floppy_vec.starts_with(ZIP_MAGIC) {
+                let (entry_name, data) = FloppyManager::unzip(floppy_vec)?;
+                floppy_vec = data;
+                image_name = PathBuf::from(entry_name);
+            }
         }
 
-        Ok(floppy_vec)
+        Ok((image_name, floppy_vec))
     }
 
+    /// Convert the named image to the target format, writing the result to 'dst_path'. Any
+    /// supported image can be converted to IMD. Conversion to a raw image requires a standard
+    /// geometry of 512 byte sectors numbered from 1. Sector attributes and surface details
+    /// the target format can't represent are dropped and listed in the returned warnings.
+    pub fn convert(
+        &self,
+        src_name: &OsString,
+        dst_path: &Path,
+        target_format: FloppyImageFormat
+    ) -> Result<FloppyConversion, FloppyError> {
+
+        let floppy = self.image_map.get(src_name).ok_or(FloppyError::ImageNotFound)?;
+        let (image_name, data) = FloppyManager::read_image(floppy)?;
+
+        let (out_vec, conversion) = FloppyManager::convert_data(&image_name, &data, target_format)?;
+
+        if let Err(e) = std::fs::write(dst_path, &out_vec) {
+            eprintln!("Couldn't write floppy image: {}", e);
+            return Err(FloppyError::FileWriteError);
+        }
+
+        for warning in &conversion.warnings {
+            log::warn!("Converting {:?} to {:?} dropped: {}", src_name, target_format, warning);
+        }
+        Ok(conversion)
+    }
+
+    /// Convert image data to the target format. The source format is identified by the
+    /// extension of 'image_name'.
+    fn convert_data(
+        image_name: &Path,
+        data: &[u8],
+        target_format: FloppyImageFormat
+    ) -> Result<(Vec<u8>, FloppyConversion), FloppyError> {
+
+        let mut conversion = FloppyConversion::default();
+        let parse_err = |e: ImdError| FloppyError::ImageParseError(e.to_string());
+
+        let image = if FloppyManager::path_has_extension(image_name, "imd") {
+            ImdImage::parse(data).map_err(|e| match e {
+                ImdError::UnsupportedVersion(v) => FloppyError::UnsupportedImdVersion(v),
+                e => FloppyError::ImageParseError(e.to_string())
+            })?
+        }
+        else if FloppyManager::path_has_extension(image_name, "td0") {
+            Td0Image::parse(data).map_err(|e| match e {
+                Td0Error::UnsupportedCompression(v) => FloppyError::UnsupportedTd0Compression(v),
+                e => FloppyError::ImageParseError(e.to_string())
+            })?.into_imd()
+        }
+        else if FloppyManager::path_has_extension(image_name, "86f") {
+            FloppyManager::bitstream_to_imd(FloppyManager::load_86f(data)?, &mut conversion)
+        }
+        else {
+            if target_format == FloppyImageFormat::Raw {
+                return Ok((data.to_vec(), conversion));
+            }
+            let fmt = DiskFormat::from_image_size(data.len()).ok_or(FloppyError::UnsupportedGeometry)?;
+            let mode = if fmt.density == DiskDensity::Double { IMD_MODE_MFM_250K } else { IMD_MODE_MFM_500K };
+            ImdImage::from_raw(
+                data,
+                fmt.cylinders as usize,
+                fmt.heads as usize,
+                fmt.sectors as usize,
+                SECTOR_SIZE,
+                mode
+            ).map_err(parse_err)?
+        };
+
+        let out_vec = match target_format {
+            FloppyImageFormat::Imd => image.to_bytes(),
+            FloppyImageFormat::Raw => {
+                let raw = image.to_raw(SECTOR_SIZE).map_err(|e| match e {
+                    ImdError::UnsupportedGeometry => FloppyError::UnsupportedGeometry,
+                    e => parse_err(e)
+                })?;
+
+                let sectors = || image.tracks.iter().flat_map(|t| t.sectors.iter().map(move |s| (t, s)));
+                conversion.warn_count(
+                    sectors().filter(|(_, s)| s.has_error()).count(),
+                    "sector(s) with data CRC errors"
+                );
+                conversion.warn_count(
+                    sectors().filter(|(_, s)| s.is_deleted()).count(),
+                    "sector(s) with deleted data address marks"
+                );
+                conversion.warn_count(
+                    sectors().filter(|(_, s)| s.record == 0).count(),
+                    "sector(s) with no data, written as zeros"
+                );
+                conversion.warn_count(
+                    sectors().filter(|(t, s)| s.cylinder != t.cylinder || s.head != t.head).count(),
+                    "sector(s) with ID fields that don't match their track"
+                );
+                raw
+            }
+        };
+
+        Ok((out_vec, conversion))
+    }
+
+    /// Decode the sectors of each track of a bitstream disk into an IMD image. Surface
+    /// defects, ID field CRC errors and the track layout itself can't be represented in a
+    /// sector image, so these are recorded as warnings.
+    fn bitstream_to_imd(mut disk: BitstreamDisk, conversion: &mut FloppyConversion) -> ImdImage {
+
+        let mut tracks = Vec::new();
+        let mut defect_tracks = 0;
+        let mut id_crc_errors = 0;
+
+        for cylinder in 0..disk.cylinders {
+            for head in 0..disk.heads {
+                let track = match disk.track_mut(cylinder, head) {
+                    Some(track) => track,
+                    None => continue
+                };
+                if track.has_surface_defects() {
+                    defect_tracks += 1;
+                }
+
+                let mut sectors = Vec::new();
+                for decoded in track.decode_sectors() {
+                    if !decoded.id_crc_ok {
+                        id_crc_errors += 1;
+                        continue;
+                    }
+                    // Records 1, 3, 5 and 7 are normal, deleted, error and deleted error sectors
+                    let record = match (&decoded.data, decoded.deleted, decoded.data_crc_ok) {
+                        (None, _, _) => 0,
+                        (Some(_), false, true) => 1,
+                        (Some(_), true, true) => 3,
+                        (Some(_), false, false) => 5,
+                        (Some(_), true, false) => 7,
+                    };
+                    sectors.push(ImdSector {
+                        number: decoded.id.r,
+                        cylinder: decoded.id.c,
+                        head: decoded.id.h,
+                        size: decoded.id.size(),
+                        record,
+                        data: decoded.data.unwrap_or_default()
+                    });
+                }
+
+                let mode = if sectors.len() >= 15 { IMD_MODE_MFM_500K } else { IMD_MODE_MFM_250K };
+                tracks.push(ImdTrack::new(mode, cylinder, head, sectors));
+            }
+        }
+
+        conversion.warnings.push("track layout and bitcell timing".to_string());
+        conversion.warn_count(defect_tracks, "track(s) with weak or missing bitcells");
+        conversion.warn_count(id_crc_errors, "sector(s) with ID field CRC errors");
+
+        ImdImage {
+            header: b"IMD 1.18: Converted from 86F".to_vec(),
+            version: "1.18".to_string(),
+            tracks
+        }
+    }
+
+    pub fn is_write_protected(&self, name: &OsString) -> bool {
+        self.image_map.get(name).map_or(false, |floppy| floppy.write_protected)
+    }
+
+    pub fn set_write_protected(&mut self, name: &OsString, state: bool) {
+        if let Some(floppy) = self.image_map.get_mut(name) {
+            floppy.write_protected = state;
+        }
+    }
+
+    /// Write modified floppy data back to the image file it was loaded from. The data must 
+    /// be the same size as the image originally loaded. IMD images are re-encoded with the
+    /// new sector data, keeping the original track layout.
+    fn write_image(floppy: &FloppyImage, data: &[u8]) -> Result<(), FloppyError> {
+
+        if floppy.write_protected 
+            || floppy.compression != FloppyCompression::None 
+            || FloppyManager::path_has_extension(&floppy.path, "td0") 
+            || FloppyManager::path_has_extension(&floppy.path, "86f") 
+        {
+            return Err(FloppyError::WriteProtected);
+        }
+
+        let file_vec = if FloppyManager::path_has_extension(&floppy.path, "imd") {
+            let original = std::fs::read(&floppy.path).map_err(|_| FloppyError::FileReadError)?;
+            let mut image = ImdImage::parse(&original).map_err(|e| FloppyError::ImageParseError(e.to_string()))?;
+
+            let raw_len = image.to_raw(SECTOR_SIZE).map_err(|e| FloppyError::ImageParseError(e.to_string()))?.len();
+            if raw_len != data.len() {
+                return Err(FloppyError::ImageSizeMismatch);
+            }
+            image.update_from_raw(data, SECTOR_SIZE).map_err(|e| FloppyError::ImageParseError(e.to_string()))?;
+            image.to_bytes()
+        }
+        else {
+            if Some(data.len() as u64) != floppy.size {
+                return Err(FloppyError::ImageSizeMismatch);
+            }
+            data.to_vec()
+        };
+
+        if let Err(e) = std::fs::write(&floppy.path, &file_vec) {
+            eprintln!("Couldn't write floppy image: {}", e);
+            return Err(FloppyError::FileWriteError);
+        }
+
+        Ok(())
+    }
+
+    fn path_has_extension(path: &Path, extension: &str) -> bool {
+        path.extension().map_or(false, |ext| ext.to_string_lossy().to_lowercase() == extension)
+    }
+
+    /// Determine whether the specified path names a floppy image, either directly, gzipped
+    /// (ie, .img.gz) or in a zip archive, returning the compression used.
+    fn match_image_name(path: &Path) -> Option<FloppyCompression> {
+
+        let is_image = |path: &Path| FLOPPY_EXTENSIONS.iter().any(|ext| FloppyManager::path_has_extension(path, ext));
+
+        if FloppyManager::path_has_extension(path, "zip") {
+            Some(FloppyCompression::Zip)
+        }
+        else if FloppyManager::path_has_extension(path, "gz") {
+            match is_image(&path.with_extension("")) {
+                true => Some(FloppyCompression::Gzip),
+                false => None
+            }
+        }
+        else if is_image(path) {
+            Some(FloppyCompression::None)
+        }
+        else {
+            None
+        }
+    }
+
+    fn gunzip(data: &[u8]) -> Result<Vec<u8>, FloppyError> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .map_err(|e| FloppyError::DecompressionError(e.to_string()))?;
+        Ok(decoded)
+    }
+
+    /// Extract the floppy image from a zip archive, returning the name of the archive entry 
+    /// and its data. An archive containing a single file is assumed to be an image regardless
+    /// of the file's name.
+    fn unzip(data: Vec<u8>) -> Result<(String, Vec<u8>), FloppyError> {
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))
+            .map_err(|e| FloppyError::DecompressionError(e.to_string()))?;
+
+        let files: Vec<String> = archive.file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(String::from)
+            .collect();
+
+        let mut candidates: Vec<String> = files.iter()
+            .filter(|name| FLOPPY_EXTENSIONS.iter().any(|ext| FloppyManager::path_has_extension(Path::new(name), ext)))
+            .cloned()
+            .collect();
+        candidates.sort();
+
+        let entry_name = match (candidates.len(), files.len()) {
+            (1, _) => candidates.remove(0),
+            (0, 1) => files[0].clone(),
+            (0, _) => return Err(FloppyError::ArchiveNoImage),
+            _ => return Err(FloppyError::ArchiveMultipleImages(candidates))
+        };
+
+        let mut decoded = Vec::new();
+        archive.by_name(&entry_name)
+            .map_err(|e| FloppyError::DecompressionError(e.to_string()))?
+            .read_to_end(&mut decoded)
+            .map_err(|e| FloppyError::DecompressionError(e.to_string()))?;
+
+        Ok((entry_name, decoded))
+    }
+
+    /// Parse an IMD image.
+    fn parse_imd(data: &[u8]) -> Result<ImdImage, FloppyError> {
+        let image = ImdImage::parse(data).map_err(|e| match e {
+            ImdError::UnsupportedVersion(v) => FloppyError::UnsupportedImdVersion(v),
+            e => FloppyError::ImageParseError(e.to_string())
+        })?;
+
+        log::debug!("Parsed IMD image version {} with {} tracks", image.version, image.tracks.len());
+
+        Ok(image)
+    }
+
+    /// Parse a Teledisk image, converting it to an IMD image.
+    fn parse_td0(data: &[u8]) -> Result<ImdImage, FloppyError> {
+        let image = Td0Image::parse(data).map_err(|e| match e {
+            Td0Error::UnsupportedCompression(v) => FloppyError::UnsupportedTd0Compression(v),
+            e => FloppyError::ImageParseError(e.to_string())
+        })?;
+
+        log::debug!(
+            "Parsed TD0 image version {} ({}) with {} tracks", 
+            image.version, 
+            if image.advanced { "advanced compression" } else { "normal" },
+            image.tracks.len()
+        );
+
+        Ok(image.into_imd())
+    }
+
+    /// Encode the tracks of an IMD image as MFM bitstream tracks, preserving sector ID fields,
+    /// deleted data marks and data CRC errors. Returns None if any track is FM encoded.
+    fn imd_to_bitstream(image: &ImdImage) -> Option<BitstreamDisk> {
+
+        let cylinders = image.tracks.iter().map(|t| t.cylinder as usize + 1).max()?;
+        let heads = image.tracks.iter().map(|t| t.head as usize + 1).max()?;
+        let mut disk = BitstreamDisk::new(cylinders.min(255) as u8, heads as u8);
+
+        for track in &image.tracks {
+            let bit_len = match track.mode {
+                IMD_MODE_MFM_500K => MFM_TRACK_BITS_500K,
+                IMD_MODE_MFM_300K => MFM_TRACK_BITS_300K,
+                IMD_MODE_MFM_250K => MFM_TRACK_BITS_250K,
+                _ => return None
+            };
+
+            let sectors: Vec<DecodedSector> = track.sectors.iter().map(|s| DecodedSector {
+                id: SectorId {
+                    c: s.cylinder,
+                    h: s.head,
+                    r: s.number,
+                    n: (0..=7u8).find(|n| 128usize << n == s.size).unwrap_or(2)
+                },
+                id_crc_ok: true,
+                data: match s.data.is_empty() {
+                    true => None,
+                    false => Some(s.data.clone())
+                },
+                data_crc_ok: !s.has_error(),
+                deleted: s.is_deleted()
+            }).collect();
+
+            disk.set_track(track.cylinder, track.head, BitstreamTrack::encode(&sectors, bit_len));
+        }
+
+        Some(disk)
+    }
+
+    /// Load an 86F image as a bitstream disk. Images using features the FDC can't reproduce
+    /// are rejected, listing every unsupported feature found.
+    fn load_86f(data: &[u8]) -> Result<BitstreamDisk, FloppyError> {
+        let image = F86Image::parse(data).map_err(|e| match e {
+            F86Error::UnsupportedFeatures(features) => FloppyError::Unsupported86fFeatures(features),
+            e => FloppyError::ImageParseError(e.to_string())
+        })?;
+
+        log::debug!(
+            "Parsed 86F image version {}.{:02} with {} tracks, {} side(s)",
+            image.major_version,
+            image.minor_version,
+            image.tracks.len(),
+            image.heads
+        );
+
+        Ok(image.into_bitstream())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_standard_geometries() {
+        for size in [368_640usize, 737_280, 1_228_800, 1_474_560] {
+            let raw: Vec<u8> = (0..size).map(|i| (i / SECTOR_SIZE) as u8).collect();
+
+            let (imd, conversion) = FloppyManager::convert_data(Path::new("disk.img"), &raw, FloppyImageFormat::Imd).unwrap();
+            assert!(conversion.is_lossless(), "{}: {:?}", size, conversion.warnings);
+
+            let (back, conversion) = FloppyManager::convert_data(Path::new("disk.imd"), &imd, FloppyImageFormat::Raw).unwrap();
+            assert!(conversion.is_lossless(), "{}: {:?}", size, conversion.warnings);
+            assert!(back == raw, "{}: raw image changed in conversion", size);
+        }
+
+        assert!(matches!(
+            FloppyManager::convert_data(Path::new("disk.img"), &[0; 1000 * SECTOR_SIZE], FloppyImageFormat::Imd),
+            Err(FloppyError::UnsupportedGeometry)
+        ));
+    }
+
+    #[test]
+    fn test_geometry_from_size() {
+        let standard = [
+            (163_840u64, 40, 1, 8),
+            (184_320, 40, 1, 9),
+            (327_680, 40, 2, 8),
+            (368_640, 40, 2, 9),
+            (737_280, 80, 2, 9),
+            (1_228_800, 80, 2, 15),
+            (1_474_560, 80, 2, 18),
+            (2_949_120, 80, 2, 36),
+        ];
+        for (size, c, h, s) in standard {
+            match FloppyGeometry::from_size(Some(size)) {
+                FloppyGeometry::Known(fmt) => {
+                    assert_eq!((fmt.cylinders, fmt.heads, fmt.sectors, fmt.sector_size), (c, h, s, SECTOR_SIZE), "{}", size);
+                    assert_eq!(fmt.image_size() as u64, size);
+                    assert_eq!(DiskFormat::from_geometry(c, h, s), fmt, "{}", size);
+                }
+                g => panic!("{}: expected a standard geometry, got {:?}", size, g)
+            }
+        }
+
+        assert_eq!(FloppyGeometry::from_size(Some(1000 * SECTOR_SIZE as u64)), FloppyGeometry::NonStandard);
+        assert_eq!(FloppyGeometry::from_size(None), FloppyGeometry::Unknown);
+
+        // A non-standard image can be given a geometry it fits within
+        let mut manager = FloppyManager::new();
+        let name = OsString::from("odd.img");
+        manager.image_map.insert(name.clone(), FloppyImage {
+            path: PathBuf::from("odd.img"),
+            rel_path: PathBuf::from("odd.img"),
+            size: Some(400 * SECTOR_SIZE as u64),
+            compression: FloppyCompression::None,
+            write_protected: false,
+            geometry: FloppyGeometry::NonStandard,
+        });
+        let fmt = *DiskFormat::from_image_size(184_320).unwrap();
+        assert!(matches!(manager.set_geometry(&name, fmt), Err(FloppyError::ImageSizeMismatch)));
+        let fmt = *DiskFormat::from_image_size(368_640).unwrap();
+        manager.set_geometry(&name, fmt).unwrap();
+        assert_eq!(manager.get_geometry(&name), Some(FloppyGeometry::Known(fmt)));
+
+        // As configured with floppy_geometry, for an image with two extra tracks
+        let fmt = DiskFormat::from_geometry(42, 2, 9);
+        manager.set_geometry(&name, fmt).unwrap();
+        assert_eq!(manager.get_geometry(&name), Some(FloppyGeometry::Known(fmt)));
+        assert_eq!(fmt.image_size(), 42 * 2 * 9 * SECTOR_SIZE);
+    }
+
+    #[test]
+    fn test_convert_lossy() {
+        let raw = vec![0xE5; 368_640];
+        let (imd, _) = FloppyManager::convert_data(Path::new("disk.img"), &raw, FloppyImageFormat::Imd).unwrap();
+
+        // Mark one sector deleted and remove the data from another
+        let mut image = ImdImage::parse(&imd).unwrap();
+        image.tracks[0].sectors[0].record = 4;
+        image.tracks[1].sectors[2].record = 0;
+        image.tracks[1].sectors[2].data.clear();
+
+        let (back, conversion) = FloppyManager::convert_data(Path::new("disk.imd"), &image.to_bytes(), FloppyImageFormat::Raw).unwrap();
+        assert_eq!(back.len(), raw.len());
+        assert_eq!(conversion.warnings.len(), 2);
+        assert!(conversion.warnings[0].contains("deleted"));
+        assert!(conversion.warnings[1].contains("no data"));
+    }
+
+    #[test]
+    fn test_imd_to_bitstream() {
+        let raw: Vec<u8> = (0..368_640usize).map(|i| (i / SECTOR_SIZE) as u8).collect();
+        let mut image = ImdImage::from_raw(&raw, 40, 2, 9, SECTOR_SIZE, IMD_MODE_MFM_250K).unwrap();
+        image.tracks[1].sectors[4].record = 5;
+
+        let mut disk = FloppyManager::imd_to_bitstream(&image).unwrap();
+        assert_eq!((disk.cylinders, disk.heads), (40, 2));
+
+        let sectors = disk.track_mut(0, 1).unwrap().decode_sectors();
+        assert_eq!(sectors.len(), 9);
+        assert!(!sectors[4].data_crc_ok);
+        assert_eq!(sectors[4].data, Some(vec![13; SECTOR_SIZE]));
+        assert!(sectors.iter().enumerate().all(|(i, s)| s.data_crc_ok == (i != 4)));
+
+        // FM tracks can't be encoded
+        image.tracks[0].mode = 0;
+        assert!(FloppyManager::imd_to_bitstream(&image).is_none());
+    }
+
+    #[test]
+    fn test_mount_eject() {
+        let dir = std::env::temp_dir().join(format!("martypc_mount_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let good_path = dir.join("disk.img");
+        let bad_path = dir.join("bad.img");
+        fs::write(&good_path, vec![0xF6; 368_640]).unwrap();
+        fs::write(&bad_path, vec![0xF6; 400 * SECTOR_SIZE]).unwrap();
+
+        let mut manager = FloppyManager::new();
+        let mut fdc = FloppyController::new();
+
+        // Images outside of a scanned directory can be mounted by path
+        manager.mount_image(&mut fdc, 0, &good_path).unwrap();
+        assert_eq!(fdc.get_image_data(0).map(|d| d.len()), Some(368_640));
+        assert_eq!(manager.mounted[&0].geometry(), FloppyGeometry::Known(*DiskFormat::from_image_size(368_640).unwrap()));
+
+        // A size that matches no disk format is rejected, leaving the drive empty
+        assert!(matches!(manager.mount_image(&mut fdc, 1, &bad_path), Err(FloppyError::InvalidImageSize(204_800))));
+        assert!(fdc.get_image_data(1).is_none());
+        assert!(matches!(manager.mount_image(&mut fdc, FDC_MAX_DRIVES, &good_path), Err(FloppyError::InvalidDrive(_))));
+        assert!(matches!(manager.mount_image(&mut fdc, 1, &dir.join("missing.img")), Err(FloppyError::ImageNotFound)));
+
+        // A failed mount over a mounted disk reloads the previous disk
+        assert!(matches!(manager.mount_image(&mut fdc, 0, &bad_path), Err(FloppyError::InvalidImageSize(_))));
+        assert_eq!(fdc.get_image_data(0).map(|d| d.len()), Some(368_640));
+        assert_eq!(manager.mounted[&0].path, good_path);
+
+        manager.eject(&mut fdc, 0).unwrap();
+        assert!(fdc.get_image_data(0).is_none());
+        assert!(manager.mounted.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,573 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    imd.rs
+
+    Parse an ImageDisk (IMD) formatted floppy image.
+
+    IMD images consist of an ASCII header and comment terminated by 0x1A,
+    followed by a series of track records. Each track record carries its own
+    geometry and sector numbering map, so tracks may have any interleave or
+    sector size. The parsed tracks are kept in their original order so that an
+    image can be written back out unchanged.
+
+*/
+
+use std::error::Error;
+use core::fmt::Display;
+
+pub const IMD_COMMENT_TERMINATOR: u8 = 0x1A;
+pub const IMD_SIGNATURE: &[u8] = b"IMD ";
+pub const IMD_SUPPORTED_MAJOR_VERSION: u32 = 1;
+pub const IMD_SECTOR_SIZE_TABLE: u8 = 0xFF;
+
+const IMD_HEAD_MASK: u8 = 0x01;
+const IMD_HEAD_CYLINDER_MAP: u8 = 0x80;
+const IMD_HEAD_HEAD_MAP: u8 = 0x40;
+
+#[derive (Debug, PartialEq)]
+pub enum ImdError {
+    InvalidHeader,
+    UnsupportedVersion(String),
+    InvalidTrack,
+    InvalidSectorSize,
+    InvalidSectorRecord,
+    Truncated,
+    UnsupportedGeometry,
+}
+impl Error for ImdError {}
+impl Display for ImdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            ImdError::InvalidHeader => write!(f, "The IMD header was missing or invalid."),
+            ImdError::UnsupportedVersion(v) => write!(f, "The IMD file is an unsupported version: {}", v),
+            ImdError::InvalidTrack => write!(f, "An IMD track record contained an invalid value."),
+            ImdError::InvalidSectorSize => write!(f, "An IMD track record specified an invalid sector size."),
+            ImdError::InvalidSectorRecord => write!(f, "An IMD sector data record was of an unknown type."),
+            ImdError::Truncated => write!(f, "The IMD file ended unexpectedly."),
+            ImdError::UnsupportedGeometry => write!(f, "The IMD image cannot be represented as a raw sector image."),
+        }
+    }
+}
+
+/// A single sector as stored in an IMD track record.
+#[derive (Clone, Debug, PartialEq)]
+pub struct ImdSector {
+    /// Sector number as recorded in the sector ID field.
+    pub number: u8,
+    /// Cylinder and head as recorded in the sector ID field. These usually match the
+    /// physical track, but copy protection schemes may use different values.
+    pub cylinder: u8,
+    pub head: u8,
+    pub size: usize,
+    /// The raw sector data record type, from 0 (data unavailable) to 8. Kept so that
+    /// compressed, deleted and bad sectors can be written back as they were read.
+    pub record: u8,
+    /// Sector data, with compressed sectors expanded. Empty if data is unavailable.
+    pub data: Vec<u8>,
+}
+
+impl ImdSector {
+    pub fn is_compressed(&self) -> bool {
+        self.record != 0 && self.record % 2 == 0
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        matches!(self.record, 3 | 4 | 7 | 8)
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.record >= 5
+    }
+}
+
+/// A single IMD track record. Sectors are kept in physical order, so the interleave
+/// of the track is preserved.
+#[derive (Clone, Debug, PartialEq)]
+pub struct ImdTrack {
+    pub mode: u8,
+    pub cylinder: u8,
+    pub head: u8,
+    /// Sector size code: the sector size is 128 << code, or each sector has its own
+    /// size if the code is IMD_SECTOR_SIZE_TABLE.
+    pub size_code: u8,
+    has_cylinder_map: bool,
+    has_head_map: bool,
+    pub sectors: Vec<ImdSector>,
+}
+
+impl ImdTrack {
+    /// Build a track record from sectors read from another image format. The sector size
+    /// code and the cylinder and head maps are derived from the sectors.
+    pub fn new(mode: u8, cylinder: u8, head: u8, sectors: Vec<ImdSector>) -> Self {
+        let size_code = match sectors.first() {
+            Some(first) if sectors.iter().all(|s| s.size == first.size) => {
+                (0..=6u8).find(|code| 128usize << code == first.size).unwrap_or(IMD_SECTOR_SIZE_TABLE)
+            }
+            Some(_) => IMD_SECTOR_SIZE_TABLE,
+            None => 2
+        };
+
+        Self {
+            mode,
+            cylinder,
+            head: head & IMD_HEAD_MASK,
+            size_code,
+            has_cylinder_map: sectors.iter().any(|s| s.cylinder != cylinder),
+            has_head_map: sectors.iter().any(|s| s.head != head & IMD_HEAD_MASK),
+            sectors
+        }
+    }
+}
+
+pub struct ImdImage {
+    /// The header and comment, up to but not including the terminator.
+    pub header: Vec<u8>,
+    pub version: String,
+    pub tracks: Vec<ImdTrack>,
+}
+
+/// A simple cursor over the image data that reports truncation as an error.
+struct ImdReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ImdReader<'a> {
+    fn read_u8(&mut self) -> Result<u8, ImdError> {
+        let b = *self.data.get(self.pos).ok_or(ImdError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, ImdError> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        Ok(lo | hi << 8)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ImdError> {
+        if self.pos + len > self.data.len() {
+            return Err(ImdError::Truncated);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+impl ImdImage {
+
+    pub fn parse(data: &[u8]) -> Result<ImdImage, ImdError> {
+
+        if !data.starts_with(IMD_SIGNATURE) {
+            return Err(ImdError::InvalidHeader);
+        }
+
+        let header_len = data.iter()
+            .position(|&b| b == IMD_COMMENT_TERMINATOR)
+            .ok_or(ImdError::InvalidHeader)?;
+        let header = data[..header_len].to_vec();
+
+        // The version follows the signature and is terminated by a colon, ie "IMD 1.18: date"
+        let version_bytes: Vec<u8> = header[IMD_SIGNATURE.len()..].iter()
+            .take_while(|&&b| b != b':' && b != b' ' && b != b'\r' && b != b'\n')
+            .copied()
+            .collect();
+        let version = String::from_utf8_lossy(&version_bytes).to_string();
+
+        let major = version.split('.').next().and_then(|m| m.parse::<u32>().ok());
+        if major != Some(IMD_SUPPORTED_MAJOR_VERSION) {
+            return Err(ImdError::UnsupportedVersion(version));
+        }
+
+        let mut reader = ImdReader { data, pos: header_len + 1 };
+        let mut tracks = Vec::new();
+
+        while !reader.at_end() {
+            tracks.push(ImdImage::parse_track(&mut reader)?);
+        }
+
+        Ok(ImdImage {
+            header,
+            version,
+            tracks
+        })
+    }
+
+    fn parse_track(reader: &mut ImdReader) -> Result<ImdTrack, ImdError> {
+
+        let mode = reader.read_u8()?;
+        let cylinder = reader.read_u8()?;
+        let head_byte = reader.read_u8()?;
+        let sector_ct = reader.read_u8()? as usize;
+        let size_code = reader.read_u8()?;
+
+        if mode > 5 || head_byte & !(IMD_HEAD_MASK | IMD_HEAD_CYLINDER_MAP | IMD_HEAD_HEAD_MAP) != 0 {
+            return Err(ImdError::InvalidTrack);
+        }
+        if size_code > 6 && size_code != IMD_SECTOR_SIZE_TABLE {
+            return Err(ImdError::InvalidSectorSize);
+        }
+
+        let head = head_byte & IMD_HEAD_MASK;
+        let has_cylinder_map = head_byte & IMD_HEAD_CYLINDER_MAP != 0;
+        let has_head_map = head_byte & IMD_HEAD_HEAD_MAP != 0;
+
+        let numbers = reader.read_bytes(sector_ct)?;
+        let cylinders = match has_cylinder_map {
+            true => reader.read_bytes(sector_ct)?.to_vec(),
+            false => vec![cylinder; sector_ct]
+        };
+        let heads = match has_head_map {
+            true => reader.read_bytes(sector_ct)?.to_vec(),
+            false => vec![head; sector_ct]
+        };
+        let sizes = match size_code {
+            IMD_SECTOR_SIZE_TABLE => {
+                let mut sizes = Vec::with_capacity(sector_ct);
+                for _ in 0..sector_ct {
+                    sizes.push(reader.read_u16()? as usize);
+                }
+                sizes
+            }
+            _ => vec![128usize << size_code; sector_ct]
+        };
+
+        let mut sectors = Vec::with_capacity(sector_ct);
+        for s in 0..sector_ct {
+            let record = reader.read_u8()?;
+            let data = match record {
+                0 => Vec::new(),
+                1 | 3 | 5 | 7 => reader.read_bytes(sizes[s])?.to_vec(),
+                2 | 4 | 6 | 8 => vec![reader.read_u8()?; sizes[s]],
+                _ => return Err(ImdError::InvalidSectorRecord)
+            };
+
+            sectors.push(ImdSector {
+                number: numbers[s],
+                cylinder: cylinders[s],
+                head: heads[s],
+                size: sizes[s],
+                record,
+                data
+            });
+        }
+
+        Ok(ImdTrack {
+            mode,
+            cylinder,
+            head,
+            size_code,
+            has_cylinder_map,
+            has_head_map,
+            sectors
+        })
+    }
+
+    /// Serialize the image back into IMD format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let mut out = self.header.clone();
+        out.push(IMD_COMMENT_TERMINATOR);
+
+        for track in &self.tracks {
+            let mut head_byte = track.head;
+            if track.has_cylinder_map {
+                head_byte |= IMD_HEAD_CYLINDER_MAP;
+            }
+            if track.has_head_map {
+                head_byte |= IMD_HEAD_HEAD_MAP;
+            }
+
+            out.extend_from_slice(&[track.mode, track.cylinder, head_byte, track.sectors.len() as u8, track.size_code]);
+            out.extend(track.sectors.iter().map(|s| s.number));
+            if track.has_cylinder_map {
+                out.extend(track.sectors.iter().map(|s| s.cylinder));
+            }
+            if track.has_head_map {
+                out.extend(track.sectors.iter().map(|s| s.head));
+            }
+            if track.size_code == IMD_SECTOR_SIZE_TABLE {
+                for s in &track.sectors {
+                    out.extend_from_slice(&(s.size as u16).to_le_bytes());
+                }
+            }
+
+            for s in &track.sectors {
+                out.push(s.record);
+                match s.record {
+                    0 => {}
+                    _ if s.is_compressed() => out.push(s.data.first().copied().unwrap_or(0)),
+                    _ => out.extend_from_slice(&s.data)
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Determine the layout of the image as a flat buffer of sectors in CHS order, returning
+    /// the number of cylinders, heads and sectors per track. This requires every track to
+    /// have the same number of sectors of the specified size, numbered consecutively from 1.
+    fn raw_geometry(&self, sector_size: usize) -> Result<(usize, usize, usize), ImdError> {
+
+        let first = self.tracks.first().ok_or(ImdError::UnsupportedGeometry)?;
+        let spt = first.sectors.len();
+        let cylinders = self.tracks.iter().map(|t| t.cylinder as usize).max().unwrap_or(0) + 1;
+        let heads = self.tracks.iter().map(|t| t.head as usize).max().unwrap_or(0) + 1;
+
+        for track in &self.tracks {
+            if track.sectors.len() != spt {
+                return Err(ImdError::UnsupportedGeometry);
+            }
+            for s in &track.sectors {
+                if s.size != sector_size || s.number == 0 || s.number as usize > spt {
+                    return Err(ImdError::UnsupportedGeometry);
+                }
+            }
+        }
+
+        Ok((cylinders, heads, spt))
+    }
+
+    fn raw_offset(cylinder: u8, head: u8, sector: u8, heads: usize, spt: usize, sector_size: usize) -> usize {
+        ((cylinder as usize * heads + head as usize) * spt + (sector as usize - 1)) * sector_size
+    }
+
+    /// Expand the image into a flat buffer of sectors in CHS order, as used for raw sector
+    /// images. Sectors with no data are filled with zeros.
+    pub fn to_raw(&self, sector_size: usize) -> Result<Vec<u8>, ImdError> {
+
+        let (cylinders, heads, spt) = self.raw_geometry(sector_size)?;
+        let mut raw = vec![0u8; cylinders * heads * spt * sector_size];
+
+        for track in &self.tracks {
+            for s in &track.sectors {
+                if !s.data.is_empty() {
+                    let offset = ImdImage::raw_offset(track.cylinder, track.head, s.number, heads, spt, sector_size);
+                    raw[offset..offset + sector_size].copy_from_slice(&s.data);
+                }
+            }
+        }
+
+        Ok(raw)
+    }
+
+    /// Build an image from a flat buffer of sectors in CHS order, with the specified geometry
+    /// and IMD track mode. Sectors are numbered consecutively from 1 and compressed if all
+    /// their bytes are identical.
+    pub fn from_raw(
+        raw: &[u8],
+        cylinders: usize,
+        heads: usize,
+        spt: usize,
+        sector_size: usize,
+        mode: u8
+    ) -> Result<ImdImage, ImdError> {
+
+        if raw.is_empty() || raw.len() != cylinders * heads * spt * sector_size || cylinders > 256 || heads > 2 || spt > 255 {
+            return Err(ImdError::UnsupportedGeometry);
+        }
+
+        let mut tracks = Vec::new();
+        for (i, track_data) in raw.chunks(spt * sector_size).enumerate() {
+            let (cylinder, head) = ((i / heads) as u8, (i % heads) as u8);
+
+            let sectors = track_data.chunks(sector_size).enumerate().map(|(s, data)| {
+                let uniform = data.iter().all(|&b| b == data[0]);
+                ImdSector {
+                    number: s as u8 + 1,
+                    cylinder,
+                    head,
+                    size: sector_size,
+                    record: if uniform { 2 } else { 1 },
+                    data: data.to_vec()
+                }
+            }).collect();
+
+            tracks.push(ImdTrack::new(mode, cylinder, head, sectors));
+        }
+
+        Ok(ImdImage {
+            header: b"IMD 1.18: Converted from raw sector image".to_vec(),
+            version: "1.18".to_string(),
+            tracks
+        })
+    }
+
+    /// Update sector data from a flat buffer of sectors previously produced by to_raw(). 
+    /// Sectors are recompressed if all their bytes are identical. Unavailable sectors
+    /// stay unavailable unless they have been written to.
+    pub fn update_from_raw(&mut self, raw: &[u8], sector_size: usize) -> Result<(), ImdError> {
+
+        let (cylinders, heads, spt) = self.raw_geometry(sector_size)?;
+        if raw.len() != cylinders * heads * spt * sector_size {
+            return Err(ImdError::UnsupportedGeometry);
+        }
+
+        for track in &mut self.tracks {
+            let (cylinder, head) = (track.cylinder, track.head);
+            for s in &mut track.sectors {
+                let offset = ImdImage::raw_offset(cylinder, head, s.number, heads, spt, sector_size);
+                let data = &raw[offset..offset + sector_size];
+
+                if s.record == 0 && data.iter().all(|&b| b == 0) {
+                    continue;
+                }
+
+                // Keep the deleted and error attributes of the original record
+                let base = match s.record {
+                    0 => 1,
+                    r if r % 2 == 0 => r - 1,
+                    r => r
+                };
+                let uniform = data.iter().all(|&b| b == data[0]);
+                s.record = if uniform { base + 1 } else { base };
+                s.data = data.to_vec();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image() -> Vec<u8> {
+        let mut img = b"IMD 1.18: 01/01/2023 12:00:00\r\nTest image".to_vec();
+        img.push(IMD_COMMENT_TERMINATOR);
+
+        // Cylinder 0, head 0: two 512 byte sectors, interleaved, second sector compressed
+        img.extend_from_slice(&[5, 0, 0, 2, 2]);
+        img.extend_from_slice(&[2, 1]);
+        img.push(1);
+        img.extend(std::iter::repeat(0xAA).take(512));
+        img.push(2);
+        img.push(0xE5);
+
+        // Cylinder 0, head 1: two sectors, one missing and one with a deleted data mark
+        img.extend_from_slice(&[5, 0, 1, 2, 2]);
+        img.extend_from_slice(&[1, 2]);
+        img.push(0);
+        img.push(4);
+        img.push(0x55);
+        img
+    }
+
+    #[test]
+    fn test_imd_parse() {
+        let data = test_image();
+        let image = ImdImage::parse(&data).unwrap();
+
+        assert_eq!(image.version, "1.18");
+        assert_eq!(image.tracks.len(), 2);
+
+        let sector = &image.tracks[0].sectors[1];
+        assert_eq!(sector.number, 1);
+        assert!(sector.is_compressed());
+        assert_eq!(sector.data, vec![0xE5; 512]);
+        assert!(image.tracks[1].sectors[1].is_deleted());
+
+        // Round trip
+        assert_eq!(image.to_bytes(), data);
+
+        let raw = image.to_raw(512).unwrap();
+        assert_eq!(raw.len(), 4 * 512);
+        assert_eq!(raw[0], 0xE5);
+        assert_eq!(raw[512], 0xAA);
+        assert_eq!(raw[1024], 0x00);
+        assert_eq!(raw[1536], 0x55);
+        assert_eq!(image.to_raw(256), Err(ImdError::UnsupportedGeometry));
+    }
+
+    #[test]
+    fn test_imd_update_from_raw() {
+        let mut image = ImdImage::parse(&test_image()).unwrap();
+
+        let mut raw = image.to_raw(512).unwrap();
+        raw[0] = 0x00;
+        raw[1024..1536].fill(0x77);
+        image.update_from_raw(&raw, 512).unwrap();
+
+        // Compressed sector became a normal sector
+        assert_eq!(image.tracks[0].sectors[1].record, 1);
+        assert_eq!(image.tracks[0].sectors[1].data[0], 0x00);
+        // Unavailable sector was written with uniform data
+        assert_eq!(image.tracks[1].sectors[0].record, 2);
+        // Deleted data mark is kept
+        assert_eq!(image.tracks[1].sectors[1].record, 4);
+
+        let reparsed = ImdImage::parse(&image.to_bytes()).unwrap();
+        assert_eq!(reparsed.to_raw(512).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_imd_from_raw() {
+        let mut raw = vec![0xF6u8; 2 * 2 * 3 * 512];
+        raw[512..1024].iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+
+        let image = ImdImage::from_raw(&raw, 2, 2, 3, 512, 5).unwrap();
+        assert_eq!(image.tracks.len(), 4);
+        assert_eq!(image.tracks[3].cylinder, 1);
+        assert_eq!(image.tracks[3].head, 1);
+        assert_eq!(image.tracks[0].size_code, 2);
+        assert!(image.tracks[0].sectors[0].is_compressed());
+        assert!(!image.tracks[0].sectors[1].is_compressed());
+
+        let reparsed = ImdImage::parse(&image.to_bytes()).unwrap();
+        assert_eq!(reparsed.to_raw(512).unwrap(), raw);
+        assert!(ImdImage::from_raw(&raw[1..], 2, 2, 3, 512, 5).is_err());
+    }
+
+    #[test]
+    fn test_imd_sector_size_table() {
+        let mut data = b"IMD 1.17: test".to_vec();
+        data.push(IMD_COMMENT_TERMINATOR);
+        data.extend_from_slice(&[5, 0, 0, 2, IMD_SECTOR_SIZE_TABLE]);
+        data.extend_from_slice(&[1, 2]);
+        data.extend_from_slice(&[0x00, 0x01, 0x80, 0x00]);
+        data.push(2);
+        data.push(0x11);
+        data.push(1);
+        data.extend(std::iter::repeat(0x22).take(128));
+
+        let image = ImdImage::parse(&data).unwrap();
+        assert_eq!(image.tracks[0].sectors[0].data.len(), 256);
+        assert_eq!(image.tracks[0].sectors[1].data.len(), 128);
+        assert_eq!(image.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_imd_errors() {
+        let mut data = test_image();
+        data[4] = b'2';
+        assert_eq!(ImdImage::parse(&data).err(), Some(ImdError::UnsupportedVersion("2.18".to_string())));
+
+        let data = test_image();
+        assert_eq!(ImdImage::parse(&data[..data.len() - 1]).err(), Some(ImdError::Truncated));
+        assert_eq!(ImdImage::parse(b"XYZ").err(), Some(ImdError::InvalidHeader));
+    }
+}
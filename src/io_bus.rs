@@ -0,0 +1,140 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    io_bus.rs
+
+    Maps IO ports to the devices that respond to them. Built-in devices are
+    mapped by type and owned by the BusInterface; additional devices can be
+    registered as boxed IoDevice trait objects and are owned by the IoBus.
+    Ports can only be claimed by one device.
+
+*/
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display
+};
+
+use crate::bus::{IoDevice, IoDeviceType};
+
+#[derive(Debug, PartialEq)]
+pub enum IoBusError {
+    PortConflict(u16, IoDeviceType),
+}
+impl Error for IoBusError {}
+impl Display for IoBusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            IoBusError::PortConflict(port, device) => write!(f, "IO port {:04X} is already mapped to {:?}", port, device),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct IoBus {
+    map: HashMap<u16, IoDeviceType>,
+    devices: Vec<Option<Box<dyn IoDevice>>>,
+}
+
+impl IoBus {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Map the specified ports to a device. If any port is already mapped, no ports are
+    /// mapped and the conflict is returned.
+    pub fn map_ports(&mut self, device: IoDeviceType, ports: &[u16]) -> Result<(), IoBusError> {
+        if let Some(port) = ports.iter().find(|p| self.map.contains_key(*p)) {
+            return Err(IoBusError::PortConflict(*port, self.map[port]))
+        }
+        self.map.extend(ports.iter().map(|p| (*p, device)));
+        Ok(())
+    }
+
+    /// Register a device on the ports given by its port_list(). Returns the device's id.
+    pub fn register(&mut self, device: Box<dyn IoDevice>) -> Result<usize, IoBusError> {
+        let id = self.devices.len();
+        self.map_ports(IoDeviceType::Dynamic(id), &device.port_list())?;
+        self.devices.push(Some(device));
+        Ok(id)
+    }
+
+    /// Return the device mapped to the specified port, if any.
+    pub fn get(&self, port: u16) -> Option<IoDeviceType> {
+        self.map.get(&port).copied()
+    }
+
+    pub fn device_mut(&mut self, id: usize) -> Option<&mut (dyn IoDevice + 'static)> {
+        self.devices.get_mut(id).and_then(|d| d.as_deref_mut())
+    }
+
+    /// Temporarily remove a registered device, so that it can be passed the bus on write.
+    /// The device must be returned with restore_device().
+    pub fn take_device(&mut self, id: usize) -> Option<Box<dyn IoDevice>> {
+        self.devices.get_mut(id).and_then(|d| d.take())
+    }
+
+    pub fn restore_device(&mut self, id: usize, device: Box<dyn IoDevice>) {
+        if let Some(slot) = self.devices.get_mut(id) {
+            *slot = Some(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{BusInterface, DeviceRunTimeUnit};
+
+    struct TestDevice {
+        ports: Vec<u16>,
+    }
+
+    impl IoDevice for TestDevice {
+        fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+            (port & 0xFF) as u8
+        }
+        fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {}
+        fn port_list(&self) -> Vec<u16> {
+            self.ports.clone()
+        }
+    }
+
+    #[test]
+    fn test_io_bus_conflicts() {
+        let mut io_bus = IoBus::new();
+        io_bus.map_ports(IoDeviceType::Pit, &[0x40, 0x41, 0x42, 0x43]).unwrap();
+
+        let id = io_bus.register(Box::new(TestDevice { ports: vec![0x300, 0x301] })).unwrap();
+        assert_eq!(io_bus.get(0x301), Some(IoDeviceType::Dynamic(id)));
+        assert_eq!(io_bus.get(0x302), None);
+
+        // A conflicting registration maps none of its ports
+        let result = io_bus.register(Box::new(TestDevice { ports: vec![0x302, 0x43] }));
+        assert_eq!(result.err(), Some(IoBusError::PortConflict(0x43, IoDeviceType::Pit)));
+        assert_eq!(io_bus.get(0x302), None);
+
+        let result = io_bus.map_ports(IoDeviceType::Serial, &[0x2F8, 0x300]);
+        assert_eq!(result, Err(IoBusError::PortConflict(0x300, IoDeviceType::Dynamic(id))));
+
+        let device = io_bus.device_mut(id).unwrap();
+        assert_eq!(device.read_u8(0x301, DeviceRunTimeUnit::SystemTicks(0)), 0x01);
+    }
+}
@@ -0,0 +1,151 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    io_history.rs
+
+    Maintains a bounded time-series of values written to selected IO ports,
+    so that the sequence in which software programs a device can be
+    reconstructed and plotted against emulated time.
+
+    The history is owned and fed by the IO trace (see io_trace.rs).
+
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_IO_HISTORY_LEN: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IoSample {
+    pub value: u8,
+    pub cycle: u64,
+}
+
+pub struct IoPortHistory {
+    max_len: usize,
+    ports: HashMap<u16, VecDeque<IoSample>>,
+}
+
+impl Default for IoPortHistory {
+    fn default() -> Self {
+        IoPortHistory::new(DEFAULT_IO_HISTORY_LEN)
+    }
+}
+
+impl IoPortHistory {
+
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len: usize::max(max_len, 1),
+            ports: HashMap::new(),
+        }
+    }
+
+    /// Start recording writes to the specified port. Watching a port that is already
+    /// watched keeps its existing history.
+    pub fn watch(&mut self, port: u16) {
+        self.ports.entry(port).or_insert_with(VecDeque::new);
+    }
+
+    pub fn unwatch(&mut self, port: u16) {
+        self.ports.remove(&port);
+    }
+
+    pub fn is_watched(&self, port: u16) -> bool {
+        self.ports.contains_key(&port)
+    }
+
+    /// Return a sorted list of watched ports.
+    pub fn watched_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.ports.keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    /// Append a sample for the specified port if it is being watched. The oldest sample
+    /// is discarded once the history for the port is full.
+    pub fn record(&mut self, port: u16, value: u8, cycle: u64) {
+        if let Some(history) = self.ports.get_mut(&port) {
+            if history.len() == self.max_len {
+                history.pop_front();
+            }
+            history.push_back(IoSample { value, cycle });
+        }
+    }
+
+    pub fn samples(&self, port: u16) -> Option<&VecDeque<IoSample>> {
+        self.ports.get(&port)
+    }
+
+    /// Format the history of each watched port as a list of the cycle and value of each write.
+    pub fn report(&self) -> String {
+        let mut text = String::new();
+        for port in self.watched_ports() {
+            text.push_str(&format!("Port {:04X}:\n", port));
+            for sample in &self.ports[&port] {
+                text.push_str(&format!("{:>12} {:02X}\n", sample.cycle, sample.value));
+            }
+        }
+        text
+    }
+
+    /// Clear recorded samples while keeping the set of watched ports.
+    pub fn clear(&mut self) {
+        for history in self.ports.values_mut() {
+            history.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_history_bounded() {
+        let mut history = IoPortHistory::new(3);
+
+        history.record(0x3D4, 0x01, 10);
+        assert!(history.samples(0x3D4).is_none());
+
+        history.watch(0x3D4);
+        for i in 0..5u8 {
+            history.record(0x3D4, i, i as u64 * 10);
+        }
+        history.record(0x3D5, 0xFF, 100);
+
+        let samples: Vec<IoSample> = history.samples(0x3D4).unwrap().iter().copied().collect();
+        assert_eq!(samples, vec![
+            IoSample { value: 2, cycle: 20 },
+            IoSample { value: 3, cycle: 30 },
+            IoSample { value: 4, cycle: 40 },
+        ]);
+
+        history.watch(0x3B4);
+        assert_eq!(history.watched_ports(), vec![0x3B4, 0x3D4]);
+        assert_eq!(
+            history.report(), 
+            "Port 03B4:\nPort 03D4:\n          20 02\n          30 03\n          40 04\n"
+        );
+
+        history.clear();
+        assert!(history.is_watched(0x3D4));
+        assert!(history.samples(0x3D4).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    io_trace.rs
+
+    Implements an optional trace of every IO bus cycle. Each port read or write
+    is recorded as an entry in a bounded ring buffer, optionally limited to a
+    set of port ranges, and passed to any registered hooks so that external
+    analyzers can observe IO traffic without modifying the device code.
+
+    Entries are recorded per bus cycle, so a word-sized IN or OUT appears as
+    two byte entries on consecutive ports, as it would on the 8088's bus.
+
+    The trace also keeps the value history of watched ports (see io_history.rs),
+    so that every IO bus cycle is recorded in one place. Port history is kept 
+    whether or not the trace itself is enabled.
+
+*/
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::io_history::IoPortHistory;
+
+pub const DEFAULT_IO_TRACE_LEN: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IoDirection {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IoTraceEntry {
+    pub port: u16,
+    pub value: u8,
+    pub direction: IoDirection,
+    pub cycle: u64,
+}
+
+impl fmt::Display for IoTraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dir = match self.direction {
+            IoDirection::Read => "IN ",
+            IoDirection::Write => "OUT",
+        };
+        write!(f, "{:>12} {} {:04X} {:02X}", self.cycle, dir, self.port, self.value)
+    }
+}
+
+pub type IoTraceHook = Box<dyn FnMut(&IoTraceEntry)>;
+
+/// Identifies a registered hook so that it can later be removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IoTraceHookId(usize);
+
+pub struct IoTrace {
+    enabled: bool,
+    max_len: usize,
+    filter: Vec<RangeInclusive<u16>>,
+    entries: VecDeque<IoTraceEntry>,
+    hooks: Vec<(IoTraceHookId, IoTraceHook)>,
+    next_hook_id: usize,
+    history: IoPortHistory,
+}
+
+impl Default for IoTrace {
+    fn default() -> Self {
+        IoTrace::new(DEFAULT_IO_TRACE_LEN)
+    }
+}
+
+impl IoTrace {
+
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            enabled: false,
+            max_len: usize::max(max_len, 1),
+            filter: Vec::new(),
+            entries: VecDeque::new(),
+            hooks: Vec::new(),
+            next_hook_id: 0,
+            history: IoPortHistory::default(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, state: bool) {
+        self.enabled = state;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Restrict tracing to the specified port ranges. An empty filter traces all ports.
+    /// Existing entries are not affected.
+    pub fn set_filter(&mut self, ranges: Vec<RangeInclusive<u16>>) {
+        self.filter = ranges;
+    }
+
+    pub fn add_filter(&mut self, range: RangeInclusive<u16>) {
+        self.filter.push(range);
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter.clear();
+    }
+
+    pub fn filter(&self) -> &[RangeInclusive<u16>] {
+        &self.filter
+    }
+
+    pub fn matches(&self, port: u16) -> bool {
+        self.filter.is_empty() || self.filter.iter().any(|r| r.contains(&port))
+    }
+
+    /// Register a hook to be called for every traced IO access that passes the port filter.
+    /// Hooks are only called while the trace is enabled.
+    pub fn add_hook(&mut self, hook: IoTraceHook) -> IoTraceHookId {
+        let id = IoTraceHookId(self.next_hook_id);
+        self.next_hook_id += 1;
+        self.hooks.push((id, hook));
+        id
+    }
+
+    /// Remove a previously registered hook. Returns false if the hook was not found.
+    pub fn remove_hook(&mut self, id: IoTraceHookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.hooks.len() != len
+    }
+
+    /// Record an IO access. Writes are added to the history of the port if it is watched. 
+    /// The access is added to the trace if tracing is enabled and the port passes the filter.
+    /// The oldest entry is discarded once the trace buffer is full.
+    pub fn record(&mut self, port: u16, value: u8, direction: IoDirection, cycle: u64) {
+        if direction == IoDirection::Write {
+            self.history.record(port, value, cycle);
+        }
+
+        if !self.enabled || !self.matches(port) {
+            return
+        }
+
+        let entry = IoTraceEntry { port, value, direction, cycle };
+
+        for (_, hook) in self.hooks.iter_mut() {
+            hook(&entry);
+        }
+
+        if self.entries.len() == self.max_len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> &VecDeque<IoTraceEntry> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn history(&self) -> &IoPortHistory {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut IoPortHistory {
+        &mut self.history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_history::IoSample;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_io_trace_filter_and_hooks() {
+        let mut trace = IoTrace::new(3);
+
+        trace.record(0x40, 0x01, IoDirection::Write, 1);
+        assert!(trace.entries().is_empty());
+
+        trace.set_enabled(true);
+        trace.set_filter(vec![0x40..=0x43, 0x3D0..=0x3DF]);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_hook = seen.clone();
+        let id = trace.add_hook(Box::new(move |e: &IoTraceEntry| seen_hook.borrow_mut().push(e.port)));
+
+        trace.record(0x43, 0x36, IoDirection::Write, 10);
+        trace.record(0x60, 0x1C, IoDirection::Read, 20);
+        trace.record(0x3D4, 0x0E, IoDirection::Write, 30);
+        trace.record(0x3D5, 0x00, IoDirection::Write, 31);
+        trace.record(0x40, 0xFF, IoDirection::Read, 40);
+
+        let entries: Vec<IoTraceEntry> = trace.entries().iter().copied().collect();
+        assert_eq!(entries, vec![
+            IoTraceEntry { port: 0x3D4, value: 0x0E, direction: IoDirection::Write, cycle: 30 },
+            IoTraceEntry { port: 0x3D5, value: 0x00, direction: IoDirection::Write, cycle: 31 },
+            IoTraceEntry { port: 0x40, value: 0xFF, direction: IoDirection::Read, cycle: 40 },
+        ]);
+        assert_eq!(*seen.borrow(), vec![0x43, 0x3D4, 0x3D5, 0x40]);
+
+        assert!(trace.remove_hook(id));
+        assert!(!trace.remove_hook(id));
+        trace.record(0x41, 0x00, IoDirection::Write, 50);
+        assert_eq!(seen.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_io_trace_port_history() {
+        let mut trace = IoTrace::new(4);
+        trace.history_mut().watch(0x3D4);
+
+        // History is kept for writes to watched ports while the trace is disabled
+        trace.record(0x3D4, 0x0E, IoDirection::Write, 10);
+        trace.record(0x3D4, 0xFF, IoDirection::Read, 20);
+        trace.record(0x3D5, 0x01, IoDirection::Write, 30);
+        assert!(trace.entries().is_empty());
+
+        let samples: Vec<IoSample> = trace.history().samples(0x3D4).unwrap().iter().copied().collect();
+        assert_eq!(samples, vec![IoSample { value: 0x0E, cycle: 10 }]);
+        assert!(trace.history().samples(0x3D5).is_none());
+    }
+}
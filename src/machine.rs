@@ -13,33 +13,40 @@ use std::{
     cell::{Cell, RefCell}, 
     collections::VecDeque,
     fs::File,
-    io::{BufWriter, Write}
+    io::{BufWriter, Write},
+    path::Path
 };
 
 use crate::{
     config::{ConfigFileParams, MachineType, VideoType, ValidatorType, TraceMode},
-    breakpoints::BreakPointType,
+    breakpoints::{BreakPointType, BreakCondition},
     bus::{BusInterface, ClockFactor, MemRangeDescriptor, DeviceEvent, MEM_CP_BIT},
     devices::{
-        pit::{self, PitDisplayState},
+        pit::PitDisplayState,
         pic::{self, PicStringState},
         ppi::{self, PpiStringState},
         dma::{self, DMAControllerStringState},
-        fdc::{self, FloppyController},
+        ems::EmsStringState,
+        fdc::{self, FloppyController, FloppyDriveStatus},
         hdc::{self, HardDiskController},
         mouse::Mouse,
         serial::{self, SerialPortController},
+        speaker::Speaker,
     
     },
-    cpu_808x::{self, Cpu, CpuError, CpuAddress, StepResult, ServiceEvent },
-    cpu_common::{CpuType, CpuOption},
+    cpu_808x::{self, Cpu, CpuError, CpuSnapshot, CpuAddress, DisassemblyResult, ReturnFrame, StepResult, ServiceEvent },
+    cpu_common::CpuOption,
     floppy_manager::{FloppyManager},
     vhd_manager,
     machine_manager::{MACHINE_DESCS, MachineDescriptor},
+    render::{FrameCapture, VideoRenderer},
     rom_manager::RomManager,
+    symbols::{CommentMap, SymbolMap},
     sound::{BUFFER_MS, VOLUME_ADJUST, SoundPlayer},
     tracelogger::TraceLogger,
+    syntax_token::SyntaxToken,
     videocard::{VideoCard, VideoCardState},
+    watch::WatchList,
 };
 
 use ringbuf::{RingBuffer, Producer, Consumer};
@@ -50,6 +57,61 @@ pub const NUM_HDDS: u32 = 2;
 
 pub const MAX_MEMORY_ADDRESS: usize = 0xFFFFF;
 
+/// A lightweight save state holding the CPU and the contents of memory. Devices aren't 
+/// included, so they continue from their current state when a snapshot is restored.
+pub struct MachineSnapshot {
+    cpu: CpuSnapshot,
+    memory: Vec<u8>,
+    cpu_cycles: u64,
+}
+
+/// Counts of retired instructions and the cycles spent in them for each opcode, collected 
+/// from the CPU's retire callback when instruction profiling is enabled.
+pub struct InstructionProfile {
+    opcodes: Vec<(u64, u64)>,
+}
+
+impl InstructionProfile {
+    pub fn new() -> Self {
+        Self {
+            opcodes: vec![(0, 0); 256]
+        }
+    }
+
+    pub fn record(&mut self, opcode: u8, cycles: u32) {
+        let (count, total) = &mut self.opcodes[opcode as usize];
+        *count += 1;
+        *total += cycles as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.opcodes.iter_mut().for_each(|e| *e = (0, 0));
+    }
+
+    /// Format the profile as a table of the executed opcodes, ordered by total cycles.
+    pub fn report(&self) -> String {
+        let total_cycles: u64 = self.opcodes.iter().map(|(_, cycles)| cycles).sum();
+        let mut rows: Vec<(usize, &(u64, u64))> = self.opcodes.iter()
+            .enumerate()
+            .filter(|(_, (count, _))| *count > 0)
+            .collect();
+        rows.sort_by(|a, b| b.1.1.cmp(&a.1.1).then(a.0.cmp(&b.0)));
+
+        let mut text = String::from("opcode        count       cycles  avg  cycles%\n");
+        for (opcode, (count, cycles)) in rows {
+            text.push_str(&format!(
+                "{:02X}     {:>12} {:>12} {:>4} {:>7.2}%\n",
+                opcode,
+                count,
+                cycles,
+                cycles / count,
+                *cycles as f64 * 100.0 / total_cycles.max(1) as f64
+            ));
+        }
+        text
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum MachineState {
     On,
@@ -74,10 +136,21 @@ pub enum ExecutionOperation {
     Pause,
     Step,
     StepOver,
+    /// Step a single foreground instruction. Any hardware interrupt dispatched during the
+    /// step has its handler run to completion before control is returned.
+    StepSkipIrq,
+    /// Run until the current routine returns to its caller.
+    StepOut,
     Run,
     Reset
 }
 
+/// Where a step over, background interrupt or step out operation stops running the CPU.
+enum RunTarget {
+    Address(CpuAddress),
+    Return(ReturnFrame)
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct DelayParams {
     pub dram_delay: u32,
@@ -129,6 +202,12 @@ impl ExecutionControl {
                     self.op.set(op);
                 }            
             }            
+            ExecutionOperation::StepSkipIrq => {
+                // Can only Step if paused / breakpointhit
+                if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
+                    self.op.set(op);
+                }
+            }
             ExecutionOperation::Run => {
                 // Can only Run if paused / breakpointhit
                 if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
@@ -160,13 +239,9 @@ impl ExecutionControl {
 
 pub struct PitData {
     buffer_consumer: Consumer<u8>,
-    samples_produced: u64,
-    ticks_per_sample: f64,
-    ticks: f64,
+    speaker: Speaker,
     log_file: Option<Box<BufWriter<File>>>,
     logging_triggered: bool,
-    fractional_part: f64,
-    next_sample_size: usize
 }
 
 #[allow(dead_code)]
@@ -190,6 +265,7 @@ pub struct Machine<'a>
     next_cpu_factor: ClockFactor,
     cpu_cycles: u64,
     system_ticks: u64,
+    instruction_profile: Option<Rc<RefCell<InstructionProfile>>>,
 }
 
 impl<'a> Machine<'a> {
@@ -204,6 +280,29 @@ impl<'a> Machine<'a> {
         ) -> Machine<'a> 
     {
 
+        // Apply any CPU clock overrides from the configuration. Device clocks are derived from
+        // the system crystal, so they keep their rates.
+        let mut machine_desc = machine_desc;
+        for (factor, config_factor) in [
+            (&mut machine_desc.cpu_factor, config.machine.cpu_clock),
+            (&mut machine_desc.cpu_turbo_factor, config.machine.cpu_turbo_clock)
+        ] {
+            match config_factor {
+                Some(ClockFactor::Divisor(0)) | Some(ClockFactor::Multiplier(0)) => {
+                    log::error!("Ignoring invalid CPU clock factor: {:?}", config_factor);
+                }
+                Some(f) => *factor = f,
+                None => {}
+            }
+        }
+
+        // Apply the installed RAM size override, in kilobytes.
+        match config.machine.ram_size {
+            Some(kb) if kb > 0 && kb <= 1024 => machine_desc.conventional_ram = kb * 1024,
+            Some(kb) => log::error!("Ignoring invalid RAM size: {}K", kb),
+            None => {}
+        }
+
         //let mut io_bus = IoBusInterface::new();
         
         //let mut trace_file_option: Box<dyn Write + 'a> = Box::new(std::io::stdout());
@@ -242,8 +341,11 @@ impl<'a> Machine<'a> {
             validator_trace = TraceLogger::from_filename(&trace_filename);
         }
 
+        // The CPU type may be overridden in the configuration file, ie, to run 80186 code.
+        let cpu_type = config.cpu.cpu_type.unwrap_or(machine_desc.cpu_type);
+
         let mut cpu = Cpu::new(
-            CpuType::Intel8088,
+            cpu_type,
             trace_mode,
             trace_file_option,
             #[cfg(feature = "cpu_validator")]
@@ -254,23 +356,55 @@ impl<'a> Machine<'a> {
 
         cpu.set_option(CpuOption::TraceLoggingEnabled(config.emulator.trace_on));
         cpu.set_option(CpuOption::OffRailsDetection(config.cpu.off_rails_detection)); 
+        cpu.set_option(CpuOption::OffRailsThreshold(config.cpu.off_rails_threshold));
+        if let Some(action) = config.cpu.off_rails_action {
+            cpu.set_runaway_callback(Box::new(move |_, _| action));
+        }
+        cpu.set_option(CpuOption::FpuPresent(config.cpu.fpu_present));
+        cpu.set_option(CpuOption::RepPrefixBug(config.cpu.rep_prefix_bug));
+        cpu.set_option(CpuOption::StrictUndefinedFlags(config.cpu.strict_undefined_flags));
+        cpu.set_option(CpuOption::JumpSanity(config.cpu.jump_sanity));
+        if let Some(action) = config.cpu.jump_sanity_action {
+            cpu.set_bad_jump_callback(Box::new(move |_, _| action));
+        }
+
+        // Collect an instruction profile from retired instructions, if enabled
+        let mut instruction_profile = None;
+        if config.cpu.instruction_profile {
+            let profile = Rc::new(RefCell::new(InstructionProfile::new()));
+            let profile_cb = profile.clone();
+            cpu.set_retire_callback(Box::new(move |_, opcode, cycles| {
+                profile_cb.borrow_mut().record(opcode, cycles);
+            }));
+            instruction_profile = Some(profile);
+        }
+
+        // Install memory and IO wait states
+        if let Some(ranges) = &config.cpu.memory_wait_states {
+            for range in ranges {
+                cpu.bus_mut().add_mem_wait_states(range.address as usize, range.size as usize, range.wait_states);
+            }
+        }
+        cpu.bus_mut().set_io_wait_states(config.cpu.io_wait_states);
+
+        // Watch IO ports for the port value history
+        for port in &config.emulator.io_history_ports {
+            cpu.bus_mut().io_trace_mut().history_mut().watch(*port);
+        }
 
         // Set up Ringbuffer for PIT channel #2 sampling for PC speaker
-        let speaker_buf_size = ((pit::PIT_MHZ * 1_000_000.0) * (BUFFER_MS as f64 / 1000.0)) as usize;
+        let pit_hz = machine_desc.timer_mhz() * 1_000_000.0;
+        let speaker_buf_size = (pit_hz * (BUFFER_MS as f64 / 1000.0)) as usize;
         let speaker_buf: RingBuffer<u8> = RingBuffer::new(speaker_buf_size);
         let (speaker_buf_producer, speaker_buf_consumer) = speaker_buf.split();
         let sample_rate = sound_player.sample_rate();
-        let pit_ticks_per_sample = (pit::PIT_MHZ * 1_000_000.0) / sample_rate as f64;
+        let pit_ticks_per_sample = pit_hz / sample_rate as f64;
 
         let pit_data = PitData {
             buffer_consumer: speaker_buf_consumer,
-            ticks_per_sample: pit_ticks_per_sample,
-            ticks: 0.0,
-            samples_produced: 0,
+            speaker: Speaker::new(pit_hz, sample_rate),
             log_file: pit_output_file_option,
             logging_triggered: false,
-            fractional_part: pit_ticks_per_sample.fract(),
-            next_sample_size: pit_ticks_per_sample.trunc() as usize
         };
 
         // open a file to write the sound to
@@ -291,6 +425,13 @@ impl<'a> Machine<'a> {
             video_trace, 
             config.emulator.video_frame_debug
         );
+        if let Some(open_bus) = config.machine.open_bus {
+            cpu.bus_mut().set_open_bus(open_bus);
+        }
+        if let Some(ems) = config.machine.ems {
+            cpu.bus_mut().install_ems((ems.page_frame as usize) << 4, ems.io_base, ems.size as usize * 1024);
+        }
+        cpu.bus_mut().set_log_rom_writes(config.emulator.debug_mode);
 
         // Load BIOS ROM images unless config option suppressed rom loading
         if !config.emulator.no_bios {
@@ -335,7 +476,8 @@ impl<'a> Machine<'a> {
             cpu_factor,
             next_cpu_factor: cpu_factor,
             cpu_cycles: 0,
-            system_ticks: 0
+            system_ticks: 0,
+            instruction_profile,
         }
     }
 
@@ -404,6 +546,16 @@ impl<'a> Machine<'a> {
         self.cpu.bus_mut().video_mut()
     }
 
+    /// Capture the current display of the video card, if there is one, as an RGBA image.
+    /// Used for screenshots, and for automated testing where there is no window to render to.
+    pub fn capture_frame(&self) -> Option<FrameCapture> {
+        let bus = self.cpu.bus();
+        let video_card = bus.video()?;
+        let mut renderer = VideoRenderer::new(video_card.get_video_type());
+
+        renderer.capture(video_card, bus)
+    }
+
     pub fn cpu(&self) -> &Cpu {
         &self.cpu
     }
@@ -418,6 +570,95 @@ impl<'a> Machine<'a> {
         self.cpu.get_option(opt)
     }    
 
+    /// Set the symbol map used for disassembly. Avoids needing to borrow CPU.
+    pub fn set_symbols(&mut self, symbols: SymbolMap) {
+        self.cpu.set_symbols(symbols);
+    }
+
+    /// Set the comment map used for disassembly. Avoids needing to borrow CPU.
+    pub fn set_comments(&mut self, comments: CommentMap) {
+        self.cpu.set_comments(comments);
+    }
+
+    /// Attach a comment to an address in the disassembly, or remove the comment at that
+    /// address if 'text' is empty. Returns the updated comment map so it can be saved.
+    pub fn set_comment(&mut self, addr: CpuAddress, text: &str) -> &CommentMap {
+        let comments = self.cpu.comments_mut();
+        if !comments.insert(addr, text) {
+            comments.remove(addr);
+        }
+        self.cpu.comments()
+    }
+
+    /// Evaluate a list of watch expressions. Avoids needing to borrow CPU.
+    pub fn evaluate_watches(&mut self, watches: &WatchList) -> Vec<(String, SyntaxToken)> {
+        watches.evaluate(&mut self.cpu)
+    }
+
+    /// Write a disassembly listing of the current code segment. Avoids needing to borrow CPU.
+    pub fn dump_cs_listing(&mut self, path: &Path) {
+        self.cpu.dump_cs_listing(path);
+    }
+
+    /// Write the value history of the watched IO ports to 'io_history.txt' in the specified 
+    /// directory.
+    pub fn dump_io_history(&self, path: &Path) {
+        let mut filename = path.to_path_buf();
+        filename.push("io_history.txt");
+
+        match std::fs::write(&filename, self.cpu.bus().io_trace().history().report()) {
+            Ok(_) => log::debug!("Wrote IO port history: {}", filename.display()),
+            Err(e) => log::error!("Failed to write IO port history '{}': {}", filename.display(), e)
+        }
+    }
+
+    /// Capture the CPU and memory in a MachineSnapshot.
+    pub fn save_snapshot(&self) -> MachineSnapshot {
+        let memory_len = self.cpu.bus().size();
+        MachineSnapshot {
+            cpu: self.cpu.save_state(),
+            memory: self.cpu.bus().get_slice_at(0, memory_len).to_vec(),
+            cpu_cycles: self.cpu_cycles,
+        }
+    }
+
+    /// Restore the CPU and memory from a MachineSnapshot.
+    pub fn restore_snapshot(&mut self, snapshot: &MachineSnapshot) {
+        self.cpu.restore_state(&snapshot.cpu);
+        self.cpu.bus_mut().restore_memory(&snapshot.memory);
+        self.cpu_cycles = snapshot.cpu_cycles;
+    }
+
+    /// Write the instruction profile to 'profile.txt' in the specified directory and start
+    /// a new profile. Does nothing unless instruction profiling is enabled in the config.
+    pub fn dump_instruction_profile(&mut self, path: &Path) {
+        let profile = match &self.instruction_profile {
+            Some(profile) => profile,
+            None => {
+                log::warn!("Instruction profiling is not enabled.");
+                return;
+            }
+        };
+
+        let mut filename = path.to_path_buf();
+        filename.push("profile.txt");
+
+        match std::fs::write(&filename, profile.borrow().report()) {
+            Ok(_) => {
+                log::debug!("Wrote instruction profile: {}", filename.display());
+                profile.borrow_mut().clear();
+            }
+            Err(e) => {
+                log::error!("Failed to write instruction profile '{}': {}", filename.display(), e);
+            }
+        }
+    }
+
+    /// Disassemble the instruction at the specified address, resolving symbols. 
+    pub fn disassemble_at(&mut self, addr: CpuAddress) -> DisassemblyResult {
+        self.cpu.disassemble_at(addr)
+    }
+
     /// Flush all trace logs for devices that have one
     pub fn flush_trace_logs(&mut self) {
         self.cpu.trace_flush();
@@ -430,15 +671,8 @@ impl<'a> Machine<'a> {
     /// This can vary during system execution if state of turbo button is toggled.
     /// CPU speed is always some factor of the main system crystal frequency.
     /// The CPU itself has no concept of its operational frequency.
-    pub fn get_cpu_mhz(&self) -> f64 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => {
-                self.machine_desc.system_crystal / (n as f64)
-            }
-            ClockFactor::Multiplier(n) => {
-                self.machine_desc.system_crystal * (n as f64)
-            }
-        }
+    pub fn effective_mhz(&self) -> f64 {
+        self.cpu_factor.mhz(self.machine_desc.system_crystal)
     }
 
     /// Set the specified state of the turbo button. True will enable turbo mode
@@ -511,10 +745,20 @@ impl<'a> Machine<'a> {
         }
     }
     
+    /// Return the status of the floppy controller's most recent command, if a floppy
+    /// controller is present.
+    pub fn fdc_status(&mut self) -> Option<FloppyDriveStatus> {
+        self.cpu.bus_mut().fdc_mut().as_mut().map(|fdc| fdc.get_status())
+    }
+
     pub fn set_nmi(&mut self, state: bool) {
         self.cpu.set_nmi(state);
     }
 
+    pub fn ems_state(&mut self) -> Option<EmsStringState> {
+        self.cpu.bus_mut().ems().as_ref().map(|ems| ems.get_string_state())
+    }
+
     pub fn dma_state(&mut self) -> DMAControllerStringState {
         // There will always be a primary DMA, so safe to unwrap.
         // TODO: Handle secondary DMA if present.
@@ -561,10 +805,30 @@ impl<'a> Machine<'a> {
         }
     }
 
+    pub fn connect_serial_tcp(&mut self, port_num: usize, address: String) {
+
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if let Err(e) = spc.connect_tcp(port_num, &address) {
+                log::error!("Failed to connect serial port: {}", e );
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
     pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
         self.cpu.set_breakpoints(bp_list)
     }
 
+    /// Replace any conditional breakpoints with 'cond', or remove them if None.
+    pub fn set_conditional_breakpoint(&mut self, cond: Option<BreakCondition>) {
+        self.cpu.clear_conditional_breakpoints();
+        if let Some(cond) = cond {
+            self.cpu.add_conditional_breakpoint(cond);
+        }
+    }
+
     pub fn reset(&mut self) {
 
         // TODO: Reload any program specified here?
@@ -595,22 +859,14 @@ impl<'a> Machine<'a> {
     /// divisor and system crystal speed.
     fn cpu_cycles_to_us(&self, cycles: u32) -> f64 {
 
-        let mhz = match self.cpu_factor {
-            ClockFactor::Divisor(n) => self.machine_desc.system_crystal / (n as f64),
-            ClockFactor::Multiplier(n) => self.machine_desc.system_crystal * (n as f64)
-        };
-
-        1.0 / mhz * cycles as f64
+        1.0 / self.effective_mhz() * cycles as f64
     }
     
     #[inline]
     /// Convert a count of CPU cycles to system clock ticks based on the current CPU
     /// clock divisor.
     fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32)
-        }
+        self.cpu_factor.cycles_to_ticks(cycles)
     }
 
     pub fn run(&mut self, cycle_target: u32, exec_control: &mut ExecutionControl) -> u64 {
@@ -633,6 +889,8 @@ impl<'a> Machine<'a> {
         }
 
         let mut step_over = false;
+        let mut step_out = false;
+        let mut skip_irq = false;
         let cycle_target_adj = match exec_control.state {
             ExecutionState::Paused => {
                 match exec_control.get_op() {
@@ -650,6 +908,22 @@ impl<'a> Machine<'a> {
                         // Execute 1 cycle
                         1                        
                     }
+                    ExecutionOperation::StepSkipIrq => {
+                        // Skip current breakpoint, if any
+                        skip_breakpoint = true;
+                        // Set skip interrupt flag
+                        skip_irq = true;
+                        // Execute 1 cycle
+                        1
+                    }
+                    ExecutionOperation::StepOut => {
+                        // Skip current breakpoint, if any
+                        skip_breakpoint = true;
+                        // Set step-out flag
+                        step_out = true;
+                        // Execute 1 cycle
+                        1
+                    }
                     ExecutionOperation::Run => {
                         // Transition to ExecutionState::Running
                         exec_control.state = ExecutionState::Running;
@@ -691,6 +965,34 @@ impl<'a> Machine<'a> {
                         // Execute one instruction only
                         1
                     },
+                    ExecutionOperation::StepSkipIrq => {
+                        log::trace!("BreakpointHit -> StepSkipIrq");
+                        // Clear CPU's breakpoint flag
+                        self.cpu.clear_breakpoint_flag();
+                        // Skip current breakpoint, if any
+                        skip_breakpoint = true;
+                        // Set skip interrupt flag
+                        skip_irq = true;
+                        // Transition to ExecutionState::Paused
+                        exec_control.state = ExecutionState::Paused;
+
+                        // Execute one instruction only
+                        1
+                    },
+                    ExecutionOperation::StepOut => {
+                        log::trace!("BreakpointHit -> StepOut");
+                        // Clear CPU's breakpoint flag
+                        self.cpu.clear_breakpoint_flag();
+                        // Skip current breakpoint, if any
+                        skip_breakpoint = true;
+                        // Set the step out flag
+                        step_out = true;
+                        // Transition to ExecutionState::Paused
+                        exec_control.state = ExecutionState::Paused;
+
+                        // Execute one instruction only
+                        1
+                    },
                     ExecutionOperation::Run => {
                         // Clear CPU's breakpoint flag
                         self.cpu.clear_breakpoint_flag();
@@ -726,8 +1028,18 @@ impl<'a> Machine<'a> {
         }
 
         let mut cycles_elapsed = 0;
+        // Set when an interrupt handler was run in the background and a foreground instruction
+        // still needs to be stepped.
+        let mut foreground_pending = false;
+        // The routine to step out of, captured before its first instruction is executed.
+        let step_out_frame = match step_out {
+            true => Some(self.cpu.return_frame()),
+            false => None
+        };
 
-        while cycles_elapsed < cycle_target_adj {
+        while cycles_elapsed < cycle_target_adj || foreground_pending {
+
+            foreground_pending = false;
 
             let fake_cycles: u32 = 7;
             let mut cpu_cycles;
@@ -752,18 +1064,25 @@ impl<'a> Machine<'a> {
             }
             
             let mut step_over_target = None;
+            let mut irq_return = None;
 
             match self.cpu.step(skip_breakpoint) {
                 Ok((step_result, step_cycles)) => {
 
                     match step_result {
-                        StepResult::Normal => {
+                        StepResult::Normal | StepResult::HaltWait => {
+                            // Devices keep running while halted, so that an interrupt can wake the CPU.
                             cpu_cycles = step_cycles;
                         },
                         StepResult::Call(target) => {
                             cpu_cycles = step_cycles;
                             step_over_target = Some(target);
                         }
+                        StepResult::Interrupt(target) => {
+                            cpu_cycles = step_cycles;
+                            step_over_target = Some(target);
+                            irq_return = Some(target);
+                        }
                         StepResult::BreakpointHit => {
                             exec_control.state = ExecutionState::BreakpointHit;
                             return 1
@@ -811,66 +1130,55 @@ impl<'a> Machine<'a> {
                 if let Some(step_over_target) = step_over_target {
 
                     log::debug!("Step over requested for CALL, return addr: {}", step_over_target );
-                    let mut cs_ip = self.cpu.get_csip();
-                    let mut step_over_cycles = 0;
-
-                    while cs_ip != step_over_target {
-
-                        match self.cpu.step(skip_breakpoint) {
-                            Ok((step_result, step_cycles)) => {
-            
-                                match step_result {
-                                    StepResult::Normal => {
-                                        cpu_cycles = step_cycles
-                                    },
-                                    StepResult::Call(_) => {
-                                        cpu_cycles = step_cycles
-                                        // We are already stepping over a base CALL instruction, so ignore futher CALLS/interrupts.
-                                    }
-                                    StepResult::BreakpointHit => {
-                                        // We can hit an 'inner' breakpoint while stepping over. This is fine, and ends the step
-                                        // over operation at the breakpoint.
-                                        exec_control.state = ExecutionState::BreakpointHit;
-                                        return instr_count
-                                    }
-                                    StepResult::ProgramEnd => {
-                                        exec_control.state = ExecutionState::Halted;
-                                        return instr_count
-                                    }
-                                }
-                            },
-                            Err(err) => {
-                                if let CpuError::CpuHaltedError(_) = err {
-                                    log::error!("CPU Halted!");
-                                    exec_control.state = ExecutionState::Halted;
-                                }
-                                self.error = true;
-                                self.error_str = Some(format!("{}", err));
-                                log::error!("CPU Error: {}\n{}", err, self.cpu.dump_instruction_history_string());
-                                cpu_cycles = 0
-                            } 
-                        }
-
-                        instr_count += 1;
-                        cycles_elapsed += cpu_cycles;
-                        self.cpu_cycles += cpu_cycles as u64;            
-
-                        step_over_cycles += cpu_cycles;
-            
-                        if cpu_cycles == 0 {
-                            log::warn!("Instruction returned 0 cycles");
-                            cpu_cycles = fake_cycles;
-                        }
-            
-                        self.run_devices(cpu_cycles, &mut kb_event_processed);
+                    if !self.run_to_target(
+                        RunTarget::Address(step_over_target),
+                        skip_breakpoint, 
+                        exec_control, 
+                        &mut instr_count, 
+                        &mut cycles_elapsed, 
+                        &mut kb_event_processed
+                    ) {
+                        return instr_count
+                    }
+                }
+            }
 
-                        cs_ip = self.cpu.get_csip();
+            // If step out was requested, run until the routine we were in has returned to its caller.
+            if let Some(frame) = step_out_frame {
+                if !self.cpu.has_returned(&frame) {
+
+                    log::debug!("Step out requested, call stack depth: {}", frame.depth());
+                    if !self.run_to_target(
+                        RunTarget::Return(frame),
+                        skip_breakpoint, 
+                        exec_control, 
+                        &mut instr_count, 
+                        &mut cycles_elapsed, 
+                        &mut kb_event_processed
+                    ) {
+                        return instr_count
+                    }
+                }
+            }
 
-                        if step_over_cycles > STEP_OVER_TIMEOUT {
-                            log::warn!("Step over operation timed out: No return after {} cycles.", STEP_OVER_TIMEOUT);
-                            break;
-                        }
+            // If a hardware interrupt was dispatched and background interrupt handling was requested,
+            // run the ISR until it returns to the interrupted instruction, then step again so that
+            // control is returned at the next foreground instruction.
+            if skip_irq {
+                if let Some(irq_return) = irq_return {
+
+                    log::debug!("Running ISR in background, return addr: {}", irq_return);
+                    if !self.run_to_target(
+                        RunTarget::Address(irq_return),
+                        skip_breakpoint, 
+                        exec_control, 
+                        &mut instr_count, 
+                        &mut cycles_elapsed, 
+                        &mut kb_event_processed
+                    ) {
+                        return instr_count
                     }
+                    foreground_pending = true;
                 }
             }
 
@@ -889,6 +1197,91 @@ impl<'a> Machine<'a> {
         instr_count
     }
 
+    /// Run the CPU until the specified target is reached. This is used to step over a CALL,
+    /// to run an interrupt handler to completion, or to step out of the current routine. 
+    /// Returns false if execution should stop, either because a breakpoint was hit or the 
+    /// program ended.
+    fn run_to_target(
+        &mut self, 
+        target: RunTarget, 
+        skip_breakpoint: bool,
+        exec_control: &mut ExecutionControl,
+        instr_count: &mut u64,
+        cycles_elapsed: &mut u32,
+        kb_event_processed: &mut bool
+    ) -> bool {
+
+        let fake_cycles: u32 = 7;
+        let mut cpu_cycles;
+        let mut step_over_cycles = 0;
+
+        while !self.target_reached(&target) {
+
+            match self.cpu.step(skip_breakpoint) {
+                Ok((step_result, step_cycles)) => {
+
+                    match step_result {
+                        StepResult::Normal | StepResult::HaltWait => {
+                            cpu_cycles = step_cycles
+                        },
+                        StepResult::Call(_) | StepResult::Interrupt(_) => {
+                            cpu_cycles = step_cycles
+                            // We are already stepping over a base CALL instruction or ISR, so ignore 
+                            // further CALLS/interrupts.
+                        }
+                        StepResult::BreakpointHit => {
+                            // We can hit an 'inner' breakpoint while stepping over. This is fine, and ends the step
+                            // over operation at the breakpoint.
+                            exec_control.state = ExecutionState::BreakpointHit;
+                            return false
+                        }
+                        StepResult::ProgramEnd => {
+                            exec_control.state = ExecutionState::Halted;
+                            return false
+                        }
+                    }
+                },
+                Err(err) => {
+                    if let CpuError::CpuHaltedError(_) = err {
+                        log::error!("CPU Halted!");
+                        exec_control.state = ExecutionState::Halted;
+                    }
+                    self.error = true;
+                    self.error_str = Some(format!("{}", err));
+                    log::error!("CPU Error: {}\n{}", err, self.cpu.dump_instruction_history_string());
+                    cpu_cycles = 0
+                } 
+            }
+
+            *instr_count += 1;
+            *cycles_elapsed += cpu_cycles;
+            self.cpu_cycles += cpu_cycles as u64;            
+
+            step_over_cycles += cpu_cycles;
+
+            if cpu_cycles == 0 {
+                log::warn!("Instruction returned 0 cycles");
+                cpu_cycles = fake_cycles;
+            }
+
+            self.run_devices(cpu_cycles, kb_event_processed);
+
+            if step_over_cycles > STEP_OVER_TIMEOUT {
+                log::warn!("Step operation timed out: No return after {} cycles.", STEP_OVER_TIMEOUT);
+                break;
+            }
+        }
+
+        true
+    }
+
+    fn target_reached(&self, target: &RunTarget) -> bool {
+        match target {
+            RunTarget::Address(address) => self.cpu.get_csip() == *address,
+            RunTarget::Return(frame) => self.cpu.has_returned(frame)
+        }
+    }
+
     pub fn run_devices(&mut self, cpu_cycles: u32, kb_event_processed: &mut bool) -> u32 {
 
         // Convert cycles into elapsed microseconds
@@ -935,7 +1328,7 @@ impl<'a> Machine<'a> {
         }
 
         // Sample the PIT channel #2 for sound
-        while self.speaker_buf_producer.len() >= self.pit_data.next_sample_size {
+        while self.speaker_buf_producer.len() >= self.pit_data.speaker.ticks_remaining() {
             self.pit_buf_to_sound_buf();
         }
 
@@ -945,25 +1338,15 @@ impl<'a> Machine<'a> {
 
     fn timer_ticks_to_cpu_cycles(&self, timer_ticks: u16) -> u32 {
 
-        let timer_multiplier = 
-            if let Some(timer_crystal) = self.machine_desc.timer_crystal {
-                // We have an alternate 
-                todo!("Unimplemented conversion for AT timer");
-                1
-            }
-            else {
-                match self.machine_desc.cpu_factor {
-                    ClockFactor::Divisor(n) => {
-                        self.machine_desc.timer_divisor / (n as u32)
-                    }
-                    ClockFactor::Multiplier(n) => {
-                        todo!("unimplemented conversion for CPU multiplier");
-                        1
-                    }
-                }
-            };
+        if self.machine_desc.timer_crystal.is_some() {
+            // The timer runs from its own crystal, so there's no whole number of CPU cycles per tick.
+            let cycles = timer_ticks as f64 * self.effective_mhz() / self.machine_desc.timer_mhz();
+            return cycles.round() as u32
+        }
 
-        timer_ticks as u32 * timer_multiplier
+        // Use the current CPU clock factor so the conversion stays correct in turbo mode.
+        let system_ticks = timer_ticks as u32 * self.machine_desc.timer_divisor;
+        self.cpu_factor.ticks_to_cycles(system_ticks)
     }
 
     /// Called to update machine once per frame.
@@ -981,68 +1364,41 @@ impl<'a> Machine<'a> {
         self.sound_player.play();
     }
 
+    /// Feed the PIT channel #2 speaker levels for the next audio sample to the speaker, and
+    /// queue the samples it produces to the sound player.
     pub fn pit_buf_to_sound_buf(&mut self) {
 
-        let nsamples = self.pit_data.next_sample_size;
-        if self.pit_data.buffer_consumer.len() < self.pit_data.next_sample_size {
+        let nsamples = self.pit_data.speaker.ticks_remaining();
+        if self.pit_data.buffer_consumer.len() < nsamples {
             return
         }
 
-        let mut sum = 0;
-        let mut sample;
-        let mut samples_read = false;
-
-        // If logging enabled, read samples and log to file.
-        if let Some(file) = self.pit_data.log_file.as_mut() {
-            if self.pit_data.logging_triggered {
-                for _ in 0..nsamples {
-
-                    sample = match self.pit_data.buffer_consumer.pop() {
-                        Some(s) => s,
-                        None => {
-                            log::trace!("No byte in pit buffer");
-                            0
-                        }
-                    };
-                    sum += sample;
-
-                    let sample_f32: f32 = if sample == 0 { 0.0 } else { 1.0 };
-                    file.write(&sample_f32.to_le_bytes()).expect("Error writing to debug sound file");
+        // If logging enabled, log samples to file.
+        let mut log_file = match self.pit_data.logging_triggered {
+            true => self.pit_data.log_file.as_mut(),
+            false => None
+        };
 
+        for _ in 0..nsamples {
+            let sample = match self.pit_data.buffer_consumer.pop() {
+                Some(s) => s,
+                None => {
+                    log::trace!("No byte in pit buffer");
+                    0
                 }
-                samples_read = true;
-            }
-        }
+            };
 
-        // Otherwise, just read samples
-        if !samples_read {
-            for _ in 0..nsamples {
-            
-                sample = match self.pit_data.buffer_consumer.pop() {
-                    Some(s) => s,
-                    None => {
-                        log::trace!("No byte in pit buffer");
-                        0
-                    }
-                };
-                sum += sample;
+            if let Some(file) = log_file.as_mut() {
+                let sample_f32: f32 = if sample == 0 { 0.0 } else { 1.0 };
+                file.write(&sample_f32.to_le_bytes()).expect("Error writing to debug sound file");
             }
-        }
-
-        // Averaging samples is effectively a poor lowpass filter.
-        // TODO: replace with actual lowpass filter from biquad?
-        let average: f32 = sum as f32 / nsamples as f32;
 
-        //log::trace!("Sample: sum: {}, ticks: {}, avg: {}", sum, pit_ticks, average);
-        self.pit_data.samples_produced += 1;
-        //log::trace!("producer: {}", self.pit_samples_produced);
-        self.sound_player.queue_sample(average as f32 * VOLUME_ADJUST);
-
-        // Calculate size of next audio sample in pit samples by carrying over fractional part
-        let next_sample_f: f64 = self.pit_data.ticks_per_sample + self.pit_data.fractional_part;
+            self.pit_data.speaker.tick(sample != 0);
+        }
 
-        self.pit_data.next_sample_size = next_sample_f as usize;
-        self.pit_data.fractional_part = next_sample_f.fract();
+        for sample in self.pit_data.speaker.drain_samples() {
+            self.sound_player.queue_sample(sample * VOLUME_ADJUST);
+        }
     }
 
 
@@ -85,6 +85,22 @@ pub struct MachineDescriptor {
     pub serial_mouse: bool, // TODO: Allow specifying which port mouse is connected to?
 }
 
+impl MachineDescriptor {
+    /// Return the CPU clock frequency in MHz for the given turbo state.
+    pub fn cpu_mhz(&self, turbo: bool) -> f64 {
+        match turbo {
+            true => self.cpu_turbo_factor.mhz(self.system_crystal),
+            false => self.cpu_factor.mhz(self.system_crystal)
+        }
+    }
+
+    /// Return the PIT input clock frequency in MHz. This is derived from the timer crystal if 
+    /// the machine has one, otherwise from the system crystal, and doesn't change with turbo.
+    pub fn timer_mhz(&self) -> f64 {
+        self.timer_crystal.unwrap_or(self.system_crystal) / self.timer_divisor as f64
+    }
+}
+
 lazy_static! {
     pub static ref MACHINE_DESCS: HashMap<MachineType, MachineDescriptor> = {
 
@@ -144,4 +160,27 @@ lazy_static! {
         );
         map
     };
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_clocks() {
+        for desc in MACHINE_DESCS.values() {
+            assert!((desc.cpu_mhz(false) - 4.772727).abs() < 0.000001);
+            assert!((desc.cpu_mhz(true) - 7.159091).abs() < 0.000001);
+            assert!((desc.timer_mhz() - 1.193182).abs() < 0.000001);
+        }
+
+        // The PIT ticks once every 4 CPU cycles at 4.77MHz, and every 6 in turbo.
+        let desc = &MACHINE_DESCS[&MachineType::IBM_XT_5160];
+        assert_eq!(desc.cpu_factor.ticks_to_cycles(desc.timer_divisor), 4);
+        assert_eq!(desc.cpu_turbo_factor.ticks_to_cycles(desc.timer_divisor), 6);
+
+        let factor = ClockFactor::Multiplier(2);
+        assert_eq!(factor.mhz(IBM_PC_SYSTEM_CLOCK), IBM_PC_SYSTEM_CLOCK * 2.0);
+        assert_eq!(factor.cycles_to_ticks(factor.ticks_to_cycles(12)), 12);
+    }
+}
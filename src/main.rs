@@ -54,18 +54,28 @@ mod config;
 mod cpu_common;
 mod cpu_808x;
 mod floppy_manager;
+mod bitstream;
+mod f86;
+mod imd;
+mod td0;
 mod egui;
 mod file_util;
 mod interrupt;
+mod io_bus;
+mod io_history;
+mod io_trace;
 mod machine;
 mod machine_manager;
+mod markers;
 mod memerror;
 mod rom_manager;
 mod sound;
+mod symbols;
 mod syntax_token;
 mod tracelogger;
 mod updatable;
 mod util;
+mod watch;
 
 mod vhd;
 mod vhd_manager;
@@ -83,18 +93,21 @@ mod arduino8088_client;
 mod arduino8088_validator;
 
 use input::MouseButton;
-use breakpoints::BreakPointType;
+use breakpoints::{BreakPointType, BreakCondition};
 use config::*;
 use machine::{Machine, MachineState, ExecutionState};
-use cpu_808x::{Cpu, CpuAddress};
+use cpu_808x::{Cpu, CpuAddress, InstructionQuery, parse_code_pattern};
 use cpu_common::CpuOption;
 use rom_manager::{RomManager, RomError, RomFeature};
 use floppy_manager::{FloppyManager, FloppyError};
+use devices::fdc::DiskFormat;
 use machine_manager::MACHINE_DESCS;
+use markers::{Marker, MarkerList, MARKER_FILE};
+use symbols::{CommentMap, SymbolMap};
 use vhd_manager::{VHDManager, VHDManagerError};
 use vhd::{VirtualHardDisk};
+use watch::WatchList;
 use videocard::{RenderMode};
-use bytequeue::ByteQueue;
 use crate::egui::{GuiEvent, GuiOption , GuiWindow, PerformanceStats};
 use render::{VideoRenderer, CompositeParams};
 use sound::SoundPlayer;
@@ -258,6 +271,17 @@ pub struct VideoData {
     composite_params: CompositeParams
 }
 
+/// Apply the geometry specified in the config to floppy images with a non-standard size. This
+/// must be repeated after each scan of the floppy directory, which rebuilds the image list.
+fn apply_floppy_geometry(geometry_list: &[FloppyGeometry], floppy_manager: &mut FloppyManager) {
+    for geometry in geometry_list {
+        let fmt = DiskFormat::from_geometry(geometry.cylinders, geometry.heads, geometry.sectors);
+        if let Err(e) = floppy_manager.set_geometry(&OsString::from(&geometry.image), fmt) {
+            log::warn!("Couldn't set geometry of floppy image {}: {}", geometry.image, e);
+        }
+    }
+}
+
 fn main() {
 
     env_logger::init();
@@ -376,6 +400,22 @@ fn main() {
         }
         std::process::exit(1);
     }
+    apply_floppy_geometry(&config.emulator.floppy_geometry, &mut floppy_manager);
+
+    // Load debugging markers from the previous session
+    let mut marker_path = PathBuf::new();
+    marker_path.push(config.emulator.basedir.clone());
+    marker_path.push(MARKER_FILE);
+
+    let mut marker_list = match MarkerList::load(&marker_path) {
+        Ok(markers) => markers,
+        Err(e) => {
+            log::error!("Error loading markers from {}: {}", marker_path.display(), e);
+            MarkerList::new()
+        }
+    };
+
+    let mut watch_list = WatchList::new();
 
     // Instantiate the VHD manager
     let mut vhd_manager = VHDManager::new();
@@ -555,6 +595,35 @@ fn main() {
     framework.gui.set_option(GuiOption::CpuTraceLoggingEnabled, config.emulator.trace_on);
     machine.set_cpu_option(CpuOption::TraceLoggingEnabled(config.emulator.trace_on));
 
+    framework.gui.marker_viewer.set_markers(marker_list.markers());
+
+    // Load symbols for the disassembly viewer
+    if let Some(symbol_file) = &config.emulator.symbol_file {
+        match SymbolMap::load(Path::new(symbol_file)) {
+            Ok(symbols) => {
+                log::debug!("Loaded {} symbols from {}", symbols.len(), symbol_file);
+                machine.set_symbols(symbols);
+            }
+            Err(e) => {
+                log::error!("Error loading symbols from {}: {}", symbol_file, e);
+            }
+        }
+    }
+
+    // Load disassembly comments. The comment file is created when the first comment is added.
+    let comment_path = config.emulator.comment_file.as_ref().map(PathBuf::from);
+    if let Some(path) = comment_path.as_ref().filter(|path| path.exists()) {
+        match CommentMap::load(path) {
+            Ok(comments) => {
+                log::debug!("Loaded {} comments from {}", comments.len(), path.display());
+                machine.set_comments(comments);
+            }
+            Err(e) => {
+                log::error!("Error loading comments from {}: {}", path.display(), e);
+            }
+        }
+    }
+
     // Debug mode on? 
     if config.emulator.debug_mode {
         // Open default debug windows
@@ -1050,9 +1119,9 @@ fn main() {
                     // ---------------------------------------------------------------------------
 
                     // Recalculate cycle target based on current CPU speed if it has changed (or uninitialized)
-                    let mhz = machine.get_cpu_mhz();
+                    let mhz = machine.effective_mhz();
                     if mhz != stat_counter.cpu_mhz {
-                        stat_counter.cycles_per_frame = (machine.get_cpu_mhz() * 1000000.0 / FPS_TARGET) as u32;
+                        stat_counter.cycles_per_frame = (machine.effective_mhz() * 1000000.0 / FPS_TARGET) as u32;
                         stat_counter.cycle_target = stat_counter.cycles_per_frame;
                         log::info!("CPU clock has changed to {}Mhz; new cycle target: {}", mhz, stat_counter.cycle_target);
                         stat_counter.cpu_mhz = mhz;
@@ -1257,6 +1326,7 @@ fn main() {
                                             video_card.get_display_extents(),
                                             composite_enabled,
                                             &video_data.composite_params,
+                                            video_card.is_color_burst_enabled(),
                                             beam_pos
                                         );
 
@@ -1277,6 +1347,7 @@ fn main() {
                                             video_card.get_display_extents(),
                                             composite_enabled,
                                             &video_data.composite_params,
+                                            video_card.is_color_burst_enabled(),
                                             beam_pos                                         
                                         );
                                     }
@@ -1324,6 +1395,13 @@ fn main() {
                                 GuiEvent::Exit => {
                                     // User chose exit option from menu. Shut down.
                                     // TODO: Add a timeout from last VHD write for safety?
+                                    #[cfg(feature = "cpu_coverage")]
+                                    {
+                                        let mut dump_path = PathBuf::new();
+                                        dump_path.push(config.emulator.basedir.clone());
+                                        dump_path.push("dumps");
+                                        machine.cpu().dump_coverage(&dump_path);
+                                    }
                                     println!("Thank you for using MartyPC!");
                                     *control_flow = ControlFlow::Exit;
                                 }
@@ -1383,38 +1461,45 @@ fn main() {
                                     if let Err(e) = floppy_manager.scan_dir(&floppy_path) {
                                         log::error!("Error scanning floppy directory: {}", e);
                                     }
+                                    apply_floppy_geometry(&config.emulator.floppy_geometry, &mut floppy_manager);
                                     if let Err(e) = vhd_manager.scan_dir(&hdd_path) {
                                         log::error!("Error scanning hdd directory: {}", e);
                                     };
                                 }
                                 GuiEvent::LoadFloppy(drive_select, filename) => {
                                     log::debug!("Load floppy image: {:?} into drive: {}", filename, drive_select);
-    
-                                    match floppy_manager.load_floppy_data(&filename) {
-                                        Ok(vec) => {
-                                            
-                                            if let Some(fdc) = machine.fdc() {
-                                                match fdc.load_image_from(drive_select, vec) {
-                                                    Ok(()) => {
-                                                        log::info!("Floppy image successfully loaded into virtual drive.");
-                                                    }
-                                                    Err(err) => {
-                                                        log::warn!("Floppy image failed to load: {}", err);
-                                                    }
-                                                }
+
+                                    if let Some(fdc) = machine.fdc() {
+                                        match floppy_manager.mount_floppy(fdc, drive_select, &filename) {
+                                            Ok(()) => {
+                                                log::info!("Floppy image successfully loaded into virtual drive.");
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to load floppy image: {:?} Error: {}", filename, e);
+                                                // TODO: Some sort of GUI indication of failure
+                                                eprintln!("Failed to read floppy image file: {:?} Error: {}", filename, e);
                                             }
-                                        } 
-                                        Err(e) => {
-                                            log::error!("Failed to load floppy image: {:?} Error: {}", filename, e);
-                                            // TODO: Some sort of GUI indication of failure
-                                            eprintln!("Failed to read floppy image file: {:?} Error: {}", filename, e);
                                         }
-                                    }                                
+                                    }
+                                }
+                                GuiEvent::SaveFloppy(drive_select) => {
+                                    if let Some(fdc) = machine.fdc() {
+                                        match floppy_manager.save_mounted(fdc, drive_select) {
+                                            Ok(()) => {
+                                                log::info!("Saved floppy image in drive: {}", drive_select);
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to save floppy image in drive: {} Error: {}", drive_select, e);
+                                            }
+                                        }
+                                    }
                                 }
                                 GuiEvent::EjectFloppy(drive_select) => {
                                     log::info!("Ejecting floppy in drive: {}", drive_select);
                                     if let Some(fdc) = machine.fdc() {
-                                        fdc.unload_image(drive_select);
+                                        if let Err(e) = floppy_manager.eject(fdc, drive_select) {
+                                            log::error!("Failed to eject floppy in drive: {} Error: {}", drive_select, e);
+                                        }
                                     }
                                 }
                                 GuiEvent::BridgeSerialPort(port_name) => {
@@ -1422,6 +1507,11 @@ fn main() {
                                     log::info!("Bridging serial port: {}", port_name);
                                     machine.bridge_serial_port(1, port_name);
                                 }
+                                GuiEvent::ConnectSerialTcp(address) => {
+    
+                                    log::info!("Connecting serial port to: {}", address);
+                                    machine.connect_serial_tcp(1, address);
+                                }
                                GuiEvent::DumpVRAM => {
                                     if let Some(video_card) = machine.videocard() {
                                         let mut dump_path = PathBuf::new();
@@ -1437,6 +1527,27 @@ fn main() {
                                                                     
                                     machine.cpu().dump_cs(&dump_path);
                                 }
+                                GuiEvent::DumpCSListing => {
+                                    let mut dump_path = PathBuf::new();
+                                    dump_path.push(config.emulator.basedir.clone());
+                                    dump_path.push("dumps");
+
+                                    machine.dump_cs_listing(&dump_path);
+                                }
+                                GuiEvent::DumpInstructionProfile => {
+                                    let mut dump_path = PathBuf::new();
+                                    dump_path.push(config.emulator.basedir.clone());
+                                    dump_path.push("dumps");
+
+                                    machine.dump_instruction_profile(&dump_path);
+                                }
+                                GuiEvent::DumpIoHistory => {
+                                    let mut dump_path = PathBuf::new();
+                                    dump_path.push(config.emulator.basedir.clone());
+                                    dump_path.push("dumps");
+
+                                    machine.dump_io_history(&dump_path);
+                                }
                                 GuiEvent::DumpAllMem => {
                                     let mut dump_path = PathBuf::new();
                                     dump_path.push(config.emulator.basedir.clone());
@@ -1446,7 +1557,7 @@ fn main() {
                                 }
                                 GuiEvent::EditBreakpoint => {
                                     // Get breakpoints from GUI
-                                    let (bp_str, bp_mem_str, bp_int_str) = framework.gui.get_breakpoints();
+                                    let (bp_str, bp_mem_str, bp_int_str, bp_cond_str) = framework.gui.get_breakpoints();
     
                                     let mut breakpoints = Vec::new();
     
@@ -1474,6 +1585,9 @@ fn main() {
                                     }
 
                                     machine.set_breakpoints(breakpoints);
+
+                                    // Replace the conditional breakpoint if the condition is valid
+                                    machine.set_conditional_breakpoint(BreakCondition::parse(bp_cond_str).ok());
                                 }
                                 GuiEvent::MemoryUpdate => {
                                     // The address bar for the memory viewer was updated. We need to 
@@ -1490,9 +1604,18 @@ fn main() {
                                     };
                                     framework.gui.memory_viewer.set_row(mem_dump_addr as usize);                                    
                                 }
+                                GuiEvent::MemoryWrite(addr, value) => {
+                                    // A byte was edited in the memory viewer.
+                                    if let Err(e) = machine.bus_mut().debug_write(addr, &[value]) {
+                                        let err_str = format!("Failed to write memory at {:05X}: {}", addr, e);
+                                        log::error!("{}", err_str);
+                                        framework.gui.show_error(&err_str);
+                                    }
+                                }
                                 GuiEvent::TokenHover(addr) => {
                                     // Hovered over a token in a TokenListView.
-                                    let debug = machine.bus_mut().get_memory_debug(addr);
+                                    let cpu_type = machine.cpu().get_cpu_type();
+                                    let debug = machine.bus_mut().get_memory_debug(addr, cpu_type);
                                     framework.gui.memory_viewer.set_hover_text(format!("{}", debug));
                                 }
                                 GuiEvent::FlushLogs => {
@@ -1528,17 +1651,127 @@ fn main() {
                                     }
                                     machine.change_state(state);
                                 }
+                                GuiEvent::CopyDisassemblySnippet => {
+                                    // Disassemble the context around cs:ip as text for bug reports.
+                                    let (cs, ip, cpu_type) = {
+                                        let cpu = machine.cpu();
+                                        (
+                                            cpu.get_register16(cpu_808x::Register16::CS), 
+                                            cpu.get_register16(cpu_808x::Register16::IP),
+                                            cpu.get_cpu_type()
+                                        )
+                                    };
+                                    let snippet = Cpu::disassembly_snippet(machine.bus_mut(), cpu_type, cs, ip, 8, 8);
+                                    framework.gui.disassembly_viewer.set_snippet(snippet);
+                                }
+                                GuiEvent::AddMarker(label) => {
+                                    let cpu = machine.cpu();
+                                    marker_list.add(Marker {
+                                        label,
+                                        instruction_count: cpu.get_instruction_count(),
+                                        cycle_count: machine.cpu_cycles(),
+                                        cs: cpu.get_register16(cpu_808x::Register16::CS),
+                                        ip: cpu.get_register16(cpu_808x::Register16::IP),
+                                        snapshot: Some(Rc::new(machine.save_snapshot())),
+                                    });
+                                    if let Err(e) = marker_list.save(&marker_path) {
+                                        log::error!("Error saving markers: {}", e);
+                                    }
+                                    framework.gui.marker_viewer.set_markers(marker_list.markers());
+                                }
+                                GuiEvent::GotoMarker(idx) => {
+                                    // Restore the marker's snapshot, if it was taken this session, and show 
+                                    // the marker location in the disassembly viewer.
+                                    if let Some(marker) = marker_list.get(idx) {
+                                        match &marker.snapshot {
+                                            Some(snapshot) => {
+                                                log::info!("Restoring marker: {}", marker.label);
+                                                machine.restore_snapshot(snapshot);
+                                            }
+                                            None => log::warn!("Marker '{}' has no snapshot to restore.", marker.label)
+                                        }
+                                        framework.gui.disassembly_viewer.set_address(format!("{:04X}:{:04X}", marker.cs, marker.ip));
+                                        framework.gui.show_window(GuiWindow::DisassemblyViewer);
+                                    }
+                                }
+                                GuiEvent::DeleteMarker(idx) => {
+                                    marker_list.remove(idx);
+                                    if let Err(e) = marker_list.save(&marker_path) {
+                                        log::error!("Error saving markers: {}", e);
+                                    }
+                                    framework.gui.marker_viewer.set_markers(marker_list.markers());
+                                }
+                                GuiEvent::AddWatch(text) => {
+                                    watch_list.add(&text);
+                                }
+                                GuiEvent::DeleteWatch(idx) => {
+                                    watch_list.remove(idx);
+                                }
+                                GuiEvent::SetComment(segment, offset, text) => {
+                                    let comments = machine.set_comment(CpuAddress::Segmented(segment, offset), &text);
+                                    if let Some(path) = &comment_path {
+                                        if let Err(e) = comments.save(path) {
+                                            log::error!("Error saving comments to {}: {}", path.display(), e);
+                                        }
+                                    }
+                                }
+                                GuiEvent::FindCode(text) => {
+                                    // Search forward from just past the top of the disassembly view, so 
+                                    // that repeated searches advance through the matches.
+                                    let view_addr = machine.cpu().eval_address(&framework.gui.disassembly_viewer.get_address());
+                                    let start = match view_addr {
+                                        Some(CpuAddress::Segmented(segment, offset)) => CpuAddress::Segmented(segment, offset.wrapping_add(1)),
+                                        Some(addr) => CpuAddress::Flat(u32::from(addr) + 1),
+                                        None => CpuAddress::Flat(0)
+                                    };
+
+                                    // Text that isn't a byte pattern is treated as an instruction query, 
+                                    // decoded linearly from the top of the view through the end of its segment.
+                                    let found = match (parse_code_pattern(&text), InstructionQuery::parse(&text)) {
+                                        (Some(pattern), _) if !pattern.is_empty() => {
+                                            Some(machine.cpu().find_code(&pattern, start, usize::MAX).next())
+                                        }
+                                        (_, Some(query)) => {
+                                            let (segment, offset) = match view_addr {
+                                                Some(CpuAddress::Segmented(segment, offset)) => (segment, offset),
+                                                Some(addr) => ((u32::from(addr) >> 4) as u16, (u32::from(addr) & 0x0F) as u16),
+                                                None => (0, 0)
+                                            };
+                                            let cpu_type = machine.cpu().get_cpu_type();
+                                            let results = Cpu::search_instructions(machine.bus_mut(), cpu_type, segment, offset, 0xFFFF, &query);
+                                            Some(results.into_iter().find(|addr| *addr != CpuAddress::Segmented(segment, offset)))
+                                        }
+                                        _ => None
+                                    };
+
+                                    let status = match found {
+                                        Some(Some(addr)) => {
+                                            framework.gui.disassembly_viewer.set_address(addr.to_string());
+                                            None
+                                        }
+                                        Some(None) => Some("Not found".to_string()),
+                                        None => Some("Invalid pattern".to_string())
+                                    };
+                                    framework.gui.disassembly_viewer.set_find_status(status);
+                                }
                                 GuiEvent::TakeScreenshot => {
                                     let mut screenshot_path = PathBuf::new();
                                     screenshot_path.push(config.emulator.basedir.clone());
                                     screenshot_path.push("screenshots");
 
-                                    video.screenshot(
-                                        &mut render_src,
-                                        video_data.render_w, 
-                                        video_data.render_h, 
-                                        &screenshot_path
-                                    );
+                                    // Capture the display directly, as render_src is only drawn 
+                                    // into when aspect correction is enabled.
+                                    match machine.capture_frame() {
+                                        Some(mut frame) => {
+                                            video.screenshot(
+                                                &mut frame.data,
+                                                frame.width, 
+                                                frame.height, 
+                                                &screenshot_path
+                                            );
+                                        }
+                                        None => log::warn!("No video display to capture for screenshot.")
+                                    }
 
                                 }
                                 _ => {}
@@ -1632,18 +1865,27 @@ fn main() {
 
                     // -- Update memory viewer window if open
                     if framework.gui.is_window_open(egui::GuiWindow::MemoryViewer) {
-                        let mem_dump_addr_str = framework.gui.memory_viewer.get_address();
-                        // Show address 0 if expression evail fails
-                        let (addr, mem_dump_addr) = match machine.cpu().eval_address(&mem_dump_addr_str) {
-                            Some(i) => {
-                                let addr: u32 = i.into();
-                                // Dump at 16 byte block boundaries
-                                (addr, addr & !0x0F)
+                        let (addr, mem_dump_addr) = if framework.gui.memory_viewer.is_following() {
+                            // Keep the current execution address centered in the view
+                            let ip = machine.cpu().get_linear_ip();
+                            framework.gui.memory_viewer.follow_address(ip as usize);
+                            (ip, framework.gui.memory_viewer.get_row() as u32)
+                        }
+                        else {
+                            let mem_dump_addr_str = framework.gui.memory_viewer.get_address();
+                            // Show address 0 if expression evail fails
+                            match machine.cpu().eval_address(&mem_dump_addr_str) {
+                                Some(i) => {
+                                    let addr: u32 = i.into();
+                                    // Dump at 16 byte block boundaries
+                                    (addr, addr & !0x0F)
+                                }
+                                None => (0,0)
                             }
-                            None => (0,0)
                         };
 
-                        let mem_dump_vec = machine.bus().dump_flat_tokens(mem_dump_addr as usize, addr as usize, 256);
+                        let mem_dump_format = framework.gui.memory_viewer.get_format();
+                        let mem_dump_vec = machine.bus().dump_flat_tokens(mem_dump_addr as usize, addr as usize, 256, mem_dump_format);
                     
                         //framework.gui.memory_viewer.set_row(mem_dump_addr as usize);
                         framework.gui.memory_viewer.set_memory(mem_dump_vec);
@@ -1661,6 +1903,18 @@ fn main() {
                         framework.gui.cpu_viewer.update_state(cpu_state);
                     }
 
+                    // -- Update prefetch queue viewer window
+                    if framework.gui.is_window_open(egui::GuiWindow::QueueViewer) {
+                        let queue_tokens = machine.cpu().queue_tokens();
+                        framework.gui.queue_viewer.update_state(queue_tokens);
+                    }
+
+                    // -- Update watch window
+                    if framework.gui.is_window_open(egui::GuiWindow::WatchViewer) {
+                        let watch_state = machine.evaluate_watches(&watch_list);
+                        framework.gui.watch_viewer.update_state(watch_state);
+                    }
+
                     // -- Update PIT viewer window
                     if framework.gui.is_window_open(egui::GuiWindow::PitViewer) {
                         let pit_state = machine.pit_state();
@@ -1685,11 +1939,24 @@ fn main() {
                         }
                     }
 
+                    // -- Update FDC viewer window
+                    if framework.gui.is_window_open(egui::GuiWindow::FdcViewer) {
+                        if let Some(fdc_status) = machine.fdc_status() {
+                            framework.gui.update_fdc_state(fdc_status);
+                        }
+                    }
+
                     // -- Update DMA viewer window
                     if framework.gui.is_window_open(egui::GuiWindow::DmaViewer) {
                         let dma_state = machine.dma_state();
                         framework.gui.dma_viewer.update_state(dma_state);
                     }
+
+                    // -- Update EMS viewer window
+                    if framework.gui.is_window_open(egui::GuiWindow::EmsViewer) {
+                        let ems_state = machine.ems_state();
+                        framework.gui.ems_viewer.update_state(ems_state);
+                    }
                     
                     // -- Update VideoCard Viewer (Replace CRTC Viewer)
                     if framework.gui.is_window_open(egui::GuiWindow::VideoCardViewer) {
@@ -1734,9 +2001,8 @@ fn main() {
                             None => 0
                         };
 
-                        let bus = machine.bus_mut();
-                        
                         let mut listview_vec = Vec::new();
+                        let mut instructions = Vec::new();
 
                         //let mut disassembly_string = String::new();
                         let mut disassembly_addr_flat = start_addr_flat as usize;
@@ -1746,54 +2012,55 @@ fn main() {
 
                             if disassembly_addr_flat < machine::MAX_MEMORY_ADDRESS {
 
-                                bus.seek(disassembly_addr_flat);
-
                                 let mut decode_vec = Vec::new();
 
-                                match Cpu::decode(bus) {
-                                    Ok(i) => {
-                                    
-                                        let instr_slice = bus.get_slice_at(disassembly_addr_flat, i.size as usize);
-                                        let instr_bytes_str = util::fmt_byte_array(instr_slice);
-                                        
-                                        decode_vec.push(SyntaxToken::MemoryAddressFlat(disassembly_addr_flat as u32, format!("{:05X}", disassembly_addr_flat)));
-
-                                        let mut instr_vec = Cpu::tokenize_instruction(&i);
+                                let decode_addr = match disassembly_addr_seg {
+                                    Some(seg_addr @ CpuAddress::Segmented(_, _)) => seg_addr,
+                                    _ => CpuAddress::Flat(disassembly_addr_flat as u32)
+                                };
+                                let mut result = machine.disassemble_at(decode_addr);
+                                let instr_bytes_str = util::fmt_byte_array(&result.bytes);
 
-                                        //let decode_str = format!("{:05X} {:012} {}\n", disassembly_addr, instr_bytes_str, i);
-                                        
-                                        disassembly_addr_flat += i.size as usize;
+                                decode_vec.push(SyntaxToken::MemoryAddressFlat(disassembly_addr_flat as u32, format!("{:05X}", disassembly_addr_flat)));
 
-                                        // If we have cs:ip, advance the offset. Wrapping of segment may provide different results 
-                                        // from advancing flat address, so if a wrap is detected, adjust the flat address.
-                                        if let Some(CpuAddress::Segmented(segment, offset)) = disassembly_addr_seg {
+                                disassembly_addr_flat += result.size;
 
-                                            decode_vec.push(SyntaxToken::MemoryAddressSeg16(segment, offset, format!("{:04X}:{:04X}", segment, offset)));
+                                // If we have cs:ip, advance the offset. Wrapping of segment may provide different results 
+                                // from advancing flat address, so if a wrap is detected, adjust the flat address.
+                                if let Some(CpuAddress::Segmented(segment, offset)) = disassembly_addr_seg {
 
-                                            let new_offset = offset.wrapping_add(i.size as u16);
-                                            if new_offset < offset {
-                                                // A wrap of the code segment occurred. Update the linear address to match.
-                                                disassembly_addr_flat = Cpu::calc_linear_address(segment, new_offset) as usize;
-                                            }
+                                    decode_vec.push(SyntaxToken::MemoryAddressSeg16(segment, offset, format!("{:04X}:{:04X}", segment, offset)));
 
-                                            disassembly_addr_seg = Some(CpuAddress::Segmented(segment, new_offset));
-                                            //*offset = new_offset;
-                                        }
-                                        decode_vec.push(SyntaxToken::InstructionBytes(format!("{:012}", instr_bytes_str)));
-                                        decode_vec.append(&mut instr_vec);
+                                    let new_offset = offset.wrapping_add(result.size as u16);
+                                    if new_offset < offset {
+                                        // A wrap of the code segment occurred. Update the linear address to match.
+                                        disassembly_addr_flat = Cpu::calc_linear_address(segment, new_offset) as usize;
                                     }
-                                    Err(_) => {
-                                        decode_vec.push(SyntaxToken::ErrorString("INVALID".to_string()));
+
+                                    disassembly_addr_seg = Some(CpuAddress::Segmented(segment, new_offset));
+                                }
+                                decode_vec.push(SyntaxToken::InstructionBytes(instr_bytes_str));
+                                if framework.gui.disassembly_viewer.show_microcode() {
+                                    let mc = result.instruction.as_ref().and_then(|i| machine.cpu().microcode_address(i));
+                                    if let Some(mc) = mc {
+                                        decode_vec.push(SyntaxToken::MicrocodeAddress(mc, format!("{:03X}", mc)));
                                     }
-                                };
+                                }
+                                decode_vec.append(&mut result.tokens);
 
                                 //disassembly_string.push_str(&decode_str);
                                 listview_vec.push(decode_vec);
+                                instructions.push(result.instruction);
                             }
                         }
 
+                        let tooltips = instructions.iter()
+                            .map(|i| i.as_ref().map(|i| machine.cpu().instruction_tooltip(i)))
+                            .collect();
+
                         //framework.gui.update_dissassembly_view(disassembly_string);
                         framework.gui.disassembly_viewer.set_content(listview_vec);
+                        framework.gui.disassembly_viewer.set_tooltips(tooltips);
                     }
 
                     // Prepare egui
@@ -1929,6 +2196,8 @@ use cpu_808x::{
 };
 #[cfg(feature = "cpu_validator")]
 use cpu_common::CpuType;
+#[cfg(feature = "cpu_validator")]
+use bytequeue::ByteQueue;
 
 #[cfg(feature = "cpu_validator")]
 pub fn main_fuzzer <'a>(
@@ -2134,7 +2403,8 @@ pub fn main_fuzzer <'a>(
         cpu.bus_mut().seek(instruction_address as usize);
         let (opcode, _cost) = cpu.bus_mut().read_u8(instruction_address as usize, 0).expect("mem err");
 
-        let mut i = match Cpu::decode(cpu.bus_mut()) {
+        let cpu_type = cpu.get_cpu_type();
+        let mut i = match Cpu::decode(cpu.bus_mut(), cpu_type) {
             Ok(i) => i,
             Err(_) => {
                 log::error!("Instruction decode error, skipping...");
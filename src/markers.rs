@@ -0,0 +1,120 @@
+/*
+  Marty PC Emulator
+  (C)2023 Daniel Balsom
+  https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+    markers.rs
+
+    Implements named markers that record interesting moments in a debugging
+    session. Markers are persisted to a session file so that they survive
+    restarts. Each marker also holds a snapshot of the machine for returning
+    to that moment. Snapshots are only kept for the current session, so 
+    markers loaded from the session file record a location only.
+
+*/
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::Path,
+    rc::Rc
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::machine::MachineSnapshot;
+
+pub const MARKER_FILE: &str = "markers.toml";
+
+#[derive(Debug)]
+pub enum MarkerError {
+    FileReadError,
+    FileWriteError,
+    ParseError,
+}
+impl Error for MarkerError {}
+impl Display for MarkerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            MarkerError::FileReadError => write!(f, "Couldn't read the marker file."),
+            MarkerError::FileWriteError => write!(f, "Couldn't write the marker file."),
+            MarkerError::ParseError => write!(f, "The marker file could not be parsed."),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Marker {
+    pub label: String,
+    pub instruction_count: u64,
+    pub cycle_count: u64,
+    pub cs: u16,
+    pub ip: u16,
+    #[serde(skip)]
+    pub snapshot: Option<Rc<MachineSnapshot>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MarkerList {
+    #[serde(default)]
+    markers: Vec<Marker>,
+}
+
+impl MarkerList {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load a marker list from the specified session file. A missing file is not an error
+    /// and produces an empty list.
+    pub fn load(path: &Path) -> Result<Self, MarkerError> {
+
+        if !path.exists() {
+            return Ok(MarkerList::new())
+        }
+
+        let marker_str = fs::read_to_string(path).map_err(|_| MarkerError::FileReadError)?;
+        toml::from_str(&marker_str).map_err(|_| MarkerError::ParseError)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MarkerError> {
+
+        let marker_str = toml::to_string(self).map_err(|_| MarkerError::FileWriteError)?;
+        fs::write(path, marker_str).map_err(|_| MarkerError::FileWriteError)
+    }
+
+    pub fn add(&mut self, marker: Marker) {
+        self.markers.push(marker);
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<Marker> {
+        if idx < self.markers.len() {
+            Some(self.markers.remove(idx))
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Marker> {
+        self.markers.get(idx)
+    }
+
+    pub fn markers(&self) -> &Vec<Marker> {
+        &self.markers
+    }
+}
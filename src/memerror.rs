@@ -5,18 +5,22 @@ use core::fmt::Display;
 #[derive(Debug)]
 pub enum MemError {
     ReadOutOfBoundsError,
+    WriteOutOfBoundsError,
     SeekOutOfBoundsError,
     FileReadError,
     MmioError,
+    WriteIgnoredError,
 }
 impl Error for MemError {}
 impl Display for MemError{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
             MemError::ReadOutOfBoundsError => write!(f, "An attempt was made to read out of buffer bounds."),
+            MemError::WriteOutOfBoundsError => write!(f, "An attempt was made to write out of buffer bounds."),
             MemError::SeekOutOfBoundsError => write!(f, "An attempt was made to move the buffer cursor out of bounds."),
             MemError::FileReadError => write!(f, "Error reading file into MemBuf."),
-            MemError::MmioError => write!(f, "Error accessing map for memory mapped device.")
+            MemError::MmioError => write!(f, "Error accessing map for memory mapped device."),
+            MemError::WriteIgnoredError => write!(f, "An attempt was made to write to ROM or unpopulated memory.")
         }
     }
 }
\ No newline at end of file
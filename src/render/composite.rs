@@ -27,7 +27,8 @@
 
     This module includes a basic conversion routine for NTSC artifact color
     from a the composite output of the composite conversion routine. It is not
-    a full NTSC simulation.
+    a full NTSC simulation. If the color burst is disabled (by the CGA's 
+    black & white mode bit) the signal is decoded as monochrome.
 
     See https://github.com/dbalsom/cga_artifact_color for more details on the
     implementation.
@@ -194,6 +195,7 @@ pub fn artifact_colors_fast(
     hue: f32,
     sat: f32,
     luma: f32,
+    burst: bool,
 ) {
 
 
@@ -220,6 +222,12 @@ pub fn artifact_colors_fast(
             }
             yiq = yiq / CCYCLE as f32;
 
+            if !burst {
+                // Without a color burst to lock onto, the monitor's color killer removes all chroma.
+                yiq.y = 0.0;
+                yiq.z = 0.0;
+            }
+
             let adjust_yiq = adjust(yiq, hue, sat, luma);
             let rgb = YIQ2RGB * adjust_yiq;
 
@@ -290,4 +298,39 @@ pub fn regen_sync_table(table: &mut [(f32, f32, f32)], table_len: usize) {
         let phase: f32 = ((x - CCYCLE_HALF) as f32) * TAU / 8.0;
         table[x as usize] = (phase, phase.cos(), phase.sin());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_burst() {
+        const W: u32 = 16;
+        const H: u32 = 2;
+
+        // A field of solid blue produces chroma on a composite monitor
+        let cga_buf = vec![1u8; (W * H) as usize];
+        let mut composite_buf = vec![0u8; (W * H * 2) as usize];
+        process_cga_composite_int(&cga_buf, W, H, 0, 0, W, &mut composite_buf);
+
+        let mut sync_table = vec![(0.0, 0.0, 0.0); (W * 2) as usize + CCYCLE as usize];
+        regen_sync_table(&mut sync_table, (W * 2) as usize);
+
+        let decode = |burst: bool| -> Vec<u8> {
+            let mut out = vec![0u8; (W * 4 * H * 2) as usize];
+            artifact_colors_fast(&composite_buf, W * 2, H, &sync_table, &mut out, W, H, 1.5, 1.0, 1.0, burst);
+            out
+        };
+
+        let is_gray = |px: &[u8]| px[0] == px[1] && px[1] == px[2];
+
+        let color = decode(true);
+        assert!(color.chunks_exact(4).any(|px| !is_gray(px)));
+
+        // With the color burst disabled, the same signal decodes to grayscale
+        let mono = decode(false);
+        assert!(mono.chunks_exact(4).all(is_gray));
+        assert!(mono.chunks_exact(4).any(|px| px[0] > 0));
+    }
+}
@@ -398,6 +398,14 @@ pub fn get_cga_gfx_color(bits: u8, palette: &CGAPalette, intensity: bool) -> &'s
     }
 }
 
+/// An RGBA image of the visible display, as returned by VideoRenderer::capture().
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub stride: usize,      // Number of bytes per row
+    pub data: Vec<u8>,
+}
+
 pub struct VideoRenderer {
     mode: DisplayMode,
     cols: u32,
@@ -548,6 +556,60 @@ impl VideoRenderer {
         }
     }
 
+    /// Render the current display of the specified video card into a new RGBA buffer, without 
+    /// requiring a window. The display is drawn through the same path as the live display: from 
+    /// the card's front buffer for direct rendering cards, or from VRAM for indirect rendering 
+    /// cards. Scanlines are doubled if the card requests it, but neither aspect correction nor 
+    /// composite conversion is applied. Returns None if the card has no displayable area.
+    pub fn capture(&mut self, video_card: Box<&dyn VideoCard>, bus: &BusInterface) -> Option<FrameCapture> {
+
+        let (width, mut height) = match video_card.get_render_mode() {
+            RenderMode::Direct => {
+                let (w, h) = video_card.get_display_aperture();
+                // Same maximum as the live display
+                (w, std::cmp::min(h, 240))
+            }
+            RenderMode::Indirect => video_card.get_display_size()
+        };
+
+        if video_card.get_scanline_double() {
+            height *= 2;
+        }
+
+        if width == 0 || height == 0 {
+            return None
+        }
+
+        let stride = width as usize * 4;
+        let mut data = vec![0; stride * height as usize];
+
+        match video_card.get_render_mode() {
+            RenderMode::Direct => {
+                self.draw_cga_direct(
+                    &mut data,
+                    width,
+                    height,
+                    video_card.get_display_buf(),
+                    video_card.get_display_extents(),
+                    false,
+                    &CompositeParams::default(),
+                    video_card.is_color_burst_enabled(),
+                    None
+                );
+            }
+            RenderMode::Indirect => {
+                self.draw(&mut data, video_card, bus, false);
+            }
+        }
+
+        Some(FrameCapture {
+            width,
+            height,
+            stride,
+            data
+        })
+    }
+
     pub fn screenshot(
         &self,
         frame: &mut [u8],
@@ -758,11 +820,12 @@ impl VideoRenderer {
         extents: &DisplayExtents,
         composite_enabled: bool,
         composite_params: &CompositeParams,
+        burst: bool,
         beam_pos: Option<(u32, u32)>
     ) {
 
         if composite_enabled {
-            self.draw_cga_direct_composite(frame, w, h, dbuf, extents, composite_params);
+            self.draw_cga_direct_composite(frame, w, h, dbuf, extents, composite_params, burst);
             return
         }
 
@@ -826,7 +889,8 @@ impl VideoRenderer {
         h: u32,        
         dbuf: &[u8],
         extents: &DisplayExtents,
-        composite_params: &CompositeParams
+        composite_params: &CompositeParams,
+        burst: bool
     ) {
 
         if let Some(composite_buf) = &mut self.composite_buf {
@@ -864,7 +928,8 @@ impl VideoRenderer {
                 max_h, 
                 composite_params.hue, 
                 composite_params.sat,
-                composite_params.luma
+                composite_params.luma,
+                burst
             );
         }
     }
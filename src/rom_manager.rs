@@ -32,6 +32,7 @@ use crate::config::{MachineType, RomOverride, RomFileOrganization};
 use crate::bus::{BusInterface, MEM_CP_BIT};
 
 pub const BIOS_READ_CYCLE_COST: u32 = 4;
+pub const OPTION_ROM_BLOCK_SIZE: usize = 512;
 
 pub enum RomError {
     DirNotFound,
@@ -39,6 +40,7 @@ pub enum RomError {
     RomNotFoundForFeature(RomFeature),
     FileNotFound,
     FileError,
+    BadChecksum,
     Unimplemented
 }
 
@@ -125,6 +127,16 @@ pub struct RomManager {
     rom_override: Option<Vec<RomOverride>>
 }
 
+/// Return the 8-bit sum of a ROM image's bytes, which is zero for a valid option ROM. If the
+/// image has the 55AAh option ROM signature, only the length given by its header is summed.
+pub fn rom_checksum(image: &[u8]) -> u8 {
+    let len = match image {
+        [0x55, 0xAA, blocks, ..] => usize::min(*blocks as usize * OPTION_ROM_BLOCK_SIZE, image.len()),
+        _ => image.len()
+    };
+    image[..len].iter().fold(0, |sum: u8, b| sum.wrapping_add(*b))
+}
+
 impl RomManager {
 
     pub fn new(
@@ -1615,9 +1627,17 @@ impl RomManager {
                     }
                 }
 
+                let rom_image = &rom_image_vec[(rom_entry.offset as usize)..];
+                if rom_entry.checksum {
+                    let sum = rom_checksum(rom_image);
+                    if sum != 0 {
+                        log::error!("[ROM OVERRIDE] Bad checksum for rom {}: {:02X}", rom_entry.path.display(), sum);
+                        return Err(RomError::BadChecksum);
+                    }
+                }
+
                 match bus.copy_from(
-                    // TODO: Override offset?
-                    &rom_image_vec[(rom_entry.offset as usize)..], 
+                    rom_image, 
                     rom_entry.address as usize, 
                     0, 
                     true) {
@@ -1748,4 +1768,29 @@ impl RomManager {
         &self.features_available
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_checksum() {
+        // A 1K option ROM, with its checksum byte in the last position, padded in the file
+        let mut image = vec![0x55, 0xAA, 0x02, 0xCB, 0x12, 0x34];
+        image.resize(1024, 0);
+        let sum = rom_checksum(&image);
+        image[1023] = 0u8.wrapping_sub(sum);
+        assert_eq!(rom_checksum(&image), 0);
+
+        image.extend_from_slice(&[0xFF; 512]);
+        assert_eq!(rom_checksum(&image), 0);
+
+        image[4] ^= 0x01;
+        assert_ne!(rom_checksum(&image), 0);
+
+        // Without a signature, the whole image is summed
+        assert_eq!(rom_checksum(&[0x01, 0x02, 0xFD]), 0);
+        assert_eq!(rom_checksum(&[]), 0);
+    }
+}
@@ -0,0 +1,331 @@
+/*
+  Marty PC Emulator
+  (C)2023 Daniel Balsom
+  https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+    symbols.rs
+
+    Implements a map of symbol names for addresses, so that the disassembly
+    can show names for known code and data addresses.
+
+    Symbols are loaded from a text file with one 'address=name' definition
+    per line, where address is either segment:offset or a linear address, in
+    hexadecimal. Blank lines and lines beginning with ';' or '#' are ignored.
+
+        F000:E05B=bios_reset
+        00400=bda_com1_port
+
+    Since many segment:offset pairs map to the same linear address, a symbol
+    defined with a segmented address only matches that exact segment:offset,
+    while a symbol defined with a linear address matches any segment:offset
+    that resolves to it.
+
+    Comments attached to addresses in the disassembly are kept in a CommentMap,
+    which uses the same file format and address matching, with 'address=text'
+    definitions. Unlike symbols, comments are edited in the debugger and can be
+    saved back to their file.
+
+*/
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::Path
+};
+
+use crate::cpu_808x::{Cpu, CpuAddress};
+
+#[derive(Debug)]
+pub enum SymbolError {
+    FileReadError,
+    FileWriteError,
+    ParseError(usize),
+}
+impl Error for SymbolError {}
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            SymbolError::FileReadError => write!(f, "Couldn't read the file."),
+            SymbolError::FileWriteError => write!(f, "Couldn't write the file."),
+            SymbolError::ParseError(line) => write!(f, "Invalid definition on line {}.", line),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    segmented: HashMap<(u16, u16), String>,
+    flat: HashMap<u32, String>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load a symbol map from the specified text file.
+    pub fn load(path: &Path) -> Result<Self, SymbolError> {
+        let symbol_str = fs::read_to_string(path).map_err(|_| SymbolError::FileReadError)?;
+        SymbolMap::parse(&symbol_str)
+    }
+
+    /// Parse a symbol map from 'address=name' definitions, one per line.
+    pub fn parse(symbol_str: &str) -> Result<Self, SymbolError> {
+        let mut map = SymbolMap::new();
+
+        for (n, line) in symbol_str.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue
+            }
+
+            let (addr_str, name) = line.split_once('=').ok_or(SymbolError::ParseError(n + 1))?;
+            let name = name.trim();
+            let addr = SymbolMap::parse_address(addr_str.trim()).ok_or(SymbolError::ParseError(n + 1))?;
+            if name.is_empty() {
+                return Err(SymbolError::ParseError(n + 1))
+            }
+            map.insert(addr, name);
+        }
+
+        Ok(map)
+    }
+
+    fn parse_address(addr_str: &str) -> Option<CpuAddress> {
+        match addr_str.split_once(':') {
+            Some((segment, offset)) => {
+                let segment = u16::from_str_radix(segment, 16).ok()?;
+                let offset = u16::from_str_radix(offset, 16).ok()?;
+                Some(CpuAddress::Segmented(segment, offset))
+            }
+            None => {
+                let addr = u32::from_str_radix(addr_str, 16).ok()?;
+                (addr <= 0xFFFFF).then_some(CpuAddress::Flat(addr))
+            }
+        }
+    }
+
+    /// Define a symbol. Symbols may only be defined for segmented or linear addresses;
+    /// an Offset address is ignored and returns false.
+    pub fn insert(&mut self, addr: CpuAddress, name: &str) -> bool {
+        match addr {
+            CpuAddress::Segmented(segment, offset) => {
+                self.segmented.insert((segment, offset), name.to_string());
+                true
+            }
+            CpuAddress::Flat(addr) => {
+                self.flat.insert(addr & 0xFFFFF, name.to_string());
+                true
+            }
+            CpuAddress::Offset(_) => false
+        }
+    }
+
+    /// Look up the symbol for the specified address. A segmented address is checked for
+    /// an exact segment:offset match first, then for a match on its linear address.
+    pub fn lookup(&self, addr: CpuAddress) -> Option<&str> {
+        match addr {
+            CpuAddress::Segmented(segment, offset) => {
+                self.segmented.get(&(segment, offset))
+                    .or_else(|| self.flat.get(&Cpu::calc_linear_address(segment, offset)))
+                    .map(|s| s.as_str())
+            }
+            CpuAddress::Flat(addr) => self.flat.get(&(addr & 0xFFFFF)).map(|s| s.as_str()),
+            CpuAddress::Offset(_) => None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.segmented.len() + self.flat.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Comments attached to addresses, shown at the end of the line in the disassembly viewer.
+#[derive(Clone, Debug, Default)]
+pub struct CommentMap {
+    segmented: HashMap<(u16, u16), String>,
+    flat: HashMap<u32, String>,
+}
+
+impl CommentMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load a comment map from the specified text file.
+    pub fn load(path: &Path) -> Result<Self, SymbolError> {
+        let comment_str = fs::read_to_string(path).map_err(|_| SymbolError::FileReadError)?;
+        CommentMap::parse(&comment_str)
+    }
+
+    /// Parse a comment map from 'address=text' definitions, one per line.
+    pub fn parse(comment_str: &str) -> Result<Self, SymbolError> {
+        let mut map = CommentMap::new();
+
+        for (n, line) in comment_str.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue
+            }
+
+            let (addr_str, text) = line.split_once('=').ok_or(SymbolError::ParseError(n + 1))?;
+            let addr = SymbolMap::parse_address(addr_str.trim()).ok_or(SymbolError::ParseError(n + 1))?;
+            if !map.insert(addr, text) {
+                return Err(SymbolError::ParseError(n + 1))
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Write the comment map to the specified text file, in the format read by load().
+    pub fn save(&self, path: &Path) -> Result<(), SymbolError> {
+        fs::write(path, self.to_text()).map_err(|_| SymbolError::FileWriteError)
+    }
+
+    /// Return the comment map as 'address=text' definitions, sorted by address.
+    pub fn to_text(&self) -> String {
+        let mut lines: Vec<(u32, String)> = self.segmented.iter()
+            .map(|((segment, offset), text)| {
+                (Cpu::calc_linear_address(*segment, *offset), format!("{:04X}:{:04X}={}", segment, offset, text))
+            })
+            .chain(self.flat.iter().map(|(addr, text)| (*addr, format!("{:05X}={}", addr, text))))
+            .collect();
+        lines.sort();
+
+        lines.into_iter().map(|(_, line)| line + "\n").collect()
+    }
+
+    /// Attach a comment to an address, replacing any existing comment. Surrounding whitespace
+    /// is trimmed. Returns false for an empty comment or an Offset address.
+    pub fn insert(&mut self, addr: CpuAddress, text: &str) -> bool {
+        let text = text.trim();
+        if text.is_empty() {
+            return false
+        }
+        match addr {
+            CpuAddress::Segmented(segment, offset) => {
+                self.segmented.insert((segment, offset), text.to_string());
+                true
+            }
+            CpuAddress::Flat(addr) => {
+                self.flat.insert(addr & 0xFFFFF, text.to_string());
+                true
+            }
+            CpuAddress::Offset(_) => false
+        }
+    }
+
+    /// Remove the comment that lookup() finds for this address, returning it. For a segmented
+    /// address, a comment defined at the equivalent flat address is removed as well.
+    pub fn remove(&mut self, addr: CpuAddress) -> Option<String> {
+        match addr {
+            CpuAddress::Segmented(segment, offset) => {
+                let flat = self.flat.remove(&Cpu::calc_linear_address(segment, offset));
+                self.segmented.remove(&(segment, offset)).or(flat)
+            }
+            CpuAddress::Flat(addr) => self.flat.remove(&(addr & 0xFFFFF)),
+            CpuAddress::Offset(_) => None
+        }
+    }
+
+    /// Look up the comment for the specified address, matching as SymbolMap::lookup() does.
+    pub fn lookup(&self, addr: CpuAddress) -> Option<&str> {
+        match addr {
+            CpuAddress::Segmented(segment, offset) => {
+                self.segmented.get(&(segment, offset))
+                    .or_else(|| self.flat.get(&Cpu::calc_linear_address(segment, offset)))
+                    .map(|s| s.as_str())
+            }
+            CpuAddress::Flat(addr) => self.flat.get(&(addr & 0xFFFFF)).map(|s| s.as_str()),
+            CpuAddress::Offset(_) => None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.segmented.len() + self.flat.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_map_parse_and_lookup() {
+        let map = SymbolMap::parse(
+            "; BIOS entry points\n\
+             F000:E05B = bios_reset\n\
+             \n\
+             00400=bda_com1_port\n\
+             1000:0010=_dos_print\n"
+        ).unwrap();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.lookup(CpuAddress::Segmented(0xF000, 0xE05B)), Some("bios_reset"));
+        // Segmented symbols only match their exact segment:offset
+        assert_eq!(map.lookup(CpuAddress::Segmented(0xFE00, 0x005B)), None);
+        assert_eq!(map.lookup(CpuAddress::Flat(0xFE05B)), None);
+        // Linear symbols match any segment:offset that resolves to them
+        assert_eq!(map.lookup(CpuAddress::Segmented(0x0040, 0x0000)), Some("bda_com1_port"));
+        assert_eq!(map.lookup(CpuAddress::Segmented(0x0000, 0x0400)), Some("bda_com1_port"));
+        assert_eq!(map.lookup(CpuAddress::Flat(0x00400)), Some("bda_com1_port"));
+        assert_eq!(map.lookup(CpuAddress::Offset(0x0400)), None);
+
+        assert!(matches!(SymbolMap::parse("F000:E05B"), Err(SymbolError::ParseError(1))));
+        assert!(matches!(SymbolMap::parse("\nxyz=foo"), Err(SymbolError::ParseError(2))));
+        assert!(matches!(SymbolMap::parse("100000=foo"), Err(SymbolError::ParseError(1))));
+        assert!(matches!(SymbolMap::parse("0400="), Err(SymbolError::ParseError(1))));
+    }
+
+    #[test]
+    fn test_comment_map() {
+        let mut map = CommentMap::parse("F000:E05B = POST entry\n00400=COM1 base = 3F8h\n").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.lookup(CpuAddress::Segmented(0xF000, 0xE05B)), Some("POST entry"));
+        assert_eq!(map.lookup(CpuAddress::Segmented(0x0040, 0x0000)), Some("COM1 base = 3F8h"));
+
+        // Comments can be replaced and removed, and empty comments are rejected
+        assert!(map.insert(CpuAddress::Segmented(0xF000, 0xE05B), "reset"));
+        assert!(!map.insert(CpuAddress::Segmented(0x1000, 0x0000), "  "));
+        assert!(!map.insert(CpuAddress::Offset(0x0010), "offset"));
+        assert!(map.insert(CpuAddress::Segmented(0x1000, 0x0010), "main loop"));
+        assert_eq!(map.remove(CpuAddress::Flat(0x00400)), Some("COM1 base = 3F8h".to_string()));
+
+        // Removing by segmented address also clears a flat comment that it would display
+        assert!(map.insert(CpuAddress::Flat(0x10020), "flat"));
+        assert!(map.insert(CpuAddress::Segmented(0x1000, 0x0020), "segmented"));
+        assert_eq!(map.remove(CpuAddress::Segmented(0x1000, 0x0020)), Some("segmented".to_string()));
+        assert_eq!(map.lookup(CpuAddress::Segmented(0x1000, 0x0020)), None);
+        assert!(map.insert(CpuAddress::Flat(0x10020), "flat"));
+        assert_eq!(map.remove(CpuAddress::Segmented(0x1000, 0x0020)), Some("flat".to_string()));
+
+        // Saved comments are sorted by linear address and parse back to the same map
+        let text = map.to_text();
+        assert_eq!(text, "1000:0010=main loop\nF000:E05B=reset\n");
+        let reloaded = CommentMap::parse(&text).unwrap();
+        assert_eq!(reloaded.lookup(CpuAddress::Flat(0x10010)), None);
+        assert_eq!(reloaded.lookup(CpuAddress::Segmented(0x1000, 0x0010)), Some("main loop"));
+
+        assert!(matches!(CommentMap::parse("F000:E05B=\n"), Err(SymbolError::ParseError(1))));
+    }
+}
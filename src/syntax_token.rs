@@ -36,7 +36,7 @@ pub trait SyntaxTokenize {
     fn tokenize(&self) -> Vec<SyntaxToken>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum SyntaxToken {
 
     NullToken,
@@ -51,7 +51,13 @@ pub enum SyntaxToken {
     MemoryAddressSeg16(u16, u16, String),
     MemoryAddressFlat(u32, String),
     MemoryByteHexValue(u32, u8, String, bool, u8),
+    MemoryWordHexValue(u32, u16, String, bool, u8),
+    MemoryByteDecimalValue(u32, u8, String, bool, u8),
     MemoryByteAsciiValue(u32, u8, String, u8),
+    // Placeholders for addresses with nothing mapped, with the address, the number of 
+    // bytes covered, the placeholder string and the cursor flag.
+    MemoryUnmappedValue(u32, u8, String, bool),
+    MemoryUnmappedAscii(u32, String),
 
     // Disassembly tokens
     ErrorText(String),
@@ -65,9 +71,57 @@ pub enum SyntaxToken {
     PlusSign,
     OpenBracket,
     CloseBracket,
-    HexValue(String),
+    // Numeric value tokens carry the raw value and its width in bytes along 
+    // with the default hex string, so they can be redrawn in another radix.
+    HexValue(u32, u8, String),
     Register(String),
-    Displacement(String),
+    Displacement(u32, u8, String),
+    // A name from the symbol map, in place of an address operand
+    Symbol(String),
+    // Starting microcode address of an instruction
+    MicrocodeAddress(u16, String),
+    // A user comment attached to the address of an instruction
+    Comment(String),
+}
+
+/// Selects how the value columns of a memory dump are tokenized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryDumpFormat {
+    /// One MemoryByteHexValue token per byte
+    HexByte,
+    /// One MemoryWordHexValue token per little-endian word
+    HexWord,
+    /// One MemoryByteDecimalValue token per byte, as a signed value
+    SignedDecimal,
+}
+
+/// Selects the radix used to draw numeric value tokens in disassembly.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValueRadix {
+    Hex,
+    Octal,
+    Binary,
+}
+
+impl Default for ValueRadix {
+    fn default() -> Self { ValueRadix::Hex }
+}
+
+impl ValueRadix {
+    /// Format a value of the given width in bytes. Returns None for Hex, in which
+    /// case the token's own string should be used.
+    pub fn format(&self, value: u32, width: u8) -> Option<String> {
+        let bits = width as usize * 8;
+        match self {
+            ValueRadix::Hex => None,
+            ValueRadix::Octal => Some(format!("0o{:o}", value)),
+            ValueRadix::Binary => Some(format!("0b{:0bits$b}", value, bits = bits)),
+        }
+    }
+}
+
+impl Default for MemoryDumpFormat {
+    fn default() -> Self { MemoryDumpFormat::HexByte }
 }
 
 impl Default for SyntaxToken {
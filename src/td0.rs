@@ -0,0 +1,694 @@
+/*
+    MartyPC Emulator
+    (C)2023 Daniel Balsom
+    https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+    td0.rs
+
+    Parse a Teledisk (TD0) formatted floppy image.
+
+    TD0 images begin with a 12 byte header, signed "TD" for normal images and
+    "td" for images using advanced compression. With advanced compression,
+    everything after the header is compressed with LZHUF (Teledisk 2.x). The
+    older LZW scheme used by Teledisk 1.x is not supported.
+
+    The header is followed by an optional comment block and a series of track
+    records, each listing the sectors of the track in physical order along with
+    their ID fields and flags. Tracks are parsed into IMD track records so that
+    both formats share the same geometry representation. Deleted data and CRC
+    error flags are kept in the IMD sector data record type.
+
+    Track and sector CRCs are not verified.
+
+*/
+
+use std::error::Error;
+use core::fmt::Display;
+
+use crate::imd::{ImdImage, ImdSector, ImdTrack};
+
+pub const TD0_SIGNATURE: &[u8] = b"TD";
+pub const TD0_ADVANCED_SIGNATURE: &[u8] = b"td";
+pub const TD0_HEADER_LEN: usize = 12;
+/// Teledisk versions before 2.0 used LZW for advanced compression.
+pub const TD0_LZHUF_MIN_VERSION: u8 = 20;
+
+const TD0_CRC_POLY: u16 = 0xA097;
+const TD0_COMMENT_PRESENT: u8 = 0x80;
+const TD0_FM: u8 = 0x80;
+const TD0_END_OF_IMAGE: u8 = 0xFF;
+
+const TD0_SECTOR_CRC_ERROR: u8 = 0x02;
+const TD0_SECTOR_DELETED: u8 = 0x04;
+const TD0_SECTOR_NO_DATA: u8 = 0x30;
+
+const TD0_ENCODING_RAW: u8 = 0;
+const TD0_ENCODING_REPEAT: u8 = 1;
+const TD0_ENCODING_RLE: u8 = 2;
+
+#[derive (Debug, PartialEq)]
+pub enum Td0Error {
+    InvalidHeader,
+    UnsupportedCompression(u8),
+    InvalidSectorSize,
+    InvalidSectorRecord,
+    InvalidTrackSide,
+    Truncated,
+}
+impl Error for Td0Error {}
+impl Display for Td0Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            Td0Error::InvalidHeader => write!(f, "The TD0 header was missing or invalid."),
+            Td0Error::UnsupportedCompression(v) => {
+                write!(f, "The TD0 image uses an unsupported advanced compression scheme (version {}.{}).", v / 10, v % 10)
+            }
+            Td0Error::InvalidSectorSize => write!(f, "A TD0 sector header specified an invalid sector size."),
+            Td0Error::InvalidSectorRecord => write!(f, "A TD0 sector data record was invalid."),
+            Td0Error::InvalidTrackSide => write!(f, "A TD0 track was on a side beyond the number of sides in the header."),
+            Td0Error::Truncated => write!(f, "The TD0 file ended unexpectedly."),
+        }
+    }
+}
+
+pub struct Td0Image {
+    pub advanced: bool,
+    pub version: u8,
+    pub comment: Option<String>,
+    pub tracks: Vec<ImdTrack>,
+}
+
+/// A simple cursor over the image data that reports truncation as an error.
+struct Td0Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Td0Reader<'a> {
+    fn read_u8(&mut self) -> Result<u8, Td0Error> {
+        let b = *self.data.get(self.pos).ok_or(Td0Error::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Td0Error> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        Ok(lo | hi << 8)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Td0Error> {
+        if self.pos + len > self.data.len() {
+            return Err(Td0Error::Truncated);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+}
+
+/// Calculate the CRC used by Teledisk headers.
+pub fn td0_crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ TD0_CRC_POLY
+            };
+        }
+    }
+    crc
+}
+
+impl Td0Image {
+
+    pub fn is_td0(data: &[u8]) -> bool {
+        data.starts_with(TD0_SIGNATURE) || data.starts_with(TD0_ADVANCED_SIGNATURE)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Td0Image, Td0Error> {
+
+        if data.len() < TD0_HEADER_LEN || !Td0Image::is_td0(data) {
+            return Err(Td0Error::InvalidHeader);
+        }
+        if td0_crc(&data[..10]) != u16::from_le_bytes([data[10], data[11]]) {
+            return Err(Td0Error::InvalidHeader);
+        }
+
+        let advanced = data.starts_with(TD0_ADVANCED_SIGNATURE);
+        let version = data[4];
+        let data_rate = data[5];
+        let stepping = data[7];
+        let sides = data[9];
+
+        let body = match advanced {
+            true if version < TD0_LZHUF_MIN_VERSION => return Err(Td0Error::UnsupportedCompression(version)),
+            true => lzhuf_decode(&data[TD0_HEADER_LEN..]),
+            false => data[TD0_HEADER_LEN..].to_vec()
+        };
+
+        let mut reader = Td0Reader { data: &body, pos: 0 };
+
+        let comment = match stepping & TD0_COMMENT_PRESENT {
+            0 => None,
+            _ => {
+                // CRC, comment length and a six byte timestamp precede the comment text.
+                // Lines of the comment are separated by NULs.
+                let _crc = reader.read_u16()?;
+                let len = reader.read_u16()? as usize;
+                let _timestamp = reader.read_bytes(6)?;
+                let text = reader.read_bytes(len)?;
+                let text: Vec<u8> = text.iter().map(|&b| if b == 0 { b'\n' } else { b }).collect();
+                Some(String::from_utf8_lossy(&text).trim_end().to_string())
+            }
+        };
+
+        let mut tracks = Vec::new();
+        loop {
+            let sector_ct = reader.read_u8()?;
+            if sector_ct == TD0_END_OF_IMAGE {
+                break;
+            }
+            let track = Td0Image::parse_track(&mut reader, sector_ct as usize, data_rate)?;
+            if track.head >= sides.max(1) {
+                return Err(Td0Error::InvalidTrackSide);
+            }
+            tracks.push(track);
+        }
+
+        Ok(Td0Image {
+            advanced,
+            version,
+            comment,
+            tracks
+        })
+    }
+
+    fn parse_track(reader: &mut Td0Reader, sector_ct: usize, data_rate: u8) -> Result<ImdTrack, Td0Error> {
+
+        let cylinder = reader.read_u8()?;
+        let head_byte = reader.read_u8()?;
+        let _crc = reader.read_u8()?;
+
+        let mut sectors = Vec::with_capacity(sector_ct);
+        for _ in 0..sector_ct {
+            let id = reader.read_bytes(6)?;
+            let (id_cylinder, id_head, number, size_code, flags) = (id[0], id[1], id[2], id[3], id[4]);
+
+            if size_code > 6 {
+                return Err(Td0Error::InvalidSectorSize);
+            }
+            let size = 128usize << size_code;
+
+            let data = match flags & TD0_SECTOR_NO_DATA {
+                0 => Td0Image::read_sector_data(reader, size)?,
+                _ => Vec::new()
+            };
+
+            // Map the sector flags to the equivalent IMD data record type
+            let mut record = 0;
+            if !data.is_empty() {
+                record = 1;
+                if flags & TD0_SECTOR_DELETED != 0 {
+                    record += 2;
+                }
+                if flags & TD0_SECTOR_CRC_ERROR != 0 {
+                    record += 4;
+                }
+            }
+
+            sectors.push(ImdSector {
+                number,
+                cylinder: id_cylinder,
+                head: id_head,
+                size,
+                record,
+                data
+            });
+        }
+
+        let fm = data_rate & TD0_FM != 0 || head_byte & TD0_FM != 0;
+        Ok(ImdTrack::new(Td0Image::imd_mode(data_rate, fm), cylinder, head_byte & 0x01, sectors))
+    }
+
+    /// Read and expand a sector data block.
+    fn read_sector_data(reader: &mut Td0Reader, size: usize) -> Result<Vec<u8>, Td0Error> {
+
+        // The block length includes the encoding byte.
+        let block_len = reader.read_u16()? as usize;
+        if block_len == 0 {
+            return Err(Td0Error::InvalidSectorRecord);
+        }
+        let encoding = reader.read_u8()?;
+        let mut block = Td0Reader { data: reader.read_bytes(block_len - 1)?, pos: 0 };
+
+        let mut data = Vec::with_capacity(size);
+        match encoding {
+            TD0_ENCODING_RAW => data.extend_from_slice(block.data),
+            TD0_ENCODING_REPEAT => {
+                // Runs of a repeated two byte pattern
+                while block.pos < block.data.len() {
+                    let count = block.read_u16()? as usize;
+                    let pattern = block.read_bytes(2)?;
+                    for _ in 0..count {
+                        data.extend_from_slice(pattern);
+                    }
+                }
+            }
+            TD0_ENCODING_RLE => {
+                // Literal runs, or repeats of a pattern of 2 * n bytes
+                while block.pos < block.data.len() {
+                    match block.read_u8()? {
+                        0 => {
+                            let len = block.read_u8()? as usize;
+                            data.extend_from_slice(block.read_bytes(len)?);
+                        }
+                        n => {
+                            let count = block.read_u8()? as usize;
+                            let pattern = block.read_bytes(2 * n as usize)?;
+                            for _ in 0..count {
+                                data.extend_from_slice(pattern);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err(Td0Error::InvalidSectorRecord)
+        }
+
+        if data.len() != size {
+            return Err(Td0Error::InvalidSectorRecord);
+        }
+        Ok(data)
+    }
+
+    /// Convert a Teledisk data rate to an IMD track mode.
+    fn imd_mode(data_rate: u8, fm: bool) -> u8 {
+        let mode = match data_rate & 0x03 {
+            0 => 2, // 250kbps
+            1 => 1, // 300kbps
+            _ => 0  // 500kbps
+        };
+        if fm { mode } else { mode + 3 }
+    }
+
+    /// Convert the image into an IMD image with the same tracks.
+    pub fn into_imd(self) -> ImdImage {
+        let mut header = b"IMD 1.18: Converted from Teledisk".to_vec();
+        if let Some(comment) = &self.comment {
+            header.extend_from_slice(b"\r\n");
+            header.extend_from_slice(comment.replace('\n', "\r\n").as_bytes());
+        }
+
+        ImdImage {
+            header,
+            version: "1.18".to_string(),
+            tracks: self.tracks
+        }
+    }
+}
+
+// LZHUF decompression, as used by Teledisk 2.x advanced compression. This is the
+// LZSS + adaptive Huffman scheme of Okumura and Yoshizaki's LZHUF.C with a 4K window.
+const LZ_N: usize = 4096;
+const LZ_F: usize = 60;
+const LZ_THRESHOLD: usize = 2;
+const LZ_N_CHAR: usize = 256 - LZ_THRESHOLD + LZ_F;
+const LZ_T: usize = LZ_N_CHAR * 2 - 1;
+const LZ_R: usize = LZ_T - 1;
+const LZ_MAX_FREQ: u32 = 0x8000;
+
+/// Adaptive Huffman tree. Leaves are symbols offset by LZ_T in 'son' and 'prnt'.
+struct HuffTree {
+    freq: [u32; LZ_T + 1],
+    son: [usize; LZ_T],
+    prnt: [usize; LZ_T + LZ_N_CHAR],
+}
+
+impl HuffTree {
+    fn new() -> Self {
+        let mut tree = HuffTree {
+            freq: [0; LZ_T + 1],
+            son: [0; LZ_T],
+            prnt: [0; LZ_T + LZ_N_CHAR],
+        };
+
+        for i in 0..LZ_N_CHAR {
+            tree.freq[i] = 1;
+            tree.son[i] = i + LZ_T;
+            tree.prnt[i + LZ_T] = i;
+        }
+        let mut i = 0;
+        for j in LZ_N_CHAR..=LZ_R {
+            tree.freq[j] = tree.freq[i] + tree.freq[i + 1];
+            tree.son[j] = i;
+            tree.prnt[i] = j;
+            tree.prnt[i + 1] = j;
+            i += 2;
+        }
+        tree.freq[LZ_T] = 0xFFFF;
+        tree.prnt[LZ_R] = 0;
+        tree
+    }
+
+    /// Rebuild the tree with halved frequencies once the root frequency reaches LZ_MAX_FREQ.
+    fn reconstruct(&mut self) {
+        let mut j = 0;
+        for i in 0..LZ_T {
+            if self.son[i] >= LZ_T {
+                self.freq[j] = (self.freq[i] + 1) / 2;
+                self.son[j] = self.son[i];
+                j += 1;
+            }
+        }
+
+        let mut i = 0;
+        for j in LZ_N_CHAR..LZ_T {
+            let f = self.freq[i] + self.freq[i + 1];
+            self.freq[j] = f;
+            let mut k = j - 1;
+            while f < self.freq[k] {
+                k -= 1;
+            }
+            k += 1;
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = f;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+            i += 2;
+        }
+
+        for i in 0..LZ_T {
+            let k = self.son[i];
+            self.prnt[k] = i;
+            if k < LZ_T {
+                self.prnt[k + 1] = i;
+            }
+        }
+    }
+
+    /// Increment the frequency of symbol 'c', swapping nodes to keep the tree ordered.
+    fn update(&mut self, c: usize) {
+        if self.freq[LZ_R] == LZ_MAX_FREQ {
+            self.reconstruct();
+        }
+
+        let mut c = self.prnt[c + LZ_T];
+        loop {
+            self.freq[c] += 1;
+            let k = self.freq[c];
+
+            if k > self.freq[c + 1] {
+                let mut l = c + 1;
+                while k > self.freq[l + 1] {
+                    l += 1;
+                }
+
+                self.freq[c] = self.freq[l];
+                self.freq[l] = k;
+
+                let i = self.son[c];
+                self.prnt[i] = l;
+                if i < LZ_T {
+                    self.prnt[i + 1] = l;
+                }
+
+                let j = self.son[l];
+                self.son[l] = i;
+                self.prnt[j] = c;
+                if j < LZ_T {
+                    self.prnt[j + 1] = c;
+                }
+                self.son[c] = j;
+                c = l;
+            }
+
+            c = self.prnt[c];
+            if c == 0 {
+                break;
+            }
+        }
+    }
+}
+
+struct LzhufBits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u16,
+    len: u32,
+    bits_read: usize,
+}
+
+impl<'a> LzhufBits<'a> {
+    /// Keep at least 8 bits buffered. Reading past the end of the data supplies zeros.
+    fn fill(&mut self) {
+        while self.len <= 8 {
+            let b = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.buf |= (b as u16) << (8 - self.len);
+            self.len += 8;
+        }
+    }
+
+    fn get_bit(&mut self) -> usize {
+        self.fill();
+        let bit = (self.buf >> 15) as usize;
+        self.buf <<= 1;
+        self.len -= 1;
+        self.bits_read += 1;
+        bit
+    }
+
+    fn get_byte(&mut self) -> usize {
+        self.fill();
+        let byte = (self.buf >> 8) as usize;
+        self.buf <<= 8;
+        self.len -= 8;
+        self.bits_read += 8;
+        byte
+    }
+
+    fn exhausted(&self) -> bool {
+        self.bits_read > self.data.len() * 8
+    }
+}
+
+/// Decompress LZHUF data until the input is exhausted.
+fn lzhuf_decode(data: &[u8]) -> Vec<u8> {
+
+    // The upper 6 bits of a match position are Huffman coded with a fixed table, where the
+    // first byte read determines both the upper bits and the length of the code.
+    const POSITION_CODE_LENGTHS: [(u32, usize); 6] = [(3, 1), (4, 3), (5, 8), (6, 12), (7, 24), (8, 16)];
+    let mut d_code = [0usize; 256];
+    let mut d_len = [0u32; 256];
+    let mut idx = 0;
+    let mut code = 0;
+    for (len, count) in POSITION_CODE_LENGTHS {
+        for _ in 0..count {
+            for _ in 0..(256 >> len) {
+                d_code[idx] = code;
+                d_len[idx] = len;
+                idx += 1;
+            }
+            code += 1;
+        }
+    }
+
+    let mut tree = HuffTree::new();
+    let mut bits = LzhufBits { data, pos: 0, buf: 0, len: 0, bits_read: 0 };
+    let mut text = [0u8; LZ_N];
+    text[..LZ_N - LZ_F].fill(b' ');
+    let mut r = LZ_N - LZ_F;
+    let mut out = Vec::new();
+
+    loop {
+        let mut c = tree.son[LZ_R];
+        while c < LZ_T {
+            c = tree.son[c + bits.get_bit()];
+        }
+        c -= LZ_T;
+        tree.update(c);
+
+        if bits.exhausted() {
+            break;
+        }
+
+        if c < 256 {
+            out.push(c as u8);
+            text[r] = c as u8;
+            r = (r + 1) & (LZ_N - 1);
+        }
+        else {
+            let mut i = bits.get_byte();
+            let upper = d_code[i] << 6;
+            for _ in 0..d_len[i] - 2 {
+                i = (i << 1) | bits.get_bit();
+            }
+            let position = upper | (i & 0x3F);
+
+            if bits.exhausted() {
+                break;
+            }
+
+            let start = r.wrapping_sub(position + 1) & (LZ_N - 1);
+            for k in 0..(c - 255 + LZ_THRESHOLD) {
+                let b = text[(start + k) & (LZ_N - 1)];
+                out.push(b);
+                text[r] = b;
+                r = (r + 1) & (LZ_N - 1);
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(signature: &[u8], version: u8, comment: bool) -> Vec<u8> {
+        let stepping = if comment { TD0_COMMENT_PRESENT } else { 0 };
+        let mut hdr = vec![signature[0], signature[1], 0, 0, version, 0x00, 0x02, stepping, 0, 2];
+        let crc = td0_crc(&hdr);
+        hdr.extend_from_slice(&crc.to_le_bytes());
+        hdr
+    }
+
+    fn test_body() -> Vec<u8> {
+        let mut body = Vec::new();
+
+        // Comment block
+        body.extend_from_slice(&[0, 0, 9, 0, 123, 0, 1, 12, 0, 0]);
+        body.extend_from_slice(b"Test\0disk");
+
+        // Cylinder 0, head 0: a repeated pattern sector and a deleted RLE sector
+        body.extend_from_slice(&[2, 0, 0, 0]);
+        body.extend_from_slice(&[0, 0, 2, 2, 0, 0]);
+        body.extend_from_slice(&[5, 0, TD0_ENCODING_REPEAT, 0, 1, 0xE5, 0xF6]);
+        body.extend_from_slice(&[0, 0, 1, 2, TD0_SECTOR_DELETED, 0]);
+        body.extend_from_slice(&[9, 0, TD0_ENCODING_RLE, 0, 2, 0xAA, 0xBB, 1, 255]);
+        body.extend_from_slice(&[0x11, 0x22]);
+
+        // Cylinder 0, head 1: a raw sector with a CRC error and a sector with no data
+        body.extend_from_slice(&[2, 0, 1, 0]);
+        body.extend_from_slice(&[0, 1, 1, 2, TD0_SECTOR_CRC_ERROR, 0]);
+        body.extend_from_slice(&[1, 2, TD0_ENCODING_RAW]);
+        body.extend(std::iter::repeat(0x55).take(512));
+        body.extend_from_slice(&[0, 1, 2, 2, 0x20, 0]);
+
+        body.push(TD0_END_OF_IMAGE);
+        body
+    }
+
+    /// Compress data using literals only, for testing the decoder.
+    fn lzhuf_encode_literals(data: &[u8]) -> Vec<u8> {
+        let mut tree = HuffTree::new();
+        let mut bits: Vec<usize> = Vec::new();
+
+        for &c in data {
+            let mut code = Vec::new();
+            let mut k = tree.prnt[c as usize + LZ_T];
+            while k != LZ_R {
+                code.push(k & 1);
+                k = tree.prnt[k];
+            }
+            bits.extend(code.iter().rev());
+            tree.update(c as usize);
+        }
+
+        bits.chunks(8)
+            .map(|chunk| chunk.iter().enumerate().fold(0u8, |b, (n, &bit)| b | (bit as u8) << (7 - n)))
+            .collect()
+    }
+
+    fn check_tracks(image: &Td0Image) {
+        assert_eq!(image.comment.as_deref(), Some("Test\ndisk"));
+        assert_eq!(image.tracks.len(), 2);
+
+        let track = &image.tracks[0];
+        assert_eq!(track.mode, 5);
+        assert_eq!(track.sectors[0].number, 2);
+        assert_eq!(track.sectors[0].data[..4], [0xE5, 0xF6, 0xE5, 0xF6]);
+        assert_eq!(track.sectors[1].data[..4], [0xAA, 0xBB, 0x11, 0x22]);
+        assert_eq!(track.sectors[1].data[511], 0x22);
+        assert!(track.sectors[1].is_deleted());
+        assert!(!track.sectors[1].has_error());
+
+        let track = &image.tracks[1];
+        assert_eq!(track.head, 1);
+        assert!(track.sectors[0].has_error());
+        assert!(!track.sectors[0].is_deleted());
+        assert_eq!(track.sectors[1].record, 0);
+    }
+
+    #[test]
+    fn test_td0_parse() {
+        let mut data = header(TD0_SIGNATURE, 21, true);
+        data.extend(test_body());
+
+        let image = Td0Image::parse(&data).unwrap();
+        assert!(!image.advanced);
+        check_tracks(&image);
+
+        let raw = image.into_imd().to_raw(512).unwrap();
+        assert_eq!(raw.len(), 4 * 512);
+        assert_eq!(raw[0], 0xAA);
+        assert_eq!(raw[512], 0xE5);
+        assert_eq!(raw[1024], 0x55);
+        assert_eq!(raw[1536], 0x00);
+    }
+
+    #[test]
+    fn test_td0_advanced_compression() {
+        let mut data = header(TD0_ADVANCED_SIGNATURE, 21, true);
+        data.extend(lzhuf_encode_literals(&test_body()));
+
+        let image = Td0Image::parse(&data).unwrap();
+        assert!(image.advanced);
+        check_tracks(&image);
+
+        // Teledisk 1.x LZW compression
+        let mut data = header(TD0_ADVANCED_SIGNATURE, 15, true);
+        data.extend(test_body());
+        assert_eq!(Td0Image::parse(&data).err(), Some(Td0Error::UnsupportedCompression(15)));
+    }
+
+    #[test]
+    fn test_td0_errors() {
+        let mut data = header(TD0_SIGNATURE, 21, true);
+        data.extend(test_body());
+        data[10] ^= 0xFF;
+        assert_eq!(Td0Image::parse(&data).err(), Some(Td0Error::InvalidHeader));
+
+        let mut data = header(TD0_SIGNATURE, 21, true);
+        data.extend(test_body());
+        data.pop();
+        assert_eq!(Td0Image::parse(&data).err(), Some(Td0Error::Truncated));
+        assert_eq!(Td0Image::parse(b"IMD 1.18").err(), Some(Td0Error::InvalidHeader));
+
+        // The test body has a track on head 1, which a single-sided image can't hold
+        let mut data = header(TD0_SIGNATURE, 21, true);
+        data[9] = 1;
+        let crc = td0_crc(&data[..10]);
+        data[10..12].copy_from_slice(&crc.to_le_bytes());
+        data.extend(test_body());
+        assert_eq!(Td0Image::parse(&data).err(), Some(Td0Error::InvalidTrackSide));
+    }
+}
@@ -244,6 +244,10 @@ pub trait VideoCard {
     /// Returns the current CGA-compatible palette and intensity attribute
     fn get_cga_palette(&self) -> (CGAPalette, bool);
 
+    /// Returns whether the adapter is currently generating a color burst on its composite output.
+    /// A monitor receiving no color burst will display the composite signal in monochrome.
+    fn is_color_burst_enabled(&self) -> bool;
+
     /// Returns a hash map of vectors containing name and value pairs.
     /// 
     /// This allows returning multiple categories of related registers.
@@ -0,0 +1,431 @@
+/*
+  Marty PC Emulator
+  (C)2023 Daniel Balsom
+  https://github.com/dbalsom/marty
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+    watch.rs
+
+    Implements watch expressions for the debugger. A watch expression is
+    evaluated against the current CPU state every frame, so values can be
+    observed live as the machine runs.
+
+    Expressions are sums and differences of registers, numbers and memory
+    references, ie:
+        ax+cx
+        word [ds:1234]
+        byte [es:bx+2]
+
+    Numbers are hexadecimal, and may be written with a '0x' prefix or 'h'
+    suffix. A memory reference reads a word unless prefixed with 'byte'. If
+    no segment is given, the reference is relative to ds. Memory is read
+    directly from the bus, so watching memory has no side effects.
+
+*/
+
+use std::{
+    error::Error,
+    fmt::Display
+};
+
+use crate::cpu_808x::{Cpu, Register8, Register16};
+use crate::syntax_token::SyntaxToken;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    InvalidNumber(String),
+}
+impl Error for WatchError {}
+impl Display for WatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self {
+            WatchError::UnexpectedEnd => write!(f, "Unexpected end of expression."),
+            WatchError::UnexpectedToken(s) => write!(f, "Unexpected '{}' in expression.", s),
+            WatchError::InvalidNumber(s) => write!(f, "Invalid number '{}'.", s),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchSize {
+    Byte,
+    Word,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchExpr {
+    Constant(u16),
+    Reg8(Register8),
+    Reg16(Register16),
+    Add(Box<WatchExpr>, Box<WatchExpr>),
+    Sub(Box<WatchExpr>, Box<WatchExpr>),
+    // Memory reference: size, segment and offset
+    Memory(WatchSize, Box<WatchExpr>, Box<WatchExpr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Plus,
+    Minus,
+    Colon,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(s) => write!(f, "{}", s),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Colon => write!(f, ":"),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+        }
+    }
+}
+
+fn tokenize(expr_str: &str) -> Result<Vec<Token>, WatchError> {
+    let mut tokens = Vec::new();
+    let mut chars = expr_str.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            c if c.is_ascii_alphanumeric() => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break
+                    }
+                    word.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => {
+                tokens.push(match c {
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    ':' => Token::Colon,
+                    '[' => Token::OpenBracket,
+                    ']' => Token::CloseBracket,
+                    _ => return Err(WatchError::UnexpectedToken(c.to_string()))
+                });
+                chars.next();
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn register_from_str(name: &str) -> Option<WatchExpr> {
+    let expr = match name {
+        "al" => WatchExpr::Reg8(Register8::AL),
+        "cl" => WatchExpr::Reg8(Register8::CL),
+        "dl" => WatchExpr::Reg8(Register8::DL),
+        "bl" => WatchExpr::Reg8(Register8::BL),
+        "ah" => WatchExpr::Reg8(Register8::AH),
+        "ch" => WatchExpr::Reg8(Register8::CH),
+        "dh" => WatchExpr::Reg8(Register8::DH),
+        "bh" => WatchExpr::Reg8(Register8::BH),
+        "ax" => WatchExpr::Reg16(Register16::AX),
+        "cx" => WatchExpr::Reg16(Register16::CX),
+        "dx" => WatchExpr::Reg16(Register16::DX),
+        "bx" => WatchExpr::Reg16(Register16::BX),
+        "sp" => WatchExpr::Reg16(Register16::SP),
+        "bp" => WatchExpr::Reg16(Register16::BP),
+        "si" => WatchExpr::Reg16(Register16::SI),
+        "di" => WatchExpr::Reg16(Register16::DI),
+        "es" => WatchExpr::Reg16(Register16::ES),
+        "cs" => WatchExpr::Reg16(Register16::CS),
+        "ss" => WatchExpr::Reg16(Register16::SS),
+        "ds" => WatchExpr::Reg16(Register16::DS),
+        "ip" => WatchExpr::Reg16(Register16::IP),
+        _ => return None
+    };
+    Some(expr)
+}
+
+pub(crate) fn number_from_str(word: &str) -> Result<u16, WatchError> {
+    let digits = word.strip_prefix("0x")
+        .or_else(|| word.strip_suffix('h'))
+        .unwrap_or(word);
+
+    u16::from_str_radix(digits, 16).map_err(|_| WatchError::InvalidNumber(word.to_string()))
+}
+
+/// A recursive descent parser over the tokens of a watch expression.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, WatchError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(WatchError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), WatchError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(WatchError::UnexpectedToken(token.to_string()))
+        }
+        Ok(())
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<WatchExpr, WatchError> {
+        let mut lhs = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.pos += 1;
+                    lhs = WatchExpr::Add(Box::new(lhs), Box::new(self.term()?));
+                }
+                Some(Token::Minus) => {
+                    self.pos += 1;
+                    lhs = WatchExpr::Sub(Box::new(lhs), Box::new(self.term()?));
+                }
+                _ => return Ok(lhs)
+            }
+        }
+    }
+
+    // term := register | number | ['byte' | 'word'] '[' [expr ':'] expr ']'
+    fn term(&mut self) -> Result<WatchExpr, WatchError> {
+        match self.next()? {
+            Token::Word(word) if word == "byte" || word == "word" => {
+                let size = if word == "byte" { WatchSize::Byte } else { WatchSize::Word };
+                self.expect(Token::OpenBracket)?;
+                self.memory(size)
+            }
+            Token::Word(word) => {
+                match register_from_str(&word) {
+                    Some(reg) => Ok(reg),
+                    None => Ok(WatchExpr::Constant(number_from_str(&word)?))
+                }
+            }
+            Token::OpenBracket => self.memory(WatchSize::Word),
+            token => Err(WatchError::UnexpectedToken(token.to_string()))
+        }
+    }
+
+    // The remainder of a memory reference, after the opening bracket
+    fn memory(&mut self, size: WatchSize) -> Result<WatchExpr, WatchError> {
+        let mut segment = WatchExpr::Reg16(Register16::DS);
+        let mut offset = self.expr()?;
+        if self.peek() == Some(&Token::Colon) {
+            self.pos += 1;
+            segment = offset;
+            offset = self.expr()?;
+        }
+        self.expect(Token::CloseBracket)?;
+        Ok(WatchExpr::Memory(size, Box::new(segment), Box::new(offset)))
+    }
+}
+
+impl WatchExpr {
+
+    /// Parse a watch expression.
+    pub fn parse(expr_str: &str) -> Result<WatchExpr, WatchError> {
+        let mut parser = Parser {
+            tokens: tokenize(expr_str)?,
+            pos: 0,
+        };
+
+        let expr = parser.expr()?;
+        match parser.peek() {
+            Some(token) => Err(WatchError::UnexpectedToken(token.to_string())),
+            None => Ok(expr)
+        }
+    }
+
+    /// Evaluate the expression against the current state of the CPU. Arithmetic wraps at 16 bits.
+    /// Memory is read as the CPU would see it, including memory-mapped devices.
+    pub fn eval(&self, cpu: &mut Cpu) -> u16 {
+        match self {
+            WatchExpr::Constant(value) => *value,
+            WatchExpr::Reg8(reg) => cpu.get_register8(*reg) as u16,
+            WatchExpr::Reg16(reg) => cpu.get_register16(*reg),
+            WatchExpr::Add(lhs, rhs) => lhs.eval(cpu).wrapping_add(rhs.eval(cpu)),
+            WatchExpr::Sub(lhs, rhs) => lhs.eval(cpu).wrapping_sub(rhs.eval(cpu)),
+            WatchExpr::Memory(size, segment, offset) => {
+                let segment = segment.eval(cpu);
+                let offset = offset.eval(cpu);
+                let mut read_byte = |offset: u16| {
+                    let addr = Cpu::calc_linear_address(segment, offset) as usize;
+                    cpu.bus_mut().debug_read(addr, 1).map_or(0xFF, |bytes| bytes[0])
+                };
+
+                match size {
+                    WatchSize::Byte => read_byte(offset) as u16,
+                    WatchSize::Word => u16::from_le_bytes([read_byte(offset), read_byte(offset.wrapping_add(1))])
+                }
+            }
+        }
+    }
+
+    /// Return the width of the expression's value in bytes, for display.
+    pub fn width(&self) -> u8 {
+        match self {
+            WatchExpr::Reg8(_) | WatchExpr::Memory(WatchSize::Byte, _, _) => 1,
+            _ => 2
+        }
+    }
+}
+
+pub struct Watch {
+    pub text: String,
+    expr: Result<WatchExpr, WatchError>,
+}
+
+/// The list of watch expressions shown in the watch viewer. Expressions are parsed
+/// once when added, and an invalid expression is kept so that its error can be shown.
+#[derive(Default)]
+pub struct WatchList {
+    watches: Vec<Watch>,
+}
+
+impl WatchList {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, text: &str) {
+        self.watches.push(Watch {
+            text: text.trim().to_string(),
+            expr: WatchExpr::parse(text),
+        });
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        if idx < self.watches.len() {
+            self.watches.remove(idx);
+        }
+    }
+
+    /// Evaluate each watch, returning its text and its value as a StateString token, or
+    /// an ErrorString token if the expression is invalid.
+    pub fn evaluate(&self, cpu: &mut Cpu) -> Vec<(String, SyntaxToken)> {
+        self.watches.iter()
+            .map(|watch| {
+                let token = match &watch.expr {
+                    Ok(expr) => {
+                        let value = expr.eval(cpu);
+                        let text = match expr.width() {
+                            1 => format!("{:02X}h ({})", value, value),
+                            _ => format!("{:04X}h ({})", value, value),
+                        };
+                        SyntaxToken::StateString(text, false, 0)
+                    }
+                    Err(e) => SyntaxToken::ErrorString(e.to_string())
+                };
+                (watch.text.clone(), token)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TraceMode;
+    use crate::cpu_common::CpuType;
+
+    #[test]
+    fn test_parse_watch() {
+        let mem = |size, segment, offset| WatchExpr::Memory(size, Box::new(segment), Box::new(offset));
+        let add = |lhs, rhs| WatchExpr::Add(Box::new(lhs), Box::new(rhs));
+
+        assert_eq!(
+            WatchExpr::parse("AX+CX"),
+            Ok(add(WatchExpr::Reg16(Register16::AX), WatchExpr::Reg16(Register16::CX)))
+        );
+        assert_eq!(
+            WatchExpr::parse("word [DS:0x1234]"),
+            Ok(mem(WatchSize::Word, WatchExpr::Reg16(Register16::DS), WatchExpr::Constant(0x1234)))
+        );
+        assert_eq!(
+            WatchExpr::parse("byte [ES:BX+2]"),
+            Ok(mem(
+                WatchSize::Byte,
+                WatchExpr::Reg16(Register16::ES),
+                add(WatchExpr::Reg16(Register16::BX), WatchExpr::Constant(2))
+            ))
+        );
+        // Default segment is ds, default size is a word. Numbers are hex.
+        assert_eq!(
+            WatchExpr::parse("[40h]"),
+            Ok(mem(WatchSize::Word, WatchExpr::Reg16(Register16::DS), WatchExpr::Constant(0x40)))
+        );
+        assert_eq!(WatchExpr::parse("bh").unwrap().width(), 1);
+        assert_eq!(WatchExpr::parse("0bh"), Ok(WatchExpr::Constant(0x0B)));
+
+        assert_eq!(WatchExpr::parse("ax+"), Err(WatchError::UnexpectedEnd));
+        assert_eq!(WatchExpr::parse("byte [ds:10"), Err(WatchError::UnexpectedEnd));
+        assert_eq!(WatchExpr::parse("ax cx"), Err(WatchError::UnexpectedToken("cx".to_string())));
+        assert_eq!(WatchExpr::parse("ax*2"), Err(WatchError::UnexpectedToken("*".to_string())));
+        assert_eq!(WatchExpr::parse("zz"), Err(WatchError::InvalidNumber("zz".to_string())));
+        assert_eq!(WatchExpr::parse("12345"), Err(WatchError::InvalidNumber("12345".to_string())));
+    }
+
+    #[test]
+    fn test_watch_eval() {
+        let mut cpu = Cpu::new(
+            CpuType::Intel8088,
+            TraceMode::None,
+            None::<std::io::Sink>,
+            #[cfg(feature = "cpu_validator")]
+            crate::config::ValidatorType::None,
+            #[cfg(feature = "cpu_validator")]
+            crate::tracelogger::TraceLogger::None,
+        );
+        cpu.reset();
+
+        cpu.set_register16(Register16::AX, 0x1000);
+        cpu.set_register16(Register16::CX, 0x0234);
+        cpu.set_register16(Register16::BX, 0x0010);
+        cpu.set_register16(Register16::DS, 0x2000);
+        cpu.set_register16(Register16::ES, 0x3000);
+        cpu.bus_mut().debug_write(0x21234, &[0x34, 0x12]).unwrap();
+        cpu.bus_mut().debug_write(0x30012, &[0x5A]).unwrap();
+
+        let mut eval = |s: &str| WatchExpr::parse(s).unwrap().eval(&mut cpu);
+        assert_eq!(eval("AX+CX"), 0x1234);
+        assert_eq!(eval("word [DS:0x1234]"), 0x1234);
+        assert_eq!(eval("[1234h]"), 0x1234);
+        assert_eq!(eval("byte [ES:BX+2]"), 0x5A);
+        assert_eq!(eval("cx-ax"), 0xF234);
+
+        let mut watches = WatchList::new();
+        watches.add("byte [es:bx+2]");
+        watches.add("ax+");
+        let state = watches.evaluate(&mut cpu);
+        assert!(matches!(&state[0].1, SyntaxToken::StateString(s, _, _) if s == "5Ah (90)"));
+        assert!(matches!(&state[1].1, SyntaxToken::ErrorString(_)));
+    }
+}